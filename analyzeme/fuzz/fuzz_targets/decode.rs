@@ -0,0 +1,10 @@
+//! Decodes arbitrary profiles, see `analyzeme::testing_common::fuzz_decode()`
+//! for the input format. Run with `cargo fuzz run decode` from `analyzeme`.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    analyzeme::testing_common::fuzz_decode(data);
+});
@@ -0,0 +1,146 @@
+use crate::{Event, ProfilingData, ProfilingDataBuilder, Timestamp};
+use measureme::{TimestampFormat, MAX_INTERVAL_TIMESTAMP};
+use serde_json::Value;
+use std::borrow::Cow;
+use std::error::Error;
+use std::path::Path;
+use std::time::Duration;
+
+/// An interval measured outside of the profiled process, e.g. the time the
+/// linker took according to `-Z time-passes`, to be added to a profile via
+/// `annotate()`.
+#[derive(Clone, Debug)]
+pub struct Annotation {
+    pub event_kind: String,
+    pub label: String,
+    /// The thread to add the event to. Annotations without a thread go to a
+    /// thread of their own, so that they don't interfere with the nesting of
+    /// the recorded events.
+    pub thread_id: Option<u32>,
+    /// The start of the interval, relative to the start of the profile.
+    pub start: Duration,
+    pub duration: Duration,
+}
+
+impl Annotation {
+    /// Creates an annotation of the event kind `Annotation`.
+    pub fn new(label: &str, start: Duration, duration: Duration) -> Annotation {
+        Annotation {
+            event_kind: "Annotation".to_string(),
+            label: label.to_string(),
+            thread_id: None,
+            start,
+            duration,
+        }
+    }
+}
+
+/// Writes a copy of the profile to the files with the given path stem, with
+/// the annotations added as interval events, so that externally measured
+/// phases show up in the analysis tools next to the recorded events.
+///
+/// The copy contains the same events, but its strings are written anew, so
+/// it doesn't refer to a shared string cache, and the ids of event kinds
+/// registered via `measureme::Profiler::register_event_kind()` and of
+/// reserved strings are not carried over. Per-thread event files are merged
+/// into the copy's `.events` file.
+pub fn annotate(
+    data: &ProfilingData,
+    annotations: &[Annotation],
+    path_stem: &Path,
+) -> Result<(), Box<dyn Error>> {
+    if data.metadata.aggregate_only {
+        Err("the profile has been recorded in aggregate-only mode and has no events to annotate")?;
+    }
+
+    let origin = data.metadata.start_time;
+    let annotation_thread = data
+        .iter()
+        .map(|e| e.thread_id)
+        .max()
+        .map_or(0, |id| id + 1);
+
+    let mut events: Vec<Event<'_>> = data.iter().map(|e| e.to_event()).collect();
+    for annotation in annotations {
+        let start = origin + annotation.start;
+
+        events.push(Event {
+            event_kind: Cow::from(&annotation.event_kind[..]),
+            label: Cow::from(&annotation.label[..]),
+            additional_data: Vec::new(),
+            timestamp: Timestamp::Interval {
+                start,
+                end: start + annotation.duration,
+            },
+            thread_id: annotation.thread_id.unwrap_or(annotation_thread),
+            integer_value: None,
+            cpu: None,
+            blocked: false,
+        });
+    }
+
+    // The recorded events are already ordered by the time they end, the
+    // stable sort keeps their order.
+    events.sort_by_key(|e| e.timestamp.end());
+
+    let fits_compact = annotations
+        .iter()
+        .all(|a| ((a.start + a.duration).as_nanos() as u64) <= MAX_INTERVAL_TIMESTAMP);
+    let timestamp_format = if fits_compact {
+        data.timestamp_format()
+    } else {
+        TimestampFormat::Wide
+    };
+
+    write_copy(data, &events, timestamp_format, path_stem, |_| {})
+}
+
+/// Writes the events, which must be ordered by the time they end, along
+/// with the metadata of `data` to the files with the given path stem. The
+/// metadata can be edited on the way. See `annotate()` for what isn't
+/// carried over.
+pub(crate) fn write_copy(
+    data: &ProfilingData,
+    events: &[Event<'_>],
+    timestamp_format: TimestampFormat,
+    path_stem: &Path,
+    edit_metadata: impl FnOnce(&mut Value),
+) -> Result<(), Box<dyn Error>> {
+    let builder = ProfilingDataBuilder::with_timestamp_format(timestamp_format);
+    write_copy_with(builder, data, events, path_stem, edit_metadata)
+}
+
+/// Like `write_copy()`, via a builder that has been set up already. The
+/// metadata is edited after the fields that aren't carried over have been
+/// reset, so `edit_metadata` can set them.
+pub(crate) fn write_copy_with(
+    mut builder: ProfilingDataBuilder,
+    data: &ProfilingData,
+    events: &[Event<'_>],
+    path_stem: &Path,
+    edit_metadata: impl FnOnce(&mut Value),
+) -> Result<(), Box<dyn Error>> {
+    let origin = data.metadata.start_time;
+    for event in events {
+        builder.write_event(event, event.thread_id, origin);
+    }
+
+    // Profiles that haven't been loaded from files, like those of a
+    // `ProfilingDataBuilder`, have no metadata in their string table.
+    let mut metadata: Value = serde_json::from_str(&data.metadata_json())
+        .unwrap_or_else(|_| Value::Object(Default::default()));
+    if let Value::Object(fields) = &mut metadata {
+        fields.insert("shared_strings".to_string(), Value::Null);
+        for field in &[
+            "event_kinds",
+            "reserved_strings",
+            "thread_event_files",
+            "event_segments",
+        ] {
+            fields.insert(field.to_string(), Value::Array(Vec::new()));
+        }
+    }
+    edit_metadata(&mut metadata);
+
+    builder.write_files(path_stem, &metadata.to_string())
+}
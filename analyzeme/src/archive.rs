@@ -0,0 +1,364 @@
+//! Reading profiles straight from `.tar`, `.tar.gz` (or `.tgz`) and `.zip`
+//! archives, as they are often attached to issues or stored as CI artifacts.
+//!
+//! A path like `artifacts.zip/profiles/foo-1234` refers to the profile with
+//! the path stem `profiles/foo-1234` inside of `artifacts.zip`. If the archive
+//! contains a single profile, the path of the archive itself is enough, and
+//! a file name like `foo-1234` is enough as long as it is unique, so users
+//! don't need to know about the directory prefixes inside of the archive.
+//!
+//! Archives are recognized by their contents, so renamed ones like the
+//! `profile.bin` a CI system stores an upload as work too. The file extension
+//! is only a fallback for archives whose start is damaged.
+
+use crate::{LoadError, LoadErrorKind};
+use measureme::ProfilerFiles;
+use rustc_hash::FxHashMap;
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::{Path, PathBuf};
+
+/// Enough of the start of a file to tell the archive formats apart: tar
+/// archives have their magic at offset 257.
+const SNIFF_LEN: usize = 512;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ArchiveFormat {
+    Tar,
+    TarGz,
+    Zip,
+}
+
+impl ArchiveFormat {
+    /// The format of the archive at `path`, judging by its first bytes or,
+    /// failing that, its file extension.
+    fn detect(path: &Path) -> Option<ArchiveFormat> {
+        let mut start = Vec::with_capacity(SNIFF_LEN);
+        if let Ok(file) = File::open(path) {
+            // Directories can be opened, but not read.
+            let _ = file.take(SNIFF_LEN as u64).read_to_end(&mut start);
+        }
+
+        ArchiveFormat::sniff(&start).or_else(|| ArchiveFormat::of(path))
+    }
+
+    fn sniff(start: &[u8]) -> Option<ArchiveFormat> {
+        if start.starts_with(&[0x1f, 0x8b]) {
+            Some(ArchiveFormat::TarGz)
+        } else if start.starts_with(b"PK\x03\x04") || start.starts_with(b"PK\x05\x06") {
+            // The latter is an empty archive.
+            Some(ArchiveFormat::Zip)
+        } else if start.get(257..262) == Some(b"ustar") {
+            Some(ArchiveFormat::Tar)
+        } else {
+            None
+        }
+    }
+
+    fn of(path: &Path) -> Option<ArchiveFormat> {
+        let name = path.file_name()?.to_str()?;
+
+        if name.ends_with(".tar") {
+            Some(ArchiveFormat::Tar)
+        } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Some(ArchiveFormat::TarGz)
+        } else if name.ends_with(".zip") {
+            Some(ArchiveFormat::Zip)
+        } else {
+            None
+        }
+    }
+}
+
+/// Whether `path` is an existing file in one of the supported archive
+/// formats.
+pub(crate) fn is_archive(path: &Path) -> bool {
+    path.is_file() && ArchiveFormat::detect(path).is_some()
+}
+
+/// If `path` points into an archive, i.e. it or one of its ancestors is an
+/// existing archive file, returns the path of the archive and the rest of
+/// `path`.
+pub(crate) fn split_archive_path(path: &Path) -> Option<(&Path, &Path)> {
+    path.ancestors()
+        .filter(|ancestor| !ancestor.as_os_str().is_empty())
+        .find(|ancestor| is_archive(ancestor))
+        .map(|archive| (archive, path.strip_prefix(archive).unwrap()))
+}
+
+/// The profile files of an archive, decompressed into memory.
+pub(crate) struct Archive {
+    path: PathBuf,
+    /// Keyed by their path in the archive, with `/` as the separator.
+    files: FxHashMap<String, Vec<u8>>,
+}
+
+impl Archive {
+    pub fn open(path: &Path) -> Result<Archive, Box<dyn Error>> {
+        let format = ArchiveFormat::detect(path)
+            .ok_or_else(|| format!("`{}` is not a supported archive", path.display()))?;
+
+        let unreadable = |e: &dyn std::fmt::Display| {
+            LoadError::new(
+                LoadErrorKind::FileUnreadable,
+                format!("couldn't read archive `{}`: {}", path.display(), e),
+            )
+        };
+
+        let file = File::open(path).map_err(|e| {
+            let kind = match e.kind() {
+                std::io::ErrorKind::NotFound => LoadErrorKind::FileMissing,
+                _ => LoadErrorKind::FileUnreadable,
+            };
+            LoadError::new(
+                kind,
+                format!("couldn't read archive `{}`: {}", path.display(), e),
+            )
+        })?;
+        let reader = BufReader::new(file);
+
+        let files = match format {
+            ArchiveFormat::Tar => read_tar(reader),
+            ArchiveFormat::TarGz => read_tar(flate2::read::GzDecoder::new(reader)),
+            ArchiveFormat::Zip => read_zip(reader),
+        }
+        .map_err(|e| unreadable(&e))?;
+
+        Ok(Archive {
+            path: path.to_path_buf(),
+            files,
+        })
+    }
+
+    /// The path stems of the profiles in the archive, sorted.
+    fn profile_stems(&self) -> Vec<&str> {
+        let mut stems: Vec<&str> = self
+            .files
+            .keys()
+            .filter_map(|name| name.strip_suffix(".events"))
+            .collect();
+        stems.sort_unstable();
+        stems
+    }
+
+    /// Finds the profile that `profile` refers to, see the module
+    /// documentation, and returns its path stem in the archive.
+    pub fn find_profile(&self, profile: &Path) -> Result<String, Box<dyn Error>> {
+        let profile = profile.to_string_lossy().replace('\\', "/");
+        let profile = profile.trim_matches('/');
+
+        let stems = self.profile_stems();
+        let matches: Vec<&str> = stems
+            .iter()
+            .copied()
+            .filter(|stem| {
+                profile.is_empty() || *stem == profile || stem.ends_with(&format!("/{}", profile))
+            })
+            .collect();
+
+        match matches[..] {
+            [stem] => Ok(stem.to_string()),
+            [] if stems.is_empty() => Err(From::from(LoadError::new(
+                LoadErrorKind::FileMissing,
+                format!(
+                    "the archive `{}` doesn't contain any profiles",
+                    self.path.display()
+                ),
+            ))),
+            [] => Err(From::from(LoadError::new(
+                LoadErrorKind::FileMissing,
+                format!(
+                    "the archive `{}` doesn't contain the profile `{}`, only: {}",
+                    self.path.display(),
+                    profile,
+                    stems.join(", ")
+                ),
+            ))),
+            _ => Err(From::from(format!(
+                "the archive `{}` contains several profiles, select one by appending its name \
+                 to the path of the archive, e.g. `{}`: {}",
+                self.path.display(),
+                self.path.join(matches[0]).display(),
+                matches.join(", ")
+            ))),
+        }
+    }
+
+    /// The name of a file in the archive for error messages, like
+    /// `artifacts.zip/foo-1234.events`.
+    pub fn display_name(&self, name: &str) -> String {
+        format!("{}/{}", self.path.display(), name)
+    }
+
+    /// Removes the file `name` from the archive and returns its contents.
+    pub fn take_file(&mut self, name: &str) -> Result<Vec<u8>, LoadError> {
+        self.files.remove(name).ok_or_else(|| {
+            LoadError::new(
+                LoadErrorKind::FileMissing,
+                format!(
+                    "the archive `{}` doesn't contain `{}`",
+                    self.path.display(),
+                    name
+                ),
+            )
+        })
+    }
+}
+
+/// Other files than those that make up a profile are skipped.
+fn is_profile_file(name: &str) -> bool {
+    ProfilerFiles::EXTENSIONS
+        .iter()
+        .any(|extension| name.ends_with(&format!(".{}", extension)))
+}
+
+fn read_tar(reader: impl Read) -> Result<FxHashMap<String, Vec<u8>>, Box<dyn Error>> {
+    let mut files = FxHashMap::default();
+
+    for entry in tar::Archive::new(reader).entries()? {
+        let mut entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+
+        let name = normalize_name(&entry.path()?.to_string_lossy());
+        if !is_profile_file(&name) {
+            continue;
+        }
+
+        let mut data = Vec::with_capacity(entry.size() as usize);
+        entry.read_to_end(&mut data)?;
+        files.insert(name, data);
+    }
+
+    Ok(files)
+}
+
+fn read_zip(
+    reader: impl Read + std::io::Seek,
+) -> Result<FxHashMap<String, Vec<u8>>, Box<dyn Error>> {
+    let mut files = FxHashMap::default();
+    let mut archive = zip::ZipArchive::new(reader)?;
+
+    for index in 0..archive.len() {
+        let mut entry = archive.by_index(index)?;
+        if !entry.is_file() {
+            continue;
+        }
+
+        let name = normalize_name(entry.name());
+        if !is_profile_file(&name) {
+            continue;
+        }
+
+        let mut data = Vec::with_capacity(entry.size() as usize);
+        entry.read_to_end(&mut data)?;
+        files.insert(name, data);
+    }
+
+    Ok(files)
+}
+
+/// Archives created on Windows may use `\` as the separator, and tar
+/// archives created via `tar -cf x.tar .` prefix every path with `./`.
+fn normalize_name(name: &str) -> String {
+    let name = name.replace('\\', "/");
+    let mut name = &name[..];
+    while let Some(rest) = name.strip_prefix("./") {
+        name = rest;
+    }
+    name.trim_start_matches('/').to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn archive(names: &[&str]) -> Archive {
+        Archive {
+            path: PathBuf::from("artifacts.zip"),
+            files: names
+                .iter()
+                .map(|name| (name.to_string(), Vec::new()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn finding_profiles() {
+        let single = archive(&[
+            "target/profiles/foo-1.events",
+            "target/profiles/foo-1.string_data",
+            "target/profiles/foo-1.string_index",
+        ]);
+        assert_eq!(
+            single.find_profile(Path::new("")).unwrap(),
+            "target/profiles/foo-1"
+        );
+        assert_eq!(
+            single.find_profile(Path::new("foo-1")).unwrap(),
+            "target/profiles/foo-1"
+        );
+        assert!(single.find_profile(Path::new("bar-1")).is_err());
+
+        let several = archive(&["a/foo-1.events", "b/foo-1.events", "b/bar-2.events"]);
+        assert!(several.find_profile(Path::new("")).is_err());
+        assert!(several.find_profile(Path::new("foo-1")).is_err());
+        assert_eq!(
+            several.find_profile(Path::new("b/foo-1")).unwrap(),
+            "b/foo-1"
+        );
+        assert_eq!(several.find_profile(Path::new("bar-2")).unwrap(), "b/bar-2");
+
+        let error = archive(&[]).find_profile(Path::new("")).err().unwrap();
+        assert_eq!(
+            LoadError::kind_of(&*error),
+            Some(LoadErrorKind::FileMissing)
+        );
+    }
+
+    #[test]
+    fn names() {
+        assert_eq!(normalize_name("./././foo.events"), "foo.events");
+        assert_eq!(normalize_name("dir\\foo.events"), "dir/foo.events");
+        assert_eq!(normalize_name("/abs/foo.events"), "abs/foo.events");
+        assert!(is_profile_file("x/foo.string_index"));
+        assert!(!is_profile_file("x/foo.json"));
+    }
+
+    #[test]
+    fn formats() {
+        assert_eq!(
+            ArchiveFormat::of(Path::new("a.tar")),
+            Some(ArchiveFormat::Tar)
+        );
+        assert_eq!(
+            ArchiveFormat::of(Path::new("a.tar.gz")),
+            Some(ArchiveFormat::TarGz)
+        );
+        assert_eq!(
+            ArchiveFormat::of(Path::new("a.tgz")),
+            Some(ArchiveFormat::TarGz)
+        );
+        assert_eq!(
+            ArchiveFormat::of(Path::new("dir/a.zip")),
+            Some(ArchiveFormat::Zip)
+        );
+        assert_eq!(ArchiveFormat::of(Path::new("foo-1234")), None);
+
+        let mut tar = vec![0; 512];
+        tar[257..262].copy_from_slice(b"ustar");
+        assert_eq!(ArchiveFormat::sniff(&tar), Some(ArchiveFormat::Tar));
+        assert_eq!(
+            ArchiveFormat::sniff(&[0x1f, 0x8b, 8, 0]),
+            Some(ArchiveFormat::TarGz)
+        );
+        assert_eq!(
+            ArchiveFormat::sniff(b"PK\x03\x04rest"),
+            Some(ArchiveFormat::Zip)
+        );
+        assert_eq!(ArchiveFormat::sniff(b"MMES"), None);
+        assert_eq!(ArchiveFormat::sniff(&[]), None);
+    }
+}
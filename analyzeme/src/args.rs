@@ -0,0 +1,144 @@
+use crate::event::Event;
+use measureme::ArgType;
+use serde::{de, Deserialize, Deserializer};
+#[cfg(feature = "serialize")]
+use serde::{Serialize, Serializer};
+use std::borrow::Cow;
+use std::fmt;
+
+/// The name and type of an event argument, as registered via
+/// `measureme::Profiler::register_arg_schema()`.
+#[derive(Clone, Debug, Eq, PartialEq, Deserialize)]
+#[cfg_attr(feature = "serialize", derive(Serialize))]
+pub struct ArgSchema {
+    pub name: String,
+    #[serde(rename = "type", deserialize_with = "arg_type_from_name")]
+    #[cfg_attr(feature = "serialize", serde(serialize_with = "arg_type_to_name"))]
+    pub arg_type: ArgType,
+}
+
+#[cfg(feature = "serialize")]
+fn arg_type_to_name<S>(arg_type: &ArgType, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    serializer.serialize_str(arg_type.name())
+}
+
+fn arg_type_from_name<'de, D>(deserializer: D) -> Result<ArgType, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let name = String::deserialize(deserializer)?;
+    ArgType::from_name(&name)
+        .ok_or_else(|| de::Error::custom(format!("unknown argument type `{}`", name)))
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub enum ArgValue<'a> {
+    String(Cow<'a, str>),
+    U64(u64),
+    Bool(bool),
+    /// The symbol is set if the profile has a symbolizer that knows the
+    /// address, see `ProfilingData::set_symbolizer()`.
+    Address {
+        address: u64,
+        symbol: Option<String>,
+    },
+    /// Where in the source code the event has been recorded, see
+    /// `measureme::SourceLocation`.
+    Location {
+        file: Cow<'a, str>,
+        line: u32,
+        column: u32,
+    },
+}
+
+impl fmt::Display for ArgValue<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArgValue::String(s) => f.write_str(s),
+            ArgValue::U64(n) => write!(f, "{}", n),
+            ArgValue::Bool(b) => write!(f, "{}", b),
+            ArgValue::Address {
+                symbol: Some(symbol),
+                ..
+            } => f.write_str(symbol),
+            ArgValue::Address {
+                address,
+                symbol: None,
+            } => write!(f, "{:#x}", address),
+            ArgValue::Location { file, line, column } => {
+                write!(f, "{}:{}:{}", file, line, column)
+            }
+        }
+    }
+}
+
+/// An event argument, decoded according to the argument schema of its event
+/// kind. See `ProfilingData::decode_args()`.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct Arg<'a> {
+    /// `None` if there is no schema for the argument.
+    pub name: Option<&'a str>,
+    pub value: ArgValue<'a>,
+}
+
+impl fmt::Display for Arg<'_> {
+    /// Displays the argument as `name=value`, or just as `value` if the
+    /// argument has no name.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.name {
+            Some(name) => write!(f, "{}={}", name, self.value),
+            None => write!(f, "{}", self.value),
+        }
+    }
+}
+
+/// Decodes the arguments of `event` according to `schema`. Arguments without
+/// a schema entry, and arguments that don't parse as the type their schema
+/// declares, are returned as strings.
+pub(crate) fn decode_args<'a>(schema: Option<&'a [ArgSchema]>, event: &Event<'a>) -> Vec<Arg<'a>> {
+    event
+        .additional_data
+        .iter()
+        .enumerate()
+        .map(|(i, arg)| {
+            let schema = schema.and_then(|schema| schema.get(i));
+
+            let value = match schema.map(|s| s.arg_type) {
+                Some(ArgType::U64) => arg.parse().ok().map(ArgValue::U64),
+                Some(ArgType::Bool) => arg.parse().ok().map(ArgValue::Bool),
+                Some(ArgType::Address) => arg
+                    .strip_prefix("0x")
+                    .and_then(|hex| u64::from_str_radix(hex, 16).ok())
+                    .map(|address| ArgValue::Address {
+                        address,
+                        symbol: None,
+                    }),
+                Some(ArgType::Location) => parse_location(arg),
+                Some(ArgType::String) | None => None,
+            };
+
+            Arg {
+                name: schema.map(|s| &s.name[..]),
+                value: value.unwrap_or_else(|| ArgValue::String(arg.clone())),
+            }
+        })
+        .collect()
+}
+
+/// Parses a location recorded as `file:line:column`. The file name may
+/// contain colons itself, e.g. after a Windows drive letter.
+fn parse_location<'a>(arg: &Cow<'a, str>) -> Option<ArgValue<'a>> {
+    let mut parts = arg.rsplitn(3, ':');
+    let column = parts.next()?.parse().ok()?;
+    let line = parts.next()?.parse().ok()?;
+    let file_len = parts.next()?.len();
+
+    let file = match arg {
+        Cow::Borrowed(arg) => Cow::Borrowed(&arg[..file_len]),
+        Cow::Owned(arg) => Cow::Owned(arg[..file_len].to_string()),
+    };
+    Some(ArgValue::Location { file, line, column })
+}
@@ -0,0 +1,272 @@
+//! Writing `EventColumns` as Arrow IPC files and reading them back, with the
+//! `arrow` feature.
+
+use crate::columns::{Dictionary, EventColumns};
+use arrow_array::cast::AsArray;
+use arrow_array::types::{UInt32Type, UInt64Type};
+use arrow_array::{
+    Array, ArrayRef, DictionaryArray, ListArray, RecordBatch, StringArray, UInt32Array, UInt64Array,
+};
+use arrow_buffer::OffsetBuffer;
+use arrow_ipc::reader::FileReader;
+use arrow_ipc::writer::FileWriter;
+use arrow_schema::{DataType, Field, Schema};
+use std::error::Error;
+use std::io::{Read, Seek, Write};
+use std::sync::Arc;
+
+/// The number of events per record batch of the files written by
+/// `EventColumns::write_arrow()`.
+const BATCH_ROWS: usize = 64 * 1024;
+
+fn dictionary_type() -> DataType {
+    DataType::Dictionary(Box::new(DataType::UInt32), Box::new(DataType::Utf8))
+}
+
+fn args_field() -> Arc<Field> {
+    Arc::new(Field::new("item", dictionary_type(), false))
+}
+
+fn schema() -> Schema {
+    Schema::new(vec![
+        Field::new("thread", DataType::UInt32, false),
+        Field::new("kind", dictionary_type(), false),
+        Field::new("label", dictionary_type(), false),
+        Field::new("start", DataType::UInt64, false),
+        Field::new("end", DataType::UInt64, true),
+        Field::new("integer_value", DataType::UInt64, true),
+        Field::new("args", DataType::List(args_field()), false),
+    ])
+}
+
+impl EventColumns {
+    /// Writes the events as an Arrow IPC file, so that large profiles can be
+    /// analyzed with tools like DataFusion, Polars or DuckDB. The file holds
+    /// a single table with one row per event and the columns
+    ///
+    /// - `thread` (`UInt32`),
+    /// - `kind` and `label` (`Dictionary<UInt32, Utf8>`),
+    /// - `start` and `end` (`UInt64`, nanoseconds since the start of the
+    ///   profile; `end` is null for instant and integer events),
+    /// - `integer_value` (`UInt64`, null for anything but integer events),
+    ///   and
+    /// - `args` (`List<Dictionary<UInt32, Utf8>>`).
+    ///
+    /// The dictionary of the string columns is `strings`, so every distinct
+    /// string is written once per column instead of once per event. The rows
+    /// are written in record batches of 64Ki events.
+    ///
+    /// Only available with the `arrow` feature.
+    pub fn write_arrow<W: Write>(&self, writer: W) -> Result<(), Box<dyn Error>> {
+        self.write_arrow_batches(writer, BATCH_ROWS)
+    }
+
+    fn write_arrow_batches<W: Write>(
+        &self,
+        writer: W,
+        batch_rows: usize,
+    ) -> Result<(), Box<dyn Error>> {
+        let schema = Arc::new(schema());
+        let strings: ArrayRef = Arc::new(StringArray::from_iter_values(&self.strings));
+        let dictionary = |keys: &[u32]| -> Result<ArrayRef, Box<dyn Error>> {
+            let keys = UInt32Array::from(keys.to_vec());
+            Ok(Arc::new(DictionaryArray::try_new(keys, strings.clone())?))
+        };
+
+        let offsets = self.args_offsets.iter().map(|&offset| offset as usize);
+        let args = ListArray::try_new(
+            args_field(),
+            OffsetBuffer::from_lengths(offsets.clone().zip(offsets.skip(1)).map(|(a, b)| b - a)),
+            dictionary(&self.args)?,
+            None,
+        )?;
+
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                Arc::new(UInt32Array::from(self.thread_id.clone())),
+                dictionary(&self.kind)?,
+                dictionary(&self.label)?,
+                Arc::new(UInt64Array::from(self.start.clone())),
+                Arc::new(UInt64Array::from(self.end.clone())),
+                Arc::new(UInt64Array::from(self.integer_value.clone())),
+                Arc::new(args),
+            ],
+        )?;
+
+        let mut writer = FileWriter::try_new(writer, &schema)?;
+        for offset in (0..self.len()).step_by(batch_rows) {
+            writer.write(&batch.slice(offset, batch_rows.min(self.len() - offset)))?;
+        }
+        writer.finish()?;
+        Ok(())
+    }
+
+    /// Reads an Arrow IPC file with the schema of the files written by
+    /// `write_arrow()`, e.g. one that has been filtered by another tool.
+    ///
+    /// Only available with the `arrow` feature.
+    pub fn read_arrow<R: Read + Seek>(reader: R) -> Result<EventColumns, Box<dyn Error>> {
+        let mut columns = EventColumns::default();
+        let mut dictionary = Dictionary::default();
+        let mut kinds = DictionaryColumn::default();
+        let mut labels = DictionaryColumn::default();
+        let mut args = DictionaryColumn::default();
+
+        columns.args_offsets.push(0);
+
+        for batch in FileReader::try_new(reader, None)? {
+            let batch = batch?;
+            let column = |name: &str| {
+                batch
+                    .column_by_name(name)
+                    .ok_or_else(|| format!("the Arrow file has no column `{}`", name))
+            };
+            let wrong_type = |name: &str| format!("the column `{}` has the wrong type", name);
+
+            let thread = column("thread")?
+                .as_primitive_opt::<UInt32Type>()
+                .ok_or_else(|| wrong_type("thread"))?;
+            let start = column("start")?
+                .as_primitive_opt::<UInt64Type>()
+                .ok_or_else(|| wrong_type("start"))?;
+            let end = column("end")?
+                .as_primitive_opt::<UInt64Type>()
+                .ok_or_else(|| wrong_type("end"))?;
+            let integer_value = column("integer_value")?
+                .as_primitive_opt::<UInt64Type>()
+                .ok_or_else(|| wrong_type("integer_value"))?;
+            let event_args = column("args")?
+                .as_list_opt::<i32>()
+                .ok_or_else(|| wrong_type("args"))?;
+
+            if thread.null_count() > 0 || start.null_count() > 0 || event_args.null_count() > 0 {
+                Err("the columns `thread`, `start` and `args` must not contain nulls")?;
+            }
+
+            columns.thread_id.extend(thread.values().iter().copied());
+            columns.start.extend(start.values().iter().copied());
+            columns.end.extend(end.iter());
+            columns.integer_value.extend(integer_value.iter());
+
+            kinds.append(&mut dictionary, column("kind")?, &mut columns.kind)?;
+            labels.append(&mut dictionary, column("label")?, &mut columns.label)?;
+
+            // The values of a sliced list array don't start at its first
+            // offset.
+            let first_offset = event_args.value_offsets()[0];
+            let values = event_args.values().slice(
+                first_offset as usize,
+                event_args.value_offsets()[batch.num_rows()] as usize - first_offset as usize,
+            );
+            let base = columns.args.len() as u32;
+            args.append(&mut dictionary, &values, &mut columns.args)?;
+            columns.args_offsets.extend(
+                event_args.value_offsets()[1..]
+                    .iter()
+                    .map(|&offset| base + (offset - first_offset) as u32),
+            );
+        }
+
+        columns.strings = dictionary.into_strings();
+        Ok(columns)
+    }
+}
+
+/// Maps the keys of a dictionary-encoded column of an Arrow file to the
+/// dictionary of `EventColumns`. The file reader hands out the same values
+/// array for every batch, so the mapping is computed once per dictionary.
+#[derive(Default)]
+struct DictionaryColumn {
+    values: Option<ArrayRef>,
+    indices: Vec<u32>,
+}
+
+impl DictionaryColumn {
+    fn append(
+        &mut self,
+        dictionary: &mut Dictionary,
+        array: &ArrayRef,
+        out: &mut Vec<u32>,
+    ) -> Result<(), Box<dyn Error>> {
+        let array = array
+            .as_dictionary_opt::<UInt32Type>()
+            .ok_or("the string columns must be dictionaries with `UInt32` keys")?;
+        let values = array.values();
+
+        if !self
+            .values
+            .as_ref()
+            .is_some_and(|known| Arc::ptr_eq(known, values))
+        {
+            let strings = values
+                .as_string_opt::<i32>()
+                .ok_or("the values of the string columns must be `Utf8`")?;
+            self.indices = strings
+                .iter()
+                .map(|s| dictionary.intern(s.unwrap_or("")))
+                .collect();
+            self.values = Some(values.clone());
+        }
+
+        if array.keys().null_count() > 0 {
+            Err("the string columns must not contain nulls")?;
+        }
+        for &key in array.keys().values().iter() {
+            let index = self
+                .indices
+                .get(key as usize)
+                .ok_or("the Arrow file refers to a string beyond the end of its dictionary")?;
+            out.push(*index);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{EventColumns, ProfilingDataBuilder};
+    use std::io::Cursor;
+
+    #[test]
+    fn round_trip() {
+        let mut b = ProfilingDataBuilder::new();
+        b.interval("Query", "typeck\u{1e}main\u{1e}42", 0, 10, 100, |b| {
+            b.interval("Query", "typeck", 0, 20, 30, |_| {});
+            b.instant("QueryCacheHit", "type_of\u{1e}main", 0, 40);
+        });
+        b.integer("CacheSize", "cache", 1, 50, 4711);
+        b.interval("GenericActivity", "codegen\u{1e}main", 1, 60, 90, |_| {});
+        let columns = b.into_profiling_data().to_columns();
+
+        // Small batches, so that the reader has to put several of them
+        // together.
+        for &batch_rows in &[1, 2, super::BATCH_ROWS] {
+            let mut file = Vec::new();
+            columns.write_arrow_batches(&mut file, batch_rows).unwrap();
+
+            let read = EventColumns::read_arrow(Cursor::new(file)).unwrap();
+            assert_eq!(read, columns);
+        }
+    }
+
+    #[test]
+    fn empty() {
+        let columns = ProfilingDataBuilder::new()
+            .into_profiling_data()
+            .to_columns();
+
+        let mut file = Vec::new();
+        columns.write_arrow(&mut file).unwrap();
+        assert_eq!(
+            EventColumns::read_arrow(Cursor::new(file)).unwrap(),
+            columns
+        );
+    }
+
+    #[test]
+    fn not_an_arrow_file() {
+        assert!(EventColumns::read_arrow(Cursor::new(b"MMES".to_vec())).is_err());
+    }
+}
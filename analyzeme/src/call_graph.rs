@@ -0,0 +1,305 @@
+use crate::{ProfilingData, TimelineEvent};
+use rustc_hash::FxHashMap;
+use std::fmt::Write;
+use std::time::Duration;
+
+/// Which labels are recorded while which other labels are active, derived
+/// from how the events nest. Unlike a flamegraph, which shows every distinct
+/// stack on its own, this merges all events with the same label into one
+/// node, which shows where the time flows between activities even if they
+/// are reached through many different paths.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CallGraph {
+    /// Ordered by total time, longest first.
+    pub nodes: Vec<CallGraphNode>,
+    /// Ordered by time, longest first.
+    pub edges: Vec<CallGraphEdge>,
+    /// The time of the events that aren't nested in other events.
+    pub total_time: Duration,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CallGraphNode {
+    pub label: String,
+    pub count: u64,
+    /// The time spent in events with this label, minus the time spent in
+    /// the events nested in them.
+    pub self_time: Duration,
+    /// The time spent in events with this label. Events nested in an event
+    /// with the same label (i.e. recursion) are only counted once.
+    pub total_time: Duration,
+}
+
+/// The events with the label of `child` that have been recorded directly
+/// within events with the label of `parent`, given as indices into
+/// `CallGraph::nodes`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CallGraphEdge {
+    pub parent: usize,
+    pub child: usize,
+    pub count: u64,
+    pub time: Duration,
+}
+
+/// Builds the call graph of the interval events of all threads.
+pub fn call_graph(data: &ProfilingData) -> CallGraph {
+    #[derive(Default)]
+    struct Builder {
+        nodes: FxHashMap<String, CallGraphNode>,
+        edges: FxHashMap<(String, String), (u64, Duration)>,
+        total_time: Duration,
+        // The labels of the events the event being visited is nested in.
+        stack: Vec<String>,
+    }
+
+    impl Builder {
+        fn visit(&mut self, event: &TimelineEvent<'_>) {
+            let duration = match event.event.duration() {
+                Some(duration) => duration,
+                None => return,
+            };
+            let label = event.event.to_event().label.into_owned();
+
+            let children: Duration = event
+                .children
+                .iter()
+                .filter_map(|child| child.event.duration())
+                .sum();
+            let recursive = self.stack.contains(&label);
+
+            let node = self
+                .nodes
+                .entry(label.clone())
+                .or_insert_with(|| CallGraphNode {
+                    label: label.clone(),
+                    count: 0,
+                    self_time: Duration::ZERO,
+                    total_time: Duration::ZERO,
+                });
+            node.count += 1;
+            node.self_time += duration.saturating_sub(children);
+            if !recursive {
+                node.total_time += duration;
+            }
+
+            match self.stack.last() {
+                Some(parent) => {
+                    let edge = self
+                        .edges
+                        .entry((parent.clone(), label.clone()))
+                        .or_default();
+                    edge.0 += 1;
+                    edge.1 += duration;
+                }
+                None => self.total_time += duration,
+            }
+
+            self.stack.push(label);
+            for child in &event.children {
+                self.visit(child);
+            }
+            self.stack.pop();
+        }
+    }
+
+    let mut builder = Builder::default();
+    for timeline in data.per_thread_timelines() {
+        for event in &timeline.events {
+            builder.visit(event);
+        }
+    }
+
+    let mut nodes: Vec<CallGraphNode> = builder.nodes.drain().map(|(_, node)| node).collect();
+    nodes.sort_by(|a, b| {
+        b.total_time
+            .cmp(&a.total_time)
+            .then_with(|| a.label.cmp(&b.label))
+    });
+    let indices: FxHashMap<&str, usize> = nodes
+        .iter()
+        .enumerate()
+        .map(|(index, node)| (&node.label[..], index))
+        .collect();
+
+    let mut edges: Vec<CallGraphEdge> = builder
+        .edges
+        .drain()
+        .map(|((parent, child), (count, time))| CallGraphEdge {
+            parent: indices[&parent[..]],
+            child: indices[&child[..]],
+            count,
+            time,
+        })
+        .collect();
+    edges.sort_by(|a, b| {
+        b.time
+            .cmp(&a.time)
+            .then_with(|| (a.parent, a.child).cmp(&(b.parent, b.child)))
+    });
+
+    CallGraph {
+        nodes,
+        edges,
+        total_time: builder.total_time,
+    }
+}
+
+impl CallGraph {
+    /// Renders the graph in the DOT language of Graphviz, e.g. for
+    /// `dot -Tsvg`. Nodes and edges that account for less than
+    /// `min_fraction` of the total time of the graph are left out, which
+    /// keeps the graphs of large profiles readable. The width of an edge
+    /// reflects its time.
+    pub fn to_dot(&self, min_fraction: f64) -> String {
+        let threshold = self.total_time.as_secs_f64() * min_fraction;
+        let shown = |time: Duration| time.as_secs_f64() >= threshold;
+        let max_edge_time = self
+            .edges
+            .iter()
+            .map(|edge| edge.time)
+            .max()
+            .unwrap_or_default()
+            .as_secs_f64();
+
+        let mut dot = String::new();
+        dot.push_str("digraph calls {\n");
+        dot.push_str("    node [shape=box, fontname=\"monospace\"];\n");
+
+        for (index, node) in self.nodes.iter().enumerate() {
+            if !shown(node.total_time) {
+                continue;
+            }
+
+            writeln!(
+                dot,
+                "    n{} [label=\"{}\\nself {:.2?}, total {:.2?}\\n{}x\"];",
+                index,
+                escape(&node.label),
+                node.self_time,
+                node.total_time,
+                node.count
+            )
+            .unwrap();
+        }
+
+        for edge in &self.edges {
+            let parent = &self.nodes[edge.parent];
+            let child = &self.nodes[edge.child];
+            if !shown(edge.time) || !shown(parent.total_time) || !shown(child.total_time) {
+                continue;
+            }
+
+            let width = if max_edge_time > 0.0 {
+                1.0 + 4.0 * edge.time.as_secs_f64() / max_edge_time
+            } else {
+                1.0
+            };
+
+            writeln!(
+                dot,
+                "    n{} -> n{} [label=\"{:.2?}, {}x\", penwidth={:.1}];",
+                edge.parent, edge.child, edge.time, edge.count, width
+            )
+            .unwrap();
+        }
+
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+fn escape(label: &str) -> String {
+    label.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ProfilingDataBuilder;
+
+    #[test]
+    fn nesting() {
+        // thread 0:  typeck (100) { mir_borrowck (30) { mir_built (10) } }
+        //            typeck (50) { mir_built (20) { mir_"built" (5) } }
+        // thread 1:  mir_built (40)
+        let mut b = ProfilingDataBuilder::new();
+        b.interval("Query", "typeck", 0, 0, 100, |b| {
+            b.interval("Query", "mir_borrowck", 0, 10, 40, |b| {
+                b.interval("Query", "mir_built", 0, 20, 30, |_| {});
+            });
+        });
+        b.interval("Query", "typeck", 0, 200, 250, |b| {
+            b.interval("Query", "mir_built", 0, 210, 230, |b| {
+                b.interval("Query", "mir_\"built\"", 0, 215, 220, |_| {});
+            });
+        });
+        b.interval("Query", "mir_built", 1, 0, 40, |_| {});
+        b.instant("Marker", "done", 1, 50);
+
+        let graph = call_graph(&b.into_profiling_data());
+        let node = |label: &str| {
+            let n = graph.nodes.iter().find(|n| n.label == label).unwrap();
+            (n.count, n.self_time.as_nanos(), n.total_time.as_nanos())
+        };
+
+        assert_eq!(node("typeck"), (2, 70 + 30, 150));
+        assert_eq!(node("mir_borrowck"), (1, 20, 30));
+        assert_eq!(node("mir_built"), (3, 10 + 15 + 40, 70));
+        assert_eq!(graph.nodes[0].label, "typeck");
+        assert_eq!(graph.total_time.as_nanos(), 190);
+
+        let edges: Vec<_> = graph
+            .edges
+            .iter()
+            .map(|e| {
+                (
+                    &graph.nodes[e.parent].label[..],
+                    &graph.nodes[e.child].label[..],
+                    e.count,
+                    e.time.as_nanos(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            edges,
+            vec![
+                ("typeck", "mir_borrowck", 1, 30),
+                ("typeck", "mir_built", 1, 20),
+                ("mir_borrowck", "mir_built", 1, 10),
+                ("mir_built", "mir_\"built\"", 1, 5),
+            ]
+        );
+
+        let dot = graph.to_dot(0.0);
+        assert!(dot.starts_with("digraph calls {\n"), "{}", dot);
+        assert!(
+            dot.contains("[label=\"typeck\\nself 100.00ns, total 150.00ns\\n2x\"];"),
+            "{}",
+            dot
+        );
+        assert!(dot.contains("mir_\\\"built\\\""), "{}", dot);
+        let typeck = graph
+            .nodes
+            .iter()
+            .position(|n| n.label == "typeck")
+            .unwrap();
+        let borrowck = graph
+            .nodes
+            .iter()
+            .position(|n| n.label == "mir_borrowck")
+            .unwrap();
+        assert!(
+            dot.contains(&format!(
+                "n{} -> n{} [label=\"30.00ns, 1x\", penwidth=5.0];",
+                typeck, borrowck
+            )),
+            "{}",
+            dot
+        );
+
+        // 10% of the 190ns in top-level events.
+        let dot = graph.to_dot(0.1);
+        assert!(!dot.contains("mir_\\\"built\\\""), "{}", dot);
+        assert!(dot.contains("mir_borrowck"), "{}", dot);
+    }
+}
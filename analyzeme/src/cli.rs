@@ -0,0 +1,80 @@
+//! Command line options that all tools share, to be included in a tool's
+//! options with `#[structopt(flatten)]`, and the handling of the errors the
+//! tools return. Only available with the `cli` feature.
+//!
+//! Every tool has a library with a `main_with_args()` function that returns
+//! the error the tool fails with, so that the tools can also be run as
+//! commands of the `mm` binary. Their binaries, like `mm`, pass the result to
+//! `exit_on_error()`.
+
+use crate::diagnostics::{init_logging, Diagnostic, MessageFormat, Verbosity};
+use std::error::Error;
+use std::ffi::OsString;
+use std::path::PathBuf;
+use std::process;
+use structopt::StructOpt;
+
+/// Prints the error of `result`, if any, in the message format the tool
+/// has been asked for with `OutputOpts`, and exits with status 1.
+pub fn exit_on_error(result: Result<(), Box<dyn Error>>) {
+    if let Err(error) = result {
+        Diagnostic::from_error(&*error).log();
+        process::exit(1);
+    }
+}
+
+/// The `--message-format`, `--quiet`, `--verbose` and `--log` flags. They
+/// are global, so for tools with sub commands they may also follow the sub
+/// command.
+#[derive(StructOpt, Debug)]
+pub struct OutputOpts {
+    /// How to print errors and warnings: `human`, or `json` for one JSON
+    /// object per message on stderr
+    #[structopt(long = "message-format", default_value = "human", raw(global = "true"))]
+    pub message_format: MessageFormat,
+
+    /// Only print errors, not warnings
+    #[structopt(long = "quiet", raw(global = "true"))]
+    pub quiet: bool,
+
+    /// Also print what is being done, e.g. which files are loaded
+    #[structopt(long = "verbose", raw(global = "true"))]
+    pub verbose: bool,
+
+    /// Append all messages, including those not printed, to this file as
+    /// one JSON object per line
+    #[structopt(long = "log", parse(from_os_str), raw(global = "true"))]
+    pub log: Option<PathBuf>,
+}
+
+impl OutputOpts {
+    /// Calls `init_logging()` with the flags. If the log file can't be
+    /// opened, messages are still printed in the requested format, so that
+    /// the returned error is too.
+    pub fn init(&self) -> Result<(), Box<dyn Error>> {
+        let verbosity = Verbosity::from_flags(self.quiet, self.verbose);
+        init_logging(verbosity, self.message_format, self.log.as_deref()).inspect_err(|_| {
+            let _ = init_logging(verbosity, self.message_format, None);
+        })
+    }
+
+    /// The flags these options have been parsed from, for passing them on
+    /// to another tool.
+    pub fn to_args(&self) -> Vec<OsString> {
+        let mut args = Vec::new();
+        if self.message_format == MessageFormat::Json {
+            args.push("--message-format=json".into());
+        }
+        if self.quiet {
+            args.push("--quiet".into());
+        }
+        if self.verbose {
+            args.push("--verbose".into());
+        }
+        if let Some(log) = &self.log {
+            args.push("--log".into());
+            args.push(log.into());
+        }
+        args
+    }
+}
@@ -0,0 +1,198 @@
+use crate::event::Event;
+use crate::ProfilingData;
+use measureme::StringId;
+use rustc_hash::FxHashMap;
+
+/// A column-oriented copy of the events of a profile, for analyses that only
+/// look at a few fields of every event, like the queries of `cargo mm sql`.
+/// See `ProfilingData::to_columns()`.
+///
+/// Event kinds, labels and arguments are dictionary-encoded: they are stored
+/// as indices into `strings`. Since the dictionary is built from the string
+/// table of the profile, every distinct string is decoded only once.
+///
+/// With the `arrow` feature, the columns can be written as an Arrow IPC file
+/// via `write_arrow()` and read back via `read_arrow()`, see these
+/// methods for the schema of the files.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct EventColumns {
+    pub thread_id: Vec<u32>,
+    pub kind: Vec<u32>,
+    pub label: Vec<u32>,
+    /// Nanoseconds since the start of the profile.
+    pub start: Vec<u64>,
+    /// Nanoseconds since the start of the profile, or `None` for instant
+    /// events.
+    pub end: Vec<Option<u64>>,
+    pub integer_value: Vec<Option<u64>>,
+    /// The arguments of event `i` are `args[args_offsets[i]..args_offsets[i + 1]]`.
+    /// This column has one more entry than the others.
+    pub args_offsets: Vec<u32>,
+    pub args: Vec<u32>,
+    /// The dictionary that the `kind`, `label` and `args` columns refer to.
+    pub strings: Vec<String>,
+}
+
+impl EventColumns {
+    pub fn len(&self) -> usize {
+        self.thread_id.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.thread_id.is_empty()
+    }
+
+    pub fn kind(&self, index: usize) -> &str {
+        &self.strings[self.kind[index] as usize]
+    }
+
+    pub fn label(&self, index: usize) -> &str {
+        &self.strings[self.label[index] as usize]
+    }
+
+    pub fn args(&self, index: usize) -> impl Iterator<Item = &str> + '_ {
+        let range = self.args_offsets[index] as usize..self.args_offsets[index + 1] as usize;
+        self.args[range]
+            .iter()
+            .map(move |&arg| &self.strings[arg as usize][..])
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct Dictionary {
+    strings: Vec<String>,
+    indices: FxHashMap<String, u32>,
+}
+
+impl Dictionary {
+    pub(crate) fn intern(&mut self, s: &str) -> u32 {
+        if let Some(&index) = self.indices.get(s) {
+            return index;
+        }
+
+        let index = self.strings.len() as u32;
+        self.strings.push(s.to_string());
+        self.indices.insert(s.to_string(), index);
+        index
+    }
+
+    pub(crate) fn into_strings(self) -> Vec<String> {
+        self.strings
+    }
+}
+
+/// See `ProfilingData::to_columns()`.
+pub(crate) fn to_columns(data: &ProfilingData) -> EventColumns {
+    let mut columns = EventColumns::default();
+    let mut dictionary = Dictionary::default();
+
+    // Caches the dictionary entries for the kinds and event ids seen so far.
+    let mut kinds = FxHashMap::<StringId, u32>::default();
+    let mut event_ids = FxHashMap::<StringId, (u32, Vec<u32>)>::default();
+
+    columns.args_offsets.push(0);
+
+    for index in 0..data.num_events() {
+        let raw_event = data.raw_event(index);
+
+        let kind = *kinds
+            .entry(raw_event.event_kind)
+            .or_insert_with(|| dictionary.intern(&data.event_kind_str(raw_event.event_kind)));
+
+        let event_id = raw_event.event_id.to_string_id();
+        let (label, args) = event_ids.entry(event_id).or_insert_with(|| {
+            let (label, args) =
+                Event::parse_event_id(data.string_table().get(event_id).to_string());
+            let label = dictionary.intern(&label);
+            let args = args.iter().map(|arg| dictionary.intern(arg)).collect();
+            (label, args)
+        });
+
+        columns.thread_id.push(raw_event.thread_id);
+        columns.kind.push(kind);
+        columns.label.push(*label);
+        columns.args.extend_from_slice(args);
+        columns.args_offsets.push(columns.args.len() as u32);
+
+        columns.start.push(raw_event.start_nanos());
+        columns.end.push(if raw_event.is_instant() {
+            None
+        } else {
+            Some(raw_event.end_nanos())
+        });
+        columns.integer_value.push(raw_event.integer_value());
+    }
+
+    columns.strings = dictionary.into_strings();
+    columns
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ProfilingDataBuilder;
+
+    #[test]
+    fn columns() {
+        let mut b = ProfilingDataBuilder::new();
+
+        b.interval("Query", "typeck", 0, 10, 100, |b| {
+            b.interval("Query", "typeck", 0, 20, 30, |_| {});
+        });
+        b.instant("QueryCacheHit", "typeck", 1, 40);
+        b.integer("CacheSize", "cache", 1, 50, 4711);
+
+        let columns = b.into_profiling_data().to_columns();
+
+        assert_eq!(columns.len(), 4);
+        assert_eq!(columns.thread_id, vec![0, 0, 1, 1]);
+        assert_eq!(columns.start, vec![20, 10, 40, 50]);
+        assert_eq!(columns.end, vec![Some(30), Some(100), None, None]);
+        assert_eq!(columns.integer_value, vec![None, None, None, Some(4711)]);
+
+        let labels: Vec<_> = (0..columns.len()).map(|i| columns.label(i)).collect();
+        assert_eq!(labels, vec!["typeck", "typeck", "typeck", "cache"]);
+        assert_eq!(columns.kind(2), "QueryCacheHit");
+
+        // Each distinct string is stored once.
+        assert_eq!(
+            columns.strings,
+            vec!["Query", "typeck", "QueryCacheHit", "CacheSize", "cache"]
+        );
+        assert_eq!(columns.args_offsets, vec![0, 0, 0, 0, 0]);
+        assert_eq!(columns.args(0).count(), 0);
+    }
+
+    // The columns hold everything needed to reconstruct the events.
+    #[test]
+    fn columns_match_events() {
+        let mut b = ProfilingDataBuilder::new();
+        b.interval("Query", "typeck\u{1e}main\u{1e}42", 3, 10, 100, |b| {
+            b.instant("QueryCacheHit", "type_of\u{1e}main", 3, 20);
+        });
+        b.integer("CacheSize", "cache", 4, 50, 4711);
+
+        let data = b.into_profiling_data();
+        let columns = data.to_columns();
+        assert_eq!(columns.len(), data.num_events());
+
+        for (i, event) in data.iter().map(|e| e.to_event()).enumerate() {
+            assert_eq!(columns.kind(i), event.event_kind);
+            assert_eq!(columns.label(i), event.label);
+            assert!(columns
+                .args(i)
+                .eq(event.additional_data.iter().map(|arg| &arg[..])));
+            assert_eq!(columns.thread_id[i], event.thread_id);
+            assert_eq!(columns.integer_value[i], event.integer_value);
+
+            let nanos = |time: std::time::SystemTime| {
+                time.duration_since(data.metadata.start_time)
+                    .unwrap()
+                    .as_nanos() as u64
+            };
+            assert_eq!(columns.start[i], nanos(event.timestamp.start()));
+            let end = (!event.timestamp.is_instant()).then(|| nanos(event.timestamp.end()));
+            assert_eq!(columns.end[i], end);
+        }
+        assert_eq!(columns.args(1).collect::<Vec<_>>(), vec!["main", "42"]);
+    }
+}
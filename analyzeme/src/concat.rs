@@ -0,0 +1,150 @@
+use crate::annotate::write_copy;
+use crate::{Event, ProfilingData};
+use measureme::TimestampFormat;
+use rustc_hash::FxHashMap;
+use serde_json::Value;
+use std::error::Error;
+use std::path::Path;
+use std::time::SystemTime;
+
+/// Writes the events of several shards of one recording into a single
+/// profile with the given path stem, e.g. of a profile whose event segments
+/// (`measureme::EventLayout::Rollover`) or per-thread event files have been
+/// archived to different places. Every shard is a profile of its own that
+/// has been loaded with whatever segments and thread files it still has.
+///
+///   - The shards must be of the same recording, i.e. have the same start
+///     time and process id.
+///   - On every thread, the events of one shard must all have ended before
+///     the events of the next shard that has events on the thread, in any
+///     order of the shards. Overlapping shards, e.g. two copies containing
+///     the same segment, are rejected.
+///   - Strings are written anew, so that string ids, which the string
+///     tables of the shards may assign differently, are remapped. Thread ids
+///     are kept. See `annotate()` for what else isn't carried over.
+///
+/// The metadata is taken from the first shard, except that the argument
+/// schemas of all shards are combined and the profile counts as truncated
+/// if any of them is. A single shard is rewritten as a profile with a
+/// single `.events` file.
+pub fn concatenate_profiles(
+    shards: &[ProfilingData],
+    path_stem: &Path,
+) -> Result<(), Box<dyn Error>> {
+    let first = match shards.first() {
+        Some(first) => first,
+        None => Err("there are no profiles to concatenate")?,
+    };
+
+    for (index, shard) in shards.iter().enumerate() {
+        if shard.metadata.aggregate_only {
+            Err(format!(
+                "shard {} has been recorded in aggregate-only mode and has no events",
+                index + 1
+            ))?;
+        }
+        if shard.metadata.start_time != first.metadata.start_time
+            || shard.metadata.process_id != first.metadata.process_id
+        {
+            Err(format!(
+                "shard {} is not of the same recording as shard 1: their start times or \
+                 process ids differ",
+                index + 1
+            ))?;
+        }
+    }
+
+    check_order(shards)?;
+
+    let mut events: Vec<Event<'_>> = shards
+        .iter()
+        .flat_map(|shard| shard.iter().map(|e| e.to_event()))
+        .collect();
+    events.sort_by_key(|e| e.timestamp.end());
+
+    let timestamp_format = if shards
+        .iter()
+        .all(|shard| shard.timestamp_format() == TimestampFormat::Compact)
+    {
+        TimestampFormat::Compact
+    } else {
+        TimestampFormat::Wide
+    };
+
+    // The shards share the metadata of the recording, so only the schemas
+    // and the state of the recording need combining.
+    let mut arg_schemas = serde_json::Map::new();
+    for shard in shards {
+        let metadata: Value = serde_json::from_str(&shard.metadata_json())?;
+        if let Some(Value::Object(schemas)) = metadata.get("arg_schemas") {
+            for (event_kind, schema) in schemas {
+                if !arg_schemas.contains_key(event_kind) {
+                    arg_schemas.insert(event_kind.clone(), schema.clone());
+                }
+            }
+        }
+    }
+    let truncated = shards.iter().any(|shard| shard.metadata.truncated);
+    let budget_exhausted = shards.iter().any(|shard| shard.metadata.budget_exhausted);
+    let dropped_events = shards
+        .iter()
+        .map(|shard| shard.metadata.dropped_events)
+        .max()
+        .unwrap_or(0);
+
+    write_copy(first, &events, timestamp_format, path_stem, |metadata| {
+        if let Value::Object(fields) = metadata {
+            fields.insert("arg_schemas".to_string(), Value::Object(arg_schemas));
+            fields.insert("truncated".to_string(), Value::from(truncated));
+            fields.insert("dropped_events".to_string(), Value::from(dropped_events));
+            fields.insert(
+                "budget_exhausted".to_string(),
+                Value::from(budget_exhausted),
+            );
+        }
+    })
+}
+
+/// Checks that the shards don't overlap on any thread, see
+/// `concatenate_profiles()`.
+fn check_order(shards: &[ProfilingData]) -> Result<(), Box<dyn Error>> {
+    // The earliest and latest end of the events of every shard on every
+    // thread. Events are recorded when they end, so these are the parts of
+    // the recording a shard covers.
+    let ranges: Vec<FxHashMap<u32, (SystemTime, SystemTime)>> = shards
+        .iter()
+        .map(|shard| {
+            let mut ranges = FxHashMap::<u32, (SystemTime, SystemTime)>::default();
+            for event in shard.iter() {
+                let end = event.timestamp.end();
+                let range = ranges.entry(event.thread_id).or_insert((end, end));
+                range.0 = range.0.min(end);
+                range.1 = range.1.max(end);
+            }
+            ranges
+        })
+        .collect();
+
+    for (i, a) in ranges.iter().enumerate() {
+        for (j, b) in ranges.iter().enumerate().skip(i + 1) {
+            for (thread_id, &(a_first, a_last)) in a {
+                let (b_first, b_last) = match b.get(thread_id) {
+                    Some(&range) => range,
+                    None => continue,
+                };
+
+                if a_first < b_last && b_first < a_last {
+                    Err(format!(
+                        "shards {} and {} overlap on thread {}, e.g. because both contain the \
+                         same events",
+                        i + 1,
+                        j + 1,
+                        thread_id
+                    ))?;
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
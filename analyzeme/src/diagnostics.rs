@@ -0,0 +1,54 @@
+//! The errors, warnings and logging of the tools are shared with the writing
+//! side and live in `measureme::diagnostics`, which this module re-exports,
+//! see there. What is left here is specific to loading profiles.
+
+pub use measureme::diagnostics::{
+    init_logging, Diagnostic, Level, LoadError, LoadErrorKind, MessageFormat, Verbosity,
+};
+use std::str::FromStr;
+
+/// How thoroughly `ProfilingData::new_validated()` and
+/// `from_bytes_validated()` check a profile, selected via `--validate` in
+/// tools. Checking takes time linear in the size of the files.
+///
+/// Without checking, decoding never panics, but a corrupt profile decodes
+/// to placeholders like `<unknown>`, intervals that end before they start
+/// have no duration, and strings can expand to far more data than the files
+/// contain. With either of the checking levels, a profile is corrupt if
+///
+///   - a string of an event is missing, isn't valid UTF-8 or lacks its
+///     terminator,
+///   - strings are nested more than a few levels deep (i.e. their
+///     components might form a cycle) or expand to more than
+///     `stringtable::MAX_STRING_LEN` bytes,
+///   - an event's kind or id is out of the range of string ids,
+///   - an interval ends before it starts, or
+///   - the metadata refers to invalid string ids.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Validation {
+    /// Don't check anything beyond the file headers and footers.
+    #[default]
+    Off,
+    /// Skip the invalid events, each with a warning, see
+    /// `ProfilingData::warnings()`. A profile with invalid metadata still
+    /// can't be loaded, as the metadata is needed to make sense of the rest.
+    Lenient,
+    /// Return a `LoadErrorKind::Corrupt` error for the first problem.
+    Strict,
+}
+
+impl FromStr for Validation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Validation, String> {
+        match s {
+            "off" => Ok(Validation::Off),
+            "lenient" => Ok(Validation::Lenient),
+            "strict" => Ok(Validation::Strict),
+            other => Err(format!(
+                "invalid validation level `{}`, expected `off`, `lenient` or `strict`",
+                other
+            )),
+        }
+    }
+}
@@ -0,0 +1,201 @@
+use crate::annotate::write_copy_with;
+use crate::{Event, ProfilingData, ProfilingDataBuilder};
+use measureme::{FileSerializationSink, ProfilerFiles, SharedStringCache, StringId};
+use rustc_hash::{FxHashMap, FxHashSet};
+use serde_json::Value;
+use std::borrow::Cow;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// What `share_strings()` did.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct SharingStats {
+    /// The number of profiles that have been rewritten.
+    pub profiles: usize,
+    /// The number of strings in the dictionary.
+    pub shared_strings: usize,
+    /// The size of the files of the profiles before.
+    pub bytes_before: u64,
+    /// The size of the files of the profiles and of the dictionary after.
+    pub bytes_after: u64,
+}
+
+/// Factors the strings (event kinds, labels and arguments) that at least
+/// `min_profiles` of the given profiles have in common out into a
+/// dictionary at `dictionary_stem`, which is a shared string table like the
+/// one of a `measureme::SharedStringCache`, and rewrites the profiles in
+/// place to refer to it. The strings that stay in a profile are written
+/// only once. Since the profiles of a corpus mostly contain the same
+/// strings, this makes their string tables a small fraction of what they
+/// were.
+///
+/// The profiles have to be in the directory of the dictionary, which is
+/// where `ProfilingData::new()` looks for it, and the dictionary must be kept
+/// as long as any of them is. All profiles are rewritten before any of them
+/// is replaced, so nothing is changed if one of them can't be loaded. The
+/// events of profiles recorded per thread or in segments end up in a single
+/// `.events` file, and profiles that used another shared string cache don't
+/// anymore. Aggregate-only profiles have no events to rewrite and are
+/// rejected.
+pub fn share_strings(
+    path_stems: &[PathBuf],
+    dictionary_stem: &Path,
+    min_profiles: usize,
+) -> Result<SharingStats, Box<dyn Error>> {
+    let dictionary_name = dictionary_stem
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or("the dictionary needs a file name")?;
+    for path_stem in path_stems {
+        if path_stem.parent() != dictionary_stem.parent() {
+            Err(format!(
+                "`{}` isn't in the directory of the dictionary",
+                path_stem.display()
+            ))?;
+        }
+    }
+
+    let mut stats = SharingStats {
+        profiles: path_stems.len(),
+        ..SharingStats::default()
+    };
+
+    // The number of profiles every string occurs in.
+    let mut occurrences = FxHashMap::<String, usize>::default();
+    for path_stem in path_stems {
+        let data = load(path_stem)?;
+        stats.bytes_before += files_size(path_stem, data.metadata.extra_event_files());
+
+        let mut strings = FxHashSet::<Cow<'_, str>>::default();
+        for event in data.iter() {
+            let event = event.to_event();
+            strings.insert(event.event_kind);
+            strings.insert(event.label);
+            strings.extend(event.additional_data);
+        }
+        for s in strings {
+            match occurrences.get_mut(&s[..]) {
+                Some(count) => *count += 1,
+                None => {
+                    occurrences.insert(s.into_owned(), 1);
+                }
+            }
+        }
+    }
+
+    let mut shared: Vec<String> = occurrences
+        .into_iter()
+        .filter(|&(_, count)| count >= min_profiles)
+        .map(|(s, _)| s)
+        .collect();
+    shared.sort();
+    stats.shared_strings = shared.len();
+
+    let temp_dictionary = temp_stem(dictionary_stem);
+    let dictionary = SharedStringCache::<FileSerializationSink>::new(&temp_dictionary)?;
+    let ids: FxHashMap<String, StringId> = shared
+        .into_iter()
+        .map(|s| {
+            let id = dictionary.intern(&s);
+            (s, id)
+        })
+        .collect();
+    drop(dictionary);
+
+    let mut rewritten = Vec::new();
+    let result = path_stems.iter().try_for_each(|path_stem| {
+        let data = load(path_stem)?;
+        let events: Vec<Event<'_>> = data.iter().map(|e| e.to_event()).collect();
+
+        let mut builder = ProfilingDataBuilder::with_timestamp_format(data.timestamp_format());
+        builder.intern_strings(ids.clone());
+        let temp = temp_stem(path_stem);
+        let extra_files: Vec<String> = data.metadata.extra_event_files().cloned().collect();
+        rewritten.push((path_stem, temp.clone(), extra_files));
+
+        write_copy_with(builder, &data, &events, &temp, |metadata| {
+            if let Value::Object(fields) = metadata {
+                fields.insert("shared_strings".to_string(), Value::from(dictionary_name));
+            }
+        })
+    });
+    if let Err(e) = result {
+        remove_files(&temp_dictionary);
+        for (_, temp, _) in &rewritten {
+            remove_files(temp);
+        }
+        return Err(e);
+    }
+
+    rename_files(&temp_dictionary, dictionary_stem)?;
+    stats.bytes_after += files_size(dictionary_stem, None);
+    for (path_stem, temp, extra_files) in rewritten {
+        for file_name in &extra_files {
+            fs::remove_file(path_stem.with_file_name(file_name))?;
+        }
+        rename_files(&temp, path_stem)?;
+        stats.bytes_after += files_size(path_stem, None);
+    }
+
+    Ok(stats)
+}
+
+fn load(path_stem: &Path) -> Result<ProfilingData, Box<dyn Error>> {
+    let data = ProfilingData::new(path_stem)
+        .map_err(|e| format!("could not load `{}`: {}", path_stem.display(), e))?;
+    if data.metadata.aggregate_only {
+        Err(format!(
+            "`{}` has been recorded in aggregate-only mode and has no events to rewrite",
+            path_stem.display()
+        ))?;
+    }
+    Ok(data)
+}
+
+/// Where a rewritten profile or the dictionary is written before it
+/// replaces the original.
+fn temp_stem(path_stem: &Path) -> PathBuf {
+    let mut file_name = path_stem.file_name().unwrap_or_default().to_os_string();
+    file_name.push("_sharing");
+    path_stem.with_file_name(file_name)
+}
+
+fn main_files(path_stem: &Path) -> Vec<PathBuf> {
+    let files = ProfilerFiles::new(path_stem);
+    vec![
+        files.events_file,
+        files.string_data_file,
+        files.string_index_file,
+    ]
+}
+
+/// The size of the files of the profile or dictionary at `path_stem`,
+/// including the given extra event files.
+fn files_size<'a>(path_stem: &Path, extra_files: impl IntoIterator<Item = &'a String>) -> u64 {
+    main_files(path_stem)
+        .into_iter()
+        .chain(
+            extra_files
+                .into_iter()
+                .map(|file_name| path_stem.with_file_name(file_name)),
+        )
+        .filter_map(|file| fs::metadata(file).ok())
+        .map(|metadata| metadata.len())
+        .sum()
+}
+
+fn rename_files(from: &Path, to: &Path) -> Result<(), Box<dyn Error>> {
+    for (from, to) in main_files(from).iter().zip(&main_files(to)) {
+        if from.exists() {
+            fs::rename(from, to)?;
+        }
+    }
+    Ok(())
+}
+
+fn remove_files(path_stem: &Path) {
+    for file in &main_files(path_stem) {
+        let _ = fs::remove_file(file);
+    }
+}
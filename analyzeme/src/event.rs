@@ -1,15 +1,31 @@
 use crate::timestamp::Timestamp;
 use memchr::memchr;
+#[cfg(feature = "serialize")]
+use serde::{Deserialize, Serialize};
 use std::borrow::Cow;
 use std::time::Duration;
 
+/// With the `serialize` feature, events implement `Serialize` and
+/// `Deserialize`. Deserialized events own their strings.
 #[derive(Clone, Eq, PartialEq, Hash, Debug)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
 pub struct Event<'a> {
     pub event_kind: Cow<'a, str>,
     pub label: Cow<'a, str>,
     pub additional_data: Vec<Cow<'a, str>>,
     pub timestamp: Timestamp,
     pub thread_id: u32,
+    /// The payload of an integer event, recorded via
+    /// `measureme::Profiler::record_integer_event()`. Integer events are
+    /// instant events.
+    pub integer_value: Option<u64>,
+    /// The CPU the event started on, if the profile has been recorded with
+    /// `measureme::ProfilerConfig::record_cpu`.
+    pub cpu: Option<u32>,
+    /// True for intervals in which the thread was blocked instead of running,
+    /// recorded via
+    /// `measureme::Profiler::start_recording_blocked_interval_event()`.
+    pub blocked: bool,
 }
 
 impl<'a> Event<'a> {
@@ -0,0 +1,602 @@
+//! Iterator adapters for transforms that many tools need, so that they can be
+//! written as a pipeline:
+//!
+//! ```ignore
+//! use analyzeme::EventIteratorExt;
+//!
+//! for nested in data
+//!     .iter()
+//!     .filter_kind("Query")
+//!     .clip_to_range(start, end)
+//!     .with_nesting()
+//! {
+//!     println!("{:indent$}{}", "", nested.event.to_event().label, indent = nested.depth * 2);
+//! }
+//! ```
+//!
+//! All adapters work on any iterator of `LightweightEvent`s in event stream
+//! order (or at least in stream order per thread), including the output of
+//! the other adapters.
+
+use crate::lightweight_event::LightweightEvent;
+use crate::timestamp::Timestamp;
+use measureme::StringId;
+use rustc_hash::FxHashMap;
+use std::cmp::Reverse;
+use std::time::{Duration, SystemTime};
+
+pub trait EventIteratorExt<'a>: Iterator<Item = LightweightEvent<'a>> + Sized {
+    /// Only yields the events with the given event kind, e.g. `"Query"`.
+    fn filter_kind(self, event_kind: &str) -> FilterKind<Self> {
+        FilterKind {
+            iter: self,
+            event_kind: event_kind.to_string(),
+            registered: None,
+            matches: FxHashMap::default(),
+        }
+    }
+
+    /// Only yields the events that overlap the time range from `start` to
+    /// `end`, with intervals shortened so that they lie within the range.
+    fn clip_to_range(self, start: SystemTime, end: SystemTime) -> ClipToRange<Self> {
+        ClipToRange {
+            iter: self,
+            start,
+            end,
+        }
+    }
+
+    /// Merges intervals that directly follow each other on the same thread
+    /// and have the same kind and label into a single interval, if the gap
+    /// between them is at most `threshold`. The merged event keeps the
+    /// `event_index` of the first interval.
+    ///
+    /// An event is only yielded once the next event of its thread is known,
+    /// so the output is in stream order per thread, but events of different
+    /// threads can be reordered.
+    fn merge_adjacent(self, threshold: Duration) -> MergeAdjacent<'a, Self> {
+        MergeAdjacent {
+            iter: self,
+            threshold,
+            pending: FxHashMap::default(),
+            remaining: None,
+        }
+    }
+
+    /// Coalesces runs of intervals that follow each other on the same thread,
+    /// have the same kind and label (but not necessarily the same arguments)
+    /// and are each shorter than `max_duration` (as is the gap between them)
+    /// into a single synthetic interval from the start of the first to the
+    /// end of the last one, which carries the number of intervals it
+    /// replaces. The synthetic event keeps the `event_index`, and thus the
+    /// arguments, of the first interval. Other events are yielded with a
+    /// count of 1.
+    ///
+    /// This is meant as a pre-pass for visual exporters, so that viewers
+    /// stay responsive for profiles with millions of tiny events. Like
+    /// `merge_adjacent()`, the output is in stream order per thread.
+    fn coalesce_short(self, max_duration: Duration) -> CoalesceShort<'a, Self> {
+        CoalesceShort {
+            iter: self,
+            max_duration,
+            pending: FxHashMap::default(),
+            remaining: None,
+        }
+    }
+
+    /// Yields every event together with its nesting depth on its thread,
+    /// where top-level events have depth 0. This has to look at the parents
+    /// of an event, which are recorded after their children, so it reads the
+    /// whole underlying iterator up front.
+    fn with_nesting(self) -> WithNesting<'a> {
+        WithNesting {
+            events: nesting_depths(self.collect()).into_iter(),
+        }
+    }
+}
+
+impl<'a, I: Iterator<Item = LightweightEvent<'a>>> EventIteratorExt<'a> for I {}
+
+pub struct FilterKind<I> {
+    iter: I,
+    event_kind: String,
+    // The raw event kind of `event_kind` if it is a registered event kind, in
+    // which case matching events only takes an integer comparison. Looked up
+    // when the first event comes along.
+    registered: Option<Option<StringId>>,
+    // Caches the result of the string comparison per event kind string.
+    matches: FxHashMap<StringId, bool>,
+}
+
+impl<'a, I: Iterator<Item = LightweightEvent<'a>>> Iterator for FilterKind<I> {
+    type Item = LightweightEvent<'a>;
+
+    fn next(&mut self) -> Option<LightweightEvent<'a>> {
+        let FilterKind {
+            iter,
+            event_kind,
+            registered,
+            matches,
+        } = self;
+
+        iter.find(|event| {
+            let kind = event.data.raw_event(event.event_index).event_kind;
+
+            let registered =
+                *registered.get_or_insert_with(|| event.data.registered_event_kind(event_kind));
+            if registered == Some(kind) {
+                return true;
+            }
+            if kind.event_kind_index().is_some() {
+                // Registered event kinds have unique names.
+                return false;
+            }
+
+            *matches
+                .entry(kind)
+                .or_insert_with(|| event.data.event_kind_str(kind) == event_kind.as_str())
+        })
+    }
+}
+
+pub struct ClipToRange<I> {
+    iter: I,
+    start: SystemTime,
+    end: SystemTime,
+}
+
+impl<'a, I: Iterator<Item = LightweightEvent<'a>>> Iterator for ClipToRange<I> {
+    type Item = LightweightEvent<'a>;
+
+    fn next(&mut self) -> Option<LightweightEvent<'a>> {
+        for mut event in &mut self.iter {
+            let timestamp = match event.timestamp {
+                Timestamp::Interval { start, end } => {
+                    if end < self.start || start > self.end {
+                        continue;
+                    }
+
+                    Timestamp::Interval {
+                        start: start.max(self.start),
+                        end: end.min(self.end),
+                    }
+                }
+                Timestamp::Instant(t) => {
+                    if t < self.start || t > self.end {
+                        continue;
+                    }
+
+                    Timestamp::Instant(t)
+                }
+            };
+
+            event.timestamp = timestamp;
+            return Some(event);
+        }
+
+        None
+    }
+}
+
+pub struct MergeAdjacent<'a, I> {
+    iter: I,
+    threshold: Duration,
+    // The last event per thread, which might still be merged with the next
+    // one.
+    pending: FxHashMap<u32, LightweightEvent<'a>>,
+    // The pending events once `iter` is exhausted, in reverse stream order.
+    remaining: Option<Vec<LightweightEvent<'a>>>,
+}
+
+impl<'a, I> MergeAdjacent<'a, I> {
+    fn can_merge(&self, prev: &LightweightEvent<'a>, next: &LightweightEvent<'a>) -> bool {
+        let (prev_end, next_start) = match (prev.timestamp, next.timestamp) {
+            (Timestamp::Interval { end, .. }, Timestamp::Interval { start, .. }) => (end, start),
+            _ => return false,
+        };
+
+        // Overlapping intervals are nested, not adjacent.
+        let within_threshold = match next_start.duration_since(prev_end) {
+            Ok(gap) => gap <= self.threshold,
+            Err(_) => false,
+        };
+
+        if !within_threshold {
+            return false;
+        }
+
+        same_kind_and_label(prev, next)
+    }
+}
+
+fn same_kind_and_label(a: &LightweightEvent<'_>, b: &LightweightEvent<'_>) -> bool {
+    let a_raw = a.data.raw_event(a.event_index);
+    let b_raw = b.data.raw_event(b.event_index);
+
+    // The same string can be stored more than once, so only compare the
+    // strings themselves if the IDs differ.
+    let same_string = |x: StringId, y: StringId| {
+        x == y || {
+            let string_table = a.data.string_table();
+            string_table.get(x).to_string() == string_table.get(y).to_string()
+        }
+    };
+
+    let data = a.data;
+    (a_raw.event_kind == b_raw.event_kind
+        || data.event_kind_str(a_raw.event_kind) == data.event_kind_str(b_raw.event_kind))
+        && same_string(a_raw.event_id.to_string_id(), b_raw.event_id.to_string_id())
+}
+
+impl<'a, I: Iterator<Item = LightweightEvent<'a>>> Iterator for MergeAdjacent<'a, I> {
+    type Item = LightweightEvent<'a>;
+
+    fn next(&mut self) -> Option<LightweightEvent<'a>> {
+        if self.remaining.is_none() {
+            while let Some(event) = self.iter.next() {
+                let mut prev = match self.pending.remove(&event.thread_id) {
+                    Some(prev) => prev,
+                    None => {
+                        self.pending.insert(event.thread_id, event);
+                        continue;
+                    }
+                };
+
+                if self.can_merge(&prev, &event) {
+                    prev.timestamp = Timestamp::Interval {
+                        start: prev.timestamp.start(),
+                        end: event.timestamp.end(),
+                    };
+                    self.pending.insert(prev.thread_id, prev);
+                } else {
+                    self.pending.insert(event.thread_id, event);
+                    return Some(prev);
+                }
+            }
+
+            let mut remaining: Vec<_> = std::mem::take(&mut self.pending).into_values().collect();
+            remaining.sort_by_key(|event| Reverse(event.event_index));
+            self.remaining = Some(remaining);
+        }
+
+        self.remaining.as_mut().unwrap().pop()
+    }
+}
+
+/// An event and the number of events it replaces, see
+/// `EventIteratorExt::coalesce_short()`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CoalescedEvent<'a> {
+    pub event: LightweightEvent<'a>,
+    pub count: usize,
+}
+
+pub struct CoalesceShort<'a, I> {
+    iter: I,
+    max_duration: Duration,
+    // The last event per thread, which might still be coalesced with the
+    // next one.
+    pending: FxHashMap<u32, CoalescedEvent<'a>>,
+    // The pending events once `iter` is exhausted, in reverse stream order.
+    remaining: Option<Vec<CoalescedEvent<'a>>>,
+}
+
+impl<'a, I> CoalesceShort<'a, I> {
+    fn is_short(&self, event: &LightweightEvent<'a>) -> bool {
+        match event.duration() {
+            Some(duration) => duration < self.max_duration,
+            None => false,
+        }
+    }
+
+    fn can_coalesce(&self, prev: &CoalescedEvent<'a>, next: &LightweightEvent<'a>) -> bool {
+        // The first event of a run is the one that has to be short, the
+        // synthetic event grows with every event added to it.
+        let first_is_short = prev.count > 1 || self.is_short(&prev.event);
+        if !first_is_short || !self.is_short(next) {
+            return false;
+        }
+
+        // Overlapping intervals are nested, not consecutive.
+        let short_gap = match next
+            .timestamp
+            .start()
+            .duration_since(prev.event.timestamp.end())
+        {
+            Ok(gap) => gap < self.max_duration,
+            Err(_) => false,
+        };
+
+        if !short_gap {
+            return false;
+        }
+
+        // Only decode the events if their ids differ, e.g. because of their
+        // arguments.
+        same_kind_and_label(&prev.event, next) || {
+            let (prev, next) = (prev.event.to_event(), next.to_event());
+            prev.event_kind == next.event_kind && prev.label == next.label
+        }
+    }
+}
+
+impl<'a, I: Iterator<Item = LightweightEvent<'a>>> Iterator for CoalesceShort<'a, I> {
+    type Item = CoalescedEvent<'a>;
+
+    fn next(&mut self) -> Option<CoalescedEvent<'a>> {
+        if self.remaining.is_none() {
+            while let Some(event) = self.iter.next() {
+                let thread_id = event.thread_id;
+                let mut prev = match self.pending.remove(&thread_id) {
+                    Some(prev) => prev,
+                    None => {
+                        self.pending
+                            .insert(thread_id, CoalescedEvent { event, count: 1 });
+                        continue;
+                    }
+                };
+
+                if self.can_coalesce(&prev, &event) {
+                    prev.event.timestamp = Timestamp::Interval {
+                        start: prev.event.timestamp.start(),
+                        end: event.timestamp.end(),
+                    };
+                    prev.count += 1;
+                    self.pending.insert(thread_id, prev);
+                } else {
+                    self.pending
+                        .insert(thread_id, CoalescedEvent { event, count: 1 });
+                    return Some(prev);
+                }
+            }
+
+            let mut remaining: Vec<_> = std::mem::take(&mut self.pending).into_values().collect();
+            remaining.sort_by_key(|coalesced| Reverse(coalesced.event.event_index));
+            self.remaining = Some(remaining);
+        }
+
+        self.remaining.as_mut().unwrap().pop()
+    }
+}
+
+/// An event and its nesting depth, see `EventIteratorExt::with_nesting()`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NestedEvent<'a> {
+    pub event: LightweightEvent<'a>,
+    pub depth: usize,
+}
+
+pub struct WithNesting<'a> {
+    events: std::vec::IntoIter<NestedEvent<'a>>,
+}
+
+impl<'a> Iterator for WithNesting<'a> {
+    type Item = NestedEvent<'a>;
+
+    fn next(&mut self) -> Option<NestedEvent<'a>> {
+        self.events.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.events.size_hint()
+    }
+}
+
+// Uses the same algorithm as `per_thread_timelines()`: walking the stream
+// backwards encounters parents before their children.
+fn nesting_depths(events: Vec<LightweightEvent<'_>>) -> Vec<NestedEvent<'_>> {
+    let mut depths = vec![0; events.len()];
+    let mut stacks = FxHashMap::<u32, Vec<&LightweightEvent<'_>>>::default();
+
+    for (index, event) in events.iter().enumerate().rev() {
+        let stack = stacks.entry(event.thread_id).or_default();
+
+        while let Some(top) = stack.last() {
+            if top.contains(event) {
+                break;
+            }
+            stack.pop();
+        }
+
+        depths[index] = stack.len();
+        stack.push(event);
+    }
+
+    events
+        .into_iter()
+        .zip(depths)
+        .map(|(event, depth)| NestedEvent { event, depth })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ProfilingDataBuilder;
+    use std::time::UNIX_EPOCH;
+
+    fn time(nanos: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_nanos(nanos)
+    }
+
+    fn labels<'a>(events: impl Iterator<Item = LightweightEvent<'a>>) -> Vec<String> {
+        events.map(|e| e.to_event().label.into_owned()).collect()
+    }
+
+    #[test]
+    fn filter_kind() {
+        let mut b = ProfilingDataBuilder::new();
+        b.interval("Query", "typeck", 0, 0, 10, |_| {});
+        b.instant("QueryCacheHit", "type_of", 0, 20);
+        b.interval("Query", "mir_borrowck", 1, 30, 40, |_| {});
+
+        let data = b.into_profiling_data();
+
+        assert_eq!(
+            labels(data.iter().filter_kind("Query")),
+            vec!["typeck", "mir_borrowck"]
+        );
+        assert_eq!(
+            labels(data.iter().filter_kind("Cache")),
+            Vec::<String>::new()
+        );
+    }
+
+    #[test]
+    fn clip_to_range() {
+        let mut b = ProfilingDataBuilder::new();
+        b.interval("Query", "before", 0, 0, 10, |_| {});
+        b.interval("Query", "start", 0, 15, 30, |_| {});
+        b.instant("Query", "inside", 0, 40);
+        b.interval("Query", "end", 0, 50, 70, |_| {});
+        b.instant("Query", "after", 0, 80);
+
+        let data = b.into_profiling_data();
+        let clipped: Vec<_> = data
+            .iter()
+            .clip_to_range(time(20), time(60))
+            .map(|e| (e.to_event().label.into_owned(), e.timestamp))
+            .collect();
+
+        assert_eq!(
+            clipped,
+            vec![
+                (
+                    "start".to_string(),
+                    Timestamp::Interval {
+                        start: time(20),
+                        end: time(30)
+                    }
+                ),
+                ("inside".to_string(), Timestamp::Instant(time(40))),
+                (
+                    "end".to_string(),
+                    Timestamp::Interval {
+                        start: time(50),
+                        end: time(60)
+                    }
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn merge_adjacent() {
+        let mut b = ProfilingDataBuilder::new();
+        b.interval("Query", "typeck", 0, 0, 10, |_| {});
+        b.interval("Query", "typeck", 1, 0, 10, |_| {});
+        b.interval("Query", "typeck", 0, 12, 20, |_| {});
+        b.interval("Query", "typeck", 0, 30, 40, |_| {});
+        b.interval("Query", "type_of", 0, 41, 50, |_| {});
+        b.interval("Query", "typeck", 1, 11, 15, |_| {});
+
+        let data = b.into_profiling_data();
+        let merged: Vec<_> = data
+            .iter()
+            .merge_adjacent(Duration::from_nanos(5))
+            .map(|e| (e.thread_id, e.timestamp.start(), e.timestamp.end()))
+            .collect();
+
+        assert_eq!(
+            merged,
+            vec![
+                (0, time(0), time(20)),
+                (0, time(30), time(40)),
+                (1, time(0), time(15)),
+                (0, time(41), time(50)),
+            ]
+        );
+    }
+
+    #[test]
+    fn nested_intervals_are_not_merged() {
+        let mut b = ProfilingDataBuilder::new();
+        b.interval("Query", "typeck", 0, 0, 100, |b| {
+            b.interval("Query", "typeck", 0, 10, 20, |_| {});
+        });
+
+        let data = b.into_profiling_data();
+        assert_eq!(
+            data.iter().merge_adjacent(Duration::from_secs(1)).count(),
+            2
+        );
+    }
+
+    #[test]
+    fn coalesce_short() {
+        let mut b = ProfilingDataBuilder::new();
+        b.interval("Query", "type_of", 0, 0, 2, |_| {});
+        b.interval("Query", "type_of", 0, 3, 5, |_| {});
+        b.interval("Query", "type_of", 1, 0, 2, |_| {});
+        b.interval("Query", "type_of", 0, 6, 8, |_| {});
+        // Too long.
+        b.interval("Query", "type_of", 0, 9, 30, |_| {});
+        b.interval("Query", "type_of", 0, 31, 33, |_| {});
+        // Another label, with different arguments.
+        b.interval("Query", "typeck", 0, 34, 36, |_| {});
+        b.interval("Query", "typeck\x1efoo", 0, 37, 39, |_| {});
+        // Too far away.
+        b.interval("Query", "typeck", 0, 100, 102, |_| {});
+        b.instant("QueryCacheHit", "typeck", 0, 103);
+
+        let data = b.into_profiling_data();
+        let coalesced: Vec<_> = data
+            .iter()
+            .coalesce_short(Duration::from_nanos(10))
+            .map(|c| {
+                (
+                    c.event.to_event().label.into_owned(),
+                    c.event.thread_id,
+                    c.event.timestamp.start(),
+                    c.event.timestamp.end(),
+                    c.count,
+                )
+            })
+            .collect();
+
+        assert_eq!(
+            coalesced,
+            vec![
+                ("type_of".to_string(), 0, time(0), time(8), 3),
+                ("type_of".to_string(), 0, time(9), time(30), 1),
+                ("type_of".to_string(), 0, time(31), time(33), 1),
+                ("typeck".to_string(), 0, time(34), time(39), 2),
+                ("typeck".to_string(), 0, time(100), time(102), 1),
+                ("type_of".to_string(), 1, time(0), time(2), 1),
+                ("typeck".to_string(), 0, time(103), time(103), 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn with_nesting() {
+        let mut b = ProfilingDataBuilder::new();
+        b.interval("Query", "e1", 0, 0, 100, |b| {
+            b.interval("Query", "e2", 0, 10, 20, |_| {});
+            b.interval("Query", "e3", 0, 30, 50, |b| {
+                b.instant("Query", "e4", 0, 40);
+            });
+        });
+        b.interval("Query", "e5", 1, 10, 20, |_| {});
+
+        let data = b.into_profiling_data();
+        let nested: Vec<_> = data
+            .iter()
+            .clip_to_range(time(25), time(200))
+            .with_nesting()
+            .map(|n| (n.event.to_event().label.into_owned(), n.depth))
+            .collect();
+
+        assert_eq!(
+            nested,
+            vec![
+                ("e4".to_string(), 2),
+                ("e3".to_string(), 1),
+                ("e1".to_string(), 0),
+            ]
+        );
+
+        let all: Vec<_> = data.iter().with_nesting().map(|n| n.depth).collect();
+        assert_eq!(all, vec![1, 2, 1, 0, 0]);
+    }
+}
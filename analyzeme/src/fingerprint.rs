@@ -0,0 +1,89 @@
+use crate::{ProfilingData, Timestamp};
+use measureme::checksum::Checksum;
+use std::collections::BTreeMap;
+
+/// Changes whenever the fingerprints of the same profiles would change, so
+/// that fingerprints computed with different schemes never collide.
+const SCHEME: &[u8] = b"measureme fingerprint 1\0";
+
+const NANOS_PER_MILLI: u128 = 1_000_000;
+
+/// Hashes the event kinds and labels of the events of a profile, with the
+/// number of events and their total duration (rounded to milliseconds) per
+/// event kind and label, using XXH64. Profiles of the same work thus have
+/// the same fingerprint regardless of when, in which process or on which
+/// threads they have been recorded, of the order of their events, of their
+/// arguments and of the versions of `measureme` and of the file format that
+/// wrote them, while differences of more than a millisecond in how long
+/// anything took make for a different fingerprint.
+pub(crate) fn fingerprint(data: &ProfilingData) -> u64 {
+    let mut totals = BTreeMap::<(String, String), (u64, u128)>::new();
+
+    for event in data.iter() {
+        let event = event.to_event();
+        let nanos = match event.timestamp {
+            Timestamp::Interval { start, end } => end.duration_since(start).unwrap().as_nanos(),
+            Timestamp::Instant(_) => 0,
+        };
+
+        let (count, total) = totals
+            .entry((event.event_kind.into_owned(), event.label.into_owned()))
+            .or_default();
+        *count += 1;
+        *total += nanos;
+    }
+
+    let mut checksum = Checksum::new();
+    checksum.update(SCHEME);
+    for ((event_kind, label), (count, total)) in &totals {
+        let millis = (total + NANOS_PER_MILLI / 2) / NANOS_PER_MILLI;
+
+        for s in [event_kind, label] {
+            checksum.update(s.as_bytes());
+            checksum.update(&[0]);
+        }
+        checksum.update(&count.to_le_bytes());
+        checksum.update(&(millis as u64).to_le_bytes());
+    }
+    checksum.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ProfilingDataBuilder;
+
+    const MS: u64 = 1_000_000;
+
+    #[test]
+    fn structural() {
+        let mut b = ProfilingDataBuilder::new();
+        b.interval("Query", "typeck", 0, 0, 10 * MS, |b| {
+            b.interval("Query", "mir_borrowck", 0, 2 * MS, 5 * MS, |_| {});
+        });
+        b.instant("Marker", "done", 0, 10 * MS);
+        let fingerprint = b.into_profiling_data().fingerprint();
+
+        // Another thread, another time, another order and a bit of jitter.
+        let mut b = ProfilingDataBuilder::new();
+        b.instant("Marker", "done", 3, 50 * MS);
+        b.interval("Query", "mir_borrowck", 3, 100 * MS, 103 * MS + 100, |_| {});
+        b.interval("Query", "typeck", 3, 200 * MS, 210 * MS, |_| {});
+        assert_eq!(b.into_profiling_data().fingerprint(), fingerprint);
+
+        let mut b = ProfilingDataBuilder::new();
+        b.interval("Query", "typeck", 0, 0, 10 * MS, |b| {
+            b.interval("Query", "mir_borrowck", 0, 2 * MS, 7 * MS, |_| {});
+        });
+        b.instant("Marker", "done", 0, 10 * MS);
+        assert_ne!(b.into_profiling_data().fingerprint(), fingerprint);
+
+        let mut b = ProfilingDataBuilder::new();
+        b.interval("Query", "typeck", 0, 0, 10 * MS, |b| {
+            b.interval("Query", "mir_borrowck", 0, 2 * MS, 5 * MS, |_| {});
+        });
+        assert_ne!(b.into_profiling_data().fingerprint(), fingerprint);
+
+        // Fingerprints may be stored, so the scheme must not change.
+        assert_eq!(fingerprint, 0x187e_c64d_32ce_cc85);
+    }
+}
@@ -0,0 +1,106 @@
+use crate::ProfilingData;
+use measureme::event_kinds::{FLOW_END_EVENT_KIND, FLOW_START_EVENT_KIND};
+use rustc_hash::FxHashMap;
+use std::time::{Duration, SystemTime};
+
+/// A flow recorded via `measureme::Profiler::record_flow_start()` and
+/// `record_flow_end()`, e.g. a work item handed from one thread to another.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Flow {
+    pub flow_id: u64,
+    /// The label of the start event.
+    pub label: String,
+    pub start_thread_id: u32,
+    pub start: SystemTime,
+    pub end_thread_id: u32,
+    pub end: SystemTime,
+}
+
+impl Flow {
+    /// The time between the start and the end of the flow, e.g. how long a
+    /// work item was queued. Zero if the clocks of the threads are skewed
+    /// such that the end was recorded before the start.
+    pub fn latency(&self) -> Duration {
+        self.end.duration_since(self.start).unwrap_or_default()
+    }
+}
+
+/// Returns the flows recorded in the profile, ordered by start time. Only
+/// the first start and the first end recorded for a flow id count, and flows
+/// whose start or end is missing (e.g. because the item was still queued
+/// when the profile ended) are left out.
+pub fn find_flows(profiling_data: &ProfilingData) -> Vec<Flow> {
+    let mut starts = FxHashMap::<u64, (String, u32, SystemTime)>::default();
+    let mut ends = FxHashMap::<u64, (u32, SystemTime)>::default();
+
+    for event in profiling_data.iter().filter(|e| e.timestamp.is_instant()) {
+        let event = event.to_event();
+        let flow_id = match event.integer_value {
+            Some(flow_id) => flow_id,
+            None => continue,
+        };
+        let t = event.timestamp.start();
+
+        if event.event_kind == FLOW_START_EVENT_KIND {
+            starts
+                .entry(flow_id)
+                .or_insert_with(|| (event.label.into_owned(), event.thread_id, t));
+        } else if event.event_kind == FLOW_END_EVENT_KIND {
+            ends.entry(flow_id).or_insert((event.thread_id, t));
+        }
+    }
+
+    let mut flows: Vec<_> = starts
+        .into_iter()
+        .filter_map(|(flow_id, (label, start_thread_id, start))| {
+            let &(end_thread_id, end) = ends.get(&flow_id)?;
+            Some(Flow {
+                flow_id,
+                label,
+                start_thread_id,
+                start,
+                end_thread_id,
+                end,
+            })
+        })
+        .collect();
+
+    flows.sort_by_key(|flow| (flow.start, flow.flow_id));
+    flows
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ProfilingDataBuilder;
+    use std::time::UNIX_EPOCH;
+
+    #[test]
+    fn flows_between_threads() {
+        let mut b = ProfilingDataBuilder::new();
+
+        b.integer(FLOW_START_EVENT_KIND, "enqueue cgu", 0, 10, 1);
+        b.integer(FLOW_START_EVENT_KIND, "enqueue cgu", 0, 20, 2);
+        // Never picked up.
+        b.integer(FLOW_START_EVENT_KIND, "enqueue cgu", 0, 30, 3);
+        b.integer(FLOW_END_EVENT_KIND, "codegen", 2, 35, 2);
+        b.integer(FLOW_END_EVENT_KIND, "codegen", 1, 50, 1);
+        // Other integer events are ignored.
+        b.integer("CacheSize", "query cache", 1, 60, 1);
+
+        let flows = find_flows(&b.into_profiling_data());
+
+        let time = |nanos| UNIX_EPOCH + Duration::from_nanos(nanos);
+        let flow = |flow_id, end_thread_id, start, end| Flow {
+            flow_id,
+            label: "enqueue cgu".to_string(),
+            start_thread_id: 0,
+            start: time(start),
+            end_thread_id,
+            end: time(end),
+        };
+
+        assert_eq!(flows, vec![flow(1, 1, 10, 50), flow(2, 2, 20, 35)]);
+        assert_eq!(flows[0].latency(), Duration::from_nanos(40));
+    }
+}
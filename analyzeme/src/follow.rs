@@ -0,0 +1,369 @@
+//! Reading a profile while it is being recorded, like `tail -f`, so that the
+//! time a long build has spent so far can be watched while it runs (see
+//! `cargo mm follow`). A `ProfileFollower` keeps the files of the profile
+//! open and only decodes what has been appended to them since the last
+//! `poll()`, which keeps the totals per event kind and event id up to date
+//! at a cost proportional to the new events.
+//!
+//! The profiler buffers events and strings before writing them, so the
+//! follower lags behind the recording by up to the size of those buffers.
+//! Events whose labels haven't been written yet show up as `<unknown>` in
+//! the summary until they are. Incomplete events and string table entries
+//! at the ends of the files are left for the next poll. Once the profiler
+//! has been dropped, its files end with their footers, after which
+//! `is_finished()` returns `true`.
+//!
+//! Only profiles recorded with `measureme::EventLayout::SingleFile`, the
+//! default, can be followed. Archives, URLs and encrypted profiles can't be
+//! followed either.
+
+use crate::diagnostics::{LoadError, LoadErrorKind};
+use crate::profile_summary::ProfileSummary;
+use crate::profiling_data::{
+    check_file_header, check_same_session, load_shared_string_table, Metadata,
+};
+use crate::stringtable::StringTable;
+use measureme::checksum::Checksum;
+use measureme::file_header::{
+    file_footer, read_full_file_header, FILE_FOOTER_SIZE, FILE_HEADER_SIZE, FILE_MAGIC_FOOTER,
+    FILE_MAGIC_STRINGTABLE_DATA, FILE_MAGIC_STRINGTABLE_INDEX,
+};
+use measureme::{
+    EventId, LabelTotals, ProfilerFiles, RawEvent, StringId, TimestampFormat, TimestampResolution,
+};
+use rustc_hash::FxHashMap;
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// One of the files of a followed profile, of which everything before
+/// `pending` has been consumed.
+struct GrowingFile {
+    path: PathBuf,
+    file: File,
+    /// The bytes that have been read, but not consumed yet.
+    pending: Vec<u8>,
+    /// The checksum of the consumed bytes, for recognizing the footer.
+    checksum: Checksum,
+    finished: bool,
+}
+
+impl GrowingFile {
+    fn open(path: PathBuf, open_error: &str) -> Result<GrowingFile, LoadError> {
+        let file = File::open(&path).map_err(|e| {
+            let kind = match e.kind() {
+                io::ErrorKind::NotFound => LoadErrorKind::FileMissing,
+                _ => LoadErrorKind::FileUnreadable,
+            };
+            LoadError::new(kind, format!("{} `{}`: {}", open_error, path.display(), e))
+        })?;
+
+        Ok(GrowingFile {
+            path,
+            file,
+            pending: Vec::new(),
+            checksum: Checksum::new(),
+            finished: false,
+        })
+    }
+
+    fn read_new(&mut self) -> Result<(), LoadError> {
+        if self.finished {
+            return Ok(());
+        }
+
+        self.file.read_to_end(&mut self.pending).map_err(|e| {
+            LoadError::new(
+                LoadErrorKind::FileUnreadable,
+                format!("couldn't read `{}`: {}", self.path.display(), e),
+            )
+        })?;
+        Ok(())
+    }
+
+    /// The pending bytes, without the footer if the file is complete.
+    fn contents(&self) -> &[u8] {
+        if self.ends_with_footer() {
+            &self.pending[..self.pending.len() - FILE_FOOTER_SIZE]
+        } else {
+            &self.pending
+        }
+    }
+
+    fn ends_with_footer(&self) -> bool {
+        let contents_len = match self.pending.len().checked_sub(FILE_FOOTER_SIZE) {
+            Some(len) => len,
+            None => return false,
+        };
+        let (contents, footer) = self.pending.split_at(contents_len);
+        if &footer[..4] != FILE_MAGIC_FOOTER {
+            return false;
+        }
+
+        let mut checksum = self.checksum.clone();
+        checksum.update(contents);
+        footer == file_footer(checksum.finish())
+    }
+
+    fn consume(&mut self, len: usize) {
+        self.checksum.update(&self.pending[..len]);
+        self.pending.drain(..len);
+
+        if self.pending.len() == FILE_FOOTER_SIZE && self.ends_with_footer() {
+            self.pending.clear();
+            self.finished = true;
+        }
+    }
+}
+
+pub struct ProfileFollower {
+    path_stem: PathBuf,
+    events: GrowingFile,
+    string_data: GrowingFile,
+    string_index: GrowingFile,
+    timestamp_format: TimestampFormat,
+    timestamp_resolution: TimestampResolution,
+    /// Created once the headers of all files have been read.
+    string_table: Option<StringTable>,
+    /// The latest complete metadata of the profile, which the profiler
+    /// rewrites e.g. when an argument schema is registered.
+    metadata: Option<Metadata>,
+    totals: FxHashMap<(StringId, EventId), LabelTotals>,
+    num_events: u64,
+    latest_nanos: u64,
+}
+
+impl ProfileFollower {
+    /// Opens the files of the profile with the given path stem, which have
+    /// to exist already. Nothing is read before the first `poll()`.
+    pub fn new(path_stem: &Path) -> Result<ProfileFollower, Box<dyn Error>> {
+        let paths = ProfilerFiles::new(path_stem);
+
+        Ok(ProfileFollower {
+            path_stem: path_stem.to_path_buf(),
+            events: GrowingFile::open(paths.events_file, "couldn't open events file")?,
+            string_data: GrowingFile::open(
+                paths.string_data_file,
+                "couldn't open string_data file",
+            )?,
+            string_index: GrowingFile::open(
+                paths.string_index_file,
+                "couldn't open string_index file",
+            )?,
+            timestamp_format: TimestampFormat::default(),
+            timestamp_resolution: TimestampResolution::default(),
+            string_table: None,
+            metadata: None,
+            totals: FxHashMap::default(),
+            num_events: 0,
+            latest_nanos: 0,
+        })
+    }
+
+    /// Reads what has been appended to the files of the profile since the
+    /// last call and returns the number of new events.
+    pub fn poll(&mut self) -> Result<usize, Box<dyn Error>> {
+        self.events.read_new()?;
+        self.string_data.read_new()?;
+        self.string_index.read_new()?;
+
+        if self.string_table.is_none() && !self.read_headers()? {
+            return Ok(0);
+        }
+
+        self.read_strings()?;
+        Ok(self.read_events())
+    }
+
+    /// Returns `true` once the profiler has finished the profile, after
+    /// which `poll()` won't find anything new.
+    pub fn is_finished(&self) -> bool {
+        self.events.finished && self.string_data.finished && self.string_index.finished
+    }
+
+    /// The number of events read so far.
+    pub fn num_events(&self) -> u64 {
+        self.num_events
+    }
+
+    /// The time from the start of the profile to the latest event read so
+    /// far.
+    pub fn recorded_time(&self) -> Duration {
+        Duration::from_nanos(self.latest_nanos)
+    }
+
+    /// The metadata of the profile, once the profiler has written it.
+    pub fn metadata(&self) -> Option<&Metadata> {
+        self.metadata.as_ref()
+    }
+
+    /// The totals of the events read so far, like the `ProfileSummary` of a
+    /// complete profile.
+    pub fn summary(&self) -> ProfileSummary {
+        let string_table = match &self.string_table {
+            Some(string_table) => string_table,
+            None => return ProfileSummary { labels: Vec::new() },
+        };
+
+        ProfileSummary::from_totals(
+            self.totals.values().cloned(),
+            string_table,
+            |kind| match &self.metadata {
+                Some(metadata) => metadata.event_kind_name(kind),
+                None => kind,
+            },
+        )
+    }
+
+    /// Checks the headers of the files once they have been written and
+    /// returns whether they have.
+    fn read_headers(&mut self) -> Result<bool, Box<dyn Error>> {
+        let files = [&self.events, &self.string_data, &self.string_index];
+        if files
+            .iter()
+            .any(|file| file.pending.len() < FILE_HEADER_SIZE)
+        {
+            return Ok(false);
+        }
+        let [events_file, string_data_file, string_index_file] =
+            files.map(|file| file.path.display().to_string());
+
+        // Files with an unknown magic are reported as not being a compact
+        // events file by `check_file_header()`.
+        let timestamp_format =
+            TimestampFormat::from_file_magic(&self.events.pending[..4]).unwrap_or_default();
+        let events = (
+            &self.events.pending[..],
+            timestamp_format.file_magic(),
+            &events_file[..],
+        );
+        let string_data = (
+            &self.string_data.pending[..],
+            FILE_MAGIC_STRINGTABLE_DATA,
+            &string_data_file[..],
+        );
+        let string_index = (
+            &self.string_index.pending[..],
+            FILE_MAGIC_STRINGTABLE_INDEX,
+            &string_index_file[..],
+        );
+
+        for (data, file_magic, file_name) in [events, string_data, string_index] {
+            check_file_header(data, file_magic, file_name)?;
+        }
+        check_same_session(events, &[string_data, string_index])?;
+        let feature_flags =
+            read_full_file_header(&self.events.pending, timestamp_format.file_magic())?
+                .feature_flags;
+
+        self.string_table = Some(StringTable::new(
+            self.string_data.pending[..FILE_HEADER_SIZE].to_vec(),
+            self.string_index.pending[..FILE_HEADER_SIZE].to_vec(),
+        )?);
+        self.timestamp_format =
+            TimestampFormat::from_file_header(timestamp_format.file_magic(), feature_flags)
+                .unwrap();
+        self.timestamp_resolution = TimestampResolution::from_feature_flags(feature_flags);
+
+        for file in [
+            &mut self.events,
+            &mut self.string_data,
+            &mut self.string_index,
+        ] {
+            file.consume(FILE_HEADER_SIZE);
+        }
+        Ok(true)
+    }
+
+    fn read_strings(&mut self) -> Result<(), Box<dyn Error>> {
+        let string_table = self.string_table.as_mut().unwrap();
+
+        let string_data = self.string_data.contents();
+        let data_len = string_data.len();
+        let index_len = string_table.extend(string_data, self.string_index.contents())?;
+        self.string_data.consume(data_len);
+        self.string_index.consume(index_len);
+
+        if data_len > 0 || index_len > 0 {
+            self.read_metadata()?;
+        }
+        Ok(())
+    }
+
+    fn read_metadata(&mut self) -> Result<(), Box<dyn Error>> {
+        let string_table = self.string_table.as_mut().unwrap();
+
+        // The metadata might refer to strings that haven't been written yet.
+        let metadata = string_table.get_metadata();
+        if metadata.check(&mut FxHashMap::default()).is_err() {
+            return Ok(());
+        }
+        let metadata: Metadata = match serde_json::from_str(&metadata.to_string()) {
+            Ok(metadata) => metadata,
+            Err(_) => return Ok(()),
+        };
+
+        if !metadata.thread_event_files.is_empty() || !metadata.event_segments.is_empty() {
+            Err(
+                "profiles recorded with `EventLayout::PerThread` or `EventLayout::Rollover` \
+                 can't be followed",
+            )?;
+        }
+
+        let previous_shared = self
+            .metadata
+            .as_ref()
+            .and_then(|m| m.shared_strings.as_ref());
+        if let Some(shared_strings) = &metadata.shared_strings {
+            if previous_shared != Some(shared_strings) {
+                let shared_path_stem = self.path_stem.with_file_name(shared_strings);
+                let shared = load_shared_string_table(&shared_path_stem, None)?;
+                string_table.set_shared(Arc::new(shared));
+            }
+        }
+
+        self.metadata = Some(metadata);
+        Ok(())
+    }
+
+    fn read_events(&mut self) -> usize {
+        let event_size = self.timestamp_format.event_size();
+        let contents = self.events.contents();
+        let len = contents.len() - contents.len() % event_size;
+
+        for event in contents[..len].chunks_exact(event_size) {
+            let raw_event = RawEvent::deserialize_as(self.timestamp_format, event)
+                .from_resolution(self.timestamp_resolution);
+            let totals = self
+                .totals
+                .entry((raw_event.event_kind, raw_event.event_id))
+                .or_insert(LabelTotals {
+                    event_kind: raw_event.event_kind,
+                    event_id: raw_event.event_id,
+                    count: 0,
+                    total_nanos: 0,
+                    max_nanos: 0,
+                });
+            totals.count += 1;
+
+            if raw_event.is_instant() {
+                self.latest_nanos = self.latest_nanos.max(raw_event.start_nanos());
+            } else {
+                let nanos = raw_event
+                    .end_nanos()
+                    .saturating_sub(raw_event.start_nanos());
+                totals.total_nanos += nanos;
+                totals.max_nanos = totals.max_nanos.max(nanos);
+                self.latest_nanos = self.latest_nanos.max(raw_event.end_nanos());
+            }
+        }
+
+        self.events.consume(len);
+        let new_events = len / event_size;
+        self.num_events += new_events as u64;
+        new_events
+    }
+}
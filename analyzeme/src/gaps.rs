@@ -0,0 +1,143 @@
+use crate::{LightweightEvent, ProfilingData, Timestamp};
+use std::time::{Duration, SystemTime};
+
+/// A stretch of time during which no thread recorded any events, together
+/// with the events around it.
+#[derive(Clone, Debug)]
+pub struct Gap<'a> {
+    pub start: SystemTime,
+    pub end: SystemTime,
+    /// The event whose start, end or instant is the start of the gap.
+    pub before: LightweightEvent<'a>,
+    /// The event whose start, end or instant is the end of the gap.
+    pub after: LightweightEvent<'a>,
+    /// The interval events that were open during the whole gap, e.g. a
+    /// `link_crate` activity while the linker runs, ordered by start time.
+    pub enclosing: Vec<LightweightEvent<'a>>,
+}
+
+impl<'a> Gap<'a> {
+    pub fn duration(&self) -> Duration {
+        self.end.duration_since(self.start).unwrap()
+    }
+}
+
+/// Finds the `count` longest stretches during which no thread recorded
+/// anything, i.e. neither started nor finished an interval event nor recorded
+/// an instant event. Unlike `find_stalls()`, which looks at every thread on
+/// its own, this finds the times in which the whole process didn't record
+/// anything, most likely because it was waiting for something external like
+/// the linker, the network or the disk. Such gaps don't show up in summaries
+/// that aggregate the time per label.
+///
+/// The result is ordered by duration, longest first.
+pub fn find_gaps(profiling_data: &ProfilingData, count: usize) -> Vec<Gap<'_>> {
+    let events: Vec<LightweightEvent<'_>> = profiling_data.iter().collect();
+
+    let mut timestamps: Vec<(SystemTime, usize)> = Vec::with_capacity(events.len() * 2);
+    for (index, event) in events.iter().enumerate() {
+        match event.timestamp {
+            Timestamp::Interval { start, end } => {
+                timestamps.push((start, index));
+                timestamps.push((end, index));
+            }
+            Timestamp::Instant(t) => timestamps.push((t, index)),
+        }
+    }
+    timestamps.sort_unstable();
+
+    let mut gaps: Vec<(SystemTime, SystemTime, usize, usize)> = timestamps
+        .windows(2)
+        .map(|window| (window[0].0, window[1].0, window[0].1, window[1].1))
+        .filter(|&(start, end, _, _)| end > start)
+        .collect();
+
+    // Longest first, earlier ones first among gaps of the same length.
+    gaps.sort_by(|a, b| {
+        let duration =
+            |gap: &(SystemTime, SystemTime, usize, usize)| gap.1.duration_since(gap.0).unwrap();
+        duration(b).cmp(&duration(a)).then(a.0.cmp(&b.0))
+    });
+    gaps.truncate(count);
+
+    gaps.into_iter()
+        .map(|(start, end, before, after)| {
+            let mut enclosing: Vec<_> = events
+                .iter()
+                .filter(|event| match event.timestamp {
+                    Timestamp::Interval {
+                        start: event_start,
+                        end: event_end,
+                    } => event_start <= start && end <= event_end,
+                    Timestamp::Instant(_) => false,
+                })
+                .cloned()
+                .collect();
+            enclosing.sort_by_key(|event| event.timestamp.start());
+
+            Gap {
+                start,
+                end,
+                before: events[before].clone(),
+                after: events[after].clone(),
+                enclosing,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ProfilingDataBuilder;
+
+    #[test]
+    fn gaps_across_threads() {
+        //  <--e1-->            <-e3->
+        //  0      10           100  120                 400 410
+        //       <---e2--->                              <-e5->
+        //       5        30
+        //  <===================== link =======================>
+        //  0                                                  450
+        let mut b = ProfilingDataBuilder::new();
+
+        b.interval("Query", "e1", 0, 0, 10, |_| {});
+        b.interval("Query", "e2", 1, 5, 30, |_| {});
+        b.interval("Query", "e3", 0, 100, 120, |_| {});
+        b.interval("GenericActivity", "link", 2, 0, 450, |b| {
+            b.interval("Query", "e5", 2, 400, 410, |_| {});
+        });
+
+        let data = b.into_profiling_data();
+        let label = |event: &LightweightEvent<'_>| event.to_event().label.into_owned();
+
+        let gaps = find_gaps(&data, 2);
+        assert_eq!(gaps.len(), 2);
+
+        let summary: Vec<_> = gaps
+            .iter()
+            .map(|gap| {
+                let since_epoch =
+                    |t: SystemTime| t.duration_since(std::time::UNIX_EPOCH).unwrap().as_nanos();
+                (
+                    since_epoch(gap.start),
+                    since_epoch(gap.end),
+                    label(&gap.before),
+                    label(&gap.after),
+                    gap.enclosing.iter().map(label).collect::<Vec<_>>(),
+                )
+            })
+            .collect();
+
+        assert_eq!(
+            summary,
+            vec![
+                (120, 400, "e3".into(), "e5".into(), vec!["link".to_string()]),
+                (30, 100, "e2".into(), "e3".into(), vec!["link".to_string()]),
+            ]
+        );
+        assert_eq!(gaps[0].duration(), Duration::from_nanos(280));
+
+        assert_eq!(find_gaps(&data, 10).len(), 8);
+    }
+}
@@ -0,0 +1,302 @@
+use crate::{ProfilingData, TimelineEvent};
+use rustc_hash::FxHashMap;
+use std::fmt::Write;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime};
+
+/// What the rows of a `Heatmap` stand for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HeatmapRows {
+    EventKind,
+    Label,
+}
+
+impl FromStr for HeatmapRows {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<HeatmapRows, String> {
+        match s {
+            "kind" => Ok(HeatmapRows::EventKind),
+            "label" => Ok(HeatmapRows::Label),
+            other => Err(format!(
+                "invalid heatmap rows `{}`, expected `kind` or `label`",
+                other
+            )),
+        }
+    }
+}
+
+/// How much time the activities of a profile took in each of a number of
+/// equally long time buckets, a compact picture of which activities dominate
+/// which part of a long build. The time of an event is its self time, i.e.
+/// the time of the events nested in it counts towards those, and is summed
+/// over all threads, so a bucket can hold more time than its duration.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Heatmap {
+    /// The duration of every bucket. Together, the buckets cover the profile
+    /// from its start to the end of its last event.
+    pub bucket_duration: Duration,
+    /// Ordered by total time, longest first.
+    pub rows: Vec<HeatmapRow>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HeatmapRow {
+    /// The event kind or label of the row, or `<other>` for the row that
+    /// combines the rows that have been left out.
+    pub name: String,
+    /// The time of the row in every bucket.
+    pub buckets: Vec<Duration>,
+}
+
+impl HeatmapRow {
+    pub fn total_time(&self) -> Duration {
+        self.buckets.iter().sum()
+    }
+}
+
+/// Builds the heatmap of the interval events of all threads with `buckets`
+/// buckets. Only the `max_rows` rows with the most time are kept, the time
+/// of the others is combined into a last row named `<other>`.
+pub fn heatmap(
+    data: &ProfilingData,
+    rows: HeatmapRows,
+    buckets: usize,
+    max_rows: usize,
+) -> Heatmap {
+    struct Builder {
+        rows: HeatmapRows,
+        start_time: SystemTime,
+        bucket_nanos: u64,
+        indices: FxHashMap<String, usize>,
+        names: Vec<String>,
+        // The time in nanoseconds per row and bucket.
+        cells: Vec<Vec<u64>>,
+        buckets: usize,
+    }
+
+    impl Builder {
+        fn nanos(&self, t: SystemTime) -> u64 {
+            t.duration_since(self.start_time)
+                .unwrap_or_default()
+                .as_nanos() as u64
+        }
+
+        fn visit(&mut self, event: &TimelineEvent<'_>) {
+            if event.event.duration().is_none() {
+                return;
+            }
+
+            let full_event = event.event.to_event();
+            let name = match self.rows {
+                HeatmapRows::EventKind => full_event.event_kind,
+                HeatmapRows::Label => full_event.label,
+            };
+            let row = match self.indices.get(&name[..]) {
+                Some(&row) => row,
+                None => {
+                    let row = self.names.len();
+                    self.indices.insert(name.to_string(), row);
+                    self.names.push(name.into_owned());
+                    self.cells.push(vec![0; self.buckets]);
+                    row
+                }
+            };
+
+            // The self time of the event is the time between its children.
+            let mut from = self.nanos(event.event.timestamp.start());
+            for child in &event.children {
+                if child.event.duration().is_none() {
+                    continue;
+                }
+                self.add(row, from, self.nanos(child.event.timestamp.start()));
+                self.visit(child);
+                from = self.nanos(child.event.timestamp.end());
+            }
+            self.add(row, from, self.nanos(event.event.timestamp.end()));
+        }
+
+        fn add(&mut self, row: usize, mut from: u64, to: u64) {
+            while from < to {
+                let bucket = ((from / self.bucket_nanos) as usize).min(self.buckets - 1);
+                let end = to.min((bucket as u64 + 1) * self.bucket_nanos);
+                self.cells[row][bucket] += end - from;
+                from = end;
+            }
+        }
+    }
+
+    let buckets = buckets.max(1);
+    let start_time = data.metadata.start_time;
+    let end_nanos = data
+        .iter()
+        .map(|event| {
+            event
+                .timestamp
+                .end()
+                .duration_since(start_time)
+                .unwrap_or_default()
+                .as_nanos() as u64
+        })
+        .max()
+        .unwrap_or(0);
+
+    let mut builder = Builder {
+        rows,
+        start_time,
+        bucket_nanos: end_nanos.div_ceil(buckets as u64).max(1),
+        indices: FxHashMap::default(),
+        names: Vec::new(),
+        cells: Vec::new(),
+        buckets,
+    };
+    for timeline in data.per_thread_timelines() {
+        for event in &timeline.events {
+            builder.visit(event);
+        }
+    }
+
+    let mut rows: Vec<HeatmapRow> = builder
+        .names
+        .into_iter()
+        .zip(builder.cells)
+        .map(|(name, cells)| HeatmapRow {
+            name,
+            buckets: cells.into_iter().map(Duration::from_nanos).collect(),
+        })
+        .collect();
+    rows.sort_by(|a, b| {
+        b.total_time()
+            .cmp(&a.total_time())
+            .then_with(|| a.name.cmp(&b.name))
+    });
+
+    if rows.len() > max_rows {
+        let mut other = HeatmapRow {
+            name: "<other>".to_string(),
+            buckets: vec![Duration::ZERO; buckets],
+        };
+        for row in rows.drain(max_rows..) {
+            for (total, time) in other.buckets.iter_mut().zip(row.buckets) {
+                *total += time;
+            }
+        }
+        rows.push(other);
+    }
+
+    Heatmap {
+        bucket_duration: Duration::from_nanos(builder.bucket_nanos),
+        rows,
+    }
+}
+
+impl Heatmap {
+    /// The longest time of any row in any bucket.
+    pub fn max_time(&self) -> Duration {
+        self.rows
+            .iter()
+            .flat_map(|row| row.buckets.iter().copied())
+            .max()
+            .unwrap_or_default()
+    }
+
+    /// Renders the heatmap as CSV, with a row per row of the heatmap and a
+    /// column per bucket. The header gives the start of every bucket in
+    /// seconds since the start of the profile, the cells the time of the
+    /// row in the bucket in milliseconds.
+    pub fn to_csv(&self) -> String {
+        let buckets = self.rows.first().map_or(0, |row| row.buckets.len());
+
+        let mut csv = String::from("name");
+        for bucket in 0..buckets {
+            let start = self.bucket_duration * bucket as u32;
+            write!(csv, ",{:.3}", start.as_secs_f64()).unwrap();
+        }
+        csv.push('\n');
+
+        for row in &self.rows {
+            csv.push_str(&escape(&row.name));
+            for time in &row.buckets {
+                write!(csv, ",{:.3}", time.as_secs_f64() * 1000.0).unwrap();
+            }
+            csv.push('\n');
+        }
+        csv
+    }
+}
+
+fn escape(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ProfilingDataBuilder;
+
+    fn profile() -> ProfilingData {
+        //  <========= a ==========>
+        //  0    <--- b --->     100
+        //       20        60
+        let mut b = ProfilingDataBuilder::new();
+
+        b.interval("Query", "a", 0, 0, 100, |b| {
+            b.interval("Activity", "b", 0, 20, 60, |_| {});
+        });
+        b.instant("Marker", "instant", 0, 50);
+        b.interval("Activity", "b", 1, 90, 100, |_| {});
+
+        b.into_profiling_data()
+    }
+
+    fn nanos(row: &HeatmapRow) -> Vec<u64> {
+        row.buckets.iter().map(|t| t.as_nanos() as u64).collect()
+    }
+
+    #[test]
+    fn self_time_per_bucket() {
+        let heatmap = heatmap(&profile(), HeatmapRows::Label, 4, 10);
+
+        assert_eq!(heatmap.bucket_duration, Duration::from_nanos(25));
+        let rows: Vec<_> = heatmap
+            .rows
+            .iter()
+            .map(|row| (&row.name[..], nanos(row)))
+            .collect();
+        assert_eq!(
+            rows,
+            vec![("a", vec![20, 0, 15, 25]), ("b", vec![5, 25, 10, 10])]
+        );
+        assert_eq!(heatmap.max_time(), Duration::from_nanos(25));
+    }
+
+    #[test]
+    fn other_row() {
+        let heatmap = heatmap(&profile(), HeatmapRows::EventKind, 2, 1);
+
+        let rows: Vec<_> = heatmap
+            .rows
+            .iter()
+            .map(|row| (&row.name[..], nanos(row)))
+            .collect();
+        assert_eq!(
+            rows,
+            vec![("Query", vec![20, 40]), ("<other>", vec![30, 20])]
+        );
+
+        assert_eq!(
+            heatmap.to_csv(),
+            "name,0.000,0.000\nQuery,0.000,0.000\n<other>,0.000,0.000\n"
+        );
+    }
+
+    #[test]
+    fn csv_escaping() {
+        assert_eq!(escape("typeck"), "typeck");
+        assert_eq!(escape("a, \"b\""), "\"a, \"\"b\"\"\"");
+    }
+}
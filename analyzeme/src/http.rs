@@ -0,0 +1,135 @@
+//! Loading profiles from `http://` and `https://` URLs, so that tools can be
+//! pointed at profiles in CI artifact storage without downloading them first.
+//! The URL is the path stem of the profile, the file extensions are appended
+//! to it. `s3://<bucket>/<key>` URLs are read through the public HTTPS
+//! endpoint of the bucket, so they only work for buckets that allow anonymous
+//! reads.
+
+use crate::{LoadError, LoadErrorKind};
+use measureme::file_header::{FILE_HEADER_SIZE, FILE_MAGIC_ENCRYPTED};
+use std::io::Read;
+use std::path::Path;
+
+/// Returns the HTTP(S) URL of the profile if `path_stem` is a URL.
+pub(crate) fn profile_url(path_stem: &Path) -> Option<String> {
+    let path_stem = path_stem.to_str()?;
+
+    if path_stem.starts_with("http://") || path_stem.starts_with("https://") {
+        Some(path_stem.to_string())
+    } else if let Some(rest) = path_stem.strip_prefix("s3://") {
+        let (bucket, key) = rest.split_at(rest.find('/')?);
+        Some(format!("https://{}.s3.amazonaws.com{}", bucket, key))
+    } else {
+        None
+    }
+}
+
+/// Returns the URL of the profile `name` next to the profile at `url`, like
+/// `Path::with_file_name()`.
+pub(crate) fn sibling_url(url: &str, name: &str) -> String {
+    match url.rfind('/') {
+        Some(index) => format!("{}/{}", &url[..index], name),
+        None => name.to_string(),
+    }
+}
+
+/// Downloads the file at `url`, which must start with one of `file_magics`
+/// (or be encrypted).
+///
+/// The header is requested on its own first, so that URLs that don't point
+/// to a profile (like the HTML page of a login form) are rejected before
+/// downloading a potentially large file. The rest of the file is then
+/// requested with a range request. Servers that don't support range
+/// requests send the whole file in response to the first request.
+pub(crate) fn fetch_file(url: &str, file_magics: &[&[u8; 4]]) -> Result<Vec<u8>, LoadError> {
+    let (mut data, complete) = get(url, 0, Some(FILE_HEADER_SIZE))?;
+
+    let magic = data.get(..4).unwrap_or(&[]);
+    if magic != FILE_MAGIC_ENCRYPTED && !file_magics.iter().any(|m| &m[..] == magic) {
+        return Err(LoadError::new(
+            LoadErrorKind::NotAProfile,
+            format!("`{}` is not a measureme file", url),
+        ));
+    }
+
+    if !complete {
+        let (rest, _) = get(url, data.len(), None)?;
+        data.extend_from_slice(&rest);
+    }
+
+    Ok(data)
+}
+
+/// Requests `len` bytes (or everything) starting at `start`. Also returns
+/// whether the response contains the rest of the file, which is the case if
+/// the server ignores the range or the file is shorter than requested.
+fn get(url: &str, start: usize, len: Option<usize>) -> Result<(Vec<u8>, bool), LoadError> {
+    let range = match len {
+        Some(len) => format!("bytes={}-{}", start, start + len - 1),
+        None => format!("bytes={}-", start),
+    };
+
+    let response = ureq::get(url)
+        .set("Range", &range)
+        .call()
+        .map_err(|e| match e {
+            ureq::Error::Status(404, _) | ureq::Error::Status(410, _) => LoadError::new(
+                LoadErrorKind::FileMissing,
+                format!("`{}` doesn't exist", url),
+            ),
+            // The file is shorter than the range, so there is nothing left.
+            ureq::Error::Status(416, _) => LoadError::new(
+                LoadErrorKind::Truncated,
+                format!("`{}` is too short to be a measureme file", url),
+            ),
+            e => LoadError::new(
+                LoadErrorKind::FileUnreadable,
+                format!("couldn't download `{}`: {}", url, e),
+            ),
+        })?;
+
+    let partial = response.status() == 206;
+
+    let mut data = Vec::new();
+    response.into_reader().read_to_end(&mut data).map_err(|e| {
+        LoadError::new(
+            LoadErrorKind::FileUnreadable,
+            format!("couldn't download `{}`: {}", url, e),
+        )
+    })?;
+
+    let complete = match len {
+        Some(len) => !partial || data.len() < len,
+        None => true,
+    };
+
+    if !partial && start > 0 {
+        data.drain(..start.min(data.len()));
+    }
+
+    Ok((data, complete))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn urls() {
+        assert_eq!(
+            profile_url(Path::new("https://ci.example.com/artifacts/foo-1234")).as_deref(),
+            Some("https://ci.example.com/artifacts/foo-1234")
+        );
+        assert_eq!(
+            profile_url(Path::new("s3://profiles/nightly/foo-1234")).as_deref(),
+            Some("https://profiles.s3.amazonaws.com/nightly/foo-1234")
+        );
+        assert_eq!(profile_url(Path::new("s3://profiles")), None);
+        assert_eq!(profile_url(Path::new("target/foo-1234")), None);
+
+        assert_eq!(
+            sibling_url("https://ci.example.com/artifacts/foo-1234", "shared"),
+            "https://ci.example.com/artifacts/shared"
+        );
+    }
+}
@@ -0,0 +1,248 @@
+//! Working out what the path given to `ProfilingData::new()` points to, so
+//! that tools accept whichever form of a profile users have at hand:
+//!
+//! - the path stem of the files of a profile, like `foo-1234`, as rustc
+//!   prints it,
+//! - one of the files of a profile, like `foo-1234.events` or
+//!   `foo-1234.0.thread_events` or `foo-1234.0.segment_events`, as shells
+//!   complete it,
+//! - a directory that contains a single profile, like the directory passed
+//!   to `-Z self-profile`,
+//! - an archive containing a profile, or a profile inside of one, see the
+//!   `archive` module.
+//!
+//! Archives are recognized by their first bytes, everything else by which
+//! files exist and how they are named.
+
+#[cfg(feature = "archives")]
+use crate::archive;
+use crate::{LoadError, LoadErrorKind};
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The extensions of the files of a profile, `<path_stem>.<extension>`, as
+/// written by `measureme` and the tools that add files next to a profile.
+const STEM_EXTENSIONS: [&str; 6] = [
+    "analysis",
+    "events",
+    "string_data",
+    "string_index",
+    "summary",
+    "search_index",
+];
+
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Input {
+    /// The path stem of the files of a profile.
+    PathStem(PathBuf),
+    /// The path of an archive and of the profile inside of it, which is
+    /// empty if the archive is supposed to contain a single profile.
+    #[cfg(feature = "archives")]
+    Archive(PathBuf, PathBuf),
+}
+
+pub(crate) fn detect(path: &Path) -> Result<Input, Box<dyn Error>> {
+    if path.is_dir() {
+        return Ok(Input::PathStem(single_profile_in(path)?));
+    }
+
+    if path.is_file() {
+        #[cfg(feature = "archives")]
+        {
+            if archive::is_archive(path) {
+                return Ok(Input::Archive(path.to_path_buf(), PathBuf::new()));
+            }
+        }
+
+        if let Some(path_stem) = path_stem_of_file(path) {
+            return Ok(Input::PathStem(path_stem));
+        }
+
+        // A file named like the path stem itself, next to the files of the
+        // profile, doesn't keep the profile from being loaded.
+        let events_file = measureme::ProfilerFiles::new(path).events_file;
+        if !events_file.is_file() {
+            Err(LoadError::new(
+                LoadErrorKind::NotAProfile,
+                format!(
+                    "`{}` is neither one of the files of a profile nor an archive",
+                    path.display()
+                ),
+            ))?;
+        }
+    }
+
+    #[cfg(feature = "archives")]
+    {
+        if let Some((archive_path, profile)) = archive::split_archive_path(path) {
+            return Ok(Input::Archive(
+                archive_path.to_path_buf(),
+                profile.to_path_buf(),
+            ));
+        }
+    }
+
+    Ok(Input::PathStem(path.to_path_buf()))
+}
+
+/// The path stem of a profile, if `path` is named like one of its files.
+fn path_stem_of_file(path: &Path) -> Option<PathBuf> {
+    let extension = path.extension()?.to_str()?;
+
+    if STEM_EXTENSIONS.contains(&extension) {
+        return Some(path.with_extension(""));
+    }
+
+    // `<path_stem>.<n>.thread_events` and `<path_stem>.<n>.segment_events`
+    if extension == "thread_events" || extension == "segment_events" {
+        let numbered = path.with_extension("");
+        let n = numbered.extension()?.to_str()?;
+        if !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()) {
+            return Some(numbered.with_extension(""));
+        }
+    }
+
+    None
+}
+
+/// The path stem of the only profile in `dir`.
+fn single_profile_in(dir: &Path) -> Result<PathBuf, Box<dyn Error>> {
+    let entries = fs::read_dir(dir).map_err(|e| {
+        LoadError::new(
+            LoadErrorKind::FileUnreadable,
+            format!("couldn't read directory `{}`: {}", dir.display(), e),
+        )
+    })?;
+
+    let mut path_stems = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().is_some_and(|e| e == "events") && path.is_file() {
+            path_stems.push(path.with_extension(""));
+        }
+    }
+    path_stems.sort();
+
+    match &path_stems[..] {
+        [path_stem] => Ok(path_stem.clone()),
+        [] => Err(From::from(LoadError::new(
+            LoadErrorKind::FileMissing,
+            format!(
+                "the directory `{}` doesn't contain any profiles",
+                dir.display()
+            ),
+        ))),
+        _ => Err(From::from(format!(
+            "the directory `{}` contains several profiles, select one by its path stem, \
+             e.g. `{}`: {}",
+            dir.display(),
+            path_stems[0].display(),
+            path_stems
+                .iter()
+                .map(|path_stem| path_stem.file_name().unwrap().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing_common::{query_labels, record_query as record};
+    use crate::{LoadError, LoadErrorKind, ProfilingData};
+    use std::fs;
+
+    #[test]
+    fn file_names() {
+        let stem = |path: &str| path_stem_of_file(Path::new(path));
+        let dir = Path::new("profiles");
+
+        assert_eq!(stem("profiles/foo-1234.events"), Some(dir.join("foo-1234")));
+        assert_eq!(
+            stem("profiles/foo-1234.string_index"),
+            Some(dir.join("foo-1234"))
+        );
+        assert_eq!(
+            stem("profiles/foo-1234.12.thread_events"),
+            Some(dir.join("foo-1234"))
+        );
+        assert_eq!(
+            stem("profiles/foo-1234.3.segment_events"),
+            Some(dir.join("foo-1234"))
+        );
+        assert_eq!(stem("profiles/foo-1234.x.thread_events"), None);
+        assert_eq!(stem("profiles/foo-1234.thread_events"), None);
+        assert_eq!(stem("profiles/foo-1234"), None);
+        assert_eq!(stem("profiles/foo-1234.json"), None);
+    }
+
+    fn labels(path: &Path) -> Vec<String> {
+        query_labels(&ProfilingData::new(path).unwrap())
+    }
+
+    fn error_kind(path: &Path) -> Option<LoadErrorKind> {
+        LoadError::kind_of(&*ProfilingData::new(path).err().unwrap())
+    }
+
+    #[test]
+    fn detect_inputs() {
+        let dir = Path::new("test-tmp").join("inputs");
+        let _ = fs::remove_dir_all(&dir);
+
+        let single = dir.join("single");
+        let typeck = single.join("foo-1234");
+        record(&typeck, "typeck");
+
+        assert_eq!(labels(&typeck), vec!["typeck"]);
+        assert_eq!(labels(&typeck.with_extension("events")), vec!["typeck"]);
+        assert_eq!(
+            labels(&typeck.with_extension("string_data")),
+            vec!["typeck"]
+        );
+        assert_eq!(labels(&single), vec!["typeck"]);
+
+        let several = dir.join("several");
+        record(&several.join("foo-1234"), "typeck");
+        record(&several.join("bar-5678"), "mir_borrowck");
+        let error = ProfilingData::new(&several).err().unwrap().to_string();
+        assert!(error.contains("contains several profiles"), "{}", error);
+        assert!(error.contains("bar-5678, foo-1234"), "{}", error);
+        assert_eq!(labels(&several.join("bar-5678")), vec!["mir_borrowck"]);
+
+        let empty = dir.join("empty");
+        fs::create_dir_all(&empty).unwrap();
+        assert_eq!(error_kind(&empty), Some(LoadErrorKind::FileMissing));
+
+        let notes = dir.join("notes.txt");
+        fs::write(&notes, "not a profile").unwrap();
+        assert_eq!(error_kind(&notes), Some(LoadErrorKind::NotAProfile));
+    }
+
+    #[cfg(feature = "archives")]
+    #[test]
+    fn detect_renamed_archives() {
+        let dir = Path::new("test-tmp").join("renamed-archives");
+        let _ = fs::remove_dir_all(&dir);
+
+        let typeck = dir.join("foo-1234");
+        record(&typeck, "typeck");
+
+        // What a CI system might store an upload as.
+        let upload = dir.join("upload.bin");
+        let mut builder = tar::Builder::new(flate2::write::GzEncoder::new(
+            fs::File::create(&upload).unwrap(),
+            flate2::Compression::default(),
+        ));
+        for extension in &["events", "string_data", "string_index"] {
+            let path = typeck.with_extension(extension);
+            let name = path.file_name().unwrap().to_owned();
+            builder.append_path_with_name(&path, name).unwrap();
+        }
+        builder.into_inner().unwrap().finish().unwrap();
+
+        assert_eq!(labels(&upload), vec!["typeck"]);
+        assert_eq!(labels(&upload.join("foo-1234")), vec!["typeck"]);
+    }
+}
@@ -0,0 +1,670 @@
+use crate::labels::LabelFormatter;
+use std::borrow::Cow;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+/// Rewrites event labels while a profile is analyzed, see
+/// `ProfilingData::set_label_rules()`, e.g. to group all
+/// `codegen_module(...)` events under one label without instrumenting the
+/// program differently. A rules file has one rule per line, given as a
+/// regular expression and its replacement:
+///
+/// ```text
+/// # Comments and empty lines are ignored.
+/// ^codegen_module\(.*\)$ => codegen_module
+/// ::h[0-9a-f]{16} =>
+/// ^(\w+)_of_(\w+)$ => $2::$1
+/// ```
+///
+/// The rules are applied in order, each to the result of the rules before
+/// it, and replace all non-overlapping matches. Replacements can refer to
+/// the groups of the expression as `$1` or `${1}`, `$$` being a literal `$`.
+/// A label can be put into a category by a rule that matches all of it.
+///
+/// The expressions support literals, `.`, the classes `\d`, `\w`, `\s` and
+/// their negations, `[...]` and `[^...]` with ranges, the anchors `^` and
+/// `$`, capturing `(...)` and non-capturing `(?:...)` groups, `|`, and the
+/// quantifiers `*`, `+`, `?`, `{n}`, `{n,}` and `{n,m}`, which are lazy if
+/// followed by `?`. They are matched by backtracking, so expressions like
+/// `(a*)*b` are slow on long labels.
+#[derive(Clone, Debug, Default)]
+pub struct LabelRules {
+    rules: Vec<Rule>,
+}
+
+impl LabelRules {
+    pub fn load(path: &Path) -> Result<LabelRules, Box<dyn Error>> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("could not read `{}`: {}", path.display(), e))?;
+        LabelRules::parse(&text)
+    }
+
+    pub fn parse(text: &str) -> Result<LabelRules, Box<dyn Error>> {
+        let mut rules = Vec::new();
+
+        for (line_number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let rule = match line.split_once(" =>") {
+                Some((pattern, replacement)) => Rule::new(pattern.trim(), replacement.trim()),
+                None => Err("it is not of the form `<regex> => <replacement>`".to_string()),
+            };
+            rules.push(rule.map_err(|e| {
+                format!(
+                    "line {} of the label rules is invalid: {}",
+                    line_number + 1,
+                    e
+                )
+            })?);
+        }
+
+        Ok(LabelRules { rules })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Applies the rules to `label`, borrowing it if none of them matches.
+    pub fn apply<'a>(&self, label: &'a str) -> Cow<'a, str> {
+        let mut label = Cow::Borrowed(label);
+        for rule in &self.rules {
+            if let Some(rewritten) = rule.replace_all(&label) {
+                label = Cow::Owned(rewritten);
+            }
+        }
+        label
+    }
+}
+
+impl LabelFormatter for LabelRules {
+    fn format<'a>(&self, label: &'a str) -> Cow<'a, str> {
+        self.apply(label)
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Rule {
+    regex: Regex,
+    replacement: Vec<Piece>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Piece {
+    Literal(String),
+    Group(usize),
+}
+
+impl Rule {
+    fn new(pattern: &str, replacement: &str) -> Result<Rule, String> {
+        let regex = Regex::new(pattern)?;
+        let replacement = parse_replacement(replacement, regex.groups)?;
+        Ok(Rule { regex, replacement })
+    }
+
+    /// Returns `None` if the expression doesn't match anywhere in `text`.
+    fn replace_all(&self, text: &str) -> Option<String> {
+        let chars: Vec<char> = text.chars().collect();
+        let mut result = String::new();
+        let mut copied = 0;
+        let mut pos = 0;
+        let mut previous_end = None;
+
+        while pos <= chars.len() {
+            let captures = match self.regex.find_at(&chars, pos) {
+                Some(captures) => captures,
+                None => break,
+            };
+            let (start, end) = captures[0].unwrap();
+            // Like empty matches at the same place, an empty match right
+            // after a match is skipped.
+            if start == end && previous_end == Some(start) {
+                pos = start + 1;
+                continue;
+            }
+            previous_end = Some(end);
+
+            result.extend(&chars[copied..start]);
+            for piece in &self.replacement {
+                match piece {
+                    Piece::Literal(literal) => result.push_str(literal),
+                    Piece::Group(group) => {
+                        if let Some((start, end)) = captures[*group] {
+                            result.extend(&chars[start..end]);
+                        }
+                    }
+                }
+            }
+            copied = end;
+            pos = if end == start { end + 1 } else { end };
+        }
+
+        // Nothing matched.
+        previous_end?;
+        result.extend(&chars[copied..]);
+        Some(result)
+    }
+}
+
+fn parse_replacement(replacement: &str, groups: usize) -> Result<Vec<Piece>, String> {
+    let mut pieces = Vec::new();
+    let mut literal = String::new();
+    let mut chars = replacement.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c != '$' {
+            literal.push(c);
+            continue;
+        }
+
+        let digits: String = match chars.peek() {
+            Some('$') => {
+                chars.next();
+                literal.push('$');
+                continue;
+            }
+            Some('{') => {
+                chars.next();
+                let digits: String = chars.by_ref().take_while(|&c| c != '}').collect();
+                if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_digit()) {
+                    return Err(format!("`${{{}}}` is not a group", digits));
+                }
+                digits
+            }
+            Some(c) if c.is_ascii_digit() => {
+                let mut digits = String::new();
+                while let Some(&c) = chars.peek().filter(|c| c.is_ascii_digit()) {
+                    digits.push(c);
+                    chars.next();
+                }
+                digits
+            }
+            _ => return Err("a `$` has to be followed by a group, use `$$` for `$`".to_string()),
+        };
+
+        let group: usize = digits
+            .parse()
+            .map_err(|_| format!("`{}` is not a group", digits))?;
+        if group > groups {
+            return Err(format!("the expression doesn't have a group {}", group));
+        }
+        if !literal.is_empty() {
+            pieces.push(Piece::Literal(std::mem::take(&mut literal)));
+        }
+        pieces.push(Piece::Group(group));
+    }
+
+    if !literal.is_empty() {
+        pieces.push(Piece::Literal(literal));
+    }
+    Ok(pieces)
+}
+
+/// The start and end of each group of a match, group 0 being all of it.
+type Captures = Vec<Option<(usize, usize)>>;
+
+#[derive(Clone, Debug)]
+struct Regex {
+    /// The whole expression, as group 0.
+    root: Node,
+    /// The number of capturing groups, not counting group 0.
+    groups: usize,
+}
+
+#[derive(Clone, Debug)]
+enum Node {
+    Char(char),
+    Any,
+    Class {
+        items: Vec<ClassItem>,
+        negated: bool,
+    },
+    Start,
+    End,
+    Group {
+        alternatives: Vec<Vec<Node>>,
+        index: Option<usize>,
+    },
+    Repeat {
+        node: Box<Node>,
+        min: u32,
+        max: Option<u32>,
+        greedy: bool,
+    },
+}
+
+#[derive(Clone, Debug)]
+enum ClassItem {
+    Range(char, char),
+    /// One of `d`, `w` and `s`, or their negations `D`, `W` and `S`.
+    Perl(char),
+}
+
+impl ClassItem {
+    fn matches(&self, c: char) -> bool {
+        match *self {
+            ClassItem::Range(from, to) => from <= c && c <= to,
+            ClassItem::Perl(kind) => {
+                let matches = match kind.to_ascii_lowercase() {
+                    'd' => c.is_ascii_digit(),
+                    'w' => c.is_alphanumeric() || c == '_',
+                    _ => c.is_whitespace(),
+                };
+                matches != kind.is_ascii_uppercase()
+            }
+        }
+    }
+}
+
+impl Regex {
+    fn new(pattern: &str) -> Result<Regex, String> {
+        let mut parser = Parser {
+            chars: pattern.chars().collect(),
+            pos: 0,
+            groups: 0,
+        };
+        let alternatives = parser.alternatives()?;
+        if parser.pos < parser.chars.len() {
+            // Only a `)` ends the alternatives early.
+            return Err(format!("unmatched `)` in `{}`", pattern));
+        }
+
+        Ok(Regex {
+            root: Node::Group {
+                alternatives,
+                index: Some(0),
+            },
+            groups: parser.groups,
+        })
+    }
+
+    /// Finds the first match that starts at or after `from`.
+    fn find_at(&self, text: &[char], from: usize) -> Option<Captures> {
+        let matcher = Matcher { text };
+
+        for start in from..=text.len() {
+            let mut captures = vec![None; self.groups + 1];
+            if matcher.match_nodes(
+                std::slice::from_ref(&self.root),
+                start,
+                &mut captures,
+                &mut |_, _| true,
+            ) {
+                return Some(captures);
+            }
+        }
+        None
+    }
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    groups: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek();
+        self.pos += 1;
+        c
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn alternatives(&mut self) -> Result<Vec<Vec<Node>>, String> {
+        let mut alternatives = vec![self.sequence()?];
+        while self.eat('|') {
+            alternatives.push(self.sequence()?);
+        }
+        Ok(alternatives)
+    }
+
+    fn sequence(&mut self) -> Result<Vec<Node>, String> {
+        let mut nodes = Vec::new();
+
+        while let Some(c) = self.peek() {
+            let node = match c {
+                '|' | ')' => break,
+                '*' | '+' | '?' | '{' => return Err(format!("nothing to repeat before `{}`", c)),
+                _ => self.atom()?,
+            };
+            nodes.push(self.quantified(node)?);
+        }
+        Ok(nodes)
+    }
+
+    fn atom(&mut self) -> Result<Node, String> {
+        Ok(match self.next().unwrap() {
+            '.' => Node::Any,
+            '^' => Node::Start,
+            '$' => Node::End,
+            '(' => {
+                let index = if self.eat('?') {
+                    if !self.eat(':') {
+                        return Err("only `(?:...)` groups are supported".to_string());
+                    }
+                    None
+                } else {
+                    self.groups += 1;
+                    Some(self.groups)
+                };
+                let alternatives = self.alternatives()?;
+                if !self.eat(')') {
+                    return Err("unclosed `(`".to_string());
+                }
+                Node::Group {
+                    alternatives,
+                    index,
+                }
+            }
+            '[' => self.class()?,
+            '\\' => match self.escape()? {
+                ClassItem::Range(c, _) => Node::Char(c),
+                perl => Node::Class {
+                    items: vec![perl],
+                    negated: false,
+                },
+            },
+            c => Node::Char(c),
+        })
+    }
+
+    fn escape(&mut self) -> Result<ClassItem, String> {
+        let c = match self.next() {
+            Some(c) => c,
+            None => return Err("the expression ends with a `\\`".to_string()),
+        };
+
+        Ok(match c {
+            'd' | 'w' | 's' | 'D' | 'W' | 'S' => ClassItem::Perl(c),
+            'n' => ClassItem::Range('\n', '\n'),
+            't' => ClassItem::Range('\t', '\t'),
+            c if !c.is_alphanumeric() => ClassItem::Range(c, c),
+            c => return Err(format!("unsupported escape `\\{}`", c)),
+        })
+    }
+
+    fn class(&mut self) -> Result<Node, String> {
+        let negated = self.eat('^');
+        let mut items = Vec::new();
+
+        loop {
+            let item = match self.next() {
+                None => return Err("unclosed `[`".to_string()),
+                // A `]` right at the start is part of the class.
+                Some(']') if !items.is_empty() => break,
+                Some('\\') => self.escape()?,
+                Some(c) => ClassItem::Range(c, c),
+            };
+
+            let from = match item {
+                ClassItem::Range(from, _) => from,
+                perl => {
+                    items.push(perl);
+                    continue;
+                }
+            };
+            if self.peek() == Some('-') && self.chars.get(self.pos + 1).is_some_and(|&c| c != ']') {
+                self.pos += 1;
+                let to = match self.next().unwrap() {
+                    '\\' => match self.escape()? {
+                        ClassItem::Range(to, _) => to,
+                        ClassItem::Perl(_) => return Err("invalid range in `[...]`".to_string()),
+                    },
+                    to => to,
+                };
+                if to < from {
+                    return Err(format!("invalid range `{}-{}` in `[...]`", from, to));
+                }
+                items.push(ClassItem::Range(from, to));
+            } else {
+                items.push(item);
+            }
+        }
+
+        Ok(Node::Class { items, negated })
+    }
+
+    fn quantified(&mut self, node: Node) -> Result<Node, String> {
+        let (min, max) = match self.peek() {
+            Some('*') => (0, None),
+            Some('+') => (1, None),
+            Some('?') => (0, Some(1)),
+            Some('{') => match self.counts() {
+                Some(counts) => counts,
+                None => return Err("invalid `{...}` quantifier".to_string()),
+            },
+            _ => return Ok(node),
+        };
+        self.pos += 1;
+        if max.is_some_and(|max| max < min) {
+            return Err(format!("invalid quantifier `{{{},{}}}`", min, max.unwrap()));
+        }
+
+        let greedy = !self.eat('?');
+        Ok(Node::Repeat {
+            node: Box::new(node),
+            min,
+            max,
+            greedy,
+        })
+    }
+
+    /// Parses `{n}`, `{n,}` or `{n,m}`, leaving the closing `}` unconsumed.
+    fn counts(&mut self) -> Option<(u32, Option<u32>)> {
+        let close = self.pos + self.chars[self.pos..].iter().position(|&c| c == '}')?;
+        let inner: String = self.chars[self.pos + 1..close].iter().collect();
+        let counts = match inner.split_once(',') {
+            None => {
+                let n = inner.parse().ok()?;
+                (n, Some(n))
+            }
+            Some((min, "")) => (min.parse().ok()?, None),
+            Some((min, max)) => (min.parse().ok()?, Some(max.parse().ok()?)),
+        };
+        self.pos = close;
+        Some(counts)
+    }
+}
+
+struct Matcher<'t> {
+    text: &'t [char],
+}
+
+impl Matcher<'_> {
+    /// Matches `nodes` at `pos` and then calls `then` with the end of the
+    /// match, backtracking into `nodes` for as long as `then` fails.
+    fn match_nodes(
+        &self,
+        nodes: &[Node],
+        pos: usize,
+        captures: &mut Captures,
+        then: &mut dyn FnMut(usize, &mut Captures) -> bool,
+    ) -> bool {
+        let (node, rest) = match nodes.split_first() {
+            Some(split) => split,
+            None => return then(pos, captures),
+        };
+
+        match node {
+            Node::Char(c) => {
+                self.text.get(pos) == Some(c) && self.match_nodes(rest, pos + 1, captures, then)
+            }
+            Node::Any => pos < self.text.len() && self.match_nodes(rest, pos + 1, captures, then),
+            Node::Class { items, negated } => match self.text.get(pos) {
+                Some(&c) if items.iter().any(|item| item.matches(c)) != *negated => {
+                    self.match_nodes(rest, pos + 1, captures, then)
+                }
+                _ => false,
+            },
+            Node::Start => pos == 0 && self.match_nodes(rest, pos, captures, then),
+            Node::End => pos == self.text.len() && self.match_nodes(rest, pos, captures, then),
+            Node::Group {
+                alternatives,
+                index,
+            } => alternatives.iter().any(|alternative| {
+                self.match_nodes(alternative, pos, captures, &mut |end, captures| {
+                    let previous = index.map(|index| captures[index].replace((pos, end)));
+                    if self.match_nodes(rest, end, captures, then) {
+                        return true;
+                    }
+                    if let (Some(index), Some(previous)) = (index, previous) {
+                        captures[*index] = previous;
+                    }
+                    false
+                })
+            }),
+            Node::Repeat {
+                node,
+                min,
+                max,
+                greedy,
+            } => self.match_repeat(node, (*min, *max, *greedy), 0, rest, pos, captures, then),
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn match_repeat(
+        &self,
+        node: &Node,
+        quantifier: (u32, Option<u32>, bool),
+        count: u32,
+        rest: &[Node],
+        pos: usize,
+        captures: &mut Captures,
+        then: &mut dyn FnMut(usize, &mut Captures) -> bool,
+    ) -> bool {
+        let (min, max, greedy) = quantifier;
+        let may_stop = count >= min;
+        let may_repeat = max.is_none_or(|max| count < max);
+
+        if may_stop && !greedy && self.match_nodes(rest, pos, captures, then) {
+            return true;
+        }
+        if may_repeat {
+            let repeated = self.match_nodes(
+                std::slice::from_ref(node),
+                pos,
+                captures,
+                &mut |end, captures| {
+                    // Repeating an empty match past the minimum would never end.
+                    (end != pos || count < min)
+                        && self.match_repeat(node, quantifier, count + 1, rest, end, captures, then)
+                },
+            );
+            if repeated {
+                return true;
+            }
+        }
+        may_stop && greedy && self.match_nodes(rest, pos, captures, then)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ProfilingDataBuilder;
+
+    fn apply(rules: &str, label: &str) -> String {
+        LabelRules::parse(rules).unwrap().apply(label).into_owned()
+    }
+
+    #[test]
+    fn categories() {
+        let rules = "# Group all codegen units.\n\
+                     ^codegen_module\\(.*\\)$ => codegen_module\n\
+                     \n\
+                     ^(?:typeck|type_of)$ => type checking\n";
+
+        assert_eq!(
+            apply(rules, "codegen_module(foo.1234-cgu.0)"),
+            "codegen_module"
+        );
+        assert_eq!(apply(rules, "typeck"), "type checking");
+        assert_eq!(apply(rules, "type_of"), "type checking");
+        assert_eq!(apply(rules, "typeck_body"), "typeck_body");
+
+        let rules = LabelRules::parse(rules).unwrap();
+        assert!(matches!(rules.apply("typeck_body"), Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn replacements() {
+        assert_eq!(apply("::h[0-9a-f]{16} =>", "foo::h0123456789abcdef"), "foo");
+        assert_eq!(
+            apply("^(\\w+)_of_(\\w+)$ => $2::$1", "type_of_item"),
+            "item::type"
+        );
+        assert_eq!(apply("(\\d+) => <${1}0>", "a1b22"), "a<10>b<220>");
+        assert_eq!(apply("\\$ => $$$$", "a$b"), "a$$b");
+        assert_eq!(apply("a? => -", "bab"), "-b-b-");
+        assert_eq!(apply("x => y\ny => z", "x"), "z");
+    }
+
+    #[test]
+    fn quantifiers_and_classes() {
+        assert_eq!(apply("<.*> => _", "<a><b>"), "_");
+        assert_eq!(apply("<.*?> => _", "<a><b>"), "__");
+        assert_eq!(apply("[^a-c]+ => _", "abxyzcd"), "ab_c_");
+        assert_eq!(apply("[]-] => _", "a]b-c"), "a_b_c");
+        assert_eq!(apply("\\s+ => _", "a  \tb"), "a_b");
+        assert_eq!(apply("^a{2,3} => _", "aaaa"), "_a");
+        assert_eq!(apply("(a|ab)c => _", "abc"), "_");
+        assert_eq!(apply("(a*)*b => _$1", "aab"), "_aa");
+    }
+
+    #[test]
+    fn rewritten_events() {
+        let mut b = ProfilingDataBuilder::new();
+        b.interval("Query", "codegen_module(a)", 0, 10, 20, |_| {});
+        b.interval("Query", "typeck", 0, 30, 40, |_| {});
+        let mut data = b.into_profiling_data();
+
+        data.set_label_rules(LabelRules::parse("^codegen_module.*$ => codegen").unwrap());
+        let labels: Vec<_> = data
+            .iter()
+            .map(|event| event.to_event().label.into_owned())
+            .collect();
+        assert_eq!(labels, vec!["codegen", "typeck"]);
+    }
+
+    #[test]
+    fn invalid_rules() {
+        for rules in &[
+            "foo",
+            "(foo => bar",
+            "foo) => bar",
+            "[a- => bar",
+            "*a => bar",
+            "\\q => bar",
+            "a{3,1} => bar",
+            "a => $1",
+            "(a) => $x",
+        ] {
+            assert!(LabelRules::parse(rules).is_err(), "{}", rules);
+        }
+
+        let error = LabelRules::parse("a => b\n\nfoo").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "line 3 of the label rules is invalid: \
+             it is not of the form `<regex> => <replacement>`"
+        );
+    }
+}
@@ -0,0 +1,358 @@
+use measureme::checksum::Checksum;
+use std::borrow::Cow;
+use std::fmt;
+
+/// Changes whenever the ids of the same labels would change.
+const LABEL_ID_SCHEME: &[u8] = b"measureme label id 1\0";
+
+/// The number of hexadecimal digits of a label id.
+const LABEL_ID_LEN: usize = 8;
+
+/// Post-processes event labels for display, see
+/// `ProfilingData::set_label_formatter()`. The raw labels stay available via
+/// `Event::label`.
+pub trait LabelFormatter: Send + Sync {
+    fn format<'a>(&self, label: &'a str) -> Cow<'a, str>;
+}
+
+pub(crate) struct BoxedLabelFormatter(pub(crate) Box<dyn LabelFormatter>);
+
+impl fmt::Debug for BoxedLabelFormatter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("LabelFormatter")
+    }
+}
+
+/// A `LabelFormatter` for profiles recorded by rustc. It
+///
+/// - demangles legacy (`_ZN...E`) symbol names,
+/// - strips crate disambiguators (`core[a1b2]::mem` becomes `core::mem`) and
+///   symbol hashes (`::h0123456789abcdef`), and
+/// - shortens paths with more than three segments to the crate name and the
+///   last two segments (`rustc_middle::ty::context::TyCtxt::foo` becomes
+///   `rustc_middle::..::TyCtxt::foo`).
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RustcLabelFormatter;
+
+impl LabelFormatter for RustcLabelFormatter {
+    fn format<'a>(&self, label: &'a str) -> Cow<'a, str> {
+        let demangled = demangle_symbols(label);
+        let stripped = strip_hashes(&demangled);
+        let shortened = shorten_paths(&stripped);
+
+        if shortened == label {
+            Cow::Borrowed(label)
+        } else {
+            Cow::Owned(shortened)
+        }
+    }
+}
+
+fn is_segment_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '{' || c == '}' || c == '#'
+}
+
+/// Replaces all legacy mangled symbols in `label` with their demangled form.
+fn demangle_symbols(label: &str) -> String {
+    let mut result = String::with_capacity(label.len());
+    let mut rest = label;
+
+    while let Some(index) = rest.find("_ZN") {
+        match demangle_legacy(&rest[index + 3..]) {
+            Some((demangled, len)) => {
+                // Some platforms add another underscore in front.
+                let prefix = &rest[..index];
+                result.push_str(prefix.strip_suffix('_').unwrap_or(prefix));
+                result.push_str(&demangled);
+                rest = &rest[index + 3 + len..];
+            }
+            None => {
+                result.push_str(&rest[..index + 3]);
+                rest = &rest[index + 3..];
+            }
+        }
+    }
+
+    result.push_str(rest);
+    result
+}
+
+/// Demangles the part of a legacy symbol after `_ZN`, returning the
+/// demangled path and the number of bytes consumed (including the final
+/// `E`).
+fn demangle_legacy(mangled: &str) -> Option<(String, usize)> {
+    let mut segments = Vec::new();
+    let mut pos = 0;
+
+    loop {
+        let rest = &mangled[pos..];
+
+        if rest.starts_with('E') {
+            pos += 1;
+            break;
+        }
+
+        let digits = rest.bytes().take_while(|b| b.is_ascii_digit()).count();
+        let len: usize = rest[..digits].parse().ok()?;
+        let segment = rest.get(digits..digits.checked_add(len)?)?;
+
+        segments.push(segment);
+        pos += digits + len;
+    }
+
+    if let Some(last) = segments.last() {
+        if is_symbol_hash(last) {
+            segments.pop();
+        }
+    }
+
+    if segments.is_empty() {
+        return None;
+    }
+
+    let segments: Vec<_> = segments.into_iter().map(unescape_segment).collect();
+    Some((segments.join("::"), pos))
+}
+
+fn is_symbol_hash(segment: &str) -> bool {
+    segment.len() == 17
+        && segment.starts_with('h')
+        && segment[1..].bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+fn unescape_segment(segment: &str) -> String {
+    // Segments starting with an escape sequence are prefixed with `_`.
+    let segment = if segment.starts_with("_$") {
+        &segment[1..]
+    } else {
+        segment
+    };
+
+    let mut result = String::with_capacity(segment.len());
+    let mut rest = segment;
+
+    while !rest.is_empty() {
+        if let Some(after) = rest.strip_prefix("..") {
+            result.push_str("::");
+            rest = after;
+            continue;
+        }
+
+        if rest.starts_with('$') {
+            if let Some(end) = rest[1..].find('$') {
+                let escape = &rest[1..end + 1];
+                let unescaped = match escape {
+                    "SP" => Some('@'),
+                    "BP" => Some('*'),
+                    "RF" => Some('&'),
+                    "LT" => Some('<'),
+                    "GT" => Some('>'),
+                    "LP" => Some('('),
+                    "RP" => Some(')'),
+                    "C" => Some(','),
+                    _ => escape
+                        .strip_prefix('u')
+                        .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                        .and_then(std::char::from_u32),
+                };
+
+                if let Some(c) = unescaped {
+                    result.push(c);
+                    rest = &rest[end + 2..];
+                    continue;
+                }
+            }
+        }
+
+        let c = rest.chars().next().unwrap();
+        result.push(c);
+        rest = &rest[c.len_utf8()..];
+    }
+
+    result
+}
+
+/// Removes crate disambiguators like the `[a1b2]` in `core[a1b2]::mem`.
+fn strip_hashes(label: &str) -> String {
+    let mut result = String::with_capacity(label.len());
+    let mut rest = label;
+
+    while let Some(index) = rest.find('[') {
+        let after = &rest[index + 1..];
+        let hex_len = after.bytes().take_while(|b| b.is_ascii_hexdigit()).count();
+
+        let is_disambiguator = (4..=16).contains(&hex_len)
+            && after[hex_len..].starts_with(']')
+            && rest[..index]
+                .chars()
+                .next_back()
+                .is_some_and(is_segment_char);
+
+        result.push_str(&rest[..index]);
+
+        if is_disambiguator {
+            rest = &after[hex_len + 1..];
+        } else {
+            result.push('[');
+            rest = after;
+        }
+    }
+
+    result.push_str(rest);
+    result
+}
+
+/// Shortens paths with more than three segments, see `RustcLabelFormatter`.
+fn shorten_paths(label: &str) -> String {
+    let mut result = String::with_capacity(label.len());
+    let mut segments: Vec<&str> = Vec::new();
+    let mut rest = label;
+
+    let flush = |segments: &mut Vec<&str>, result: &mut String| {
+        if segments.len() > 3 {
+            let last_two = &segments[segments.len() - 2..];
+            result.push_str(segments[0]);
+            result.push_str("::..::");
+            result.push_str(&last_two.join("::"));
+        } else {
+            result.push_str(&segments.join("::"));
+        }
+        segments.clear();
+    };
+
+    while !rest.is_empty() {
+        let segment_len: usize = rest
+            .chars()
+            .take_while(|&c| is_segment_char(c))
+            .map(char::len_utf8)
+            .sum();
+
+        if segment_len > 0 {
+            segments.push(&rest[..segment_len]);
+            rest = &rest[segment_len..];
+
+            // Only continue the path if another segment follows.
+            if let Some(after) = rest.strip_prefix("::") {
+                if after.starts_with(is_segment_char) {
+                    rest = after;
+                    continue;
+                }
+            }
+
+            flush(&mut segments, &mut result);
+        } else {
+            let c = rest.chars().next().unwrap();
+            result.push(c);
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+
+    flush(&mut segments, &mut result);
+    result
+}
+
+/// A short id for `label`, the first digits of its XXH64 hash, which is the
+/// same across runs, machines and versions of `measureme`. Tools print it in
+/// place of the end of labels that are too long to show in full, see
+/// `truncate_label()`, so that the full label can be looked up later, e.g.
+/// via `cargo mm label <id>`. Ids are short enough that different labels can
+/// share one, so lookups list every label with the id.
+pub fn label_id(label: &str) -> String {
+    let mut checksum = Checksum::new();
+    checksum.update(LABEL_ID_SCHEME);
+    checksum.update(label.as_bytes());
+
+    let mut id = format!("{:016x}", checksum.finish());
+    id.truncate(LABEL_ID_LEN);
+    id
+}
+
+/// Shortens `label` to `max_len` characters if it is longer, replacing the
+/// end with `…#` and its `label_id()`. Labels are never shortened to less
+/// than the id itself.
+pub fn truncate_label(label: &str, max_len: usize) -> Cow<'_, str> {
+    if label.chars().count() <= max_len {
+        return Cow::Borrowed(label);
+    }
+
+    let keep = max_len.saturating_sub(LABEL_ID_LEN + 2);
+    let mut truncated: String = label.chars().take(keep).collect();
+    truncated.push_str("…#");
+    truncated.push_str(&label_id(label));
+    Cow::Owned(truncated)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format(label: &str) -> Cow<'_, str> {
+        RustcLabelFormatter.format(label)
+    }
+
+    #[test]
+    fn truncation() {
+        let label = "<rustc_middle::ty::Ty as rustc_type_ir::fold::TypeFoldable<TyCtxt>>";
+        let id = label_id(label);
+        assert_eq!(id.len(), 8);
+        assert!(id.bytes().all(|b| b.is_ascii_hexdigit()), "{}", id);
+        assert_eq!(label_id(label), id);
+        assert_ne!(label_id("typeck"), id);
+
+        assert!(matches!(truncate_label(label, 100), Cow::Borrowed(_)));
+        let truncated = truncate_label(label, 30);
+        assert_eq!(truncated, format!("<rustc_middle::ty::T…#{}", id));
+        assert_eq!(truncated.chars().count(), 30);
+        assert_eq!(truncate_label(label, 3), format!("…#{}", id));
+    }
+
+    #[test]
+    fn plain_labels_are_borrowed() {
+        assert!(matches!(format("typeck"), Cow::Borrowed("typeck")));
+        assert!(matches!(format("LLVM_emit_obj"), Cow::Borrowed(_)));
+        assert_eq!(format("[a, b]"), "[a, b]");
+    }
+
+    #[test]
+    fn demangling() {
+        assert_eq!(
+            format("codegen_fn _ZN4core3ptr13drop_in_place17h0123456789abcdefE"),
+            "codegen_fn core::ptr::drop_in_place"
+        );
+        assert_eq!(format("__ZN3foo3BarE"), "foo::Bar");
+        assert_eq!(
+            format("_ZN66_$LT$alloc..vec..Vec$LT$T$GT$$u20$as$u20$core..ops..drop..Drop$GT$4dropE"),
+            "<alloc::vec::Vec<T> as core::..::drop::Drop>::drop"
+        );
+        // Not a valid symbol.
+        assert_eq!(format("_ZN3fo"), "_ZN3fo");
+        // A segment length that overflows.
+        assert_eq!(
+            format("_ZN18446744073709551615aE"),
+            "_ZN18446744073709551615aE"
+        );
+    }
+
+    #[test]
+    fn crate_hashes() {
+        assert_eq!(
+            format("typeck(core[a1b2c3d4]::mem::swap)"),
+            "typeck(core::mem::swap)"
+        );
+        assert_eq!(format("items[3]"), "items[3]");
+    }
+
+    #[test]
+    fn shortened_paths() {
+        assert_eq!(
+            format("rustc_middle::ty::context::TyCtxt::foo"),
+            "rustc_middle::..::TyCtxt::foo"
+        );
+        assert_eq!(
+            format("<std::collections::hash::map::HashMap<K, V> as a::b::c::d::Clone>::clone"),
+            "<std::..::map::HashMap<K, V> as a::..::d::Clone>::clone"
+        );
+        assert_eq!(format("foo::{closure#0}::bar::baz"), "foo::..::bar::baz");
+        assert_eq!(format("a::b::c"), "a::b::c");
+    }
+}
@@ -6,24 +6,151 @@
 //!
 //! To create a [`ProfilingData`], call the [`ProfilingData::new()`] function and
 //! provide a `Path` with the directory and file name for the trace files.
+//! [`ProfilingData::load_all()`] loads several profiles in parallel.
 //!
 //! To retrieve an `Iterator` of all of the events in the file,
 //! call the [`ProfilingData::iter()`] method.
 //!
+//! The `Path` can also be one of the files of the profile, like
+//! `foo-1234.events`, or a directory containing a single profile.
+//! With the `archives` feature (enabled by default), it can also point
+//! to a `.tar`, `.tar.gz` or `.zip` archive containing the trace files, or to
+//! a profile within one, like `artifacts.zip/foo-1234`.
+//! With the `http` feature, it can also be an `http://`, `https://` or `s3://`
+//! URL. Profiles that aren't files at all, e.g. the output of another
+//! process or of a decompressing reader, can be decoded with
+//! [`ProfilingData::from_readers()`].
+//!
+//! With the `serialize` feature, [`Event`], [`Metadata`] and
+//! [`ProfileSummary`] implement `serde::Serialize` and `Deserialize`, so
+//! that tools can cache decoded events or send them to other processes.
+//!
+//! With the `cli` feature, the [`cli`] module has the command line options
+//! all tools share.
+//!
+//! Tools can store what they derive from a profile next to it with
+//! [`AnalysisResults`], so that later invocations reuse the results for as
+//! long as the profile doesn't change.
+//!
+//! A profile that is still being recorded can be read incrementally with a
+//! [`ProfileFollower`], which keeps running totals of the events written so
+//! far.
+//!
+//! Decoding never panics, even for corrupt files, but it may return garbled
+//! strings. Tools reading untrusted profiles can use
+//! [`ProfilingData::from_bytes_strict()`] instead, which validates all events
+//! and strings up front and returns an error for anything malformed, or
+//! [`ProfilingData::new_validated()`] with a [`Validation`] level, which can
+//! also skip malformed events with a warning instead. The decoder is fuzzed
+//! with the target in `analyzeme/fuzz`.
+//!
 //! [`ProfilingData`]: struct.ProfilingData.html
+//! [`ProfilingData::load_all()`]: struct.ProfilingData.html#method.load_all
 //! [`ProfilingData::iter()`]: struct.ProfilingData.html#method.iter
+//! [`ProfilingData::from_readers()`]: struct.ProfilingData.html#method.from_readers
+//! [`ProfilingData::from_bytes_strict()`]: struct.ProfilingData.html#method.from_bytes_strict
+//! [`ProfilingData::new_validated()`]: struct.ProfilingData.html#method.new_validated
+//! [`Validation`]: enum.Validation.html
+//! [`AnalysisResults`]: struct.AnalysisResults.html
+//! [`Event`]: struct.Event.html
+//! [`Metadata`]: struct.Metadata.html
+//! [`ProfileSummary`]: struct.ProfileSummary.html
+//! [`ProfileFollower`]: struct.ProfileFollower.html
+//! [`cli`]: cli/index.html
 
+mod annotate;
+#[cfg(feature = "archives")]
+mod archive;
+mod args;
+#[cfg(feature = "arrow")]
+mod arrow;
+mod call_graph;
+#[cfg(feature = "cli")]
+pub mod cli;
+mod columns;
+mod concat;
+mod diagnostics;
+mod dictionary;
 mod event;
+mod event_adapters;
+mod fingerprint;
+mod flows;
+mod follow;
+mod gaps;
+mod heatmap;
+#[cfg(feature = "http")]
+mod http;
+mod input;
+mod label_rules;
+mod labels;
 mod lightweight_event;
+mod merge;
+mod nesting;
+mod normalize;
+mod phases;
+mod profile_summary;
 mod profiling_data;
+mod results;
+mod sampling;
+mod scrub;
+mod search;
+mod self_profile_events;
+mod session;
+mod sort;
 mod stack_collapse;
+mod stalls;
 mod stringtable;
+mod symbols;
 pub mod testing_common;
+mod threads;
+mod timeline;
 mod timestamp;
 
+pub use crate::annotate::{annotate, Annotation};
+pub use crate::args::{Arg, ArgSchema, ArgValue};
+pub use crate::call_graph::{call_graph, CallGraph, CallGraphEdge, CallGraphNode};
+pub use crate::columns::EventColumns;
+pub use crate::concat::concatenate_profiles;
+pub use crate::diagnostics::{
+    init_logging, Diagnostic, Level, LoadError, LoadErrorKind, MessageFormat, Validation, Verbosity,
+};
+pub use crate::dictionary::{share_strings, SharingStats};
 pub use crate::event::Event;
+pub use crate::event_adapters::{
+    ClipToRange, CoalesceShort, CoalescedEvent, EventIteratorExt, FilterKind, MergeAdjacent,
+    NestedEvent, WithNesting,
+};
+pub use crate::flows::{find_flows, Flow};
+pub use crate::follow::ProfileFollower;
+pub use crate::gaps::{find_gaps, Gap};
+pub use crate::heatmap::{heatmap, Heatmap, HeatmapRow, HeatmapRows};
+pub use crate::label_rules::LabelRules;
+pub use crate::labels::{label_id, truncate_label, LabelFormatter, RustcLabelFormatter};
 pub use crate::lightweight_event::LightweightEvent;
-pub use crate::profiling_data::{ProfilingData, ProfilingDataBuilder};
+pub use crate::merge::merge_profiles;
+pub use crate::nesting::{find_nesting_violations, NestingViolation};
+pub use crate::normalize::{normalize, snapshot_text};
+pub use crate::phases::{find_phases, innermost_phase, Phase};
+pub use crate::profile_summary::{LabelSummary, ProfileSummary};
+pub use crate::profiling_data::{
+    Metadata, Overhead, ProfilingData, ProfilingDataBuilder, ReservedStrings,
+};
+pub use crate::results::AnalysisResults;
+pub use crate::sampling::{sample_events, EventSample};
+pub use crate::scrub::{scrub_paths, PathScrubber};
+pub use crate::search::{SearchIndex, SearchMatch};
+pub use crate::self_profile_events::{filter_self_profile_events, SelfProfileEvents};
+pub use crate::session::{find_session_segments, session_segment, SessionSegment};
+pub use crate::sort::{sort_events_file, sort_profile, DEFAULT_MAX_EVENTS_IN_MEMORY};
 pub use crate::stack_collapse::collapse_stacks;
-pub use crate::stringtable::{StringRef, StringTable};
+pub use crate::stalls::{find_stalls, Stall};
+pub use crate::stringtable::{
+    StringRef, StringResolver, StringTable, StringTableStats, StringUsage,
+};
+#[cfg(feature = "debuginfo")]
+pub use crate::symbols::DebugInfoSymbolizer;
+pub use crate::symbols::{SymbolMap, Symbolizer};
+pub use crate::threads::{ThreadEnd, Track};
+pub use crate::timeline::{ThreadTimeline, TimelineEvent};
 pub use crate::timestamp::Timestamp;
+pub use measureme::{SystemInfo, ToolInfo};
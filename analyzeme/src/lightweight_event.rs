@@ -35,6 +35,17 @@ impl<'a> LightweightEvent<'a> {
         }
     }
 
+    /// Returns the CPU the event started on, if it has been recorded. Like
+    /// the timestamp, this doesn't need the string table.
+    pub fn cpu(&self) -> Option<u32> {
+        self.data.raw_event(self.event_index).cpu()
+    }
+
+    /// Returns true if the event is a blocked interval, see `Event::blocked`.
+    pub fn is_blocked(&self) -> bool {
+        self.data.raw_event(self.event_index).is_blocked()
+    }
+
     pub fn duration(&self) -> Option<Duration> {
         match self.timestamp {
             Timestamp::Interval { start, end } => end.duration_since(start).ok(),
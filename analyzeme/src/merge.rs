@@ -0,0 +1,141 @@
+use crate::profiling_data::Metadata;
+use crate::{Event, ProfilingData, ProfilingDataBuilder};
+use measureme::TimestampFormat;
+use rustc_hash::FxHashMap;
+use std::time::UNIX_EPOCH;
+
+/// Combines several profiles into one, e.g. the profile rustc recorded for
+/// a crate with the profiles its build script and proc macros recorded via
+/// `measureme::build_tools`, so that analysis tools see the whole picture.
+///
+///   - Events are placed on a common time axis that starts with the
+///     earliest profile and are ordered by the time they end, like a
+///     profiler would have written them.
+///   - Every profile gets its own threads, numbered in the order of the
+///     profiles and then in the order in which threads first occur.
+///   - The metadata is taken from the first profile, except that the
+///     argument schemas of all profiles are combined and the profile counts
+///     as truncated if any of them is.
+///
+/// The profiles should be complete, i.e. not aggregate-only.
+pub fn merge_profiles(profiles: &[ProfilingData]) -> ProfilingData {
+    let origin = profiles
+        .iter()
+        .map(|data| data.metadata.start_time)
+        .min()
+        .unwrap_or(UNIX_EPOCH);
+
+    let mut events: Vec<(u32, Event<'_>)> = Vec::new();
+    let mut next_thread_id = 0;
+
+    for data in profiles {
+        let thread_ids = data.dense_thread_ids();
+        events.extend(
+            data.iter()
+                .map(|e| e.to_event())
+                .map(|e| (next_thread_id + thread_ids[&e.thread_id], e)),
+        );
+        next_thread_id += thread_ids.len() as u32;
+    }
+
+    events.sort_by_key(|(_, e)| e.timestamp.end());
+
+    let timestamp_format = if profiles
+        .iter()
+        .all(|data| data.timestamp_format() == TimestampFormat::Compact)
+    {
+        TimestampFormat::Compact
+    } else {
+        TimestampFormat::Wide
+    };
+
+    let mut builder = ProfilingDataBuilder::with_timestamp_format(timestamp_format);
+
+    for (thread_id, event) in &events {
+        builder.write_event(event, *thread_id, origin);
+    }
+
+    // If several profiles have a schema for the same event kind, the first
+    // one wins.
+    let mut arg_schemas = FxHashMap::default();
+    for data in profiles {
+        for (event_kind, schema) in &data.metadata.arg_schemas {
+            arg_schemas
+                .entry(event_kind.clone())
+                .or_insert_with(|| schema.clone());
+        }
+    }
+
+    let first = profiles.first().map(|data| &data.metadata);
+
+    builder.into_profiling_data_with_metadata(Metadata {
+        start_time: origin,
+        process_id: first.map_or(0, |m| m.process_id),
+        cmd: first.map_or_else(String::new, |m| m.cmd.clone()),
+        truncated: profiles.iter().any(|data| data.metadata.truncated),
+        dropped_events: profiles
+            .iter()
+            .map(|data| data.metadata.dropped_events)
+            .sum(),
+        arg_schemas,
+        shared_strings: None,
+        tool: first.and_then(|m| m.tool.clone()),
+        aggregate_only: false,
+        budget_exhausted: profiles.iter().any(|data| data.metadata.budget_exhausted),
+        event_kinds: Vec::new(),
+        reserved_strings: Vec::new(),
+        thread_event_files: Vec::new(),
+        event_segments: Vec::new(),
+        overhead: None,
+        system: first.and_then(|m| m.system.clone()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn profile(start_nanos: u64, build: impl FnOnce(&mut ProfilingDataBuilder)) -> ProfilingData {
+        let mut builder = ProfilingDataBuilder::new();
+        build(&mut builder);
+        let mut data = builder.into_profiling_data();
+        data.metadata.start_time = UNIX_EPOCH + Duration::from_nanos(start_nanos);
+        data
+    }
+
+    #[test]
+    fn merging() {
+        let rustc = profile(1_000, |b| {
+            b.interval("GenericActivity", "expand_crate", 5, 0, 500, |_| {});
+            b.interval("Query", "typeck", 7, 600, 700, |_| {});
+        });
+        let proc_macro = profile(1_100, |b| {
+            b.interval("ProcMacro", "derive(Serialize)", 5, 10, 50, |_| {});
+        });
+
+        let merged = merge_profiles(&[rustc, proc_macro]);
+        assert_eq!(
+            merged.metadata.start_time,
+            UNIX_EPOCH + Duration::from_nanos(1_000)
+        );
+
+        let events: Vec<_> = merged
+            .iter()
+            .map(|e| e.to_event())
+            .map(|e| {
+                let start = e.timestamp.start().duration_since(UNIX_EPOCH).unwrap();
+                (e.label.into_owned(), e.thread_id, start.as_nanos())
+            })
+            .collect();
+
+        assert_eq!(
+            events,
+            vec![
+                ("derive(Serialize)".to_string(), 2, 1_110),
+                ("expand_crate".to_string(), 0, 1_000),
+                ("typeck".to_string(), 1, 1_600),
+            ]
+        );
+    }
+}
@@ -0,0 +1,167 @@
+use crate::{LightweightEvent, ProfilingData, Timestamp};
+use rustc_hash::FxHashMap;
+use std::time::{Duration, SystemTime};
+
+/// Two interval events on the same thread that overlap without one
+/// containing the other, which a profile recorded with the RAII API can't
+/// contain. Such profiles come from embedders that record the start and end
+/// of events by hand, or from timestamps that went backwards.
+#[derive(Clone, Debug)]
+pub struct NestingViolation<'a> {
+    pub thread_id: u32,
+    /// The event that started first.
+    pub earlier: LightweightEvent<'a>,
+    /// The event that started while `earlier` was running, but ended after
+    /// it.
+    pub later: LightweightEvent<'a>,
+}
+
+impl<'a> NestingViolation<'a> {
+    /// The time during which both events were running.
+    pub fn overlap(&self) -> Duration {
+        let start = self.later.timestamp.start();
+        self.earlier
+            .timestamp
+            .end()
+            .duration_since(start)
+            .unwrap_or_default()
+    }
+}
+
+/// Checks that the interval events of each thread are properly nested, i.e.
+/// that any two of them are either disjoint or one of them contains the
+/// other. Analyses that attribute self time or build stacks, like
+/// `summarize` and the flamegraphs, assume that they are, so broken nesting
+/// silently makes their results wrong.
+///
+/// An event that partially overlaps several events it started in is
+/// reported once for each of them. Instant events can't overlap anything
+/// and are ignored. The result is ordered by thread id and then by the
+/// start of the later event.
+pub fn find_nesting_violations(profiling_data: &ProfilingData) -> Vec<NestingViolation<'_>> {
+    let mut events_per_thread = FxHashMap::<u32, Vec<LightweightEvent<'_>>>::default();
+
+    for event in profiling_data.iter() {
+        if let Timestamp::Interval { .. } = event.timestamp {
+            events_per_thread
+                .entry(event.thread_id)
+                .or_default()
+                .push(event);
+        }
+    }
+
+    let mut thread_ids: Vec<u32> = events_per_thread.keys().cloned().collect();
+    thread_ids.sort_unstable();
+
+    let mut violations = Vec::new();
+
+    for thread_id in thread_ids {
+        let events = events_per_thread.get_mut(&thread_id).unwrap();
+        // Parents before their children. Among events with the same start
+        // and end, the one recorded last is the parent, as in
+        // `per_thread_timelines()`.
+        events.sort_by(|a, b| {
+            (a.timestamp.start(), b.timestamp.end(), b.event_index).cmp(&(
+                b.timestamp.start(),
+                a.timestamp.end(),
+                a.event_index,
+            ))
+        });
+
+        // The events that are running at the start of the current one,
+        // innermost last.
+        let mut open: Vec<(SystemTime, usize)> = Vec::new();
+
+        for (index, event) in events.iter().enumerate() {
+            let (start, end) = (event.timestamp.start(), event.timestamp.end());
+
+            while open.last().is_some_and(|&(open_end, _)| open_end <= start) {
+                open.pop();
+            }
+
+            for &(open_end, open_index) in open.iter().rev() {
+                if open_end >= end {
+                    break;
+                }
+                violations.push(NestingViolation {
+                    thread_id,
+                    earlier: events[open_index].clone(),
+                    later: event.clone(),
+                });
+            }
+
+            open.push((end, index));
+        }
+    }
+
+    violations
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ProfilingDataBuilder;
+
+    fn pairs(data: &ProfilingData) -> Vec<(u32, String, String)> {
+        let label = |event: &LightweightEvent<'_>| event.to_event().label.into_owned();
+        find_nesting_violations(data)
+            .iter()
+            .map(|v| (v.thread_id, label(&v.earlier), label(&v.later)))
+            .collect()
+    }
+
+    #[test]
+    fn properly_nested() {
+        let mut b = ProfilingDataBuilder::new();
+
+        b.interval("Query", "outer", 0, 0, 100, |b| {
+            b.interval("Query", "same", 0, 10, 50, |b| {
+                b.interval("Query", "same", 0, 10, 50, |_| {});
+            });
+            b.interval("Query", "adjacent", 0, 50, 100, |_| {});
+        });
+        b.instant("Marker", "instant", 0, 120);
+        // Other threads may overlap freely.
+        b.interval("Query", "other", 1, 40, 150, |_| {});
+
+        assert!(pairs(&b.into_profiling_data()).is_empty());
+    }
+
+    #[test]
+    fn partial_overlaps() {
+        //  <======= a =======>
+        //  0    <--- b --->  40
+        //       10  <---c--------->
+        //           20     30     60
+        let mut b = ProfilingDataBuilder::new();
+
+        b.interval("Query", "b", 0, 10, 30, |_| {});
+        b.interval("Query", "a", 0, 0, 40, |_| {});
+        b.interval("Query", "c", 0, 20, 60, |_| {});
+        b.interval("Query", "d", 1, 0, 10, |_| {});
+        b.interval("Query", "e", 1, 5, 15, |_| {});
+
+        let data = b.into_profiling_data();
+        assert_eq!(
+            pairs(&data),
+            vec![
+                (0, "b".to_string(), "c".to_string()),
+                (0, "a".to_string(), "c".to_string()),
+                (1, "d".to_string(), "e".to_string()),
+            ]
+        );
+
+        let overlaps: Vec<_> = find_nesting_violations(&data)
+            .iter()
+            .map(|v| v.overlap())
+            .collect();
+        assert_eq!(
+            overlaps,
+            vec![
+                Duration::from_nanos(10),
+                Duration::from_nanos(20),
+                Duration::from_nanos(5)
+            ]
+        );
+    }
+}
@@ -0,0 +1,238 @@
+use crate::profiling_data::Metadata;
+use crate::{Event, ProfilingData, ProfilingDataBuilder, TimelineEvent, Timestamp};
+use measureme::RawEvent;
+use std::collections::BTreeSet;
+use std::fmt::Write;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Rewrites a profile into a canonical form that only depends on which
+/// events have been recorded and how they nest, not on the actual timings or
+/// on the environment the profile was recorded in:
+///
+///   - Timestamps are rebased so that the earliest event starts at zero and
+///     are then rounded down to a multiple of `bucket_size` (a `bucket_size`
+///     of zero keeps the original resolution).
+///   - Thread ids are renumbered in the order in which threads first occur
+///     in the event stream.
+///   - Strings are stored in sorted order.
+///   - The metadata is replaced by fixed values, except for the argument
+///     schemas.
+///
+/// The order of the events in the stream is preserved. This is useful for
+/// snapshot-testing instrumentation. See also `snapshot_text()`.
+pub fn normalize(data: &ProfilingData, bucket_size: Duration) -> ProfilingData {
+    let events: Vec<Event<'_>> = data.iter().map(|e| e.to_event()).collect();
+
+    let origin = events
+        .iter()
+        .map(|e| e.timestamp.start())
+        .min()
+        .unwrap_or(data.metadata.start_time);
+
+    let bucket_nanos = bucket_size.as_nanos() as u64;
+    let normalize_time = |t: SystemTime| {
+        let nanos = t.duration_since(origin).unwrap().as_nanos() as u64;
+        if bucket_nanos == 0 {
+            nanos
+        } else {
+            nanos - nanos % bucket_nanos
+        }
+    };
+
+    let thread_ids = data.dense_thread_ids();
+
+    let mut builder = ProfilingDataBuilder::with_timestamp_format(data.timestamp_format());
+
+    // Allocate all strings up front, so that the string table doesn't depend
+    // on the order in which strings have been allocated by the profiler.
+    let strings: BTreeSet<&str> = events
+        .iter()
+        .flat_map(|e| {
+            let args = e.additional_data.iter().map(|arg| &arg[..]);
+            std::iter::once(&e.event_kind[..])
+                .chain(std::iter::once(&e.label[..]))
+                .chain(args)
+        })
+        .collect();
+
+    for s in strings {
+        builder.alloc_string(s);
+    }
+
+    for event in &events {
+        let event_kind = builder.alloc_string(&event.event_kind);
+        let args: Vec<&str> = event.additional_data.iter().map(|arg| &arg[..]).collect();
+        let event_id = builder.alloc_event_id(&event.label, &args);
+        let thread_id = thread_ids[&event.thread_id];
+
+        let raw_event = match event.timestamp {
+            Timestamp::Interval { start, end } => {
+                let raw_event = RawEvent::new_interval_wide(
+                    event_kind,
+                    event_id,
+                    thread_id,
+                    normalize_time(start),
+                    normalize_time(end),
+                );
+
+                if event.blocked {
+                    raw_event.as_blocked()
+                } else {
+                    raw_event
+                }
+            }
+            Timestamp::Instant(t) => match event.integer_value {
+                Some(value) => RawEvent::new_integer_wide(
+                    event_kind,
+                    event_id,
+                    thread_id,
+                    normalize_time(t),
+                    value,
+                ),
+                None => {
+                    RawEvent::new_instant_wide(event_kind, event_id, thread_id, normalize_time(t))
+                }
+            },
+        };
+
+        builder.write_raw_event(&raw_event);
+    }
+
+    builder.into_profiling_data_with_metadata(Metadata {
+        start_time: UNIX_EPOCH,
+        process_id: 0,
+        cmd: String::new(),
+        truncated: false,
+        dropped_events: 0,
+        arg_schemas: data.metadata.arg_schemas.clone(),
+        shared_strings: None,
+        tool: None,
+        aggregate_only: data.metadata.aggregate_only,
+        budget_exhausted: false,
+        event_kinds: Vec::new(),
+        reserved_strings: Vec::new(),
+        thread_event_files: Vec::new(),
+        event_segments: Vec::new(),
+        overhead: None,
+        system: None,
+    })
+}
+
+/// Renders the events of every thread as an indented tree, one event per
+/// line, with timestamps in nanoseconds relative to the start of the
+/// profile. Together with `normalize()` this gives a stable textual
+/// representation of a profile that can be compared against a snapshot.
+pub fn snapshot_text(data: &ProfilingData) -> String {
+    fn render(
+        out: &mut String,
+        events: &[TimelineEvent<'_>],
+        depth: usize,
+        start_time: SystemTime,
+    ) {
+        let nanos = |t: SystemTime| t.duration_since(start_time).unwrap().as_nanos();
+
+        for timeline_event in events {
+            let event = timeline_event.event.to_event();
+
+            write!(
+                out,
+                "{:indent$}{} {}",
+                "",
+                event.event_kind,
+                event.label,
+                indent = depth * 2
+            )
+            .unwrap();
+
+            if !event.additional_data.is_empty() {
+                write!(out, " ({})", event.additional_data.join(", ")).unwrap();
+            }
+
+            if let Some(value) = event.integer_value {
+                write!(out, " [{}]", value).unwrap();
+            }
+
+            match event.timestamp {
+                Timestamp::Interval { start, end } => {
+                    writeln!(out, " {}..{}", nanos(start), nanos(end)).unwrap()
+                }
+                Timestamp::Instant(t) => writeln!(out, " @{}", nanos(t)).unwrap(),
+            }
+
+            render(out, &timeline_event.children, depth + 1, start_time);
+        }
+    }
+
+    let mut out = String::new();
+
+    for timeline in data.per_thread_timelines() {
+        writeln!(out, "thread {}", timeline.thread_id).unwrap();
+        render(&mut out, &timeline.events, 1, data.metadata.start_time);
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(b: &mut ProfilingDataBuilder, thread: u32, offset: u64, jitter: u64) {
+        b.interval(
+            "Query",
+            "outer",
+            thread,
+            offset,
+            offset + 100 + jitter,
+            |b| {
+                b.interval(
+                    "Query",
+                    "inner",
+                    thread,
+                    offset + 10,
+                    offset + 50 + jitter,
+                    |_| {},
+                );
+                b.instant("QueryCacheHit", "hit", thread, offset + 60 + jitter);
+                b.integer("CacheSize", "entries", thread, offset + 70 + jitter, 12);
+            },
+        );
+    }
+
+    #[test]
+    fn independent_of_timings_and_thread_ids() {
+        let mut b1 = ProfilingDataBuilder::new();
+        record(&mut b1, 7, 1000, 0);
+        b1.instant("Marker", "marker", 7, 1150);
+        record(&mut b1, 3, 1200, 0);
+
+        // Same structure, but different thread ids, timings and string
+        // allocation order.
+        let mut b2 = ProfilingDataBuilder::new();
+        b2.alloc_string("hit");
+        record(&mut b2, 42, 5000, 3);
+        b2.instant("Marker", "marker", 42, 5155);
+        record(&mut b2, 9, 5200, 7);
+
+        let bucket = Duration::from_nanos(10);
+        let n1 = normalize(&b1.into_profiling_data(), bucket);
+        let n2 = normalize(&b2.into_profiling_data(), bucket);
+
+        let expected = "\
+thread 0
+  Query outer 0..100
+    Query inner 10..50
+    QueryCacheHit hit @60
+    CacheSize entries [12] @70
+  Marker marker @150
+thread 1
+  Query outer 200..300
+    Query inner 210..250
+    QueryCacheHit hit @260
+    CacheSize entries [12] @270
+";
+
+        assert_eq!(snapshot_text(&n1), expected);
+        assert_eq!(snapshot_text(&n2), expected);
+    }
+}
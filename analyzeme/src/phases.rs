@@ -0,0 +1,121 @@
+use crate::ProfilingData;
+use measureme::event_kinds::{PHASE_END_EVENT_KIND, PHASE_START_EVENT_KIND};
+use std::time::{Duration, SystemTime};
+
+/// A phase delimited via `measureme::Profiler::start_phase()` and
+/// `end_phase()`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Phase {
+    pub name: String,
+    pub start: SystemTime,
+    pub end: SystemTime,
+    /// The number of phases this phase is nested in.
+    pub depth: usize,
+}
+
+impl Phase {
+    pub fn duration(&self) -> Duration {
+        self.end.duration_since(self.start).unwrap()
+    }
+
+    pub fn contains(&self, t: SystemTime) -> bool {
+        t >= self.start && t < self.end
+    }
+}
+
+/// Returns the phases recorded in the profile, ordered by start time. Phases
+/// that were never ended (e.g. because the process crashed) are treated as
+/// ending with the last event of the profile.
+pub fn find_phases(profiling_data: &ProfilingData) -> Vec<Phase> {
+    let mut phases = Vec::new();
+    // Indices into `phases` of the phases that haven't ended yet.
+    let mut open = Vec::<usize>::new();
+    let mut last_timestamp = None;
+
+    for event in profiling_data.iter() {
+        last_timestamp = last_timestamp.max(Some(event.timestamp.end()));
+
+        if !event.timestamp.is_instant() {
+            continue;
+        }
+
+        let event = event.to_event();
+        let t = event.timestamp.start();
+
+        if event.event_kind == PHASE_START_EVENT_KIND {
+            open.push(phases.len());
+            phases.push(Phase {
+                name: event.label.into_owned(),
+                start: t,
+                end: t,
+                depth: open.len() - 1,
+            });
+        } else if event.event_kind == PHASE_END_EVENT_KIND {
+            if let Some(index) = open.pop() {
+                phases[index].end = t;
+            }
+        }
+    }
+
+    for index in open {
+        phases[index].end = last_timestamp.unwrap();
+    }
+
+    phases
+}
+
+/// Returns the index of the innermost phase in `phases` (as returned by
+/// `find_phases()`) that contains `t`.
+pub fn innermost_phase(phases: &[Phase], t: SystemTime) -> Option<usize> {
+    // Phases are properly nested, so among the phases containing `t` the
+    // innermost one is the one that started last.
+    phases.iter().rposition(|phase| phase.contains(t))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ProfilingDataBuilder;
+    use std::time::UNIX_EPOCH;
+
+    fn time(nanos: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_nanos(nanos)
+    }
+
+    #[test]
+    fn nested_and_unfinished_phases() {
+        let mut b = ProfilingDataBuilder::new();
+
+        b.instant(PHASE_START_EVENT_KIND, "expansion", 0, 0);
+        b.interval("Query", "q1", 0, 10, 20, |_| {});
+        b.instant(PHASE_END_EVENT_KIND, "expansion", 0, 30);
+        b.instant(PHASE_START_EVENT_KIND, "analysis", 0, 30);
+        b.instant(PHASE_START_EVENT_KIND, "type-checking", 1, 40);
+        b.instant(PHASE_END_EVENT_KIND, "type-checking", 1, 60);
+        b.interval("Query", "q2", 0, 70, 90, |_| {});
+
+        let phases = find_phases(&b.into_profiling_data());
+
+        let phase = |name: &str, start, end, depth| Phase {
+            name: name.to_string(),
+            start: time(start),
+            end: time(end),
+            depth,
+        };
+
+        assert_eq!(
+            phases,
+            vec![
+                phase("expansion", 0, 30, 0),
+                phase("analysis", 30, 90, 0),
+                phase("type-checking", 40, 60, 1),
+            ]
+        );
+
+        assert_eq!(innermost_phase(&phases, time(10)), Some(0));
+        assert_eq!(innermost_phase(&phases, time(30)), Some(1));
+        assert_eq!(innermost_phase(&phases, time(50)), Some(2));
+        assert_eq!(innermost_phase(&phases, time(60)), Some(1));
+        assert_eq!(innermost_phase(&phases, time(90)), None);
+    }
+}
@@ -0,0 +1,165 @@
+use crate::event::Event;
+use crate::profiling_data::{load_string_table, Metadata};
+use crate::stringtable::StringTable;
+use measureme::summary::SUMMARY_FORMAT_VERSION;
+use measureme::{EventId, LabelTotals, ProfilerFiles, StringId};
+use rustc_hash::FxHashMap;
+use serde::Deserialize;
+#[cfg(feature = "serialize")]
+use serde::Serialize;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+/// The totals for all events with a given kind and label.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub struct LabelSummary {
+    pub event_kind: String,
+    pub label: String,
+    /// The number of events, including instant and integer events.
+    pub count: u64,
+    /// The total duration of the interval events.
+    pub total_time: Duration,
+    /// The duration of the longest interval event.
+    pub max_time: Duration,
+}
+
+/// The running totals that the profiler writes to a `.summary` file next to
+/// the profile if `measureme::ProfilerConfig::summary` is enabled, see the
+/// `measureme::summary` module. Loading them only needs the profile's string
+/// table, not its event stream.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub struct ProfileSummary {
+    /// Ordered by total time, longest first. Events whose ids only differ in
+    /// their arguments are combined.
+    pub labels: Vec<LabelSummary>,
+}
+
+#[derive(Deserialize)]
+struct SummaryFile {
+    version: u32,
+    labels: Vec<SummaryEntry>,
+}
+
+#[derive(Deserialize)]
+struct SummaryEntry {
+    event_kind: u32,
+    event_id: u32,
+    count: u64,
+    total_nanos: u64,
+    max_nanos: u64,
+}
+
+impl ProfileSummary {
+    pub fn new(path_stem: &Path) -> Result<ProfileSummary, Box<dyn Error>> {
+        let summary_file = ProfilerFiles::new(path_stem).summary_file;
+
+        let contents = fs::read_to_string(&summary_file).map_err(|e| {
+            format!(
+                "couldn't read summary file `{}`: {}",
+                summary_file.display(),
+                e
+            )
+        })?;
+        let summary: SummaryFile = serde_json::from_str(&contents)?;
+
+        if summary.version != SUMMARY_FORMAT_VERSION {
+            Err(format!(
+                "summary file format version '{}' is not supported by this version of `analyzeme`",
+                summary.version
+            ))?;
+        }
+
+        let string_table = load_string_table(path_stem)?;
+        let metadata: Metadata = serde_json::from_str(&string_table.get_metadata().to_string())?;
+
+        if let Some(entry) = summary.labels.iter().find(|entry| {
+            !StringId::from_raw(entry.event_kind).is_valid()
+                || !EventId::from_raw(entry.event_id).is_valid()
+        }) {
+            Err(format!(
+                "summary file `{}` refers to the invalid string ids {} and {}",
+                summary_file.display(),
+                entry.event_kind,
+                entry.event_id
+            ))?;
+        }
+
+        let totals = summary.labels.into_iter().map(|entry| LabelTotals {
+            event_kind: StringId::from_raw(entry.event_kind),
+            event_id: EventId::from_raw(entry.event_id),
+            count: entry.count,
+            total_nanos: entry.total_nanos,
+            max_nanos: entry.max_nanos,
+        });
+        Ok(ProfileSummary::from_totals(totals, &string_table, |kind| {
+            metadata.event_kind_name(kind)
+        }))
+    }
+
+    /// Combines the totals per event kind and id, as the profiler or
+    /// `ProfileFollower` keeps them, into totals per event kind and label.
+    /// `event_kind_name` resolves registered event kinds, see
+    /// `Metadata::event_kind_name()`.
+    pub(crate) fn from_totals(
+        totals: impl IntoIterator<Item = LabelTotals>,
+        string_table: &StringTable,
+        event_kind_name: impl Fn(StringId) -> StringId,
+    ) -> ProfileSummary {
+        let mut labels = FxHashMap::<(String, String), LabelSummary>::default();
+
+        for entry in totals {
+            let event_kind = string_table
+                .get(event_kind_name(entry.event_kind))
+                .to_string()
+                .into_owned();
+            let event_id = string_table.get(entry.event_id.to_string_id()).to_string();
+            let (label, _) = Event::parse_event_id(event_id);
+            let label = label.into_owned();
+
+            let summary = labels
+                .entry((event_kind.clone(), label.clone()))
+                .or_insert_with(|| LabelSummary {
+                    event_kind,
+                    label,
+                    count: 0,
+                    total_time: Duration::from_nanos(0),
+                    max_time: Duration::from_nanos(0),
+                });
+
+            let max_time = Duration::from_nanos(entry.max_nanos);
+            summary.count += entry.count;
+            summary.total_time += Duration::from_nanos(entry.total_nanos);
+            summary.max_time = summary.max_time.max(max_time);
+        }
+
+        let mut labels: Vec<_> = labels.into_values().collect();
+        labels.sort_by(|a, b| {
+            b.total_time
+                .cmp(&a.total_time)
+                .then_with(|| (&a.event_kind, &a.label).cmp(&(&b.event_kind, &b.label)))
+        });
+
+        ProfileSummary { labels }
+    }
+
+    /// Returns the total time of the interval events per event kind, longest
+    /// first.
+    pub fn total_time_per_kind(&self) -> Vec<(String, Duration)> {
+        let mut kinds = FxHashMap::<&str, Duration>::default();
+
+        for label in &self.labels {
+            *kinds.entry(&label.event_kind).or_default() += label.total_time;
+        }
+
+        let mut kinds: Vec<_> = kinds
+            .into_iter()
+            .map(|(kind, total_time)| (kind.to_string(), total_time))
+            .collect();
+        kinds.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        kinds
+    }
+}
@@ -1,22 +1,298 @@
+use crate::annotate::write_copy_with;
+#[cfg(feature = "archives")]
+use crate::archive::Archive;
+use crate::args::{self, Arg, ArgSchema, ArgValue};
+use crate::columns::{self, EventColumns};
+use crate::diagnostics::{Diagnostic, LoadError, LoadErrorKind, Validation};
 use crate::event::Event;
+use crate::fingerprint;
+#[cfg(feature = "http")]
+use crate::http;
+use crate::input::{self, Input};
+use crate::label_rules::LabelRules;
+use crate::labels::{BoxedLabelFormatter, LabelFormatter};
 use crate::lightweight_event::LightweightEvent;
+use crate::sort;
+use crate::stringtable::StringResolver;
+use crate::symbols::{BoxedSymbolizer, Symbolizer};
+use crate::threads::{self, ThreadEnd, Track};
+use crate::timeline::{self, ThreadTimeline};
 use crate::timestamp::Timestamp;
 use crate::StringTable;
+use measureme::checksum::checksum;
+use measureme::encryption::{self, ProfileCipher};
+use measureme::event_id::SEPARATOR_BYTE;
 use measureme::file_header::{
-    read_file_header, write_file_header, CURRENT_FILE_FORMAT_VERSION, FILE_HEADER_SIZE,
-    FILE_MAGIC_EVENT_STREAM,
+    file_footer, read_file_header, read_full_file_header, verify_file_footer,
+    write_file_header_with_features, CURRENT_FILE_FORMAT_VERSION, FEATURE_BLOCKED_INTERVALS,
+    FILE_HEADER_SIZE, FILE_MAGIC_STRINGTABLE_DATA, FILE_MAGIC_STRINGTABLE_INDEX,
 };
+#[cfg(feature = "http")]
+use measureme::file_header::{FILE_MAGIC_EVENT_STREAM, FILE_MAGIC_EVENT_STREAM_WIDE};
+use measureme::stringtable::StringTableBuilder;
+use measureme::stringtable::{FIRST_RESERVED_STRING_ID, FIRST_SHARED_STRING_ID, MAX_STRING_ID};
 use measureme::ByteVecSink;
-use measureme::{EventId, ProfilerFiles, RawEvent, SerializationSink, StringTableBuilder};
+use measureme::{
+    EventId, ProfilerFiles, RawEvent, SerializationSink, StringComponent, StringId, SystemInfo,
+    TimestampFormat, TimestampResolution, ToolInfo,
+};
+use rustc_hash::FxHashMap;
 use serde::{Deserialize, Deserializer};
+#[cfg(feature = "serialize")]
+use serde::{Serialize, Serializer};
+use std::borrow::Cow;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
 use std::error::Error;
 use std::fs;
-use std::mem;
+use std::io::Read;
+use std::num::NonZeroUsize;
 use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Reads the file at `path`, decrypting it if necessary.
+fn read_file(
+    path: &Path,
+    cipher: Option<&dyn ProfileCipher>,
+    read_error: &str,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    measureme::activity!("read_file");
+    let data = fs::read(path).map_err(|e| {
+        let kind = match e.kind() {
+            std::io::ErrorKind::NotFound => LoadErrorKind::FileMissing,
+            _ => LoadErrorKind::FileUnreadable,
+        };
+        LoadError::new(kind, format!("{} `{}`: {}", read_error, path.display(), e))
+    })?;
+    Diagnostic::info(
+        "file-read",
+        format!("read `{}` ({} bytes)", path.display(), data.len()),
+    )
+    .log();
+
+    decrypt_if_needed(data, cipher, &path.display().to_string())
+}
+
+/// Reads the given files, each with the error message to use if it can't be
+/// read, on a thread per file, so that slow disks and decryption overlap.
+fn read_files(
+    files: &[(&Path, &str)],
+    cipher: Option<&dyn ProfileCipher>,
+) -> Result<Vec<Vec<u8>>, Box<dyn Error>> {
+    if files.len() < 2 {
+        return files
+            .iter()
+            .map(|&(path, read_error)| read_file(path, cipher, read_error))
+            .collect();
+    }
+
+    thread::scope(|scope| {
+        let readers: Vec<_> = files
+            .iter()
+            .map(|&(path, read_error)| {
+                scope.spawn(move || read_file(path, cipher, read_error).map_err(sendable))
+            })
+            .collect();
+
+        readers
+            .into_iter()
+            .map(|reader| reader.join().unwrap().map_err(|e| e as Box<dyn Error>))
+            .collect()
+    })
+}
+
+/// Errors have to be `Send` to be passed between threads. They are mostly
+/// `LoadError`s, which keep their kind, anything else keeps its message.
+fn sendable(error: Box<dyn Error>) -> Box<dyn Error + Send + Sync> {
+    match error.downcast::<LoadError>() {
+        Ok(error) => error,
+        Err(error) => error.to_string().into(),
+    }
+}
+
+/// The most `invalid-event` warnings `Validation::Lenient` reports one by
+/// one, before summing up the rest.
+const MAX_EVENT_WARNINGS: usize = 10;
+
+/// The most profiles `ProfilingData::load_all()` loads at the same time.
+/// Every profile is read on a few threads of its own already.
+const MAX_PARALLEL_LOADS: usize = 8;
+
+fn decrypt_if_needed(
+    data: Vec<u8>,
+    cipher: Option<&dyn ProfileCipher>,
+    file_name: &str,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    if !encryption::is_encrypted(&data) {
+        return Ok(data);
+    }
+
+    match cipher {
+        Some(cipher) => Ok(encryption::decrypt_file(&data, cipher).map_err(|e| {
+            LoadError::new(LoadErrorKind::Encrypted, format!("`{}`: {}", file_name, e))
+        })?),
+        None => Err(From::from(LoadError::new(
+            LoadErrorKind::Encrypted,
+            format!(
+                "`{}` is encrypted, use `ProfilingData::new_encrypted()` for reading it",
+                file_name
+            ),
+        ))),
+    }
+}
+
+/// Adds the file name to an error of the `file_header` functions, which
+/// return `LoadError`s with the kind, telling e.g. files whose footer is
+/// missing apart from files whose footer doesn't match their contents.
+fn in_file(file_name: &str, error: Box<dyn Error>) -> LoadError {
+    let kind = LoadError::kind_of(&*error).unwrap_or(LoadErrorKind::Corrupt);
+    LoadError::new(kind, format!("`{}`: {}", file_name, error))
+}
+
+/// Checks the header of `data` (which must be long enough to have one) and
+/// returns the file format version.
+pub(crate) fn check_file_header(
+    data: &[u8],
+    file_magic: &[u8; 4],
+    file_name: &str,
+) -> Result<u32, LoadError> {
+    let header = read_full_file_header(data, file_magic).map_err(|e| in_file(file_name, e))?;
+
+    if header.version < CURRENT_FILE_FORMAT_VERSION {
+        return Err(LoadError::new(
+            LoadErrorKind::FormatTooOld,
+            format!(
+                "`{}` has file format version '{}', which is not supported by this version of \
+                 `measureme` (version '{}')",
+                file_name, header.version, CURRENT_FILE_FORMAT_VERSION
+            ),
+        ));
+    }
 
-const RAW_EVENT_SIZE: usize = mem::size_of::<RawEvent>();
+    header.check_readable().map_err(|e| in_file(file_name, e))?;
+
+    Ok(header.version)
+}
+
+/// Checks that the files, whose headers have already been checked, have
+/// been written by the same profiling session as the events file, so that
+/// mixing up the files of different runs doesn't produce garbled labels.
+pub(crate) fn check_same_session(
+    (events, events_magic, events_file): (&[u8], &[u8; 4], &str),
+    others: &[(&[u8], &[u8; 4], &str)],
+) -> Result<(), Box<dyn Error>> {
+    let session_id = read_full_file_header(events, events_magic)?.session_id;
+
+    for &(data, file_magic, file_name) in others {
+        if read_full_file_header(data, file_magic)?.session_id != session_id {
+            Err(LoadError::new(
+                LoadErrorKind::Mismatched,
+                format!(
+                    "`{}` and `{}` have been written by different profiling sessions, \
+                     the files of different runs have probably been mixed up",
+                    file_name, events_file
+                ),
+            ))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks the header of `data`, verifies the checksum in its footer and
+/// removes the footer.
+fn strip_file_footer(
+    mut data: Vec<u8>,
+    file_magic: &[u8; 4],
+    file_name: &str,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    check_file_header(&data, file_magic, file_name)?;
+
+    let len = match verify_file_footer(&data) {
+        Ok(contents) => contents.len(),
+        Err(e) => Err(in_file(file_name, e))?,
+    };
+    data.truncate(len);
+
+    Ok(data)
+}
+
+/// Loads the string table of a `measureme::SharedStringCache`.
+pub(crate) fn load_shared_string_table(
+    path_stem: &Path,
+    cipher: Option<&dyn ProfileCipher>,
+) -> Result<StringTable, Box<dyn Error>> {
+    let paths = ProfilerFiles::new(path_stem);
+    let mut files = Vec::new();
+
+    for (path, file_magic) in [
+        (&paths.string_data_file, FILE_MAGIC_STRINGTABLE_DATA),
+        (&paths.string_index_file, FILE_MAGIC_STRINGTABLE_INDEX),
+    ] {
+        if !path.exists() {
+            Err(LoadError::new(
+                LoadErrorKind::FileMissing,
+                format!(
+                    "the profile refers to the shared string cache `{}`, which doesn't exist",
+                    path.display()
+                ),
+            ))?;
+        }
+
+        let data = read_file(path, cipher, "couldn't read shared string cache")?;
+        files.push(strip_file_footer(
+            data,
+            file_magic,
+            &path.display().to_string(),
+        )?);
+    }
+
+    let index_data = files.pop().unwrap();
+    let string_data = files.pop().unwrap();
+    StringTable::new(string_data, index_data)
+}
+
+/// Loads only the string table of the profile at `path_stem`, e.g. for
+/// reading its `.summary` file without touching the event stream.
+pub(crate) fn load_string_table(path_stem: &Path) -> Result<StringTable, Box<dyn Error>> {
+    let paths = ProfilerFiles::new(path_stem);
+
+    let string_data = read_file(
+        &paths.string_data_file,
+        None,
+        "couldn't read string_data file",
+    )?;
+    let index_data = read_file(
+        &paths.string_index_file,
+        None,
+        "couldn't read string_index file",
+    )?;
+
+    let string_data = strip_file_footer(
+        string_data,
+        FILE_MAGIC_STRINGTABLE_DATA,
+        &paths.string_data_file.display().to_string(),
+    )?;
+    let index_data = strip_file_footer(
+        index_data,
+        FILE_MAGIC_STRINGTABLE_INDEX,
+        &paths.string_index_file.display().to_string(),
+    )?;
+
+    let mut string_table = StringTable::new(string_data, index_data)?;
+    let metadata: Metadata = serde_json::from_str(&string_table.get_metadata().to_string())?;
+    metadata.check_file_names()?;
+
+    if let Some(shared_strings) = &metadata.shared_strings {
+        let shared = load_shared_string_table(&path_stem.with_file_name(shared_strings), None)?;
+        string_table.set_shared(Arc::new(shared));
+    }
+
+    Ok(string_table)
+}
 
 fn system_time_from_nanos<'de, D>(deserializer: D) -> Result<SystemTime, D::Error>
 where
@@ -28,67 +304,1190 @@ where
         .expect("a time that can be represented as SystemTime"))
 }
 
-#[derive(Debug, Deserialize)]
+#[cfg(feature = "serialize")]
+fn system_time_to_nanos<S>(time: &SystemTime, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    let duration_from_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    serializer.serialize_u64(duration_from_epoch.as_nanos() as u64)
+}
+
+/// With the `serialize` feature, the metadata implements `Serialize`, which
+/// writes it in the format it has in the profile.
+#[derive(Clone, Debug, Deserialize)]
+#[cfg_attr(feature = "serialize", derive(Serialize))]
 pub struct Metadata {
     #[serde(deserialize_with = "system_time_from_nanos")]
+    #[cfg_attr(feature = "serialize", serde(serialize_with = "system_time_to_nanos"))]
     pub start_time: SystemTime,
     pub process_id: u32,
     pub cmd: String,
+    /// `true` if the profiler stopped recording because it could not write
+    /// the profile, e.g. because the disk was full. Events after that point
+    /// are missing.
+    #[serde(default)]
+    pub truncated: bool,
+    /// The number of events that the profiler discarded because its buffer
+    /// was full, see `measureme::OverrunPolicy::DropEvents`, or because
+    /// their timestamps didn't fit into the profile's `TimestampFormat`.
+    #[serde(default)]
+    pub dropped_events: u64,
+    /// The argument schemas registered for each event kind.
+    #[serde(default)]
+    pub arg_schemas: FxHashMap<String, Vec<ArgSchema>>,
+    /// The file name of the `measureme::SharedStringCache` the profile
+    /// refers to. Its files are expected next to the profile's files.
+    #[serde(default)]
+    pub shared_strings: Option<String>,
+    /// The application that recorded the profile, if it called
+    /// `measureme::Profiler::set_tool_info()`.
+    #[serde(default)]
+    pub tool: Option<ToolInfo>,
+    /// `true` if the profile has been recorded with
+    /// `measureme::RecordingMode::AggregateOnly`: it doesn't contain any
+    /// events, only the totals in its `ProfileSummary`.
+    #[serde(default)]
+    pub aggregate_only: bool,
+    /// `true` if the profiler stopped writing events because the profile
+    /// exceeded its `measureme::RecordingBudget`. Events after that point
+    /// are missing, but the `ProfileSummary` of a profile recorded with
+    /// `measureme::BudgetPolicy::AggregateOnly` still counts them.
+    #[serde(default)]
+    pub budget_exhausted: bool,
+    /// The ids of the names of the event kinds registered via
+    /// `measureme::Profiler::register_event_kind()`, indexed by
+    /// `StringId::event_kind_index()`.
+    #[serde(default)]
+    pub event_kinds: Vec<u32>,
+    /// The ranges of string ids reserved via
+    /// `measureme::Profiler::reserve_string_ids()`, see
+    /// `ProfilingData::register_string_resolver()`.
+    #[serde(default)]
+    pub reserved_strings: Vec<ReservedStrings>,
+    /// The names of the events files of the threads of a profile recorded
+    /// with `measureme::EventLayout::PerThread`. Their events are merged
+    /// into the events of the profile when it is loaded.
+    #[serde(default)]
+    pub thread_event_files: Vec<String>,
+    /// The names of the segments of the events of a profile recorded with
+    /// `measureme::EventLayout::Rollover`, oldest first. Like the files of
+    /// the threads, they are merged into the events of the profile when it
+    /// is loaded.
+    #[serde(default)]
+    pub event_segments: Vec<String>,
+    /// The profiler's estimate of its own overhead, see
+    /// `measureme::overhead`.
+    #[serde(default)]
+    pub overhead: Option<Overhead>,
+    /// The machine the profile has been recorded on, see
+    /// `measureme::system_info`.
+    #[serde(default)]
+    pub system: Option<SystemInfo>,
+}
+
+impl Metadata {
+    /// The files of the threads and the segments, whose events are merged
+    /// into those of the `.events` file.
+    pub(crate) fn extra_event_files(&self) -> impl Iterator<Item = &String> {
+        self.thread_event_files
+            .iter()
+            .chain(self.event_segments.iter())
+    }
+
+    /// Checks that the files the metadata refers to are plain file names,
+    /// so that a profile can only make the loader read files next to its
+    /// own, not e.g. `../../etc/passwd` or some other URL.
+    fn check_file_names(&self) -> Result<(), LoadError> {
+        let names = self.extra_event_files().chain(self.shared_strings.iter());
+        for name in names {
+            let is_plain = !name.is_empty()
+                && name != "."
+                && !name.contains("..")
+                && !name.contains(['/', '\\', ':', '\0']);
+            if !is_plain {
+                return Err(LoadError::new(
+                    LoadErrorKind::Corrupt,
+                    format!(
+                        "the metadata of the profile refers to `{}`, which isn't a plain file name",
+                        name
+                    ),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the id of the string with the name of the event kind `kind`,
+    /// which is `kind` itself unless it is a registered event kind.
+    pub(crate) fn event_kind_name(&self, kind: StringId) -> StringId {
+        match kind.event_kind_index() {
+            Some(index) => self
+                .event_kinds
+                .get(index as usize)
+                .filter(|&&name| name <= MAX_STRING_ID)
+                .map_or(StringId::INVALID, |&name| StringId::new(name)),
+            None => kind,
+        }
+    }
+}
+
+/// See `measureme::ReservedStringIds`.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "serialize", derive(Serialize))]
+pub struct ReservedStrings {
+    pub name: String,
+    pub first: u32,
+    pub len: u32,
+}
+
+/// See `measureme::overhead`.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "serialize", derive(Serialize))]
+pub struct Overhead {
+    pub recording_nanos: u64,
+    pub elapsed_nanos: u64,
+    pub bytes_written: u64,
+}
+
+impl Overhead {
+    /// The estimated time the profiler spent recording, summed over all
+    /// threads.
+    pub fn recording_time(&self) -> Duration {
+        Duration::from_nanos(self.recording_nanos)
+    }
+
+    /// The recording time as a percentage of the wall time the profiler
+    /// existed. With several threads, this can exceed 100%.
+    pub fn percent_of_wall_time(&self) -> f64 {
+        if self.elapsed_nanos == 0 {
+            return 0.0;
+        }
+        self.recording_nanos as f64 / self.elapsed_nanos as f64 * 100.0
+    }
 }
 
 #[derive(Debug)]
 pub struct ProfilingData {
     event_data: Vec<u8>,
+    timestamp_format: TimestampFormat,
+    timestamp_resolution: TimestampResolution,
     string_table: StringTable,
     pub metadata: Metadata,
+    label_formatter: Option<BoxedLabelFormatter>,
+    label_rules: Option<LabelRules>,
+    symbolizer: Option<BoxedSymbolizer>,
+    warnings: Vec<Diagnostic>,
 }
 
 impl ProfilingData {
+    /// Loads the profile with the given path stem. `path_stem` can also be
+    /// one of the files of the profile, like `foo-1234.events`, or a
+    /// directory that contains a single profile. With the `archives`
+    /// feature, it can also point to a `.tar`, `.tar.gz` or `.zip` archive
+    /// containing the profile, or to a profile inside of one, like
+    /// `artifacts.zip/foo-1234`. Archives are recognized by their contents
+    /// and decompressed in memory. With the `http` feature, `path_stem` can
+    /// also be an `http://`, `https://` or `s3://` URL, to which the file
+    /// extensions are appended.
     pub fn new(path_stem: &Path) -> Result<ProfilingData, Box<dyn Error>> {
+        ProfilingData::load(path_stem, None, Validation::Off)
+    }
+
+    /// Like `new()`, but checks the events of the profile as selected by
+    /// `validation`, see `Validation`.
+    pub fn new_validated(
+        path_stem: &Path,
+        validation: Validation,
+    ) -> Result<ProfilingData, Box<dyn Error>> {
+        ProfilingData::load(path_stem, None, validation)
+    }
+
+    /// Loads several profiles, like `new()`, on a small pool of threads, so
+    /// that reading and decoding one profile overlaps with reading the next.
+    /// The profiles are returned in the order of `path_stems`. If any of them
+    /// can't be loaded, the error of the first one of those is returned.
+    pub fn load_all<P: AsRef<Path> + Sync>(
+        path_stems: &[P],
+    ) -> Result<Vec<ProfilingData>, Box<dyn Error>> {
+        let workers = thread::available_parallelism()
+            .map_or(1, NonZeroUsize::get)
+            .min(MAX_PARALLEL_LOADS)
+            .min(path_stems.len());
+        let next = AtomicUsize::new(0);
+
+        let mut loaded = thread::scope(|scope| {
+            let workers: Vec<_> = (0..workers)
+                .map(|_| {
+                    scope.spawn(|| {
+                        let mut loaded = Vec::new();
+                        loop {
+                            let index = next.fetch_add(1, Ordering::Relaxed);
+                            match path_stems.get(index) {
+                                Some(path_stem) => loaded.push((
+                                    index,
+                                    ProfilingData::new(path_stem.as_ref()).map_err(sendable),
+                                )),
+                                None => return loaded,
+                            }
+                        }
+                    })
+                })
+                .collect();
+
+            workers
+                .into_iter()
+                .flat_map(|worker| worker.join().unwrap())
+                .collect::<Vec<_>>()
+        });
+
+        loaded.sort_by_key(|&(index, _)| index);
+        loaded
+            .into_iter()
+            .map(|(_, data)| data.map_err(|e| e as Box<dyn Error>))
+            .collect()
+    }
+
+    /// Like `new()`, but for profiles that have been written through a
+    /// `measureme::EncryptedSerializationSink`, using the same cipher.
+    pub fn new_encrypted(
+        path_stem: &Path,
+        cipher: &dyn ProfileCipher,
+    ) -> Result<ProfilingData, Box<dyn Error>> {
+        ProfilingData::load(path_stem, Some(cipher), Validation::Off)
+    }
+
+    fn load(
+        path: &Path,
+        cipher: Option<&dyn ProfileCipher>,
+        validation: Validation,
+    ) -> Result<ProfilingData, Box<dyn Error>> {
+        measureme::activity!("load_profile");
+        let started = Instant::now();
+        let data = ProfilingData::load_from_input(path, cipher, validation)?;
+        Diagnostic::info(
+            "profile-loaded",
+            format!(
+                "loaded `{}` in {:.2?}: {} events, {} warnings",
+                path.display(),
+                started.elapsed(),
+                data.num_events(),
+                data.warnings.len()
+            ),
+        )
+        .log();
+        Ok(data)
+    }
+
+    /// Loads the profile from the kind of input `path` refers to, see
+    /// `new()`.
+    fn load_from_input(
+        path: &Path,
+        cipher: Option<&dyn ProfileCipher>,
+        validation: Validation,
+    ) -> Result<ProfilingData, Box<dyn Error>> {
+        #[cfg(feature = "http")]
+        {
+            if let Some(url) = http::profile_url(path) {
+                return ProfilingData::load_from_url(&url, cipher, validation);
+            }
+        }
+
+        // Without archives, `Input` only has one variant.
+        #[cfg_attr(
+            not(feature = "archives"),
+            allow(clippy::infallible_destructuring_match)
+        )]
+        let path_stem = match input::detect(path)? {
+            Input::PathStem(path_stem) => path_stem,
+            #[cfg(feature = "archives")]
+            Input::Archive(archive_path, profile) => {
+                return ProfilingData::load_from_archive(
+                    &archive_path,
+                    &profile,
+                    cipher,
+                    validation,
+                );
+            }
+        };
+        let path_stem = path_stem.as_path();
+
         let paths = ProfilerFiles::new(path_stem);
 
-        let string_data = fs::read(paths.string_data_file).expect("couldn't read string_data file");
-        let index_data =
-            fs::read(paths.string_index_file).expect("couldn't read string_index file");
-        let event_data = fs::read(paths.events_file).expect("couldn't read events file");
+        let mut files = read_files(
+            &[
+                (&paths.events_file, "couldn't read events file"),
+                (&paths.string_data_file, "couldn't read string_data file"),
+                (&paths.string_index_file, "couldn't read string_index file"),
+            ],
+            cipher,
+        )?;
+        let index_data = files.pop().unwrap();
+        let string_data = files.pop().unwrap();
+        let event_data = files.pop().unwrap();
+
+        let events_file = paths.events_file.display().to_string();
+        let mut data = ProfilingData::decode(
+            event_data,
+            string_data,
+            index_data,
+            [
+                &events_file,
+                &paths.string_data_file.display().to_string(),
+                &paths.string_index_file.display().to_string(),
+            ],
+            validation,
+        )?;
+
+        let mut paths: Vec<_> = data
+            .metadata
+            .thread_event_files
+            .iter()
+            .map(|file_name| path_stem.with_file_name(file_name))
+            .collect();
+        let thread_files = paths.len();
+
+        // Old segments may have been pruned after they have been processed.
+        for file_name in &data.metadata.event_segments {
+            let path = path_stem.with_file_name(file_name);
+            if path.exists() {
+                paths.push(path);
+            } else {
+                data.warnings.push(Diagnostic::warning(
+                    "missing-segment",
+                    format!(
+                        "the segment `{}` doesn't exist anymore, its events are missing",
+                        path.display()
+                    ),
+                ));
+            }
+        }
 
-        let event_data_format = read_file_header(&event_data, FILE_MAGIC_EVENT_STREAM)?;
-        if event_data_format != CURRENT_FILE_FORMAT_VERSION {
-            Err(format!(
-                "Event stream file format version '{}' is not supported
-                 by this version of `measureme`.",
-                event_data_format
-            ))?;
+        let files: Vec<_> = paths
+            .iter()
+            .enumerate()
+            .map(|(index, path)| {
+                let context = if index < thread_files {
+                    "couldn't read thread events file"
+                } else {
+                    "couldn't read segment events file"
+                };
+                (path.as_path(), context)
+            })
+            .collect();
+        let thread_event_files = read_files(&files, cipher)?
+            .into_iter()
+            .zip(&paths)
+            .map(|(events, path)| (events, path.display().to_string()))
+            .collect();
+        data.merge_thread_event_files(thread_event_files, &events_file)?;
+
+        if let Some(shared_strings) = &data.metadata.shared_strings {
+            let shared_path_stem = path_stem.with_file_name(shared_strings);
+            let shared = load_shared_string_table(&shared_path_stem, cipher)?;
+            data.string_table.set_shared(Arc::new(shared));
+        }
+
+        data.validate(validation)?;
+        Ok(data)
+    }
+
+    #[cfg(feature = "archives")]
+    fn load_from_archive(
+        archive_path: &Path,
+        profile: &Path,
+        cipher: Option<&dyn ProfileCipher>,
+        validation: Validation,
+    ) -> Result<ProfilingData, Box<dyn Error>> {
+        let mut archive = Archive::open(archive_path)?;
+        let path_stem = archive.find_profile(profile)?;
+
+        let mut read = |path_stem: &str, extension: &str| {
+            let name = format!("{}.{}", path_stem, extension);
+            let display_name = archive.display_name(&name);
+            let data = decrypt_if_needed(archive.take_file(&name)?, cipher, &display_name)?;
+            Ok::<_, Box<dyn Error>>((data, display_name))
+        };
+
+        let (string_data, string_data_file) = read(&path_stem, "string_data")?;
+        let (index_data, string_index_file) = read(&path_stem, "string_index")?;
+        let (event_data, events_file) = read(&path_stem, "events")?;
+
+        let mut data = ProfilingData::decode(
+            event_data,
+            string_data,
+            index_data,
+            [&events_file, &string_data_file, &string_index_file],
+            validation,
+        )?;
+
+        // Like the shared string cache below, the files of the threads are
+        // expected next to the profile in the archive.
+        let directory = match path_stem.rfind('/') {
+            Some(index) => &path_stem[..index + 1],
+            None => "",
+        };
+        let mut thread_event_files = Vec::new();
+        for file_name in data.metadata.extra_event_files() {
+            let (stem, extension) = file_name.rsplit_once('.').unwrap_or((file_name, ""));
+            thread_event_files.push(read(&format!("{}{}", directory, stem), extension)?);
+        }
+        data.merge_thread_event_files(thread_event_files, &events_file)?;
+
+        // The shared string cache is expected next to the profile in the
+        // archive, just like on disk.
+        if let Some(shared_strings) = &data.metadata.shared_strings {
+            let shared_path_stem = match path_stem.rfind('/') {
+                Some(index) => format!("{}/{}", &path_stem[..index], shared_strings),
+                None => shared_strings.clone(),
+            };
+
+            let (string_data, string_data_file) = read(&shared_path_stem, "string_data")?;
+            let (index_data, string_index_file) = read(&shared_path_stem, "string_index")?;
+            let string_data =
+                strip_file_footer(string_data, FILE_MAGIC_STRINGTABLE_DATA, &string_data_file)?;
+            let index_data =
+                strip_file_footer(index_data, FILE_MAGIC_STRINGTABLE_INDEX, &string_index_file)?;
+
+            let shared = StringTable::new(string_data, index_data)?;
+            data.string_table.set_shared(Arc::new(shared));
+        }
+
+        data.validate(validation)?;
+        Ok(data)
+    }
+
+    /// The string index is downloaded first, as it is the smallest file, so
+    /// that URLs that don't point to a profile fail quickly.
+    #[cfg(feature = "http")]
+    fn load_from_url(
+        url: &str,
+        cipher: Option<&dyn ProfileCipher>,
+        validation: Validation,
+    ) -> Result<ProfilingData, Box<dyn Error>> {
+        let fetch = |url: &str, extension: &str, file_magics: &[&[u8; 4]]| {
+            let url = format!("{}.{}", url, extension);
+            let data = decrypt_if_needed(http::fetch_file(&url, file_magics)?, cipher, &url)?;
+            Ok::<_, Box<dyn Error>>((data, url))
+        };
+
+        let (index_data, string_index_url) =
+            fetch(url, "string_index", &[FILE_MAGIC_STRINGTABLE_INDEX])?;
+        let (string_data, string_data_url) =
+            fetch(url, "string_data", &[FILE_MAGIC_STRINGTABLE_DATA])?;
+        let (event_data, events_url) = fetch(
+            url,
+            "events",
+            &[FILE_MAGIC_EVENT_STREAM, FILE_MAGIC_EVENT_STREAM_WIDE],
+        )?;
+
+        let mut data = ProfilingData::decode(
+            event_data,
+            string_data,
+            index_data,
+            [&events_url, &string_data_url, &string_index_url],
+            validation,
+        )?;
+
+        let mut thread_event_files = Vec::new();
+        for file_name in data.metadata.extra_event_files() {
+            let url = http::sibling_url(url, file_name);
+            let events = decrypt_if_needed(
+                http::fetch_file(
+                    &url,
+                    &[FILE_MAGIC_EVENT_STREAM, FILE_MAGIC_EVENT_STREAM_WIDE],
+                )?,
+                cipher,
+                &url,
+            )?;
+            thread_event_files.push((events, url));
+        }
+        data.merge_thread_event_files(thread_event_files, &events_url)?;
+
+        if let Some(shared_strings) = &data.metadata.shared_strings {
+            let shared_url = http::sibling_url(url, shared_strings);
+
+            let (index_data, string_index_url) =
+                fetch(&shared_url, "string_index", &[FILE_MAGIC_STRINGTABLE_INDEX])?;
+            let (string_data, string_data_url) =
+                fetch(&shared_url, "string_data", &[FILE_MAGIC_STRINGTABLE_DATA])?;
+            let string_data =
+                strip_file_footer(string_data, FILE_MAGIC_STRINGTABLE_DATA, &string_data_url)?;
+            let index_data =
+                strip_file_footer(index_data, FILE_MAGIC_STRINGTABLE_INDEX, &string_index_url)?;
+
+            let shared = StringTable::new(string_data, index_data)?;
+            data.string_table.set_shared(Arc::new(shared));
+        }
+
+        data.validate(validation)?;
+        Ok(data)
+    }
+
+    /// Creates a `ProfilingData` from the contents of the `.events`,
+    /// `.string_data` and `.string_index` files of a profile. This doesn't
+    /// touch the file system, so it also works for tools without one, like
+    /// viewers compiled to WebAssembly that run in a browser.
+    pub fn from_bytes(
+        event_data: Vec<u8>,
+        string_data: Vec<u8>,
+        index_data: Vec<u8>,
+    ) -> Result<ProfilingData, Box<dyn Error>> {
+        ProfilingData::from_bytes_validated(event_data, string_data, index_data, Validation::Off)
+    }
+
+    /// Like `from_bytes()`, for tools that load profiles from untrusted
+    /// sources, e.g. servers that analyze profiles users upload. Decoding a
+    /// profile never panics, but a corrupt one decodes to placeholders like
+    /// `<unknown>`, and its strings can expand to far more data than the
+    /// files contain. This checks the whole profile upfront instead and
+    /// returns a `LoadErrorKind::Corrupt` error for anything listed at
+    /// `Validation::Strict`.
+    ///
+    /// Checking takes time linear in the size of the files.
+    pub fn from_bytes_strict(
+        event_data: Vec<u8>,
+        string_data: Vec<u8>,
+        index_data: Vec<u8>,
+    ) -> Result<ProfilingData, Box<dyn Error>> {
+        ProfilingData::from_bytes_validated(event_data, string_data, index_data, Validation::Strict)
+    }
+
+    /// Like `from_bytes()`, but checks the events of the profile as selected
+    /// by `validation`, see `Validation`.
+    pub fn from_bytes_validated(
+        event_data: Vec<u8>,
+        string_data: Vec<u8>,
+        index_data: Vec<u8>,
+        validation: Validation,
+    ) -> Result<ProfilingData, Box<dyn Error>> {
+        let mut data = ProfilingData::decode(
+            event_data,
+            string_data,
+            index_data,
+            ["events", "string_data", "string_index"],
+            validation,
+        )?;
+        data.validate(validation)?;
+        Ok(data)
+    }
+
+    /// Like `from_bytes()`, but reads the contents of the files from
+    /// streams, e.g. from the pipes of another process, from decompressing
+    /// readers or from network sockets, without going through temporary
+    /// files. The streams are read to their ends one after another, in the
+    /// order of the arguments, so a producer that writes all three at once
+    /// must not block on the later ones while the earlier ones are read.
+    pub fn from_readers(
+        events: impl Read,
+        string_data: impl Read,
+        string_index: impl Read,
+    ) -> Result<ProfilingData, Box<dyn Error>> {
+        fn read_stream(mut stream: impl Read, name: &str) -> Result<Vec<u8>, LoadError> {
+            let mut data = Vec::new();
+            stream.read_to_end(&mut data).map_err(|e| {
+                LoadError::new(
+                    LoadErrorKind::FileUnreadable,
+                    format!("couldn't read the {} stream: {}", name, e),
+                )
+            })?;
+            Ok(data)
         }
 
+        ProfilingData::from_bytes(
+            read_stream(events, "events")?,
+            read_stream(string_data, "string_data")?,
+            read_stream(string_index, "string_index")?,
+        )
+    }
+
+    /// `file_names` are the names of the events, string data and string
+    /// index files, for error messages. Unless `validation` is
+    /// `Validation::Off`, the metadata is checked before it is parsed; the
+    /// events are checked by `validate()` once all files are loaded.
+    fn decode(
+        mut event_data: Vec<u8>,
+        string_data: Vec<u8>,
+        index_data: Vec<u8>,
+        file_names: [&str; 3],
+        validation: Validation,
+    ) -> Result<ProfilingData, Box<dyn Error>> {
+        let [events_file, string_data_file, string_index_file] = file_names;
+
+        // Files with an unknown magic are reported as not being a compact
+        // events file by `read_file_header()`.
+        let file_magic = event_data
+            .get(..4)
+            .and_then(TimestampFormat::from_file_magic)
+            .unwrap_or_default()
+            .file_magic();
+
+        check_file_header(&event_data, file_magic, events_file)?;
+        let feature_flags = read_full_file_header(&event_data, file_magic)?.feature_flags;
+        let timestamp_format =
+            TimestampFormat::from_file_header(file_magic, feature_flags).unwrap();
+        let timestamp_resolution = TimestampResolution::from_feature_flags(feature_flags);
+
+        let string_data =
+            strip_file_footer(string_data, FILE_MAGIC_STRINGTABLE_DATA, string_data_file)?;
+        let index_data =
+            strip_file_footer(index_data, FILE_MAGIC_STRINGTABLE_INDEX, string_index_file)?;
+
+        check_same_session(
+            (&event_data, timestamp_format.file_magic(), events_file),
+            &[
+                (&string_data, FILE_MAGIC_STRINGTABLE_DATA, string_data_file),
+                (&index_data, FILE_MAGIC_STRINGTABLE_INDEX, string_index_file),
+            ],
+        )?;
+
         let string_table = StringTable::new(string_data, index_data)?;
 
+        if validation != Validation::Off {
+            string_table
+                .get_metadata()
+                .check(&mut FxHashMap::default())
+                .map_err(|e| {
+                    LoadError::new(
+                        LoadErrorKind::Corrupt,
+                        format!("the metadata of the profile is invalid: {}", e),
+                    )
+                })?;
+        }
+
         let metadata = string_table.get_metadata().to_string();
-        let metadata: Metadata = serde_json::from_str(&metadata)?;
+        let metadata: Metadata = serde_json::from_str(&metadata).map_err(|e| {
+            LoadError::new(
+                LoadErrorKind::Corrupt,
+                format!("the metadata of the profile is invalid: {}", e),
+            )
+        })?;
+        metadata.check_file_names()?;
+
+        // If the profiler failed to write the events file, the footer is
+        // most likely missing.
+        match verify_file_footer(&event_data) {
+            Ok(contents) => {
+                let len = contents.len();
+                event_data.truncate(len);
+            }
+            Err(_) if metadata.truncated => {}
+            Err(e) => Err(in_file(events_file, e))?,
+        }
+
+        let event_byte_count = event_data.len() - FILE_HEADER_SIZE;
+        let partial_event_size = event_byte_count % timestamp_format.event_size();
+        if metadata.truncated {
+            // The last event might have been written only partially.
+            event_data.truncate(event_data.len() - partial_event_size);
+        } else if partial_event_size != 0 {
+            Err(LoadError::new(
+                LoadErrorKind::Corrupt,
+                format!("`{}` ends with a partial event", events_file),
+            ))?;
+        }
+
+        sort::sort_events(timestamp_format, &mut event_data[FILE_HEADER_SIZE..]);
 
         Ok(ProfilingData {
             string_table,
             event_data,
+            timestamp_format,
+            timestamp_resolution,
             metadata,
+            label_formatter: None,
+            label_rules: None,
+            symbolizer: None,
+            warnings: Vec::new(),
         })
     }
 
+    /// Merges the events of the files of a profile recorded with
+    /// `measureme::EventLayout::PerThread` or of its segments with
+    /// `EventLayout::Rollover`, given with their names, into the events of
+    /// the profile. The events of every file are ordered by the
+    /// time they end (after sorting them if necessary, see the `sort`
+    /// module), so a merge keeps them in that order across files.
+    fn merge_thread_event_files(
+        &mut self,
+        mut files: Vec<(Vec<u8>, String)>,
+        events_file: &str,
+    ) -> Result<(), Box<dyn Error>> {
+        if files.is_empty() {
+            return Ok(());
+        }
+        measureme::activity!("merge_thread_event_files");
+
+        let file_magic = self.timestamp_format.file_magic();
+        let event_size = self.timestamp_format.event_size();
+        // The ends of the events of every file.
+        let mut ends = Vec::with_capacity(files.len());
+
+        for (data, file_name) in &mut files {
+            check_file_header(data, file_magic, file_name)?;
+            check_same_session(
+                (&self.event_data, file_magic, events_file),
+                &[(data, file_magic, file_name)],
+            )?;
+
+            let mut end = match verify_file_footer(data) {
+                Ok(contents) => contents.len(),
+                Err(_) if self.metadata.truncated => data.len(),
+                Err(e) => Err(in_file(file_name, e))?,
+            };
+
+            let partial_event_size = (end - FILE_HEADER_SIZE) % event_size;
+            if self.metadata.truncated {
+                end -= partial_event_size;
+            } else if partial_event_size != 0 {
+                Err(LoadError::new(
+                    LoadErrorKind::Corrupt,
+                    format!("`{}` ends with a partial event", file_name),
+                ))?;
+            }
+
+            sort::sort_events(self.timestamp_format, &mut data[FILE_HEADER_SIZE..end]);
+            ends.push(end);
+        }
+
+        let mut streams = vec![&self.event_data[FILE_HEADER_SIZE..]];
+        for ((data, _), &end) in files.iter().zip(&ends) {
+            streams.push(&data[FILE_HEADER_SIZE..end]);
+        }
+
+        let end_nanos = |event: &[u8]| {
+            let raw_event = RawEvent::deserialize_as(self.timestamp_format, &event[..event_size]);
+            if raw_event.is_instant() {
+                raw_event.start_nanos()
+            } else {
+                raw_event.end_nanos()
+            }
+        };
+
+        // The next event of every stream, keyed by its end and the index of
+        // the stream, so that ties keep the order of the streams.
+        let mut heap: BinaryHeap<Reverse<(u64, usize)>> = streams
+            .iter()
+            .enumerate()
+            .filter(|(_, events)| !events.is_empty())
+            .map(|(index, events)| Reverse((end_nanos(events), index)))
+            .collect();
+
+        let mut event_data = self.event_data[..FILE_HEADER_SIZE].to_vec();
+        event_data.reserve(streams.iter().map(|events| events.len()).sum());
+
+        while let Some(Reverse((_, index))) = heap.pop() {
+            let (event, rest) = streams[index].split_at(event_size);
+            event_data.extend_from_slice(event);
+            streams[index] = rest;
+
+            if !rest.is_empty() {
+                heap.push(Reverse((end_nanos(rest), index)));
+            }
+        }
+
+        self.event_data = event_data;
+        Ok(())
+    }
+
+    // See `Validation`.
+    fn validate(&mut self, validation: Validation) -> Result<(), LoadError> {
+        if validation == Validation::Off {
+            return Ok(());
+        }
+
+        let corrupt = |message: String| LoadError::new(LoadErrorKind::Corrupt, message);
+
+        if let Some(&id) = self
+            .metadata
+            .event_kinds
+            .iter()
+            .find(|&&id| id > MAX_STRING_ID)
+        {
+            return Err(corrupt(format!(
+                "the metadata refers to the invalid string id {}",
+                id
+            )));
+        }
+
+        for range in &self.metadata.reserved_strings {
+            if range.first < FIRST_RESERVED_STRING_ID
+                || range.first as u64 + range.len as u64 > FIRST_SHARED_STRING_ID as u64
+            {
+                return Err(corrupt(format!(
+                    "the metadata reserves invalid string ids for `{}`",
+                    range.name
+                )));
+            }
+        }
+
+        let mut checked = FxHashMap::default();
+        let mut invalid = Vec::new();
+
+        for event_index in 0..self.num_events() {
+            let problem = match self.check_event(event_index, &mut checked) {
+                Ok(()) => continue,
+                Err(problem) => format!("event {}: {}", event_index, problem),
+            };
+
+            match validation {
+                Validation::Strict => return Err(corrupt(problem)),
+                _ => invalid.push((event_index, problem)),
+            }
+        }
+
+        if invalid.is_empty() {
+            return Ok(());
+        }
+
+        let event_size = self.timestamp_format.event_size();
+        let mut event_data = Vec::with_capacity(self.event_data.len());
+        event_data.extend_from_slice(&self.event_data[..FILE_HEADER_SIZE]);
+        let mut next = 0;
+        for &(event_index, _) in &invalid {
+            event_data.extend_from_slice(
+                &self.event_data[event_index_to_addr(next, event_size)
+                    ..event_index_to_addr(event_index, event_size)],
+            );
+            next = event_index + 1;
+        }
+        event_data.extend_from_slice(&self.event_data[event_index_to_addr(next, event_size)..]);
+        self.event_data = event_data;
+
+        let skipped = invalid.len();
+        self.warnings.extend(
+            invalid
+                .into_iter()
+                .take(MAX_EVENT_WARNINGS)
+                .map(|(_, problem)| {
+                    Diagnostic::warning("invalid-event", format!("skipped {}", problem))
+                }),
+        );
+        if skipped > MAX_EVENT_WARNINGS {
+            self.warnings.push(Diagnostic::warning(
+                "invalid-event",
+                format!(
+                    "skipped {} more invalid events",
+                    skipped - MAX_EVENT_WARNINGS
+                ),
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn check_event(
+        &self,
+        event_index: usize,
+        checked: &mut FxHashMap<StringId, usize>,
+    ) -> Result<(), String> {
+        let raw_event = self.unchecked_raw_event(event_index);
+
+        if !raw_event.event_kind.is_valid() {
+            return Err(format!(
+                "has the invalid event kind id {}",
+                raw_event.event_kind.as_u32()
+            ));
+        }
+        if !raw_event.event_id.is_valid() {
+            return Err(format!(
+                "has the invalid event id {}",
+                raw_event.event_id.as_u32()
+            ));
+        }
+
+        if !raw_event.is_instant() && raw_event.end_nanos() < raw_event.start_nanos() {
+            return Err("ends before it starts".to_string());
+        }
+
+        for id in [
+            self.metadata.event_kind_name(raw_event.event_kind),
+            raw_event.event_id.to_string_id(),
+        ] {
+            self.string_table.get(id).check(checked)?;
+        }
+
+        Ok(())
+    }
+
+    /// The problems found while loading the profile that didn't keep it from
+    /// being loaded, i.e. the events skipped by `Validation::Lenient`.
+    pub fn warnings(&self) -> &[Diagnostic] {
+        &self.warnings
+    }
+
+    /// Returns the event with the given index, i.e. the `event_index`th
+    /// event returned by `iter()`. Panics if there is no such event.
+    pub fn event(&self, event_index: usize) -> LightweightEvent<'_> {
+        assert!(event_index < self.num_events(), "event index out of bounds");
+        self.decode_lightweight_event(event_index)
+    }
+
     pub fn iter<'a>(&'a self) -> ProfilerEventIterator<'a> {
-        ProfilerEventIterator::new(&self)
+        ProfilerEventIterator::new(self)
+    }
+
+    /// Returns the events of every thread, arranged as a list of top-level
+    /// events with their nested children. Timelines are ordered by thread id.
+    pub fn per_thread_timelines(&self) -> Vec<ThreadTimeline<'_>> {
+        timeline::per_thread_timelines(self)
+    }
+
+    /// Returns the names of all threads that have been registered via
+    /// `measureme::Profiler::register_current_thread()` and of all tracks
+    /// opened via `measureme::Profiler::open_track()`, keyed by thread id.
+    pub fn thread_names(&self) -> FxHashMap<u32, String> {
+        threads::thread_names(self)
+    }
+
+    /// Returns the tracks opened via `measureme::Profiler::open_track()`,
+    /// ordered by id. Their events are recorded with the id of the track in
+    /// place of a thread id.
+    pub fn tracks(&self) -> Vec<Track> {
+        threads::tracks(self)
+    }
+
+    /// Returns the external ids of the threads that have been registered via
+    /// `measureme::Profiler::register_external_thread()`, e.g. tokio task
+    /// ids, keyed by the thread id they were mapped to.
+    pub fn external_thread_ids(&self) -> FxHashMap<u32, u64> {
+        threads::external_thread_ids(self)
+    }
+
+    /// Returns how the recording ended for every thread occurring in the
+    /// profile, ordered by thread id. Threads that called
+    /// `measureme::Profiler::finish_thread()` have finished cleanly. For the
+    /// others, intervals that were still open at the end of the profile are
+    /// missing, see `ThreadEnd::unclosed_time`.
+    pub fn thread_ends(&self) -> Vec<ThreadEnd> {
+        threads::thread_ends(self)
+    }
+
+    /// Returns a mapping from every thread id occurring in the profile to a
+    /// small, dense id, numbering threads in the order in which they first
+    /// occur in the event stream. This is useful for displaying profiles
+    /// that use large or sparse ids, like OS thread ids.
+    pub fn dense_thread_ids(&self) -> FxHashMap<u32, u32> {
+        threads::dense_thread_ids(self)
+    }
+
+    /// Decodes the arguments of `event` according to the argument schema
+    /// registered for its event kind, if any. Arguments that are not covered
+    /// by the schema, or that don't parse as the declared type, are returned
+    /// as unnamed strings. Addresses are resolved via the symbolizer set via
+    /// `set_symbolizer()`, if any.
+    pub fn decode_args<'a>(&'a self, event: &Event<'a>) -> Vec<Arg<'a>> {
+        let schema = self
+            .metadata
+            .arg_schemas
+            .get(&event.event_kind[..])
+            .map(|schema| &schema[..]);
+
+        let mut args = args::decode_args(schema, event);
+
+        if let Some(ref symbolizer) = self.symbolizer {
+            for arg in &mut args {
+                if let ArgValue::Address {
+                    address,
+                    ref mut symbol,
+                } = arg.value
+                {
+                    *symbol = symbolizer.0.symbolize(address);
+                }
+            }
+        }
+
+        args
+    }
+
+    /// Sets the formatter that `display_label()` applies to labels, e.g.
+    /// `RustcLabelFormatter` for profiles recorded by rustc.
+    pub fn set_label_formatter(&mut self, formatter: impl LabelFormatter + 'static) {
+        self.label_formatter = Some(BoxedLabelFormatter(Box::new(formatter)));
+    }
+
+    /// Sets the rules that rewrite the labels of the events this profile
+    /// decodes, for tools to group and report them by the rewritten labels.
+    /// Unlike with `set_label_formatter()`, the original labels aren't kept
+    /// in `Event::label`, but `LightweightEvent`s and the string table still
+    /// return them.
+    pub fn set_label_rules(&mut self, rules: LabelRules) {
+        self.label_rules = Some(rules).filter(|rules| !rules.is_empty());
+    }
+
+    /// Sets the symbolizer that `decode_args()` resolves address arguments
+    /// with, e.g. a `SymbolMap` for JIT-compiled code.
+    pub fn set_symbolizer(&mut self, symbolizer: impl Symbolizer + 'static) {
+        self.symbolizer = Some(BoxedSymbolizer(Box::new(symbolizer)));
+    }
+
+    /// Makes the string ids that the profiler reserved under `name` via
+    /// `measureme::Profiler::reserve_string_ids()` resolve via `resolver`,
+    /// which gets the index of an id within the range. Without a resolver,
+    /// the ids resolve to `<unknown>`. Returns an error if the profile
+    /// doesn't reserve any ids under `name`.
+    ///
+    /// `merge_profiles()` and `normalize()` store the resolved strings in
+    /// the profiles they create, so the resolvers have to be registered
+    /// before calling them.
+    pub fn register_string_resolver(
+        &mut self,
+        name: &str,
+        resolver: impl StringResolver + 'static,
+    ) -> Result<(), Box<dyn Error>> {
+        let range = self
+            .metadata
+            .reserved_strings
+            .iter()
+            .find(|range| range.name == name)
+            .ok_or_else(|| format!("the profile doesn't reserve any string ids for `{}`", name))?;
+
+        if range.first > MAX_STRING_ID {
+            Err(format!(
+                "the profile reserves invalid string ids for `{}`",
+                name
+            ))?;
+        }
+
+        self.string_table
+            .set_resolver(StringId::new(range.first), range.len, resolver);
+        Ok(())
+    }
+
+    /// Returns the label of `event` as it should be displayed, i.e. after
+    /// applying the formatter set via `set_label_formatter()`. The raw label
+    /// is still available via `event.label`.
+    pub fn display_label<'a>(&self, event: &'a Event<'_>) -> Cow<'a, str> {
+        match self.label_formatter {
+            Some(ref formatter) => formatter.0.format(&event.label),
+            None => Cow::Borrowed(&event.label),
+        }
     }
 
     pub fn num_events(&self) -> usize {
+        let event_size = self.timestamp_format.event_size();
         let event_byte_count = self.event_data.len() - FILE_HEADER_SIZE;
-        assert!(event_byte_count % RAW_EVENT_SIZE == 0);
-        event_byte_count / RAW_EVENT_SIZE
+        assert!(event_byte_count % event_size == 0);
+        event_byte_count / event_size
     }
 
-    pub(crate) fn decode_full_event<'a>(&'a self, event_index: usize) -> Event<'a> {
-        let event_start_addr = event_index_to_addr(event_index);
-        let event_end_addr = event_start_addr.checked_add(RAW_EVENT_SIZE).unwrap();
+    /// The metadata as the profiler wrote it.
+    pub(crate) fn metadata_json(&self) -> String {
+        self.string_table.get_metadata().to_string().into_owned()
+    }
+
+    /// How the timestamps of the events are stored in the events file.
+    pub fn timestamp_format(&self) -> TimestampFormat {
+        self.timestamp_format
+    }
+
+    /// The unit of the timestamps in the events file. The timestamps of the
+    /// events this returns are always in nanoseconds.
+    pub fn timestamp_resolution(&self) -> TimestampResolution {
+        self.timestamp_resolution
+    }
+
+    /// Returns a hash of what the profile recorded, see `fingerprint()`.
+    pub fn fingerprint(&self) -> u64 {
+        fingerprint::fingerprint(self)
+    }
+
+    /// Returns a column-oriented copy of all events, see `EventColumns`.
+    pub fn to_columns(&self) -> EventColumns {
+        columns::to_columns(self)
+    }
+
+    /// Writes the events to the files with the given path stem, as a
+    /// profile that loads like any other, e.g. after transforming them via
+    /// `filter_self_profile_events()` or building them with a
+    /// `ProfilingDataBuilder`.
+    ///
+    /// The copy has a string table of its own, in which every string is
+    /// written once, including those of a shared string cache. Like
+    /// `annotate()`, it writes the names of registered event kinds and the
+    /// strings of reserved ids as plain strings. The start time, process id,
+    /// command line and truncation of the metadata are taken from
+    /// `self.metadata`, the rest of the metadata as the profiler wrote it.
+    pub fn save(&self, path_stem: &Path) -> Result<(), Box<dyn Error>> {
+        if self.metadata.aggregate_only {
+            Err("the profile has been recorded in aggregate-only mode and has no events to save")?;
+        }
+
+        let events: Vec<Event<'_>> = self.iter().map(|e| e.to_event()).collect();
+        let mut builder = ProfilingDataBuilder::with_timestamp_format(self.timestamp_format);
+        builder.intern_strings(FxHashMap::default());
+
+        let metadata = &self.metadata;
+        write_copy_with(builder, self, &events, path_stem, |json| {
+            if let serde_json::Value::Object(fields) = json {
+                let start_time = metadata
+                    .start_time
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default();
+                fields.insert("start_time".into(), (start_time.as_nanos() as u64).into());
+                fields.insert("process_id".into(), metadata.process_id.into());
+                fields.insert("cmd".into(), metadata.cmd.clone().into());
+                fields.insert("truncated".into(), metadata.truncated.into());
+                fields.insert("dropped_events".into(), metadata.dropped_events.into());
+                fields.insert("budget_exhausted".into(), metadata.budget_exhausted.into());
+            }
+        })
+    }
+
+    /// Returns the `event_kind` of the raw events of the given registered
+    /// event kind (see `measureme::Profiler::register_event_kind()`), which
+    /// allows comparing event kinds without resolving strings.
+    pub fn registered_event_kind(&self, name: &str) -> Option<StringId> {
+        self.metadata
+            .event_kinds
+            .iter()
+            .position(|&id| {
+                id <= MAX_STRING_ID && self.string_table.get(StringId::new(id)).to_string() == name
+            })
+            .map(|index| StringId::new_event_kind(index as u32))
+    }
+
+    /// Returns the name of the event kind `kind`, which is the event kind of
+    /// one of the raw events of the profile.
+    pub(crate) fn event_kind_str(&self, kind: StringId) -> Cow<'_, str> {
+        self.string_table
+            .get(self.metadata.event_kind_name(kind))
+            .to_string()
+    }
+
+    /// The profile's string table, e.g. for `StringTable::stats()`.
+    pub fn string_table(&self) -> &StringTable {
+        &self.string_table
+    }
+
+    /// Decodes the event with the given index. Ids that are out of range, as
+    /// only a corrupt profile has them, are replaced with
+    /// `StringId::INVALID`, which resolves to a placeholder string.
+    pub(crate) fn raw_event(&self, event_index: usize) -> RawEvent {
+        let mut raw_event = self.unchecked_raw_event(event_index);
+        if !raw_event.event_kind.is_valid() {
+            raw_event.event_kind = StringId::INVALID;
+        }
+        if !raw_event.event_id.is_valid() {
+            raw_event.event_id = EventId::INVALID;
+        }
+        raw_event
+    }
+
+    fn unchecked_raw_event(&self, event_index: usize) -> RawEvent {
+        let event_size = self.timestamp_format.event_size();
+        let event_start_addr = event_index_to_addr(event_index, event_size);
+        let event_end_addr = event_start_addr.checked_add(event_size).unwrap();
 
-        let raw_event_bytes = &self.event_data[event_start_addr..event_end_addr];
-        let raw_event = RawEvent::deserialize(raw_event_bytes);
+        RawEvent::deserialize_as(
+            self.timestamp_format,
+            &self.event_data[event_start_addr..event_end_addr],
+        )
+        .from_resolution(self.timestamp_resolution)
+    }
+
+    pub(crate) fn decode_full_event<'a>(&'a self, event_index: usize) -> Event<'a> {
+        let raw_event = self.raw_event(event_index);
 
         let string_table = &self.string_table;
 
@@ -98,29 +1497,39 @@ impl ProfilingData {
             .get(raw_event.event_id.to_string_id())
             .to_string();
         // Parse out the label and arguments from the `event_id`.
-        let (label, additional_data) = Event::parse_event_id(event_id);
+        let (mut label, additional_data) = Event::parse_event_id(event_id);
+        if let Some(rules) = &self.label_rules {
+            let rewritten = match rules.apply(&label) {
+                Cow::Owned(rewritten) => Some(rewritten),
+                Cow::Borrowed(_) => None,
+            };
+            if let Some(rewritten) = rewritten {
+                label = Cow::Owned(rewritten);
+            }
+        }
 
         Event {
-            event_kind: string_table.get(raw_event.event_kind).to_string(),
+            event_kind: string_table
+                .get(self.metadata.event_kind_name(raw_event.event_kind))
+                .to_string(),
             label,
             additional_data,
             timestamp,
             thread_id: raw_event.thread_id,
+            integer_value: raw_event.integer_value(),
+            cpu: raw_event.cpu(),
+            blocked: raw_event.is_blocked(),
         }
     }
 
     fn decode_lightweight_event<'a>(&'a self, event_index: usize) -> LightweightEvent<'a> {
-        let event_start_addr = event_index_to_addr(event_index);
-        let event_end_addr = event_start_addr.checked_add(RAW_EVENT_SIZE).unwrap();
-
-        let raw_event_bytes = &self.event_data[event_start_addr..event_end_addr];
-        let raw_event = RawEvent::deserialize(raw_event_bytes);
+        let raw_event = self.raw_event(event_index);
 
         let timestamp = Timestamp::from_raw_event(&raw_event, self.metadata.start_time);
 
         LightweightEvent {
             data: self,
-            event_index: event_index,
+            event_index,
             timestamp,
             thread_id: raw_event.thread_id,
         }
@@ -190,30 +1599,47 @@ impl<'a> DoubleEndedIterator for ProfilerEventIterator<'a> {
 /// writing tests and other things that are not performance sensitive.
 pub struct ProfilingDataBuilder {
     event_sink: ByteVecSink,
+    timestamp_format: TimestampFormat,
     string_table_data_sink: Arc<ByteVecSink>,
     string_table_index_sink: Arc<ByteVecSink>,
     string_table: StringTableBuilder<ByteVecSink>,
+    // The ids of the strings written so far, if strings are written only
+    // once, see `intern_strings()`.
+    interned: Option<FxHashMap<String, StringId>>,
 }
 
 impl ProfilingDataBuilder {
     pub fn new() -> ProfilingDataBuilder {
+        ProfilingDataBuilder::with_timestamp_format(TimestampFormat::Compact)
+    }
+
+    /// Creates a builder for a profile whose events are stored in the given
+    /// format. Timestamps that don't fit into it make the builder panic.
+    pub fn with_timestamp_format(timestamp_format: TimestampFormat) -> ProfilingDataBuilder {
         let event_sink = ByteVecSink::new();
         let string_table_data_sink = Arc::new(ByteVecSink::new());
         let string_table_index_sink = Arc::new(ByteVecSink::new());
 
-        // The first thing in every file we generate must be the file header.
-        write_file_header(&event_sink, FILE_MAGIC_EVENT_STREAM);
-
         let string_table = StringTableBuilder::new(
             string_table_data_sink.clone(),
             string_table_index_sink.clone(),
         );
 
+        // The first thing in every file we generate must be the file header.
+        write_file_header_with_features(
+            &event_sink,
+            timestamp_format.file_magic(),
+            timestamp_format.feature_flags() | FEATURE_BLOCKED_INTERVALS,
+            string_table.session_id(),
+        );
+
         ProfilingDataBuilder {
             event_sink,
+            timestamp_format,
             string_table_data_sink,
             string_table_index_sink,
             string_table,
+            interned: None,
         }
     }
 
@@ -237,7 +1663,28 @@ impl ProfilingDataBuilder {
         inner(self);
 
         let raw_event =
-            RawEvent::new_interval(event_kind, event_id, thread_id, start_nanos, end_nanos);
+            RawEvent::new_interval_wide(event_kind, event_id, thread_id, start_nanos, end_nanos);
+
+        self.write_raw_event(&raw_event);
+
+        self
+    }
+
+    /// Record a blocked interval event, see `Event::blocked`.
+    pub fn blocked_interval(
+        &mut self,
+        event_kind: &str,
+        event_id: &str,
+        thread_id: u32,
+        start_nanos: u64,
+        end_nanos: u64,
+    ) -> &mut Self {
+        let event_kind = self.string_table.alloc(event_kind);
+        let event_id = EventId::from_label(self.string_table.alloc(event_id));
+
+        let raw_event =
+            RawEvent::new_interval_wide(event_kind, event_id, thread_id, start_nanos, end_nanos)
+                .as_blocked();
 
         self.write_raw_event(&raw_event);
 
@@ -255,15 +1702,131 @@ impl ProfilingDataBuilder {
         let event_kind = self.string_table.alloc(event_kind);
         let event_id = EventId::from_label(self.string_table.alloc(event_id));
 
-        let raw_event = RawEvent::new_instant(event_kind, event_id, thread_id, timestamp_nanos);
+        let raw_event =
+            RawEvent::new_instant_wide(event_kind, event_id, thread_id, timestamp_nanos);
+
+        self.write_raw_event(&raw_event);
+
+        self
+    }
+
+    /// Record an integer event with the given data.
+    pub fn integer(
+        &mut self,
+        event_kind: &str,
+        event_id: &str,
+        thread_id: u32,
+        timestamp_nanos: u64,
+        value: u64,
+    ) -> &mut Self {
+        let event_kind = self.string_table.alloc(event_kind);
+        let event_id = EventId::from_label(self.string_table.alloc(event_id));
+
+        let raw_event =
+            RawEvent::new_integer_wide(event_kind, event_id, thread_id, timestamp_nanos, value);
 
         self.write_raw_event(&raw_event);
 
         self
     }
 
+    /// Allocates an event id with the given label and arguments.
+    pub(crate) fn alloc_event_id(&mut self, label: &str, args: &[&str]) -> EventId {
+        let label = self.alloc_string(label);
+
+        if args.is_empty() {
+            return EventId::from_label(label);
+        }
+
+        let mut components = vec![StringComponent::Ref(label)];
+        for &arg in args {
+            components.push(StringComponent::Value(SEPARATOR_BYTE));
+            components.push(StringComponent::Ref(self.alloc_string(arg)));
+        }
+
+        EventId::from_label(self.string_table.alloc(&components[..]))
+    }
+
+    pub(crate) fn alloc_string(&mut self, s: &str) -> StringId {
+        match &mut self.interned {
+            Some(interned) => match interned.get(s) {
+                Some(&id) => id,
+                None => {
+                    let id = self.string_table.alloc(s);
+                    interned.insert(s.to_string(), id);
+                    id
+                }
+            },
+            None => self.string_table.alloc(s),
+        }
+    }
+
+    /// Makes the strings allocated via `write_event()` etc. be written only
+    /// once, and the strings in `shared` not at all: they get the ids they
+    /// have in `shared`, e.g. those of a shared string table.
+    pub(crate) fn intern_strings(&mut self, shared: FxHashMap<String, StringId>) {
+        self.interned = Some(shared);
+    }
+
+    /// Writes a copy of `event`, which may come from another profile, with
+    /// its timestamps relative to `origin`.
+    pub(crate) fn write_event(&mut self, event: &Event<'_>, thread_id: u32, origin: SystemTime) {
+        let nanos = |t: SystemTime| t.duration_since(origin).unwrap().as_nanos() as u64;
+
+        let event_kind = self.alloc_string(&event.event_kind);
+        let args: Vec<&str> = event.additional_data.iter().map(|arg| &arg[..]).collect();
+        let event_id = self.alloc_event_id(&event.label, &args);
+
+        let raw_event = match event.timestamp {
+            Timestamp::Interval { start, end } => {
+                let raw_event = RawEvent::new_interval_wide(
+                    event_kind,
+                    event_id,
+                    thread_id,
+                    nanos(start),
+                    nanos(end),
+                );
+
+                if event.blocked {
+                    raw_event.as_blocked()
+                } else {
+                    raw_event
+                }
+            }
+            Timestamp::Instant(t) => match event.integer_value {
+                Some(value) => {
+                    RawEvent::new_integer_wide(event_kind, event_id, thread_id, nanos(t), value)
+                }
+                None => RawEvent::new_instant_wide(event_kind, event_id, thread_id, nanos(t)),
+            },
+        };
+
+        self.write_raw_event(&raw_event);
+    }
+
     /// Convert this builder into a `ProfilingData` object that can be iterated.
     pub fn into_profiling_data(self) -> ProfilingData {
+        self.into_profiling_data_with_metadata(Metadata {
+            start_time: UNIX_EPOCH,
+            process_id: 0,
+            cmd: "test cmd".to_string(),
+            truncated: false,
+            dropped_events: 0,
+            arg_schemas: FxHashMap::default(),
+            shared_strings: None,
+            tool: None,
+            aggregate_only: false,
+            budget_exhausted: false,
+            event_kinds: Vec::new(),
+            reserved_strings: Vec::new(),
+            thread_event_files: Vec::new(),
+            event_segments: Vec::new(),
+            overhead: None,
+            system: None,
+        })
+    }
+
+    pub(crate) fn into_profiling_data_with_metadata(self, metadata: Metadata) -> ProfilingData {
         // Drop the string table, so that the `string_table_data_sink` and
         // `string_table_index_sink` fields are the only event-sink references
         // left. This enables us to unwrap the `Arc`s and get the byte data out.
@@ -278,35 +1841,79 @@ impl ProfilingDataBuilder {
             .into_bytes();
 
         assert_eq!(
-            read_file_header(&event_data, FILE_MAGIC_EVENT_STREAM).unwrap(),
+            read_file_header(&event_data, self.timestamp_format.file_magic()).unwrap(),
             CURRENT_FILE_FORMAT_VERSION
         );
         let string_table = StringTable::new(data_bytes, index_bytes).unwrap();
-        let metadata = Metadata {
-            start_time: UNIX_EPOCH,
-            process_id: 0,
-            cmd: "test cmd".to_string(),
-        };
 
         ProfilingData {
             event_data,
+            timestamp_format: self.timestamp_format,
+            timestamp_resolution: TimestampResolution::Nanoseconds,
             string_table,
             metadata,
+            label_formatter: None,
+            label_rules: None,
+            symbolizer: None,
+            warnings: Vec::new(),
         }
     }
 
-    fn write_raw_event(&mut self, raw_event: &RawEvent) {
-        self.event_sink
-            .write_atomic(std::mem::size_of::<RawEvent>(), |bytes| {
-                raw_event.serialize(bytes);
-            });
+    /// Writes the profile to the files with the given path stem, with
+    /// `metadata` as the JSON metadata, instead of loading it.
+    pub(crate) fn write_files(
+        self,
+        path_stem: &Path,
+        metadata: &str,
+    ) -> Result<(), Box<dyn Error>> {
+        self.string_table.alloc_metadata(metadata);
+        drop(self.string_table);
+
+        let files = ProfilerFiles::new(path_stem);
+        let contents = [
+            (files.events_file, self.event_sink.into_bytes()),
+            (
+                files.string_data_file,
+                Arc::try_unwrap(self.string_table_data_sink)
+                    .unwrap()
+                    .into_bytes(),
+            ),
+            (
+                files.string_index_file,
+                Arc::try_unwrap(self.string_table_index_sink)
+                    .unwrap()
+                    .into_bytes(),
+            ),
+        ];
+
+        for (path, mut bytes) in contents {
+            let footer = file_footer(checksum(&bytes));
+            bytes.extend_from_slice(&footer);
+            fs::write(&path, bytes)
+                .map_err(|e| format!("could not write `{}`: {}", path.display(), e))?;
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn write_raw_event(&mut self, raw_event: &RawEvent) {
+        let format = self.timestamp_format;
+        self.event_sink.write_atomic(format.event_size(), |bytes| {
+            raw_event.serialize_as(format, bytes);
+        });
+    }
+}
+
+impl Default for ProfilingDataBuilder {
+    fn default() -> ProfilingDataBuilder {
+        ProfilingDataBuilder::new()
     }
 }
 
 impl<'a> ExactSizeIterator for ProfilerEventIterator<'a> {}
 
-fn event_index_to_addr(event_index: usize) -> usize {
-    FILE_HEADER_SIZE + event_index * mem::size_of::<RawEvent>()
+fn event_index_to_addr(event_index: usize, event_size: usize) -> usize {
+    FILE_HEADER_SIZE + event_index * event_size
 }
 
 #[rustfmt::skip]
@@ -332,6 +1939,9 @@ mod tests {
                 end: SystemTime::UNIX_EPOCH + Duration::from_nanos(end_nanos),
             },
             thread_id,
+            integer_value: None,
+            cpu: None,
+            blocked: false,
         }
     }
 
@@ -349,6 +1959,9 @@ mod tests {
                 SystemTime::UNIX_EPOCH + Duration::from_nanos(timestamp_nanos),
             ),
             thread_id,
+            integer_value: None,
+            cpu: None,
+            blocked: false,
         }
     }
 
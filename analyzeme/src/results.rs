@@ -0,0 +1,210 @@
+//! Storing what tools have derived from a profile, e.g. self-times or the
+//! critical path, next to the profile as `<path_stem>.analysis`, so that
+//! later invocations of the same or other tools can reuse the results
+//! instead of recomputing them.
+//!
+//! The results are keyed by the profile's fingerprint (see
+//! `ProfilingData::fingerprint()`) together with the start time and process
+//! id of the recording, as the fingerprint alone doesn't tell apart
+//! recordings of the same work: if the profile changes, e.g. because a new
+//! profile has been recorded with the same path stem, the stored results
+//! are discarded when loading. Results are plain JSON values stored
+//! under a name of the tool's choosing, which should include a version if
+//! the format of the value may change, like `"mytool/critical_path/1"`.
+
+use crate::ProfilingData;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+/// Changes whenever the layout of the file changes.
+const FILE_FORMAT: u32 = 1;
+
+#[derive(Serialize, Deserialize)]
+struct ResultsFile {
+    version: u32,
+    key: ProfileKey,
+    results: BTreeMap<String, serde_json::Value>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct ProfileKey {
+    fingerprint: u64,
+    /// In nanoseconds since the Unix epoch.
+    start_time: u64,
+    process_id: u32,
+}
+
+impl ProfileKey {
+    fn new(data: &ProfilingData) -> ProfileKey {
+        ProfileKey {
+            fingerprint: data.fingerprint(),
+            start_time: data
+                .metadata
+                .start_time
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_nanos() as u64,
+            process_id: data.metadata.process_id,
+        }
+    }
+}
+
+/// The analysis results stored for a profile.
+#[derive(Clone, Debug)]
+pub struct AnalysisResults {
+    file: PathBuf,
+    key: ProfileKey,
+    results: BTreeMap<String, serde_json::Value>,
+    modified: bool,
+}
+
+impl AnalysisResults {
+    /// Loads the results stored for the profile `data` that has been loaded
+    /// from `path_stem`. Results that have been stored for a different
+    /// profile or in an unknown format are ignored, as are unreadable files,
+    /// since the results can always be computed anew.
+    pub fn load(path_stem: &Path, data: &ProfilingData) -> AnalysisResults {
+        let file = path_stem.with_extension("analysis");
+        let key = ProfileKey::new(data);
+
+        let results = fs::read(&file)
+            .ok()
+            .and_then(|bytes| serde_json::from_slice::<ResultsFile>(&bytes).ok())
+            .filter(|stored| stored.version == FILE_FORMAT && stored.key == key)
+            .map(|stored| stored.results)
+            .unwrap_or_default();
+
+        AnalysisResults {
+            file,
+            key,
+            results,
+            modified: false,
+        }
+    }
+
+    /// The names of the stored results, in alphabetical order.
+    pub fn names(&self) -> impl Iterator<Item = &str> + '_ {
+        self.results.keys().map(|name| &name[..])
+    }
+
+    /// Returns the result stored under `name`, or `None` if there is none
+    /// or it doesn't have the expected type.
+    pub fn get<T: DeserializeOwned>(&self, name: &str) -> Option<T> {
+        serde_json::from_value(self.results.get(name)?.clone()).ok()
+    }
+
+    /// Stores `value` under `name`, replacing any previous result. The
+    /// result is only written to the file by `save()`.
+    pub fn insert<T: Serialize>(&mut self, name: &str, value: &T) -> Result<(), Box<dyn Error>> {
+        self.results
+            .insert(name.to_string(), serde_json::to_value(value)?);
+        self.modified = true;
+        Ok(())
+    }
+
+    pub fn remove(&mut self, name: &str) -> bool {
+        let removed = self.results.remove(name).is_some();
+        self.modified |= removed;
+        removed
+    }
+
+    /// Returns the result stored under `name`, computing and storing it
+    /// first if there is none.
+    pub fn get_or_insert_with<T, F>(&mut self, name: &str, compute: F) -> Result<T, Box<dyn Error>>
+    where
+        T: Serialize + DeserializeOwned,
+        F: FnOnce() -> T,
+    {
+        if let Some(value) = self.get(name) {
+            return Ok(value);
+        }
+
+        let value = compute();
+        self.insert(name, &value)?;
+        Ok(value)
+    }
+
+    /// Writes the results to `<path_stem>.analysis` if they have changed
+    /// since they were loaded.
+    pub fn save(&mut self) -> io::Result<()> {
+        if !self.modified {
+            return Ok(());
+        }
+
+        let file = ResultsFile {
+            version: FILE_FORMAT,
+            key: self.key,
+            results: std::mem::take(&mut self.results),
+        };
+        let json = serde_json::to_vec(&file);
+        self.results = file.results;
+
+        fs::write(&self.file, json?)?;
+        self.modified = false;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ProfilingDataBuilder;
+
+    #[test]
+    fn stored_results() {
+        let dir = Path::new("test-tmp").join("analysis_results");
+        fs::create_dir_all(&dir).unwrap();
+        let path_stem = dir.join("profile");
+        let _ = fs::remove_file(path_stem.with_extension("analysis"));
+
+        const MS: u64 = 1_000_000;
+        let profile = |duration: u64| {
+            let mut b = ProfilingDataBuilder::new();
+            b.interval("Query", "typeck", 0, 0, duration * MS, |_| {});
+            b.into_profiling_data()
+        };
+        let data = profile(100);
+
+        let mut results = AnalysisResults::load(&path_stem, &data);
+        assert_eq!(results.names().count(), 0);
+        let mut computed = 0;
+        let self_times: Vec<(String, u64)> = results
+            .get_or_insert_with("test/self_times/1", || {
+                computed += 1;
+                vec![("typeck".to_string(), 100)]
+            })
+            .unwrap();
+        results.insert("test/event_count/1", &1u64).unwrap();
+        results.save().unwrap();
+
+        // A later invocation reuses the stored results.
+        let mut results = AnalysisResults::load(&path_stem, &data);
+        assert_eq!(
+            results.names().collect::<Vec<_>>(),
+            ["test/event_count/1", "test/self_times/1"]
+        );
+        let reloaded: Vec<(String, u64)> = results
+            .get_or_insert_with("test/self_times/1", || unreachable!())
+            .unwrap();
+        assert_eq!(reloaded, self_times);
+        assert_eq!(computed, 1);
+        assert_eq!(results.get::<u64>("test/event_count/1"), Some(1));
+        assert_eq!(results.get::<String>("test/event_count/1"), None);
+
+        assert!(results.remove("test/event_count/1"));
+        results.save().unwrap();
+        let results = AnalysisResults::load(&path_stem, &data);
+        assert_eq!(results.names().collect::<Vec<_>>(), ["test/self_times/1"]);
+
+        // The results of a different profile with the same path stem are
+        // discarded.
+        let results = AnalysisResults::load(&path_stem, &profile(200));
+        assert_eq!(results.names().count(), 0);
+    }
+}
@@ -0,0 +1,186 @@
+use crate::{LightweightEvent, ProfilingData};
+use rustc_hash::FxHashMap;
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+use std::time::Duration;
+
+/// A random sample of the interval events of a profile, in which every
+/// event's chance of being picked is proportional to its duration, see
+/// `sample_events()`.
+#[derive(Clone, Debug)]
+pub struct EventSample<'a> {
+    /// The sampled events, in the order of the profile.
+    pub events: Vec<LightweightEvent<'a>>,
+    /// The number of interval events the sample has been drawn from.
+    pub population: usize,
+    /// The summed duration of these events, including nested ones.
+    pub total_duration: Duration,
+}
+
+impl<'a> EventSample<'a> {
+    /// Estimates the summed duration of the events of each label, by
+    /// attributing an equal share of the total duration to every sampled
+    /// event. Sorted by the estimated duration, longest first.
+    ///
+    /// This is approximate: events that are longer than the total duration
+    /// divided by the sample size are underrepresented, as every event can
+    /// only be sampled once. Sampled events are decoded, the others aren't.
+    pub fn estimated_duration_by_label(&self) -> Vec<(String, Duration)> {
+        if self.events.is_empty() {
+            return Vec::new();
+        }
+
+        let mut counts = FxHashMap::<String, u32>::default();
+        for event in &self.events {
+            *counts
+                .entry(event.to_event().label.into_owned())
+                .or_default() += 1;
+        }
+
+        let per_event = self.total_duration / self.events.len() as u32;
+        let mut estimates: Vec<_> = counts
+            .into_iter()
+            .map(|(label, count)| (label, per_event * count))
+            .collect();
+        estimates.sort_by(|(a_label, a), (b_label, b)| b.cmp(a).then(a_label.cmp(b_label)));
+        estimates
+    }
+}
+
+/// Draws a random sample of up to `sample_size` interval events, weighted by
+/// their duration (including nested events), without replacement, so
+/// that interactive tools can show an approximate breakdown of an enormous
+/// profile right away. This needs a single pass over the events, which
+/// doesn't decode any strings, via weighted reservoir sampling (Efraimidis
+/// and Spirakis' A-Res). Instant events and intervals of zero duration are
+/// never sampled.
+///
+/// The same `seed` always gives the same sample of the same profile.
+pub fn sample_events(data: &ProfilingData, sample_size: usize, seed: u64) -> EventSample<'_> {
+    let mut rng = SplitMix64(seed);
+    // The `sample_size` events with the largest keys, smallest key first.
+    let mut reservoir = BinaryHeap::<Reverse<(Key, usize)>>::with_capacity(sample_size + 1);
+    let mut population = 0;
+    let mut total_duration = Duration::default();
+
+    for event in data.iter() {
+        let duration = match event.duration() {
+            Some(duration) if duration > Duration::default() => duration,
+            _ => continue,
+        };
+        population += 1;
+        total_duration += duration;
+
+        if sample_size == 0 {
+            continue;
+        }
+
+        // The key is `u^(1/w)` for a uniform `u` in (0, 1), compared via its
+        // logarithm, which doesn't underflow for long events.
+        let key = Key(rng.next_f64().ln() / duration.as_nanos() as f64);
+        if reservoir.len() < sample_size {
+            reservoir.push(Reverse((key, event.event_index)));
+        } else if reservoir.peek().is_some_and(|Reverse((min, _))| key > *min) {
+            reservoir.pop();
+            reservoir.push(Reverse((key, event.event_index)));
+        }
+    }
+
+    let mut indices: Vec<usize> = reservoir
+        .into_iter()
+        .map(|Reverse((_, index))| index)
+        .collect();
+    indices.sort_unstable();
+
+    EventSample {
+        events: indices.into_iter().map(|index| data.event(index)).collect(),
+        population,
+        total_duration,
+    }
+}
+
+/// A sampling key, which is never NaN.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Key(f64);
+
+impl Eq for Key {}
+
+impl PartialOrd for Key {
+    fn partial_cmp(&self, other: &Key) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Key {
+    fn cmp(&self, other: &Key) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+/// Sebastiano Vigna's SplitMix64, which is plenty for sampling.
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// A uniform number in (0, 1].
+    fn next_f64(&mut self) -> f64 {
+        ((self.next_u64() >> 11) + 1) as f64 / (1u64 << 53) as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ProfilingDataBuilder;
+
+    #[test]
+    fn weighted_sample() {
+        let mut b = ProfilingDataBuilder::new();
+        let mut t = 0;
+        // 100 long events and 900 short ones that together take a tenth of
+        // the time.
+        for i in 0..1000 {
+            let (label, duration) = if i % 10 == 0 {
+                ("long", 81)
+            } else {
+                ("short", 1)
+            };
+            b.interval("Query", label, 0, t, t + duration, |_| {});
+            t += duration;
+        }
+        b.instant("Marker", "instant", 0, t);
+        let data = b.into_profiling_data();
+
+        let sample = sample_events(&data, 50, 7);
+        assert_eq!(sample.events.len(), 50);
+        assert_eq!(sample.population, 1000);
+        assert_eq!(sample.total_duration, Duration::from_nanos(9000));
+        assert!(sample
+            .events
+            .windows(2)
+            .all(|w| w[0].event_index < w[1].event_index));
+
+        let estimates = sample.estimated_duration_by_label();
+        assert_eq!(estimates[0].0, "long");
+        assert!(
+            estimates[0].1 >= Duration::from_nanos(7200),
+            "{:?}",
+            estimates
+        );
+
+        let again = sample_events(&data, 50, 7);
+        let indices =
+            |s: &EventSample<'_>| s.events.iter().map(|e| e.event_index).collect::<Vec<_>>();
+        assert_eq!(indices(&again), indices(&sample));
+
+        assert_eq!(sample_events(&data, 2000, 7).events.len(), 1000);
+        assert!(sample_events(&data, 0, 7).events.is_empty());
+    }
+}
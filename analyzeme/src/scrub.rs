@@ -0,0 +1,254 @@
+use crate::annotate::write_copy;
+use crate::{Event, ProfilingData};
+use serde_json::Value;
+use std::borrow::Cow;
+use std::env;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+/// The directories below which the home directories of users live, on
+/// Linux, macOS and Windows.
+const HOME_ROOTS: &[&str] = &["/home/", "/Users/", "C:\\Users\\", "C:/Users/"];
+
+/// Replaces the directory prefixes of filesystem paths in strings with
+/// placeholders like `$SRC` or `$CARGO_HOME`, so that a profile can be shared
+/// without giving away usernames or how the machine is laid out, while the
+/// rest of the paths stays readable. See `scrub_paths()`.
+///
+/// Prefixes only match whole path components at the start of a path, i.e.
+/// `/home/me` matches `/home/me/src/lib.rs` but neither `/home/meg` nor
+/// `/backup/home/me`. Longer prefixes take precedence over shorter ones.
+/// Paths in the home directory of any user that no prefix matches are
+/// replaced up to the username with `$HOME`.
+#[derive(Clone, Debug, Default)]
+pub struct PathScrubber {
+    // (prefix, placeholder), longest prefix first.
+    prefixes: Vec<(String, String)>,
+}
+
+impl PathScrubber {
+    /// Creates a scrubber without any prefixes, which only replaces home
+    /// directories.
+    pub fn new() -> PathScrubber {
+        PathScrubber::default()
+    }
+
+    /// Creates a scrubber for the directories of the machine it runs on:
+    /// the current directory becomes `$SRC`, the cargo and rustup homes
+    /// (`CARGO_HOME` and `RUSTUP_HOME`, or `.cargo` and `.rustup` in the
+    /// home directory) become `$CARGO_HOME` and `$RUSTUP_HOME` and the home
+    /// directory becomes `$HOME`.
+    pub fn from_env() -> PathScrubber {
+        let mut scrubber = PathScrubber::new();
+        let home = env::var_os("HOME")
+            .or_else(|| env::var_os("USERPROFILE"))
+            .map(PathBuf::from);
+
+        let dirs = [
+            ("$SRC", env::current_dir().ok()),
+            (
+                "$CARGO_HOME",
+                env::var_os("CARGO_HOME")
+                    .map(PathBuf::from)
+                    .or_else(|| home.as_ref().map(|home| home.join(".cargo"))),
+            ),
+            (
+                "$RUSTUP_HOME",
+                env::var_os("RUSTUP_HOME")
+                    .map(PathBuf::from)
+                    .or_else(|| home.as_ref().map(|home| home.join(".rustup"))),
+            ),
+            ("$HOME", home.clone()),
+        ];
+
+        for (placeholder, dir) in dirs {
+            if let Some(dir) = dir.as_deref().and_then(Path::to_str) {
+                scrubber.add_prefix(dir, placeholder);
+            }
+        }
+
+        scrubber
+    }
+
+    /// Replaces `prefix` with `placeholder`, instead of any placeholder
+    /// given for the same prefix before.
+    pub fn add_prefix(&mut self, prefix: &str, placeholder: &str) {
+        let prefix = prefix.trim_end_matches(is_separator);
+        if prefix.is_empty() {
+            return;
+        }
+
+        self.prefixes.retain(|(p, _)| p != prefix);
+        self.prefixes
+            .push((prefix.to_string(), placeholder.to_string()));
+        self.prefixes
+            .sort_by(|(a, _), (b, _)| b.len().cmp(&a.len()).then_with(|| a.cmp(b)));
+    }
+
+    pub fn scrub<'s>(&self, s: &'s str) -> Cow<'s, str> {
+        let mut result = String::new();
+        let mut copied = 0;
+        let mut i = 0;
+
+        while i < s.len() {
+            let at_path_start = s[..i]
+                .chars()
+                .next_back()
+                .is_none_or(|c| !is_path_char(c) && !is_separator(c));
+
+            if at_path_start {
+                if let Some((len, placeholder)) = self.match_prefix(&s[i..]) {
+                    result.push_str(&s[copied..i]);
+                    result.push_str(placeholder);
+                    i += len;
+                    copied = i;
+                    continue;
+                }
+            }
+
+            i += s[i..].chars().next().unwrap().len_utf8();
+        }
+
+        if copied == 0 {
+            Cow::Borrowed(s)
+        } else {
+            result.push_str(&s[copied..]);
+            Cow::Owned(result)
+        }
+    }
+
+    /// Returns the length of the prefix at the start of `s` and the
+    /// placeholder to replace it with.
+    fn match_prefix(&self, s: &str) -> Option<(usize, &str)> {
+        for (prefix, placeholder) in &self.prefixes {
+            if let Some(rest) = s.strip_prefix(&prefix[..]) {
+                if rest.chars().next().is_none_or(|c| !is_path_char(c)) {
+                    return Some((prefix.len(), placeholder));
+                }
+            }
+        }
+
+        for root in HOME_ROOTS {
+            if let Some(rest) = s.strip_prefix(root) {
+                let user = rest.len() - rest.trim_start_matches(is_path_char).len();
+                if user > 0 {
+                    return Some((root.len() + user, "$HOME"));
+                }
+            }
+        }
+
+        None
+    }
+}
+
+/// The characters of a path component, besides which paths in the strings of
+/// rustc are delimited by quotes, whitespace, parentheses, colons (as in
+/// `src/lib.rs:12:5`) or the like.
+fn is_path_char(c: char) -> bool {
+    c.is_alphanumeric() || matches!(c, '_' | '-' | '.' | '~' | '+' | '@' | '$')
+}
+
+fn is_separator(c: char) -> bool {
+    c == '/' || c == '\\'
+}
+
+/// Writes a copy of the profile to the files with the given path stem, with
+/// the paths in event kinds, labels, arguments and the metadata (e.g. the
+/// command line) scrubbed via `scrubber`. Like `annotate()`, the copy has a
+/// string table of its own.
+pub fn scrub_paths(
+    data: &ProfilingData,
+    scrubber: &PathScrubber,
+    path_stem: &Path,
+) -> Result<(), Box<dyn Error>> {
+    if data.metadata.aggregate_only {
+        Err("the profile has been recorded in aggregate-only mode and has no events to scrub")?;
+    }
+
+    let events: Vec<Event<'_>> = data
+        .iter()
+        .map(|e| {
+            let event = e.to_event();
+            Event {
+                event_kind: scrub_cow(event.event_kind, scrubber),
+                label: scrub_cow(event.label, scrubber),
+                additional_data: event
+                    .additional_data
+                    .into_iter()
+                    .map(|arg| scrub_cow(arg, scrubber))
+                    .collect(),
+                ..event
+            }
+        })
+        .collect();
+
+    write_copy(
+        data,
+        &events,
+        data.timestamp_format(),
+        path_stem,
+        |metadata| scrub_json(metadata, scrubber),
+    )
+}
+
+// Keeps borrowed strings borrowed if there's nothing to scrub.
+fn scrub_cow<'a>(s: Cow<'a, str>, scrubber: &PathScrubber) -> Cow<'a, str> {
+    let scrubbed = match scrubber.scrub(&s) {
+        Cow::Owned(scrubbed) => Some(scrubbed),
+        Cow::Borrowed(_) => None,
+    };
+    scrubbed.map_or(s, Cow::Owned)
+}
+
+fn scrub_json(value: &mut Value, scrubber: &PathScrubber) {
+    match value {
+        Value::String(s) => {
+            if let Cow::Owned(scrubbed) = scrubber.scrub(s) {
+                *s = scrubbed;
+            }
+        }
+        Value::Array(values) => values.iter_mut().for_each(|v| scrub_json(v, scrubber)),
+        Value::Object(fields) => fields.values_mut().for_each(|v| scrub_json(v, scrubber)),
+        Value::Null | Value::Bool(_) | Value::Number(_) => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prefixes() {
+        let mut scrubber = PathScrubber::new();
+        scrubber.add_prefix("/home/me", "$HOME");
+        scrubber.add_prefix("/home/me/work/foo/", "$SRC");
+        scrubber.add_prefix("/home/me/.cargo", "$CARGO_HOME");
+        let scrub = |s| scrubber.scrub(s).into_owned();
+
+        assert_eq!(
+            scrub("rustc --crate-name foo /home/me/work/foo/src/lib.rs --out-dir=/home/me/work/foo/target"),
+            "rustc --crate-name foo $SRC/src/lib.rs --out-dir=$SRC/target"
+        );
+        assert_eq!(
+            scrub("`/home/me/.cargo/registry/src/serde-1.0/src/de.rs:12:5`"),
+            "`$CARGO_HOME/registry/src/serde-1.0/src/de.rs:12:5`"
+        );
+        assert_eq!(scrub("/home/me/notes.txt"), "$HOME/notes.txt");
+        assert_eq!(scrub("/home/me"), "$HOME");
+
+        // Only whole components at the start of a path.
+        assert_eq!(scrub("/home/meg/lib.rs"), "$HOME/lib.rs");
+        assert_eq!(scrub("/backup/home/me/lib.rs"), "/backup/home/me/lib.rs");
+        assert_eq!(scrub("typeck"), "typeck");
+        assert!(matches!(scrubber.scrub("typeck"), Cow::Borrowed(_)));
+
+        assert_eq!(
+            scrub("C:\\Users\\Me\\src\\main.rs and /Users/me/x"),
+            "$HOME\\src\\main.rs and $HOME/x"
+        );
+
+        let mut scrubber = scrubber.clone();
+        scrubber.add_prefix("/home/me", "~");
+        assert_eq!(scrubber.scrub("/home/me/notes.txt"), "~/notes.txt");
+    }
+}
@@ -0,0 +1,390 @@
+use crate::ProfilingData;
+use byteorder::{ByteOrder, LittleEndian};
+use rustc_hash::FxHashMap;
+use std::error::Error;
+
+const MAGIC: &[u8; 4] = b"MMSI";
+const VERSION: u32 = 1;
+const CORRUPT: &str = "the search index is corrupt";
+
+/// An index for finding the events whose label or arguments contain a given
+/// substring, e.g. all events mentioning `serde_json`, without decoding the
+/// strings of all events for every search.
+///
+/// The index is a suffix array over the distinct labels and arguments of the
+/// profile's events, which takes a while to build for large profiles, so it
+/// can be stored next to the profile via `to_bytes()` and loaded again via
+/// `from_bytes()`.
+#[derive(Clone, Debug)]
+pub struct SearchIndex {
+    num_events: u32,
+    // The distinct strings, each followed by a zero byte.
+    text: Vec<u8>,
+    // The offset of each string in `text`.
+    starts: Vec<u32>,
+    // The indices of the events each string occurs in, in ascending order.
+    postings: Vec<Vec<u32>>,
+    // The offsets of all suffixes of `text`, in lexicographic order.
+    suffixes: Vec<u32>,
+}
+
+/// A string found by `SearchIndex::search()`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SearchMatch<'a> {
+    pub string: &'a str,
+    /// The indices of the events whose label or arguments are `string`, see
+    /// `ProfilingData::event()`.
+    pub events: &'a [u32],
+}
+
+impl SearchIndex {
+    pub fn build(data: &ProfilingData) -> SearchIndex {
+        let mut text = Vec::new();
+        let mut starts = Vec::new();
+        let mut postings: Vec<Vec<u32>> = Vec::new();
+        let mut ids = FxHashMap::<String, usize>::default();
+
+        for (event_index, event) in data.iter().enumerate() {
+            let event = event.to_event();
+            let strings = std::iter::once(&event.label).chain(&event.additional_data);
+
+            for string in strings {
+                let id = match ids.get(&string[..]) {
+                    Some(&id) => id,
+                    None => {
+                        // Strings can't contain the separator, see `search()`.
+                        starts.push(text.len() as u32);
+                        text.extend_from_slice(string.replace('\0', "").as_bytes());
+                        text.push(0);
+                        postings.push(Vec::new());
+                        ids.insert(string.to_string(), postings.len() - 1);
+                        postings.len() - 1
+                    }
+                };
+
+                let events = &mut postings[id];
+                if events.last() != Some(&(event_index as u32)) {
+                    events.push(event_index as u32);
+                }
+            }
+        }
+
+        assert!(text.len() < u32::MAX as usize, "too many strings to index");
+        let suffixes = suffix_array(&text);
+
+        SearchIndex {
+            num_events: data.num_events() as u32,
+            text,
+            starts,
+            postings,
+            suffixes,
+        }
+    }
+
+    /// Returns the strings containing `pattern`, in the order in which they
+    /// first occur in the profile.
+    pub fn search(&self, pattern: &str) -> Vec<SearchMatch<'_>> {
+        if pattern.contains('\0') {
+            return Vec::new();
+        }
+
+        let pattern = pattern.as_bytes();
+        let prefix = |suffix: u32| {
+            let suffix = &self.text[suffix as usize..];
+            &suffix[..pattern.len().min(suffix.len())]
+        };
+
+        let first = self.suffixes.partition_point(|&s| prefix(s) < pattern);
+        let last = self.suffixes.partition_point(|&s| prefix(s) <= pattern);
+
+        let mut ids: Vec<usize> = self.suffixes[first..last]
+            .iter()
+            .map(|&suffix| self.starts.partition_point(|&start| start <= suffix) - 1)
+            .collect();
+        ids.sort_unstable();
+        ids.dedup();
+
+        ids.into_iter()
+            .map(|id| SearchMatch {
+                string: self.string(id),
+                events: &self.postings[id],
+            })
+            .collect()
+    }
+
+    pub fn num_strings(&self) -> usize {
+        self.starts.len()
+    }
+
+    /// The number of events of the indexed profile, which allows telling
+    /// whether a stored index still fits a profile.
+    pub fn num_events(&self) -> usize {
+        self.num_events as usize
+    }
+
+    fn string(&self, id: usize) -> &str {
+        let start = self.starts[id] as usize;
+        let end = self
+            .starts
+            .get(id + 1)
+            .map_or(self.text.len(), |&s| s as usize)
+            - 1;
+        // The text is made of whole strings, the index has been validated.
+        std::str::from_utf8(&self.text[start..end]).unwrap()
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        let push = |bytes: &mut Vec<u8>, value: u32| {
+            let mut buf = [0; 4];
+            LittleEndian::write_u32(&mut buf, value);
+            bytes.extend_from_slice(&buf);
+        };
+
+        bytes.extend_from_slice(MAGIC);
+        push(&mut bytes, VERSION);
+        push(&mut bytes, self.num_events);
+
+        push(&mut bytes, self.starts.len() as u32);
+        for (id, events) in self.postings.iter().enumerate() {
+            let string = self.string(id);
+            push(&mut bytes, string.len() as u32);
+            bytes.extend_from_slice(string.as_bytes());
+            push(&mut bytes, events.len() as u32);
+            for &event in events {
+                push(&mut bytes, event);
+            }
+        }
+
+        for &suffix in &self.suffixes {
+            push(&mut bytes, suffix);
+        }
+
+        bytes
+    }
+
+    /// Loads an index written by `to_bytes()`. Returns an error if the data
+    /// is not a valid index.
+    pub fn from_bytes(bytes: &[u8]) -> Result<SearchIndex, Box<dyn Error>> {
+        if bytes.get(..4) != Some(&MAGIC[..]) {
+            Err("not a search index")?;
+        }
+
+        let mut reader = Reader { bytes, pos: 4 };
+
+        let version = reader.read_u32()?;
+        if version != VERSION {
+            Err(format!("unsupported search index version {}", version))?;
+        }
+        let num_events = reader.read_u32()?;
+
+        let mut text = Vec::new();
+        let mut starts = Vec::new();
+        let mut postings = Vec::new();
+
+        for _ in 0..reader.read_u32()? {
+            let len = reader.read_u32()? as usize;
+            let string = std::str::from_utf8(reader.read(len)?).map_err(|_| CORRUPT)?;
+            if string.contains('\0') {
+                Err(CORRUPT)?;
+            }
+
+            starts.push(text.len() as u32);
+            text.extend_from_slice(string.as_bytes());
+            text.push(0);
+
+            let count = reader.read_u32()? as usize;
+            let events = reader.read_u32s(count)?;
+            if events.iter().any(|&event| event >= num_events) {
+                Err(CORRUPT)?;
+            }
+            postings.push(events);
+        }
+
+        let suffixes = reader.read_u32s(text.len())?;
+        if reader.pos != bytes.len() || suffixes.iter().any(|&s| s as usize >= text.len()) {
+            Err(CORRUPT)?;
+        }
+
+        Ok(SearchIndex {
+            num_events,
+            text,
+            starts,
+            postings,
+            suffixes,
+        })
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn read(&mut self, len: usize) -> Result<&'a [u8], &'static str> {
+        let end = self.pos.checked_add(len).ok_or(CORRUPT)?;
+        let slice = self.bytes.get(self.pos..end).ok_or(CORRUPT)?;
+        self.pos = end;
+        Ok(slice)
+    }
+
+    fn read_u32(&mut self) -> Result<u32, &'static str> {
+        self.read(4).map(LittleEndian::read_u32)
+    }
+
+    fn read_u32s(&mut self, count: usize) -> Result<Vec<u32>, &'static str> {
+        let bytes = self.read(count.checked_mul(4).ok_or(CORRUPT)?)?;
+        Ok(bytes.chunks(4).map(LittleEndian::read_u32).collect())
+    }
+}
+
+/// Sorts the suffixes of `text` by prefix doubling: the suffixes are first
+/// sorted by their first `k = 7` bytes, and then `k` doubles in every round.
+/// Only groups of suffixes that are still equal are sorted again, which most
+/// suffixes leave after a few rounds.
+fn suffix_array(text: &[u8]) -> Vec<u32> {
+    const FIRST_K: usize = 7;
+
+    let n = text.len();
+
+    // The first bytes, 9 bits each, so that the end of a short suffix sorts
+    // before all bytes.
+    let first_bytes = |i: usize| {
+        (0..FIRST_K).fold(0u64, |key, j| {
+            let byte = text.get(i + j).map_or(0, |&b| b as u64 + 1);
+            key << 9 | byte
+        })
+    };
+    let mut keyed: Vec<(u64, u32)> = (0..n).map(|i| (first_bytes(i), i as u32)).collect();
+    keyed.sort_unstable();
+    let mut suffixes: Vec<u32> = keyed.iter().map(|&(_, i)| i).collect();
+
+    // The rank of a suffix is the position of the first suffix in
+    // `suffixes` that starts with the same `k` bytes.
+    let mut rank = vec![0; n];
+    // The ranges of `suffixes` that start with the same `k` bytes and thus
+    // may still be out of order.
+    let mut groups = Vec::new();
+
+    let mut group_start = 0;
+    for j in 0..n {
+        if j > 0 && keyed[j - 1].0 != keyed[j].0 {
+            if j - group_start > 1 {
+                groups.push((group_start, j));
+            }
+            group_start = j;
+        }
+        rank[suffixes[j] as usize] = group_start as u32;
+    }
+    if n - group_start > 1 {
+        groups.push((group_start, n));
+    }
+
+    let mut k = FIRST_K;
+    let mut new_ranks = Vec::new();
+
+    while !groups.is_empty() {
+        let mut next_groups = Vec::new();
+
+        // Suffixes shorter than `k` sort before all others with the same
+        // first `k` bytes.
+        let key = |i: u32| rank.get(i as usize + k).map_or(0, |&r| r as u64 + 1);
+
+        for &(start, end) in &groups {
+            // Looking up the keys once per suffix instead of once per
+            // comparison makes a big difference for large groups.
+            keyed.clear();
+            keyed.extend(suffixes[start..end].iter().map(|&i| (key(i), i)));
+            keyed.sort_unstable();
+
+            let mut group_start = start;
+            for (j, &(key, i)) in (start..end).zip(&keyed) {
+                if j > start && keyed[j - start - 1].0 != key {
+                    if j - group_start > 1 {
+                        next_groups.push((group_start, j));
+                    }
+                    group_start = j;
+                }
+                suffixes[j] = i;
+                new_ranks.push((i, group_start as u32));
+            }
+            if end - group_start > 1 {
+                next_groups.push((group_start, end));
+            }
+        }
+
+        // The keys of this round have to use the ranks of the last one.
+        for (i, r) in new_ranks.drain(..) {
+            rank[i as usize] = r;
+        }
+
+        groups = next_groups;
+        k *= 2;
+    }
+
+    suffixes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ProfilingDataBuilder;
+
+    #[test]
+    fn suffixes() {
+        // Includes an empty string and repetitions longer than the eight
+        // bytes of the first round.
+        let texts: &[&[u8]] = &[
+            b"",
+            b"banana\0bandana\0\0",
+            b"abcabcabcabcabcabcabc\0abcabcabcabcabcabc\0",
+        ];
+
+        for text in texts {
+            let mut expected: Vec<u32> = (0..text.len() as u32).collect();
+            expected.sort_by_key(|&i| &text[i as usize..]);
+            assert_eq!(suffix_array(text), expected);
+        }
+    }
+
+    #[test]
+    fn search() {
+        let mut b = ProfilingDataBuilder::new();
+        b.interval("Query", "serde_json::to_string", 0, 0, 10, |_| {});
+        b.interval("Query", "typeck", 0, 10, 20, |_| {});
+        b.interval("Codegen", "codegen_module", 0, 20, 30, |_| {});
+        b.interval("Query", "serde_json::to_string", 1, 30, 40, |_| {});
+
+        let data = b.into_profiling_data();
+        let index = SearchIndex::build(&data);
+        assert_eq!(index.num_strings(), 3);
+
+        let strings = |index: &SearchIndex, pattern| -> Vec<(String, Vec<u32>)> {
+            index
+                .search(pattern)
+                .into_iter()
+                .map(|m| (m.string.to_string(), m.events.to_vec()))
+                .collect()
+        };
+
+        assert_eq!(
+            strings(&index, "json"),
+            vec![("serde_json::to_string".to_string(), vec![0, 3])]
+        );
+        assert_eq!(
+            strings(&index, "co"),
+            vec![("codegen_module".to_string(), vec![2])]
+        );
+        assert_eq!(strings(&index, "e").len(), 3);
+        assert!(strings(&index, "xyz").is_empty());
+        assert!(strings(&index, "typeck\0").is_empty());
+
+        let loaded = SearchIndex::from_bytes(&index.to_bytes()).unwrap();
+        assert_eq!(strings(&loaded, "json"), strings(&index, "json"));
+
+        let bytes = index.to_bytes();
+        assert!(SearchIndex::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        assert!(SearchIndex::from_bytes(b"MMSI").is_err());
+    }
+}
@@ -0,0 +1,246 @@
+use crate::event::Event;
+use crate::ProfilingData;
+use crate::ProfilingDataBuilder;
+use measureme::rustc::*;
+use measureme::{EventId, StringId};
+use rustc_hash::FxHashMap;
+
+/// A set of event categories, named like the values that rustc accepts for
+/// `-Z self-profile-events`. Passing a profile and such a set to
+/// `filter_self_profile_events()` shows what the profile would have looked
+/// like had it been recorded with that setting.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SelfProfileEvents(u32);
+
+impl SelfProfileEvents {
+    pub const GENERIC_ACTIVITIES: SelfProfileEvents = SelfProfileEvents(1 << 0);
+    pub const QUERY_PROVIDERS: SelfProfileEvents = SelfProfileEvents(1 << 1);
+    pub const QUERY_CACHE_HITS: SelfProfileEvents = SelfProfileEvents(1 << 2);
+    pub const QUERY_BLOCKED: SelfProfileEvents = SelfProfileEvents(1 << 3);
+    pub const INCR_CACHE_LOADS: SelfProfileEvents = SelfProfileEvents(1 << 4);
+    pub const QUERY_KEYS: SelfProfileEvents = SelfProfileEvents(1 << 5);
+    pub const FUNCTION_ARGS: SelfProfileEvents = SelfProfileEvents(1 << 6);
+    pub const LLVM: SelfProfileEvents = SelfProfileEvents(1 << 7);
+    pub const INCR_RESULT_HASHING: SelfProfileEvents = SelfProfileEvents(1 << 8);
+    pub const ARTIFACT_SIZES: SelfProfileEvents = SelfProfileEvents(1 << 9);
+
+    pub const NONE: SelfProfileEvents = SelfProfileEvents(0);
+    pub const ALL: SelfProfileEvents = SelfProfileEvents((1 << 10) - 1);
+    pub const ARGS: SelfProfileEvents =
+        SelfProfileEvents(Self::QUERY_KEYS.0 | Self::FUNCTION_ARGS.0);
+    /// What rustc records if `-Z self-profile-events` isn't given.
+    pub const DEFAULT: SelfProfileEvents = SelfProfileEvents(
+        Self::GENERIC_ACTIVITIES.0
+            | Self::QUERY_PROVIDERS.0
+            | Self::QUERY_BLOCKED.0
+            | Self::INCR_CACHE_LOADS.0
+            | Self::INCR_RESULT_HASHING.0
+            | Self::ARTIFACT_SIZES.0,
+    );
+
+    const NAMES: &'static [(&'static str, SelfProfileEvents)] = &[
+        ("none", Self::NONE),
+        ("all", Self::ALL),
+        ("default", Self::DEFAULT),
+        ("generic-activity", Self::GENERIC_ACTIVITIES),
+        ("query-provider", Self::QUERY_PROVIDERS),
+        ("query-cache-hit", Self::QUERY_CACHE_HITS),
+        ("query-blocked", Self::QUERY_BLOCKED),
+        ("incr-cache-load", Self::INCR_CACHE_LOADS),
+        ("incr-result-hashing", Self::INCR_RESULT_HASHING),
+        ("query-keys", Self::QUERY_KEYS),
+        ("function-args", Self::FUNCTION_ARGS),
+        ("args", Self::ARGS),
+        ("llvm", Self::LLVM),
+        ("artifact-sizes", Self::ARTIFACT_SIZES),
+    ];
+
+    /// Parses a comma-separated list of category names, like
+    /// `default,query-keys`.
+    pub fn parse(spec: &str) -> Result<SelfProfileEvents, String> {
+        let mut events = Self::NONE;
+
+        for name in spec.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+            match Self::NAMES.iter().find(|&&(n, _)| n == name) {
+                Some(&(_, category)) => events.0 |= category.0,
+                None => {
+                    let names: Vec<_> = Self::NAMES.iter().map(|&(n, _)| n).collect();
+                    return Err(format!(
+                        "unknown self-profile event category `{}`, expected one of: {}",
+                        name,
+                        names.join(", ")
+                    ));
+                }
+            }
+        }
+
+        Ok(events)
+    }
+
+    pub fn contains(self, other: SelfProfileEvents) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+/// Returns the category an event of the given kind belongs to and the
+/// category that controls whether its arguments are recorded, or `None` for
+/// events that rustc records regardless of `-Z self-profile-events`, like
+/// the profiler's own markers.
+fn categories(event_kind: &str) -> Option<(SelfProfileEvents, SelfProfileEvents)> {
+    let no_args = SelfProfileEvents::NONE;
+
+    Some(match event_kind {
+        GENERIC_ACTIVITY_EVENT_KIND => (
+            SelfProfileEvents::GENERIC_ACTIVITIES,
+            SelfProfileEvents::FUNCTION_ARGS,
+        ),
+        QUERY_EVENT_KIND => (
+            SelfProfileEvents::QUERY_PROVIDERS,
+            SelfProfileEvents::QUERY_KEYS,
+        ),
+        QUERY_CACHE_HIT_EVENT_KIND => (
+            SelfProfileEvents::QUERY_CACHE_HITS,
+            SelfProfileEvents::QUERY_KEYS,
+        ),
+        QUERY_BLOCKED_EVENT_KIND => (
+            SelfProfileEvents::QUERY_BLOCKED,
+            SelfProfileEvents::QUERY_KEYS,
+        ),
+        INCREMENTAL_LOAD_RESULT_EVENT_KIND => (
+            SelfProfileEvents::INCR_CACHE_LOADS,
+            SelfProfileEvents::QUERY_KEYS,
+        ),
+        INCREMENTAL_RESULT_HASHING_EVENT_KIND => (SelfProfileEvents::INCR_RESULT_HASHING, no_args),
+        LLVM_PASS_EVENT_KIND => (SelfProfileEvents::LLVM, no_args),
+        ARTIFACT_SIZE_EVENT_KIND => (SelfProfileEvents::ARTIFACT_SIZES, no_args),
+        _ => return None,
+    })
+}
+
+/// Returns a copy of `data` with the events that rustc wouldn't have
+/// recorded with `-Z self-profile-events` set to `events`: events of
+/// disabled categories are removed and arguments are stripped from labels if
+/// their category is disabled. Events that don't belong to any category are
+/// kept. The result can be analyzed like any other profile, e.g. the
+/// self-time of a query then includes the time of its removed children,
+/// just like in a profile that has been recorded with the narrower setting.
+pub fn filter_self_profile_events(
+    data: &ProfilingData,
+    events: SelfProfileEvents,
+) -> ProfilingData {
+    let mut builder = ProfilingDataBuilder::with_timestamp_format(data.timestamp_format());
+
+    // The rewritten event kind and id for each (event kind, event id) pair,
+    // or `None` if events with this kind aren't recorded.
+    let mut rewritten = FxHashMap::<(StringId, StringId), Option<(StringId, EventId)>>::default();
+
+    for index in 0..data.num_events() {
+        let raw_event = data.raw_event(index);
+        let key = (raw_event.event_kind, raw_event.event_id.to_string_id());
+
+        let ids = *rewritten.entry(key).or_insert_with(|| {
+            let event_kind = data.event_kind_str(key.0);
+            let (label, args) = Event::parse_event_id(data.string_table().get(key.1).to_string());
+
+            let args: Vec<&str> = match categories(&event_kind) {
+                Some((category, _)) if !events.contains(category) => return None,
+                Some((_, args_category)) if !events.contains(args_category) => Vec::new(),
+                _ => args.iter().map(|arg| &arg[..]).collect(),
+            };
+
+            Some((
+                builder.alloc_string(&event_kind),
+                builder.alloc_event_id(&label, &args),
+            ))
+        });
+
+        if let Some((event_kind, event_id)) = ids {
+            let mut raw_event = raw_event;
+            raw_event.event_kind = event_kind;
+            raw_event.event_id = event_id;
+            builder.write_raw_event(&raw_event);
+        }
+    }
+
+    let mut metadata = data.metadata.clone();
+    // All strings have been copied into the new profile.
+    metadata.shared_strings = None;
+
+    builder.into_profiling_data_with_metadata(metadata)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        assert_eq!(
+            SelfProfileEvents::parse("default").unwrap(),
+            SelfProfileEvents::DEFAULT
+        );
+
+        let events = SelfProfileEvents::parse("query-provider, args").unwrap();
+        assert!(events.contains(SelfProfileEvents::QUERY_PROVIDERS));
+        assert!(events.contains(SelfProfileEvents::QUERY_KEYS));
+        assert!(events.contains(SelfProfileEvents::FUNCTION_ARGS));
+        assert!(!events.contains(SelfProfileEvents::GENERIC_ACTIVITIES));
+
+        assert!(SelfProfileEvents::parse("all")
+            .unwrap()
+            .contains(SelfProfileEvents::LLVM));
+        assert!(SelfProfileEvents::parse("query-provider,bogus")
+            .unwrap_err()
+            .contains("`bogus`"));
+    }
+
+    #[test]
+    fn filtering() {
+        use measureme::event_kinds::THREAD_REGISTRATION_EVENT_KIND;
+
+        let mut b = ProfilingDataBuilder::new();
+
+        b.instant(THREAD_REGISTRATION_EVENT_KIND, "main", 0, 0);
+        b.interval(QUERY_EVENT_KIND, "typeck\u{1e}foo", 0, 10, 100, |b| {
+            b.instant(QUERY_CACHE_HIT_EVENT_KIND, "type_of\u{1e}foo", 0, 20);
+            b.interval(GENERIC_ACTIVITY_EVENT_KIND, "LLVM_passes", 0, 30, 40, |b| {
+                b.interval(LLVM_PASS_EVENT_KIND, "InstCombine", 0, 31, 32, |_| {});
+            });
+        });
+
+        let data = b.into_profiling_data();
+
+        let labels = |events: SelfProfileEvents| {
+            filter_self_profile_events(&data, events)
+                .iter()
+                .map(|e| {
+                    let e = e.to_event();
+                    let mut label = e.label.into_owned();
+                    for arg in e.additional_data {
+                        label.push_str(&format!("({})", arg));
+                    }
+                    label
+                })
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            labels(SelfProfileEvents::DEFAULT),
+            vec!["main", "LLVM_passes", "typeck"]
+        );
+        assert_eq!(
+            labels(SelfProfileEvents::parse("query-provider,query-keys,query-cache-hit").unwrap()),
+            vec!["main", "type_of(foo)", "typeck(foo)"]
+        );
+        assert_eq!(
+            labels(SelfProfileEvents::ALL),
+            vec![
+                "main",
+                "type_of(foo)",
+                "InstCombine",
+                "LLVM_passes",
+                "typeck(foo)"
+            ]
+        );
+    }
+}
@@ -0,0 +1,108 @@
+use crate::ProfilingData;
+use measureme::event_kinds::SESSION_MARKER_EVENT_KIND;
+use std::time::{Duration, SystemTime};
+
+/// A segment of the session, from a marker recorded via
+/// `measureme::Profiler::record_session_marker()` to the next one.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SessionSegment {
+    pub name: String,
+    pub start: SystemTime,
+    pub end: SystemTime,
+}
+
+impl SessionSegment {
+    pub fn duration(&self) -> Duration {
+        self.end.duration_since(self.start).unwrap()
+    }
+
+    pub fn contains(&self, t: SystemTime) -> bool {
+        t >= self.start && t < self.end
+    }
+}
+
+/// Returns the segments of the session recorded in the profile, ordered by
+/// start time. The last segment ends with the last event of the profile.
+/// Whatever happened before the first marker isn't part of any segment.
+pub fn find_session_segments(profiling_data: &ProfilingData) -> Vec<SessionSegment> {
+    let mut segments = Vec::<SessionSegment>::new();
+    let mut last_timestamp = None;
+
+    for event in profiling_data.iter() {
+        last_timestamp = last_timestamp.max(Some(event.timestamp.end()));
+
+        if !event.timestamp.is_instant() {
+            continue;
+        }
+
+        let event = event.to_event();
+        if event.event_kind == SESSION_MARKER_EVENT_KIND {
+            let t = event.timestamp.start();
+            if let Some(previous) = segments.last_mut() {
+                previous.end = t;
+            }
+            segments.push(SessionSegment {
+                name: event.label.into_owned(),
+                start: t,
+                end: t,
+            });
+        }
+    }
+
+    if let Some(last) = segments.last_mut() {
+        last.end = last_timestamp.unwrap();
+    }
+
+    segments
+}
+
+/// Returns the index of the segment in `segments` (as returned by
+/// `find_session_segments()`) that contains `t`.
+pub fn session_segment(segments: &[SessionSegment], t: SystemTime) -> Option<usize> {
+    let index = segments
+        .partition_point(|segment| segment.start <= t)
+        .checked_sub(1)?;
+    Some(index).filter(|&index| segments[index].contains(t))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ProfilingDataBuilder;
+    use std::time::UNIX_EPOCH;
+
+    fn time(nanos: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_nanos(nanos)
+    }
+
+    #[test]
+    fn segments() {
+        let mut b = ProfilingDataBuilder::new();
+
+        b.interval("Query", "setup", 0, 0, 5, |_| {});
+        b.instant(SESSION_MARKER_EVENT_KIND, "expansion", 0, 10);
+        b.interval("Query", "q1", 0, 10, 20, |_| {});
+        b.instant(SESSION_MARKER_EVENT_KIND, "codegen", 1, 30);
+        b.interval("Query", "q2", 0, 70, 90, |_| {});
+
+        let segments = find_session_segments(&b.into_profiling_data());
+
+        let segment = |name: &str, start, end| SessionSegment {
+            name: name.to_string(),
+            start: time(start),
+            end: time(end),
+        };
+
+        assert_eq!(
+            segments,
+            vec![segment("expansion", 10, 30), segment("codegen", 30, 90)]
+        );
+        assert_eq!(segments[1].duration(), Duration::from_nanos(60));
+
+        assert_eq!(session_segment(&segments, time(0)), None);
+        assert_eq!(session_segment(&segments, time(10)), Some(0));
+        assert_eq!(session_segment(&segments, time(30)), Some(1));
+        assert_eq!(session_segment(&segments, time(89)), Some(1));
+        assert_eq!(session_segment(&segments, time(90)), None);
+    }
+}
@@ -0,0 +1,272 @@
+//! Analysis relies on the events being ordered by the time they end (for
+//! instant events, the time they happened), which is the order in which a
+//! profiler writes them as long as every thread writes its own events in
+//! order. Sinks that buffer events or write them from several threads may
+//! reorder them, though, so `ProfilingData` checks the order when loading a
+//! profile and sorts the events if necessary. A stable sort keeps the order
+//! of events that ended at the same time, e.g. of a child and its parent.
+//!
+//! Sorting on every load costs time and needs the events twice in memory, so
+//! `sort_profile()` sorts the events files of a profile once and for all,
+//! via an external merge sort that only holds a bounded number of events in
+//! memory at a time.
+
+use measureme::checksum::Checksum;
+use measureme::file_header::{
+    file_footer, read_full_file_header, FILE_FOOTER_SIZE, FILE_HEADER_SIZE, FILE_MAGIC_FOOTER,
+};
+use measureme::{RawEvent, TimestampFormat};
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+/// How many events `sort_profile()` holds in memory by default, which for
+/// wide events amounts to 256 MiB.
+pub const DEFAULT_MAX_EVENTS_IN_MEMORY: usize = 8 << 20;
+
+/// The time by which events are ordered.
+fn sort_key(format: TimestampFormat, event: &[u8]) -> u64 {
+    let raw_event = RawEvent::deserialize_as(format, event);
+    if raw_event.is_instant() {
+        raw_event.start_nanos()
+    } else {
+        raw_event.end_nanos()
+    }
+}
+
+fn is_sorted(format: TimestampFormat, events: &[u8]) -> bool {
+    let keys = events
+        .chunks_exact(format.event_size())
+        .map(|event| sort_key(format, event));
+    keys.clone().zip(keys.skip(1)).all(|(a, b)| a <= b)
+}
+
+/// Sorts the events, given without header and footer, in place. Returns
+/// `false` if they were already sorted.
+pub(crate) fn sort_events(format: TimestampFormat, events: &mut [u8]) -> bool {
+    if is_sorted(format, events) {
+        return false;
+    }
+
+    let event_size = format.event_size();
+    let mut sorted: Vec<(u64, &[u8])> = events
+        .chunks_exact(event_size)
+        .map(|event| (sort_key(format, event), event))
+        .collect();
+    sorted.sort_by_key(|&(key, _)| key);
+
+    let sorted: Vec<u8> = sorted
+        .into_iter()
+        .flat_map(|(_, event)| event.iter().copied())
+        .collect();
+    events.copy_from_slice(&sorted);
+    true
+}
+
+/// Sorts the events files of the profile with the given path stem, i.e. its
+/// `.events` file and the files of its threads or segments, if any, holding
+/// at most `max_events_in_memory` events in memory at a time. Returns the
+/// files that had to be sorted.
+///
+/// The files must be complete, i.e. have their footer, which is updated.
+pub fn sort_profile(
+    path_stem: &Path,
+    max_events_in_memory: usize,
+) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let mut sorted = Vec::new();
+    for path in events_files(path_stem)? {
+        if sort_events_file(&path, max_events_in_memory)? {
+            sorted.push(path);
+        }
+    }
+    Ok(sorted)
+}
+
+/// The `.events` file of the profile, followed by the `.<n>.thread_events`
+/// and `.<n>.segment_events` files next to it.
+fn events_files(path_stem: &Path) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let events_file = path_stem.with_extension("events");
+    let stem = path_stem
+        .file_name()
+        .ok_or("invalid path stem")?
+        .to_string_lossy();
+    let dir = match path_stem.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let name = match path.file_name() {
+            Some(name) => name.to_string_lossy().into_owned(),
+            None => continue,
+        };
+        let index = name
+            .strip_prefix(&*stem)
+            .and_then(|rest| rest.strip_prefix('.'))
+            .and_then(|rest| {
+                rest.strip_suffix(".thread_events")
+                    .or_else(|| rest.strip_suffix(".segment_events"))
+            });
+        if let Some(index) = index.and_then(|index| index.parse::<usize>().ok()) {
+            files.push((index, path));
+        }
+    }
+    files.sort();
+
+    let mut paths = vec![events_file];
+    paths.extend(files.into_iter().map(|(_, path)| path));
+    Ok(paths)
+}
+
+/// Sorts a single events file, see `sort_profile()`. Returns `false` if its
+/// events were already sorted, in which case it is left alone.
+pub fn sort_events_file(path: &Path, max_events_in_memory: usize) -> Result<bool, Box<dyn Error>> {
+    let error = |message: &str| format!("`{}`: {}", path.display(), message);
+
+    let len = fs::metadata(path)?.len() as usize;
+    if len < FILE_HEADER_SIZE + FILE_FOOTER_SIZE {
+        Err(error("the file is too short to be an events file"))?;
+    }
+
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut header = [0; FILE_HEADER_SIZE];
+    reader.read_exact(&mut header)?;
+    let file_magic = TimestampFormat::from_file_magic(&header[..4])
+        .ok_or_else(|| error("the file is not an events file"))?
+        .file_magic();
+    let feature_flags = read_full_file_header(&header, file_magic)?.feature_flags;
+    let format = TimestampFormat::from_file_header(file_magic, feature_flags).unwrap();
+    let event_size = format.event_size();
+
+    let events_len = len - FILE_HEADER_SIZE - FILE_FOOTER_SIZE;
+    if events_len % event_size != 0 {
+        Err(error("the file ends with a partial event"))?;
+    }
+    let chunk_len = max_events_in_memory.max(1) * event_size;
+
+    // The first pass checks the footer and whether sorting is needed at all.
+    let mut checksum = Checksum::new();
+    checksum.update(&header);
+    let mut needs_sorting = false;
+    let mut last_key = 0;
+    let mut chunk = Vec::new();
+    let mut remaining = events_len;
+    while remaining > 0 {
+        read_chunk(&mut reader, &mut chunk, remaining.min(chunk_len))?;
+        remaining -= chunk.len();
+        checksum.update(&chunk);
+
+        for event in chunk.chunks_exact(event_size) {
+            let key = sort_key(format, event);
+            needs_sorting |= key < last_key;
+            last_key = key;
+        }
+    }
+    let mut footer = [0; FILE_FOOTER_SIZE];
+    reader.read_exact(&mut footer)?;
+    if footer != file_footer(checksum.finish()) {
+        let message = if &footer[..4] == FILE_MAGIC_FOOTER {
+            "the checksum does not match the file contents"
+        } else {
+            "the file footer is missing, the file is probably incomplete"
+        };
+        Err(error(message))?;
+    }
+
+    if !needs_sorting {
+        return Ok(false);
+    }
+
+    // The second pass writes sorted runs of up to `chunk_len` bytes, which
+    // are then merged. A single run is written directly.
+    let mut reader = BufReader::new(File::open(path)?);
+    reader.read_exact(&mut header)?;
+    let mut runs = Vec::new();
+    let mut remaining = events_len;
+    while remaining > 0 {
+        read_chunk(&mut reader, &mut chunk, remaining.min(chunk_len))?;
+        remaining -= chunk.len();
+        sort_events(format, &mut chunk);
+
+        if events_len <= chunk_len {
+            break;
+        }
+        let run_path = path.with_extension(format!("sort{}", runs.len()));
+        fs::write(&run_path, &chunk)?;
+        runs.push(run_path);
+    }
+
+    let sorted_path = path.with_extension("sorted");
+    let result = write_sorted(&sorted_path, &header, format, &runs, &chunk);
+    for run in &runs {
+        let _ = fs::remove_file(run);
+    }
+    if let Err(e) = result {
+        let _ = fs::remove_file(&sorted_path);
+        return Err(e);
+    }
+
+    fs::rename(&sorted_path, path)?;
+    Ok(true)
+}
+
+fn read_chunk(reader: &mut impl Read, chunk: &mut Vec<u8>, len: usize) -> std::io::Result<()> {
+    chunk.resize(len, 0);
+    reader.read_exact(chunk)
+}
+
+/// Writes the events of `runs`, or `events` if there are no runs, to `path`.
+fn write_sorted(
+    path: &Path,
+    header: &[u8],
+    format: TimestampFormat,
+    runs: &[PathBuf],
+    events: &[u8],
+) -> Result<(), Box<dyn Error>> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    let mut checksum = Checksum::new();
+    let mut write = |bytes: &[u8]| {
+        checksum.update(bytes);
+        writer.write_all(bytes)
+    };
+    write(header)?;
+
+    if runs.is_empty() {
+        write(events)?;
+    } else {
+        let event_size = format.event_size();
+        let mut readers = runs
+            .iter()
+            .map(|run| Ok(BufReader::new(File::open(run)?)))
+            .collect::<Result<Vec<_>, Box<dyn Error>>>()?;
+        let mut next = vec![vec![0; event_size]; runs.len()];
+
+        // The next event of every run, keyed by its time and the index of
+        // the run, so that ties keep the order of the runs, which keeps the
+        // sort stable.
+        let mut heap = BinaryHeap::new();
+        for (index, reader) in readers.iter_mut().enumerate() {
+            reader.read_exact(&mut next[index])?;
+            heap.push(Reverse((sort_key(format, &next[index]), index)));
+        }
+
+        while let Some(Reverse((_, index))) = heap.pop() {
+            write(&next[index])?;
+
+            match readers[index].read_exact(&mut next[index]) {
+                Ok(()) => heap.push(Reverse((sort_key(format, &next[index]), index))),
+                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => {}
+                Err(e) => Err(e)?,
+            }
+        }
+    }
+
+    writer.write_all(&file_footer(checksum.finish()))?;
+    writer.flush()?;
+    Ok(())
+}
@@ -17,7 +17,7 @@ struct PerThreadState<'a> {
 /// Uses a variation of the algorithm in `summarize`.
 // Original implementation provided by @andjo403 in
 // https://github.com/michaelwoerister/measureme/pull/1
-pub fn collapse_stacks<'a>(profiling_data: &ProfilingData) -> FxHashMap<String, u64> {
+pub fn collapse_stacks(profiling_data: &ProfilingData) -> FxHashMap<String, u64> {
     let mut counters = FxHashMap::default();
     let mut threads = FxHashMap::<_, PerThreadState<'_>>::default();
 
@@ -0,0 +1,127 @@
+use crate::{ProfilingData, Timestamp};
+use rustc_hash::FxHashMap;
+use std::time::{Duration, SystemTime};
+
+/// A stretch of time during which a thread did not record any events.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Stall {
+    pub thread_id: u32,
+    pub start: SystemTime,
+    pub end: SystemTime,
+}
+
+impl Stall {
+    pub fn duration(&self) -> Duration {
+        self.end.duration_since(self.start).unwrap()
+    }
+}
+
+/// Finds all stretches longer than `threshold` during which a thread did not
+/// record anything, i.e. neither started nor finished an interval event nor
+/// recorded an instant event (like a heartbeat, see
+/// `measureme::Profiler::record_heartbeat()`). Such stretches can indicate a
+/// deadlock or a thread waiting on something external.
+///
+/// Only the time between the first and the last event of a thread is
+/// considered. The result is ordered by thread id and then by start time.
+pub fn find_stalls(profiling_data: &ProfilingData, threshold: Duration) -> Vec<Stall> {
+    let mut timestamps_per_thread = FxHashMap::<u32, Vec<SystemTime>>::default();
+
+    for event in profiling_data.iter() {
+        let timestamps = timestamps_per_thread.entry(event.thread_id).or_default();
+
+        match event.timestamp {
+            Timestamp::Interval { start, end } => {
+                timestamps.push(start);
+                timestamps.push(end);
+            }
+            Timestamp::Instant(t) => timestamps.push(t),
+        }
+    }
+
+    let mut thread_ids: Vec<u32> = timestamps_per_thread.keys().cloned().collect();
+    thread_ids.sort_unstable();
+
+    let mut stalls = Vec::new();
+
+    for thread_id in thread_ids {
+        let timestamps = timestamps_per_thread.get_mut(&thread_id).unwrap();
+        timestamps.sort_unstable();
+
+        for window in timestamps.windows(2) {
+            let (start, end) = (window[0], window[1]);
+
+            if end.duration_since(start).unwrap() > threshold {
+                stalls.push(Stall {
+                    thread_id,
+                    start,
+                    end,
+                });
+            }
+        }
+    }
+
+    stalls
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ProfilingDataBuilder;
+    use measureme::event_kinds::HEARTBEAT_EVENT_KIND;
+    use std::time::UNIX_EPOCH;
+
+    fn stall(thread_id: u32, start_nanos: u64, end_nanos: u64) -> Stall {
+        Stall {
+            thread_id,
+            start: UNIX_EPOCH + Duration::from_nanos(start_nanos),
+            end: UNIX_EPOCH + Duration::from_nanos(end_nanos),
+        }
+    }
+
+    #[test]
+    fn gaps_between_events() {
+        //            hb                  hb
+        //  <--e1-->  |     <----e2---->  |             <--e3-->
+        //  0     10  15    100      200  205           500   510
+        let mut b = ProfilingDataBuilder::new();
+
+        b.interval("Query", "e1", 0, 0, 10, |_| {});
+        b.instant(HEARTBEAT_EVENT_KIND, HEARTBEAT_EVENT_KIND, 0, 15);
+        b.interval("Query", "e2", 0, 100, 200, |_| {});
+        b.instant(HEARTBEAT_EVENT_KIND, HEARTBEAT_EVENT_KIND, 0, 205);
+        b.interval("Query", "e3", 0, 500, 510, |_| {});
+
+        // Thread 1 only takes part in the beginning.
+        b.interval("Query", "e1", 1, 0, 40, |b| {
+            b.instant(HEARTBEAT_EVENT_KIND, HEARTBEAT_EVENT_KIND, 1, 20);
+        });
+
+        let data = b.into_profiling_data();
+
+        // Being inside of an interval event without recording anything else
+        // counts as a stall too, since that's what a deadlock looks like.
+        assert_eq!(
+            find_stalls(&data, Duration::from_nanos(50)),
+            vec![stall(0, 15, 100), stall(0, 100, 200), stall(0, 205, 500)]
+        );
+
+        assert_eq!(
+            find_stalls(&data, Duration::from_nanos(100)),
+            vec![stall(0, 205, 500)]
+        );
+
+        assert_eq!(
+            find_stalls(&data, Duration::from_nanos(10)),
+            vec![
+                stall(0, 15, 100),
+                stall(0, 100, 200),
+                stall(0, 205, 500),
+                stall(1, 0, 20),
+                stall(1, 20, 40),
+            ]
+        );
+
+        assert!(find_stalls(&data, Duration::from_nanos(1000)).is_empty());
+    }
+}
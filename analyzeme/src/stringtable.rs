@@ -1,24 +1,81 @@
 //! See module-level documentation `measureme::stringtable`.
 
-use byteorder::{BigEndian, ByteOrder, LittleEndian};
+use crate::{LoadError, LoadErrorKind};
+use byteorder::{BigEndian, ByteOrder};
 use measureme::file_header::{
-    read_file_header, strip_file_header, CURRENT_FILE_FORMAT_VERSION, FILE_MAGIC_STRINGTABLE_DATA,
-    FILE_MAGIC_STRINGTABLE_INDEX,
+    read_full_file_header, strip_file_header, CURRENT_FILE_FORMAT_VERSION, FILE_HEADER_SIZE,
+    FILE_MAGIC_STRINGTABLE_DATA, FILE_MAGIC_STRINGTABLE_INDEX,
+};
+use measureme::stringtable::{
+    read_index_block, read_string_addr, read_virtual_mapping, FIRST_REGULAR_STRING_ID,
+    FIRST_RESERVED_STRING_ID, FIRST_SHARED_STRING_ID, INDEX_BLOCK_HEADER_SIZE, MAX_STRING_ID,
+    METADATA_STRING_ID, STRINGS_BLOCK, STRING_ID_MASK, TERMINATOR, VIRTUAL_MAPPING_SIZE,
 };
-use measureme::stringtable::{METADATA_STRING_ID, STRING_ID_MASK, TERMINATOR};
 use measureme::{Addr, StringId};
 use memchr::memchr;
 use rustc_hash::FxHashMap;
 use std::borrow::Cow;
 use std::error::Error;
+use std::fmt;
+use std::ops::Range;
+use std::sync::{Arc, OnceLock};
+
+/// Resolves the strings of a range of `StringId`s that the profiler reserved
+/// via `measureme::Profiler::reserve_string_ids()`, given their index within
+/// the range. See `ProfilingData::register_string_resolver()`.
+pub trait StringResolver: Send + Sync {
+    fn resolve(&self, index: u32) -> Option<Cow<'_, str>>;
+}
+
+impl<F> StringResolver for F
+where
+    F: Fn(u32) -> Option<Cow<'static, str>> + Send + Sync,
+{
+    fn resolve(&self, index: u32) -> Option<Cow<'_, str>> {
+        self(index)
+    }
+}
+
+struct ReservedRange {
+    first: u32,
+    len: u32,
+    resolver: Box<dyn StringResolver>,
+}
 
-fn deserialize_index_entry(bytes: &[u8]) -> (StringId, Addr) {
-    (
-        StringId::new(LittleEndian::read_u32(&bytes[0..4])),
-        Addr(LittleEndian::read_u32(&bytes[4..8])),
-    )
+impl fmt::Debug for ReservedRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "ReservedRange({}..{})",
+            self.first,
+            self.first + self.len
+        )
+    }
+}
+
+// A string that `StringRef::resolve()` found.
+enum Resolved<'st> {
+    // The data of the table containing the string and the position of the
+    // string in it.
+    Data(&'st [u8], usize),
+    // A string of a reserved range.
+    Text(Cow<'st, str>),
+}
+
+// A strings block of the index, see `measureme::stringtable`.
+#[derive(Clone, Copy, Debug)]
+struct StringsBlock {
+    first_id: u32,
+    len: u32,
+    // The position of the block's entries in the index.
+    start: usize,
 }
 
+// The loaded table merges its blocks of virtual mappings into one. A table
+// that is extended while the profile is recorded merges them once it has
+// more than this many.
+const MAX_VIRTUAL_BLOCKS: usize = 8;
+
 #[derive(Copy, Clone)]
 pub struct StringRef<'st> {
     id: StringId,
@@ -29,14 +86,36 @@ pub struct StringRef<'st> {
 // be resolved.
 const UNKNOWN_STRING: &str = "<unknown>";
 
+// Strings nest via string id components, e.g. an event id refers to its
+// label and arguments. Legitimate profiles only nest a few levels deep, the
+// limit guards against cycles in corrupt ones.
+const MAX_NESTING_DEPTH: usize = 64;
+
+const STRING_ID_SIZE: usize = std::mem::size_of::<StringId>();
+
+/// The maximum length of a string in a profile loaded via
+/// `ProfilingData::from_bytes_strict()`. Strings are made of components that
+/// can be shared, so corrupt string tables can contain strings that expand to
+/// far more data than the table itself.
+pub const MAX_STRING_LEN: usize = 16 * 1024 * 1024;
+
 impl<'st> StringRef<'st> {
     /// Expands the StringRef into an actual string. This method will
     /// avoid allocating a `String` if it can instead return a `&str` pointing
     /// into the raw string table data.
     pub fn to_string(&self) -> Cow<'st, str> {
-        let addr = match self.get_addr() {
-            Ok(addr) => addr,
-            Err(_) => return Cow::from(UNKNOWN_STRING),
+        self.expand_at_depth(0)
+    }
+
+    fn expand_at_depth(&self, depth: usize) -> Cow<'st, str> {
+        if depth > MAX_NESTING_DEPTH {
+            return Cow::from(UNKNOWN_STRING);
+        }
+
+        let (string_data, pos) = match self.resolve() {
+            Some(Resolved::Data(string_data, pos)) => (string_data, pos),
+            Some(Resolved::Text(text)) => return text,
+            None => return Cow::from(UNKNOWN_STRING),
         };
 
         // Try to avoid the allocation, which we can do if this is
@@ -44,85 +123,200 @@ impl<'st> StringRef<'st> {
         //  - a string with a single value component (`[value, 0xFF]`) or
         //  - a string with a single reference component (`[string_id, 0xFF]`)
 
-        let pos = addr.as_usize();
-        let slice_to_search = &self.table.string_data[pos..];
+        let slice_to_search = &string_data[pos..];
 
         // Find the first 0xFF byte which which is either the sequence
         // terminator or a byte in the middle of string id. Use `memchr` which
-        // is super fast.
-        let terminator_pos = memchr(TERMINATOR, slice_to_search).unwrap();
-
-        // Check if this is a string containing a single StringId component
-        let first_byte = self.table.string_data[pos];
-        const STRING_ID_SIZE: usize = std::mem::size_of::<StringId>();
-        if terminator_pos == pos + STRING_ID_SIZE && is_utf8_continuation_byte(first_byte) {
-            let id = decode_string_id_from_data(&self.table.string_data[pos..pos + STRING_ID_SIZE]);
-            return StringRef {
-                id,
-                table: self.table,
+        // is super fast. Corrupt data might lack the terminator, which the
+        // slow path deals with.
+        if let Some(terminator_pos) = memchr(TERMINATOR, slice_to_search) {
+            // Check if this is a string containing a single StringId component
+            let first_byte = slice_to_search[0];
+            if terminator_pos == STRING_ID_SIZE && is_utf8_continuation_byte(first_byte) {
+                let id = decode_string_id_from_data(&slice_to_search[..STRING_ID_SIZE]);
+                return StringRef {
+                    id,
+                    table: self.table,
+                }
+                .expand_at_depth(depth + 1);
             }
-            .to_string();
-        }
 
-        // Decode the bytes until the terminator. If there is a string id in
-        // between somewhere this will fail, and we fall back to the allocating
-        // path.
-        if let Ok(s) = std::str::from_utf8(&slice_to_search[..terminator_pos]) {
-            Cow::from(s)
-        } else {
-            // This is the slow path where we actually allocate a `String` on
-            // the heap and expand into that. If you suspect that there is a
-            // bug in the fast path above, you can easily check if always taking
-            // the slow path fixes the issue.
-            let mut output = String::new();
-            self.write_to_string(&mut output);
-            Cow::from(output)
+            // Decode the bytes until the terminator. If there is a string id
+            // in between somewhere this will fail, and we fall back to the
+            // allocating path.
+            if let Ok(s) = std::str::from_utf8(&slice_to_search[..terminator_pos]) {
+                return Cow::from(s);
+            }
         }
+
+        // This is the slow path where we actually allocate a `String` on
+        // the heap and expand into that. If you suspect that there is a
+        // bug in the fast path above, you can easily check if always taking
+        // the slow path fixes the issue.
+        let mut output = String::new();
+        self.write_at_depth(&mut output, depth);
+        Cow::from(output)
     }
 
     pub fn write_to_string(&self, output: &mut String) {
-        let addr = match self.get_addr() {
-            Ok(addr) => addr,
-            Err(_) => {
+        self.write_at_depth(output, 0)
+    }
+
+    // Never panics, even for corrupt data: ids that can't be resolved are
+    // written as `<unknown>`, invalid UTF-8 as U+FFFD, and the string ends
+    // with the data if the terminator is missing.
+    fn write_at_depth(&self, output: &mut String, depth: usize) {
+        if depth > MAX_NESTING_DEPTH {
+            output.push_str(UNKNOWN_STRING);
+            return;
+        }
+
+        let (string_data, mut pos) = match self.resolve() {
+            Some(Resolved::Data(string_data, pos)) => (string_data, pos),
+            Some(Resolved::Text(text)) => {
+                output.push_str(&text);
+                return;
+            }
+            None => {
                 output.push_str(UNKNOWN_STRING);
                 return;
             }
         };
 
-        let mut pos = addr.as_usize();
-
-        loop {
-            let byte = self.table.string_data[pos];
-
+        while let Some(&byte) = string_data.get(pos) {
             if byte == TERMINATOR {
                 return;
             } else if is_utf8_continuation_byte(byte) {
+                let id = match string_data.get(pos..pos + STRING_ID_SIZE) {
+                    Some(bytes) => decode_string_id_from_data(bytes),
+                    None => return,
+                };
+
                 let string_ref = StringRef {
-                    id: decode_string_id_from_data(&self.table.string_data[pos..pos + 4]),
+                    id,
                     table: self.table,
                 };
 
-                string_ref.write_to_string(output);
+                string_ref.write_at_depth(output, depth + 1);
 
-                pos += 4;
+                pos += STRING_ID_SIZE;
+            } else if let Some((c, len)) = decode_utf8_char(&string_data[pos..]) {
+                output.push(c);
+                pos += len;
             } else {
-                while let Some((c, len)) = decode_utf8_char(&self.table.string_data[pos..]) {
-                    output.push(c);
-                    pos += len;
+                output.push(char::REPLACEMENT_CHARACTER);
+                pos += 1;
+            }
+        }
+    }
+
+    /// Like `to_string()`, but returns an error instead of substituting
+    /// `<unknown>` and U+FFFD if the string can't be decoded, e.g. because
+    /// the profile is corrupt, or if it would expand to more than
+    /// `MAX_STRING_LEN` bytes. Ids in ranges reserved via
+    /// `measureme::Profiler::reserve_string_ids()` count as valid even without
+    /// a resolver. Returns the (approximate) length of the string. `checked`
+    /// caches the lengths of the strings checked so far, so that checking
+    /// many strings with common components takes linear time.
+    pub(crate) fn check(&self, checked: &mut FxHashMap<StringId, usize>) -> Result<usize, String> {
+        self.check_at_depth(checked, 0)
+    }
+
+    fn check_at_depth(
+        &self,
+        checked: &mut FxHashMap<StringId, usize>,
+        depth: usize,
+    ) -> Result<usize, String> {
+        let id = self.id.as_u32();
+
+        if let Some(&len) = checked.get(&self.id) {
+            return Ok(len);
+        }
+
+        if depth > MAX_NESTING_DEPTH {
+            return Err(format!(
+                "string {} is nested too deeply, its components might form a cycle",
+                id
+            ));
+        }
+
+        let (string_data, mut pos) = match self.resolve() {
+            Some(Resolved::Data(string_data, pos)) => (string_data, pos),
+            Some(Resolved::Text(text)) => return Ok(text.len()),
+            None if self.table.is_reserved(self.resolved_id()) => return Ok(UNKNOWN_STRING.len()),
+            None => return Err(format!("string {} is missing", id)),
+        };
+
+        let mut len = 0;
+
+        loop {
+            match string_data.get(pos) {
+                None => return Err(format!("string {} lacks its terminator", id)),
+                Some(&TERMINATOR) => break,
+                Some(&byte) if is_utf8_continuation_byte(byte) => {
+                    let component = match string_data.get(pos..pos + STRING_ID_SIZE) {
+                        Some(bytes) => decode_string_id_from_data(bytes),
+                        None => return Err(format!("string {} lacks its terminator", id)),
+                    };
+
+                    len += StringRef {
+                        id: component,
+                        table: self.table,
+                    }
+                    .check_at_depth(checked, depth + 1)?;
+
+                    pos += STRING_ID_SIZE;
                 }
+                Some(_) => match decode_utf8_char(&string_data[pos..]) {
+                    Some((_, char_len)) => {
+                        len += char_len;
+                        pos += char_len;
+                    }
+                    None => return Err(format!("string {} is not valid UTF-8", id)),
+                },
+            }
+
+            if len > MAX_STRING_LEN {
+                return Err(format!(
+                    "string {} is longer than {} bytes",
+                    id, MAX_STRING_LEN
+                ));
             }
         }
+
+        checked.insert(self.id, len);
+        Ok(len)
     }
 
-    fn get_addr(&self) -> Result<Addr, ()> {
+    // The id `self.id` refers to after resolving virtual ids, or `self.id`
+    // if it can't be resolved.
+    fn resolved_id(&self) -> StringId {
         if self.id.is_virtual() {
-            match self.table.index.get(&self.id) {
-                Some(&addr) => Ok(addr),
-                None => Err(()),
-            }
+            self.table.lookup_virtual(self.id).unwrap_or(self.id)
+        } else {
+            self.id
+        }
+    }
+
+    fn resolve(&self) -> Option<Resolved<'st>> {
+        let id = if self.id.is_virtual() {
+            self.table.lookup_virtual(self.id)?
         } else {
-            Ok(self.id.to_addr())
+            self.id
+        };
+
+        if let Some(range) = self.table.reserved_range(id) {
+            let text = range.resolver.resolve(id.as_u32() - range.first)?;
+            return Some(Resolved::Text(text));
         }
+
+        let table = match &self.table.shared {
+            Some(shared) if id.as_u32() >= FIRST_SHARED_STRING_ID => shared,
+            _ => self.table,
+        };
+
+        let addr = table.lookup_addr(id)?;
+        Some(Resolved::Data(&table.string_data[..], addr.as_usize()))
     }
 }
 
@@ -148,83 +342,398 @@ fn decode_string_id_from_data(bytes: &[u8]) -> StringId {
 // encoding.
 fn decode_utf8_char(bytes: &[u8]) -> Option<(char, usize)> {
     use std::convert::TryFrom;
-    let first_byte = bytes[0] as u32;
+
+    // The payload of the continuation byte at `index`, if there is one.
+    let continuation = |index: usize| match bytes.get(index) {
+        Some(&byte) if is_utf8_continuation_byte(byte) => Some((byte & 0b0011_1111) as u32),
+        _ => None,
+    };
+
+    let first_byte = *bytes.first()? as u32;
     let (codepoint, len) = if (first_byte & 0b1000_0000) == 0 {
         // The highest bit is zero, so this is a single-byte char
         (first_byte, 1)
     } else if (first_byte & 0b1110_0000) == 0b1100_0000 {
         // This is a two byte character
         let bits0 = first_byte & 0b0001_1111;
-        let bits1 = (bytes[1] & 0b0011_1111) as u32;
+        let bits1 = continuation(1)?;
 
         (bits0 << 6 | bits1, 2)
     } else if (first_byte & 0b1111_0000) == 0b1110_0000 {
         // This is a three byte character
         let bits0 = first_byte & 0b0000_1111;
-        let bits1 = (bytes[1] & 0b0011_1111) as u32;
-        let bits2 = (bytes[2] & 0b0011_1111) as u32;
+        let bits1 = continuation(1)?;
+        let bits2 = continuation(2)?;
 
         ((bits0 << 12) | (bits1 << 6) | bits2, 3)
     } else if (first_byte & 0b1111_1000) == 0b1111_0000 {
         // This is a four byte character
         let bits0 = first_byte & 0b0000_0111;
-        let bits1 = (bytes[1] & 0b0011_1111) as u32;
-        let bits2 = (bytes[2] & 0b0011_1111) as u32;
-        let bits3 = (bytes[3] & 0b0011_1111) as u32;
+        let bits1 = continuation(1)?;
+        let bits2 = continuation(2)?;
+        let bits3 = continuation(3)?;
 
         ((bits0 << 18) | (bits1 << 12) | (bits2 << 6) | bits3, 4)
     } else {
         return None;
     };
 
-    match char::try_from(codepoint) {
-        Ok(c) => {
-            debug_assert!({
-                let test_bytes = &mut [0u8; 8];
-                c.encode_utf8(test_bytes);
-                &test_bytes[..len] == &bytes[..len]
-            });
+    // Surrogates and values beyond U+10FFFF aren't chars, and overlong
+    // encodings aren't valid UTF-8.
+    let c = char::try_from(codepoint)
+        .ok()
+        .filter(|c| c.len_utf8() == len)?;
 
-            Some((c, len))
-        }
-        Err(e) => {
-            panic!("StringTable: Encountered invalid UTF8 char: {:?}", e);
-        }
-    }
+    debug_assert!({
+        let test_bytes = &mut [0u8; 8];
+        c.encode_utf8(test_bytes);
+        test_bytes[..len] == bytes[..len]
+    });
+
+    Some((c, len))
 }
 
 /// Read-only version of the string table
 #[derive(Debug)]
 pub struct StringTable {
-    // TODO: Replace with something lazy
     string_data: Vec<u8>,
-    index: FxHashMap<StringId, Addr>,
+    // The index, including its header. The offsets of the regular strings
+    // are read from it when they are looked up, instead of being decoded
+    // when the table is loaded.
+    index_data: Vec<u8>,
+    // The strings blocks of the index, in the order of their ids.
+    strings_blocks: Vec<StringsBlock>,
+    // The blocks of virtual mappings of the index, one after the other, and
+    // the range of each block. Each block is sorted by virtual id, so the
+    // mappings are looked up via binary search.
+    virtual_mappings: Vec<u8>,
+    virtual_blocks: Vec<Range<usize>>,
+    // The string table of the `measureme::SharedStringCache` the profile
+    // refers to, if any.
+    shared: Option<Arc<StringTable>>,
+    reserved: Vec<ReservedRange>,
+    // Computed by the first call of `stats()`.
+    stats: OnceLock<StringTableStats>,
+}
+
+/// Statistics about the strings in a profile's string table, which can help
+/// embedders tune what they intern, see `StringTable::stats()`. Shared and
+/// reserved strings aren't included.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct StringTableStats {
+    /// The size of the `.string_data` file, not counting its header.
+    pub data_bytes: u64,
+    /// The size of the `.string_index` file, not counting its header.
+    pub index_bytes: u64,
+    /// The number of strings in the table.
+    pub entries: usize,
+    /// The number of virtual `StringId`s mapped to strings in the table.
+    pub virtual_mappings: usize,
+    /// The number of strings with a different content.
+    pub distinct_entries: usize,
+    /// The total length of the strings when expanded, in bytes.
+    pub expanded_bytes: u64,
+    /// The part of `expanded_bytes` taken by strings whose content is the
+    /// same as that of another entry, i.e. that could have been interned
+    /// once.
+    pub duplicate_bytes: u64,
+    // The distinct strings, by their first `StringId`, length and number of
+    // entries, ordered by length, longest first.
+    by_len: Vec<(StringId, usize, usize)>,
+}
+
+/// A string of the table, as listed by `StringTableStats::largest()` and
+/// `most_duplicated()`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StringUsage {
+    /// The first entry with this content.
+    pub id: StringId,
+    /// The length of the expanded string, in bytes.
+    pub len: usize,
+    /// The number of entries with this content.
+    pub count: usize,
+}
+
+impl StringTableStats {
+    /// The share of the entries that duplicate the content of another entry,
+    /// between 0 and 1.
+    pub fn duplicate_ratio(&self) -> f64 {
+        if self.entries == 0 {
+            return 0.0;
+        }
+        (self.entries - self.distinct_entries) as f64 / self.entries as f64
+    }
+
+    /// The `n` longest distinct strings, longest first.
+    pub fn largest(&self, n: usize) -> Vec<StringUsage> {
+        self.by_len
+            .iter()
+            .take(n)
+            .map(|&(id, len, count)| StringUsage { id, len, count })
+            .collect()
+    }
+
+    /// The `n` strings whose duplicates take the most bytes, i.e. the
+    /// strings most worth interning only once.
+    pub fn most_duplicated(&self, n: usize) -> Vec<StringUsage> {
+        let mut duplicated: Vec<_> = self
+            .by_len
+            .iter()
+            .filter(|&&(_, _, count)| count > 1)
+            .map(|&(id, len, count)| StringUsage { id, len, count })
+            .collect();
+        duplicated.sort_by_key(|usage| std::cmp::Reverse(usage.len * (usage.count - 1)));
+        duplicated.truncate(n);
+        duplicated
+    }
 }
 
 impl StringTable {
     pub fn new(string_data: Vec<u8>, index_data: Vec<u8>) -> Result<StringTable, Box<dyn Error>> {
-        let string_data_format = read_file_header(&string_data, FILE_MAGIC_STRINGTABLE_DATA)?;
-        let index_data_format = read_file_header(&index_data, FILE_MAGIC_STRINGTABLE_INDEX)?;
+        let string_data_header = read_full_file_header(&string_data, FILE_MAGIC_STRINGTABLE_DATA)?;
+        let index_data_header = read_full_file_header(&index_data, FILE_MAGIC_STRINGTABLE_INDEX)?;
 
-        if string_data_format != index_data_format {
+        if string_data_header.version != index_data_header.version {
             Err("Mismatch between StringTable DATA and INDEX format version")?;
         }
 
-        if string_data_format != CURRENT_FILE_FORMAT_VERSION {
+        if string_data_header.version < CURRENT_FILE_FORMAT_VERSION {
             Err(format!(
                 "StringTable file format version '{}' is not supported
                          by this version of `measureme`.",
-                string_data_format
+                string_data_header.version
+            ))?;
+        }
+
+        string_data_header.check_readable()?;
+        index_data_header.check_readable()?;
+
+        if string_data_header.session_id != index_data_header.session_id {
+            Err(LoadError::new(
+                LoadErrorKind::Mismatched,
+                "the StringTable DATA and INDEX have been written by different profiling \
+                 sessions, e.g. because the files of different runs have been mixed up",
             ))?;
         }
 
-        assert!(index_data.len() % 8 == 0);
-        let index: FxHashMap<_, _> = strip_file_header(&index_data)
-            .chunks(8)
-            .map(deserialize_index_entry)
+        let mut table = StringTable {
+            string_data,
+            index_data,
+            strings_blocks: Vec::new(),
+            virtual_mappings: Vec::new(),
+            virtual_blocks: Vec::new(),
+            shared: None,
+            reserved: Vec::new(),
+            stats: OnceLock::new(),
+        };
+
+        if table.add_blocks(FILE_HEADER_SIZE, false)? != table.index_data.len() {
+            Err("StringTable INDEX is corrupt")?;
+        }
+        table.merge_virtual_blocks();
+
+        Ok(table)
+    }
+
+    /// Appends `string_data` and the blocks of `index_data`, the bytes
+    /// that have been appended to the string table's files since the last
+    /// call, for reading a profile while it is recorded (see the `follow`
+    /// module). Blocks that are incomplete or refer to string data that
+    /// hasn't been written yet are left for the next call, so this returns
+    /// the number of bytes of `index_data` that have been added.
+    pub(crate) fn extend(
+        &mut self,
+        string_data: &[u8],
+        index_data: &[u8],
+    ) -> Result<usize, Box<dyn Error>> {
+        self.string_data.extend_from_slice(string_data);
+
+        let start = self.index_data.len();
+        self.index_data.extend_from_slice(index_data);
+        let end = self.add_blocks(start, true)?;
+        self.index_data.truncate(end);
+
+        if self.virtual_blocks.len() > MAX_VIRTUAL_BLOCKS {
+            self.merge_virtual_blocks();
+        }
+
+        self.stats = OnceLock::new();
+        Ok(end - start)
+    }
+
+    // Adds the blocks of the index from `pos` on, up to the first one that
+    // is incomplete, and returns where that one starts. With `wait_for_data`,
+    // this also stops at a strings block that refers to string data beyond
+    // the end of `string_data`.
+    fn add_blocks(&mut self, mut pos: usize, wait_for_data: bool) -> Result<usize, Box<dyn Error>> {
+        while let Some((header, entries)) = read_index_block(&self.index_data[pos..]) {
+            let start = pos + INDEX_BLOCK_HEADER_SIZE;
+
+            if header.kind == STRINGS_BLOCK {
+                let previous_end = self
+                    .strings_blocks
+                    .last()
+                    .map_or(0, |block| block.first_id as u64 + block.len as u64);
+                let end = header.first_id as u64 + header.len as u64;
+                if header.first_id < FIRST_REGULAR_STRING_ID
+                    || (header.first_id as u64) < previous_end
+                    || end > MAX_STRING_ID as u64 + 1
+                {
+                    Err(LoadError::new(
+                        LoadErrorKind::Corrupt,
+                        "StringTable INDEX contains a block of strings with ids that are \
+                         virtual or already taken",
+                    ))?;
+                }
+
+                let string_data_len = self.string_data.len() as u64;
+                if wait_for_data
+                    && (0..header.len as usize).any(|i| {
+                        let addr = read_string_addr(entries, i);
+                        addr != u64::MAX && addr >= string_data_len
+                    })
+                {
+                    break;
+                }
+
+                self.strings_blocks.push(StringsBlock {
+                    first_id: header.first_id,
+                    len: header.len,
+                    start,
+                });
+            } else {
+                let mappings = start..start + entries.len();
+                let block_start = self.virtual_mappings.len();
+                self.virtual_mappings
+                    .extend_from_slice(&self.index_data[mappings]);
+                self.virtual_blocks
+                    .push(block_start..self.virtual_mappings.len());
+            }
+
+            pos = start + entries.len();
+        }
+
+        Ok(pos)
+    }
+
+    // Merges the blocks of virtual mappings into one, so that looking up a
+    // virtual id takes a single binary search. The last block's mapping of
+    // an id wins, as in `lookup_virtual()`.
+    fn merge_virtual_blocks(&mut self) {
+        if self.virtual_blocks.len() <= 1 {
+            return;
+        }
+
+        let mut mappings: Vec<(u32, u32)> = self
+            .virtual_blocks
+            .iter()
+            .flat_map(|block| {
+                let entries = &self.virtual_mappings[block.clone()];
+                (0..entries.len() / VIRTUAL_MAPPING_SIZE)
+                    .map(move |i| read_virtual_mapping(entries, i))
+            })
             .collect();
 
-        Ok(StringTable { string_data, index })
+        // The sort is stable, so the mappings of an id stay in the order of
+        // their blocks.
+        mappings.sort_by_key(|&(id, _)| id);
+        mappings.reverse();
+        mappings.dedup_by_key(|&mut (id, _)| id);
+        mappings.reverse();
+
+        self.virtual_mappings.clear();
+        for (virtual_id, target) in mappings {
+            self.virtual_mappings
+                .extend_from_slice(&virtual_id.to_le_bytes());
+            self.virtual_mappings
+                .extend_from_slice(&target.to_le_bytes());
+        }
+        self.virtual_blocks.clear();
+        self.virtual_blocks.push(0..self.virtual_mappings.len());
+    }
+
+    /// Makes ids from `measureme::stringtable::FIRST_SHARED_STRING_ID` on
+    /// resolve to the strings in `shared`, the string table of the
+    /// `measureme::SharedStringCache` that the profile was recorded with.
+    pub fn set_shared(&mut self, shared: Arc<StringTable>) {
+        self.shared = Some(shared);
+    }
+
+    /// Makes the `len` ids from `first` on, which the profiler reserved via
+    /// `measureme::Profiler::reserve_string_ids()`, resolve via `resolver`.
+    /// Ids without a resolver resolve to `<unknown>`.
+    pub fn set_resolver(
+        &mut self,
+        first: StringId,
+        len: u32,
+        resolver: impl StringResolver + 'static,
+    ) {
+        self.reserved.retain(|range| range.first != first.as_u32());
+        self.reserved.push(ReservedRange {
+            first: first.as_u32(),
+            len,
+            resolver: Box::new(resolver),
+        });
+    }
+
+    fn is_reserved(&self, id: StringId) -> bool {
+        (FIRST_RESERVED_STRING_ID..FIRST_SHARED_STRING_ID).contains(&id.as_u32())
+    }
+
+    fn reserved_range(&self, id: StringId) -> Option<&ReservedRange> {
+        if !self.is_reserved(id) {
+            return None;
+        }
+
+        self.reserved
+            .iter()
+            .find(|range| id.as_u32().wrapping_sub(range.first) < range.len)
+    }
+
+    // The regular id the virtual `id` is mapped to. Later blocks override
+    // earlier ones, e.g. those of a previous session of a resumed profile.
+    fn lookup_virtual(&self, id: StringId) -> Option<StringId> {
+        self.virtual_blocks.iter().rev().find_map(|block| {
+            let mappings = &self.virtual_mappings[block.clone()];
+            let (mut low, mut high) = (0, mappings.len() / VIRTUAL_MAPPING_SIZE);
+
+            while low < high {
+                let mid = low + (high - low) / 2;
+                let (virtual_id, target) = read_virtual_mapping(mappings, mid);
+
+                match virtual_id.cmp(&id.as_u32()) {
+                    std::cmp::Ordering::Less => low = mid + 1,
+                    std::cmp::Ordering::Greater => high = mid,
+                    std::cmp::Ordering::Equal => {
+                        // A target that isn't a regular id leaves `id` missing.
+                        return (FIRST_REGULAR_STRING_ID..=MAX_STRING_ID)
+                            .contains(&target)
+                            .then(|| StringId::new(target));
+                    }
+                }
+            }
+
+            None
+        })
+    }
+
+    fn lookup_addr(&self, id: StringId) -> Option<Addr> {
+        let id = id.as_u32();
+        let block_index = self
+            .strings_blocks
+            .partition_point(|block| block.first_id <= id)
+            .checked_sub(1)?;
+        let block = &self.strings_blocks[block_index];
+
+        let index = id - block.first_id;
+        if index >= block.len {
+            return None;
+        }
+
+        // Offsets beyond the string data include the `u64::MAX` of strings
+        // that have never been written.
+        let addr = read_string_addr(&self.index_data[block.start..], index as usize);
+        (addr < self.string_data.len() as u64).then_some(Addr(addr))
     }
 
     #[inline]
@@ -236,12 +745,64 @@ impl StringTable {
         let id = StringId::new(METADATA_STRING_ID);
         self.get(id)
     }
+
+    /// Returns statistics about the strings in the table. They are computed
+    /// the first time this is called, which expands every string.
+    pub fn stats(&self) -> &StringTableStats {
+        self.stats.get_or_init(|| self.compute_stats())
+    }
+
+    fn compute_stats(&self) -> StringTableStats {
+        let mut stats = StringTableStats {
+            data_bytes: strip_file_header(&self.string_data).len() as u64,
+            index_bytes: (self.index_data.len() - FILE_HEADER_SIZE) as u64,
+            virtual_mappings: self.virtual_mappings.len() / VIRTUAL_MAPPING_SIZE,
+            ..StringTableStats::default()
+        };
+
+        // The first id and the number of entries of every distinct string.
+        let mut distinct = FxHashMap::<Cow<'_, str>, (StringId, usize)>::default();
+
+        let ids = self
+            .strings_blocks
+            .iter()
+            .flat_map(|block| block.first_id..block.first_id + block.len)
+            .map(StringId::new);
+        for id in ids {
+            if id.as_u32() == METADATA_STRING_ID || self.lookup_addr(id).is_none() {
+                continue;
+            }
+
+            let string = self.get(id).to_string();
+            stats.entries += 1;
+            stats.expanded_bytes += string.len() as u64;
+
+            let (_, count) = distinct.entry(string).or_insert((id, 0));
+            *count += 1;
+        }
+
+        stats.distinct_entries = distinct.len();
+        stats.by_len = distinct
+            .into_iter()
+            .map(|(string, (id, count))| {
+                stats.duplicate_bytes += (string.len() * (count - 1)) as u64;
+                (id, string.len(), count)
+            })
+            .collect();
+        stats
+            .by_len
+            .sort_by_key(|&(id, len, _)| (std::cmp::Reverse(len), id.as_u32()));
+        stats
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use measureme::{ByteVecSink, StringComponent, StringTableBuilder};
+    use measureme::stringtable::{
+        IndexBlockHeader, StringTableBuilder, STRING_ADDR_SIZE, VIRTUAL_MAPPINGS_BLOCK,
+    };
+    use measureme::{ByteVecSink, StringComponent};
     use std::sync::Arc;
 
     #[test]
@@ -285,6 +846,46 @@ mod tests {
         }
     }
 
+    #[test]
+    fn extend_in_pieces() {
+        let data_sink = Arc::new(ByteVecSink::new());
+        let index_sink = Arc::new(ByteVecSink::new());
+
+        let expected_strings = ["abc", "", "a longer string", "xyz"];
+        let string_ids: Vec<_> = {
+            let builder = StringTableBuilder::new(data_sink.clone(), index_sink.clone());
+            expected_strings.iter().map(|&s| builder.alloc(s)).collect()
+        };
+
+        let data_bytes = Arc::try_unwrap(data_sink).unwrap().into_bytes();
+        let index_bytes = Arc::try_unwrap(index_sink).unwrap().into_bytes();
+        let (data_header, data) = data_bytes.split_at(FILE_HEADER_SIZE);
+        let (index_header, index) = index_bytes.split_at(FILE_HEADER_SIZE);
+
+        let mut string_table =
+            StringTable::new(data_header.to_vec(), index_header.to_vec()).unwrap();
+
+        // The index is a single strings block.
+        let block_len = INDEX_BLOCK_HEADER_SIZE + expected_strings.len() * STRING_ADDR_SIZE;
+        assert_eq!(index.len(), block_len);
+
+        // The whole index, but only half of the data: the block refers to
+        // strings that haven't been written yet, so it is left for later.
+        let half = data.len() / 2;
+        assert_eq!(string_table.extend(&data[..half], index).unwrap(), 0);
+        assert_eq!(string_table.get(string_ids[0]).to_string(), UNKNOWN_STRING);
+
+        // The rest of the data, with the block cut off.
+        let rest = &index[..block_len - 1];
+        assert_eq!(string_table.extend(&data[half..], rest).unwrap(), 0);
+        assert_eq!(string_table.get(string_ids[0]).to_string(), UNKNOWN_STRING);
+
+        assert_eq!(string_table.extend(&[], index).unwrap(), block_len);
+        for (&id, &expected_string) in string_ids.iter().zip(expected_strings.iter()) {
+            assert_eq!(string_table.get(id).to_string(), expected_string);
+        }
+    }
+
     #[test]
     fn composite_string() {
         let data_sink = Arc::new(ByteVecSink::new());
@@ -340,6 +941,171 @@ mod tests {
         }
     }
 
+    #[test]
+    fn virtual_strings() {
+        let data_sink = Arc::new(ByteVecSink::new());
+        let index_sink = Arc::new(ByteVecSink::new());
+
+        {
+            let builder = StringTableBuilder::new(data_sink.clone(), index_sink.clone());
+
+            let abc = builder.alloc("abc");
+            let xyz = builder.alloc("xyz");
+
+            // Map in non-sorted order and re-map some ids.
+            builder.map_virtual_to_concrete_string(StringId::new_virtual(30), abc);
+            builder.map_virtual_to_concrete_string(StringId::new_virtual(10), abc);
+            builder.bulk_map_virtual_to_single_concrete_string(
+                [40, 20, 30].iter().map(|&id| StringId::new_virtual(id)),
+                xyz,
+            );
+            builder.map_virtual_to_concrete_string(StringId::new_virtual(20), abc);
+        }
+
+        let data_bytes = Arc::try_unwrap(data_sink).unwrap().into_bytes();
+        let index_bytes = Arc::try_unwrap(index_sink).unwrap().into_bytes();
+
+        // A block with the two strings and one with a mapping per distinct
+        // virtual id.
+        let index = strip_file_header(&index_bytes);
+        let (header, entries) = read_index_block(index).unwrap();
+        assert_eq!((header.kind, header.len), (STRINGS_BLOCK, 2));
+        let index = &index[INDEX_BLOCK_HEADER_SIZE + entries.len()..];
+        let (header, entries) = read_index_block(index).unwrap();
+        assert_eq!((header.kind, header.len), (VIRTUAL_MAPPINGS_BLOCK, 4));
+        assert_eq!(index.len(), INDEX_BLOCK_HEADER_SIZE + entries.len());
+
+        let string_table = StringTable::new(data_bytes, index_bytes).unwrap();
+        let get = |id| string_table.get(StringId::new_virtual(id)).to_string();
+
+        assert_eq!(get(10), "abc");
+        assert_eq!(get(20), "abc");
+        assert_eq!(get(30), "xyz");
+        assert_eq!(get(40), "xyz");
+        assert_eq!(get(15), UNKNOWN_STRING);
+        assert_eq!(get(50), UNKNOWN_STRING);
+    }
+
+    #[test]
+    fn later_virtual_mappings_win() {
+        let data_sink = Arc::new(ByteVecSink::new());
+        let index_sink = Arc::new(ByteVecSink::new());
+
+        let (abc, xyz) = {
+            let builder = StringTableBuilder::new(data_sink.clone(), index_sink.clone());
+            let abc = builder.alloc("abc");
+            let xyz = builder.alloc("xyz");
+            builder.map_virtual_to_concrete_string(StringId::new_virtual(1), abc);
+            builder.map_virtual_to_concrete_string(StringId::new_virtual(2), abc);
+            (abc, xyz)
+        };
+
+        // A second block, as a resumed profile has one, that re-maps one id
+        // and maps another to an invalid id.
+        let mut index_bytes = Arc::try_unwrap(index_sink).unwrap().into_bytes();
+        let header = IndexBlockHeader {
+            kind: VIRTUAL_MAPPINGS_BLOCK,
+            first_id: 0,
+            len: 2,
+        };
+        header.write(&mut index_bytes);
+        for &(virtual_id, target) in &[(2, xyz.as_u32()), (3, MAX_STRING_ID + 1)] {
+            index_bytes.extend_from_slice(&u32::to_le_bytes(virtual_id));
+            index_bytes.extend_from_slice(&u32::to_le_bytes(target));
+        }
+
+        let data_bytes = Arc::try_unwrap(data_sink).unwrap().into_bytes();
+        let string_table = StringTable::new(data_bytes.clone(), index_bytes.clone()).unwrap();
+        let get = |id| string_table.get(StringId::new_virtual(id)).to_string();
+
+        assert_eq!(string_table.get(abc).to_string(), "abc");
+        assert_eq!(get(1), "abc");
+        assert_eq!(get(2), "xyz");
+        assert_eq!(get(3), UNKNOWN_STRING);
+        // The blocks are merged when the table is loaded.
+        assert_eq!(string_table.stats().virtual_mappings, 3);
+
+        // A block that is cut off is corrupt.
+        index_bytes.pop();
+        assert!(StringTable::new(data_bytes, index_bytes).is_err());
+    }
+
+    #[test]
+    fn many_blocks() {
+        use measureme::stringtable::VIRTUAL_BLOCK_LEN;
+
+        let data_sink = Arc::new(ByteVecSink::new());
+        let index_sink = Arc::new(ByteVecSink::new());
+
+        let string_ids: Vec<_> = {
+            let builder = StringTableBuilder::new(data_sink.clone(), index_sink.clone());
+            let string_ids: Vec<_> = (0..10_000)
+                .map(|i| builder.alloc(&i.to_string()[..]))
+                .collect();
+            builder.bulk_map_virtual_to_single_concrete_string(
+                (0..VIRTUAL_BLOCK_LEN as u32 + 1).map(StringId::new_virtual),
+                string_ids[1],
+            );
+            builder.map_virtual_to_concrete_string(StringId::new_virtual(0), string_ids[2]);
+            string_ids
+        };
+
+        let data_bytes = Arc::try_unwrap(data_sink).unwrap().into_bytes();
+        let index_bytes = Arc::try_unwrap(index_sink).unwrap().into_bytes();
+
+        // The strings and virtual mappings are written in several blocks
+        // while the profile is recorded.
+        let mut index = strip_file_header(&index_bytes);
+        let mut kinds = Vec::new();
+        while let Some((header, entries)) = read_index_block(index) {
+            kinds.push(header.kind);
+            index = &index[INDEX_BLOCK_HEADER_SIZE + entries.len()..];
+        }
+        assert!(index.is_empty());
+        assert!(kinds.iter().filter(|&&kind| kind == STRINGS_BLOCK).count() > 1);
+        assert_eq!(
+            kinds
+                .iter()
+                .filter(|&&kind| kind == VIRTUAL_MAPPINGS_BLOCK)
+                .count(),
+            2
+        );
+
+        let string_table = StringTable::new(data_bytes, index_bytes).unwrap();
+        for (i, &id) in string_ids.iter().enumerate() {
+            assert_eq!(string_table.get(id).to_string(), i.to_string());
+        }
+        let get = |id| string_table.get(StringId::new_virtual(id)).to_string();
+        assert_eq!(get(0), "2");
+        assert_eq!(get(VIRTUAL_BLOCK_LEN as u32), "1");
+        assert_eq!(get(VIRTUAL_BLOCK_LEN as u32 + 1), UNKNOWN_STRING);
+    }
+
+    #[test]
+    fn overlapping_strings_blocks_are_corrupt() {
+        let data_sink = Arc::new(ByteVecSink::new());
+        let index_sink = Arc::new(ByteVecSink::new());
+
+        let abc = {
+            let builder = StringTableBuilder::new(data_sink.clone(), index_sink.clone());
+            builder.alloc("abc")
+        };
+
+        let data_bytes = Arc::try_unwrap(data_sink).unwrap().into_bytes();
+        let mut index_bytes = Arc::try_unwrap(index_sink).unwrap().into_bytes();
+        let header = IndexBlockHeader {
+            kind: STRINGS_BLOCK,
+            first_id: abc.as_u32(),
+            len: 1,
+        };
+        header.write(&mut index_bytes);
+        index_bytes.extend_from_slice(&(FILE_HEADER_SIZE as u64).to_le_bytes());
+
+        let err = StringTable::new(data_bytes, index_bytes).unwrap_err();
+        let err = err.downcast_ref::<LoadError>().unwrap();
+        assert_eq!(err.kind, LoadErrorKind::Corrupt);
+    }
+
     #[test]
     fn utf8_char_decoding() {
         use std::convert::TryFrom;
@@ -355,4 +1121,46 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn stats() {
+        let data_sink = Arc::new(ByteVecSink::new());
+        let index_sink = Arc::new(ByteVecSink::new());
+
+        let (long, short, xyz, virtual_id) = {
+            let builder = StringTableBuilder::new(data_sink.clone(), index_sink.clone());
+
+            let long = builder.alloc("alloc::vec::Vec<alloc::string::String>");
+            builder.alloc("alloc::vec::Vec<alloc::string::String>");
+            builder.alloc("alloc::vec::Vec<alloc::string::String>");
+            let short = builder.alloc("ab");
+            builder.alloc(&[StringComponent::Value("a"), StringComponent::Value("b")][..]);
+            let xyz = builder.alloc("xyz");
+
+            let virtual_id = StringId::new_virtual(7);
+            builder.map_virtual_to_concrete_string(virtual_id, short);
+            (long, short, xyz, virtual_id)
+        };
+
+        let data_bytes = Arc::try_unwrap(data_sink).unwrap().into_bytes();
+        let index_bytes = Arc::try_unwrap(index_sink).unwrap().into_bytes();
+        let string_table = StringTable::new(data_bytes, index_bytes).unwrap();
+        assert_eq!(string_table.get(virtual_id).to_string(), "ab");
+
+        let stats = string_table.stats();
+        assert_eq!(stats.entries, 6);
+        assert_eq!(stats.distinct_entries, 3);
+        assert_eq!(stats.virtual_mappings, 1);
+        assert_eq!(stats.expanded_bytes, 3 * 38 + 2 * 2 + 3);
+        assert_eq!(stats.duplicate_bytes, 2 * 38 + 2);
+        assert_eq!(stats.duplicate_ratio(), 0.5);
+        assert!(stats.data_bytes > 0 && stats.index_bytes > 0);
+
+        let usage = |id, len, count| StringUsage { id, len, count };
+        assert_eq!(stats.largest(2), vec![usage(long, 38, 3), usage(xyz, 3, 1)]);
+        assert_eq!(
+            stats.most_duplicated(10),
+            vec![usage(long, 38, 3), usage(short, 2, 2)]
+        );
+    }
 }
@@ -0,0 +1,235 @@
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+#[cfg(feature = "debuginfo")]
+use std::sync::Mutex;
+
+/// Resolves code addresses recorded as `measureme::ArgType::Address`
+/// arguments to function names, see `ProfilingData::set_symbolizer()`.
+/// `DebugInfoSymbolizer` covers code with debug info, `SymbolMap`
+/// JIT-compiled code.
+pub trait Symbolizer: Send + Sync {
+    fn symbolize(&self, address: u64) -> Option<String>;
+}
+
+pub(crate) struct BoxedSymbolizer(pub(crate) Box<dyn Symbolizer>);
+
+impl fmt::Debug for BoxedSymbolizer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Symbolizer")
+    }
+}
+
+/// A `Symbolizer` for symbol maps in the format that JITs write for `perf`
+/// (`/tmp/perf-<pid>.map`): one symbol per line, given as its start address
+/// and size in hexadecimal and its name, e.g. `7f3a00001000 40 my_function`.
+/// Addresses within a symbol resolve to `name+0x<offset>`.
+#[derive(Clone, Debug, Default)]
+pub struct SymbolMap {
+    // (start, size, name), ordered by start.
+    symbols: Vec<(u64, u64, String)>,
+}
+
+impl SymbolMap {
+    pub fn load(path: &Path) -> Result<SymbolMap, Box<dyn Error>> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("could not read `{}`: {}", path.display(), e))?;
+        SymbolMap::parse(&text)
+    }
+
+    pub fn parse(text: &str) -> Result<SymbolMap, Box<dyn Error>> {
+        let mut symbols = Vec::new();
+
+        for (line_number, line) in text.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let mut parts = line.splitn(3, ' ');
+            let mut hex = || {
+                let part = parts.next()?;
+                u64::from_str_radix(part.strip_prefix("0x").unwrap_or(part), 16).ok()
+            };
+
+            match (hex(), hex(), parts.next()) {
+                (Some(start), Some(size), Some(name)) => {
+                    symbols.push((start, size, name.trim().to_string()))
+                }
+                _ => Err(format!(
+                    "line {} of the symbol map is not of the form `<start> <size> <name>`",
+                    line_number + 1
+                ))?,
+            }
+        }
+
+        symbols.sort_by_key(|&(start, _, _)| start);
+        Ok(SymbolMap { symbols })
+    }
+}
+
+impl Symbolizer for SymbolMap {
+    fn symbolize(&self, address: u64) -> Option<String> {
+        // The last symbol that starts at or before the address.
+        let index = self
+            .symbols
+            .partition_point(|&(start, _, _)| start <= address)
+            .checked_sub(1)?;
+        let (start, size, ref name) = self.symbols[index];
+
+        match address - start {
+            0 => Some(name.clone()),
+            offset if offset < size => Some(format!("{}+{:#x}", name, offset)),
+            _ => None,
+        }
+    }
+}
+
+/// A `Symbolizer` that reads the DWARF debug info and the symbol table of an
+/// executable or shared library with `addr2line`. Addresses within inlined
+/// functions resolve to the name of the innermost one. Only available with
+/// the `debuginfo` feature.
+#[cfg(feature = "debuginfo")]
+pub struct DebugInfoSymbolizer {
+    // `addr2line::Loader` parses the debug info lazily and thus isn't `Sync`.
+    loader: Mutex<addr2line::Loader>,
+    load_bias: u64,
+}
+
+#[cfg(feature = "debuginfo")]
+impl DebugInfoSymbolizer {
+    pub fn load(path: &Path) -> Result<DebugInfoSymbolizer, Box<dyn Error>> {
+        let loader = addr2line::Loader::new(path)
+            .map_err(|e| format!("could not read `{}`: {}", path.display(), e))?;
+        Ok(DebugInfoSymbolizer {
+            loader: Mutex::new(loader),
+            load_bias: 0,
+        })
+    }
+
+    /// Sets the difference between the recorded addresses and the addresses
+    /// in the file, which position-independent executables and shared
+    /// libraries have, e.g. `dl_phdr_info::dlpi_addr` on Linux.
+    pub fn with_load_bias(mut self, load_bias: u64) -> DebugInfoSymbolizer {
+        self.load_bias = load_bias;
+        self
+    }
+}
+
+#[cfg(feature = "debuginfo")]
+impl fmt::Debug for DebugInfoSymbolizer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DebugInfoSymbolizer")
+            .field("load_bias", &self.load_bias)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(feature = "debuginfo")]
+impl Symbolizer for DebugInfoSymbolizer {
+    fn symbolize(&self, address: u64) -> Option<String> {
+        // `addr2line` looks up `address..address + 1`, which overflows for
+        // `u64::MAX`.
+        let address = address
+            .checked_sub(self.load_bias)
+            .filter(|&address| address < u64::MAX)?;
+        let loader = self.loader.lock().unwrap_or_else(|e| e.into_inner());
+
+        if let Ok(mut frames) = loader.find_frames(address) {
+            if let Ok(Some(frame)) = frames.next() {
+                if let Some(Ok(name)) = frame.function.as_ref().map(|f| f.demangle()) {
+                    return Some(name.into_owned());
+                }
+            }
+        }
+
+        // Code without debug info, e.g. that of a library.
+        let name = loader.find_symbol(address)?;
+        Some(addr2line::demangle_auto(name.into(), None).into_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn perf_map() {
+        let map = SymbolMap::parse(
+            "7f0000002000 100 Function:bar\n\
+             7f0000001000 40 Function:foo\n\
+             \n",
+        )
+        .unwrap();
+
+        assert_eq!(map.symbolize(0x7f0000001000), Some("Function:foo".into()));
+        assert_eq!(
+            map.symbolize(0x7f0000001010),
+            Some("Function:foo+0x10".into())
+        );
+        assert_eq!(map.symbolize(0x7f0000001040), None);
+        assert_eq!(
+            map.symbolize(0x7f00000020ff),
+            Some("Function:bar+0xff".into())
+        );
+        assert_eq!(map.symbolize(0x7f0000000fff), None);
+
+        assert!(SymbolMap::parse("7f0000001000 foo").is_err());
+    }
+
+    // Compiles a tiny binary with debug info and resolves the address of
+    // one of its functions, as found in its symbol table. Only on Linux, as
+    // elsewhere the debug info isn't part of the binary.
+    #[cfg(all(feature = "debuginfo", target_os = "linux"))]
+    #[test]
+    fn debug_info() {
+        use object::{Object, ObjectSymbol};
+        use std::process::Command;
+
+        let dir = Path::new("test-tmp").join("symbols");
+        fs::create_dir_all(&dir).unwrap();
+        let source = dir.join("tiny.rs");
+        let binary = dir.join("tiny");
+        fs::write(
+            &source,
+            "#[no_mangle]\n\
+             #[inline(never)]\n\
+             pub extern \"C\" fn measureme_symbolize_me(x: u64) -> u64 {\n\
+             \x20   x.wrapping_mul(31) ^ 7\n\
+             }\n\
+             fn main() {\n\
+             \x20   println!(\"{}\", measureme_symbolize_me(std::env::args().count() as u64));\n\
+             }\n",
+        )
+        .unwrap();
+
+        let status = Command::new("rustc")
+            .arg("-g")
+            .arg(&source)
+            .arg("-o")
+            .arg(&binary)
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        let data = fs::read(&binary).unwrap();
+        let file = object::File::parse(&*data).unwrap();
+        let address = file
+            .symbols()
+            .find(|symbol| symbol.name() == Ok("measureme_symbolize_me"))
+            .unwrap()
+            .address();
+
+        let symbolizer = DebugInfoSymbolizer::load(&binary).unwrap();
+        let name = Some("measureme_symbolize_me".to_string());
+        assert_eq!(symbolizer.symbolize(address), name);
+        assert_eq!(symbolizer.symbolize(address + 4), name);
+        assert_eq!(symbolizer.symbolize(u64::MAX), None);
+
+        let symbolizer = symbolizer.with_load_bias(0x1000_0000);
+        assert_eq!(symbolizer.symbolize(address + 0x1000_0000), name);
+        assert_eq!(symbolizer.symbolize(address), None);
+
+        assert!(DebugInfoSymbolizer::load(&dir.join("missing")).is_err());
+    }
+}
@@ -1,6 +1,9 @@
 use crate::timestamp::Timestamp;
-use crate::{Event, ProfilingData};
-use measureme::{EventId, EventIdBuilder, Profiler, SerializationSink, StringId};
+use crate::{Event, ProfilingData, Validation};
+use measureme::{
+    EventId, EventIdBuilder, FileSerializationSink, Profiler, ProfilerConfig, SerializationSink,
+    StringId,
+};
 use rustc_hash::FxHashMap;
 use std::borrow::Cow;
 use std::default::Default;
@@ -40,8 +43,9 @@ fn generate_profiling_data<S: SerializationSink>(
     filestem: &Path,
     num_stacks: usize,
     num_threads: usize,
+    config: &ProfilerConfig,
 ) -> Vec<Event<'static>> {
-    let profiler = Arc::new(Profiler::<S>::new(Path::new(filestem)).unwrap());
+    let profiler = Arc::new(Profiler::<S>::with_config(Path::new(filestem), config).unwrap());
 
     let event_id_virtual = EventId::from_label(StringId::new_virtual(42));
     let event_id_builder = EventIdBuilder::new(&profiler);
@@ -193,18 +197,132 @@ pub fn run_serialization_bench<S: SerializationSink>(
     num_threads: usize,
 ) {
     let filestem = mk_filestem(file_name_stem);
-    generate_profiling_data::<S>(&filestem, num_events, num_threads);
+    generate_profiling_data::<S>(
+        &filestem,
+        num_events,
+        num_threads,
+        &ProfilerConfig::default(),
+    );
 }
 
 pub fn run_end_to_end_serialization_test<S: SerializationSink>(
     file_name_stem: &str,
     num_threads: usize,
+) {
+    run_end_to_end_serialization_test_with_config::<S>(
+        file_name_stem,
+        num_threads,
+        &ProfilerConfig::default(),
+    );
+}
+
+pub fn run_end_to_end_serialization_test_with_config<S: SerializationSink>(
+    file_name_stem: &str,
+    num_threads: usize,
+    config: &ProfilerConfig,
 ) {
     let filestem = mk_filestem(file_name_stem);
-    let expected_events = generate_profiling_data::<S>(&filestem, 10_000, num_threads);
+    let expected_events = generate_profiling_data::<S>(&filestem, 10_000, num_threads, config);
     process_profiling_data(&filestem, &expected_events);
 }
 
+/// Records a profile with a single `Query` interval with the given label at
+/// `path_stem`.
+pub fn record_query(path_stem: &Path, label: &str) {
+    let profiler = Profiler::<FileSerializationSink>::new(path_stem).unwrap();
+    let kind = profiler.alloc_string("Query");
+    let label = EventId::from_label(profiler.alloc_string(label));
+    drop(profiler.start_recording_interval_event(kind, label, 0));
+}
+
+/// The labels of the `Query` events of `data`, in order.
+pub fn query_labels(data: &ProfilingData) -> Vec<String> {
+    data.iter()
+        .map(|e| e.to_event())
+        .filter(|e| e.event_kind == "Query")
+        .map(|e| e.label.into_owned())
+        .collect()
+}
+
+/// Splits the input of the `decode` fuzz target (see `analyzeme/fuzz`) into
+/// the contents of the events, string data and string index files. The input
+/// starts with the sizes of the first two as little-endian `u32`s, the index
+/// is the rest.
+pub fn split_fuzz_input(input: &[u8]) -> Option<(Vec<u8>, Vec<u8>, Vec<u8>)> {
+    let size = |bytes: &[u8]| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    let events_len = size(input.get(0..4)?) as usize;
+    let string_data_len = size(input.get(4..8)?) as usize;
+
+    let rest = input.get(8..)?;
+    let events = rest.get(..events_len)?;
+    let rest = &rest[events_len..];
+    let string_data = rest.get(..string_data_len)?;
+    let index = &rest[string_data_len..];
+
+    Some((events.to_vec(), string_data.to_vec(), index.to_vec()))
+}
+
+/// The inverse of `split_fuzz_input()`.
+pub fn join_fuzz_input(events: &[u8], string_data: &[u8], index: &[u8]) -> Vec<u8> {
+    let mut input = Vec::new();
+    input.extend_from_slice(&(events.len() as u32).to_le_bytes());
+    input.extend_from_slice(&(string_data.len() as u32).to_le_bytes());
+    input.extend_from_slice(events);
+    input.extend_from_slice(string_data);
+    input.extend_from_slice(index);
+    input
+}
+
+/// Decodes a fuzz input (see `split_fuzz_input()`) with
+/// `ProfilingData::from_bytes()`, `from_bytes_strict()` and with
+/// `Validation::Lenient` and looks at everything they return, which must not
+/// panic. Returns whether the strict decoding succeeded.
+pub fn fuzz_decode(input: &[u8]) -> bool {
+    let (events, string_data, index) = match split_fuzz_input(input) {
+        Some(files) => files,
+        None => return false,
+    };
+
+    if let Ok(data) = ProfilingData::from_bytes(events.clone(), string_data.clone(), index.clone())
+    {
+        for event in data.iter() {
+            let event = event.to_event();
+            data.decode_args(&event);
+        }
+        data.thread_names();
+    }
+
+    if let Ok(data) = ProfilingData::from_bytes_validated(
+        events.clone(),
+        string_data.clone(),
+        index.clone(),
+        Validation::Lenient,
+    ) {
+        for event in data.iter() {
+            let event = event.to_event();
+            data.decode_args(&event);
+        }
+        data.per_thread_timelines();
+    }
+
+    match ProfilingData::from_bytes_strict(events, string_data, index) {
+        Ok(data) => {
+            for event in data.iter() {
+                let _ = event.duration();
+                let event = event.to_event();
+                let _ = event.duration();
+                data.decode_args(&event);
+            }
+            data.thread_names();
+            data.thread_ends();
+            data.dense_thread_ids();
+            data.per_thread_timelines();
+            true
+        }
+        Err(_) => false,
+    }
+}
+
 fn pseudo_invocation<S: SerializationSink>(
     profiler: &Profiler<S>,
     random: usize,
@@ -244,5 +362,8 @@ fn pseudo_invocation<S: SerializationSink>(
             start: SystemTime::UNIX_EPOCH,
             end: SystemTime::UNIX_EPOCH,
         },
+        integer_value: None,
+        cpu: None,
+        blocked: false,
     });
 }
@@ -0,0 +1,224 @@
+use crate::ProfilingData;
+use measureme::event_kinds::{
+    EXTERNAL_THREAD_ID_EVENT_KIND, THREAD_FINISHED_EVENT_KIND, THREAD_REGISTRATION_EVENT_KIND,
+    TRACK_CLOSED_EVENT_KIND, TRACK_OPENED_EVENT_KIND,
+};
+use rustc_hash::FxHashMap;
+use std::time::{Duration, SystemTime};
+
+/// How the recording ended for a thread, see `ProfilingData::thread_ends()`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ThreadEnd {
+    pub thread_id: u32,
+    /// When the thread recorded its `ThreadFinished` event (see
+    /// `measureme::Profiler::finish_thread()`), or its `TrackClosed` event
+    /// for a track, or `None` if it was still running when the profile
+    /// ended.
+    pub finished: Option<SystemTime>,
+    /// The latest end of any other event recorded by the thread.
+    pub last_event_end: SystemTime,
+    /// For threads that haven't finished, the time between `last_event_end`
+    /// and the end of the profile. Intervals that were still open on the
+    /// thread are not in the profile, so this is what the thread spent in
+    /// them (or idle) as far as the profile can tell.
+    pub unclosed_time: Duration,
+}
+
+impl ThreadEnd {
+    /// A thread that has finished can't have recorded anything afterwards.
+    /// If it did, the profile is corrupt (or the embedder reused the thread
+    /// id).
+    pub fn is_consistent(&self) -> bool {
+        self.finished
+            .is_none_or(|finished| self.last_event_end <= finished)
+    }
+}
+
+/// A track for a logical task, see `measureme::Profiler::open_track()`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Track {
+    pub track_id: u32,
+    pub name: String,
+    pub opened: SystemTime,
+    /// When the track was closed via `measureme::Profiler::close_track()`,
+    /// or `None` if the task was still running when the profile ended.
+    pub closed: Option<SystemTime>,
+}
+
+/// See `ProfilingData::thread_names()`.
+pub(crate) fn thread_names(data: &ProfilingData) -> FxHashMap<u32, String> {
+    let mut thread_names = FxHashMap::default();
+
+    for event in data.iter().filter(|e| e.timestamp.is_instant()) {
+        let event = event.to_event();
+
+        if event.event_kind == THREAD_REGISTRATION_EVENT_KIND
+            || event.event_kind == TRACK_OPENED_EVENT_KIND
+        {
+            thread_names.insert(event.thread_id, event.label.into_owned());
+        }
+    }
+
+    thread_names
+}
+
+/// See `ProfilingData::tracks()`.
+pub(crate) fn tracks(data: &ProfilingData) -> Vec<Track> {
+    let mut tracks = Vec::<Track>::new();
+    let mut closed = FxHashMap::default();
+
+    for event in data.iter().filter(|e| e.timestamp.is_instant()) {
+        let event = event.to_event();
+
+        if event.event_kind == TRACK_OPENED_EVENT_KIND {
+            tracks.push(Track {
+                track_id: event.thread_id,
+                name: event.label.into_owned(),
+                opened: event.timestamp.start(),
+                closed: None,
+            });
+        } else if event.event_kind == TRACK_CLOSED_EVENT_KIND {
+            closed.insert(event.thread_id, event.timestamp.start());
+        }
+    }
+
+    for track in &mut tracks {
+        track.closed = closed.get(&track.track_id).copied();
+    }
+
+    tracks.sort_by_key(|track| track.track_id);
+    tracks
+}
+
+/// See `ProfilingData::external_thread_ids()`.
+pub(crate) fn external_thread_ids(data: &ProfilingData) -> FxHashMap<u32, u64> {
+    let mut external_ids = FxHashMap::default();
+
+    for event in data.iter().filter(|e| e.timestamp.is_instant()) {
+        let event = event.to_event();
+
+        if event.event_kind == EXTERNAL_THREAD_ID_EVENT_KIND {
+            if let Ok(external_id) = event.label.parse() {
+                external_ids.insert(event.thread_id, external_id);
+            }
+        }
+    }
+
+    external_ids
+}
+
+/// See `ProfilingData::thread_ends()`.
+pub(crate) fn thread_ends(data: &ProfilingData) -> Vec<ThreadEnd> {
+    let mut profile_end = data.metadata.start_time;
+    let mut threads = FxHashMap::<u32, (Option<SystemTime>, SystemTime)>::default();
+
+    for event in data.iter() {
+        let end = event.timestamp.end();
+        profile_end = profile_end.max(end);
+
+        let (finished, last_event_end) = threads
+            .entry(event.thread_id)
+            .or_insert((None, data.metadata.start_time));
+
+        let is_finished_marker = event.timestamp.is_instant() && {
+            let event_kind = event.to_event().event_kind;
+            event_kind == THREAD_FINISHED_EVENT_KIND || event_kind == TRACK_CLOSED_EVENT_KIND
+        };
+        if is_finished_marker {
+            *finished = Some(finished.map_or(end, |finished| finished.max(end)));
+        } else {
+            *last_event_end = (*last_event_end).max(end);
+        }
+    }
+
+    let mut thread_ends: Vec<_> = threads
+        .into_iter()
+        .map(|(thread_id, (finished, last_event_end))| ThreadEnd {
+            thread_id,
+            finished,
+            last_event_end,
+            unclosed_time: match finished {
+                Some(_) => Duration::from_nanos(0),
+                None => profile_end
+                    .duration_since(last_event_end)
+                    .unwrap_or_default(),
+            },
+        })
+        .collect();
+
+    thread_ends.sort_by_key(|thread_end| thread_end.thread_id);
+    thread_ends
+}
+
+/// See `ProfilingData::dense_thread_ids()`.
+pub(crate) fn dense_thread_ids(data: &ProfilingData) -> FxHashMap<u32, u32> {
+    let mut dense_ids = FxHashMap::default();
+
+    for event in data.iter() {
+        let next_id = dense_ids.len() as u32;
+        dense_ids.entry(event.thread_id).or_insert(next_id);
+    }
+
+    dense_ids
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ProfilingDataBuilder;
+    use std::time::Duration;
+
+    #[test]
+    fn names_and_dense_ids() {
+        let mut b = ProfilingDataBuilder::new();
+
+        // Thread ids as they would be assigned by `ThreadIdScheme::Os`
+        b.instant(super::THREAD_REGISTRATION_EVENT_KIND, "main", 4711, 0);
+        b.instant(super::THREAD_REGISTRATION_EVENT_KIND, "worker-1", 4790, 1);
+        b.interval("Query", "e1", 4790, 2, 10, |_| {});
+        b.interval("Query", "e2", 38, 3, 11, |_| {});
+        b.interval("Query", "e3", 4711, 1, 12, |_| {});
+
+        let data = b.into_profiling_data();
+
+        let names = data.thread_names();
+        assert_eq!(names.len(), 2);
+        assert_eq!(names[&4711], "main");
+        assert_eq!(names[&4790], "worker-1");
+
+        let dense_ids = data.dense_thread_ids();
+        assert_eq!(dense_ids.len(), 3);
+        assert_eq!(dense_ids[&4711], 0);
+        assert_eq!(dense_ids[&4790], 1);
+        assert_eq!(dense_ids[&38], 2);
+    }
+
+    #[test]
+    fn thread_ends() {
+        let mut b = ProfilingDataBuilder::new();
+
+        b.interval("Query", "e1", 0, 10, 20, |_| {});
+        b.instant(super::THREAD_FINISHED_EVENT_KIND, "", 0, 25);
+        b.interval("Query", "e2", 1, 10, 40, |_| {});
+        b.interval("Query", "e3", 2, 30, 100, |_| {});
+        b.instant(super::THREAD_FINISHED_EVENT_KIND, "", 2, 90);
+
+        let data = b.into_profiling_data();
+        let ends = data.thread_ends();
+        let at = |nanos| data.metadata.start_time + Duration::from_nanos(nanos);
+
+        assert_eq!(ends.len(), 3);
+
+        assert_eq!(ends[0].finished, Some(at(25)));
+        assert_eq!(ends[0].unclosed_time, Duration::from_nanos(0));
+        assert!(ends[0].is_consistent());
+
+        // Thread 1 was still running when the last event, on thread 2, ended.
+        assert_eq!(ends[1].finished, None);
+        assert_eq!(ends[1].last_event_end, at(40));
+        assert_eq!(ends[1].unclosed_time, Duration::from_nanos(60));
+        assert!(ends[1].is_consistent());
+
+        // Thread 2 recorded an event that ended after it finished.
+        assert!(!ends[2].is_consistent());
+    }
+}
@@ -0,0 +1,204 @@
+use crate::{LightweightEvent, ProfilingData};
+use rustc_hash::FxHashMap;
+
+/// An event together with all the events that were recorded while it was
+/// active on the same thread.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TimelineEvent<'a> {
+    pub event: LightweightEvent<'a>,
+    /// The direct children of `event`, ordered by their position in the
+    /// event stream (which is the order in which they happened).
+    pub children: Vec<TimelineEvent<'a>>,
+}
+
+/// All events recorded by a single thread, arranged as a forest of
+/// top-level events and their nested children.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ThreadTimeline<'a> {
+    pub thread_id: u32,
+    pub events: Vec<TimelineEvent<'a>>,
+}
+
+/// Reconstructs the nesting structure of the events on every thread.
+///
+/// This uses the same stack-based algorithm as `summarize`: interval events
+/// occur in the stream at their "end" time, so walking the stream backwards
+/// encounters parents before their children.
+///
+/// Timestamps alone are ambiguous when two intervals have exactly the same
+/// start and end (or a zero-length event sits at the boundary of another
+/// one). In these cases the order in the event stream decides: an event
+/// that was recorded earlier and is contained in a later one is treated as
+/// its child, since a parent is always recorded after all of its children.
+pub(crate) fn per_thread_timelines(data: &ProfilingData) -> Vec<ThreadTimeline<'_>> {
+    #[derive(Default)]
+    struct PerThreadState<'a> {
+        stack: Vec<TimelineEvent<'a>>,
+        // Collected in reverse order, see `finish()`.
+        top_level: Vec<TimelineEvent<'a>>,
+    }
+
+    impl<'a> PerThreadState<'a> {
+        fn pop(&mut self) -> bool {
+            let mut node = match self.stack.pop() {
+                Some(node) => node,
+                None => return false,
+            };
+
+            // We walk the stream backwards, so children have been pushed in
+            // reverse order.
+            node.children.reverse();
+
+            match self.stack.last_mut() {
+                Some(parent) => parent.children.push(node),
+                None => self.top_level.push(node),
+            }
+
+            true
+        }
+
+        fn finish(mut self) -> Vec<TimelineEvent<'a>> {
+            while self.pop() {}
+            self.top_level.reverse();
+            self.top_level
+        }
+    }
+
+    let mut threads = FxHashMap::<u32, PerThreadState<'_>>::default();
+
+    for current_event in data.iter().rev() {
+        let thread = threads.entry(current_event.thread_id).or_default();
+
+        // Pop all events from the stack that are not parents of the
+        // current event.
+        while let Some(current_top) = thread.stack.last() {
+            if current_top.event.contains(&current_event) {
+                break;
+            }
+
+            thread.pop();
+        }
+
+        thread.stack.push(TimelineEvent {
+            event: current_event,
+            children: Vec::new(),
+        });
+    }
+
+    let mut timelines: Vec<_> = threads
+        .into_iter()
+        .map(|(thread_id, state)| ThreadTimeline {
+            thread_id,
+            events: state.finish(),
+        })
+        .collect();
+
+    timelines.sort_by_key(|timeline| timeline.thread_id);
+
+    timelines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ProfilingDataBuilder;
+
+    // Turns the timeline into `(label, children)` pairs so tests can compare
+    // against a readable expected value.
+    #[derive(Debug, PartialEq)]
+    struct Node(String, Vec<Node>);
+
+    fn labels(events: &[TimelineEvent<'_>]) -> Vec<Node> {
+        events
+            .iter()
+            .map(|e| Node(e.event.to_event().label.into_owned(), labels(&e.children)))
+            .collect()
+    }
+
+    fn leaf(label: &str) -> Node {
+        Node(label.to_string(), vec![])
+    }
+
+    fn node(label: &str, children: Vec<Node>) -> Node {
+        Node(label.to_string(), children)
+    }
+
+    #[test]
+    fn nesting_and_order() {
+        //                  <-e4->
+        //      <--e2-->   <--e3-->
+        //  <-----------e1----------->   <--e5-->
+        //  0                       100  110    120
+
+        let mut b = ProfilingDataBuilder::new();
+
+        b.interval("Query", "e1", 0, 0, 100, |b| {
+            b.interval("Query", "e2", 0, 10, 20, |_| {});
+            b.interval("Query", "e3", 0, 30, 50, |b| {
+                b.interval("Query", "e4", 0, 35, 45, |_| {});
+            });
+        });
+        b.interval("Query", "e5", 0, 110, 120, |_| {});
+
+        let data = b.into_profiling_data();
+        let timelines = data.per_thread_timelines();
+
+        assert_eq!(timelines.len(), 1);
+        assert_eq!(timelines[0].thread_id, 0);
+        assert_eq!(
+            labels(&timelines[0].events),
+            vec![
+                node("e1", vec![leaf("e2"), node("e3", vec![leaf("e4")])]),
+                leaf("e5"),
+            ]
+        );
+    }
+
+    #[test]
+    fn identical_timestamps() {
+        // Three intervals covering exactly the same time span, followed by
+        // a zero-length sibling at the end boundary.
+        let mut b = ProfilingDataBuilder::new();
+
+        b.interval("Query", "outer", 0, 10, 20, |b| {
+            b.interval("Query", "middle", 0, 10, 20, |b| {
+                b.interval("Query", "inner", 0, 10, 20, |_| {});
+            });
+        });
+        b.interval("Query", "z1", 0, 20, 20, |_| {});
+
+        let data = b.into_profiling_data();
+        let timelines = data.per_thread_timelines();
+
+        assert_eq!(
+            labels(&timelines[0].events),
+            vec![
+                node("outer", vec![node("middle", vec![leaf("inner")])]),
+                leaf("z1"),
+            ]
+        );
+    }
+
+    #[test]
+    fn instants_and_threads() {
+        let mut b = ProfilingDataBuilder::new();
+
+        b.interval("Query", "e1", 1, 0, 100, |b| {
+            b.instant("QueryCacheHit", "hit", 1, 50);
+        });
+        b.instant("QueryCacheHit", "top", 0, 5);
+        b.interval("Query", "e2", 0, 10, 20, |_| {});
+
+        let data = b.into_profiling_data();
+        let timelines = data.per_thread_timelines();
+
+        assert_eq!(timelines.len(), 2);
+        assert_eq!(timelines[0].thread_id, 0);
+        assert_eq!(labels(&timelines[0].events), vec![leaf("top"), leaf("e2")]);
+        assert_eq!(timelines[1].thread_id, 1);
+        assert_eq!(
+            labels(&timelines[1].events),
+            vec![node("e1", vec![leaf("hit")])]
+        );
+    }
+}
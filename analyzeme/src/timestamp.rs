@@ -1,7 +1,10 @@
 use measureme::RawEvent;
+#[cfg(feature = "serialize")]
+use serde::{Deserialize, Serialize};
 use std::time::{Duration, SystemTime};
 
 #[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
 pub enum Timestamp {
     Interval { start: SystemTime, end: SystemTime },
     Instant(SystemTime),
@@ -0,0 +1,77 @@
+use analyzeme::{annotate, Annotation, ProfilingData, Timestamp};
+use measureme::{ArgType, EventId, EventIdBuilder, FileSerializationSink, Profiler, ToolInfo};
+use std::path::Path;
+use std::time::Duration;
+
+#[test]
+fn annotated_copy() {
+    let dir = Path::new("test-tmp").join("annotate");
+    let path_stem = dir.join("original");
+    let annotated_stem = dir.join("annotated");
+
+    {
+        let profiler = Profiler::<FileSerializationSink>::new(&path_stem).unwrap();
+        profiler.register_arg_schema("Query", &[("key", ArgType::String)]);
+        profiler.set_tool_info(ToolInfo {
+            name: "rustc".to_string(),
+            version: "1.80.0".to_string(),
+            ..ToolInfo::default()
+        });
+
+        let kind = profiler.alloc_string("Query");
+        let label = profiler.alloc_string("typeck");
+        let arg = profiler.alloc_string("main");
+        let event_id = EventIdBuilder::new(&profiler).from_label_and_arg(label, arg);
+        drop(profiler.start_recording_interval_event(kind, event_id, 3));
+
+        let kind = profiler.register_event_kind("Marker");
+        profiler.record_instant_event(kind, EventId::from_label(label), 3);
+    }
+
+    let data = ProfilingData::new(&path_stem).unwrap();
+    let link = Annotation::new("link", Duration::from_secs(5), Duration::from_millis(800));
+    let on_thread = Annotation {
+        thread_id: Some(3),
+        event_kind: "TimePasses".to_string(),
+        ..Annotation::new("llvm", Duration::from_secs(2), Duration::from_secs(1))
+    };
+    annotate(&data, &[link, on_thread], &annotated_stem).unwrap();
+
+    let annotated = ProfilingData::new(&annotated_stem).unwrap();
+    assert_eq!(annotated.metadata.start_time, data.metadata.start_time);
+    assert_eq!(annotated.metadata.cmd, data.metadata.cmd);
+    assert_eq!(annotated.metadata.tool.as_ref().unwrap().name, "rustc");
+    assert_eq!(annotated.metadata.arg_schemas["Query"][0].name, "key");
+
+    let events: Vec<_> = annotated
+        .iter()
+        .map(|e| e.to_event())
+        .map(|e| {
+            let args: Vec<_> = e.additional_data.iter().map(|a| a.to_string()).collect();
+            (
+                e.event_kind.into_owned(),
+                e.label.into_owned(),
+                args,
+                e.thread_id,
+            )
+        })
+        .collect();
+    assert_eq!(
+        events,
+        vec![
+            ("Query".into(), "typeck".into(), vec!["main".into()], 3),
+            ("Marker".into(), "typeck".into(), vec![], 3),
+            ("TimePasses".into(), "llvm".into(), vec![], 3),
+            ("Annotation".into(), "link".into(), vec![], 4),
+        ]
+    );
+
+    let link = annotated.iter().next_back().unwrap().to_event();
+    assert_eq!(
+        link.timestamp,
+        Timestamp::Interval {
+            start: data.metadata.start_time + Duration::from_secs(5),
+            end: data.metadata.start_time + Duration::from_millis(5800),
+        }
+    );
+}
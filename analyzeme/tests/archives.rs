@@ -0,0 +1,93 @@
+#![cfg(feature = "archives")]
+
+use analyzeme::testing_common::{query_labels, record_query as record};
+use analyzeme::ProfilingData;
+use measureme::ProfilerFiles;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// The files of the profile with the given path stem, and the names they
+/// get in the archive.
+fn files(path_stem: &Path, prefix: &str) -> Vec<(PathBuf, String)> {
+    let files = ProfilerFiles::new(path_stem);
+    [
+        files.events_file,
+        files.string_data_file,
+        files.string_index_file,
+    ]
+    .iter()
+    .map(|path| {
+        let name = format!("{}{}", prefix, path.file_name().unwrap().to_str().unwrap());
+        (path.clone(), name)
+    })
+    .collect()
+}
+
+fn write_tar(writer: impl Write, files: &[(PathBuf, String)]) {
+    let mut builder = tar::Builder::new(writer);
+    for (path, name) in files {
+        builder.append_path_with_name(path, name).unwrap();
+    }
+    builder.into_inner().unwrap().flush().unwrap();
+}
+
+fn write_zip(path: &Path, files: &[(PathBuf, String)]) {
+    let mut writer = zip::ZipWriter::new(File::create(path).unwrap());
+    let options = zip::write::FileOptions::default();
+    for (file, name) in files {
+        writer.start_file(name, options).unwrap();
+        writer.write_all(&std::fs::read(file).unwrap()).unwrap();
+    }
+    writer.finish().unwrap();
+}
+
+fn labels(path: &Path) -> Vec<String> {
+    query_labels(&ProfilingData::new(path).unwrap())
+}
+
+#[test]
+fn load_from_archives() {
+    let dir = Path::new("test-tmp").join("archives");
+    let typeck = dir.join("foo-1234");
+    let borrowck = dir.join("bar-5678");
+    record(&typeck, "typeck");
+    record(&borrowck, "mir_borrowck");
+
+    let single = files(&typeck, "target/profiles/");
+
+    let tar = dir.join("single.tar");
+    write_tar(File::create(&tar).unwrap(), &single);
+    assert_eq!(labels(&tar), vec!["typeck"]);
+
+    let tar_gz = dir.join("single.tar.gz");
+    write_tar(
+        flate2::write::GzEncoder::new(
+            File::create(&tar_gz).unwrap(),
+            flate2::Compression::default(),
+        ),
+        &single,
+    );
+    assert_eq!(labels(&tar_gz), vec!["typeck"]);
+
+    let zip = dir.join("single.zip");
+    write_zip(&zip, &single);
+    assert_eq!(labels(&zip), vec!["typeck"]);
+    assert_eq!(labels(&zip.join("foo-1234")), vec!["typeck"]);
+    assert_eq!(
+        labels(&zip.join("target/profiles/foo-1234")),
+        vec!["typeck"]
+    );
+    assert!(ProfilingData::new(&zip.join("bar-5678")).is_err());
+
+    // With several profiles in the archive, one has to be selected.
+    let mut both = single;
+    both.extend(files(&borrowck, ""));
+    let zip = dir.join("both.zip");
+    write_zip(&zip, &both);
+
+    let error = ProfilingData::new(&zip).err().unwrap().to_string();
+    assert!(error.contains("contains several profiles"), "{}", error);
+    assert_eq!(labels(&zip.join("bar-5678")), vec!["mir_borrowck"]);
+    assert_eq!(labels(&zip.join("foo-1234")), vec!["typeck"]);
+}
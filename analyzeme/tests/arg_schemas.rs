@@ -0,0 +1,127 @@
+use analyzeme::{Arg, ArgValue, ProfilingData, SymbolMap};
+use measureme::event_id::SEPARATOR_BYTE;
+use measureme::{ArgType, EventId, FileSerializationSink, Profiler, StringComponent};
+use std::borrow::Cow;
+use std::path::Path;
+
+#[test]
+fn decode_typed_args() {
+    let filestem = Path::new("test-tmp")
+        .join("arg_schemas")
+        .join("decode_typed_args");
+
+    {
+        let profiler = Profiler::<FileSerializationSink>::new(&filestem).unwrap();
+
+        // Register a schema, then replace it, to check that the last version
+        // wins.
+        profiler.register_arg_schema("Read", &[("bytes", ArgType::String)]);
+        profiler.register_arg_schema(
+            "Read",
+            &[("bytes", ArgType::U64), ("cached", ArgType::Bool)],
+        );
+        profiler.register_arg_schema("Open", &[("path \"quoted\"", ArgType::String)]);
+
+        let event_id = |label: &str, args: &[&str]| {
+            let mut components = vec![StringComponent::Value(label)];
+            for arg in args {
+                components.push(StringComponent::Value(SEPARATOR_BYTE));
+                components.push(StringComponent::Value(arg));
+            }
+            EventId::from_virtual(profiler.alloc_string(&components[..]))
+        };
+
+        let read = profiler.alloc_string("Read");
+        let open = profiler.alloc_string("Open");
+        let other = profiler.alloc_string("Other");
+
+        profiler.record_instant_event(read, event_id("read", &["4096", "true", "extra"]), 0);
+        profiler.record_instant_event(read, event_id("read", &["lots", "no"]), 0);
+        profiler.record_instant_event(open, event_id("open", &["/tmp/x"]), 0);
+        profiler.record_instant_event(other, event_id("other", &["1"]), 0);
+    }
+
+    let data = ProfilingData::new(&filestem).unwrap();
+    let events: Vec<_> = data.iter().map(|e| e.to_event()).collect();
+    let args: Vec<Vec<Arg<'_>>> = events.iter().map(|e| data.decode_args(e)).collect();
+
+    let string = |s: &'static str| ArgValue::String(Cow::from(s));
+
+    assert_eq!(
+        args[0],
+        vec![
+            Arg {
+                name: Some("bytes"),
+                value: ArgValue::U64(4096)
+            },
+            Arg {
+                name: Some("cached"),
+                value: ArgValue::Bool(true)
+            },
+            Arg {
+                name: None,
+                value: string("extra")
+            },
+        ]
+    );
+
+    // Values that don't match the declared type are kept as strings.
+    assert_eq!(args[1][0].value, string("lots"));
+    assert_eq!(args[1][1].value, string("no"));
+
+    assert_eq!(args[2][0].to_string(), "path \"quoted\"=/tmp/x");
+    assert_eq!(args[3][0].to_string(), "1");
+
+    let rendered: Vec<String> = args[0].iter().map(|arg| arg.to_string()).collect();
+    assert_eq!(rendered, ["bytes=4096", "cached=true", "extra"]);
+}
+
+#[test]
+fn symbolize_addresses() {
+    let filestem = Path::new("test-tmp")
+        .join("arg_schemas")
+        .join("symbolize_addresses");
+
+    {
+        let profiler = Profiler::<FileSerializationSink>::new(&filestem).unwrap();
+        profiler.register_arg_schema("JitCall", &[("target", ArgType::Address)]);
+
+        let kind = profiler.alloc_string("JitCall");
+        for address in &[0x7f00_0000_1008u64, 0x7f00_0000_9000] {
+            let components = [
+                StringComponent::Value("call"),
+                StringComponent::Value(SEPARATOR_BYTE),
+                StringComponent::Value(&format!("{:#x}", address)),
+            ];
+            let event_id = EventId::from_virtual(profiler.alloc_string(&components[..]));
+            profiler.record_instant_event(kind, event_id, 0);
+        }
+    }
+
+    let mut data = ProfilingData::new(&filestem).unwrap();
+
+    let rendered = |data: &ProfilingData| -> Vec<String> {
+        data.iter()
+            .map(|e| data.decode_args(&e.to_event())[0].to_string())
+            .collect()
+    };
+
+    let first = data.iter().next().unwrap().to_event();
+    assert_eq!(
+        data.decode_args(&first)[0].value,
+        ArgValue::Address {
+            address: 0x7f00_0000_1008,
+            symbol: None
+        }
+    );
+    assert_eq!(
+        rendered(&data),
+        ["target=0x7f0000001008", "target=0x7f0000009000"]
+    );
+
+    data.set_symbolizer(SymbolMap::parse("7f0000001000 40 jit::fib\n").unwrap());
+    assert_eq!(
+        rendered(&data),
+        ["target=jit::fib+0x8", "target=0x7f0000009000"]
+    );
+}
@@ -0,0 +1,70 @@
+use analyzeme::{ProfileSummary, ProfilingData};
+use measureme::{
+    BudgetPolicy, EventId, FileSerializationSink, Profiler, ProfilerConfig, RecordingBudget,
+};
+use std::path::Path;
+
+fn record(name: &str, budget: RecordingBudget) -> ProfilingData {
+    let path_stem = Path::new("test-tmp").join("budget").join(name);
+    let config = ProfilerConfig {
+        budget,
+        ..ProfilerConfig::default()
+    };
+
+    {
+        let profiler = Profiler::<FileSerializationSink>::with_config(&path_stem, &config).unwrap();
+        let kind = profiler.alloc_string("Query");
+        let label = EventId::from_label(profiler.alloc_string("typeck"));
+        for _ in 0..10 {
+            drop(profiler.start_recording_interval_event(kind, label, 0));
+        }
+    }
+
+    ProfilingData::new(&path_stem).unwrap()
+}
+
+#[test]
+fn max_events() {
+    let data = record(
+        "max_events",
+        RecordingBudget {
+            max_events: Some(4),
+            ..RecordingBudget::default()
+        },
+    );
+
+    assert!(data.metadata.budget_exhausted);
+    assert_eq!(data.num_events(), 4);
+
+    // Within the budget, the profile isn't marked.
+    let data = record(
+        "within_budget",
+        RecordingBudget {
+            max_events: Some(10),
+            ..RecordingBudget::default()
+        },
+    );
+    assert!(!data.metadata.budget_exhausted);
+    assert_eq!(data.num_events(), 10);
+}
+
+#[test]
+fn aggregate_only_when_exhausted() {
+    let data = record(
+        "aggregate_only",
+        RecordingBudget {
+            max_events: Some(4),
+            max_bytes: None,
+            when_exhausted: BudgetPolicy::AggregateOnly,
+        },
+    );
+
+    assert!(data.metadata.budget_exhausted);
+    assert_eq!(data.num_events(), 4);
+
+    // The summary still counts all events.
+    let summary =
+        ProfileSummary::new(&Path::new("test-tmp").join("budget").join("aggregate_only")).unwrap();
+    assert_eq!(summary.labels.len(), 1);
+    assert_eq!(summary.labels[0].count, 10);
+}
@@ -0,0 +1,71 @@
+use analyzeme::{merge_profiles, ProfilingData};
+use measureme::build_tools::BuildToolKind;
+use measureme::{BuildToolProfiler, EventId, FileSerializationSink, Profiler};
+use std::path::Path;
+
+fn events(data: &ProfilingData) -> Vec<(String, String, Vec<String>)> {
+    data.iter()
+        .map(|e| e.to_event())
+        .filter(|e| e.event_kind == "Query" || e.event_kind == "ProcMacro")
+        .map(|e| {
+            let args = e.additional_data.iter().map(|a| a.to_string()).collect();
+            (e.event_kind.into_owned(), e.label.into_owned(), args)
+        })
+        .collect()
+}
+
+// The environment is process-wide, so everything that depends on it happens
+// in a single test.
+#[test]
+fn proc_macro_profiles() {
+    let dir = Path::new("test-tmp").join("build_tools");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+
+    std::env::remove_var("MEASUREME_OUT_DIR");
+    std::env::set_var("CARGO_CRATE_NAME", "regex");
+    assert!(BuildToolProfiler::from_env("serde_derive", "1.0.0")
+        .unwrap()
+        .is_none());
+
+    std::env::set_var("MEASUREME_OUT_DIR", &dir);
+    let profiler = BuildToolProfiler::from_env("serde_derive", "1.0.0")
+        .unwrap()
+        .unwrap();
+    assert_eq!(profiler.invoking_crate(), "regex");
+    assert_eq!(profiler.kind(), BuildToolKind::ProcMacro);
+    drop(profiler.time_with_arg("derive(Serialize)", "Regex"));
+    drop(profiler);
+    std::env::remove_var("MEASUREME_OUT_DIR");
+
+    let path_stem = dir.join(format!("regex.serde_derive.0-{}", std::process::id()));
+    let proc_macro = ProfilingData::new(&path_stem).unwrap();
+
+    let tool = proc_macro.metadata.tool.as_ref().unwrap();
+    assert_eq!(tool.name, "serde_derive");
+    assert_eq!(tool.invoking_crate(), Some("regex"));
+
+    let rustc = {
+        let path_stem = dir.join("regex-1234");
+        let profiler = Profiler::<FileSerializationSink>::new(&path_stem).unwrap();
+        let kind = profiler.alloc_string("Query");
+        let label = EventId::from_label(profiler.alloc_string("typeck"));
+        drop(profiler.start_recording_interval_event(kind, label, 0));
+        drop(profiler);
+        ProfilingData::new(&path_stem).unwrap()
+    };
+
+    let merged = merge_profiles(&[rustc, proc_macro]);
+    assert!(merged.metadata.tool.is_none());
+    assert_eq!(
+        events(&merged),
+        vec![
+            (
+                "ProcMacro".to_string(),
+                "derive(Serialize)".to_string(),
+                vec!["Regex".to_string()]
+            ),
+            ("Query".to_string(), "typeck".to_string(), vec![]),
+        ]
+    );
+}
@@ -0,0 +1,129 @@
+use analyzeme::{LoadError, LoadErrorKind, ProfilingData};
+use measureme::{EventId, FileSerializationSink, Profiler, ProfilerFiles};
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+fn record_profile(file_name_stem: &str) -> PathBuf {
+    let filestem = Path::new("test-tmp").join("checksums").join(file_name_stem);
+
+    let profiler = Profiler::<FileSerializationSink>::new(&filestem).unwrap();
+    let kind = profiler.alloc_string("Generic");
+    let event_id = EventId::from_label(profiler.alloc_string("event"));
+
+    for _ in 0..100 {
+        profiler.record_instant_event(kind, event_id, 0);
+    }
+
+    filestem
+}
+
+fn expect_corrupted(filestem: &Path) {
+    let error = ProfilingData::new(filestem).unwrap_err().to_string();
+    assert!(error.contains("corrupted in transit"), "{}", error);
+}
+
+#[test]
+fn intact_profile() {
+    let filestem = record_profile("intact_profile");
+    assert_eq!(ProfilingData::new(&filestem).unwrap().num_events(), 100);
+}
+
+#[test]
+fn damaged_events_file() {
+    let filestem = record_profile("damaged_events_file");
+    let path = ProfilerFiles::new(&filestem).events_file;
+
+    let mut data = fs::read(&path).unwrap();
+    data[100] ^= 0x01;
+    fs::write(&path, data).unwrap();
+
+    expect_corrupted(&filestem);
+}
+
+#[test]
+fn incomplete_string_data_file() {
+    let filestem = record_profile("incomplete_string_data_file");
+    let path = ProfilerFiles::new(&filestem).string_data_file;
+
+    let data = fs::read(&path).unwrap();
+    fs::write(&path, &data[..data.len() - 5]).unwrap();
+
+    expect_corrupted(&filestem);
+}
+
+#[test]
+fn profile_from_bytes() {
+    let filestem = record_profile("profile_from_bytes");
+    let files = ProfilerFiles::new(&filestem);
+
+    let read = |path: &Path| fs::read(path).unwrap();
+    let data = ProfilingData::from_bytes(
+        read(&files.events_file),
+        read(&files.string_data_file),
+        read(&files.string_index_file),
+    )
+    .unwrap();
+    assert_eq!(data.num_events(), 100);
+
+    let mut events = read(&files.events_file);
+    events[100] ^= 0x01;
+    let error = ProfilingData::from_bytes(
+        events,
+        read(&files.string_data_file),
+        read(&files.string_index_file),
+    )
+    .unwrap_err()
+    .to_string();
+    assert!(error.starts_with("`events`: "), "{}", error);
+}
+
+#[test]
+fn profile_from_readers() {
+    let filestem = record_profile("profile_from_readers");
+    let files = ProfilerFiles::new(&filestem);
+
+    let open = |path: &Path| fs::File::open(path).unwrap();
+    let data = ProfilingData::from_readers(
+        open(&files.events_file),
+        open(&files.string_data_file),
+        open(&files.string_index_file),
+    )
+    .unwrap();
+    assert_eq!(data.num_events(), 100);
+
+    // A stream that ends early is reported like an incomplete file.
+    let events = fs::read(&files.events_file).unwrap();
+    let error = ProfilingData::from_readers(
+        &events[..events.len() - 5],
+        open(&files.string_data_file),
+        open(&files.string_index_file),
+    )
+    .unwrap_err()
+    .to_string();
+    assert!(error.starts_with("`events`: "), "{}", error);
+
+    struct Failing;
+    impl Read for Failing {
+        fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+            Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "the producer exited",
+            ))
+        }
+    }
+    let error = ProfilingData::from_readers(
+        open(&files.events_file),
+        Failing,
+        open(&files.string_index_file),
+    )
+    .unwrap_err();
+    assert_eq!(
+        LoadError::kind_of(&*error),
+        Some(LoadErrorKind::FileUnreadable)
+    );
+    assert_eq!(
+        error.to_string(),
+        "couldn't read the string_data stream: the producer exited"
+    );
+}
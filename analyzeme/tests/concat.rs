@@ -0,0 +1,106 @@
+use analyzeme::{concatenate_profiles, ProfilingData};
+use measureme::{
+    EventId, EventLayout, FileSerializationSink, Profiler, ProfilerConfig, ProfilerFiles, Rollover,
+    RAW_EVENT_SIZE,
+};
+use std::fs;
+use std::path::Path;
+
+fn labels(data: &ProfilingData) -> Vec<String> {
+    data.iter()
+        .map(|e| e.to_event().label.into_owned())
+        .collect()
+}
+
+// Copies the files of the profile to `dir`, without the listed segments.
+fn copy_without_segments(path_stem: &Path, dir: &Path, deleted: &[usize]) {
+    fs::create_dir_all(dir).unwrap();
+    let stem = path_stem.file_name().unwrap().to_str().unwrap();
+    for entry in fs::read_dir(path_stem.parent().unwrap()).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_file()
+            && path
+                .file_name()
+                .unwrap()
+                .to_str()
+                .unwrap()
+                .starts_with(stem)
+        {
+            fs::copy(&path, dir.join(path.file_name().unwrap())).unwrap();
+        }
+    }
+
+    let files = ProfilerFiles::new(&dir.join(stem));
+    for &index in deleted {
+        fs::remove_file(files.segment_events_file(index)).unwrap();
+    }
+}
+
+#[test]
+fn concatenated_segments() {
+    let dir = Path::new("test-tmp").join("concat");
+    let path_stem = dir.join("recording").join("profile");
+    fs::create_dir_all(path_stem.parent().unwrap()).unwrap();
+
+    let config = ProfilerConfig {
+        event_layout: EventLayout::Rollover(Rollover {
+            max_bytes: Some(10 * RAW_EVENT_SIZE as u64),
+            ..Rollover::default()
+        }),
+        ..ProfilerConfig::default()
+    };
+    {
+        let profiler = Profiler::<FileSerializationSink>::with_config(&path_stem, &config).unwrap();
+        let kind = profiler.alloc_string("Query");
+        for i in 0..25 {
+            let id = EventId::from_label(profiler.alloc_string(&*format!("work-{}", i)));
+            drop(profiler.start_recording_interval_event(kind, id, 0));
+        }
+    }
+    let original = ProfilingData::new(&path_stem).unwrap();
+    assert_eq!(original.metadata.event_segments.len(), 3);
+
+    // The first segment has been archived, the others are still where the
+    // profile has been recorded.
+    let archived = dir.join("archived");
+    let recent = dir.join("recent");
+    copy_without_segments(&path_stem, &archived, &[1, 2]);
+    copy_without_segments(&path_stem, &recent, &[0]);
+    let load = |dir: &Path| ProfilingData::new(&dir.join("profile")).unwrap();
+    let shards = [load(&recent), load(&archived)];
+    assert_eq!(shards[1].num_events(), 10);
+
+    let concatenated_stem = dir.join("concatenated");
+    concatenate_profiles(&shards, &concatenated_stem).unwrap();
+
+    let concatenated = ProfilingData::new(&concatenated_stem).unwrap();
+    assert!(concatenated.warnings().is_empty());
+    assert!(concatenated.metadata.event_segments.is_empty());
+    assert_eq!(
+        concatenated.metadata.start_time,
+        original.metadata.start_time
+    );
+    assert_eq!(labels(&concatenated), labels(&original));
+
+    // The same segment twice is rejected, as is a shard of another
+    // recording.
+    let error =
+        concatenate_profiles(&[load(&archived), load(&archived)], &concatenated_stem).unwrap_err();
+    assert!(
+        error.to_string().contains("overlap on thread 0"),
+        "{}",
+        error
+    );
+
+    let other_stem = dir.join("other");
+    drop(Profiler::<FileSerializationSink>::new(&other_stem).unwrap());
+    let other = ProfilingData::new(&other_stem).unwrap();
+    let error = concatenate_profiles(&[load(&archived), other], &concatenated_stem).unwrap_err();
+    assert!(
+        error.to_string().contains("not of the same recording"),
+        "{}",
+        error
+    );
+
+    assert!(concatenate_profiles(&[], &concatenated_stem).is_err());
+}
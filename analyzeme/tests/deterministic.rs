@@ -0,0 +1,84 @@
+use analyzeme::ProfilingData;
+use measureme::config::Clock;
+use measureme::{
+    ArgType, EventIdBuilder, FileSerializationSink, Profiler, ProfilerConfig, ProfilerFiles,
+    SharedStringCache,
+};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+fn config() -> ProfilerConfig {
+    ProfilerConfig {
+        clock: Clock::Logical,
+        summary: true,
+        record_cpu: true,
+        deterministic: true,
+        ..ProfilerConfig::default()
+    }
+}
+
+fn record(dir: &Path) -> PathBuf {
+    let path_stem = dir.join("profile");
+    let config = config();
+
+    let cache =
+        SharedStringCache::<FileSerializationSink>::with_config(&dir.join("shared"), &config)
+            .unwrap();
+
+    let mut profiler = Profiler::<FileSerializationSink>::with_config(&path_stem, &config).unwrap();
+    profiler.set_shared_string_cache(Arc::new(cache));
+    profiler.register_arg_schema("Query", &[("key", ArgType::String)]);
+
+    let thread_id = profiler.register_current_thread();
+    let query = profiler.register_event_kind("Query");
+    let typeck = profiler.intern_shared_string("typeck");
+    let builder = EventIdBuilder::new(&profiler);
+
+    profiler.start_phase("analysis");
+    for key in &["foo", "bar", "foo"] {
+        let event_id = builder.from_label_and_arg(typeck, profiler.alloc_string(*key));
+        drop(profiler.start_recording_interval_event(query, event_id, thread_id));
+    }
+    profiler.end_phase();
+
+    path_stem
+}
+
+fn files(path_stem: &Path) -> Vec<Vec<u8>> {
+    let files = ProfilerFiles::new(path_stem);
+    let shared = ProfilerFiles::new(&path_stem.with_file_name("shared"));
+    [
+        files.events_file,
+        files.string_data_file,
+        files.string_index_file,
+        files.summary_file,
+        shared.string_data_file,
+        shared.string_index_file,
+    ]
+    .iter()
+    .map(|path| std::fs::read(path).unwrap())
+    .collect()
+}
+
+#[test]
+fn identical_runs_produce_identical_files() {
+    let dir = Path::new("test-tmp").join("deterministic");
+    let first = record(&dir.join("first"));
+    let second = record(&dir.join("second"));
+
+    assert!(files(&first) == files(&second));
+
+    let data = ProfilingData::new(&first).unwrap();
+    assert_eq!(data.metadata.process_id, 0);
+    assert_eq!(data.metadata.cmd, "");
+    assert_eq!(data.metadata.system, None);
+
+    // The logical clock ticks once per timestamp.
+    let queries: Vec<_> = data
+        .iter()
+        .map(|e| e.to_event())
+        .filter(|e| e.event_kind == "Query")
+        .collect();
+    assert_eq!(queries.len(), 3);
+    assert_eq!(queries[0].duration().unwrap().as_nanos(), 1);
+}
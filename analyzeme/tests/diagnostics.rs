@@ -0,0 +1,139 @@
+use analyzeme::{LoadError, LoadErrorKind, ProfilingData};
+use measureme::checksum::checksum;
+use measureme::file_header::{
+    file_footer, CURRENT_FILE_FORMAT_VERSION, FILE_FOOTER_SIZE, FILE_HEADER_SIZE,
+};
+use measureme::{FileSerializationSink, Profiler, ProfilerFiles};
+use std::path::{Path, PathBuf};
+
+fn write_profile(name: &str) -> (PathBuf, ProfilerFiles) {
+    let path_stem = Path::new("test-tmp").join("diagnostics").join(name);
+    drop(Profiler::<FileSerializationSink>::new(&path_stem).unwrap());
+    let files = ProfilerFiles::new(&path_stem);
+    (path_stem, files)
+}
+
+fn load_error_kind(path_stem: &Path) -> Option<LoadErrorKind> {
+    let error = ProfilingData::new(path_stem).err().unwrap();
+    LoadError::kind_of(&*error)
+}
+
+fn modify(path: &Path, f: impl FnOnce(&mut Vec<u8>)) {
+    let mut data = std::fs::read(path).unwrap();
+    f(&mut data);
+    std::fs::write(path, data).unwrap();
+}
+
+#[test]
+fn missing_profile() {
+    let path_stem = Path::new("test-tmp").join("diagnostics").join("missing");
+    assert_eq!(
+        load_error_kind(&path_stem),
+        Some(LoadErrorKind::FileMissing)
+    );
+}
+
+#[test]
+fn truncated_profile() {
+    let (path_stem, files) = write_profile("truncated");
+    modify(&files.events_file, |data| data.truncate(data.len() - 1));
+    assert_eq!(load_error_kind(&path_stem), Some(LoadErrorKind::Truncated));
+}
+
+#[test]
+fn corrupt_profile() {
+    let (path_stem, files) = write_profile("corrupt");
+    modify(&files.string_data_file, |data| {
+        data[FILE_HEADER_SIZE] ^= 0xff
+    });
+    assert_eq!(load_error_kind(&path_stem), Some(LoadErrorKind::Corrupt));
+}
+
+#[test]
+fn not_a_profile() {
+    let (path_stem, files) = write_profile("not_a_profile");
+    std::fs::write(&files.events_file, b"this is not a profile").unwrap();
+    assert_eq!(
+        load_error_kind(&path_stem),
+        Some(LoadErrorKind::NotAProfile)
+    );
+}
+
+/// Overwrites a field of the header and updates the footer, as if the file
+/// had been written by a different version of `measureme`.
+fn set_header_field(data: &mut Vec<u8>, offset: usize, value: u32) {
+    data.truncate(data.len() - FILE_FOOTER_SIZE);
+    data[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+    let footer = file_footer(checksum(data));
+    data.extend_from_slice(&footer);
+}
+
+#[test]
+fn format_too_new() {
+    let (path_stem, files) = write_profile("too_new");
+
+    // A newer writer that older readers can still read.
+    modify(&files.events_file, |data| {
+        set_header_field(data, 4, CURRENT_FILE_FORMAT_VERSION + 1)
+    });
+    assert!(ProfilingData::new(&path_stem).is_ok());
+
+    // A newer writer that needs a newer reader.
+    modify(&files.events_file, |data| {
+        set_header_field(data, 8, CURRENT_FILE_FORMAT_VERSION + 1)
+    });
+    assert_eq!(
+        load_error_kind(&path_stem),
+        Some(LoadErrorKind::FormatTooNew)
+    );
+}
+
+#[test]
+fn mismatched_files() {
+    let (path_stem, files) = write_profile("mismatched");
+    let (_, other_files) = write_profile("mismatched_other");
+
+    // The string index of a different run.
+    std::fs::copy(&other_files.string_index_file, &files.string_index_file).unwrap();
+    assert_eq!(load_error_kind(&path_stem), Some(LoadErrorKind::Mismatched));
+
+    // The whole string table of a different run.
+    std::fs::copy(&other_files.string_data_file, &files.string_data_file).unwrap();
+    let error = ProfilingData::new(&path_stem).err().unwrap();
+    assert_eq!(LoadError::kind_of(&*error), Some(LoadErrorKind::Mismatched));
+    assert!(error.to_string().contains("mismatched.events"), "{}", error);
+}
+
+#[test]
+fn load_all() {
+    let dir = Path::new("test-tmp").join("diagnostics");
+    let path_stems: Vec<_> = (0..5)
+        .map(|i| {
+            let path_stem = dir.join(format!("load_all_{}", i));
+            let profiler = Profiler::<FileSerializationSink>::new(&path_stem).unwrap();
+            let kind = profiler.alloc_string("Marker");
+            for _ in 0..i {
+                profiler.record_instant_event(kind, measureme::EventId::INVALID, 0);
+            }
+            path_stem
+        })
+        .collect();
+
+    let profiles = ProfilingData::load_all(&path_stems).unwrap();
+    let event_counts: Vec<_> = profiles.iter().map(|data| data.num_events()).collect();
+    assert_eq!(event_counts, vec![0, 1, 2, 3, 4]);
+
+    // The error of the first profile that can't be loaded, with its kind.
+    let mut path_stems = path_stems;
+    path_stems.insert(2, dir.join("load_all_missing"));
+    let (_, files) = write_profile("load_all_truncated");
+    modify(&files.events_file, |data| data.truncate(data.len() - 1));
+    path_stems.push(dir.join("load_all_truncated"));
+
+    let error = ProfilingData::load_all(&path_stems).err().unwrap();
+    assert_eq!(
+        LoadError::kind_of(&*error),
+        Some(LoadErrorKind::FileMissing)
+    );
+    assert!(error.to_string().contains("load_all_missing"), "{}", error);
+}
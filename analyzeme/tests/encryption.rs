@@ -0,0 +1,91 @@
+use analyzeme::ProfilingData;
+use measureme::checksum::checksum;
+use measureme::{
+    EncryptedSerializationSink, EventId, FileSerializationSink, ProfileCipher, Profiler,
+};
+use std::error::Error;
+use std::path::Path;
+
+/// A toy cipher for testing: XORs the data with the key and appends a
+/// checksum as the "authentication tag". Don't use this for anything real.
+struct ToyCipher {
+    key: u8,
+}
+
+impl ToyCipher {
+    fn tag(&self, associated_data: &[u8], plaintext: &[u8]) -> [u8; 8] {
+        let mut data = vec![self.key];
+        data.extend_from_slice(associated_data);
+        data.extend_from_slice(plaintext);
+        checksum(&data).to_le_bytes()
+    }
+}
+
+impl ProfileCipher for ToyCipher {
+    fn load() -> Result<Self, Box<dyn Error>> {
+        Ok(ToyCipher { key: 0x5A })
+    }
+
+    fn encrypt(&self, associated_data: &[u8], plaintext: &[u8]) -> Vec<u8> {
+        let mut ciphertext: Vec<u8> = plaintext.iter().map(|b| b ^ self.key).collect();
+        ciphertext.extend_from_slice(&self.tag(associated_data, plaintext));
+        ciphertext
+    }
+
+    fn decrypt(
+        &self,
+        associated_data: &[u8],
+        ciphertext: &[u8],
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        let (data, tag) = ciphertext.split_at(ciphertext.len().checked_sub(8).ok_or("too short")?);
+        let plaintext: Vec<u8> = data.iter().map(|b| b ^ self.key).collect();
+
+        if self.tag(associated_data, &plaintext) != tag {
+            Err("authentication failed")?;
+        }
+
+        Ok(plaintext)
+    }
+}
+
+#[test]
+fn encrypted_profile() {
+    let filestem = Path::new("test-tmp")
+        .join("encryption")
+        .join("encrypted_profile");
+
+    {
+        let profiler =
+            Profiler::<EncryptedSerializationSink<FileSerializationSink, ToyCipher>>::new(
+                &filestem,
+            )
+            .unwrap();
+        let kind = profiler.alloc_string("Generic");
+
+        // Enough events for a couple of chunks.
+        for i in 0..10_000 {
+            let event_id =
+                EventId::from_label(profiler.alloc_string(&format!("/secret/path/{}", i)[..]));
+            profiler.record_instant_event(kind, event_id, 0);
+        }
+    }
+
+    let events_file = std::fs::read(filestem.with_extension("events")).unwrap();
+    assert!(!events_file.windows(6).any(|w| w == b"secret"));
+
+    let error = ProfilingData::new(&filestem).unwrap_err().to_string();
+    assert!(error.contains("is encrypted"), "{}", error);
+
+    let wrong_key = ToyCipher { key: 0x42 };
+    let error = ProfilingData::new_encrypted(&filestem, &wrong_key)
+        .unwrap_err()
+        .to_string();
+    assert!(error.contains("could not decrypt"), "{}", error);
+
+    let data = ProfilingData::new_encrypted(&filestem, &ToyCipher::load().unwrap()).unwrap();
+    assert_eq!(data.num_events(), 10_000);
+    assert_eq!(
+        data.iter().next_back().unwrap().to_event().label,
+        "/secret/path/9999"
+    );
+}
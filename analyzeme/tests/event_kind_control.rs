@@ -0,0 +1,64 @@
+use analyzeme::ProfilingData;
+use measureme::control::POLL_INTERVAL;
+use measureme::{EventId, FileSerializationSink, Profiler, ProfilerConfig};
+use std::path::Path;
+
+#[test]
+fn switch_event_kinds_via_control_file() {
+    let dir = Path::new("test-tmp").join("event_kind_control");
+    std::fs::create_dir_all(&dir).unwrap();
+    let path_stem = dir.join("profile");
+    let control_file = dir.join("control");
+    std::fs::write(&control_file, "# Too expensive for now\nVerbose off\n").unwrap();
+
+    let config = ProfilerConfig {
+        control_file: Some(control_file.clone()),
+        ..ProfilerConfig::default()
+    };
+
+    {
+        let profiler = Profiler::<FileSerializationSink>::with_config(&path_stem, &config).unwrap();
+        let query = profiler.register_event_kind("Query");
+        let verbose = profiler.register_event_kind("Verbose");
+        // Not registered, so it can't be switched.
+        let generic = profiler.alloc_string("Verbose");
+
+        let record = |label: &str| {
+            let label = EventId::from_label(profiler.alloc_string(label));
+            for kind in [query, verbose, generic] {
+                profiler.record_instant_event(kind, label, 1);
+            }
+        };
+
+        record("before");
+
+        std::fs::write(&control_file, "Verbose on\n").unwrap();
+        std::thread::sleep(POLL_INTERVAL * 2);
+        record("during");
+
+        std::fs::remove_file(&control_file).unwrap();
+        std::fs::write(&control_file, "Query off\nVerbose off\nQuery on\n").unwrap();
+        std::thread::sleep(POLL_INTERVAL * 2);
+        record("after");
+    }
+
+    let data = ProfilingData::new(&path_stem).unwrap();
+    let events: Vec<_> = data
+        .iter()
+        .map(|e| e.to_event())
+        .map(|e| format!("{} {}", e.event_kind, e.label))
+        .collect();
+
+    assert_eq!(
+        events,
+        vec![
+            "Query before",
+            "Verbose before",
+            "Query during",
+            "Verbose during",
+            "Verbose during",
+            "Query after",
+            "Verbose after",
+        ]
+    );
+}
@@ -0,0 +1,53 @@
+use analyzeme::ProfileFollower;
+use measureme::{EventId, EventIdBuilder, FileSerializationSink, Profiler};
+use std::path::Path;
+
+#[test]
+fn follow_while_recording() {
+    let path_stem = Path::new("test-tmp").join("follow").join("profile");
+
+    let profiler = Profiler::<FileSerializationSink>::new(&path_stem).unwrap();
+    let mut follower = ProfileFollower::new(&path_stem).unwrap();
+
+    let query = profiler.alloc_string("Query");
+    let cache_hit = profiler.alloc_string("QueryCacheHit");
+    let typeck = profiler.alloc_string("typeck");
+    let builder = EventIdBuilder::new(&profiler);
+
+    for arg in &["foo", "bar"] {
+        let event_id = builder.from_label_and_arg(typeck, profiler.alloc_string(*arg));
+        drop(profiler.start_recording_interval_event(query, event_id, 0));
+    }
+    profiler.flush_strings();
+
+    // Whatever the sinks have written so far.
+    follower.poll().unwrap();
+    assert!(!follower.is_finished());
+
+    profiler.record_instant_event(cache_hit, EventId::from_label(typeck), 0);
+    drop(profiler);
+
+    follower.poll().unwrap();
+    assert!(follower.is_finished());
+    assert_eq!(follower.num_events(), 3);
+    assert!(follower.metadata().is_some());
+
+    let mut labels: Vec<_> = follower
+        .summary()
+        .labels
+        .iter()
+        .map(|l| (l.event_kind.clone(), l.label.clone(), l.count))
+        .collect();
+    labels.sort();
+    assert_eq!(
+        labels,
+        vec![
+            ("Query".to_string(), "typeck".to_string(), 2),
+            ("QueryCacheHit".to_string(), "typeck".to_string(), 1)
+        ]
+    );
+
+    // Nothing changes once the profile is complete.
+    assert_eq!(follower.poll().unwrap(), 0);
+    assert_eq!(follower.num_events(), 3);
+}
@@ -0,0 +1,48 @@
+use analyzeme::ProfilingData;
+use measureme::{global, ProfilerConfig};
+use std::path::Path;
+use std::thread;
+
+fn nested() {
+    measureme::activity!("Tool", "nested");
+}
+
+#[test]
+fn global_activities() {
+    // Without a profiler, activities do nothing.
+    nested();
+    assert!(!global::is_enabled());
+
+    let path_stem = Path::new("test-tmp").join("global_profiler");
+    global::init(&path_stem, &ProfilerConfig::default()).unwrap();
+    assert!(global::is_enabled());
+
+    {
+        measureme::activity!("outer");
+        nested();
+        thread::spawn(nested).join().unwrap();
+    }
+    global::finish();
+    assert!(!global::is_enabled());
+    nested();
+
+    let data = ProfilingData::new(&path_stem).unwrap();
+    let mut events: Vec<_> = data
+        .iter()
+        .filter(|e| e.duration().is_some())
+        .map(|e| {
+            let e = e.to_event();
+            (e.event_kind.into_owned(), e.label.into_owned(), e.thread_id)
+        })
+        .collect();
+    events.sort();
+
+    assert_eq!(events.len(), 3, "{:?}", events);
+    assert_eq!(events[0].0, "GenericActivity");
+    assert_eq!(events[0].1, "outer");
+    assert_eq!(events[1].1, "nested");
+    assert_eq!(events[2].1, "nested");
+    // The activity on the spawned thread has a thread id of its own.
+    assert_ne!(events[1].2, events[2].2);
+    assert!(events[0].2 == events[1].2 || events[0].2 == events[2].2);
+}
@@ -0,0 +1,132 @@
+#![cfg(feature = "http")]
+
+use analyzeme::testing_common::{query_labels as labels, record_query};
+use analyzeme::{LoadError, LoadErrorKind, ProfilingData};
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// Serves the files in `dir` on a local port and records the `Range` header
+/// of every request. Only understands what `ureq` sends.
+fn serve(dir: PathBuf, support_ranges: bool) -> (String, Arc<Mutex<Vec<String>>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let requests = Arc::new(Mutex::new(Vec::new()));
+    let log = requests.clone();
+
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+            let mut request_line = String::new();
+            reader.read_line(&mut request_line).unwrap();
+            let path = request_line.split(' ').nth(1).unwrap().to_string();
+
+            let mut range = None;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line.trim().is_empty() {
+                    break;
+                }
+                if let Some(value) = line
+                    .strip_prefix("range: ")
+                    .or(line.strip_prefix("Range: "))
+                {
+                    range = Some(value.trim().to_string());
+                }
+            }
+
+            log.lock()
+                .unwrap()
+                .push(format!("{} {}", path, range.as_deref().unwrap_or("-")));
+
+            let body = match std::fs::read(dir.join(&path[1..])) {
+                Ok(body) => body,
+                Err(_) => {
+                    let _ = stream.write_all(
+                        b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                    );
+                    continue;
+                }
+            };
+
+            let (status, body) = match range.filter(|_| support_ranges) {
+                Some(range) => {
+                    let range = range.strip_prefix("bytes=").unwrap();
+                    let (start, end) = range.split_at(range.find('-').unwrap());
+                    let start: usize = start.parse().unwrap();
+                    let end = match end[1..].parse::<usize>() {
+                        Ok(end) => (end + 1).min(body.len()),
+                        Err(_) => body.len(),
+                    };
+                    ("206 Partial Content", body[start..end].to_vec())
+                }
+                None => ("200 OK", body),
+            };
+
+            let header = format!(
+                "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                status,
+                body.len()
+            );
+            let _ = stream.write_all(header.as_bytes());
+            let _ = stream.write_all(&body);
+        }
+    });
+
+    (url, requests)
+}
+
+fn record(dir: &Path) {
+    record_query(&dir.join("foo-1234"), "typeck");
+}
+
+#[test]
+fn load_over_http() {
+    let dir = Path::new("test-tmp").join("http");
+    record(&dir);
+
+    let (url, requests) = serve(dir.clone(), true);
+
+    let data = ProfilingData::new(Path::new(&format!("{}/foo-1234", url))).unwrap();
+    assert_eq!(labels(&data), vec!["typeck"]);
+
+    // The header of every file is requested first, starting with the index.
+    let requests = requests.lock().unwrap().clone();
+    assert_eq!(
+        requests,
+        vec![
+            "/foo-1234.string_index bytes=0-31",
+            "/foo-1234.string_index bytes=32-",
+            "/foo-1234.string_data bytes=0-31",
+            "/foo-1234.string_data bytes=32-",
+            "/foo-1234.events bytes=0-31",
+            "/foo-1234.events bytes=32-",
+        ]
+    );
+
+    let error = ProfilingData::new(Path::new(&format!("{}/bar-5678", url)))
+        .err()
+        .unwrap();
+    assert_eq!(
+        LoadError::kind_of(&*error),
+        Some(LoadErrorKind::FileMissing)
+    );
+}
+
+#[test]
+fn load_without_range_support() {
+    let dir = Path::new("test-tmp").join("http_no_ranges");
+    record(&dir);
+
+    let (url, requests) = serve(dir.clone(), false);
+
+    let data = ProfilingData::new(Path::new(&format!("{}/foo-1234", url))).unwrap();
+    assert_eq!(labels(&data), vec!["typeck"]);
+
+    // Every file is downloaded with a single request.
+    assert_eq!(requests.lock().unwrap().len(), 3);
+}
@@ -0,0 +1,73 @@
+use analyzeme::ProfilingData;
+use measureme::file_header::{
+    read_full_file_header, FEATURE_INTEGER_EVENTS, FILE_MAGIC_EVENT_STREAM,
+};
+use measureme::{
+    EventId, FileSerializationSink, Profiler, ProfilerConfig, ProfilerFiles, TimestampFormat,
+    MAX_INTEGER_VALUE,
+};
+use std::path::Path;
+
+#[test]
+fn record_integer_events() {
+    let filestem = Path::new("test-tmp")
+        .join("integer_events")
+        .join("record_integer_events");
+
+    {
+        let profiler = Profiler::<FileSerializationSink>::new(&filestem).unwrap();
+        let kind = profiler.alloc_string("CacheSize");
+        let event_id = EventId::from_label(profiler.alloc_string("query_cache"));
+
+        profiler.record_integer_event(kind, event_id, 0, 0);
+        profiler.record_instant_event(kind, event_id, 0);
+        profiler.record_integer_event(kind, event_id, 0, 4096);
+        profiler.record_integer_event(kind, event_id, 0, MAX_INTEGER_VALUE);
+    }
+
+    let data = ProfilingData::new(&filestem).unwrap();
+    let events: Vec<_> = data.iter().map(|e| e.to_event()).collect();
+
+    assert!(events.iter().all(|e| e.timestamp.is_instant()));
+    assert!(events.iter().all(|e| e.label == "query_cache"));
+
+    let values: Vec<_> = events.iter().map(|e| e.integer_value).collect();
+    assert_eq!(values, [Some(0), None, Some(4096), Some(MAX_INTEGER_VALUE)]);
+}
+
+#[test]
+fn compact_format_without_integer_events() {
+    let filestem = Path::new("test-tmp")
+        .join("integer_events")
+        .join("compact_format_without_integer_events");
+    let config = ProfilerConfig {
+        timestamp_format: TimestampFormat::CompactWithoutIntegers,
+        ..ProfilerConfig::default()
+    };
+
+    {
+        let profiler = Profiler::<FileSerializationSink>::with_config(&filestem, &config).unwrap();
+        let kind = profiler.alloc_string("CacheSize");
+        let event_id = EventId::from_label(profiler.alloc_string("query_cache"));
+
+        profiler.record_integer_event(kind, event_id, 0, 4096);
+        profiler.record_instant_event(kind, event_id, 0);
+    }
+
+    // The header doesn't reserve any timestamps for integer events, so the
+    // profiler dropped the integer event.
+    let events_file = std::fs::read(ProfilerFiles::new(&filestem).events_file).unwrap();
+    let header = read_full_file_header(&events_file, FILE_MAGIC_EVENT_STREAM).unwrap();
+    assert_eq!(header.feature_flags & FEATURE_INTEGER_EVENTS, 0);
+
+    let data = ProfilingData::new(&filestem).unwrap();
+    assert_eq!(
+        data.timestamp_format(),
+        TimestampFormat::CompactWithoutIntegers
+    );
+    assert_eq!(data.metadata.dropped_events, 1);
+
+    let events: Vec<_> = data.iter().map(|e| e.to_event()).collect();
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].integer_value, None);
+}
@@ -0,0 +1,34 @@
+use analyzeme::ProfilingData;
+use measureme::{EventId, FileSerializationSink, Profiler, ProfilerConfig};
+use std::path::Path;
+
+fn record(path_stem: &Path, config: &ProfilerConfig) -> ProfilingData {
+    {
+        let profiler = Profiler::<FileSerializationSink>::with_config(path_stem, config).unwrap();
+        let kind = profiler.alloc_string("Query");
+        let label = EventId::from_label(profiler.alloc_string("typeck"));
+        for _ in 0..1_000 {
+            drop(profiler.start_recording_interval_event(kind, label, 0));
+        }
+    }
+
+    ProfilingData::new(path_stem).unwrap()
+}
+
+#[test]
+fn overhead() {
+    let dir = Path::new("test-tmp").join("overhead");
+
+    let data = record(&dir.join("profile"), &ProfilerConfig::default());
+    let overhead = data.metadata.overhead.unwrap();
+    assert!(overhead.recording_nanos > 0);
+    assert!(overhead.elapsed_nanos >= overhead.recording_nanos);
+    assert!(overhead.bytes_written > 0);
+
+    let config = ProfilerConfig {
+        deterministic: true,
+        ..ProfilerConfig::default()
+    };
+    let data = record(&dir.join("deterministic"), &config);
+    assert_eq!(data.metadata.overhead, None);
+}
@@ -0,0 +1,59 @@
+use analyzeme::ProfilingData;
+use measureme::event_kinds::{PROFILER_PAUSED_EVENT_KIND, PROFILER_RESUMED_EVENT_KIND};
+use measureme::{EventId, FileSerializationSink, Profiler};
+use std::path::Path;
+
+#[test]
+fn pause_and_resume() {
+    let filestem = Path::new("test-tmp")
+        .join("pause_resume")
+        .join("pause_and_resume");
+
+    {
+        let profiler = Profiler::<FileSerializationSink>::new(&filestem).unwrap();
+        let kind = profiler.alloc_string("Generic");
+        let event_id = |label: &str| EventId::from_label(profiler.alloc_string(label));
+
+        profiler.record_instant_event(kind, event_id("before"), 0);
+
+        let straddling = profiler.start_recording_interval_event(kind, event_id("straddling"), 0);
+
+        profiler.pause_recording(0);
+        assert!(profiler.is_recording_paused());
+        // Pausing twice must not emit a second marker.
+        profiler.pause_recording(0);
+
+        profiler.record_instant_event(kind, event_id("discarded"), 0);
+        drop(profiler.start_recording_interval_event(kind, event_id("discarded"), 0));
+
+        profiler.resume_recording(0);
+        profiler.resume_recording(0);
+        assert!(!profiler.is_recording_paused());
+
+        drop(straddling);
+        profiler.record_instant_event(kind, event_id("after"), 0);
+    }
+
+    let data = ProfilingData::new(&filestem).unwrap();
+    let events: Vec<_> = data
+        .iter()
+        .map(|e| {
+            let e = e.to_event();
+            (e.event_kind.into_owned(), e.label.into_owned())
+        })
+        .collect();
+
+    let generic = |label: &str| ("Generic".to_string(), label.to_string());
+    let marker = |kind: &str| (kind.to_string(), kind.to_string());
+
+    assert_eq!(
+        events,
+        vec![
+            generic("before"),
+            marker(PROFILER_PAUSED_EVENT_KIND),
+            marker(PROFILER_RESUMED_EVENT_KIND),
+            generic("straddling"),
+            generic("after"),
+        ]
+    );
+}
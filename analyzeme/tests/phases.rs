@@ -0,0 +1,44 @@
+use analyzeme::{find_phases, ProfilingData};
+use measureme::{EventId, FileSerializationSink, Profiler};
+use std::path::Path;
+
+#[test]
+fn nested_phases() {
+    let filestem = Path::new("test-tmp").join("phases").join("nested_phases");
+
+    {
+        let profiler = Profiler::<FileSerializationSink>::new(&filestem).unwrap();
+        let kind = profiler.alloc_string("Generic");
+        let event_id = EventId::from_label(profiler.alloc_string("event"));
+
+        profiler.start_phase("expansion");
+        profiler.record_instant_event(kind, event_id, 0);
+        profiler.end_phase();
+
+        profiler.start_phase("analysis");
+        profiler.start_phase("type-checking");
+        profiler.end_phase();
+        profiler.end_phase();
+
+        // Without an active phase, this must not record anything.
+        profiler.end_phase();
+
+        profiler.start_phase("codegen");
+    }
+
+    let data = ProfilingData::new(&filestem).unwrap();
+    let phases: Vec<_> = find_phases(&data)
+        .into_iter()
+        .map(|phase| (phase.name, phase.depth))
+        .collect();
+
+    assert_eq!(
+        phases,
+        vec![
+            ("expansion".to_string(), 0),
+            ("analysis".to_string(), 0),
+            ("type-checking".to_string(), 1),
+            ("codegen".to_string(), 0),
+        ]
+    );
+}
@@ -0,0 +1,55 @@
+use analyzeme::{EventIteratorExt, ProfileSummary, ProfilingData};
+use measureme::{EventId, FileSerializationSink, Profiler, ProfilerConfig};
+use std::path::Path;
+
+#[test]
+fn registered_event_kinds() {
+    let path_stem = Path::new("test-tmp")
+        .join("registered_event_kinds")
+        .join("profile");
+
+    let config = ProfilerConfig {
+        summary: true,
+        ..ProfilerConfig::default()
+    };
+
+    let query = {
+        let profiler = Profiler::<FileSerializationSink>::with_config(&path_stem, &config).unwrap();
+        let query = profiler.register_event_kind("Query");
+        let generic_activity = profiler.register_event_kind("GenericActivity");
+        assert_ne!(query, generic_activity);
+        assert_eq!(profiler.register_event_kind("Query"), query);
+
+        let typeck = EventId::from_label(profiler.alloc_string("typeck"));
+        drop(profiler.start_recording_interval_event(query, typeck, 0));
+        drop(profiler.start_recording_interval_event(generic_activity, typeck, 0));
+        profiler.record_instant_event(query, typeck, 0);
+        query
+    };
+
+    let data = ProfilingData::new(&path_stem).unwrap();
+    assert_eq!(data.registered_event_kind("Query"), Some(query));
+    assert_eq!(data.registered_event_kind("typeck"), None);
+
+    let kinds: Vec<_> = data.iter().map(|e| e.to_event().event_kind).collect();
+    assert_eq!(kinds, vec!["Query", "GenericActivity", "Query"]);
+    assert_eq!(data.iter().filter_kind("Query").count(), 2);
+
+    let summary = ProfileSummary::new(&path_stem).unwrap();
+    let mut kinds: Vec<_> = summary
+        .labels
+        .iter()
+        .map(|l| (l.event_kind.as_str(), l.count))
+        .collect();
+    kinds.sort();
+    assert_eq!(kinds, vec![("GenericActivity", 1), ("Query", 2)]);
+
+    // Resuming keeps the registered kinds.
+    {
+        let profiler = Profiler::<FileSerializationSink>::resume(&path_stem).unwrap();
+        assert_eq!(profiler.register_event_kind("Query"), query);
+    }
+
+    let data = ProfilingData::new(&path_stem).unwrap();
+    assert_eq!(data.iter().filter_kind("Query").count(), 2);
+}
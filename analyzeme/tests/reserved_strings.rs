@@ -0,0 +1,63 @@
+use analyzeme::testing_common::query_labels as labels;
+use analyzeme::ProfilingData;
+use measureme::{EventIdBuilder, FileSerializationSink, Profiler};
+use std::borrow::Cow;
+use std::path::Path;
+
+const QUERY_NAMES: [&str; 3] = ["type_of", "typeck", "mir_built"];
+
+#[test]
+fn reserved_strings() {
+    let path_stem = Path::new("test-tmp")
+        .join("reserved_strings")
+        .join("profile");
+
+    {
+        let profiler = Profiler::<FileSerializationSink>::new(&path_stem).unwrap();
+        let query_names =
+            profiler.reserve_string_ids("rustc-query-names", QUERY_NAMES.len() as u32);
+        let other = profiler.reserve_string_ids("other", 10);
+        assert_ne!(query_names.first(), other.first());
+        assert_eq!(
+            profiler.reserve_string_ids("rustc-query-names", QUERY_NAMES.len() as u32),
+            query_names
+        );
+        assert_eq!(query_names.index_of(query_names.get(2)), Some(2));
+        assert_eq!(query_names.index_of(other.get(0)), None);
+
+        let kind = profiler.alloc_string("Query");
+        let builder = EventIdBuilder::new(&profiler);
+        let typeck = builder.from_label(query_names.get(1));
+        let type_of = builder.from_label_and_arg(query_names.get(0), profiler.alloc_string("Foo"));
+        drop(profiler.start_recording_interval_event(kind, typeck, 0));
+        drop(profiler.start_recording_interval_event(kind, type_of, 0));
+    }
+
+    let mut data = ProfilingData::new(&path_stem).unwrap();
+    assert_eq!(data.metadata.reserved_strings.len(), 2);
+    assert_eq!(labels(&data), vec!["<unknown>", "<unknown>"]);
+
+    data.register_string_resolver("rustc-query-names", |index: u32| {
+        QUERY_NAMES
+            .get(index as usize)
+            .map(|&name| Cow::Borrowed(name))
+    })
+    .unwrap();
+    assert!(data
+        .register_string_resolver("chalk", |_: u32| None)
+        .is_err());
+
+    assert_eq!(labels(&data), vec!["typeck", "type_of"]);
+    let args: Vec<_> = data
+        .iter()
+        .map(|e| e.to_event())
+        .filter(|e| e.event_kind == "Query")
+        .map(|e| {
+            e.additional_data
+                .iter()
+                .map(|a| a.to_string())
+                .collect::<Vec<_>>()
+        })
+        .collect();
+    assert_eq!(args, vec![vec![], vec!["Foo".to_string()]]);
+}
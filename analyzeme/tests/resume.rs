@@ -0,0 +1,69 @@
+use analyzeme::ProfilingData;
+use measureme::{EventId, FileSerializationSink, Profiler, ProfilerFiles};
+use std::path::Path;
+
+fn record(profiler: &Profiler<FileSerializationSink>, label: &str) -> u32 {
+    let thread_id = profiler.register_current_thread();
+    let kind = profiler.alloc_string("Query");
+    let label = EventId::from_label(profiler.alloc_string(label));
+    drop(profiler.start_recording_interval_event(kind, label, thread_id));
+    thread_id
+}
+
+#[test]
+fn resume_appends_to_profile() {
+    let path_stem = Path::new("test-tmp").join("resume").join("server");
+
+    let first_thread = {
+        let profiler = Profiler::<FileSerializationSink>::new(&path_stem).unwrap();
+        record(&profiler, "typeck")
+    };
+
+    let start_time = ProfilingData::new(&path_stem).unwrap().metadata.start_time;
+
+    let second_thread = {
+        let profiler = Profiler::<FileSerializationSink>::resume(&path_stem).unwrap();
+        record(&profiler, "mir_borrowck")
+    };
+
+    // Resuming can be repeated.
+    drop(Profiler::<FileSerializationSink>::resume(&path_stem).unwrap());
+
+    assert_ne!(first_thread, second_thread);
+
+    let data = ProfilingData::new(&path_stem).unwrap();
+    assert_eq!(data.metadata.start_time, start_time);
+
+    let events: Vec<_> = data
+        .iter()
+        .map(|e| e.to_event())
+        .filter(|e| e.event_kind == "Query")
+        .collect();
+
+    assert_eq!(events.len(), 2);
+    assert_eq!(events[0].label, "typeck");
+    assert_eq!(events[0].thread_id, first_thread);
+    assert_eq!(events[1].label, "mir_borrowck");
+    assert_eq!(events[1].thread_id, second_thread);
+    assert!(events[0].timestamp.end() <= events[1].timestamp.start());
+}
+
+#[test]
+fn resume_rejects_incomplete_profiles() {
+    let path_stem = Path::new("test-tmp").join("resume").join("incomplete");
+
+    assert!(Profiler::<FileSerializationSink>::resume(&path_stem).is_err());
+
+    drop(Profiler::<FileSerializationSink>::new(&path_stem).unwrap());
+
+    // Cut off the footer, as if the process had crashed.
+    let events_file = ProfilerFiles::new(&path_stem).events_file;
+    let mut events = std::fs::read(&events_file).unwrap();
+    events.truncate(events.len() - 1);
+    std::fs::write(&events_file, events).unwrap();
+
+    let error = Profiler::<FileSerializationSink>::resume(&path_stem)
+        .err()
+        .unwrap();
+    assert!(error.to_string().contains("footer is missing"), "{}", error);
+}
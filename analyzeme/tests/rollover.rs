@@ -0,0 +1,125 @@
+use analyzeme::ProfilingData;
+use measureme::file_header::{FILE_FOOTER_SIZE, FILE_HEADER_SIZE};
+use measureme::{
+    EventId, EventLayout, FileSerializationSink, Profiler, ProfilerConfig, ProfilerFiles, Rollover,
+    RAW_EVENT_SIZE,
+};
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+fn config(rollover: Rollover) -> ProfilerConfig {
+    ProfilerConfig {
+        event_layout: EventLayout::Rollover(rollover),
+        ..ProfilerConfig::default()
+    }
+}
+
+#[test]
+fn rollover_by_size() {
+    let dir = Path::new("test-tmp").join("rollover");
+    fs::create_dir_all(&dir).unwrap();
+    let path_stem = dir.join("by_size");
+    let paths = ProfilerFiles::new(&path_stem);
+
+    let rollover = Rollover {
+        max_bytes: Some(100 * RAW_EVENT_SIZE as u64),
+        ..Rollover::default()
+    };
+    let profiler = Arc::new(
+        Profiler::<FileSerializationSink>::with_config(&path_stem, &config(rollover)).unwrap(),
+    );
+
+    let threads: Vec<_> = (0..4)
+        .map(|i| {
+            let profiler = profiler.clone();
+            std::thread::spawn(move || {
+                let kind = profiler.alloc_string("Query");
+                let id = EventId::from_label(profiler.alloc_string(&*format!("work-{}", i)));
+                let thread_id = profiler.register_current_thread();
+
+                for _ in 0..250 {
+                    drop(profiler.start_recording_interval_event(kind, id, thread_id));
+                }
+            })
+        })
+        .collect();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+
+    // 4 registrations and 1000 intervals make for 10 full segments and an
+    // empty one.
+    let finished = profiler.finished_event_segments();
+    assert_eq!(finished.len(), 10);
+    assert_eq!(finished[3], paths.segment_events_file(3));
+    drop(profiler);
+
+    assert_eq!(
+        fs::read(&paths.events_file).unwrap().len(),
+        FILE_HEADER_SIZE + FILE_FOOTER_SIZE
+    );
+    assert_eq!(
+        fs::read(paths.segment_events_file(0)).unwrap().len(),
+        FILE_HEADER_SIZE + 100 * RAW_EVENT_SIZE + FILE_FOOTER_SIZE
+    );
+
+    let data = ProfilingData::new(&path_stem).unwrap();
+    assert_eq!(data.metadata.event_segments.len(), 11);
+    assert_eq!(data.metadata.event_segments[0], "by_size.0.segment_events");
+    assert_eq!(data.num_events(), 1004);
+    assert!(data.warnings().is_empty());
+
+    let mut labels: Vec<_> = data
+        .iter()
+        .filter(|e| e.to_event().event_kind == "Query")
+        .map(|e| e.to_event().label.into_owned())
+        .collect();
+    labels.dedup();
+    assert!(labels.len() >= 4, "{:?}", labels);
+
+    // Deleting processed segments leaves the rest of the profile loadable.
+    fs::remove_file(paths.segment_events_file(0)).unwrap();
+    fs::remove_file(paths.segment_events_file(1)).unwrap();
+    let data = ProfilingData::new(&path_stem).unwrap();
+    assert_eq!(data.num_events(), 804);
+    let codes: Vec<_> = data.warnings().iter().map(|w| w.code).collect();
+    assert_eq!(codes, ["missing-segment", "missing-segment"]);
+}
+
+#[test]
+fn rollover_by_time() {
+    let path_stem = Path::new("test-tmp").join("rollover").join("by_time");
+
+    let rollover = Rollover {
+        interval: Some(Duration::from_millis(20)),
+        ..Rollover::default()
+    };
+    let profiler =
+        Profiler::<FileSerializationSink>::with_config(&path_stem, &config(rollover)).unwrap();
+    let kind = profiler.alloc_string("Query");
+    let id = EventId::from_label(profiler.alloc_string("work"));
+
+    profiler.record_instant_event(kind, id, 0);
+    assert!(profiler.finished_event_segments().is_empty());
+    std::thread::sleep(Duration::from_millis(30));
+    profiler.record_instant_event(kind, id, 0);
+    profiler.record_instant_event(kind, id, 0);
+    assert_eq!(profiler.finished_event_segments().len(), 1);
+    drop(profiler);
+
+    let data = ProfilingData::new(&path_stem).unwrap();
+    assert_eq!(data.metadata.event_segments.len(), 2);
+    assert_eq!(data.num_events(), 3);
+}
+
+#[test]
+fn rollover_needs_a_limit() {
+    let path_stem = Path::new("test-tmp").join("rollover").join("no_limit");
+    assert!(Profiler::<FileSerializationSink>::with_config(
+        &path_stem,
+        &config(Rollover::default())
+    )
+    .is_err());
+}
@@ -0,0 +1,75 @@
+use analyzeme::{
+    filter_self_profile_events, ProfilingData, ProfilingDataBuilder, SelfProfileEvents,
+};
+use measureme::{EventId, EventIdBuilder, FileSerializationSink, Profiler};
+use std::path::Path;
+
+fn events(data: &ProfilingData) -> Vec<(String, String, Vec<String>, u32)> {
+    data.iter()
+        .map(|e| e.to_event())
+        .map(|e| {
+            let args = e.additional_data.iter().map(|a| a.to_string()).collect();
+            (
+                e.event_kind.into_owned(),
+                e.label.into_owned(),
+                args,
+                e.thread_id,
+            )
+        })
+        .collect()
+}
+
+#[test]
+fn saved_copy() {
+    let dir = Path::new("test-tmp").join("save");
+    let path_stem = dir.join("original");
+
+    {
+        let profiler = Profiler::<FileSerializationSink>::new(&path_stem).unwrap();
+        let query = profiler.alloc_string("Query");
+        let label = profiler.alloc_string("typeck");
+        let arg = profiler.alloc_string("main");
+        let event_id = EventIdBuilder::new(&profiler).from_label_and_arg(label, arg);
+        drop(profiler.start_recording_interval_event(query, event_id, 1));
+
+        let marker = profiler.register_event_kind("Marker");
+        profiler.record_instant_event(marker, EventId::from_label(label), 2);
+        let hit = profiler.alloc_string("QueryCacheHit");
+        profiler.record_instant_event(hit, EventId::from_label(label), 1);
+    }
+
+    let data = ProfilingData::new(&path_stem).unwrap();
+    let saved_stem = dir.join("saved");
+    data.save(&saved_stem).unwrap();
+
+    let saved = ProfilingData::new(&saved_stem).unwrap();
+    assert_eq!(events(&saved), events(&data));
+    assert_eq!(saved.metadata.start_time, data.metadata.start_time);
+    assert_eq!(saved.metadata.cmd, data.metadata.cmd);
+    assert_eq!(saved.metadata.process_id, data.metadata.process_id);
+
+    // A transformed profile is saved with its changes.
+    let filtered = filter_self_profile_events(&data, SelfProfileEvents::parse("default").unwrap());
+    let filtered_stem = dir.join("filtered");
+    filtered.save(&filtered_stem).unwrap();
+    let reloaded = ProfilingData::new(&filtered_stem).unwrap();
+    assert_eq!(events(&reloaded), events(&filtered));
+    assert!(events(&reloaded).len() < events(&data).len());
+}
+
+#[test]
+fn saved_builder_profile() {
+    let mut b = ProfilingDataBuilder::new();
+    b.interval("Query", "typeck", 0, 10, 100, |b| {
+        b.interval("Query", "type_of", 0, 20, 30, |_| {});
+    });
+    b.instant("Marker", "done", 1, 200);
+    let data = b.into_profiling_data();
+
+    let path_stem = Path::new("test-tmp").join("save_builder");
+    data.save(&path_stem).unwrap();
+
+    let saved = ProfilingData::new(&path_stem).unwrap();
+    assert_eq!(events(&saved), events(&data));
+    assert_eq!(saved.metadata.cmd, "test cmd");
+}
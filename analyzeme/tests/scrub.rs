@@ -0,0 +1,55 @@
+use analyzeme::{scrub_paths, PathScrubber, ProfilingData};
+use measureme::{EventIdBuilder, FileSerializationSink, Profiler};
+use std::path::Path;
+
+#[test]
+fn scrubbed_copy() {
+    let dir = Path::new("test-tmp").join("scrub");
+    let path_stem = dir.join("original");
+    let scrubbed_stem = dir.join("scrubbed");
+
+    {
+        let profiler = Profiler::<FileSerializationSink>::new(&path_stem).unwrap();
+        let kind = profiler.alloc_string("GenericActivity");
+        let label = profiler.alloc_string("incr_comp_persist_result");
+        let arg = profiler.alloc_string("/home/alice/work/foo/target/debug/incremental");
+        let event_id = EventIdBuilder::new(&profiler).from_label_and_arg(label, arg);
+        drop(profiler.start_recording_interval_event(kind, event_id, 1));
+
+        let label = profiler.alloc_string("/home/alice/.cargo/registry/src/serde/lib.rs");
+        drop(profiler.start_recording_interval_event(
+            kind,
+            EventIdBuilder::new(&profiler).from_label(label),
+            1,
+        ));
+    }
+
+    let data = ProfilingData::new(&path_stem).unwrap();
+    let mut scrubber = PathScrubber::new();
+    scrubber.add_prefix("/home/alice/work/foo", "$SRC");
+    scrubber.add_prefix("/home/alice/.cargo", "$CARGO_HOME");
+    scrub_paths(&data, &scrubber, &scrubbed_stem).unwrap();
+
+    let scrubbed = ProfilingData::new(&scrubbed_stem).unwrap();
+    assert_eq!(scrubbed.metadata.cmd, scrubber.scrub(&data.metadata.cmd));
+    assert_eq!(scrubbed.metadata.start_time, data.metadata.start_time);
+
+    let events: Vec<_> = scrubbed
+        .iter()
+        .map(|e| e.to_event())
+        .map(|e| {
+            let args: Vec<_> = e.additional_data.iter().map(|a| a.to_string()).collect();
+            (e.label.into_owned(), args)
+        })
+        .collect();
+    assert_eq!(
+        events,
+        vec![
+            (
+                "incr_comp_persist_result".into(),
+                vec!["$SRC/target/debug/incremental".to_string()]
+            ),
+            ("$CARGO_HOME/registry/src/serde/lib.rs".into(), vec![]),
+        ]
+    );
+}
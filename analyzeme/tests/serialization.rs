@@ -1,5 +1,10 @@
-use analyzeme::testing_common::run_end_to_end_serialization_test;
-use measureme::{FileSerializationSink, MmapSerializationSink};
+use analyzeme::testing_common::{
+    run_end_to_end_serialization_test, run_end_to_end_serialization_test_with_config,
+};
+use measureme::{
+    BufferedSerializationSink, FileSerializationSink, FileSinkConfig, MmapSerializationSink,
+    ProfilerConfig,
+};
 
 #[test]
 fn test_file_serialization_sink_1_thread() {
@@ -32,3 +37,39 @@ fn test_mmap_serialization_sink_8_threads() {
         8,
     );
 }
+
+#[test]
+fn test_buffered_serialization_sink_1_thread() {
+    run_end_to_end_serialization_test::<BufferedSerializationSink>(
+        "buffered_serialization_sink_test_1_thread",
+        1,
+    );
+}
+
+#[test]
+fn test_buffered_serialization_sink_8_threads() {
+    run_end_to_end_serialization_test::<BufferedSerializationSink>(
+        "buffered_serialization_sink_test_8_threads",
+        8,
+    );
+}
+
+#[test]
+fn test_file_serialization_sink_tuned() {
+    // A buffer smaller than some of the writes, early flushes and
+    // preallocation in small steps exercise all code paths of the sink.
+    let config = ProfilerConfig {
+        file_sink: FileSinkConfig {
+            buffer_size: 100,
+            write_behind_threshold: Some(64),
+            preallocate: 4096,
+        },
+        ..ProfilerConfig::default()
+    };
+
+    run_end_to_end_serialization_test_with_config::<FileSerializationSink>(
+        "file_serialization_sink_test_tuned",
+        8,
+        &config,
+    );
+}
@@ -0,0 +1,58 @@
+#![cfg(feature = "serialize")]
+
+use analyzeme::{Event, Metadata, ProfileSummary, ProfilingData};
+use measureme::{
+    ArgType, EventIdBuilder, FileSerializationSink, Profiler, ProfilerConfig, ToolInfo,
+};
+use std::path::Path;
+
+#[test]
+fn round_trips() {
+    let path_stem = Path::new("test-tmp").join("serialize").join("profile");
+    let config = ProfilerConfig {
+        summary: true,
+        ..ProfilerConfig::default()
+    };
+
+    {
+        let profiler = Profiler::<FileSerializationSink>::with_config(&path_stem, &config).unwrap();
+        profiler.set_tool_info(ToolInfo {
+            name: "rustc".to_string(),
+            version: "1.60.0-nightly".to_string(),
+            git_sha: None,
+            flags: vec!["-Zself-profile".to_string()],
+        });
+        profiler.register_arg_schema("Query", &[("key", ArgType::U64)]);
+
+        let query = profiler.alloc_string("Query");
+        let event_id = EventIdBuilder::new(&profiler)
+            .from_label_and_arg(profiler.alloc_string("typeck"), profiler.alloc_string("42"));
+        drop(profiler.start_recording_interval_event(query, event_id, 0));
+        profiler.record_instant_event(query, event_id, 0);
+    }
+
+    let data = ProfilingData::new(&path_stem).unwrap();
+
+    let events: Vec<Event<'_>> = data.iter().map(|e| e.to_event()).collect();
+    let json = serde_json::to_string(&events).unwrap();
+    let deserialized: Vec<Event<'static>> = serde_json::from_str(&json).unwrap();
+    assert_eq!(deserialized, events);
+    assert!(deserialized
+        .iter()
+        .any(|e| e.label == "typeck" && e.additional_data == ["42"] && !e.timestamp.is_instant()));
+
+    let json = serde_json::to_string(&data.metadata).unwrap();
+    let metadata: Metadata = serde_json::from_str(&json).unwrap();
+    assert_eq!(metadata.start_time, data.metadata.start_time);
+    assert_eq!(metadata.process_id, data.metadata.process_id);
+    assert_eq!(metadata.tool, data.metadata.tool);
+    assert_eq!(metadata.arg_schemas, data.metadata.arg_schemas);
+    assert_eq!(metadata.arg_schemas["Query"][0].arg_type, ArgType::U64);
+
+    let summary = ProfileSummary::new(&path_stem).unwrap();
+    let json = serde_json::to_string(&summary).unwrap();
+    assert_eq!(
+        serde_json::from_str::<ProfileSummary>(&json).unwrap(),
+        summary
+    );
+}
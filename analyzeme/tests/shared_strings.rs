@@ -0,0 +1,182 @@
+use analyzeme::{share_strings, ProfilingData};
+use measureme::{
+    EventId, EventIdBuilder, FileSerializationSink, Profiler, ProfilerConfig, ProfilerFiles,
+    SharedStringCache,
+};
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+fn count_occurrences(haystack: &[u8], needle: &[u8]) -> usize {
+    haystack
+        .windows(needle.len())
+        .filter(|window| *window == needle)
+        .count()
+}
+
+#[test]
+fn profiles_sharing_a_string_cache() {
+    let dir = Path::new("test-tmp").join("shared_strings");
+    let cache_stem = dir.join("shared");
+    let cache = Arc::new(SharedStringCache::<FileSerializationSink>::new(&cache_stem).unwrap());
+
+    let mut ids = Vec::new();
+
+    for unit in &["unit-1", "unit-2"] {
+        let mut profiler = Profiler::<FileSerializationSink>::new(&dir.join(unit)).unwrap();
+        profiler.set_shared_string_cache(cache.clone());
+
+        let kind = profiler.intern_shared_string("Query");
+        let label = profiler.intern_shared_string("typeck");
+        let unique_label = profiler.alloc_string(*unit);
+        ids.push((kind, label));
+
+        profiler.record_instant_event(kind, EventId::from_label(label), 0);
+        profiler.record_instant_event(kind, EventId::from_label(unique_label), 0);
+    }
+
+    drop(cache);
+
+    // Shared strings have the same id in every profile.
+    assert_eq!(ids[0], ids[1]);
+
+    let cache_data = fs::read(ProfilerFiles::new(&cache_stem).string_data_file).unwrap();
+    assert_eq!(count_occurrences(&cache_data, b"typeck"), 1);
+
+    for unit in &["unit-1", "unit-2"] {
+        let path_stem = dir.join(unit);
+        let string_data = fs::read(ProfilerFiles::new(&path_stem).string_data_file).unwrap();
+        assert_eq!(count_occurrences(&string_data, b"typeck"), 0);
+
+        let data = ProfilingData::new(&path_stem).unwrap();
+        assert_eq!(data.metadata.shared_strings.as_deref(), Some("shared"));
+
+        let events: Vec<_> = data
+            .iter()
+            .map(|e| {
+                let e = e.to_event();
+                (e.event_kind.into_owned(), e.label.into_owned())
+            })
+            .collect();
+
+        assert_eq!(
+            events,
+            vec![
+                ("Query".to_string(), "typeck".to_string()),
+                ("Query".to_string(), unit.to_string()),
+            ]
+        );
+    }
+}
+
+#[test]
+fn missing_string_cache() {
+    let dir = Path::new("test-tmp").join("shared_strings_missing");
+    let cache_stem = dir.join("shared");
+    let cache = Arc::new(SharedStringCache::<FileSerializationSink>::new(&cache_stem).unwrap());
+
+    let mut profiler = Profiler::<FileSerializationSink>::new(&dir.join("unit")).unwrap();
+    profiler.set_shared_string_cache(cache);
+    drop(profiler);
+
+    fs::remove_file(ProfilerFiles::new(&cache_stem).string_index_file).unwrap();
+
+    let error = ProfilingData::new(&dir.join("unit"))
+        .unwrap_err()
+        .to_string();
+    assert!(error.contains("doesn't exist"), "{}", error);
+}
+
+#[test]
+fn capped_string_cache() {
+    let path_stem = Path::new("test-tmp").join("capped_string_cache");
+    let config = ProfilerConfig {
+        string_cache_capacity: Some(2),
+        ..ProfilerConfig::default()
+    };
+    let profiler = Profiler::<FileSerializationSink>::with_config(&path_stem, &config).unwrap();
+
+    let kind = profiler.intern_string("Query");
+    let labels = ["file-a", "file-b", "file-a", "file-c", "file-a", "file-b"];
+    for label in &labels {
+        let label = profiler.intern_string(label);
+        profiler.record_instant_event(kind, EventId::from_label(label), 0);
+    }
+    let stats = profiler.string_cache_stats();
+    assert_eq!(stats.hits + stats.misses, 1 + labels.len() as u64);
+    assert!(stats.evictions > 0);
+    drop(profiler);
+
+    // `file-a` is used often enough to stay cached, `file-b` is evicted by
+    // `file-c` and written again.
+    let string_data = fs::read(ProfilerFiles::new(&path_stem).string_data_file).unwrap();
+    assert_eq!(count_occurrences(&string_data, b"file-a"), 1);
+    assert_eq!(count_occurrences(&string_data, b"file-b"), 2);
+
+    let data = ProfilingData::new(&path_stem).unwrap();
+    let recorded: Vec<_> = data
+        .iter()
+        .map(|e| e.to_event().label.into_owned())
+        .collect();
+    assert_eq!(recorded, labels);
+}
+
+fn events(path_stem: &Path) -> Vec<(String, String, Vec<String>)> {
+    ProfilingData::new(path_stem)
+        .unwrap()
+        .iter()
+        .map(|e| {
+            let e = e.to_event();
+            let args = e.additional_data.iter().map(|a| a.to_string()).collect();
+            (e.event_kind.into_owned(), e.label.into_owned(), args)
+        })
+        .collect()
+}
+
+#[test]
+fn sharing_strings_across_profiles() {
+    let dir = Path::new("test-tmp").join("share_strings");
+    let _ = fs::remove_dir_all(&dir);
+    let units = ["unit-1", "unit-2", "unit-3"];
+
+    for unit in &units {
+        let profiler = Profiler::<FileSerializationSink>::new(&dir.join(unit)).unwrap();
+        let builder = EventIdBuilder::new(&profiler);
+        for i in 0..100 {
+            // Every profile writes these strings for every event, and one
+            // string that only it has.
+            let kind = profiler.alloc_string("Query");
+            let label = profiler.alloc_string("typeck");
+            let arg = profiler.alloc_string(if i == 0 { *unit } else { "std::vec::Vec" });
+            profiler.record_instant_event(kind, builder.from_label_and_arg(label, arg), 0);
+        }
+    }
+
+    let path_stems: Vec<_> = units.iter().map(|unit| dir.join(unit)).collect();
+    let before: Vec<_> = path_stems.iter().map(|p| events(p)).collect();
+
+    let dictionary_stem = dir.join("dictionary");
+    let stats = share_strings(&path_stems, &dictionary_stem, 2).unwrap();
+    assert_eq!(stats.profiles, 3);
+    assert_eq!(stats.shared_strings, 3);
+    assert!(stats.bytes_after * 2 < stats.bytes_before, "{:?}", stats);
+
+    let dictionary = fs::read(ProfilerFiles::new(&dictionary_stem).string_data_file).unwrap();
+    assert_eq!(count_occurrences(&dictionary, b"typeck"), 1);
+    assert_eq!(count_occurrences(&dictionary, b"unit-1"), 0);
+
+    for (path_stem, before) in path_stems.iter().zip(&before) {
+        assert_eq!(&events(path_stem), before);
+
+        let string_data = fs::read(ProfilerFiles::new(path_stem).string_data_file).unwrap();
+        assert_eq!(count_occurrences(&string_data, b"typeck"), 0);
+        let data = ProfilingData::new(path_stem).unwrap();
+        assert_eq!(data.metadata.shared_strings.as_deref(), Some("dictionary"));
+    }
+
+    // Profiles outside of the dictionary's directory are rejected.
+    let error = share_strings(&path_stems, &dir.join("elsewhere").join("dictionary"), 2)
+        .unwrap_err()
+        .to_string();
+    assert!(error.contains("isn't in the directory"), "{}", error);
+}
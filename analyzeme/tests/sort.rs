@@ -0,0 +1,55 @@
+use analyzeme::{sort_profile, ProfilingData};
+use measureme::checksum::checksum;
+use measureme::file_header::{file_footer, FILE_FOOTER_SIZE, FILE_HEADER_SIZE};
+use measureme::{EventId, FileSerializationSink, Profiler, ProfilerFiles, TimestampFormat};
+use std::fs;
+use std::path::Path;
+
+fn labels(data: &ProfilingData) -> Vec<String> {
+    data.iter()
+        .map(|e| e.to_event().label.into_owned())
+        .collect()
+}
+
+#[test]
+fn unsorted_events() {
+    let path_stem = Path::new("test-tmp").join("sort").join("profile");
+    {
+        let profiler = Profiler::<FileSerializationSink>::new(&path_stem).unwrap();
+        let kind = profiler.alloc_string("Query");
+        for i in 0..10 {
+            let id = EventId::from_label(profiler.alloc_string(&*format!("q{}", i)));
+            profiler.record_instant_event(kind, id, 0);
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+    }
+    let expected = labels(&ProfilingData::new(&path_stem).unwrap());
+
+    // Reverse the order of the events, as a sink writing the events of
+    // several threads might have reordered them, and fix up the footer.
+    let events_file = ProfilerFiles::new(&path_stem).events_file;
+    let bytes = fs::read(&events_file).unwrap();
+    let event_size = TimestampFormat::Compact.event_size();
+    let (header, events) = bytes[..bytes.len() - FILE_FOOTER_SIZE].split_at(FILE_HEADER_SIZE);
+    let mut shuffled = header.to_vec();
+    for event in events.chunks_exact(event_size).rev() {
+        shuffled.extend_from_slice(event);
+    }
+    let footer = file_footer(checksum(&shuffled));
+    shuffled.extend_from_slice(&footer);
+    fs::write(&events_file, &shuffled).unwrap();
+
+    // Loading sorts the events.
+    assert_eq!(labels(&ProfilingData::new(&path_stem).unwrap()), expected);
+
+    // Sorting the file via runs of three events gives the same order, and
+    // sorting it again does nothing.
+    assert_eq!(
+        sort_profile(&path_stem, 3).unwrap(),
+        vec![events_file.clone()]
+    );
+    assert_eq!(fs::read(&events_file).unwrap().len(), bytes.len());
+    assert_eq!(labels(&ProfilingData::new(&path_stem).unwrap()), expected);
+    assert!(sort_profile(&path_stem, 3).unwrap().is_empty());
+    assert!(!path_stem.with_extension("sorted").exists());
+}
@@ -0,0 +1,42 @@
+use analyzeme::{ArgValue, ProfilingData};
+use measureme::{global, ProfilerConfig};
+use std::path::Path;
+
+#[test]
+fn profile_scope_records_locations() {
+    let path_stem = Path::new("test-tmp").join("source_locations");
+    global::init(&path_stem, &ProfilerConfig::default()).unwrap();
+
+    let line = line!() + 2;
+    for _ in 0..2 {
+        measureme::profile_scope!("Tool", "scope");
+    }
+    global::finish();
+
+    let data = ProfilingData::new(&path_stem).unwrap();
+    let events: Vec<_> = data
+        .iter()
+        .filter(|e| e.duration().is_some())
+        .map(|e| e.to_event())
+        .collect();
+    assert_eq!(events.len(), 2);
+
+    for event in &events {
+        assert_eq!(event.label, "scope");
+        let args = data.decode_args(event);
+        assert_eq!(args.len(), 1);
+        assert_eq!(args[0].name, Some("location"));
+        match args[0].value {
+            ArgValue::Location {
+                ref file,
+                line: event_line,
+                column,
+            } => {
+                assert!(file.ends_with("source_locations.rs"), "{}", file);
+                assert_eq!(event_line, line);
+                assert_eq!(column, 9);
+            }
+            ref other => panic!("expected a location, found {:?}", other),
+        }
+    }
+}
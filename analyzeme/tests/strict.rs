@@ -0,0 +1,303 @@
+use analyzeme::testing_common::{fuzz_decode, join_fuzz_input};
+use analyzeme::{LoadError, LoadErrorKind, ProfilingData, Validation};
+use measureme::checksum::checksum;
+use measureme::file_header::{file_footer, FILE_FOOTER_SIZE, FILE_HEADER_SIZE};
+use measureme::{
+    EventIdBuilder, FileSerializationSink, Profiler, ProfilerFiles, StringComponent, StringId,
+};
+use std::path::Path;
+
+type Files = (Vec<u8>, Vec<u8>, Vec<u8>);
+
+fn record(name: &str, record: impl FnOnce(&Profiler<FileSerializationSink>)) -> Files {
+    let path_stem = Path::new("test-tmp").join("strict").join(name);
+
+    {
+        let profiler = Profiler::<FileSerializationSink>::new(&path_stem).unwrap();
+        record(&profiler);
+    }
+
+    let paths = ProfilerFiles::new(&path_stem);
+    (
+        std::fs::read(&paths.events_file).unwrap(),
+        std::fs::read(&paths.string_data_file).unwrap(),
+        std::fs::read(&paths.string_index_file).unwrap(),
+    )
+}
+
+fn sample_profile() -> Files {
+    record("sample", |profiler| {
+        let query = profiler.alloc_string("Query");
+        let builder = EventIdBuilder::new(profiler);
+        let typeck = builder.from_label_and_arg(
+            profiler.alloc_string("typeck"),
+            profiler.alloc_string("main"),
+        );
+
+        let virtual_id = StringId::new_virtual(7);
+        let type_of = profiler.alloc_string("type_of");
+        profiler.map_virtual_to_concrete_string(virtual_id, type_of);
+
+        let thread_id = profiler.register_current_thread();
+        let _outer = profiler.start_recording_interval_event(query, typeck, thread_id);
+        drop(profiler.start_recording_interval_event(
+            query,
+            builder.from_label(virtual_id),
+            thread_id,
+        ));
+        profiler.record_integer_event(query, typeck, thread_id, 42);
+    })
+}
+
+fn is_corrupt(result: Result<ProfilingData, Box<dyn std::error::Error>>) -> bool {
+    match result {
+        Ok(_) => false,
+        Err(e) => LoadError::kind_of(&*e) == Some(LoadErrorKind::Corrupt),
+    }
+}
+
+#[test]
+fn valid_profile() {
+    let (events, string_data, index) = sample_profile();
+    let data = ProfilingData::from_bytes_strict(events, string_data, index).unwrap();
+
+    let labels: Vec<_> = data
+        .iter()
+        .map(|e| e.to_event().label.into_owned())
+        .filter(|label| label != "foo")
+        .collect();
+    assert!(labels.contains(&"typeck".to_string()));
+    assert!(labels.contains(&"type_of".to_string()));
+}
+
+#[test]
+fn cyclic_strings() {
+    let (events, string_data, index) = record("cycle", |profiler| {
+        let kind = profiler.alloc_string("Query");
+        let virtual_id = StringId::new_virtual(1);
+        let cycle = profiler.alloc_string(&[StringComponent::Ref(virtual_id)][..]);
+        profiler.map_virtual_to_concrete_string(virtual_id, cycle);
+        profiler.record_instant_event(kind, EventIdBuilder::new(profiler).from_label(cycle), 0);
+    });
+
+    let data =
+        ProfilingData::from_bytes(events.clone(), string_data.clone(), index.clone()).unwrap();
+    let event = data.iter().next_back().unwrap().to_event();
+    assert_eq!(event.label, "<unknown>");
+
+    assert!(is_corrupt(ProfilingData::from_bytes_strict(
+        events,
+        string_data,
+        index
+    )));
+}
+
+// Overwrites the `u32` at `offset` of the first event and fixes up the
+// checksum, so that only the event itself is corrupt.
+fn corrupt_first_event(events: &[u8], offset: usize, value: u32) -> Vec<u8> {
+    let mut events = events[..events.len() - FILE_FOOTER_SIZE].to_vec();
+    let pos = FILE_HEADER_SIZE + offset;
+    events[pos..pos + 4].copy_from_slice(&value.to_le_bytes());
+    let footer = file_footer(checksum(&events));
+    events.extend_from_slice(&footer);
+    events
+}
+
+#[test]
+fn out_of_range_ids() {
+    let (events, string_data, index) = sample_profile();
+
+    // The event kind and the event id of the first event.
+    for &offset in &[0, 4] {
+        let events = corrupt_first_event(&events, offset, 0xF000_0000);
+
+        let data =
+            ProfilingData::from_bytes(events.clone(), string_data.clone(), index.clone()).unwrap();
+        let event = data.iter().next().unwrap().to_event();
+        if offset == 0 {
+            assert_eq!(event.event_kind, "<unknown>");
+        } else {
+            assert_eq!(event.label, "<unknown>");
+        }
+
+        let data = ProfilingData::from_bytes_validated(
+            events.clone(),
+            string_data.clone(),
+            index.clone(),
+            Validation::Lenient,
+        )
+        .unwrap();
+        assert_eq!(data.warnings()[0].code, "invalid-event");
+
+        assert!(is_corrupt(ProfilingData::from_bytes_strict(
+            events.clone(),
+            string_data.clone(),
+            index.clone()
+        )));
+        fuzz_decode(&join_fuzz_input(&events, &string_data, &index));
+    }
+}
+
+#[test]
+fn lenient_validation() {
+    let (events, string_data, index) = record("lenient", |profiler| {
+        let kind = profiler.alloc_string("Query");
+        let builder = EventIdBuilder::new(profiler);
+        let virtual_id = StringId::new_virtual(1);
+        let cycle = profiler.alloc_string(&[StringComponent::Ref(virtual_id)][..]);
+        profiler.map_virtual_to_concrete_string(virtual_id, cycle);
+
+        profiler.record_instant_event(kind, builder.from_label(profiler.alloc_string("a")), 0);
+        profiler.record_instant_event(kind, builder.from_label(cycle), 0);
+        profiler.record_instant_event(kind, builder.from_label(profiler.alloc_string("b")), 0);
+    });
+
+    let data = ProfilingData::from_bytes_validated(
+        events.clone(),
+        string_data.clone(),
+        index.clone(),
+        Validation::Lenient,
+    )
+    .unwrap();
+    let labels: Vec<_> = data
+        .iter()
+        .map(|e| e.to_event().label.into_owned())
+        .collect();
+    assert_eq!(labels, vec!["a", "b"]);
+
+    let warnings = data.warnings();
+    assert_eq!(warnings.len(), 1);
+    assert_eq!(warnings[0].code, "invalid-event");
+    assert!(
+        warnings[0].message.starts_with("skipped event 1: "),
+        "{}",
+        warnings[0].message
+    );
+
+    assert!(is_corrupt(ProfilingData::from_bytes_validated(
+        events.clone(),
+        string_data.clone(),
+        index.clone(),
+        Validation::Strict,
+    )));
+
+    let data =
+        ProfilingData::from_bytes_validated(events, string_data, index, Validation::Off).unwrap();
+    assert_eq!(data.num_events(), 3);
+    assert!(data.warnings().is_empty());
+}
+
+// Replaces the `shared_strings` and `tool` fields of the metadata (and of
+// its earlier copies) with
+// `"shared_strings": "<name>"`, padded so that the string data keeps its
+// length, and fixes up the checksum.
+fn with_shared_strings(string_data: &[u8], name: &str) -> Vec<u8> {
+    let fields = br#""shared_strings": null, "tool": null,"#;
+    let mut string_data = string_data[..string_data.len() - FILE_FOOTER_SIZE].to_vec();
+    let mut replacement = format!(r#""shared_strings": "{}","#, name).into_bytes();
+    assert!(replacement.len() <= fields.len());
+    replacement.resize(fields.len(), b' ');
+
+    let positions: Vec<_> = (0..string_data.len() - fields.len())
+        .filter(|&pos| &string_data[pos..pos + fields.len()] == fields)
+        .collect();
+    assert!(!positions.is_empty());
+    for pos in positions {
+        string_data[pos..pos + fields.len()].copy_from_slice(&replacement);
+    }
+
+    let footer = file_footer(checksum(&string_data));
+    string_data.extend_from_slice(&footer);
+    string_data
+}
+
+#[test]
+fn shared_strings_must_be_file_names() {
+    let (events, string_data, index) = sample_profile();
+
+    let plain = with_shared_strings(&string_data, "cache");
+    let data = ProfilingData::from_bytes_strict(events.clone(), plain, index.clone()).unwrap();
+    assert_eq!(data.metadata.shared_strings.as_deref(), Some("cache"));
+
+    for name in &[
+        "../cache",
+        "dir/cache",
+        "..",
+        "c:\\\\cache",
+        "http://x/c",
+        "",
+    ] {
+        let string_data = with_shared_strings(&string_data, name);
+        assert!(
+            is_corrupt(ProfilingData::from_bytes_strict(
+                events.clone(),
+                string_data.clone(),
+                index.clone()
+            )),
+            "{}",
+            name
+        );
+        assert!(is_corrupt(ProfilingData::from_bytes(
+            events.clone(),
+            string_data.clone(),
+            index.clone()
+        )));
+        fuzz_decode(&join_fuzz_input(&events, &string_data, &index));
+    }
+}
+
+#[test]
+fn corpus() {
+    let dir = Path::new("fuzz").join("corpus").join("decode");
+    let mut count = 0;
+
+    for entry in std::fs::read_dir(&dir).unwrap() {
+        fuzz_decode(&std::fs::read(entry.unwrap().path()).unwrap());
+        count += 1;
+    }
+
+    assert!(count > 0);
+}
+
+// A cheap stand-in for the fuzz target that runs with the other tests: the
+// sample profile with random corruptions must never make decoding panic.
+#[test]
+fn random_corruptions() {
+    let (events, string_data, index) = sample_profile();
+    assert!(fuzz_decode(&join_fuzz_input(&events, &string_data, &index)));
+
+    // xorshift, so that failures are reproducible.
+    let mut state = 0x2545_f491_4f6c_dd1du64;
+    let mut random = move |bound: usize| {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        (state % bound as u64) as usize
+    };
+
+    for _ in 0..5_000 {
+        let mut files = [events.clone(), string_data.clone(), index.clone()];
+
+        for _ in 0..1 + random(4) {
+            let file = &mut files[random(3)];
+            if file.is_empty() {
+                continue;
+            }
+
+            let pos = random(file.len());
+            match random(4) {
+                0 => file[pos] ^= 1 << random(8),
+                1 => file[pos] = [0x00, 0x80, 0xC3, 0xFF][random(4)],
+                2 => file.truncate(pos),
+                _ => {
+                    let len = random(file.len() - pos) + 1;
+                    let copy = file[pos..pos + len].to_vec();
+                    let at = random(file.len());
+                    file.splice(at..at, copy);
+                }
+            }
+        }
+
+        fuzz_decode(&join_fuzz_input(&files[0], &files[1], &files[2]));
+    }
+}
@@ -0,0 +1,115 @@
+use analyzeme::{LabelSummary, ProfileSummary};
+use measureme::{EventId, EventIdBuilder, FileSerializationSink, Profiler, ProfilerConfig};
+use std::path::Path;
+use std::time::Duration;
+
+#[test]
+fn summary_sidecar() {
+    let path_stem = Path::new("test-tmp").join("summary").join("profile");
+
+    let config = ProfilerConfig {
+        summary: true,
+        ..ProfilerConfig::default()
+    };
+
+    {
+        let profiler = Profiler::<FileSerializationSink>::with_config(&path_stem, &config).unwrap();
+        let query = profiler.alloc_string("Query");
+        let cache_hit = profiler.alloc_string("QueryCacheHit");
+        let typeck = profiler.alloc_string("typeck");
+        let builder = EventIdBuilder::new(&profiler);
+
+        for arg in &["foo", "bar"] {
+            let event_id = builder.from_label_and_arg(typeck, profiler.alloc_string(*arg));
+            drop(profiler.start_recording_interval_event(query, event_id, 0));
+        }
+        profiler.record_instant_event(cache_hit, EventId::from_label(typeck), 0);
+
+        // Events recorded while paused aren't counted.
+        profiler.pause_recording(0);
+        profiler.record_instant_event(cache_hit, EventId::from_label(typeck), 0);
+        profiler.resume_recording(0);
+
+        let totals = profiler.label_totals();
+        assert_eq!(totals.len(), 3);
+        assert_eq!(totals.iter().map(|t| t.count).sum::<u64>(), 3);
+    }
+
+    let summary = ProfileSummary::new(&path_stem).unwrap();
+
+    let mut labels: Vec<_> = summary
+        .labels
+        .iter()
+        .map(|l| (l.event_kind.as_str(), l.label.as_str(), l.count))
+        .collect();
+    labels.sort();
+    assert_eq!(
+        labels,
+        vec![("Query", "typeck", 2), ("QueryCacheHit", "typeck", 1)]
+    );
+
+    let query: &LabelSummary = summary
+        .labels
+        .iter()
+        .find(|l| l.event_kind == "Query")
+        .unwrap();
+    assert!(query.max_time <= query.total_time);
+
+    let per_kind = summary.total_time_per_kind();
+    assert_eq!(per_kind.len(), 2);
+    assert!(per_kind.contains(&("QueryCacheHit".to_string(), Duration::from_nanos(0))));
+}
+
+#[test]
+fn no_summary_by_default() {
+    let path_stem = Path::new("test-tmp").join("summary").join("disabled");
+
+    {
+        let profiler = Profiler::<FileSerializationSink>::new(&path_stem).unwrap();
+        let kind = profiler.alloc_string("Query");
+        profiler.record_instant_event(kind, EventId::from_label(kind), 0);
+        assert!(profiler.label_totals().is_empty());
+    }
+
+    assert!(ProfileSummary::new(&path_stem).is_err());
+}
+
+#[test]
+fn aggregate_only() {
+    use analyzeme::ProfilingData;
+    use measureme::file_header::FILE_HEADER_SIZE;
+    use measureme::{ProfilerFiles, RecordingMode};
+
+    let path_stem = Path::new("test-tmp").join("summary").join("aggregate_only");
+
+    let config = ProfilerConfig {
+        recording_mode: RecordingMode::AggregateOnly,
+        ..ProfilerConfig::default()
+    };
+
+    {
+        let profiler = Profiler::<FileSerializationSink>::with_config(&path_stem, &config).unwrap();
+        let query = profiler.alloc_string("Query");
+        let typeck = EventId::from_label(profiler.alloc_string("typeck"));
+
+        profiler.register_current_thread();
+        profiler.start_phase("analysis");
+        for _ in 0..10 {
+            drop(profiler.start_recording_interval_event(query, typeck, 0));
+        }
+        profiler.end_phase();
+    }
+
+    // Only the header and the footer, no events.
+    let events_file = std::fs::metadata(ProfilerFiles::new(&path_stem).events_file).unwrap();
+    assert_eq!(events_file.len(), FILE_HEADER_SIZE as u64 + 12);
+
+    let data = ProfilingData::new(&path_stem).unwrap();
+    assert!(data.metadata.aggregate_only);
+    assert_eq!(data.num_events(), 0);
+
+    let summary = ProfileSummary::new(&path_stem).unwrap();
+    assert_eq!(summary.labels.len(), 1);
+    assert_eq!(summary.labels[0].label, "typeck");
+    assert_eq!(summary.labels[0].count, 10);
+}
@@ -0,0 +1,25 @@
+use analyzeme::ProfilingData;
+use measureme::{FileSerializationSink, Profiler, SystemInfo};
+use std::path::Path;
+
+#[test]
+fn system_info_in_metadata() {
+    let path_stem = Path::new("test-tmp").join("system_info").join("profile");
+    drop(Profiler::<FileSerializationSink>::new(&path_stem).unwrap());
+
+    let recorded = SystemInfo::capture();
+    let system = ProfilingData::new(&path_stem)
+        .unwrap()
+        .metadata
+        .system
+        .unwrap();
+    assert_eq!(system.cpu_model, recorded.cpu_model);
+    assert_eq!(system.cores, recorded.cores);
+    assert_eq!(system.memory_bytes, recorded.memory_bytes);
+    assert_eq!(system.os, std::env::consts::OS);
+    assert_eq!(system.os_version, recorded.os_version);
+    assert_eq!(system.arch, std::env::consts::ARCH);
+    assert!(system
+        .describe()
+        .ends_with(&format!("({}, {})", system.os, system.arch)));
+}
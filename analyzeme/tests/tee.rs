@@ -0,0 +1,77 @@
+use analyzeme::ProfilingData;
+use measureme::{EventId, FileSerializationSink, Profiler, TeeSerializationSink};
+use std::path::Path;
+
+type TeeSink = TeeSerializationSink<FileSerializationSink, FileSerializationSink>;
+
+#[test]
+fn both_copies_are_complete_profiles() {
+    let dir = Path::new("test-tmp").join("tee");
+    let path_stem = dir.join("profile");
+
+    {
+        let profiler = Profiler::<TeeSink>::new(&path_stem).unwrap();
+
+        let kind = profiler.alloc_string("Query");
+        for label in &["typeck", "mir_borrowck", "typeck"] {
+            let label = profiler.alloc_string(*label);
+            let _guard =
+                profiler.start_recording_interval_event(kind, EventId::from_label(label), 0);
+        }
+    }
+
+    let labels = |path_stem: &Path| {
+        ProfilingData::new(path_stem)
+            .unwrap()
+            .iter()
+            .map(|event| event.to_event().label.into_owned())
+            .collect::<Vec<_>>()
+    };
+
+    let primary = labels(&path_stem);
+    assert_eq!(primary, vec!["typeck", "mir_borrowck", "typeck"]);
+    assert_eq!(labels(&dir.join("tee").join("profile")), primary);
+}
+
+#[test]
+fn live_ring_buffer_next_to_the_file() {
+    use measureme::file_header::FILE_HEADER_SIZE;
+    use measureme::{
+        ProfilerConfig, ProfilerFiles, RingBufferSink, SerializationSink, RAW_EVENT_SIZE,
+    };
+
+    let path_stem = Path::new("test-tmp").join("tee").join("live");
+    let paths = ProfilerFiles::new(&path_stem);
+    let sink = |path: &Path, ring: RingBufferSink| {
+        TeeSerializationSink::new(FileSerializationSink::from_path(path).unwrap(), ring)
+    };
+
+    // Keep the header and the last two events.
+    let events = RingBufferSink::new(Some(2 * RAW_EVENT_SIZE));
+    let live_events = events.reader();
+
+    let profiler = Profiler::with_sinks(
+        sink(&paths.events_file, events),
+        sink(&paths.string_data_file, RingBufferSink::new(None)),
+        sink(&paths.string_index_file, RingBufferSink::new(None)),
+        &ProfilerConfig::default(),
+    );
+
+    let kind = profiler.alloc_string("Query");
+    for label in &["typeck", "mir_borrowck", "type_of"] {
+        let label = profiler.alloc_string(*label);
+        profiler.record_instant_event(kind, EventId::from_label(label), 0);
+    }
+
+    let snapshot = live_events.snapshot();
+    assert_eq!(
+        snapshot.start_addr.0,
+        FILE_HEADER_SIZE as u64 + RAW_EVENT_SIZE as u64
+    );
+    assert_eq!(snapshot.data.len(), 2 * RAW_EVENT_SIZE);
+
+    drop(profiler);
+
+    let data = ProfilingData::new(&path_stem).unwrap();
+    assert_eq!(data.num_events(), 3);
+}
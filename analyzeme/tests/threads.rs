@@ -0,0 +1,392 @@
+use analyzeme::ProfilingData;
+use measureme::file_header::{FILE_FOOTER_SIZE, FILE_HEADER_SIZE};
+use measureme::{
+    checked_thread_id, with_current_profiler, EventId, EventLayout, FileSerializationSink,
+    Profiler, ProfilerConfig, ProfilerFiles, ThreadIdOverflow, ThreadIdScheme, ThreadPoolHooks,
+};
+use std::path::Path;
+use std::sync::mpsc;
+use std::sync::{Arc, Barrier};
+
+fn record_on_preexisting_threads(file_name_stem: &str, scheme: ThreadIdScheme) {
+    let filestem = Path::new("test-tmp").join("threads").join(file_name_stem);
+
+    // Spawn the "pool" before the profiler exists and hand the profiler to
+    // the threads afterwards.
+    let barrier = Arc::new(Barrier::new(3));
+    let senders_and_threads: Vec<_> = (0..2)
+        .map(|i| {
+            let (sender, receiver) = mpsc::channel::<Arc<Profiler<FileSerializationSink>>>();
+            let barrier = barrier.clone();
+            let thread = std::thread::Builder::new()
+                .name(format!("worker-{}", i))
+                .spawn(move || {
+                    let profiler = receiver.recv().unwrap();
+                    let kind = profiler.alloc_string("Query");
+                    let id = EventId::from_label(profiler.alloc_string("work"));
+
+                    for _ in 0..3 {
+                        let thread_id = profiler.register_current_thread();
+                        let _guard = profiler.start_recording_interval_event(kind, id, thread_id);
+                    }
+
+                    // Keep all threads alive until every thread has been
+                    // registered, so the OS can't reuse thread ids.
+                    barrier.wait();
+                })
+                .unwrap();
+            (sender, thread)
+        })
+        .collect();
+
+    let mut profiler = Profiler::<FileSerializationSink>::new(&filestem).unwrap();
+    profiler.set_thread_id_scheme(scheme);
+    let profiler = Arc::new(profiler);
+
+    for (sender, _) in &senders_and_threads {
+        sender.send(profiler.clone()).unwrap();
+    }
+
+    barrier.wait();
+
+    for (_, thread) in senders_and_threads {
+        thread.join().unwrap();
+    }
+
+    drop(profiler);
+
+    let data = ProfilingData::new(&filestem).unwrap();
+
+    let mut names: Vec<_> = data.thread_names().into_iter().collect();
+    names.sort_by(|a, b| a.1.cmp(&b.1));
+
+    assert_eq!(names.len(), 2);
+    assert_eq!(names[0].1, "worker-0");
+    assert_eq!(names[1].1, "worker-1");
+    assert_ne!(names[0].0, names[1].0);
+
+    if scheme == ThreadIdScheme::Sequential {
+        let mut ids: Vec<_> = names.iter().map(|&(id, _)| id).collect();
+        ids.sort();
+        assert_eq!(ids, vec![0, 1]);
+    }
+
+    // Every thread registered exactly once and recorded three events.
+    for &(thread_id, _) in &names {
+        assert_eq!(data.iter().filter(|e| e.thread_id == thread_id).count(), 4);
+    }
+
+    let dense_ids = data.dense_thread_ids();
+    let mut dense: Vec<_> = dense_ids.values().cloned().collect();
+    dense.sort();
+    assert_eq!(dense, vec![0, 1]);
+}
+
+#[test]
+fn sequential_thread_ids() {
+    record_on_preexisting_threads("sequential_thread_ids", ThreadIdScheme::Sequential);
+}
+
+#[test]
+fn os_thread_ids() {
+    record_on_preexisting_threads("os_thread_ids", ThreadIdScheme::Os);
+}
+
+#[test]
+fn external_thread_ids() {
+    let filestem = Path::new("test-tmp")
+        .join("threads")
+        .join("external_thread_ids");
+    let profiler = Profiler::<FileSerializationSink>::new(&filestem).unwrap();
+    let kind = profiler.alloc_string("Query");
+    let id = EventId::from_label(profiler.alloc_string("poll"));
+
+    // Task ids that would collide if they were truncated to 32 bits.
+    let main = profiler.register_current_thread();
+    let task_1 = profiler.register_external_thread(1 << 32, "task-1");
+    let task_2 = profiler.register_external_thread(2 << 32, "task-2");
+    assert_eq!(
+        profiler.register_external_thread(1 << 32, "ignored"),
+        task_1
+    );
+    assert_eq!(
+        [main, task_1, task_2],
+        [0, 1, 2],
+        "external ids are kept apart from registered threads"
+    );
+
+    for &thread_id in &[task_1, task_2, task_1] {
+        drop(profiler.start_recording_interval_event(kind, id, thread_id));
+    }
+    drop(profiler);
+
+    let data = ProfilingData::new(&filestem).unwrap();
+
+    let external_ids = data.external_thread_ids();
+    assert_eq!(external_ids.len(), 2);
+    assert_eq!(external_ids[&task_1], 1 << 32);
+    assert_eq!(external_ids[&task_2], 2 << 32);
+
+    let names = data.thread_names();
+    assert_eq!(names[&task_1], "task-1");
+    assert_eq!(names[&task_2], "task-2");
+
+    assert_eq!(checked_thread_id(70_000), Ok(70_000));
+    assert_eq!(
+        checked_thread_id(1 << 32),
+        Err(ThreadIdOverflow { id: 1 << 32 })
+    );
+}
+
+#[test]
+fn tracks() {
+    let filestem = Path::new("test-tmp").join("threads").join("tracks");
+    let profiler = Arc::new(Profiler::<FileSerializationSink>::new(&filestem).unwrap());
+    let kind = profiler.alloc_string("Query");
+    let id = EventId::from_label(profiler.alloc_string("poll"));
+
+    let main = profiler.register_current_thread();
+    let task_1 = profiler.open_track("task-1");
+    let task_2 = profiler.open_track("task-2");
+    assert_eq!([main, task_1, task_2], [0, 1, 2]);
+
+    // Both tasks are polled on both threads, like on a work-stealing pool.
+    for _ in 0..2 {
+        drop(profiler.start_recording_interval_event(kind, id, task_1));
+        let worker = {
+            let profiler = profiler.clone();
+            std::thread::spawn(move || {
+                drop(profiler.start_recording_interval_event(kind, id, task_1));
+                drop(profiler.start_recording_interval_event(kind, id, task_2));
+            })
+        };
+        worker.join().unwrap();
+        drop(profiler.start_recording_interval_event(kind, id, task_2));
+    }
+    profiler.close_track(task_1);
+    drop(profiler);
+
+    let data = ProfilingData::new(&filestem).unwrap();
+
+    let tracks = data.tracks();
+    assert_eq!(tracks.len(), 2);
+    assert_eq!(
+        (tracks[0].track_id, &tracks[0].name[..]),
+        (task_1, "task-1")
+    );
+    assert_eq!(
+        (tracks[1].track_id, &tracks[1].name[..]),
+        (task_2, "task-2")
+    );
+    assert!(tracks[0].closed.is_some());
+    assert_eq!(tracks[1].closed, None);
+
+    assert_eq!(data.thread_names()[&task_2], "task-2");
+    for &track_id in &[task_1, task_2] {
+        let intervals = data
+            .iter()
+            .filter(|e| e.thread_id == track_id && !e.timestamp.is_instant())
+            .count();
+        assert_eq!(intervals, 4);
+    }
+
+    let thread_ends = data.thread_ends();
+    let finished = |thread_id| {
+        thread_ends
+            .iter()
+            .find(|thread_end| thread_end.thread_id == thread_id)
+            .unwrap()
+            .finished
+    };
+    assert_eq!(finished(task_1), tracks[0].closed);
+    assert_eq!(finished(task_2), None);
+}
+
+#[test]
+fn concurrent_interning() {
+    let filestem = Path::new("test-tmp")
+        .join("threads")
+        .join("concurrent_interning");
+    let profiler = Arc::new(Profiler::<FileSerializationSink>::new(&filestem).unwrap());
+
+    // Enough strings for the string table to write its shards several times,
+    // plus one that is too large to be buffered.
+    let threads: Vec<_> = (0..8)
+        .map(|i| {
+            let profiler = profiler.clone();
+            std::thread::spawn(move || {
+                let kind = profiler.alloc_string("Query");
+                let thread_id = profiler.register_current_thread();
+
+                for j in 0..2_000 {
+                    let label = if j == 1_000 {
+                        format!("{}-{}", i, "x".repeat(100_000))
+                    } else {
+                        format!("{}-{}", i, j)
+                    };
+                    let id = EventId::from_label(profiler.alloc_string(&label[..]));
+                    drop(profiler.start_recording_interval_event(kind, id, thread_id));
+                }
+            })
+        })
+        .collect();
+
+    for thread in threads {
+        thread.join().unwrap();
+    }
+
+    drop(profiler);
+
+    let data = ProfilingData::new(&filestem).unwrap();
+    let mut labels: Vec<_> = data
+        .iter()
+        .map(|e| e.to_event())
+        .filter(|e| e.event_kind == "Query")
+        .map(|e| e.label.into_owned())
+        .collect();
+    labels.sort();
+
+    let mut expected: Vec<_> = (0..8)
+        .flat_map(|i| {
+            (0..2_000).map(move |j| {
+                if j == 1_000 {
+                    format!("{}-{}", i, "x".repeat(100_000))
+                } else {
+                    format!("{}-{}", i, j)
+                }
+            })
+        })
+        .collect();
+    expected.sort();
+
+    assert_eq!(labels, expected);
+}
+
+#[test]
+fn per_thread_event_files() {
+    let filestem = Path::new("test-tmp")
+        .join("threads")
+        .join("per_thread_event_files");
+    let config = ProfilerConfig {
+        event_layout: EventLayout::PerThread,
+        ..ProfilerConfig::default()
+    };
+    let profiler =
+        Arc::new(Profiler::<FileSerializationSink>::with_config(&filestem, &config).unwrap());
+
+    let threads: Vec<_> = (0..4)
+        .map(|i| {
+            let profiler = profiler.clone();
+            std::thread::spawn(move || {
+                let kind = profiler.alloc_string("Query");
+                let id = EventId::from_label(profiler.alloc_string(&*format!("work-{}", i)));
+                let thread_id = profiler.register_current_thread();
+
+                for _ in 0..1_000 {
+                    drop(profiler.start_recording_interval_event(kind, id, thread_id));
+                }
+            })
+        })
+        .collect();
+
+    for thread in threads {
+        thread.join().unwrap();
+    }
+
+    drop(profiler);
+
+    let paths = ProfilerFiles::new(&filestem);
+    assert_eq!(
+        std::fs::read(&paths.events_file).unwrap().len(),
+        FILE_HEADER_SIZE + FILE_FOOTER_SIZE
+    );
+    for index in 0..4 {
+        assert!(paths.thread_events_file(index).exists());
+    }
+
+    let data = ProfilingData::new(&filestem).unwrap();
+    assert_eq!(data.metadata.thread_event_files.len(), 4);
+
+    let events: Vec<_> = data.iter().map(|e| e.to_event()).collect();
+    assert!(events
+        .windows(2)
+        .all(|pair| pair[0].timestamp.end() <= pair[1].timestamp.end()));
+
+    let mut counts = std::collections::HashMap::new();
+    for event in events.iter().filter(|e| e.event_kind == "Query") {
+        *counts.entry(event.label.to_string()).or_insert(0) += 1;
+    }
+    assert_eq!(counts.len(), 4);
+    assert!(counts.values().all(|&count| count == 1_000));
+    assert_eq!(data.thread_names().len(), 4);
+}
+
+#[test]
+fn thread_pool_hooks() {
+    let filestem = Path::new("test-tmp")
+        .join("threads")
+        .join("thread_pool_hooks");
+    let profiler = Arc::new(Profiler::<FileSerializationSink>::new(&filestem).unwrap());
+    let hooks = ThreadPoolHooks::new(profiler.clone()).with_name("workers");
+
+    // Two threads of a pool with start and exit hooks, one of them named by
+    // the pool, and one thread running its body via `run()`.
+    let record = || {
+        with_current_profiler(|profiler: &Profiler<FileSerializationSink>, thread_id| {
+            let kind = profiler.alloc_string("Query");
+            let id = EventId::from_label(profiler.alloc_string("work"));
+            drop(profiler.start_recording_interval_event(kind, id, thread_id));
+        })
+        .unwrap();
+    };
+
+    let pool_threads: Vec<_> = (0..2)
+        .map(|index| {
+            let start_handler = hooks.start_handler();
+            let exit_handler = hooks.exit_handler();
+            let mut builder = std::thread::Builder::new();
+            if index == 1 {
+                builder = builder.name("named".to_string());
+            }
+            builder
+                .spawn(move || {
+                    start_handler(index);
+                    record();
+                    exit_handler(index);
+                    assert!(
+                        with_current_profiler(|_: &Profiler<FileSerializationSink>, _| ())
+                            .is_none()
+                    );
+                })
+                .unwrap()
+        })
+        .collect();
+
+    let run_hooks = hooks.clone();
+    let run_thread = std::thread::spawn(move || run_hooks.run(record));
+
+    for thread in pool_threads {
+        thread.join().unwrap();
+    }
+    run_thread.join().unwrap();
+
+    assert!(with_current_profiler(|_: &Profiler<FileSerializationSink>, _| ()).is_none());
+    drop(hooks);
+    drop(profiler);
+
+    let data = ProfilingData::new(&filestem).unwrap();
+
+    let mut names: Vec<_> = data.thread_names().into_values().collect();
+    names.sort();
+    assert_eq!(names, vec!["named", "workers", "workers-0"]);
+
+    // Every thread recorded its event and finished.
+    let ends = data.thread_ends();
+    assert_eq!(ends.len(), 3);
+    for end in ends {
+        assert!(end.finished.is_some());
+        assert!(data
+            .iter()
+            .any(|e| e.thread_id == end.thread_id && e.to_event().label == "work"));
+    }
+}
@@ -0,0 +1,39 @@
+use analyzeme::{ProfilingData, ToolInfo};
+use measureme::{FileSerializationSink, Profiler};
+use std::path::Path;
+
+#[test]
+fn tool_info_in_metadata() {
+    let path_stem = Path::new("test-tmp").join("tool_info").join("with_info");
+
+    {
+        let profiler = Profiler::<FileSerializationSink>::new(&path_stem).unwrap();
+        profiler.set_tool_info(measureme::ToolInfo {
+            name: "rustc".to_string(),
+            version: "1.50.0-nightly".to_string(),
+            git_sha: Some("0123abcd".to_string()),
+            flags: vec![
+                "-Zself-profile-events=default,args".to_string(),
+                "--cfg \"feature=\\\"std\\\"\"".to_string(),
+            ],
+        });
+    }
+
+    let data = ProfilingData::new(&path_stem).unwrap();
+    assert_eq!(
+        data.metadata.tool,
+        Some(ToolInfo {
+            name: "rustc".to_string(),
+            version: "1.50.0-nightly".to_string(),
+            git_sha: Some("0123abcd".to_string()),
+            flags: vec![
+                "-Zself-profile-events=default,args".to_string(),
+                "--cfg \"feature=\\\"std\\\"\"".to_string(),
+            ],
+        })
+    );
+
+    let path_stem = Path::new("test-tmp").join("tool_info").join("without_info");
+    drop(Profiler::<FileSerializationSink>::new(&path_stem).unwrap());
+    assert_eq!(ProfilingData::new(&path_stem).unwrap().metadata.tool, None);
+}
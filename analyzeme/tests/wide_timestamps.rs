@@ -0,0 +1,266 @@
+use analyzeme::{ProfilingData, ProfilingDataBuilder, Timestamp};
+use measureme::checksum::checksum;
+use measureme::config::Clock;
+use measureme::file_header::{file_footer, FEATURE_MICROSECOND_TIMESTAMPS, FILE_FOOTER_SIZE};
+use measureme::{
+    EventId, FileSerializationSink, Profiler, ProfilerConfig, ProfilerFiles, TimestampFormat,
+    TimestampResolution, MAX_INTERVAL_TIMESTAMP, MAX_INTERVAL_TIMESTAMP_WITHOUT_INTEGERS,
+    MAX_WIDE_INTEGER_VALUE, MAX_WIDE_TIMESTAMP, WIDE_RAW_EVENT_SIZE,
+};
+use std::path::Path;
+use std::time::{Duration, UNIX_EPOCH};
+
+#[test]
+fn profiler_writes_wide_events() {
+    let path_stem = Path::new("test-tmp")
+        .join("wide_timestamps")
+        .join("profiler_writes_wide_events");
+
+    let config = ProfilerConfig {
+        timestamp_format: TimestampFormat::Wide,
+        ..ProfilerConfig::default()
+    };
+
+    {
+        let profiler = Profiler::<FileSerializationSink>::with_config(&path_stem, &config).unwrap();
+        let kind = profiler.alloc_string("Query");
+        let label = EventId::from_label(profiler.alloc_string("typeck"));
+
+        profiler.record_instant_event(kind, label, 0);
+        profiler.record_integer_event(kind, label, 0, MAX_WIDE_INTEGER_VALUE);
+        drop(profiler.start_recording_interval_event(kind, label, 0));
+    }
+
+    let events_file = std::fs::read(ProfilerFiles::new(&path_stem).events_file).unwrap();
+    assert_eq!(&events_file[0..4], b"MMEW");
+    // The feature flags announce the wide timestamps.
+    assert_eq!(events_file[12] & 1, 1);
+
+    let data = ProfilingData::new(&path_stem).unwrap();
+    assert_eq!(data.timestamp_format(), TimestampFormat::Wide);
+    assert_eq!(data.num_events(), 3);
+
+    let events: Vec<_> = data.iter().map(|e| e.to_event()).collect();
+    assert!(events[0].timestamp.is_instant());
+    assert_eq!(events[1].integer_value, Some(MAX_WIDE_INTEGER_VALUE));
+    assert!(!events[2].timestamp.is_instant());
+
+    // Sanity check of the event size: header, three events and the footer.
+    assert_eq!(events_file.len(), 32 + 3 * WIDE_RAW_EVENT_SIZE + 12);
+}
+
+#[test]
+fn timestamps_beyond_the_compact_range() {
+    // An interval that ends after several years of uptime.
+    let end = 5 * 365 * 24 * 3600 * 1_000_000_000u64;
+    assert!(end > MAX_INTERVAL_TIMESTAMP);
+
+    let mut b = ProfilingDataBuilder::with_timestamp_format(TimestampFormat::Wide);
+    b.interval("Query", "typeck", 0, end - 10, end, |_| {});
+    b.instant("Heartbeat", "", 0, MAX_WIDE_TIMESTAMP);
+
+    let data = b.into_profiling_data();
+    let events: Vec<_> = data.iter().map(|e| e.to_event()).collect();
+
+    assert_eq!(
+        events[0].timestamp,
+        Timestamp::Interval {
+            start: UNIX_EPOCH + Duration::from_nanos(end - 10),
+            end: UNIX_EPOCH + Duration::from_nanos(end),
+        }
+    );
+    assert_eq!(
+        events[1].timestamp,
+        Timestamp::Instant(UNIX_EPOCH + Duration::from_nanos(MAX_WIDE_TIMESTAMP))
+    );
+}
+
+#[test]
+fn compact_timestamps_without_integer_events() {
+    // An interval that ends after three days: beyond the range of the
+    // compact format with integer events, but not of the one without.
+    let end = 72 * 3600 * 1_000_000_000u64;
+    assert!(end > MAX_INTERVAL_TIMESTAMP);
+    assert!(end < MAX_INTERVAL_TIMESTAMP_WITHOUT_INTEGERS);
+
+    let mut b =
+        ProfilingDataBuilder::with_timestamp_format(TimestampFormat::CompactWithoutIntegers);
+    b.interval("Query", "typeck", 0, end - 10, end, |_| {});
+    b.instant("Heartbeat", "", 0, end + 10);
+
+    let data = b.into_profiling_data();
+    assert_eq!(
+        data.timestamp_format(),
+        TimestampFormat::CompactWithoutIntegers
+    );
+
+    let events: Vec<_> = data.iter().map(|e| e.to_event()).collect();
+    assert_eq!(
+        events[0].timestamp,
+        Timestamp::Interval {
+            start: UNIX_EPOCH + Duration::from_nanos(end - 10),
+            end: UNIX_EPOCH + Duration::from_nanos(end),
+        }
+    );
+    assert_eq!(events[0].integer_value, None);
+    assert_eq!(
+        events[1].timestamp,
+        Timestamp::Instant(UNIX_EPOCH + Duration::from_nanos(end + 10))
+    );
+}
+
+#[test]
+fn cpu_ids() {
+    let record = |name: &str, timestamp_format| {
+        let path_stem = Path::new("test-tmp").join("wide_timestamps").join(name);
+        let config = ProfilerConfig {
+            timestamp_format,
+            record_cpu: true,
+            ..ProfilerConfig::default()
+        };
+
+        {
+            let profiler =
+                Profiler::<FileSerializationSink>::with_config(&path_stem, &config).unwrap();
+            let kind = profiler.alloc_string("Query");
+            let label = EventId::from_label(profiler.alloc_string("typeck"));
+            profiler.record_instant_event(kind, label, 0);
+            drop(profiler.start_recording_interval_event(kind, label, 0));
+        }
+
+        ProfilingData::new(&path_stem).unwrap()
+    };
+
+    let data = record("cpu_ids", TimestampFormat::Wide);
+    assert_eq!(data.num_events(), 2);
+    for event in data.iter() {
+        assert_eq!(event.cpu(), event.to_event().cpu);
+        assert_eq!(event.cpu().is_some(), cfg!(target_os = "linux"));
+    }
+
+    // The compact format has no room for them.
+    let data = record("cpu_ids_compact", TimestampFormat::Compact);
+    assert!(data.iter().all(|event| event.cpu().is_none()));
+}
+
+#[test]
+fn microsecond_resolution() {
+    let record = |name: &str, timestamp_resolution, clock| {
+        let path_stem = Path::new("test-tmp").join("wide_timestamps").join(name);
+        let config = ProfilerConfig {
+            timestamp_resolution,
+            clock,
+            ..ProfilerConfig::default()
+        };
+
+        let profiler = Profiler::<FileSerializationSink>::with_config(&path_stem, &config).unwrap();
+        let kind = profiler.alloc_string("Query");
+        let label = EventId::from_label(profiler.alloc_string("typeck"));
+        {
+            let _outer = profiler.start_recording_interval_event(kind, label, 0);
+            std::thread::sleep(Duration::from_millis(2));
+            drop(profiler.start_recording_interval_event(kind, label, 0));
+        }
+        drop(profiler);
+
+        let events_file = std::fs::read(ProfilerFiles::new(&path_stem).events_file).unwrap();
+        (events_file, ProfilingData::new(&path_stem).unwrap())
+    };
+
+    let (events_file, data) = record(
+        "microsecond_resolution",
+        TimestampResolution::Microseconds,
+        Clock::Monotonic,
+    );
+    // The feature flags announce the resolution, so that the timestamps are
+    // scaled back to nanoseconds.
+    assert_eq!(
+        events_file[12] & FEATURE_MICROSECOND_TIMESTAMPS as u8,
+        FEATURE_MICROSECOND_TIMESTAMPS as u8
+    );
+    assert_eq!(
+        data.timestamp_resolution(),
+        TimestampResolution::Microseconds
+    );
+
+    let events: Vec<_> = data.iter().map(|e| e.to_event()).collect();
+    let outer = events[1].duration().unwrap();
+    assert!(outer >= Duration::from_millis(2), "{:?}", outer);
+    for event in &events {
+        let start = event
+            .timestamp
+            .start()
+            .duration_since(data.metadata.start_time)
+            .unwrap();
+        assert_eq!(start.subsec_nanos() % 1_000, 0);
+    }
+    // Rounding keeps the events nested.
+    assert!(events[0].timestamp.start() >= events[1].timestamp.start());
+    assert!(events[0].timestamp.end() <= events[1].timestamp.end());
+
+    // Logical timestamps are counters, which can't be rounded.
+    let (events_file, data) = record(
+        "microsecond_resolution_logical",
+        TimestampResolution::Microseconds,
+        Clock::Logical,
+    );
+    assert_eq!(events_file[12] & FEATURE_MICROSECOND_TIMESTAMPS as u8, 0);
+    assert_eq!(
+        data.timestamp_resolution(),
+        TimestampResolution::Nanoseconds
+    );
+}
+
+#[test]
+fn compact_profiles_drop_out_of_range_events() {
+    let path_stem = Path::new("test-tmp")
+        .join("wide_timestamps")
+        .join("compact_out_of_range");
+    drop(Profiler::<FileSerializationSink>::new(&path_stem).unwrap());
+
+    // Move the start of the profile three days into the past, so that the
+    // resumed profiler's intervals end outside of the compact range while
+    // its instant events are still within it.
+    let string_data_file = ProfilerFiles::new(&path_stem).string_data_file;
+    let string_data = std::fs::read(&string_data_file).unwrap();
+    let mut string_data = string_data[..string_data.len() - FILE_FOOTER_SIZE].to_vec();
+    let key = b"\"start_time\": ";
+    let pos = string_data
+        .windows(key.len())
+        .rposition(|w| w == key)
+        .unwrap()
+        + key.len();
+    let len = string_data[pos..]
+        .iter()
+        .take_while(|b| b.is_ascii_digit())
+        .count();
+    let start_time: u64 = std::str::from_utf8(&string_data[pos..pos + len])
+        .unwrap()
+        .parse()
+        .unwrap();
+    let moved = (start_time - 72 * 3600 * 1_000_000_000).to_string();
+    assert_eq!(moved.len(), len);
+    string_data[pos..pos + len].copy_from_slice(moved.as_bytes());
+    let footer = file_footer(checksum(&string_data));
+    string_data.extend_from_slice(&footer);
+    std::fs::write(&string_data_file, string_data).unwrap();
+
+    {
+        let profiler = Profiler::<FileSerializationSink>::resume(&path_stem).unwrap();
+        let kind = profiler.alloc_string("Query");
+        let label = EventId::from_label(profiler.alloc_string("typeck"));
+        profiler.record_instant_event(kind, label, 0);
+        // Used to panic when the guard was dropped.
+        drop(profiler.start_recording_interval_event(kind, label, 0));
+    }
+
+    let data = ProfilingData::new(&path_stem).unwrap();
+    assert_eq!(data.metadata.dropped_events, 1);
+    let events: Vec<_> = data
+        .iter()
+        .map(|e| e.to_event())
+        .filter(|e| e.event_kind == "Query")
+        .collect();
+    assert_eq!(events.len(), 1);
+    assert!(events[0].timestamp.is_instant());
+}
@@ -0,0 +1,92 @@
+use analyzeme::ProfilingData;
+use measureme::{Addr, EventId, FileSerializationSink, Profiler, SerializationSink, WriteError};
+use std::error::Error;
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+const EVENTS_FILE_CAPACITY: usize = 1000;
+
+/// A sink that behaves like a disk running full once more than
+/// `EVENTS_FILE_CAPACITY` bytes have been written to the `.events` file.
+struct DiskFullSink {
+    inner: FileSerializationSink,
+    capacity: Option<usize>,
+    bytes_written: AtomicUsize,
+    failed: AtomicBool,
+}
+
+impl SerializationSink for DiskFullSink {
+    fn from_path(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let capacity = if path.extension().unwrap() == "events" {
+            Some(EVENTS_FILE_CAPACITY)
+        } else {
+            None
+        };
+
+        Ok(DiskFullSink {
+            inner: FileSerializationSink::from_path(path)?,
+            capacity,
+            bytes_written: AtomicUsize::new(0),
+            failed: AtomicBool::new(false),
+        })
+    }
+
+    fn write_atomic<W>(&self, num_bytes: usize, write: W) -> Addr
+    where
+        W: FnOnce(&mut [u8]),
+    {
+        let pos = self.bytes_written.fetch_add(num_bytes, Ordering::SeqCst);
+
+        match self.capacity {
+            Some(capacity) if pos + num_bytes > capacity => {
+                self.failed.store(true, Ordering::SeqCst);
+                Addr(pos as u64)
+            }
+            _ => self.inner.write_atomic(num_bytes, write),
+        }
+    }
+
+    fn has_failed(&self) -> bool {
+        self.failed.load(Ordering::SeqCst)
+    }
+
+    fn write_error(&self) -> Option<WriteError> {
+        if self.has_failed() {
+            Some(WriteError::new(io::ErrorKind::StorageFull, "disk full"))
+        } else {
+            None
+        }
+    }
+}
+
+#[test]
+fn stop_recording_on_write_failure() {
+    let filestem = Path::new("test-tmp")
+        .join("write_failure")
+        .join("stop_recording_on_write_failure");
+
+    {
+        let profiler = Profiler::<DiskFullSink>::new(&filestem).unwrap();
+        let kind = profiler.alloc_string("Generic");
+        let event_id = EventId::from_label(profiler.alloc_string("event"));
+
+        assert!(profiler.health().is_ok());
+
+        for _ in 0..100 {
+            profiler.record_instant_event(kind, event_id, 0);
+        }
+
+        // Not all of the events fit.
+        assert_eq!(
+            profiler.health().unwrap_err().kind,
+            io::ErrorKind::StorageFull
+        );
+    }
+
+    let data = ProfilingData::new(&filestem).unwrap();
+
+    assert!(data.metadata.truncated);
+    let num_events = data.iter().count();
+    assert!(num_events > 0 && num_events < 100);
+}
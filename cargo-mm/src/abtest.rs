@@ -0,0 +1,427 @@
+//! `cargo mm abtest` compares repeated runs of a benchmark, e.g. five
+//! profiles of a build on `main` and five of the same build on a branch, and
+//! reports only the labels whose self time changed significantly. A single
+//! pair of profiles can't tell a regression from run-to-run noise, which for
+//! small queries is often larger than the change one is looking for.
+
+use analyzeme::{ProfilingData, TimelineEvent};
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Test {
+    /// Welch's t-test, which compares the means without assuming that both
+    /// sets of runs have the same variance.
+    Welch,
+    /// The Mann-Whitney U test, which compares the ranks of the runs and thus
+    /// isn't thrown off by outliers, e.g. a run during which the machine was
+    /// busy with something else.
+    MannWhitney,
+}
+
+impl FromStr for Test {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Test, String> {
+        match s {
+            "welch" => Ok(Test::Welch),
+            "mann-whitney" => Ok(Test::MannWhitney),
+            other => Err(format!(
+                "invalid test `{}`, expected `welch` or `mann-whitney`",
+                other
+            )),
+        }
+    }
+}
+
+impl Test {
+    /// Returns the two-sided p-value for the null hypothesis that both
+    /// samples come from the same distribution.
+    fn p_value(self, a: &[f64], b: &[f64]) -> f64 {
+        match self {
+            Test::Welch => welch_t_test(a, b),
+            Test::MannWhitney => mann_whitney_u_test(a, b),
+        }
+    }
+}
+
+struct Change {
+    label: String,
+    base: Duration,
+    changed: Duration,
+    p_value: f64,
+}
+
+pub fn print_changes(
+    base_dir: &Path,
+    changed_dir: &Path,
+    test: Test,
+    alpha: f64,
+) -> Result<(), Box<dyn Error>> {
+    let base = self_times_of_runs(base_dir)?;
+    let changed = self_times_of_runs(changed_dir)?;
+
+    let labels: BTreeSet<&String> = base.iter().chain(&changed).flat_map(|r| r.keys()).collect();
+
+    let mut changes = Vec::new();
+    for label in &labels {
+        // A label that doesn't occur in a run took no time in it.
+        let samples = |runs: &[BTreeMap<String, u64>]| -> Vec<f64> {
+            runs.iter()
+                .map(|run| run.get(*label).copied().unwrap_or(0) as f64)
+                .collect()
+        };
+        let (a, b) = (samples(&base), samples(&changed));
+
+        let p_value = test.p_value(&a, &b);
+        if p_value < alpha {
+            changes.push(Change {
+                label: label.to_string(),
+                base: Duration::from_nanos(mean(&a) as u64),
+                changed: Duration::from_nanos(mean(&b) as u64),
+                p_value,
+            });
+        }
+    }
+
+    // The largest absolute changes first.
+    let delta = |c: &Change| (c.changed.as_nanos() as i128 - c.base.as_nanos() as i128).abs();
+    changes.sort_by(|a, b| delta(b).cmp(&delta(a)).then_with(|| a.label.cmp(&b.label)));
+
+    println!(
+        "Compared {} runs in `{}` with {} runs in `{}`.",
+        base.len(),
+        base_dir.display(),
+        changed.len(),
+        changed_dir.display()
+    );
+
+    if changes.is_empty() {
+        println!(
+            "None of the {} labels changed significantly (p < {}).",
+            labels.len(),
+            alpha
+        );
+        return Ok(());
+    }
+
+    println!(
+        "{} of {} labels changed significantly (p < {}), by mean self time:",
+        changes.len(),
+        labels.len(),
+        alpha
+    );
+    println!();
+    println!(
+        "  {:>12}  {:>12}  {:>9}  {:>8}  Label",
+        "Base", "Changed", "Change", "p"
+    );
+    for change in &changes {
+        let percent = if change.base.as_nanos() == 0 {
+            "-".to_string()
+        } else {
+            let nanos = |d: Duration| d.as_nanos() as f64;
+            format!(
+                "{:+.2}%",
+                (nanos(change.changed) - nanos(change.base)) / nanos(change.base) * 100.0
+            )
+        };
+
+        println!(
+            "  {:>12}  {:>12}  {:>9}  {:>8.4}  {}",
+            format!("{:.2?}", change.base),
+            format!("{:.2?}", change.changed),
+            percent,
+            change.p_value,
+            change.label
+        );
+    }
+
+    Ok(())
+}
+
+/// Loads the profiles in `dir` and returns the self time per label, in
+/// nanoseconds, of each of them.
+fn self_times_of_runs(dir: &Path) -> Result<Vec<BTreeMap<String, u64>>, Box<dyn Error>> {
+    let mut path_stems: Vec<PathBuf> = fs::read_dir(dir)
+        .map_err(|e| format!("could not read `{}`: {}", dir.display(), e))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|e| e == "events"))
+        .map(|path| path.with_extension(""))
+        .collect();
+    path_stems.sort();
+
+    if path_stems.len() < 2 {
+        Err(format!(
+            "`{}` contains {} profiles, but at least two runs are needed to tell changes \
+             from noise",
+            dir.display(),
+            path_stems.len()
+        ))?;
+    }
+
+    Ok(ProfilingData::load_all(&path_stems)?
+        .iter()
+        .map(self_times)
+        .collect())
+}
+
+fn self_times(data: &ProfilingData) -> BTreeMap<String, u64> {
+    fn visit(event: &TimelineEvent<'_>, self_times: &mut BTreeMap<String, u64>) {
+        let duration = match event.event.duration() {
+            Some(duration) => duration.as_nanos() as u64,
+            None => return,
+        };
+        let children: u64 = event
+            .children
+            .iter()
+            .filter_map(|child| child.event.duration())
+            .map(|d| d.as_nanos() as u64)
+            .sum();
+
+        let label = event.event.to_event().label.into_owned();
+        *self_times.entry(label).or_default() += duration.saturating_sub(children);
+
+        for child in &event.children {
+            visit(child, self_times);
+        }
+    }
+
+    let mut self_times = BTreeMap::new();
+    for timeline in data.per_thread_timelines() {
+        for event in &timeline.events {
+            visit(event, &mut self_times);
+        }
+    }
+    self_times
+}
+
+fn mean(xs: &[f64]) -> f64 {
+    xs.iter().sum::<f64>() / xs.len() as f64
+}
+
+fn variance(xs: &[f64]) -> f64 {
+    let m = mean(xs);
+    xs.iter().map(|x| (x - m) * (x - m)).sum::<f64>() / (xs.len() - 1) as f64
+}
+
+pub(crate) fn welch_t_test(a: &[f64], b: &[f64]) -> f64 {
+    let (va, vb) = (variance(a) / a.len() as f64, variance(b) / b.len() as f64);
+    let difference = mean(b) - mean(a);
+
+    // Without any noise, every difference is significant.
+    if va + vb == 0.0 {
+        return if difference == 0.0 { 1.0 } else { 0.0 };
+    }
+
+    let t = difference / (va + vb).sqrt();
+    // The Welch-Satterthwaite approximation of the degrees of freedom.
+    let df = (va + vb).powi(2) / (va * va / (a.len() - 1) as f64 + vb * vb / (b.len() - 1) as f64);
+
+    // The two-sided tail probability of Student's t distribution.
+    regularized_incomplete_beta(df / (df + t * t), df / 2.0, 0.5)
+}
+
+/// Uses the normal approximation of the distribution of U, with a
+/// correction for ties and for continuity, which is accurate enough from
+/// about five runs on each side.
+fn mann_whitney_u_test(a: &[f64], b: &[f64]) -> f64 {
+    let mut all: Vec<(f64, bool)> = a
+        .iter()
+        .map(|&x| (x, true))
+        .chain(b.iter().map(|&x| (x, false)))
+        .collect();
+    all.sort_by(|x, y| x.0.partial_cmp(&y.0).unwrap());
+
+    let (n1, n2) = (a.len() as f64, b.len() as f64);
+    let n = n1 + n2;
+
+    // Tied values get the mean of the ranks they span.
+    let mut rank_sum_a = 0.0;
+    let mut tie_correction = 0.0;
+    let mut start = 0;
+    while start < all.len() {
+        let end = start
+            + all[start..]
+                .iter()
+                .take_while(|x| x.0 == all[start].0)
+                .count();
+        let rank = (start + end + 1) as f64 / 2.0;
+        rank_sum_a += rank * all[start..end].iter().filter(|x| x.1).count() as f64;
+
+        let ties = (end - start) as f64;
+        tie_correction += ties * ties * ties - ties;
+        start = end;
+    }
+
+    let u = rank_sum_a - n1 * (n1 + 1.0) / 2.0;
+    let mean_u = n1 * n2 / 2.0;
+    let variance_u = n1 * n2 / 12.0 * (n + 1.0 - tie_correction / (n * (n - 1.0)));
+
+    if variance_u == 0.0 {
+        return 1.0;
+    }
+
+    let z = ((u - mean_u).abs() - 0.5).max(0.0) / variance_u.sqrt();
+    erfc(z / std::f64::consts::SQRT_2)
+}
+
+/// The complementary error function, with a relative error below 1.2e-7
+/// (Numerical Recipes, `erfcc`).
+fn erfc(x: f64) -> f64 {
+    let z = x.abs();
+    let t = 1.0 / (1.0 + 0.5 * z);
+    let r = t
+        * (-z * z - 1.26551223
+            + t * (1.00002368
+                + t * (0.37409196
+                    + t * (0.09678418
+                        + t * (-0.18628806
+                            + t * (0.27886807
+                                + t * (-1.13520398
+                                    + t * (1.48851587 + t * (-0.82215223 + t * 0.17087277)))))))))
+            .exp();
+    if x >= 0.0 {
+        r
+    } else {
+        2.0 - r
+    }
+}
+
+/// The natural logarithm of the gamma function, via the Lanczos
+/// approximation.
+fn ln_gamma(x: f64) -> f64 {
+    const COEFFICIENTS: [f64; 6] = [
+        76.18009172947146,
+        -86.50532032941677,
+        24.01409824083091,
+        -1.231739572450155,
+        0.1208650973866179e-2,
+        -0.5395239384953e-5,
+    ];
+
+    let tmp = x + 5.5 - (x + 0.5) * (x + 5.5).ln();
+    let series: f64 = 1.000000000190015
+        + COEFFICIENTS
+            .iter()
+            .enumerate()
+            .map(|(i, c)| c / (x + 1.0 + i as f64))
+            .sum::<f64>();
+    -tmp + (2.5066282746310005 * series / x).ln()
+}
+
+/// The regularized incomplete beta function `I_x(a, b)`, evaluated with a
+/// continued fraction (Numerical Recipes, `betai`).
+fn regularized_incomplete_beta(x: f64, a: f64, b: f64) -> f64 {
+    if x <= 0.0 {
+        return 0.0;
+    }
+    if x >= 1.0 {
+        return 1.0;
+    }
+
+    let front =
+        (ln_gamma(a + b) - ln_gamma(a) - ln_gamma(b) + a * x.ln() + b * (1.0 - x).ln()).exp();
+
+    // The continued fraction converges quickly for x below this point, the
+    // symmetry `I_x(a, b) = 1 - I_(1-x)(b, a)` covers the rest.
+    if x < (a + 1.0) / (a + b + 2.0) {
+        front * beta_continued_fraction(x, a, b) / a
+    } else {
+        1.0 - front * beta_continued_fraction(1.0 - x, b, a) / b
+    }
+}
+
+fn beta_continued_fraction(x: f64, a: f64, b: f64) -> f64 {
+    const TINY: f64 = 1e-300;
+
+    let mut c = 1.0;
+    let mut d = 1.0 - (a + b) * x / (a + 1.0);
+    if d.abs() < TINY {
+        d = TINY;
+    }
+    d = 1.0 / d;
+    let mut h = d;
+
+    for m in 1..300 {
+        let m = m as f64;
+
+        for numerator in [
+            m * (b - m) * x / ((a + 2.0 * m - 1.0) * (a + 2.0 * m)),
+            -(a + m) * (a + b + m) * x / ((a + 2.0 * m) * (a + 2.0 * m + 1.0)),
+        ] {
+            d = 1.0 + numerator * d;
+            if d.abs() < TINY {
+                d = TINY;
+            }
+            c = 1.0 + numerator / c;
+            if c.abs() < TINY {
+                c = TINY;
+            }
+            d = 1.0 / d;
+            h *= d * c;
+        }
+
+        if (d * c - 1.0).abs() < 1e-12 {
+            break;
+        }
+    }
+
+    h
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-3,
+            "{} is not close to {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn welch() {
+        let a = [
+            27.5, 21.0, 19.0, 23.6, 17.0, 17.9, 16.9, 20.1, 21.9, 22.6, 23.1, 19.6, 19.0, 21.7,
+            21.4,
+        ];
+        let b = [
+            27.1, 22.0, 20.8, 23.4, 23.4, 23.5, 25.8, 22.0, 24.8, 20.2, 21.9, 22.1, 22.9, 20.5,
+            24.4,
+        ];
+        assert_close(welch_t_test(&a, &b), 0.02138);
+        assert_close(welch_t_test(&b, &a), 0.02138);
+
+        assert_eq!(welch_t_test(&[1.0, 1.0], &[1.0, 1.0]), 1.0);
+        assert_eq!(welch_t_test(&[1.0, 1.0], &[2.0, 2.0]), 0.0);
+    }
+
+    #[test]
+    fn mann_whitney() {
+        let a = [1.0, 2.0, 3.0, 4.0, 5.0, 6.0];
+        let b = [7.0, 8.0, 9.0, 10.0, 11.0, 12.0];
+        // U = 0, z = (18 - 0.5) / sqrt(39)
+        assert_close(mann_whitney_u_test(&a, &b), 0.005075);
+
+        let c = [1.0, 3.0, 5.0, 7.0, 9.0, 11.0];
+        let d = [2.0, 4.0, 6.0, 8.0, 10.0, 12.0];
+        assert!(mann_whitney_u_test(&c, &d) > 0.5);
+
+        assert_eq!(mann_whitney_u_test(&[1.0, 1.0], &[1.0, 1.0]), 1.0);
+    }
+
+    #[test]
+    fn tests() {
+        assert_eq!("welch".parse(), Ok(Test::Welch));
+        assert_eq!("mann-whitney".parse(), Ok(Test::MannWhitney));
+        assert!("student".parse::<Test>().is_err());
+    }
+}
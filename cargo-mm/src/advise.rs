@@ -0,0 +1,398 @@
+//! `cargo mm advise` looks for a few well-known reasons for slow builds in
+//! a rustc profile and suggests what to do about them, for people who don't
+//! know what to look for in a profile:
+//!
+//! - a single codegen unit that takes most of the codegen time, which LLVM
+//!   can't spread across threads,
+//! - one generic function that makes up a large part of all instances that
+//!   are codegened (i.e. of the `symbol_name` queries),
+//! - an incremental rebuild that could reuse only few query results,
+//! - a long stretch at the end of the build during which only one thread
+//!   was busy.
+//!
+//! The checks are heuristics with fixed thresholds, so a profile without
+//! any of these problems can still be slow, and a reported problem may be
+//! inherent to the crate.
+
+use analyzeme::{ProfilingData, TimelineEvent, Timestamp};
+use measureme::rustc::{INCREMENTAL_LOAD_RESULT_EVENT_KIND, INCREMENTAL_RESULT_HASHING_EVENT_KIND};
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+/// The share of the codegen time above which a codegen unit is reported.
+const DOMINANT_CODEGEN_UNIT_SHARE: f64 = 0.5;
+/// The share of all codegened instances above which a generic function is
+/// reported, if it has at least `MIN_INSTANCES` of them.
+const DOMINANT_GENERIC_SHARE: f64 = 0.2;
+const MIN_INSTANCES: usize = 50;
+/// The share of query results an incremental rebuild has to reuse at least,
+/// once it has seen `MIN_INCREMENTAL_RESULTS` of them.
+const MIN_INCREMENTAL_HIT_RATE: f64 = 0.25;
+const MIN_INCREMENTAL_RESULTS: u64 = 100;
+/// The share of the build a single-threaded tail has to take to be reported.
+const SINGLE_THREADED_TAIL_SHARE: f64 = 0.2;
+
+/// A problem found in a profile, with what to do about it.
+#[derive(Debug, PartialEq)]
+pub struct Advice {
+    pub finding: String,
+    pub suggestion: String,
+}
+
+pub fn print_advice(path_stem: &Path) -> Result<(), Box<dyn Error>> {
+    let data = ProfilingData::new(path_stem)?;
+    let advice = advise(&data);
+
+    if advice.is_empty() {
+        println!("Found none of the problems `cargo mm advise` knows about.");
+        return Ok(());
+    }
+
+    for (index, advice) in advice.iter().enumerate() {
+        if index > 0 {
+            println!();
+        }
+        println!("* {}", advice.finding);
+        println!("  {}", advice.suggestion);
+    }
+
+    Ok(())
+}
+
+pub fn advise(data: &ProfilingData) -> Vec<Advice> {
+    let checks: [fn(&ProfilingData) -> Option<Advice>; 4] = [
+        dominant_codegen_unit,
+        dominant_generic,
+        low_incremental_hit_rate,
+        single_threaded_tail,
+    ];
+    checks.iter().filter_map(|check| check(data)).collect()
+}
+
+fn dominant_codegen_unit(data: &ProfilingData) -> Option<Advice> {
+    // The time of the outermost events about a codegen unit, i.e. with its
+    // name as argument, per codegen unit.
+    fn walk(event: &TimelineEvent<'_>, times: &mut HashMap<String, Duration>) {
+        let full = event.event.to_event();
+        match full
+            .additional_data
+            .iter()
+            .find(|arg| arg.contains("-cgu."))
+        {
+            Some(cgu) => *times.entry(cgu.to_string()).or_default() += duration(&full.timestamp),
+            None => {
+                for child in &event.children {
+                    walk(child, times);
+                }
+            }
+        }
+    }
+
+    let mut times = HashMap::new();
+    for thread in data.per_thread_timelines() {
+        for event in &thread.events {
+            walk(event, &mut times);
+        }
+    }
+
+    let total: Duration = times.values().sum();
+    let (cgu, &time) = times.iter().max_by_key(|&(cgu, time)| (time, cgu))?;
+    let share = share(time, total);
+    if times.len() < 2 || share < DOMINANT_CODEGEN_UNIT_SHARE {
+        return None;
+    }
+
+    Some(Advice {
+        finding: format!(
+            "Codegen unit `{}` takes {:.0}% of the codegen time ({:.2?} of {:.2?}), so LLVM \
+             can't spread the work across threads.",
+            cgu,
+            share * 100.0,
+            time,
+            total
+        ),
+        suggestion: "Split up the largest modules of the crate, or check the unit for a huge \
+                     function, e.g. one generated by a macro."
+            .to_string(),
+    })
+}
+
+fn dominant_generic(data: &ProfilingData) -> Option<Advice> {
+    let mut instances = HashSet::new();
+    for event in data.iter() {
+        let event = event.to_event();
+        if event.label == "symbol_name" {
+            if let Some(instance) = event.additional_data.first() {
+                instances.insert(instance.to_string());
+            }
+        }
+    }
+
+    let mut per_generic = HashMap::<String, usize>::new();
+    for instance in &instances {
+        *per_generic.entry(generic_name(instance)).or_default() += 1;
+    }
+    // Drop glue is generic over every type that is dropped, there's nothing
+    // to be done about it.
+    per_generic.retain(|generic, _| generic != "core::ptr::drop_in_place");
+
+    let (generic, &count) = per_generic
+        .iter()
+        .max_by_key(|&(generic, count)| (count, generic))?;
+    let share = count as f64 / instances.len() as f64;
+    if count < MIN_INSTANCES || share < DOMINANT_GENERIC_SHARE {
+        return None;
+    }
+
+    Some(Advice {
+        finding: format!(
+            "`{}` is instantiated {} times, {:.0}% of all {} instances that are codegened.",
+            generic,
+            count,
+            share * 100.0,
+            instances.len()
+        ),
+        suggestion: "Move the parts of its body that don't depend on the generic parameters \
+                     into a non-generic inner function, or take `&dyn Trait` instead of a \
+                     generic parameter."
+            .to_string(),
+    })
+}
+
+/// Strips the generic arguments from the name of an instance, e.g.
+/// `<alloc::vec::Vec<u8> as core::ops::Drop>::drop` is an instance of
+/// `<alloc::vec::Vec as core::ops::Drop>::drop`. Brackets that start a
+/// qualified path are kept.
+fn generic_name(instance: &str) -> String {
+    let mut name = String::with_capacity(instance.len());
+    // For every open bracket, whether it is kept.
+    let mut brackets = Vec::new();
+
+    for c in instance.chars() {
+        let skipping = brackets.contains(&false);
+        match c {
+            '<' => {
+                let keep = !skipping && !name.ends_with(|c: char| c.is_alphanumeric() || c == '_');
+                let keep = keep && !name.ends_with("::");
+                brackets.push(keep);
+                if keep {
+                    name.push(c);
+                }
+            }
+            // The guard pops the bracket, whether or not it is kept.
+            '>' if brackets.pop() == Some(true) => name.push(c),
+            '>' => {}
+            _ if !skipping => name.push(c),
+            _ => {}
+        }
+    }
+
+    // Turbofish brackets leave their `::` behind.
+    let len = name.trim_end_matches("::").len();
+    name.truncate(len);
+    name.replace("::::", "::")
+}
+
+fn low_incremental_hit_rate(data: &ProfilingData) -> Option<Advice> {
+    let mut loaded = 0u64;
+    let mut hashed = 0u64;
+    for event in data.iter() {
+        let event = event.to_event();
+        if event.event_kind == INCREMENTAL_LOAD_RESULT_EVENT_KIND {
+            loaded += 1;
+        } else if event.event_kind == INCREMENTAL_RESULT_HASHING_EVENT_KIND {
+            hashed += 1;
+        }
+    }
+
+    // Builds from scratch, which can't reuse anything, load no results.
+    let total = loaded + hashed;
+    let hit_rate = loaded as f64 / total as f64;
+    if loaded == 0 || total < MIN_INCREMENTAL_RESULTS || hit_rate >= MIN_INCREMENTAL_HIT_RATE {
+        return None;
+    }
+
+    Some(Advice {
+        finding: format!(
+            "This incremental rebuild loaded only {:.0}% of its query results from the \
+             incremental cache ({} loaded, {} recomputed).",
+            hit_rate * 100.0,
+            loaded,
+            hashed
+        ),
+        suggestion: "Check for something that invalidates the incremental state on every \
+                     build, e.g. changing `RUSTFLAGS`, a build script that reruns every time, or \
+                     `env!` and `include_str!` of generated files."
+            .to_string(),
+    })
+}
+
+fn single_threaded_tail(data: &ProfilingData) -> Option<Advice> {
+    // Threads count as busy while they are in an event without nested
+    // events, so that e.g. a thread waiting for others in `codegen_crate`
+    // doesn't count.
+    fn collect_leaves<'a>(event: &'a TimelineEvent<'a>, leaves: &mut Vec<&'a TimelineEvent<'a>>) {
+        if event.children.is_empty() {
+            leaves.push(event);
+        }
+        for child in &event.children {
+            collect_leaves(child, leaves);
+        }
+    }
+
+    let timelines = data.per_thread_timelines();
+    let mut threads: Vec<(SystemTime, u32, Vec<&TimelineEvent<'_>>)> = timelines
+        .iter()
+        .filter_map(|thread| {
+            let mut thread_leaves = Vec::new();
+            for event in &thread.events {
+                collect_leaves(event, &mut thread_leaves);
+            }
+            let last_end = thread_leaves
+                .iter()
+                .map(|e| e.event.timestamp.end())
+                .max()?;
+            Some((last_end, thread.thread_id, thread_leaves))
+        })
+        .collect();
+    threads.sort_by_key(|&(last_end, thread_id, _)| (last_end, thread_id));
+
+    let (end, thread_id, tail_leaves) = threads.pop()?;
+    let (cut, _, _) = threads.last()?;
+    let start = data
+        .iter()
+        .map(|e| e.timestamp.start())
+        .min()
+        .unwrap_or(end);
+
+    let tail = end.duration_since(*cut).unwrap_or_default();
+    let share = share(tail, end.duration_since(start).unwrap_or_default());
+    if share < SINGLE_THREADED_TAIL_SHARE {
+        return None;
+    }
+
+    // What the thread was busy with after the others had finished.
+    let mut labels = HashMap::<String, Duration>::new();
+    for leaf in tail_leaves {
+        let (leaf_start, leaf_end) = (leaf.event.timestamp.start(), leaf.event.timestamp.end());
+        let overlap = leaf_end
+            .duration_since(leaf_start.max(*cut))
+            .unwrap_or_default();
+        if overlap > Duration::default() {
+            *labels
+                .entry(leaf.event.to_event().label.into_owned())
+                .or_default() += overlap;
+        }
+    }
+    let busy_with = match labels.iter().max_by_key(|&(label, time)| (time, label)) {
+        Some((label, _)) => format!(", mostly with `{}`", label),
+        None => String::new(),
+    };
+
+    Some(Advice {
+        finding: format!(
+            "During the last {:.2?} ({:.0}%) of the build, only thread {} was busy{}.",
+            tail,
+            share * 100.0,
+            thread_id,
+            busy_with
+        ),
+        suggestion: "Work that runs at the end, like LTO or optimizing the last codegen unit, \
+                     doesn't use the other threads. Try thin instead of fat LTO, more codegen \
+                     units, or splitting up the crate so that this work starts earlier."
+            .to_string(),
+    })
+}
+
+fn duration(timestamp: &Timestamp) -> Duration {
+    match *timestamp {
+        Timestamp::Interval { start, end } => end.duration_since(start).unwrap_or_default(),
+        Timestamp::Instant(_) => Duration::default(),
+    }
+}
+
+fn share(part: Duration, total: Duration) -> f64 {
+    if total == Duration::default() {
+        0.0
+    } else {
+        part.as_secs_f64() / total.as_secs_f64()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use analyzeme::ProfilingDataBuilder;
+
+    #[test]
+    fn generic_names() {
+        assert_eq!(
+            generic_name("<alloc::vec::Vec<u8> as core::ops::Drop>::drop"),
+            "<alloc::vec::Vec as core::ops::Drop>::drop"
+        );
+        assert_eq!(
+            generic_name("core::ptr::drop_in_place::<alloc::string::String>"),
+            "core::ptr::drop_in_place"
+        );
+        assert_eq!(
+            generic_name("serde_json::de::from_str::<my_crate::Config<'_>>"),
+            "serde_json::de::from_str"
+        );
+        assert_eq!(
+            generic_name("<my_crate::Parser<R>>::parse::<Vec<u8>>"),
+            "<my_crate::Parser>::parse"
+        );
+        assert_eq!(generic_name("my_crate::main"), "my_crate::main");
+    }
+
+    #[test]
+    fn healthy_profile() {
+        let mut b = ProfilingDataBuilder::new();
+        b.interval("Query", "typeck", 0, 0, 100, |_| {});
+        b.interval("Query", "typeck", 1, 0, 100, |_| {});
+        assert_eq!(advise(&b.into_profiling_data()), vec![]);
+    }
+
+    #[test]
+    fn pathologies() {
+        let mut b = ProfilingDataBuilder::new();
+        let mut t = 0;
+        for i in 0..100 {
+            let instance = format!("my_crate::process::<Item{}>", i);
+            let label = format!("symbol_name\x1e{}", instance);
+            b.interval("Query", &label, 0, t, t + 1, |_| {});
+            t += 1;
+        }
+        for _ in 0..10 {
+            b.interval("IncrementalLoadResult", "type_of", 0, t, t + 1, |_| {});
+            t += 1;
+        }
+        for _ in 0..90 {
+            b.interval("IncrementalResultHashing", "type_of", 0, t, t + 1, |_| {});
+            t += 1;
+        }
+        let codegen = |cgu| format!("codegen_module\x1ea.1-cgu.{}", cgu);
+        b.interval("GenericActivity", &codegen(0), 1, t, t + 10, |_| {});
+        b.interval("GenericActivity", &codegen(1), 0, t, t + 800, |b| {
+            b.interval("LLVM Pass", "optimize", 0, t + 10, t + 790, |_| {});
+        });
+
+        let advice = advise(&b.into_profiling_data());
+        let findings: Vec<_> = advice.iter().map(|a| &a.finding[..]).collect();
+        assert_eq!(
+            findings,
+            vec![
+                "Codegen unit `a.1-cgu.1` takes 99% of the codegen time (800.00ns of 810.00ns), \
+                 so LLVM can't spread the work across threads.",
+                "`my_crate::process` is instantiated 100 times, 100% of all 100 instances that \
+                 are codegened.",
+                "This incremental rebuild loaded only 10% of its query results from the \
+                 incremental cache (10 loaded, 90 recomputed).",
+                "During the last 780.00ns (79%) of the build, only thread 0 was busy, mostly \
+                 with `optimize`.",
+            ]
+        );
+    }
+}
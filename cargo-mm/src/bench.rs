@@ -0,0 +1,474 @@
+//! `cargo mm bench` turns the invocations of a single label across a corpus
+//! of profiles into a benchmark in the format `criterion` writes to
+//! `target/criterion`, so that dashboards built for `criterion` can track
+//! the cost of a specific query. Like `criterion`, it keeps the previous
+//! run of the benchmark as `base` and reports the change against it.
+
+use crate::abtest::welch_t_test;
+use analyzeme::{ProfilingData, TimelineEvent};
+use serde_json::{json, Value};
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// `criterion`'s defaults for the confidence level of its intervals, the
+/// significance level of its comparisons and the threshold below which
+/// changes count as noise.
+const CONFIDENCE_LEVEL: f64 = 0.95;
+const SIGNIFICANCE_LEVEL: f64 = 0.05;
+const NOISE_THRESHOLD: f64 = 0.01;
+
+/// The quantile of the standard normal distribution for `CONFIDENCE_LEVEL`.
+const Z: f64 = 1.959964;
+
+/// A point estimate with its confidence interval, in `criterion`'s terms.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Estimate {
+    point: f64,
+    lower: f64,
+    upper: f64,
+    standard_error: f64,
+}
+
+impl Estimate {
+    fn to_json(self) -> Value {
+        json!({
+            "confidence_interval": {
+                "confidence_level": CONFIDENCE_LEVEL,
+                "lower_bound": self.lower,
+                "upper_bound": self.upper,
+            },
+            "point_estimate": self.point,
+            "standard_error": self.standard_error,
+        })
+    }
+
+    fn from_json(value: &Value) -> Option<Estimate> {
+        let interval = &value["confidence_interval"];
+        Some(Estimate {
+            point: value["point_estimate"].as_f64()?,
+            lower: interval["lower_bound"].as_f64()?,
+            upper: interval["upper_bound"].as_f64()?,
+            standard_error: value["standard_error"].as_f64()?,
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Estimates {
+    mean: Estimate,
+    median: Estimate,
+    std_dev: Estimate,
+    median_abs_dev: Estimate,
+}
+
+impl Estimates {
+    /// The estimates for the given durations, in nanoseconds. The intervals
+    /// are normal approximations for the mean and the standard deviation
+    /// and order statistics for the median and the median absolute
+    /// deviation, where `criterion` bootstraps, which takes far too long
+    /// for the number of invocations a corpus of profiles has.
+    fn new(samples: &[f64]) -> Estimates {
+        let n = samples.len() as f64;
+        let mean = samples.iter().sum::<f64>() / n;
+        let std_dev = if samples.len() > 1 {
+            (samples.iter().map(|x| (x - mean) * (x - mean)).sum::<f64>() / (n - 1.0)).sqrt()
+        } else {
+            0.0
+        };
+
+        let mean_error = std_dev / n.sqrt();
+        let std_dev_error = if samples.len() > 1 {
+            std_dev / (2.0 * (n - 1.0)).sqrt()
+        } else {
+            0.0
+        };
+
+        let mut sorted = samples.to_vec();
+        sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let median = median_estimate(&sorted);
+
+        let mut deviations: Vec<f64> = sorted.iter().map(|x| (x - median.point).abs()).collect();
+        deviations.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        // Scaled like `criterion` does, which makes it estimate the standard
+        // deviation for normally distributed samples.
+        let mad = median_estimate(&deviations);
+        let scale = |x: f64| x * 1.4826;
+
+        Estimates {
+            mean: Estimate {
+                point: mean,
+                lower: mean - Z * mean_error,
+                upper: mean + Z * mean_error,
+                standard_error: mean_error,
+            },
+            median,
+            std_dev: Estimate {
+                point: std_dev,
+                lower: (std_dev - Z * std_dev_error).max(0.0),
+                upper: std_dev + Z * std_dev_error,
+                standard_error: std_dev_error,
+            },
+            median_abs_dev: Estimate {
+                point: scale(mad.point),
+                lower: scale(mad.lower),
+                upper: scale(mad.upper),
+                standard_error: scale(mad.standard_error),
+            },
+        }
+    }
+
+    fn to_json(self) -> Value {
+        json!({
+            "mean": self.mean.to_json(),
+            "median": self.median.to_json(),
+            "median_abs_dev": self.median_abs_dev.to_json(),
+            "slope": null,
+            "std_dev": self.std_dev.to_json(),
+        })
+    }
+
+    fn from_json(value: &Value) -> Option<Estimates> {
+        Some(Estimates {
+            mean: Estimate::from_json(&value["mean"])?,
+            median: Estimate::from_json(&value["median"])?,
+            std_dev: Estimate::from_json(&value["std_dev"])?,
+            median_abs_dev: Estimate::from_json(&value["median_abs_dev"])?,
+        })
+    }
+}
+
+/// The median of sorted samples, with the interval between the order
+/// statistics whose ranks are `Z` standard deviations of a binomial
+/// distribution away from the middle.
+fn median_estimate(sorted: &[f64]) -> Estimate {
+    let n = sorted.len();
+    let median = if n % 2 == 1 {
+        sorted[n / 2]
+    } else {
+        (sorted[n / 2 - 1] + sorted[n / 2]) / 2.0
+    };
+
+    let spread = Z * (n as f64).sqrt() / 2.0;
+    let rank =
+        |offset: f64| (((n as f64 + 1.0) / 2.0 + offset).round().max(1.0) as usize).min(n) - 1;
+    let (lower, upper) = (sorted[rank(-spread)], sorted[rank(spread)]);
+
+    Estimate {
+        point: median,
+        lower: lower.min(median),
+        upper: upper.max(median),
+        standard_error: (upper - lower) / (2.0 * Z),
+    }
+}
+
+/// The relative change from `base` to `new`, with an interval via the delta
+/// method.
+fn relative_change(base: Estimate, new: Estimate) -> Estimate {
+    if base.point == 0.0 {
+        return Estimate {
+            point: 0.0,
+            lower: 0.0,
+            upper: 0.0,
+            standard_error: 0.0,
+        };
+    }
+
+    let ratio = new.point / base.point;
+    let relative_error = |e: Estimate| {
+        if e.point == 0.0 {
+            0.0
+        } else {
+            e.standard_error / e.point
+        }
+    };
+    let standard_error = ratio * relative_error(base).hypot(relative_error(new));
+
+    Estimate {
+        point: ratio - 1.0,
+        lower: ratio - 1.0 - Z * standard_error,
+        upper: ratio - 1.0 + Z * standard_error,
+        standard_error,
+    }
+}
+
+/// Collects the durations of the events labeled `label`, or their self
+/// times if `self_time` is set, in nanoseconds, from the given profiles. A
+/// directory stands for all profiles in it.
+fn collect_samples(
+    profiles: &[PathBuf],
+    label: &str,
+    self_time: bool,
+) -> Result<Vec<f64>, Box<dyn Error>> {
+    fn visit(event: &TimelineEvent<'_>, label: &str, samples: &mut Vec<f64>) {
+        if let Some(duration) = event.event.duration() {
+            if event.event.to_event().label == label {
+                let children: u128 = event
+                    .children
+                    .iter()
+                    .filter_map(|child| child.event.duration())
+                    .map(|d| d.as_nanos())
+                    .sum();
+                samples.push(duration.as_nanos().saturating_sub(children) as f64);
+            }
+        }
+
+        for child in &event.children {
+            visit(child, label, samples);
+        }
+    }
+
+    let mut path_stems = Vec::new();
+    for path in profiles {
+        if path.is_dir() {
+            let mut in_dir: Vec<PathBuf> = fs::read_dir(path)
+                .map_err(|e| format!("could not read `{}`: {}", path.display(), e))?
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| path.extension().is_some_and(|e| e == "events"))
+                .map(|path| path.with_extension(""))
+                .collect();
+            in_dir.sort();
+            path_stems.extend(in_dir);
+        } else {
+            path_stems.push(path.clone());
+        }
+    }
+
+    let mut samples = Vec::new();
+    for data in ProfilingData::load_all(&path_stems)? {
+        if self_time {
+            for timeline in data.per_thread_timelines() {
+                for event in &timeline.events {
+                    visit(event, label, &mut samples);
+                }
+            }
+        } else {
+            samples.extend(
+                data.iter()
+                    .filter_map(|event| Some((event.duration()?, event)))
+                    .filter(|(_, event)| event.to_event().label == label)
+                    .map(|(duration, _)| duration.as_nanos() as f64),
+            );
+        }
+    }
+
+    if samples.is_empty() {
+        Err(format!(
+            "none of the {} profiles contains an interval labeled `{}`",
+            path_stems.len(),
+            label
+        ))?;
+    }
+
+    Ok(samples)
+}
+
+/// Replaces the characters `criterion` doesn't allow in directory names.
+fn directory_name(id: &str) -> String {
+    id.chars()
+        .map(|c| match c {
+            '?' | '"' | '/' | '\\' | '*' | '<' | '>' | ':' | '|' | '^' => '_',
+            c => c,
+        })
+        .collect()
+}
+
+fn write_json(path: &Path, value: &Value) -> Result<(), Box<dyn Error>> {
+    fs::write(path, serde_json::to_string(value)?)
+        .map_err(|e| format!("could not write `{}`: {}", path.display(), e).into())
+}
+
+fn read_json(path: &Path) -> Option<Value> {
+    serde_json::from_str(&fs::read_to_string(path).ok()?).ok()
+}
+
+/// Formats nanoseconds like `criterion` does, with four significant digits.
+fn format_time(ns: f64) -> String {
+    let (value, unit) = if ns < 1.0 {
+        (ns * 1e3, "ps")
+    } else if ns < 1e3 {
+        (ns, "ns")
+    } else if ns < 1e6 {
+        (ns / 1e3, "µs")
+    } else if ns < 1e9 {
+        (ns / 1e6, "ms")
+    } else {
+        (ns / 1e9, "s")
+    };
+
+    if value >= 100.0 {
+        format!("{:.1} {}", value, unit)
+    } else if value >= 10.0 {
+        format!("{:.2} {}", value, unit)
+    } else {
+        format!("{:.3} {}", value, unit)
+    }
+}
+
+fn format_change(change: f64) -> String {
+    format!("{:+.4}%", change * 100.0)
+}
+
+/// Writes the benchmark `id` (the label by default) for the invocations of
+/// `label` in `profiles` to `criterion_dir` and prints a report like the
+/// one of `criterion`.
+pub fn run_benchmark(
+    profiles: &[PathBuf],
+    label: &str,
+    id: Option<&str>,
+    self_time: bool,
+    criterion_dir: &Path,
+) -> Result<(), Box<dyn Error>> {
+    let id = id.unwrap_or(label);
+    let samples = collect_samples(profiles, label, self_time)?;
+    let estimates = Estimates::new(&samples);
+
+    let benchmark_dir = criterion_dir.join(directory_name(id));
+    let (new_dir, base_dir) = (benchmark_dir.join("new"), benchmark_dir.join("base"));
+
+    // The previous run becomes the base of this one.
+    if new_dir.exists() {
+        if base_dir.exists() {
+            fs::remove_dir_all(&base_dir)?;
+        }
+        fs::rename(&new_dir, &base_dir)?;
+    }
+    fs::create_dir_all(&new_dir)
+        .map_err(|e| format!("could not create `{}`: {}", new_dir.display(), e))?;
+
+    write_json(
+        &new_dir.join("benchmark.json"),
+        &json!({
+            "group_id": id,
+            "function_id": null,
+            "value_str": null,
+            "throughput": null,
+            "full_id": id,
+            "directory_name": directory_name(id),
+            "title": id,
+        }),
+    )?;
+    write_json(&new_dir.join("estimates.json"), &estimates.to_json())?;
+    // Every invocation is a sample of a single iteration.
+    write_json(
+        &new_dir.join("sample.json"),
+        &json!({
+            "sampling_mode": "Flat",
+            "iters": vec![1.0; samples.len()],
+            "times": samples,
+        }),
+    )?;
+
+    println!(
+        "{:<24}time:   [{} {} {}]",
+        id,
+        format_time(estimates.mean.lower),
+        format_time(estimates.mean.point),
+        format_time(estimates.mean.upper)
+    );
+
+    let base = match (
+        read_json(&base_dir.join("estimates.json")).and_then(|e| Estimates::from_json(&e)),
+        read_json(&base_dir.join("sample.json")),
+    ) {
+        (Some(estimates), Some(sample)) => {
+            let times: Option<Vec<f64>> = sample["times"]
+                .as_array()
+                .map(|times| times.iter().filter_map(Value::as_f64).collect());
+            times.map(|times| (estimates, times))
+        }
+        _ => None,
+    };
+
+    let (base_estimates, base_samples) = match base {
+        Some(base) => base,
+        None => return Ok(()),
+    };
+
+    let mean_change = relative_change(base_estimates.mean, estimates.mean);
+    let median_change = relative_change(base_estimates.median, estimates.median);
+    let p_value = if base_samples.len() > 1 && samples.len() > 1 {
+        welch_t_test(&base_samples, &samples)
+    } else {
+        1.0
+    };
+
+    let change_dir = benchmark_dir.join("change");
+    fs::create_dir_all(&change_dir)?;
+    write_json(
+        &change_dir.join("estimates.json"),
+        &json!({
+            "mean": mean_change.to_json(),
+            "median": median_change.to_json(),
+        }),
+    )?;
+
+    let significant = p_value < SIGNIFICANCE_LEVEL;
+    println!(
+        "{:<24}change: [{} {} {}] (p = {:.2} {} {:.2})",
+        "",
+        format_change(mean_change.lower),
+        format_change(mean_change.point),
+        format_change(mean_change.upper),
+        p_value,
+        if significant { "<" } else { ">" },
+        SIGNIFICANCE_LEVEL
+    );
+
+    let verdict = if !significant {
+        "No change in performance detected."
+    } else if mean_change.lower > NOISE_THRESHOLD {
+        "Performance has regressed."
+    } else if mean_change.upper < -NOISE_THRESHOLD {
+        "Performance has improved."
+    } else {
+        "Change within noise threshold."
+    };
+    println!("{:<24}{}", "", verdict);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn estimates() {
+        let samples: Vec<f64> = (1..=101).map(|x| x as f64).collect();
+        let estimates = Estimates::new(&samples);
+
+        assert_eq!(estimates.mean.point, 51.0);
+        assert!(estimates.mean.lower < 51.0 && estimates.mean.upper > 51.0);
+        assert_eq!(estimates.median.point, 51.0);
+        assert_eq!(
+            (estimates.median.lower, estimates.median.upper),
+            (41.0, 61.0)
+        );
+        // The median absolute deviation of 1..=101 is 25.
+        assert!((estimates.median_abs_dev.point - 25.0 * 1.4826).abs() < 1e-9);
+        assert!((estimates.std_dev.point - 29.3002).abs() < 1e-3);
+
+        let single = Estimates::new(&[5.0]);
+        assert_eq!(single.median.point, 5.0);
+        assert_eq!(single.std_dev.point, 0.0);
+    }
+
+    #[test]
+    fn change() {
+        let base = Estimates::new(&[100.0, 102.0, 98.0, 101.0, 99.0]).mean;
+        let new = Estimates::new(&[110.0, 112.0, 108.0, 111.0, 109.0]).mean;
+        let change = relative_change(base, new);
+        assert!((change.point - 0.1).abs() < 1e-9);
+        assert!(change.lower < 0.1 && change.upper > 0.1 && change.lower > 0.0);
+    }
+
+    #[test]
+    fn formatting() {
+        assert_eq!(format_time(1234.0), "1.234 µs");
+        assert_eq!(format_time(12_345_678.0), "12.35 ms");
+        assert_eq!(format_time(0.5), "500.0 ps");
+        assert_eq!(format_change(-0.012345), "-1.2345%");
+        assert_eq!(directory_name("typeck/foo<'_>"), "typeck_foo_'__");
+    }
+}
@@ -0,0 +1,338 @@
+//! `cargo mm corpus` helps with directories that accumulate many profiles,
+//! e.g. the profiles of every CI run. `cargo mm corpus index <dir>` writes a
+//! catalog of the profiles in the directory and its subdirectories to
+//! `<dir>/mm-corpus.json`, with the fingerprint, metadata, duration and file
+//! sizes of each of them, and `cargo mm corpus query <dir>` lists the
+//! profiles of the catalog that match the given filters, without loading
+//! any of them. Indexing again only loads the profiles that are new or have
+//! changed since. `cargo mm corpus share <dir>` moves the strings that the
+//! profiles of each directory have in common into a dictionary in that
+//! directory, `mm-dictionary`, which makes the corpus much smaller.
+
+use analyzeme::{share_strings, ProfilingData};
+use measureme::ProfilerFiles;
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The name of the catalog in the indexed directory.
+pub const CATALOG_FILE: &str = "mm-corpus.json";
+
+/// The name of the dictionary of the strings shared by the profiles of a
+/// directory, see `share()`.
+pub const DICTIONARY: &str = "mm-dictionary";
+
+/// The filters of `cargo mm corpus query`. Profiles have to match all of
+/// the filters that are set.
+#[derive(Debug, Default)]
+pub struct Query {
+    pub crate_name: Option<String>,
+    pub cmd_contains: Option<String>,
+    pub slower_than: Option<Duration>,
+    pub faster_than: Option<Duration>,
+}
+
+impl Query {
+    fn matches(&self, entry: &Value) -> bool {
+        let duration = Duration::from_nanos(entry["duration_nanos"].as_u64().unwrap_or(0));
+
+        self.crate_name
+            .as_ref()
+            .is_none_or(|name| entry["crate"].as_str() == Some(name))
+            && self.cmd_contains.as_ref().is_none_or(|s| {
+                entry["cmd"]
+                    .as_str()
+                    .is_some_and(|cmd| cmd.contains(&s[..]))
+            })
+            && self.slower_than.is_none_or(|min| duration > min)
+            && self.faster_than.is_none_or(|max| duration < max)
+    }
+}
+
+/// Indexes the profiles in `dir`, reusing the entries of the existing
+/// catalog for profiles whose files haven't changed. Profiles that can't be
+/// loaded are left out with a warning.
+pub fn index(dir: &Path) -> Result<(), Box<dyn Error>> {
+    let catalog_path = dir.join(CATALOG_FILE);
+    let mut previous: HashMap<String, Value> = match fs::read(&catalog_path) {
+        Ok(catalog) => serde_json::from_slice::<Vec<Value>>(&catalog)
+            .map_err(|e| format!("could not read `{}`: {}", catalog_path.display(), e))?
+            .into_iter()
+            .filter_map(|entry| Some((entry["path"].as_str()?.to_string(), entry)))
+            .collect(),
+        Err(_) => HashMap::new(),
+    };
+
+    let mut path_stems = Vec::new();
+    find_profiles(dir, &mut path_stems)?;
+    path_stems.sort();
+
+    let mut entries = Vec::new();
+    let mut loaded = 0;
+    for path_stem in &path_stems {
+        let path = relative_path(dir, path_stem);
+        let (modified, size) = file_state(path_stem)?;
+
+        match previous.remove(&path) {
+            Some(entry)
+                if entry["modified"].as_u64() == Some(modified)
+                    && entry["size"].as_u64() == Some(size) =>
+            {
+                entries.push(entry);
+            }
+            _ => match ProfilingData::new(path_stem) {
+                Ok(data) => {
+                    entries.push(catalog_entry(&path, path_stem, modified, size, &data));
+                    loaded += 1;
+                }
+                Err(e) => eprintln!("Warning: skipping `{}`: {}", path_stem.display(), e),
+            },
+        }
+    }
+
+    fs::write(&catalog_path, serde_json::to_vec_pretty(&entries)?)?;
+    println!(
+        "Indexed {} profiles in `{}` ({} loaded, {} removed)",
+        entries.len(),
+        catalog_path.display(),
+        loaded,
+        previous.len()
+    );
+    Ok(())
+}
+
+/// Prints the profiles of the catalog of `dir` that match `query`, oldest
+/// first, as a table or as one JSON object per line.
+pub fn print_query(dir: &Path, query: &Query, json: bool) -> Result<(), Box<dyn Error>> {
+    let catalog_path = dir.join(CATALOG_FILE);
+    let catalog = fs::read(&catalog_path).map_err(|e| {
+        format!(
+            "could not read `{}`, run `cargo mm corpus index` first: {}",
+            catalog_path.display(),
+            e
+        )
+    })?;
+
+    let mut entries: Vec<Value> = serde_json::from_slice::<Vec<Value>>(&catalog)?
+        .into_iter()
+        .filter(|entry| query.matches(entry))
+        .collect();
+    entries.sort_by_key(|entry| entry["start_time"].as_u64());
+
+    if json {
+        for entry in &entries {
+            println!("{}", entry);
+        }
+        return Ok(());
+    }
+
+    println!(
+        "{:<20} {:>12} {:>12} {:>12}  {:<16}  Profile",
+        "Crate", "Duration", "Events", "Size", "Fingerprint"
+    );
+    for entry in &entries {
+        println!(
+            "{:<20} {:>12} {:>12} {:>12}  {:<16}  {}",
+            entry["crate"].as_str().unwrap_or("-"),
+            format!(
+                "{:.2?}",
+                Duration::from_nanos(entry["duration_nanos"].as_u64().unwrap_or(0))
+            ),
+            entry["events"].as_u64().unwrap_or(0),
+            entry["size"].as_u64().unwrap_or(0),
+            entry["fingerprint"].as_str().unwrap_or("-"),
+            entry["path"].as_str().unwrap_or("-"),
+        );
+    }
+    println!("{} profiles", entries.len());
+    Ok(())
+}
+
+/// Lets the profiles in each directory below `dir` share the strings that
+/// at least `min_profiles` of them contain, via a dictionary in that
+/// directory, see `analyzeme::share_strings()`. The profiles must not be
+/// moved away from their dictionary afterwards.
+pub fn share(dir: &Path, min_profiles: usize) -> Result<(), Box<dyn Error>> {
+    let mut path_stems = Vec::new();
+    find_profiles(dir, &mut path_stems)?;
+
+    let mut by_dir = BTreeMap::<PathBuf, Vec<PathBuf>>::new();
+    for path_stem in path_stems {
+        let parent = path_stem.parent().unwrap_or(dir).to_path_buf();
+        by_dir.entry(parent).or_default().push(path_stem);
+    }
+
+    for (profile_dir, mut path_stems) in by_dir {
+        path_stems.sort();
+        let stats = share_strings(&path_stems, &profile_dir.join(DICTIONARY), min_profiles)?;
+        println!(
+            "Shared {} strings between {} profiles in `{}`: {} bytes before, {} after ({:.0}% saved)",
+            stats.shared_strings,
+            stats.profiles,
+            profile_dir.display(),
+            stats.bytes_before,
+            stats.bytes_after,
+            100.0 - stats.bytes_after as f64 / stats.bytes_before.max(1) as f64 * 100.0
+        );
+    }
+    Ok(())
+}
+
+fn catalog_entry(
+    path: &str,
+    path_stem: &Path,
+    modified: u64,
+    size: u64,
+    data: &ProfilingData,
+) -> Value {
+    let metadata = &data.metadata;
+    // The profile lasts from the start of the profiler to its last event.
+    let end = data
+        .iter()
+        .map(|event| event.timestamp.end())
+        .max()
+        .unwrap_or(metadata.start_time);
+    let duration = end.duration_since(metadata.start_time).unwrap_or_default();
+
+    json!({
+        "path": path,
+        "modified": modified,
+        "size": size,
+        "fingerprint": format!("{:016x}", data.fingerprint()),
+        "crate": crate_name(data, path_stem),
+        "cmd": metadata.cmd,
+        "process_id": metadata.process_id,
+        "start_time": unix_nanos(metadata.start_time),
+        "duration_nanos": duration.as_nanos() as u64,
+        "events": data.num_events(),
+        "tool": metadata.tool.as_ref().map(|tool| format!("{} {}", tool.name, tool.version)),
+        "truncated": metadata.truncated,
+    })
+}
+
+/// The crate a profile has been recorded for, as `summarize crates` tells.
+fn crate_name(data: &ProfilingData, path_stem: &Path) -> String {
+    if let Some(name) = data.metadata.tool.as_ref().and_then(|t| t.invoking_crate()) {
+        return name.to_string();
+    }
+    crate_name_from(&data.metadata.cmd, path_stem)
+}
+
+fn crate_name_from(cmd: &str, path_stem: &Path) -> String {
+    if let Some(index) = cmd.find(" --crate-name ") {
+        let rest = &cmd[index + " --crate-name ".len()..];
+        return rest[..rest.find(' ').unwrap_or(rest.len())].to_string();
+    }
+
+    // rustc names profiles `<crate name>-<pid>`.
+    let stem = path_stem.file_name().unwrap_or_default().to_string_lossy();
+    match stem.rfind('-') {
+        Some(index) => stem[..index].to_string(),
+        None => stem.into_owned(),
+    }
+}
+
+/// Collects the path stems of the profiles in `dir` and its
+/// subdirectories.
+fn find_profiles(dir: &Path, path_stems: &mut Vec<PathBuf>) -> Result<(), Box<dyn Error>> {
+    for entry in
+        fs::read_dir(dir).map_err(|e| format!("could not read `{}`: {}", dir.display(), e))?
+    {
+        let path = entry?.path();
+        if path.is_dir() {
+            find_profiles(&path, path_stems)?;
+        } else if path.extension().is_some_and(|e| e == "events") {
+            path_stems.push(path.with_extension(""));
+        }
+    }
+    Ok(())
+}
+
+/// The last modification, in seconds since the epoch, and the total size
+/// of the files of a profile, which tell whether it needs to be indexed
+/// again.
+fn file_state(path_stem: &Path) -> Result<(u64, u64), Box<dyn Error>> {
+    let files = ProfilerFiles::new(path_stem);
+    let mut modified = UNIX_EPOCH;
+    let mut size = 0;
+    for file in [
+        &files.events_file,
+        &files.string_data_file,
+        &files.string_index_file,
+    ] {
+        if let Ok(metadata) = fs::metadata(file) {
+            modified = modified.max(metadata.modified()?);
+            size += metadata.len();
+        }
+    }
+    Ok((unix_nanos(modified) / 1_000_000_000, size))
+}
+
+fn unix_nanos(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos() as u64
+}
+
+/// The path of a profile in the catalog, relative to the indexed directory
+/// and with `/` as separator.
+fn relative_path(dir: &Path, path_stem: &Path) -> String {
+    path_stem
+        .strip_prefix(dir)
+        .unwrap_or(path_stem)
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn queries() {
+        let entry = json!({
+            "crate": "serde",
+            "cmd": "rustc --crate-name serde --edition=2018",
+            "duration_nanos": 2_000_000_000u64,
+        });
+
+        assert!(Query::default().matches(&entry));
+        let query = Query {
+            crate_name: Some("serde".to_string()),
+            slower_than: Some(Duration::from_secs(1)),
+            ..Query::default()
+        };
+        assert!(query.matches(&entry));
+
+        let query = Query {
+            crate_name: Some("syn".to_string()),
+            ..Query::default()
+        };
+        assert!(!query.matches(&entry));
+        let query = Query {
+            faster_than: Some(Duration::from_secs(2)),
+            ..Query::default()
+        };
+        assert!(!query.matches(&entry));
+        let query = Query {
+            cmd_contains: Some("--edition=2018".to_string()),
+            ..Query::default()
+        };
+        assert!(query.matches(&entry));
+    }
+
+    #[test]
+    fn crate_names() {
+        let stem = Path::new("target/mm/serde_json-1234");
+        assert_eq!(
+            crate_name_from("rustc --crate-name serde --edition=2018", stem),
+            "serde"
+        );
+        assert_eq!(crate_name_from("rustc src/lib.rs", stem), "serde_json");
+    }
+}
@@ -0,0 +1,78 @@
+//! `cargo mm follow` watches a profile while it is being recorded, e.g. of a
+//! long build in another terminal, and redraws the labels that took the most
+//! time so far, like `top`, until the profiler has finished the profile.
+//! When stdout isn't a terminal, only the final totals are printed. A
+//! profile whose recording crashed is never finished, so following it has to
+//! be interrupted.
+
+use analyzeme::ProfileFollower;
+use std::error::Error;
+use std::io::{self, IsTerminal, Write};
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+pub fn follow(path_stem: &Path, top: usize, interval: Duration) -> Result<(), Box<dyn Error>> {
+    let mut follower = ProfileFollower::new(path_stem)?;
+    let live = io::stdout().is_terminal();
+
+    loop {
+        follower.poll()?;
+        let finished = follower.is_finished();
+
+        if live || finished {
+            let mut out = io::stdout().lock();
+            if live {
+                // Clear the screen and move the cursor to the top left.
+                write!(out, "\x1b[2J\x1b[H")?;
+            }
+            print_totals(&mut out, path_stem, &follower, top)?;
+            out.flush()?;
+        }
+
+        if finished {
+            return Ok(());
+        }
+        thread::sleep(interval);
+    }
+}
+
+fn print_totals(
+    out: &mut impl Write,
+    path_stem: &Path,
+    follower: &ProfileFollower,
+    top: usize,
+) -> io::Result<()> {
+    let state = if follower.is_finished() {
+        "finished"
+    } else {
+        "recording"
+    };
+    writeln!(
+        out,
+        "Profile `{}` ({}): {:.2?}, {} events",
+        path_stem.display(),
+        state,
+        follower.recorded_time(),
+        follower.num_events()
+    )?;
+    writeln!(out)?;
+
+    writeln!(
+        out,
+        "  {:>12} {:>10} {:>12}  {:<20} Label",
+        "Total time", "Count", "Max time", "Event kind"
+    )?;
+    for label in follower.summary().labels.iter().take(top) {
+        writeln!(
+            out,
+            "  {:>12} {:>10} {:>12}  {:<20} {}",
+            format!("{:.2?}", label.total_time),
+            label.count,
+            format!("{:.2?}", label.max_time),
+            label.event_kind,
+            label.label
+        )?;
+    }
+    Ok(())
+}
@@ -0,0 +1,75 @@
+//! `cargo mm grep` finds the labels and arguments containing a substring.
+//! Decoding all strings of a large profile takes a while, so the search goes
+//! through an `analyzeme::SearchIndex`, which is built on the first search
+//! and stored next to the profile as `<path_stem>.search_index`. The index is
+//! rebuilt if it is older than the profile's events file.
+
+use analyzeme::{ProfilingData, SearchIndex, Timestamp};
+use measureme::ProfilerFiles;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+pub fn print_matches(path_stem: &Path, pattern: &str) -> Result<(), Box<dyn Error>> {
+    let data = ProfilingData::new(path_stem)?;
+    let index = load_or_build_index(path_stem, &data)?;
+
+    let matches = index.search(pattern);
+    if matches.is_empty() {
+        println!("No labels or arguments contain `{}`.", pattern);
+        return Ok(());
+    }
+
+    println!("{:>10}  {:>12}  String", "Events", "Total time");
+    for m in &matches {
+        let total: Duration = m
+            .events
+            .iter()
+            .map(|&index| match data.event(index as usize).timestamp {
+                Timestamp::Interval { start, end } => end.duration_since(start).unwrap_or_default(),
+                Timestamp::Instant(_) => Duration::default(),
+            })
+            .sum();
+
+        println!(
+            "{:>10}  {:>12}  {}",
+            m.events.len(),
+            format!("{:.2?}", total),
+            m.string
+        );
+    }
+
+    Ok(())
+}
+
+fn load_or_build_index(
+    path_stem: &Path,
+    data: &ProfilingData,
+) -> Result<SearchIndex, Box<dyn Error>> {
+    let index_file = path_stem.with_extension("search_index");
+    let events_modified = fs::metadata(ProfilerFiles::new(path_stem).events_file)
+        .and_then(|m| m.modified())
+        .ok();
+
+    let is_current = fs::metadata(&index_file)
+        .and_then(|m| m.modified())
+        .is_ok_and(|modified| Some(modified) >= events_modified);
+
+    if is_current {
+        let index = fs::read(&index_file)
+            .ok()
+            .and_then(|bytes| SearchIndex::from_bytes(&bytes).ok());
+
+        // Also rebuild indexes of other profiles, e.g. if a profile has been
+        // copied over an older one.
+        if let Some(index) = index.filter(|index| index.num_events() == data.num_events()) {
+            return Ok(index);
+        }
+    }
+
+    let index = SearchIndex::build(data);
+    // The index is only a cache, searching works without it.
+    let _ = fs::write(&index_file, index.to_bytes());
+    Ok(index)
+}
@@ -0,0 +1,45 @@
+//! `cargo mm label` expands the ids that `summarize` prints in place of the
+//! end of labels that are too long for its tables, see
+//! `analyzeme::truncate_label()`.
+
+use analyzeme::{label_id, LabelFormatter, ProfilingData, RustcLabelFormatter};
+use std::collections::HashSet;
+use std::error::Error;
+use std::path::Path;
+
+pub fn print_labels(path_stem: &Path, id: &str) -> Result<(), Box<dyn Error>> {
+    // Also accept the id as printed, e.g. `…#3fa2c81b`.
+    let id = id.trim_start_matches(['…', '#']).to_ascii_lowercase();
+    if id.is_empty() || !id.bytes().all(|b| b.is_ascii_hexdigit()) {
+        Err(format!("`{}` is not a label id", id))?;
+    }
+
+    let data = ProfilingData::new(path_stem)?;
+
+    let mut seen = HashSet::new();
+    let mut matches = Vec::new();
+    for event in data.iter() {
+        let label = event.to_event().label;
+        if !seen.insert(label.clone()) {
+            continue;
+        }
+
+        // The tables show either the labels as recorded or, with
+        // `--pretty-labels`, their formatted version.
+        let pretty = RustcLabelFormatter.format(&label).into_owned();
+        for candidate in [&label[..], &pretty[..]] {
+            if label_id(candidate) == id && !matches.contains(&candidate.to_string()) {
+                matches.push(candidate.to_string());
+            }
+        }
+    }
+
+    if matches.is_empty() {
+        println!("No label of the profile has the id `{}`.", id);
+    }
+    for label in matches {
+        println!("{}", label);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,1098 @@
+//! `cargo mm` bundles the common profiling workflows behind a single cargo
+//! subcommand: recording a profile of the current crate with rustc's
+//! `-Z self-profile` flag, and handing it to `summarize`, `crox`,
+//! `flamegraph`, `stack_collapse`, `mmdot` or `heatmap`, printing quick
+//! statistics about a profile, validating it, following a profile while it
+//! is recorded, checking that its events are properly nested, querying it
+//! with a subset of SQL, searching its labels and arguments, expanding the
+//! labels `summarize` shortened, adding externally measured events to it,
+//! scrubbing the paths in it, comparing repeated runs with statistical tests
+//! and deleting stale profiles. The tools are expected to be installed (e.g. via
+//! `cargo install`) and available in `PATH`, except when the commands are run
+//! via the `mm` binary, which includes all tools.
+
+use std::env;
+use std::error::Error;
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{Duration, SystemTime};
+
+use analyzeme::cli::OutputOpts;
+use analyzeme::{
+    annotate, concatenate_profiles, scrub_paths, sort_profile, Annotation, LoadError,
+    LoadErrorKind, PathScrubber, ProfilingData, Validation, DEFAULT_MAX_EVENTS_IN_MEMORY,
+};
+use measureme::housekeeping::{remove_stale_profiles, CleanupOptions, StaleReason};
+use structopt::StructOpt;
+
+mod abtest;
+mod advise;
+mod bench;
+mod corpus;
+mod follow;
+mod grep;
+mod label;
+mod nesting;
+#[cfg(feature = "sql")]
+mod sql;
+mod stats;
+
+#[derive(StructOpt, Debug)]
+struct CommonOpt {
+    /// The directory profiles are recorded to
+    #[structopt(long = "out-dir", default_value = "target/mm", parse(from_os_str))]
+    out_dir: PathBuf,
+
+    /// The toolchain used for recording profiles. `-Z self-profile` requires
+    /// a nightly compiler.
+    #[structopt(long = "toolchain", default_value = "nightly")]
+    toolchain: String,
+}
+
+#[derive(StructOpt, Debug)]
+struct ProfileOpt {
+    /// The profile to use, given as the path without file extension, like
+    /// the tools take it. Defaults to the most recent profile in the output
+    /// directory.
+    #[structopt(parse(from_os_str))]
+    profile: Option<PathBuf>,
+
+    /// The same as the positional profile, which older versions only took
+    /// as a flag
+    #[structopt(
+        long = "profile",
+        parse(from_os_str),
+        raw(hidden = "true", conflicts_with = r#""profile""#)
+    )]
+    profile_flag: Option<PathBuf>,
+
+    /// Record a new profile first
+    #[structopt(long = "record")]
+    record: bool,
+}
+
+#[derive(StructOpt, Debug)]
+enum MmCommand {
+    /// Builds the current crate with `-Z self-profile` and records a profile
+    #[structopt(name = "record")]
+    Record {
+        #[structopt(flatten)]
+        common: CommonOpt,
+
+        /// Additional arguments for `cargo rustc`, e.g. `--release`
+        cargo_args: Vec<String>,
+    },
+
+    /// Prints a summary of a profile via `summarize`
+    #[structopt(name = "summarize")]
+    Summarize {
+        #[structopt(flatten)]
+        common: CommonOpt,
+
+        #[structopt(flatten)]
+        profile: ProfileOpt,
+    },
+
+    /// Compares a profile against a base profile via `summarize diff`
+    #[structopt(name = "diff")]
+    Diff {
+        #[structopt(flatten)]
+        common: CommonOpt,
+
+        #[structopt(flatten)]
+        profile: ProfileOpt,
+
+        /// Record the base profile from this git revision (e.g. `main`)
+        #[structopt(long = "against", required_unless = "base")]
+        against: Option<String>,
+
+        /// Use an existing base profile
+        #[structopt(long = "base", parse(from_os_str))]
+        base: Option<PathBuf>,
+    },
+
+    /// Converts a profile into another format, written to the output directory
+    #[structopt(name = "export")]
+    Export {
+        #[structopt(flatten)]
+        common: CommonOpt,
+
+        #[structopt(flatten)]
+        profile: ProfileOpt,
+
+        /// One of `chrome` or `firefox` (via `crox`), `flamegraph`, `folded`
+        /// (via `stack_collapse`), `dot` (via `mmdot`), `heatmap` or
+        /// `heatmap-csv` (via `heatmap`), or `arrow` (an Arrow IPC file of
+        /// the events, see `analyzeme::EventColumns::write_arrow()`)
+        #[structopt(long = "format", default_value = "chrome")]
+        format: String,
+    },
+
+    /// Prints file sizes, event counts per kind and the most frequent labels
+    /// of a profile
+    #[structopt(name = "stats")]
+    Stats {
+        #[structopt(flatten)]
+        common: CommonOpt,
+
+        #[structopt(flatten)]
+        profile: ProfileOpt,
+
+        /// The number of labels to list
+        #[structopt(long = "top", default_value = "10")]
+        top: usize,
+    },
+
+    /// Checks that a profile is intact, i.e. that its files are complete and
+    /// belong together and that all of its events and strings are
+    /// well-formed, and lists the events that aren't
+    #[structopt(name = "validate")]
+    Validate {
+        #[structopt(flatten)]
+        common: CommonOpt,
+
+        #[structopt(flatten)]
+        profile: ProfileOpt,
+    },
+
+    /// Looks for known reasons for slow builds in a profile, like a single
+    /// huge codegen unit or a single-threaded tail, and suggests what to do
+    /// about them
+    #[structopt(name = "advise")]
+    Advise {
+        #[structopt(flatten)]
+        common: CommonOpt,
+
+        #[structopt(flatten)]
+        profile: ProfileOpt,
+    },
+
+    /// Follows a profile while it is being recorded and shows the labels
+    /// that took the most time so far, updated until the recording ends
+    #[structopt(name = "follow")]
+    Follow {
+        #[structopt(flatten)]
+        common: CommonOpt,
+
+        #[structopt(flatten)]
+        profile: ProfileOpt,
+
+        /// The number of labels to show
+        #[structopt(long = "top", default_value = "20")]
+        top: usize,
+
+        /// How often to look for new events, e.g. `500ms`
+        #[structopt(
+            long = "interval",
+            default_value = "1s",
+            parse(try_from_str = "parse_duration")
+        )]
+        interval: Duration,
+    },
+
+    /// Checks that the interval events on each thread of a profile are
+    /// properly nested and lists the pairs of events that partially overlap,
+    /// which make self times and flamegraphs wrong
+    #[structopt(name = "check-nesting")]
+    CheckNesting {
+        #[structopt(flatten)]
+        common: CommonOpt,
+
+        #[structopt(flatten)]
+        profile: ProfileOpt,
+
+        /// The number of pairs to list
+        #[structopt(long = "limit", default_value = "20")]
+        limit: usize,
+    },
+
+    /// Runs a SQLite query against the `events` table of a profile, which
+    /// has the columns `thread`, `kind`, `label`, `args`, `start`, `end`,
+    /// `dur` and `value`, e.g. `SELECT label, sum(dur) FROM events GROUP BY
+    /// label`. The profile can also be an Arrow file written by `cargo mm
+    /// export --format arrow`
+    #[cfg(feature = "sql")]
+    #[structopt(name = "sql")]
+    Sql {
+        #[structopt(flatten)]
+        common: CommonOpt,
+
+        query: String,
+
+        #[structopt(flatten)]
+        profile: ProfileOpt,
+    },
+
+    /// Lists the labels and arguments of a profile that contain a substring,
+    /// with the number and total duration of their events. The search index
+    /// is stored next to the profile and reused by later searches.
+    #[structopt(name = "grep")]
+    Grep {
+        #[structopt(flatten)]
+        common: CommonOpt,
+
+        pattern: String,
+
+        #[structopt(flatten)]
+        profile: ProfileOpt,
+    },
+
+    /// Prints the full label for an id that `summarize` printed in place of
+    /// the end of a long label, e.g. `3fa2c81b` for `rustc_middle::…#3fa2c81b`
+    #[structopt(name = "label")]
+    Label {
+        #[structopt(flatten)]
+        common: CommonOpt,
+
+        id: String,
+
+        #[structopt(flatten)]
+        profile: ProfileOpt,
+    },
+
+    /// Writes a copy of a profile with an additional interval event, e.g. for
+    /// the linker time reported by `-Z time-passes`
+    #[structopt(name = "annotate")]
+    Annotate {
+        #[structopt(flatten)]
+        common: CommonOpt,
+
+        #[structopt(flatten)]
+        profile: ProfileOpt,
+
+        /// The start of the event, relative to the start of the profile, e.g.
+        /// `12.3s` or `150ms`
+        #[structopt(long = "at", parse(try_from_str = "parse_duration"))]
+        at: Duration,
+
+        /// The duration of the event, e.g. `0.8s`
+        #[structopt(long = "dur", parse(try_from_str = "parse_duration"))]
+        dur: Duration,
+
+        #[structopt(long = "label")]
+        label: String,
+
+        #[structopt(long = "event-kind", default_value = "Annotation")]
+        event_kind: String,
+
+        /// The thread to add the event to, instead of a thread of its own
+        #[structopt(long = "thread")]
+        thread: Option<u32>,
+
+        /// The path stem of the copy. Defaults to the profile's path stem
+        /// with `-annotated` appended.
+        #[structopt(long = "output", parse(from_os_str))]
+        output: Option<PathBuf>,
+    },
+
+    /// Writes a copy of a profile in which the directories of paths are
+    /// replaced with placeholders, i.e. the current directory with `$SRC`,
+    /// the cargo and rustup homes with `$CARGO_HOME` and `$RUSTUP_HOME` and
+    /// home directories with `$HOME`, so that it can be shared without
+    /// giving away usernames or the layout of the machine
+    #[structopt(name = "scrub")]
+    Scrub {
+        #[structopt(flatten)]
+        common: CommonOpt,
+
+        #[structopt(flatten)]
+        profile: ProfileOpt,
+
+        /// Replaces a further directory, given as `<dir>=<placeholder>`
+        /// (e.g. `/opt/rust=$SYSROOT`). Can be given multiple times.
+        #[structopt(long = "map", number_of_values = 1)]
+        map: Vec<String>,
+
+        /// The path stem of the copy. Defaults to the profile's path stem
+        /// with `-scrubbed` appended.
+        #[structopt(long = "output", parse(from_os_str))]
+        output: Option<PathBuf>,
+    },
+
+    /// Sorts the events of a profile by time in place, for profiles whose
+    /// sinks wrote them out of order, so that they don't have to be sorted
+    /// whenever the profile is loaded
+    #[structopt(name = "normalize")]
+    Normalize {
+        #[structopt(flatten)]
+        common: CommonOpt,
+
+        #[structopt(flatten)]
+        profile: ProfileOpt,
+
+        /// The maximum number of events to hold in memory while sorting.
+        /// Larger files are sorted in runs of this many events, which are
+        /// then merged.
+        #[structopt(long = "max-events-in-memory")]
+        max_events_in_memory: Option<usize>,
+    },
+
+    /// Concatenates shards of one recording, e.g. event segments or
+    /// per-thread event files that have been archived to different places,
+    /// into a single profile
+    #[structopt(name = "cat")]
+    Cat {
+        /// The path stems of the shards, each a profile of its own
+        #[structopt(parse(from_os_str), required = true)]
+        shards: Vec<PathBuf>,
+
+        /// The path stem of the concatenated profile
+        #[structopt(long = "output", parse(from_os_str))]
+        output: PathBuf,
+    },
+
+    /// Compares the profiles of repeated runs in two directories and lists the
+    /// labels whose self time changed significantly
+    #[structopt(name = "abtest")]
+    Abtest {
+        /// The directory containing the profiles of the base runs
+        #[structopt(parse(from_os_str))]
+        base_dir: PathBuf,
+
+        /// The directory containing the profiles of the changed runs
+        #[structopt(parse(from_os_str))]
+        changed_dir: PathBuf,
+
+        /// One of `welch` (Welch's t-test) or `mann-whitney` (the
+        /// Mann-Whitney U test, which is robust against outliers)
+        #[structopt(long = "test", default_value = "welch")]
+        test: abtest::Test,
+
+        /// The significance level: changes with a p-value of at least this
+        /// are considered noise
+        #[structopt(long = "alpha", default_value = "0.05")]
+        alpha: f64,
+    },
+
+    /// Writes the durations of a label across a corpus of profiles as a
+    /// benchmark in `criterion`'s format and compares them with the previous
+    /// run of the benchmark
+    #[structopt(name = "bench")]
+    Bench {
+        /// The label of the events to benchmark, e.g. `typeck`
+        label: String,
+
+        /// The profiles, given as paths without file extension, or
+        /// directories of profiles
+        #[structopt(parse(from_os_str), raw(required = "true", min_values = "1"))]
+        profiles: Vec<PathBuf>,
+
+        /// The id of the benchmark, which defaults to the label
+        #[structopt(long = "id")]
+        id: Option<String>,
+
+        /// Use the self time of the events instead of their duration
+        #[structopt(long = "self-time")]
+        self_time: bool,
+
+        /// The directory `criterion` writes its results to
+        #[structopt(
+            long = "criterion-dir",
+            default_value = "target/criterion",
+            parse(from_os_str)
+        )]
+        criterion_dir: PathBuf,
+    },
+
+    /// Catalogs a directory of many profiles, e.g. of CI runs, and finds the
+    /// profiles matching some filters
+    #[structopt(name = "corpus")]
+    Corpus {
+        #[structopt(subcommand)]
+        command: CorpusCommand,
+    },
+
+    /// Deletes old profiles and incomplete profiles left behind by crashed
+    /// processes from the output directory
+    #[structopt(name = "clean")]
+    Clean {
+        #[structopt(flatten)]
+        common: CommonOpt,
+
+        /// Also delete complete profiles that are older than this many days
+        #[structopt(long = "older-than")]
+        older_than_days: Option<u64>,
+    },
+}
+
+#[derive(StructOpt, Debug)]
+enum CorpusCommand {
+    /// Writes a catalog of the profiles in a directory and its
+    /// subdirectories to `<dir>/mm-corpus.json`. Profiles that haven't
+    /// changed since the last run aren't loaded again.
+    #[structopt(name = "index")]
+    Index {
+        #[structopt(parse(from_os_str))]
+        dir: PathBuf,
+    },
+
+    /// Moves the strings that the profiles in each directory have in common
+    /// into a dictionary in that directory, `mm-dictionary`, and rewrites the
+    /// profiles to refer to it. The dictionary has to stay next to the
+    /// profiles.
+    #[structopt(name = "share")]
+    Share {
+        #[structopt(parse(from_os_str))]
+        dir: PathBuf,
+
+        /// Only share strings that at least this many profiles contain
+        #[structopt(long = "min-profiles", default_value = "2")]
+        min_profiles: usize,
+    },
+
+    /// Lists the profiles of a directory's catalog that match all of the
+    /// given filters
+    #[structopt(name = "query")]
+    Query {
+        #[structopt(parse(from_os_str))]
+        dir: PathBuf,
+
+        /// Only profiles of this crate
+        #[structopt(long = "crate")]
+        crate_name: Option<String>,
+
+        /// Only profiles whose command line contains this string
+        #[structopt(long = "cmd")]
+        cmd_contains: Option<String>,
+
+        /// Only profiles that took longer than this, e.g. `30s`
+        #[structopt(long = "slower-than", parse(try_from_str = "parse_duration"))]
+        slower_than: Option<Duration>,
+
+        /// Only profiles that took less than this
+        #[structopt(long = "faster-than", parse(try_from_str = "parse_duration"))]
+        faster_than: Option<Duration>,
+
+        /// Print the catalog entries as one JSON object per line
+        #[structopt(long = "json")]
+        json: bool,
+    },
+}
+
+/// Profile the current crate with measureme
+#[derive(StructOpt, Debug)]
+struct MmOpt {
+    #[structopt(flatten)]
+    output: OutputOpts,
+
+    #[structopt(subcommand)]
+    command: MmCommand,
+}
+
+/// Runs `cargo mm` with the given command line arguments, which start with
+/// `cargo mm`, like those cargo passes to the `cargo-mm` binary.
+pub fn main_with_args(args: impl IntoIterator<Item = OsString>) -> Result<(), Box<dyn Error>> {
+    let mut args = args.into_iter();
+    args.next();
+
+    // The arguments are parsed as those of a binary named `cargo mm` rather
+    // than as those of a sub command `mm` of `cargo`, so that the flags of
+    // `OutputOpts` may follow the command: values of global flags only make
+    // it up one level of sub commands.
+    match args.next() {
+        Some(mm) if mm == "mm" => command_with_args(std::iter::once("cargo mm".into()).chain(args)),
+        _ => Err("`cargo-mm` is run by cargo, as `cargo mm <COMMAND>`")?,
+    }
+}
+
+/// Like `main_with_args()`, but for arguments that start with the name of
+/// the binary and are followed by a command, like `mm stats`.
+pub fn command_with_args(args: impl IntoIterator<Item = OsString>) -> Result<(), Box<dyn Error>> {
+    run(MmOpt::from_iter(args))
+}
+
+/// The names of the commands of `cargo mm`, in the order of `--help`.
+pub fn command_names() -> Vec<String> {
+    MmCommand::clap()
+        .p
+        .subcommands
+        .iter()
+        .map(|command| command.get_name().to_string())
+        .collect()
+}
+
+fn run(opt: MmOpt) -> Result<(), Box<dyn Error>> {
+    opt.output.init()?;
+    let output = &opt.output;
+
+    match opt.command {
+        MmCommand::Record { common, cargo_args } => {
+            let profile = record(&env::current_dir()?, &common, &cargo_args)?;
+            println!("Recorded profile `{}`", profile.display());
+        }
+
+        MmCommand::Summarize { common, profile } => {
+            let profile = select_profile(&common, &profile)?;
+            run_tool(
+                "summarize",
+                Path::new("."),
+                output,
+                &["summarize".as_ref(), profile.as_os_str()],
+            )?;
+        }
+
+        MmCommand::Diff {
+            common,
+            profile,
+            against,
+            base,
+        } => {
+            let change = select_profile(&common, &profile)?;
+
+            let base = match (base, against) {
+                (Some(base), _) => base,
+                (None, Some(revision)) => record_revision(&common, &revision)?,
+                (None, None) => unreachable!(),
+            };
+
+            run_tool(
+                "summarize",
+                Path::new("."),
+                output,
+                &["diff".as_ref(), base.as_os_str(), change.as_os_str()],
+            )?;
+        }
+
+        MmCommand::Export {
+            common,
+            profile,
+            format,
+        } => {
+            let events_file = select_profile(&common, &profile)?.with_extension("events");
+            let profile = fs::canonicalize(&events_file)
+                .map_err(|e| {
+                    LoadError::new(
+                        LoadErrorKind::FileMissing,
+                        format!(
+                            "couldn't find events file `{}`: {}",
+                            events_file.display(),
+                            e
+                        ),
+                    )
+                })?
+                .with_extension("");
+
+            if format == "arrow" {
+                fs::create_dir_all(&common.out_dir)?;
+                let path = common.out_dir.join("events.arrow");
+                export_arrow(&profile, &path)?;
+                println!("Wrote `{}`", path.display());
+                return Ok(());
+            }
+
+            let (tool, flags, file_name): (_, &[&str], _) = match &format[..] {
+                "chrome" => ("crox", &[], "chrome_profiler.json"),
+                "firefox" => ("crox", &["--firefox"], "firefox_profile.json"),
+                "flamegraph" => ("flamegraph", &[], "rustc.svg"),
+                "folded" => ("stack_collapse", &[], "out.stacks_folded"),
+                "dot" => ("mmdot", &[], "call_graph.dot"),
+                "heatmap" => ("heatmap", &["--format", "png"], "heatmap.png"),
+                "heatmap-csv" => ("heatmap", &[], "heatmap.csv"),
+                other => Err(format!(
+                    "unknown export format `{}`, expected `chrome`, `firefox`, `flamegraph`, \
+                     `folded`, `dot`, `heatmap`, `heatmap-csv` or `arrow`",
+                    other
+                ))?,
+            };
+
+            let mut args: Vec<&std::ffi::OsStr> = flags.iter().map(std::ffi::OsStr::new).collect();
+            args.push(profile.as_os_str());
+
+            fs::create_dir_all(&common.out_dir)?;
+            run_tool(tool, &common.out_dir, output, &args)?;
+            println!("Wrote `{}`", common.out_dir.join(file_name).display());
+        }
+
+        MmCommand::Stats {
+            common,
+            profile,
+            top,
+        } => {
+            let profile = select_profile(&common, &profile)?;
+            stats::print_stats(&profile, top)?;
+        }
+
+        MmCommand::Validate { common, profile } => {
+            let profile = select_profile(&common, &profile)?;
+            let data = ProfilingData::new_validated(&profile, Validation::Lenient)?;
+
+            for warning in data.warnings() {
+                warning.log();
+            }
+            let invalid = data
+                .warnings()
+                .iter()
+                .any(|warning| warning.code == "invalid-event");
+            if invalid {
+                Err(LoadError::new(
+                    LoadErrorKind::Corrupt,
+                    format!("`{}` has invalid events", profile.display()),
+                ))?;
+            }
+
+            println!(
+                "`{}` is valid: {} events",
+                profile.display(),
+                data.num_events()
+            );
+        }
+
+        MmCommand::Advise { common, profile } => {
+            let profile = select_profile(&common, &profile)?;
+            advise::print_advice(&profile)?;
+        }
+
+        MmCommand::Follow {
+            common,
+            profile,
+            top,
+            interval,
+        } => {
+            let profile = select_profile(&common, &profile)?;
+            follow::follow(&profile, top, interval)?;
+        }
+
+        MmCommand::CheckNesting {
+            common,
+            profile,
+            limit,
+        } => {
+            let profile = select_profile(&common, &profile)?;
+            nesting::check_nesting(&profile, limit)?;
+        }
+
+        #[cfg(feature = "sql")]
+        MmCommand::Sql {
+            common,
+            profile,
+            query,
+        } => {
+            let profile = select_profile(&common, &profile)?;
+            let table = if profile.extension().is_some_and(|e| e == "arrow") {
+                analyzeme::EventColumns::read_arrow(fs::File::open(&profile)?)?
+            } else {
+                ProfilingData::new(&profile)?.to_columns()
+            };
+            sql::print_result(&sql::run_query(&table, &query)?);
+        }
+
+        MmCommand::Grep {
+            common,
+            profile,
+            pattern,
+        } => {
+            let profile = select_profile(&common, &profile)?;
+            grep::print_matches(&profile, &pattern)?;
+        }
+
+        MmCommand::Label {
+            common,
+            profile,
+            id,
+        } => {
+            let profile = select_profile(&common, &profile)?;
+            label::print_labels(&profile, &id)?;
+        }
+
+        MmCommand::Annotate {
+            common,
+            profile,
+            at,
+            dur,
+            label,
+            event_kind,
+            thread,
+            output,
+        } => {
+            let profile = select_profile(&common, &profile)?;
+            let output = output.unwrap_or_else(|| path_with_suffix(&profile, "-annotated"));
+
+            let annotation = Annotation {
+                event_kind,
+                thread_id: thread,
+                ..Annotation::new(&label, at, dur)
+            };
+            annotate(&ProfilingData::new(&profile)?, &[annotation], &output)?;
+            println!("Wrote `{}`", output.display());
+        }
+
+        MmCommand::Scrub {
+            common,
+            profile,
+            map,
+            output,
+        } => {
+            let profile = select_profile(&common, &profile)?;
+            let output = output.unwrap_or_else(|| path_with_suffix(&profile, "-scrubbed"));
+
+            let mut scrubber = PathScrubber::from_env();
+            for mapping in &map {
+                match mapping.split_once('=') {
+                    Some((dir, placeholder)) => scrubber.add_prefix(dir, placeholder),
+                    None => Err(format!(
+                        "invalid mapping `{}`, expected `<dir>=<placeholder>`",
+                        mapping
+                    ))?,
+                }
+            }
+
+            scrub_paths(&ProfilingData::new(&profile)?, &scrubber, &output)?;
+            println!("Wrote `{}`", output.display());
+        }
+
+        MmCommand::Normalize {
+            common,
+            profile,
+            max_events_in_memory,
+        } => {
+            let profile = select_profile(&common, &profile)?;
+            let max_events = max_events_in_memory.unwrap_or(DEFAULT_MAX_EVENTS_IN_MEMORY);
+
+            let sorted = sort_profile(&profile, max_events)?;
+            if sorted.is_empty() {
+                println!("The events of `{}` are already sorted", profile.display());
+            }
+            for path in sorted {
+                println!("Sorted `{}`", path.display());
+            }
+        }
+
+        MmCommand::Cat { shards, output } => {
+            let shards = shards
+                .iter()
+                .map(|shard| {
+                    ProfilingData::new(shard)
+                        .map_err(|e| format!("could not load `{}`: {}", shard.display(), e))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+
+            concatenate_profiles(&shards, &output)?;
+            println!("Wrote `{}`", output.display());
+        }
+
+        MmCommand::Abtest {
+            base_dir,
+            changed_dir,
+            test,
+            alpha,
+        } => {
+            abtest::print_changes(&base_dir, &changed_dir, test, alpha)?;
+        }
+
+        MmCommand::Bench {
+            label,
+            profiles,
+            id,
+            self_time,
+            criterion_dir,
+        } => {
+            bench::run_benchmark(&profiles, &label, id.as_deref(), self_time, &criterion_dir)?;
+        }
+
+        MmCommand::Corpus { command } => match command {
+            CorpusCommand::Index { dir } => corpus::index(&dir)?,
+            CorpusCommand::Share { dir, min_profiles } => corpus::share(&dir, min_profiles)?,
+            CorpusCommand::Query {
+                dir,
+                crate_name,
+                cmd_contains,
+                slower_than,
+                faster_than,
+                json,
+            } => {
+                let query = corpus::Query {
+                    crate_name,
+                    cmd_contains,
+                    slower_than,
+                    faster_than,
+                };
+                corpus::print_query(&dir, &query, json)?;
+            }
+        },
+
+        MmCommand::Clean {
+            common,
+            older_than_days,
+        } => {
+            if !common.out_dir.exists() {
+                return Ok(());
+            }
+
+            let options = CleanupOptions {
+                max_age: older_than_days.map(|days| Duration::from_secs(days * 24 * 60 * 60)),
+                remove_incomplete: true,
+            };
+
+            for profile in remove_stale_profiles(&common.out_dir, &options)? {
+                let reason = match profile.reason {
+                    StaleReason::TooOld => "old",
+                    StaleReason::Incomplete => "incomplete",
+                };
+                println!(
+                    "Deleted {} profile `{}`",
+                    reason,
+                    profile.path_stem.display()
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes the events of the profile `path_stem` to the Arrow IPC file `path`.
+#[cfg(feature = "arrow")]
+fn export_arrow(path_stem: &Path, path: &Path) -> Result<(), Box<dyn Error>> {
+    let columns = ProfilingData::new(path_stem)?.to_columns();
+    let mut file = std::io::BufWriter::new(fs::File::create(path)?);
+    columns.write_arrow(&mut file)?;
+    std::io::Write::flush(&mut file)?;
+    Ok(())
+}
+
+#[cfg(not(feature = "arrow"))]
+fn export_arrow(_path_stem: &Path, _path: &Path) -> Result<(), Box<dyn Error>> {
+    Err("`cargo mm` has been built without the `arrow` feature")?
+}
+
+/// Appends `suffix` to the file name of a profile's path stem.
+fn path_with_suffix(path_stem: &Path, suffix: &str) -> PathBuf {
+    let mut file_name = path_stem.file_name().unwrap_or_default().to_os_string();
+    file_name.push(suffix);
+    path_stem.with_file_name(file_name)
+}
+
+/// Builds the crate in `crate_dir` with `-Z self-profile` and returns the
+/// path of the recorded profile.
+fn record(
+    crate_dir: &Path,
+    common: &CommonOpt,
+    cargo_args: &[String],
+) -> Result<PathBuf, Box<dyn Error>> {
+    let out_dir = env::current_dir()?.join(&common.out_dir);
+    fs::create_dir_all(&out_dir)?;
+
+    let previous = latest_profile(&out_dir)?;
+
+    let status = Command::new("cargo")
+        .current_dir(crate_dir)
+        .arg(format!("+{}", common.toolchain))
+        .arg("rustc")
+        .args(cargo_args)
+        .arg("--")
+        .arg(format!("-Zself-profile={}", out_dir.display()))
+        .status()?;
+
+    if !status.success() {
+        Err(format!("`cargo rustc` failed with {}", status))?;
+    }
+
+    match latest_profile(&out_dir)? {
+        Some(profile) if Some(&profile) != previous.as_ref() => Ok(profile.0),
+        _ => Err(
+            "no profile has been recorded, the crate was probably up-to-date. \
+                  Run `cargo clean -p <crate>` and try again.",
+        )?,
+    }
+}
+
+/// Records a profile of the given git revision of the current crate, using a
+/// temporary git worktree.
+fn record_revision(common: &CommonOpt, revision: &str) -> Result<PathBuf, Box<dyn Error>> {
+    let worktree = env::current_dir()?
+        .join(&common.out_dir)
+        .join(format!("worktree-{}", revision.replace('/', "-")));
+
+    git(&[
+        "worktree".as_ref(),
+        "add".as_ref(),
+        "--detach".as_ref(),
+        worktree.as_os_str(),
+        revision.as_ref(),
+    ])?;
+
+    // The base profile goes into its own directory, so that it doesn't get
+    // picked as the most recent profile later on.
+    let base_common = CommonOpt {
+        out_dir: common.out_dir.join("base"),
+        toolchain: common.toolchain.clone(),
+    };
+    let result = record(&worktree, &base_common, &[]);
+
+    git(&[
+        "worktree".as_ref(),
+        "remove".as_ref(),
+        "--force".as_ref(),
+        worktree.as_os_str(),
+    ])?;
+
+    result
+}
+
+fn select_profile(common: &CommonOpt, opt: &ProfileOpt) -> Result<PathBuf, Box<dyn Error>> {
+    if opt.record {
+        return record(&env::current_dir()?, common, &[]);
+    }
+
+    if let Some(profile) = opt.profile.as_ref().or(opt.profile_flag.as_ref()) {
+        return Ok(profile.clone());
+    }
+
+    match latest_profile(&common.out_dir)? {
+        Some((profile, _)) => Ok(profile),
+        None => Err(format!(
+            "no profile found in `{}`, run `cargo mm record` first",
+            common.out_dir.display()
+        ))?,
+    }
+}
+
+/// Returns the most recently modified profile in `dir` (as a path without
+/// file extension), together with its modification time.
+fn latest_profile(dir: &Path) -> Result<Option<(PathBuf, SystemTime)>, Box<dyn Error>> {
+    if !dir.exists() {
+        return Ok(None);
+    }
+
+    let mut latest: Option<(PathBuf, SystemTime)> = None;
+
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+
+        if path.extension().filter(|e| *e == "events").is_none() {
+            continue;
+        }
+
+        let modified = fs::metadata(&path)?.modified()?;
+
+        if latest.as_ref().is_none_or(|&(_, t)| modified > t) {
+            latest = Some((path.with_extension(""), modified));
+        }
+    }
+
+    Ok(latest)
+}
+
+/// Runs one of the tools. Within the `mm` binary, which includes all of them,
+/// the tool is run via `mm` itself, so that it doesn't have to be installed.
+/// The tool prints its messages as requested by `output`.
+fn run_tool(
+    tool: &str,
+    dir: &Path,
+    output: &OutputOpts,
+    args: &[&std::ffi::OsStr],
+) -> Result<(), Box<dyn Error>> {
+    let mm = env::current_exe()
+        .ok()
+        .filter(|exe| exe.file_stem() == Some("mm".as_ref()));
+    let mut command = match mm {
+        // The arguments of `summarize` start with one of its sub commands,
+        // which are commands of `mm`.
+        Some(mm) if tool == "summarize" => Command::new(mm),
+        Some(mm) => {
+            let mut command = Command::new(mm);
+            command.arg(tool);
+            command
+        }
+        None => Command::new(tool),
+    };
+
+    let status = command
+        .current_dir(dir)
+        .args(args)
+        .args(output.to_args())
+        .status()
+        .map_err(|e| {
+            format!(
+                "could not run `{}` ({}). Install it via \
+                 `cargo install --git https://github.com/rust-lang/measureme {}`",
+                tool, e, tool
+            )
+        })?;
+
+    if !status.success() {
+        Err(format!("`{}` failed with {}", tool, status))?;
+    }
+
+    Ok(())
+}
+
+fn git(args: &[&std::ffi::OsStr]) -> Result<(), Box<dyn Error>> {
+    let status = Command::new("git").args(args).status()?;
+
+    if !status.success() {
+        Err(format!("`git {:?}` failed with {}", args, status))?;
+    }
+
+    Ok(())
+}
+
+/// Parses durations like `12.3s`, `150ms`, `20us` or `500ns`.
+fn parse_duration(s: &str) -> Result<Duration, String> {
+    let split = s.find(|c: char| c.is_ascii_alphabetic()).unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+
+    let nanos_per_unit = match unit {
+        "s" => 1e9,
+        "ms" => 1e6,
+        "us" => 1e3,
+        "ns" => 1.0,
+        _ => return Err(format!("`{}` needs a unit: `s`, `ms`, `us` or `ns`", s)),
+    };
+
+    match number.parse::<f64>() {
+        Ok(number) if number >= 0.0 => Ok(Duration::from_nanos(
+            (number * nanos_per_unit).round() as u64
+        )),
+        _ => Err(format!("`{}` is not a valid duration", s)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("12.3s"), Ok(Duration::from_millis(12_300)));
+        assert_eq!(parse_duration("150ms"), Ok(Duration::from_millis(150)));
+        assert_eq!(parse_duration("20us"), Ok(Duration::from_micros(20)));
+        assert_eq!(parse_duration("500ns"), Ok(Duration::from_nanos(500)));
+        assert!(parse_duration("12").is_err());
+        assert!(parse_duration("-1s").is_err());
+        assert!(parse_duration("1h").is_err());
+    }
+
+    #[test]
+    fn commands() {
+        let names = command_names();
+        assert!(names.iter().any(|name| name == "stats"));
+        assert!(names.iter().any(|name| name == "validate"));
+        // Sub commands of `corpus` aren't commands of their own.
+        assert!(!names.iter().any(|name| name == "index"));
+    }
+
+    #[test]
+    fn profile_argument() {
+        let profile = |args: &[&str]| match MmOpt::from_iter_safe(args).unwrap().command {
+            MmCommand::Grep {
+                pattern, profile, ..
+            } => {
+                assert_eq!(pattern, "serde");
+                select_profile(&CommonOpt::from_iter(&["mm"]), &profile).unwrap()
+            }
+            command => panic!("unexpected command {:?}", command),
+        };
+
+        assert_eq!(profile(&["mm", "grep", "serde", "foo"]), Path::new("foo"));
+        assert_eq!(
+            profile(&["mm", "grep", "serde", "--profile", "foo"]),
+            Path::new("foo")
+        );
+        assert!(
+            MmOpt::from_iter_safe(&["mm", "grep", "serde", "foo", "--profile", "foo"]).is_err()
+        );
+    }
+}
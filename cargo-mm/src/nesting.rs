@@ -0,0 +1,54 @@
+//! `cargo mm check-nesting` verifies that the interval events on each thread
+//! of a profile are properly nested, and lists the pairs of events that
+//! partially overlap. Such profiles show wrong self times in `summarize` and
+//! wrong stacks in flamegraphs without any other sign that something is off,
+//! so the command fails if it finds any, e.g. for checking the profiles of
+//! an embedder in CI.
+
+use analyzeme::{LightweightEvent, ProfilingData};
+use std::error::Error;
+use std::path::Path;
+use std::time::SystemTime;
+
+pub fn check_nesting(path_stem: &Path, limit: usize) -> Result<(), Box<dyn Error>> {
+    let data = ProfilingData::new(path_stem)?;
+    let violations = analyzeme::find_nesting_violations(&data);
+
+    if violations.is_empty() {
+        println!("The events of all threads are properly nested.");
+        return Ok(());
+    }
+
+    let start_time = data.metadata.start_time;
+    for violation in violations.iter().take(limit) {
+        println!(
+            "thread {}, overlapping for {:?}:",
+            violation.thread_id,
+            violation.overlap()
+        );
+        println!("  {}", describe(&violation.earlier, start_time));
+        println!("  {}", describe(&violation.later, start_time));
+    }
+    if violations.len() > limit {
+        println!("... and {} more", violations.len() - limit);
+    }
+
+    Err(format!(
+        "found {} pairs of partially overlapping events",
+        violations.len()
+    ))?
+}
+
+/// The kind, label and the times of an event, relative to the start of the
+/// profile.
+fn describe(event: &LightweightEvent<'_>, start_time: SystemTime) -> String {
+    let since_start = |t: SystemTime| t.duration_since(start_time).unwrap_or_default();
+    let full = event.to_event();
+    format!(
+        "{} `{}`: {:?} - {:?}",
+        full.event_kind,
+        full.label,
+        since_start(event.timestamp.start()),
+        since_start(event.timestamp.end())
+    )
+}
@@ -0,0 +1,293 @@
+//! `cargo mm sql` runs ad-hoc queries against the events of a profile, e.g.
+//!
+//! ```text
+//! SELECT label, count(*), sum(dur) FROM events
+//! WHERE kind = 'Query' GROUP BY label ORDER BY sum(dur) DESC LIMIT 10
+//! ```
+//!
+//! The queries are run by an embedded, in-memory SQLite database, so they
+//! can use anything SQLite's `SELECT` supports. The database has a single
+//! table, `events`, built from `analyzeme::EventColumns`, with the columns
+//! `thread`, `kind`, `label`, `args` (the arguments joined with `,`), `start`
+//! and `end` (in nanoseconds since the start of the profile), `dur`
+//! (`end - start`) and `value` (the payload of integer events). `end` and
+//! `dur` are `NULL` for instant events, and `value` is `NULL` for all but
+//! integer events. Only available with the `sql` feature.
+
+use analyzeme::EventColumns;
+use rusqlite::types::ValueRef;
+use rusqlite::{params, Connection};
+use std::error::Error;
+use std::fmt;
+
+const CREATE_TABLE: &str = "CREATE TABLE events (
+    thread INTEGER NOT NULL,
+    kind TEXT NOT NULL,
+    label TEXT NOT NULL,
+    args TEXT NOT NULL,
+    start INTEGER NOT NULL,
+    \"end\" INTEGER,
+    dur INTEGER,
+    value INTEGER
+)";
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Null,
+    Int(i64),
+    Float(f64),
+    Str(String),
+}
+
+impl From<ValueRef<'_>> for Value {
+    fn from(value: ValueRef<'_>) -> Value {
+        match value {
+            ValueRef::Null => Value::Null,
+            ValueRef::Integer(i) => Value::Int(i),
+            ValueRef::Real(f) => Value::Float(f),
+            ValueRef::Text(s) | ValueRef::Blob(s) => Value::Str(String::from_utf8_lossy(s).into()),
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Value::Null => f.write_str("NULL"),
+            Value::Int(i) => write!(f, "{}", i),
+            Value::Float(x) => write!(f, "{:.3}", x),
+            Value::Str(ref s) => f.write_str(s),
+        }
+    }
+}
+
+/// The result of a query: column names and rows.
+pub struct QueryResult {
+    pub columns: Vec<String>,
+    pub rows: Vec<Vec<Value>>,
+}
+
+/// Copies `table` into an in-memory database, see the module documentation.
+fn load(table: &EventColumns) -> Result<Connection, Box<dyn Error>> {
+    let mut connection = Connection::open_in_memory()?;
+    connection.execute(CREATE_TABLE, [])?;
+
+    let transaction = connection.transaction()?;
+    {
+        let mut insert =
+            transaction.prepare("INSERT INTO events VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)")?;
+        let int = |value: u64| value as i64;
+
+        for row in 0..table.len() {
+            let start = table.start[row];
+            let end = table.end[row];
+            insert.execute(params![
+                table.thread_id[row],
+                table.kind(row),
+                table.label(row),
+                table.args(row).collect::<Vec<_>>().join(","),
+                int(start),
+                end.map(int),
+                end.map(|end| int(end - start)),
+                table.integer_value[row].map(int),
+            ])?;
+        }
+    }
+    transaction.commit()?;
+
+    Ok(connection)
+}
+
+pub fn run_query(table: &EventColumns, query: &str) -> Result<QueryResult, Box<dyn Error>> {
+    let connection = load(table)?;
+    let mut statement = connection.prepare(query)?;
+    if !statement.readonly() {
+        Err("only queries that read the events are supported")?;
+    }
+
+    let columns: Vec<String> = statement
+        .column_names()
+        .into_iter()
+        .map(String::from)
+        .collect();
+
+    let mut rows = Vec::new();
+    let mut results = statement.query([])?;
+    while let Some(row) = results.next()? {
+        let values = (0..columns.len())
+            .map(|index| row.get_ref(index).map(Value::from))
+            .collect::<Result<_, _>>()?;
+        rows.push(values);
+    }
+
+    Ok(QueryResult { columns, rows })
+}
+
+pub fn print_result(result: &QueryResult) {
+    let rows: Vec<Vec<String>> = result
+        .rows
+        .iter()
+        .map(|row| row.iter().map(|value| value.to_string()).collect())
+        .collect();
+
+    let mut widths: Vec<usize> = result.columns.iter().map(|c| c.chars().count()).collect();
+    for row in &rows {
+        for (width, value) in widths.iter_mut().zip(row) {
+            *width = (*width).max(value.chars().count());
+        }
+    }
+
+    let print_row = |row: &[String]| {
+        let cells: Vec<_> = row
+            .iter()
+            .zip(&widths)
+            .map(|(value, &width)| format!("{:<width$}", value, width = width))
+            .collect();
+        println!("{}", cells.join(" | ").trim_end());
+    };
+
+    print_row(&result.columns);
+    let separator: Vec<_> = widths.iter().map(|&width| "-".repeat(width)).collect();
+    println!("{}", separator.join("-+-"));
+    for row in &rows {
+        print_row(row);
+    }
+
+    println!("({} rows)", rows.len());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use analyzeme::ProfilingDataBuilder;
+
+    fn table() -> EventColumns {
+        let mut b = ProfilingDataBuilder::new();
+
+        b.interval("Query", "typeck", 0, 0, 100, |b| {
+            b.interval("Query", "mir_borrowck", 0, 10, 40, |_| {});
+        });
+        b.interval("Query", "typeck", 1, 0, 50, |_| {});
+        b.instant("QueryCacheHit", "typeck", 1, 60);
+        b.integer("CacheSize", "cache", 1, 70, 4711);
+
+        b.into_profiling_data().to_columns()
+    }
+
+    fn query(query: &str) -> Vec<Vec<Value>> {
+        run_query(&table(), query).unwrap().rows
+    }
+
+    fn s(s: &str) -> Value {
+        Value::Str(s.to_string())
+    }
+
+    #[test]
+    fn group_by() {
+        let result = run_query(
+            &table(),
+            "SELECT label, count(*), sum(dur) AS total FROM events \
+             WHERE kind = 'Query' GROUP BY label ORDER BY total DESC",
+        )
+        .unwrap();
+
+        assert_eq!(result.columns, vec!["label", "count(*)", "total"]);
+        assert_eq!(
+            result.rows,
+            vec![
+                vec![s("typeck"), Value::Int(2), Value::Int(150)],
+                vec![s("mir_borrowck"), Value::Int(1), Value::Int(30)],
+            ]
+        );
+    }
+
+    #[test]
+    fn filters_and_expressions() {
+        assert_eq!(
+            query("select label, dur / 10 from events where dur is not null and label like 'typ%' order by 2"),
+            vec![
+                vec![s("typeck"), Value::Int(5)],
+                vec![s("typeck"), Value::Int(10)],
+            ]
+        );
+        assert_eq!(
+            query("SELECT value FROM events WHERE NOT value IS NULL"),
+            vec![vec![Value::Int(4711)]]
+        );
+        assert_eq!(
+            query("SELECT kind FROM events WHERE thread = 1 AND (end IS NULL OR start < 10) ORDER BY start LIMIT 2"),
+            vec![vec![s("Query")], vec![s("QueryCacheHit")]]
+        );
+    }
+
+    #[test]
+    fn aggregates_without_group_by() {
+        assert_eq!(
+            query("SELECT count(*), count(dur), min(start), max(label), avg(dur) FROM events"),
+            vec![vec![
+                Value::Int(5),
+                Value::Int(3),
+                Value::Int(0),
+                s("typeck"),
+                Value::Float(60.0),
+            ]]
+        );
+        assert_eq!(
+            query("SELECT count(*), sum(dur) FROM events WHERE label = 'nothing'"),
+            vec![vec![Value::Int(0), Value::Null]]
+        );
+    }
+
+    // Queries can use everything SQLite supports.
+    #[test]
+    fn sqlite_features() {
+        assert_eq!(
+            query(
+                "SELECT DISTINCT label FROM events WHERE kind IN ('Query', 'CacheSize') \
+                 GROUP BY label HAVING count(*) < 2 ORDER BY label"
+            ),
+            vec![vec![s("cache")], vec![s("mir_borrowck")]]
+        );
+        assert_eq!(
+            query(
+                "WITH t AS (SELECT thread, sum(dur) AS total FROM events GROUP BY thread) \
+                 SELECT thread, CASE WHEN total > 100 THEN 'busy' ELSE 'idle' END FROM t"
+            ),
+            vec![
+                vec![Value::Int(0), s("busy")],
+                vec![Value::Int(1), s("idle")],
+            ]
+        );
+    }
+
+    // The Arrow files of `cargo mm export --format arrow` give the same
+    // table.
+    #[test]
+    fn arrow_file() {
+        let mut file = Vec::new();
+        table().write_arrow(&mut file).unwrap();
+        let table = EventColumns::read_arrow(std::io::Cursor::new(file)).unwrap();
+
+        assert_eq!(
+            run_query(
+                &table,
+                "SELECT label, args, dur FROM events ORDER BY dur DESC LIMIT 1"
+            )
+            .unwrap()
+            .rows,
+            vec![vec![s("typeck"), s(""), Value::Int(100)]]
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let error = |query: &str| run_query(&table(), query).err().unwrap().to_string();
+
+        assert!(error("SELECT foo FROM events").contains("no such column: foo"));
+        assert!(error("SELECT label FROM other").contains("no such table: other"));
+        assert_eq!(
+            error("DELETE FROM events"),
+            "only queries that read the events are supported"
+        );
+    }
+}
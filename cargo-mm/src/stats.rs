@@ -0,0 +1,126 @@
+//! `cargo mm stats` gives a quick overview of a profile before analyzing it
+//! in depth: how large its files are, which kinds of events it contains and
+//! which labels occur most often. Everything is computed in a single pass
+//! over the events.
+
+use analyzeme::{ProfilingData, Timestamp};
+use measureme::ProfilerFiles;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+#[derive(Default)]
+struct KindStats {
+    count: u64,
+    intervals: u64,
+    total_duration: Duration,
+}
+
+impl KindStats {
+    fn average_duration(&self) -> Option<Duration> {
+        if self.intervals == 0 {
+            None
+        } else {
+            Some(Duration::from_nanos(
+                (self.total_duration.as_nanos() / self.intervals as u128) as u64,
+            ))
+        }
+    }
+}
+
+pub fn print_stats(path_stem: &Path, top: usize) -> Result<(), Box<dyn Error>> {
+    // Loading the profile first reports missing files with their paths.
+    let data = ProfilingData::new(path_stem)?;
+
+    let files = ProfilerFiles::new(path_stem);
+    let file_size = |path: &Path| {
+        fs::metadata(path)
+            .map(|metadata| metadata.len())
+            .map_err(|e| format!("couldn't read the size of `{}`: {}", path.display(), e))
+    };
+    let events_size = file_size(&files.events_file)?;
+    let string_data_size = file_size(&files.string_data_file)?;
+    let string_index_size = file_size(&files.string_index_file)?;
+
+    let mut total = KindStats::default();
+    let mut kinds = HashMap::<String, KindStats>::new();
+    let mut labels = HashMap::<String, u64>::new();
+
+    for event in data.iter() {
+        let event = event.to_event();
+        let stats = kinds.entry(event.event_kind.into_owned()).or_default();
+
+        for stats in [&mut *stats, &mut total] {
+            stats.count += 1;
+
+            if let Timestamp::Interval { start, end } = event.timestamp {
+                stats.intervals += 1;
+                stats.total_duration += end.duration_since(start).unwrap_or_default();
+            }
+        }
+
+        *labels.entry(event.label.into_owned()).or_default() += 1;
+    }
+
+    println!("Profile `{}`", path_stem.display());
+    if let Some(system) = &data.metadata.system {
+        println!("Recorded on {}", system.describe());
+    }
+    println!();
+    println!("File sizes:");
+    println!("  events:       {:>12} bytes", events_size);
+    println!("  string_data:  {:>12} bytes", string_data_size);
+    println!("  string_index: {:>12} bytes", string_index_size);
+    println!(
+        "  string table: {:>12} bytes",
+        string_data_size + string_index_size
+    );
+    println!();
+
+    println!(
+        "Events: {} ({} intervals, average duration {})",
+        total.count,
+        total.intervals,
+        format_average(&total)
+    );
+
+    let mut kinds: Vec<_> = kinds.into_iter().collect();
+    kinds.sort_by(|(a_kind, a), (b_kind, b)| b.count.cmp(&a.count).then(a_kind.cmp(b_kind)));
+
+    for (kind, stats) in &kinds {
+        println!(
+            "  {:<30} {:>10}  average duration {}",
+            kind,
+            stats.count,
+            format_average(stats)
+        );
+    }
+    println!();
+
+    let mut labels: Vec<_> = labels.into_iter().collect();
+    labels.sort_by(|(a_label, a), (b_label, b)| b.cmp(a).then(a_label.cmp(b_label)));
+
+    println!("Most frequent labels ({} distinct):", labels.len());
+    for (label, count) in labels.iter().take(top) {
+        println!("  {:>10}  {}", count, label);
+    }
+
+    if data.metadata.dropped_events > 0 {
+        println!();
+        println!(
+            "Warning: the profiler dropped {} events.",
+            data.metadata.dropped_events
+        );
+    }
+
+    Ok(())
+}
+
+fn format_average(stats: &KindStats) -> String {
+    match stats.average_duration() {
+        Some(duration) => format!("{:.2?}", duration),
+        None => "-".to_string(),
+    }
+}
@@ -0,0 +1,309 @@
+//! Export to the Gecko profile format, which the Firefox Profiler
+//! (https://profiler.firefox.com) can load. Its UI copes with much larger
+//! traces than `chrome://tracing` and profiles can be shared via permalinks.
+//!
+//! The profile doesn't contain any samples. Events are represented as
+//! markers instead: interval events become interval markers and instant
+//! events become instant markers, with the event kind as the marker's
+//! category and the event arguments (and the value of integer events) as its
+//! payload.
+
+use crate::{
+    add_coalesced_count, export_events, generate_thread_to_collapsed_thread_mapping, get_args,
+    ExportedEvents, Opt, ProcessTrack,
+};
+use analyzeme::{find_stalls, CoalescedEvent, ProfilingData};
+use rustc_hash::FxHashMap;
+use serde::Serialize;
+use serde_json::json;
+use std::time::{Duration, SystemTime};
+
+/// The version of the Gecko profile format that is written.
+const GECKO_PROFILE_VERSION: u32 = 24;
+
+const PHASE_INSTANT: u8 = 0;
+const PHASE_INTERVAL: u8 = 1;
+
+/// The colors the Firefox Profiler knows, assigned to the categories in turn.
+const COLORS: &[&str] = &[
+    "blue",
+    "green",
+    "orange",
+    "purple",
+    "yellow",
+    "red",
+    "lightblue",
+    "brown",
+    "magenta",
+];
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Category {
+    name: String,
+    color: &'static str,
+    subcategories: Vec<&'static str>,
+}
+
+/// A marker row: name (as an index into the thread's string table), start
+/// time, end time (in milliseconds), phase, category and payload.
+type Marker = (
+    usize,
+    f64,
+    Option<f64>,
+    u8,
+    usize,
+    Option<serde_json::Value>,
+);
+
+struct Thread {
+    name: String,
+    process_name: Option<String>,
+    process_id: u32,
+    thread_id: u32,
+    strings: Vec<String>,
+    string_indices: FxHashMap<String, usize>,
+    markers: Vec<Marker>,
+}
+
+impl Thread {
+    fn intern(&mut self, s: &str) -> usize {
+        if let Some(&index) = self.string_indices.get(s) {
+            return index;
+        }
+
+        let index = self.strings.len();
+        self.strings.push(s.to_string());
+        self.string_indices.insert(s.to_string(), index);
+        index
+    }
+}
+
+/// Collects the markers of one or more profiles, each thread of which becomes
+/// a thread of the Gecko profile.
+pub struct GeckoProfile {
+    /// Times are emitted in milliseconds relative to this point.
+    start_time: Duration,
+    categories: Vec<Category>,
+    category_indices: FxHashMap<String, usize>,
+    threads: Vec<Thread>,
+}
+
+impl GeckoProfile {
+    /// `start_time` is the origin of the timeline, relative to the time
+    /// origin of the `ProcessTrack`s.
+    pub fn new(start_time: Duration) -> GeckoProfile {
+        GeckoProfile {
+            start_time,
+            categories: Vec::new(),
+            category_indices: FxHashMap::default(),
+            threads: Vec::new(),
+        }
+    }
+
+    fn category(&mut self, event_kind: &str) -> usize {
+        if let Some(&index) = self.category_indices.get(event_kind) {
+            return index;
+        }
+
+        let index = self.categories.len();
+        self.categories.push(Category {
+            name: event_kind.to_string(),
+            color: COLORS[index % COLORS.len()],
+            subcategories: vec!["Other"],
+        });
+        self.category_indices.insert(event_kind.to_string(), index);
+        index
+    }
+
+    fn millis(&self, track: &ProcessTrack, t: SystemTime) -> f64 {
+        let t = track
+            .timestamp(t)
+            .checked_sub(self.start_time)
+            .unwrap_or_else(|| Duration::from_nanos(0));
+        t.as_nanos() as f64 / 1_000_000.0
+    }
+
+    /// Adds a thread for every thread of `data`, respecting the options that
+    /// `crox` also applies to Chrome traces.
+    pub fn add_profile(&mut self, opt: &Opt, data: &ProfilingData, track: &ProcessTrack) {
+        let thread_to_collapsed_thread = generate_thread_to_collapsed_thread_mapping(opt, data);
+        let thread_names = if opt.collapse_threads {
+            FxHashMap::default()
+        } else {
+            data.thread_names()
+        };
+
+        let collapsed_thread_id = |thread_id: u32| {
+            *thread_to_collapsed_thread
+                .get(&thread_id)
+                .unwrap_or(&thread_id)
+        };
+        let new_thread = |thread_id: u32| Thread {
+            name: thread_names
+                .get(&thread_id)
+                .cloned()
+                .unwrap_or_else(|| format!("Thread {}", thread_id)),
+            process_name: track.process_name.clone(),
+            process_id: track.process_id,
+            thread_id,
+            strings: Vec::new(),
+            string_indices: FxHashMap::default(),
+            markers: Vec::new(),
+        };
+
+        let mut threads = FxHashMap::<u32, Thread>::default();
+
+        let ExportedEvents { events, dropped } = export_events(opt, data, true);
+        for CoalescedEvent { event, count } in events {
+            let (end, phase) = match event.duration() {
+                Some(_) => (
+                    Some(self.millis(track, event.timestamp.end())),
+                    PHASE_INTERVAL,
+                ),
+                None => (None, PHASE_INSTANT),
+            };
+            let start = self.millis(track, event.timestamp.start());
+
+            let full_event = event.to_event();
+            let category = self.category(&full_event.event_kind);
+
+            let mut payload = get_args(data, &full_event).unwrap_or_default();
+            if let Some(value) = full_event.integer_value {
+                payload.insert("value".to_string(), json!(value));
+            }
+            add_coalesced_count(&mut payload, count);
+            let payload = if payload.is_empty() {
+                None
+            } else {
+                payload.insert("type".to_string(), json!(full_event.event_kind));
+                Some(json!(payload))
+            };
+
+            let thread_id = collapsed_thread_id(event.thread_id);
+            let thread = threads
+                .entry(thread_id)
+                .or_insert_with(|| new_thread(thread_id));
+            let name = thread.intern(&full_event.label);
+            thread
+                .markers
+                .push((name, start, end, phase, category, payload));
+        }
+
+        // tell how many events were left out because of `--max-events`
+        if !dropped.is_empty() {
+            let category = self.category("Dropped");
+
+            for dropped in dropped {
+                let start = self.millis(track, dropped.timestamp);
+                let payload = json!({ "type": "Dropped", "dropped_events": dropped.count });
+
+                let thread_id = collapsed_thread_id(dropped.thread_id);
+                let thread = threads
+                    .entry(thread_id)
+                    .or_insert_with(|| new_thread(thread_id));
+                let name = thread.intern("dropped events");
+                thread
+                    .markers
+                    .push((name, start, None, PHASE_INSTANT, category, Some(payload)));
+            }
+        }
+
+        // highlight stretches in which a thread did not record anything
+        if let Some(stall_threshold) = opt.stall_threshold {
+            let category = self.category("Stall");
+
+            for stall in find_stalls(data, Duration::from_micros(stall_threshold)) {
+                let start = self.millis(track, stall.start);
+                let end = self.millis(track, stall.end);
+
+                let thread_id = collapsed_thread_id(stall.thread_id);
+                let thread = threads
+                    .entry(thread_id)
+                    .or_insert_with(|| new_thread(thread_id));
+                let name = thread.intern("stall");
+                thread
+                    .markers
+                    .push((name, start, Some(end), PHASE_INTERVAL, category, None));
+            }
+        }
+
+        let mut threads: Vec<_> = threads.into_values().collect();
+        threads.sort_by_key(|thread| thread.thread_id);
+        self.threads.extend(threads);
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        let threads: Vec<_> = self
+            .threads
+            .iter()
+            .map(|thread| {
+                json!({
+                    "name": thread.name,
+                    "processType": "default",
+                    "processName": thread.process_name,
+                    "pid": thread.process_id,
+                    "tid": thread.thread_id,
+                    "registerTime": 0,
+                    "unregisterTime": null,
+                    "samples": {
+                        "schema": { "stack": 0, "time": 1, "eventDelay": 2 },
+                        "data": [],
+                    },
+                    "markers": {
+                        "schema": {
+                            "name": 0,
+                            "startTime": 1,
+                            "endTime": 2,
+                            "phase": 3,
+                            "category": 4,
+                            "data": 5,
+                        },
+                        "data": thread.markers,
+                    },
+                    "stackTable": {
+                        "schema": { "prefix": 0, "frame": 1 },
+                        "data": [],
+                    },
+                    "frameTable": {
+                        "schema": {
+                            "location": 0,
+                            "relevantForJS": 1,
+                            "innerWindowID": 2,
+                            "implementation": 3,
+                            "optimizations": 4,
+                            "line": 5,
+                            "column": 6,
+                            "category": 7,
+                            "subcategory": 8,
+                        },
+                        "data": [],
+                    },
+                    "stringTable": thread.strings,
+                })
+            })
+            .collect();
+
+        json!({
+            "meta": {
+                "version": GECKO_PROFILE_VERSION,
+                "startTime": self.start_time.as_nanos() as f64 / 1_000_000.0,
+                "shutdownTime": null,
+                "interval": 1,
+                "stackwalk": 0,
+                "debug": 0,
+                "gcpoison": 0,
+                "asyncstack": 0,
+                "processType": 0,
+                "product": "measureme",
+                "categories": self.categories,
+                "markerSchema": [],
+            },
+            "libs": [],
+            "pages": [],
+            "processes": [],
+            "pausedRanges": [],
+            "threads": threads,
+        })
+    }
+}
@@ -0,0 +1,726 @@
+use rustc_hash::FxHashMap;
+use std::ffi::OsString;
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use analyzeme::cli::OutputOpts;
+use analyzeme::{
+    filter_self_profile_events, find_flows, find_stalls, ArgValue, CoalescedEvent,
+    EventIteratorExt, LabelRules, ProfilingData, SelfProfileEvents, SymbolMap, Timestamp,
+};
+
+use measureme::rustc::{
+    INCREMENTAL_LOAD_RESULT_EVENT_KIND, INCREMENTAL_RESULT_HASHING_EVENT_KIND,
+    QUERY_BLOCKED_EVENT_KIND, QUERY_CACHE_HIT_EVENT_KIND, QUERY_EVENT_KIND,
+};
+use measureme::{ProfilerConfig, ToolInfo};
+use serde::{Serialize, Serializer};
+use serde_json::json;
+use std::cmp;
+use structopt::StructOpt;
+
+mod firefox;
+mod trace_writer;
+
+use trace_writer::TraceWriter;
+
+fn as_micros<S: Serializer>(d: &Duration, s: S) -> Result<S::Ok, S::Error> {
+    let v = (d.as_secs() * 1_000_000) + (d.subsec_nanos() as u64 / 1_000);
+    s.serialize_u64(v)
+}
+
+#[derive(Clone, Copy, Eq, PartialEq, Serialize)]
+enum EventType {
+    #[serde(rename = "X")]
+    Complete,
+}
+
+#[derive(Serialize)]
+struct Event {
+    name: String,
+    #[serde(rename = "cat")]
+    category: String,
+    #[serde(rename = "ph")]
+    event_type: EventType,
+    #[serde(rename = "ts", serialize_with = "as_micros")]
+    #[serde()]
+    timestamp: Duration,
+    #[serde(rename = "dur", serialize_with = "as_micros")]
+    duration: Duration,
+    #[serde(rename = "pid")]
+    process_id: u32,
+    #[serde(rename = "tid")]
+    thread_id: u32,
+    args: Option<Args>,
+}
+
+impl Event {
+    fn start_us(&self) -> u64 {
+        self.timestamp.as_micros() as u64
+    }
+
+    fn end_us(&self) -> u64 {
+        (self.timestamp + self.duration).as_micros() as u64
+    }
+}
+
+/// The `args` of an event, which Perfetto and the Chromium tools show when
+/// an event is selected. Unlike a hash map, this keeps the arguments in the
+/// order in which the event recorded them.
+#[derive(Default)]
+struct Args(Vec<(String, serde_json::Value)>);
+
+impl Args {
+    fn insert(&mut self, name: String, value: serde_json::Value) {
+        self.0.push((name, value));
+    }
+
+    fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl Serialize for Args {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeMap;
+
+        let mut map = serializer.serialize_map(Some(self.0.len()))?;
+        for (name, value) in &self.0 {
+            map.serialize_entry(name, value)?;
+        }
+        map.end()
+    }
+}
+
+#[derive(StructOpt, Debug)]
+struct Opt {
+    #[structopt(required_unless = "dir")]
+    file_prefix: Vec<PathBuf>,
+    /// all event trace files in dir will be merged to one chrome_profiler.json file
+    #[structopt(long = "dir")]
+    dir: Option<PathBuf>,
+    /// collapse threads without overlapping events
+    #[structopt(long = "collapse-threads")]
+    collapse_threads: bool,
+    /// filter out events with shorter duration (in microseconds)
+    #[structopt(long = "minimum-duration")]
+    minimum_duration: Option<u128>,
+    /// coalesce runs of consecutive events with the same label that are
+    /// shorter than this (in microseconds) into a single event
+    #[structopt(long = "coalesce")]
+    coalesce: Option<u64>,
+    /// keep at most this many events per thread, the longest ones, and
+    /// record how many were dropped
+    #[structopt(long = "max-events")]
+    max_events: Option<usize>,
+    /// add `Stall` events for stretches longer than this (in microseconds)
+    /// during which a thread did not record any events
+    #[structopt(long = "stall-threshold")]
+    stall_threshold: Option<u64>,
+    /// compare two profiles: the first <file_prefix> is the base and the
+    /// second one the changed profile. They are placed on adjacent process
+    /// tracks, both starting at time zero.
+    #[structopt(long = "compare")]
+    compare: bool,
+    /// only keep the events rustc would have recorded with this value for
+    /// `-Z self-profile-events` (e.g. `default` or `query-provider,args`)
+    #[structopt(long = "self-profile-events")]
+    self_profile_events: Option<String>,
+    /// write a `firefox_profile.json` for the Firefox Profiler
+    /// (profiler.firefox.com) instead of a `chrome_profiler.json`
+    #[structopt(long = "firefox")]
+    firefox: bool,
+    /// resolve address arguments via this symbol map in the format JITs
+    /// write for `perf` (`<start> <size> <name>` per line, in hexadecimal)
+    #[structopt(long = "symbol-map")]
+    symbol_map: Option<PathBuf>,
+    /// rewrite labels with the rules in this file, one `<regex> =>
+    /// <replacement>` per line, e.g. to group all `codegen_module(...)`
+    /// events under one name
+    #[structopt(long = "label-rules")]
+    label_rules: Option<PathBuf>,
+    /// compress the output with gzip, adding `.gz` to the file names
+    #[structopt(long = "gzip")]
+    gzip: bool,
+    /// split the Chrome trace into files of about this many megabytes of
+    /// JSON, `chrome_profiler.<n>.json`, listed in
+    /// `chrome_profiler.manifest.json`
+    #[structopt(long = "chunk-size")]
+    chunk_size: Option<u64>,
+    #[structopt(flatten)]
+    output: OutputOpts,
+    /// profile crox itself, writing the profile to <path_stem>
+    #[structopt(long = "self-profile")]
+    self_profile: Option<PathBuf>,
+}
+
+/// Determines the process track a profile's events are placed on.
+struct ProcessTrack {
+    process_id: u32,
+    process_name: Option<String>,
+    sort_index: u64,
+    /// Timestamps are emitted relative to this point in time.
+    time_origin: SystemTime,
+}
+
+impl ProcessTrack {
+    /// The track for a profile shown on its own: its events are placed at
+    /// their real time and processes are ordered by start time.
+    fn for_profile(data: &ProfilingData) -> ProcessTrack {
+        ProcessTrack {
+            process_id: data.metadata.process_id,
+            process_name: crate_name(&data.metadata.cmd).map(String::from),
+            sort_index: data
+                .metadata
+                .start_time
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_micros() as u64,
+            time_origin: UNIX_EPOCH,
+        }
+    }
+
+    /// The track for one side of a comparison: the profile gets a synthetic
+    /// process id, so that the two sides end up next to each other even if
+    /// they have been recorded by processes with the same id, and starts at
+    /// time zero.
+    fn for_comparison(data: &ProfilingData, side: &str, index: u32) -> ProcessTrack {
+        let name = match crate_name(&data.metadata.cmd) {
+            Some(crate_name) => format!("{}: {}", side, crate_name),
+            None => side.to_string(),
+        };
+
+        ProcessTrack {
+            process_id: index + 1,
+            process_name: Some(name),
+            sort_index: index as u64,
+            time_origin: data.metadata.start_time,
+        }
+    }
+
+    fn timestamp(&self, t: SystemTime) -> Duration {
+        t.duration_since(self.time_origin)
+            .unwrap_or_else(|_| Duration::from_nanos(0))
+    }
+}
+
+// generate mapping from thread_id to collapsed thread_id or an empty map
+fn generate_thread_to_collapsed_thread_mapping(
+    opt: &Opt,
+    data: &ProfilingData,
+) -> FxHashMap<u32, u32> {
+    let mut thread_to_collapsed_thread: FxHashMap<u32, u32> = FxHashMap::default();
+
+    if opt.collapse_threads {
+        // collect start and end times for all threads
+        let mut thread_start_and_end: FxHashMap<u32, (SystemTime, SystemTime)> =
+            FxHashMap::default();
+        for event in data.iter() {
+            thread_start_and_end
+                .entry(event.thread_id)
+                .and_modify(|(thread_start, thread_end)| {
+                    let (event_min, event_max) = timestamp_to_min_max(event.timestamp);
+                    *thread_start = cmp::min(*thread_start, event_min);
+                    *thread_end = cmp::max(*thread_end, event_max);
+                })
+                .or_insert_with(|| timestamp_to_min_max(event.timestamp));
+        }
+        // collect the the threads in order of the end time
+        let mut end_and_thread = thread_start_and_end
+            .iter()
+            .map(|(&thread_id, &(_start, end))| (end, thread_id))
+            .collect::<Vec<_>>();
+
+        end_and_thread.sort_unstable_by_key(|&(end, _thread_id)| end);
+        let mut next_end_iter = end_and_thread.iter().peekable();
+
+        // collect the the threads in order of the start time
+        let mut start_and_thread = thread_start_and_end
+            .iter()
+            .map(|(&thread_id, &(start, _end))| (start, thread_id))
+            .collect::<Vec<_>>();
+
+        start_and_thread.sort_unstable_by_key(|&(start, _thread_id)| start);
+
+        let mut current_thread_id = 0; // use new thread_ids to avoid strange gaps in the numbers
+        for &(start, thread_id) in start_and_thread.iter() {
+            // safe to unwrap due to end_and_thread and start_and_thread have the same length
+            let (next_end, next_thread_id) = next_end_iter.peek().unwrap();
+            if start > *next_end {
+                next_end_iter.next();
+                // need to lookup the thread_id due to new and collapsed threads
+                let mapped_thread_id = *thread_to_collapsed_thread
+                    .get(next_thread_id)
+                    .unwrap_or(next_thread_id);
+
+                thread_to_collapsed_thread.insert(thread_id, mapped_thread_id);
+            } else {
+                thread_to_collapsed_thread.insert(thread_id, current_thread_id);
+                current_thread_id += 1;
+            }
+        }
+    }
+    thread_to_collapsed_thread
+}
+
+/// Arguments are keyed by the names from the event kind's argument schema.
+/// Without a schema, the argument of rustc's query events is its
+/// `query_key`, and other arguments fall back to `arg{i}`.
+fn get_args(data: &ProfilingData, full_event: &analyzeme::Event) -> Option<Args> {
+    let is_query = [
+        QUERY_EVENT_KIND,
+        QUERY_BLOCKED_EVENT_KIND,
+        QUERY_CACHE_HIT_EVENT_KIND,
+        INCREMENTAL_LOAD_RESULT_EVENT_KIND,
+        INCREMENTAL_RESULT_HASHING_EVENT_KIND,
+    ]
+    .contains(&&full_event.event_kind[..]);
+
+    if !full_event.additional_data.is_empty() {
+        Some(Args(
+            data.decode_args(full_event)
+                .into_iter()
+                .enumerate()
+                .map(|(i, arg)| {
+                    let name = match arg.name {
+                        Some(name) => name.to_string(),
+                        None if is_query && i == 0 => "query_key".to_string(),
+                        None => format!("arg{}", i),
+                    };
+                    let value = match arg.value {
+                        ArgValue::String(s) => json!(s),
+                        ArgValue::U64(n) => json!(n),
+                        ArgValue::Bool(b) => json!(b),
+                        address @ ArgValue::Address { .. } => json!(address.to_string()),
+                        location @ ArgValue::Location { .. } => json!(location.to_string()),
+                    };
+                    (name, value)
+                })
+                .collect(),
+        ))
+    } else {
+        None
+    }
+}
+
+/// The number of events `--max-events` left out on a thread.
+struct DroppedEvents {
+    thread_id: u32,
+    count: usize,
+    /// The start of the earliest event on the thread, where the annotation
+    /// is placed.
+    timestamp: SystemTime,
+}
+
+struct ExportedEvents<'a> {
+    events: Box<dyn Iterator<Item = CoalescedEvent<'a>> + 'a>,
+    dropped: Vec<DroppedEvents>,
+}
+
+/// The events to export, in the order of the event stream: short ones are
+/// coalesced if requested via `--coalesce`, then the ones shorter than
+/// `--minimum-duration` (and instant events, unless `include_instants` is
+/// set) are left out, and finally only the longest `--max-events` events of
+/// every thread are kept.
+fn export_events<'a>(
+    opt: &Opt,
+    data: &'a ProfilingData,
+    include_instants: bool,
+) -> ExportedEvents<'a> {
+    let events: Box<dyn Iterator<Item = CoalescedEvent<'a>> + 'a> = match opt.coalesce {
+        Some(coalesce) => Box::new(data.iter().coalesce_short(Duration::from_micros(coalesce))),
+        None => Box::new(data.iter().map(|event| CoalescedEvent { event, count: 1 })),
+    };
+
+    let minimum_duration = opt.minimum_duration;
+    let events = Box::new(events.filter(move |e| match e.event.duration() {
+        Some(duration) => minimum_duration.is_none_or(|min| duration.as_micros() >= min),
+        None => include_instants,
+    }));
+
+    match opt.max_events {
+        Some(max_events) => keep_longest(events, max_events),
+        None => ExportedEvents {
+            events,
+            dropped: Vec::new(),
+        },
+    }
+}
+
+/// Keeps the `max_events` longest events of every thread. Since events
+/// contain the events nested within them, this drops the innermost events
+/// first and the kept ones still form complete stacks.
+fn keep_longest<'a>(
+    events: impl Iterator<Item = CoalescedEvent<'a>>,
+    max_events: usize,
+) -> ExportedEvents<'a> {
+    let events: Vec<_> = events.collect();
+
+    let mut per_thread = FxHashMap::<u32, Vec<usize>>::default();
+    for (index, e) in events.iter().enumerate() {
+        per_thread.entry(e.event.thread_id).or_default().push(index);
+    }
+
+    let mut keep = vec![true; events.len()];
+    let mut dropped = Vec::new();
+
+    for (thread_id, mut indices) in per_thread {
+        if indices.len() <= max_events {
+            continue;
+        }
+
+        let timestamp = indices
+            .iter()
+            .map(|&index| events[index].event.timestamp.start())
+            .min()
+            .unwrap();
+
+        // Longest first, earlier events first among equally long ones.
+        indices.sort_by_key(|&index| cmp::Reverse(events[index].event.duration()));
+        for &index in &indices[max_events..] {
+            keep[index] = false;
+        }
+
+        dropped.push(DroppedEvents {
+            thread_id,
+            count: indices.len() - max_events,
+            timestamp,
+        });
+    }
+
+    dropped.sort_by_key(|d| d.thread_id);
+
+    ExportedEvents {
+        events: Box::new(
+            events
+                .into_iter()
+                .zip(keep)
+                .filter(|(_, keep)| *keep)
+                .map(|(e, _)| e),
+        ),
+        dropped,
+    }
+}
+
+/// Adds the number of events a coalesced event replaces to its arguments.
+fn add_coalesced_count(args: &mut Args, count: usize) {
+    if count > 1 {
+        args.insert("coalesced_events".to_string(), json!(count));
+    }
+}
+
+/// Runs `crox` with the given command line arguments, the first of which is
+/// the name of the binary. Exits the process if the arguments are invalid,
+/// like the `crox` binary does, and returns the error if `crox` fails, for
+/// `analyzeme::cli::exit_on_error()`.
+pub fn main_with_args(
+    args: impl IntoIterator<Item = OsString>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let result = run(Opt::from_iter(args));
+    measureme::global::finish();
+    result
+}
+
+fn run(opt: Opt) -> Result<(), Box<dyn std::error::Error>> {
+    opt.output.init()?;
+
+    if let Some(path_stem) = &opt.self_profile {
+        measureme::global::init(path_stem, &ProfilerConfig::default())?;
+        measureme::global::set_tool_info(ToolInfo {
+            name: "crox".to_string(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            git_sha: None,
+            flags: Vec::new(),
+        });
+    }
+
+    if opt.compare && (opt.file_prefix.len() != 2 || opt.dir.is_some()) {
+        Err("--compare requires exactly two <file_prefix> arguments and no --dir")?;
+    }
+
+    if opt.firefox && opt.chunk_size.is_some() {
+        Err("--chunk-size only applies to the Chrome trace, not to --firefox")?;
+    }
+
+    if opt.chunk_size == Some(0) {
+        Err("--chunk-size must be at least 1 (megabyte)")?;
+    }
+
+    let self_profile_events = match &opt.self_profile_events {
+        Some(spec) => Some(SelfProfileEvents::parse(spec)?),
+        None => None,
+    };
+
+    let symbol_map = match &opt.symbol_map {
+        Some(path) => Some(SymbolMap::load(path)?),
+        None => None,
+    };
+
+    let label_rules = match &opt.label_rules {
+        Some(path) => Some(LabelRules::load(path)?),
+        None => None,
+    };
+
+    let dir_paths = file_prefixes_in_dir(&opt)?;
+
+    let prepare_profile = |index: usize, mut data: ProfilingData| {
+        if let Some(events) = self_profile_events {
+            data = filter_self_profile_events(&data, events);
+        }
+
+        if let Some(symbol_map) = &symbol_map {
+            data.set_symbolizer(symbol_map.clone());
+        }
+
+        if let Some(label_rules) = &label_rules {
+            data.set_label_rules(label_rules.clone());
+        }
+
+        let track = if opt.compare {
+            let side = if index == 0 { "base" } else { "changed" };
+            ProcessTrack::for_comparison(&data, side, index as u32)
+        } else {
+            ProcessTrack::for_profile(&data)
+        };
+
+        (data, track)
+    };
+
+    if opt.firefox {
+        let file_prefixes: Vec<_> = opt.file_prefix.iter().chain(dir_paths.iter()).collect();
+        let profiles: Vec<_> = ProfilingData::load_all(&file_prefixes)?
+            .into_iter()
+            .enumerate()
+            .map(|(index, data)| prepare_profile(index, data))
+            .collect();
+
+        // Comparisons start at zero, otherwise the earliest profile starts
+        // at the beginning of the timeline.
+        let start_time = profiles
+            .iter()
+            .filter(|_| !opt.compare)
+            .map(|(data, track)| track.timestamp(data.metadata.start_time))
+            .min()
+            .unwrap_or_else(|| Duration::from_nanos(0));
+
+        measureme::activity!("write_firefox_profile");
+        let mut profile = firefox::GeckoProfile::new(start_time);
+        for (data, track) in &profiles {
+            profile.add_profile(&opt, data, track);
+        }
+
+        let firefox_file = trace_writer::create_file("firefox_profile.json", opt.gzip)?;
+        serde_json::to_writer(firefox_file, &profile.to_json())?;
+
+        return Ok(());
+    }
+
+    let mut writer = TraceWriter::new(opt.gzip, opt.chunk_size.map(|mb| mb * 1_000_000));
+
+    // The profiles are loaded one after the other here, so that only one
+    // of them has to fit into memory at a time.
+    for (index, file_prefix) in opt.file_prefix.iter().chain(dir_paths.iter()).enumerate() {
+        let (data, track) = prepare_profile(index, ProfilingData::new(file_prefix)?);
+        emit_profile(&mut writer, &opt, &data, &track)?;
+    }
+
+    writer.finish()
+}
+
+fn emit_profile(
+    writer: &mut TraceWriter,
+    opt: &Opt,
+    data: &ProfilingData,
+    track: &ProcessTrack,
+) -> Result<(), Box<dyn std::error::Error>> {
+    measureme::activity!("emit_profile");
+    let thread_to_collapsed_thread = generate_thread_to_collapsed_thread_mapping(opt, data);
+
+    // the metadata comes first, so that every chunk of a chunked trace
+    // repeats it
+
+    // add the names of registered threads, unless threads have been
+    // collapsed in which case a name would apply to several threads
+    if !opt.collapse_threads {
+        let mut thread_names: Vec<_> = data.thread_names().into_iter().collect();
+        thread_names.sort();
+        // External ids don't fit into a `tid`, so they go into the name.
+        let external_thread_ids = data.external_thread_ids();
+
+        for (thread_id, thread_name) in thread_names {
+            let thread_name = match external_thread_ids.get(&thread_id) {
+                Some(external_id) => format!("{} ({})", thread_name, external_id),
+                None => thread_name,
+            };
+            let thread_name = json!({
+                "name": "thread_name",
+                "ph" : "M",
+                "ts" : 0,
+                "tid" : thread_id,
+                "cat" : "",
+                "pid" : track.process_id,
+                "args": {
+                    "name" : thread_name
+                }
+            });
+            writer.write_metadata(&thread_name)?;
+        }
+    }
+    // add crate name for the process_id
+    if let Some(ref name) = track.process_name {
+        let process_name = json!({
+            "name": "process_name",
+            "ph" : "M",
+            "ts" : 0,
+            "tid" : 0,
+            "cat" : "",
+            "pid" : track.process_id,
+            "args": {
+                "name" : name
+            }
+        });
+        writer.write_metadata(&process_name)?;
+    }
+    // sort the processes after start time
+    let process_name = json!({
+        "name": "process_sort_index",
+        "ph" : "M",
+        "ts" : 0,
+        "tid" : 0,
+        "cat" : "",
+        "pid" : track.process_id,
+        "args": {
+            "sort_index" : track.sort_index
+        }
+    });
+    writer.write_metadata(&process_name)?;
+
+    // Chrome does not seem to like how many QueryCacheHit events we generate
+    // only handle Interval events for now
+    let ExportedEvents { events, dropped } = export_events(opt, data, false);
+    for CoalescedEvent { event, count } in events {
+        let duration = event.duration().unwrap();
+        let full_event = event.to_event();
+        let mut args = get_args(data, &full_event);
+        if count > 1 {
+            add_coalesced_count(args.get_or_insert_with(Args::default), count);
+        }
+        let crox_event = Event {
+            name: full_event.label.clone().into_owned(),
+            category: full_event.event_kind.clone().into_owned(),
+            event_type: EventType::Complete,
+            timestamp: track.timestamp(event.timestamp.start()),
+            duration,
+            process_id: track.process_id,
+            thread_id: *thread_to_collapsed_thread
+                .get(&event.thread_id)
+                .unwrap_or(&event.thread_id),
+            args,
+        };
+        writer.write_event(&crox_event, crox_event.start_us(), crox_event.end_us())?;
+    }
+    // tell how many events were left out because of `--max-events`
+    for dropped in dropped {
+        let thread_id = *thread_to_collapsed_thread
+            .get(&dropped.thread_id)
+            .unwrap_or(&dropped.thread_id);
+        let timestamp = track.timestamp(dropped.timestamp).as_micros() as u64;
+        let annotation = json!({
+            "name": "dropped events",
+            "ph" : "i",
+            "s" : "t",
+            "ts" : timestamp,
+            "tid" : thread_id,
+            "cat" : "Dropped",
+            "pid" : track.process_id,
+            "args": {
+                "dropped_events" : dropped.count
+            }
+        });
+        writer.write_event(&annotation, timestamp, timestamp)?;
+    }
+    // draw flows as arrows from where they start to where they end, tagged
+    // with the latency in between; ids are only unique within a profile
+    for flow in find_flows(data) {
+        let id = format!("{}:{}", track.process_id, flow.flow_id);
+        let endpoints = [
+            ("s", flow.start_thread_id, flow.start),
+            ("f", flow.end_thread_id, flow.end),
+        ];
+
+        for (phase, thread_id, t) in endpoints {
+            let thread_id = *thread_to_collapsed_thread
+                .get(&thread_id)
+                .unwrap_or(&thread_id);
+            let timestamp = track.timestamp(t).as_micros() as u64;
+            let flow_event = json!({
+                "name": flow.label,
+                "ph" : phase,
+                "bp" : "e",
+                "id" : id,
+                "ts" : timestamp,
+                "tid" : thread_id,
+                "cat" : "Flow",
+                "pid" : track.process_id,
+                "args": {
+                    "latency_us" : flow.latency().as_micros() as u64
+                }
+            });
+            writer.write_event(&flow_event, timestamp, timestamp)?;
+        }
+    }
+    // highlight stretches in which a thread did not record anything
+    if let Some(stall_threshold) = opt.stall_threshold {
+        let stalls = find_stalls(data, Duration::from_micros(stall_threshold));
+
+        for stall in stalls {
+            let crox_event = Event {
+                name: "stall".to_string(),
+                category: "Stall".to_string(),
+                event_type: EventType::Complete,
+                timestamp: track.timestamp(stall.start),
+                duration: stall.duration(),
+                process_id: track.process_id,
+                thread_id: *thread_to_collapsed_thread
+                    .get(&stall.thread_id)
+                    .unwrap_or(&stall.thread_id),
+                args: None,
+            };
+            writer.write_event(&crox_event, crox_event.start_us(), crox_event.end_us())?;
+        }
+    }
+    Ok(())
+}
+
+fn crate_name(cmd: &str) -> Option<&str> {
+    let index = cmd.find(" --crate-name ")? + 14;
+    let (_, last) = cmd.split_at(index);
+    let (crate_name, _) = last.split_at(last.find(' ').unwrap_or(last.len()));
+    Some(crate_name)
+}
+
+fn file_prefixes_in_dir(opt: &Opt) -> Result<Vec<PathBuf>, std::io::Error> {
+    let mut result = Vec::new();
+    if let Some(dir_path) = &opt.dir {
+        for entry in fs::read_dir(dir_path)? {
+            let entry = entry?;
+            let path = entry.path();
+            if path.extension().filter(|e| *e == "events").is_some() {
+                result.push(path)
+            }
+        }
+    }
+    Ok(result)
+}
+
+fn timestamp_to_min_max(timestamp: Timestamp) -> (SystemTime, SystemTime) {
+    match timestamp {
+        Timestamp::Instant(t) => (t, t),
+        Timestamp::Interval { start, end } => {
+            // Usually start should always be greater than end, but let's not
+            // choke on invalid data here.
+            (cmp::min(start, end), cmp::max(start, end))
+        }
+    }
+}
@@ -0,0 +1,236 @@
+//! Writing the Chrome trace, optionally compressed with gzip (`--gzip`) and
+//! split into chunks of a maximum size (`--chunk-size`), as browsers refuse
+//! to load single traces of several gigabytes.
+//!
+//! Chunks are named `chrome_profiler.<n>.json` and listed in
+//! `chrome_profiler.manifest.json` with the number of events and the time
+//! span they cover. Every chunk is a trace of its own: the metadata events
+//! naming processes and threads are repeated at the start of each one.
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::Serialize;
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+const FILE_STEM: &str = "chrome_profiler";
+
+enum Sink {
+    Plain(BufWriter<File>),
+    Gzip(GzEncoder<BufWriter<File>>),
+}
+
+impl Sink {
+    fn create(file_name: &str, gzip: bool) -> Result<Sink, Box<dyn Error>> {
+        let file = BufWriter::new(File::create(file_name)?);
+        Ok(if gzip {
+            Sink::Gzip(GzEncoder::new(file, Compression::default()))
+        } else {
+            Sink::Plain(file)
+        })
+    }
+
+    fn write_all(&mut self, bytes: &[u8]) -> std::io::Result<()> {
+        match self {
+            Sink::Plain(writer) => writer.write_all(bytes),
+            Sink::Gzip(writer) => writer.write_all(bytes),
+        }
+    }
+
+    fn finish(self) -> std::io::Result<()> {
+        match self {
+            Sink::Plain(mut writer) => writer.flush(),
+            Sink::Gzip(writer) => writer.finish()?.flush(),
+        }
+    }
+}
+
+/// Creates the file `file_name` (with `.gz` appended if `gzip` is set) for
+/// output that isn't chunked, like the Firefox profile.
+pub fn create_file(file_name: &str, gzip: bool) -> Result<impl Write, Box<dyn Error>> {
+    struct Writer(Option<Sink>);
+
+    impl Write for Writer {
+        fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+            self.0.as_mut().unwrap().write_all(bytes)?;
+            Ok(bytes.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Drop for Writer {
+        fn drop(&mut self) {
+            if let Some(sink) = self.0.take() {
+                let _ = sink.finish();
+            }
+        }
+    }
+
+    let file_name = if gzip {
+        format!("{}.gz", file_name)
+    } else {
+        file_name.to_string()
+    };
+    Ok(Writer(Some(Sink::create(&file_name, gzip)?)))
+}
+
+struct Chunk {
+    sink: Sink,
+    info: ChunkInfo,
+    bytes: u64,
+    is_empty: bool,
+}
+
+#[derive(Serialize)]
+struct ChunkInfo {
+    file: String,
+    events: u64,
+    /// The earliest start and the latest end of the events in the chunk, in
+    /// microseconds like the timestamps of the events.
+    start_us: Option<u64>,
+    end_us: Option<u64>,
+}
+
+#[derive(Serialize)]
+struct Manifest<'a> {
+    chunks: &'a [ChunkInfo],
+}
+
+pub struct TraceWriter {
+    gzip: bool,
+    /// In bytes of uncompressed JSON.
+    chunk_size: Option<u64>,
+    current: Option<Chunk>,
+    finished: Vec<ChunkInfo>,
+    metadata: Vec<Vec<u8>>,
+}
+
+impl TraceWriter {
+    pub fn new(gzip: bool, chunk_size: Option<u64>) -> TraceWriter {
+        TraceWriter {
+            gzip,
+            chunk_size,
+            current: None,
+            finished: Vec::new(),
+            metadata: Vec::new(),
+        }
+    }
+
+    /// Writes an event that spans the microseconds `start_us..end_us`.
+    pub fn write_event(
+        &mut self,
+        event: &impl Serialize,
+        start_us: u64,
+        end_us: u64,
+    ) -> Result<(), Box<dyn Error>> {
+        let chunk = self.current_chunk()?;
+        chunk.write(&serde_json::to_vec(event)?)?;
+
+        let info = &mut chunk.info;
+        info.events += 1;
+        info.start_us = Some(info.start_us.map_or(start_us, |s| s.min(start_us)));
+        info.end_us = Some(info.end_us.map_or(end_us, |e| e.max(end_us)));
+
+        self.finish_chunk_if_full()
+    }
+
+    /// Writes a metadata event (`"ph": "M"`), which is repeated in every
+    /// later chunk.
+    pub fn write_metadata(&mut self, event: &impl Serialize) -> Result<(), Box<dyn Error>> {
+        let json = serde_json::to_vec(event)?;
+        self.current_chunk()?.write(&json)?;
+        self.metadata.push(json);
+
+        self.finish_chunk_if_full()
+    }
+
+    /// Finishes the last chunk and, if the trace is chunked, writes the
+    /// manifest.
+    pub fn finish(mut self) -> Result<(), Box<dyn Error>> {
+        // Even an empty trace gets a file.
+        self.current_chunk()?;
+        self.finish_chunk()?;
+
+        if self.chunk_size.is_some() {
+            let manifest = Manifest {
+                chunks: &self.finished,
+            };
+            let file = BufWriter::new(File::create(format!("{}.manifest.json", FILE_STEM))?);
+            serde_json::to_writer_pretty(file, &manifest)?;
+        }
+
+        Ok(())
+    }
+
+    fn current_chunk(&mut self) -> Result<&mut Chunk, Box<dyn Error>> {
+        if self.current.is_none() {
+            let file = match self.chunk_size {
+                Some(_) => format!("{}.{}.json", FILE_STEM, self.finished.len()),
+                None => format!("{}.json", FILE_STEM),
+            };
+            let file = if self.gzip {
+                format!("{}.gz", file)
+            } else {
+                file
+            };
+
+            let mut chunk = Chunk {
+                sink: Sink::create(&file, self.gzip)?,
+                info: ChunkInfo {
+                    file,
+                    events: 0,
+                    start_us: None,
+                    end_us: None,
+                },
+                bytes: 0,
+                is_empty: true,
+            };
+            chunk.sink.write_all(b"[")?;
+            for json in &self.metadata {
+                chunk.write(json)?;
+            }
+
+            self.current = Some(chunk);
+        }
+
+        Ok(self.current.as_mut().unwrap())
+    }
+
+    fn finish_chunk_if_full(&mut self) -> Result<(), Box<dyn Error>> {
+        let is_full = match (&self.current, self.chunk_size) {
+            (Some(chunk), Some(chunk_size)) => chunk.bytes >= chunk_size,
+            _ => false,
+        };
+
+        if is_full {
+            self.finish_chunk()?;
+        }
+        Ok(())
+    }
+
+    fn finish_chunk(&mut self) -> Result<(), Box<dyn Error>> {
+        if let Some(mut chunk) = self.current.take() {
+            chunk.sink.write_all(b"]\n")?;
+            chunk.sink.finish()?;
+            self.finished.push(chunk.info);
+        }
+        Ok(())
+    }
+}
+
+impl Chunk {
+    fn write(&mut self, json: &[u8]) -> std::io::Result<()> {
+        if !self.is_empty {
+            self.sink.write_all(b",\n")?;
+            self.bytes += 2;
+        }
+        self.sink.write_all(json)?;
+        self.bytes += json.len() as u64;
+        self.is_empty = false;
+        Ok(())
+    }
+}
@@ -0,0 +1,58 @@
+use std::error::Error;
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::PathBuf;
+
+use analyzeme::cli::OutputOpts;
+use analyzeme::{collapse_stacks, Diagnostic, ProfilingData};
+use inferno::flamegraph::{from_lines, Options as FlamegraphOptions};
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+struct Opt {
+    file_prefix: PathBuf,
+
+    #[structopt(flatten)]
+    output: OutputOpts,
+}
+
+/// Runs `flamegraph` with the given command line arguments, the first of which is
+/// the name of the binary. Exits the process if the arguments are invalid,
+/// like the `flamegraph` binary does, and returns the error if `flamegraph` fails, for
+/// `analyzeme::cli::exit_on_error()`.
+pub fn main_with_args(args: impl IntoIterator<Item = OsString>) -> Result<(), Box<dyn Error>> {
+    run(Opt::from_iter(args))
+}
+
+fn run(opt: Opt) -> Result<(), Box<dyn Error>> {
+    opt.output.init()?;
+
+    let profiling_data = ProfilingData::new(&opt.file_prefix)?;
+
+    let recorded_stacks = collapse_stacks(&profiling_data)
+        .iter()
+        .map(|(unique_stack, count)| format!("{} {}", unique_stack, count))
+        .collect::<Vec<_>>();
+
+    let file = BufWriter::new(File::create("rustc.svg")?);
+    let mut flamegraph_options = FlamegraphOptions::default();
+
+    from_lines(
+        &mut flamegraph_options,
+        recorded_stacks.iter().map(|s| s.as_ref()),
+        file,
+    )
+    .expect(
+        "unable to generate a flamegraph \
+         from the collapsed stack data",
+    );
+
+    Diagnostic::info(
+        "file-written",
+        format!("wrote {} stacks to `rustc.svg`", recorded_stacks.len()),
+    )
+    .log();
+
+    Ok(())
+}
@@ -0,0 +1,164 @@
+use std::error::Error;
+use std::ffi::OsString;
+use std::fs;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use analyzeme::cli::OutputOpts;
+use analyzeme::{heatmap, Diagnostic, Heatmap, HeatmapRows, ProfilingData};
+use structopt::StructOpt;
+
+mod png;
+
+/// The size of a cell of the rendered heatmap in pixels.
+const CELL_WIDTH: u32 = 8;
+const CELL_HEIGHT: u32 = 16;
+
+/// The colors of an empty cell, of cells with half of the longest time and
+/// of the cell with the longest time, between which the colors of the other
+/// cells are interpolated.
+const COLORS: [[u8; 3]; 3] = [[255, 255, 255], [253, 141, 60], [128, 0, 38]];
+
+#[derive(Clone, Copy, Debug)]
+enum Format {
+    Csv,
+    Png,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Format, String> {
+        match s {
+            "csv" => Ok(Format::Csv),
+            "png" => Ok(Format::Png),
+            other => Err(format!(
+                "invalid format `{}`, expected `csv` or `png`",
+                other
+            )),
+        }
+    }
+}
+
+#[derive(StructOpt, Debug)]
+struct Opt {
+    file_prefix: PathBuf,
+
+    /// The number of equally long time buckets the profile is divided into
+    #[structopt(long = "buckets", default_value = "100")]
+    buckets: usize,
+
+    /// What the rows stand for: `kind` for event kinds or `label` for labels
+    #[structopt(long = "rows", default_value = "kind")]
+    rows: HeatmapRows,
+
+    /// The number of rows with the most time to keep, the time of the others
+    /// is combined into a row `<other>`
+    #[structopt(long = "top", default_value = "20")]
+    top: usize,
+
+    /// `csv` to write `heatmap.csv`, or `png` to render the heatmap to
+    /// `heatmap.png`
+    #[structopt(long = "format", default_value = "csv")]
+    format: Format,
+
+    #[structopt(flatten)]
+    output: OutputOpts,
+}
+
+/// Runs `heatmap` with the given command line arguments, the first of which is
+/// the name of the binary. Exits the process if the arguments are invalid,
+/// like the `heatmap` binary does, and returns the error if `heatmap` fails, for
+/// `analyzeme::cli::exit_on_error()`.
+pub fn main_with_args(args: impl IntoIterator<Item = OsString>) -> Result<(), Box<dyn Error>> {
+    run(Opt::from_iter(args))
+}
+
+fn run(opt: Opt) -> Result<(), Box<dyn Error>> {
+    opt.output.init()?;
+
+    if opt.buckets == 0 {
+        Err("`--buckets` must be at least 1")?;
+    }
+
+    let profiling_data = ProfilingData::new(&opt.file_prefix)?;
+
+    let heatmap = heatmap(&profiling_data, opt.rows, opt.buckets, opt.top);
+    if heatmap.rows.is_empty() {
+        Err("the profile doesn't contain any interval events")?;
+    }
+
+    let output = match opt.format {
+        Format::Csv => {
+            fs::write("heatmap.csv", heatmap.to_csv())?;
+            "heatmap.csv"
+        }
+        Format::Png => {
+            fs::write("heatmap.png", render(&heatmap)?)?;
+
+            // The image has no room for text, so name its rows here.
+            println!("Rows of `heatmap.png`, from top to bottom:");
+            for (i, row) in heatmap.rows.iter().enumerate() {
+                println!(
+                    "{:>4}  {:>12}  {}",
+                    i + 1,
+                    format!("{:.2?}", row.total_time()),
+                    row.name
+                );
+            }
+            "heatmap.png"
+        }
+    };
+
+    Diagnostic::info(
+        "file-written",
+        format!(
+            "wrote {} rows of {} buckets of {:.2?} to `{}`",
+            heatmap.rows.len(),
+            opt.buckets,
+            heatmap.bucket_duration,
+            output
+        ),
+    )
+    .log();
+
+    Ok(())
+}
+
+/// Renders every cell as a block of pixels, with the rows from top to bottom
+/// and time from left to right.
+fn render(heatmap: &Heatmap) -> Result<Vec<u8>, Box<dyn Error>> {
+    let max_nanos = heatmap.max_time().as_nanos().max(1) as f64;
+    let buckets = heatmap.rows[0].buckets.len() as u32;
+    let width = buckets * CELL_WIDTH;
+    let height = heatmap.rows.len() as u32 * CELL_HEIGHT;
+
+    let mut pixels = Vec::with_capacity(width as usize * height as usize);
+    for row in &heatmap.rows {
+        let colors: Vec<[u8; 3]> = row
+            .buckets
+            .iter()
+            .map(|time| color(time.as_nanos() as f64 / max_nanos))
+            .collect();
+        for _ in 0..CELL_HEIGHT {
+            for color in &colors {
+                pixels.extend(std::iter::repeat_n(*color, CELL_WIDTH as usize));
+            }
+        }
+    }
+
+    Ok(png::encode_rgb(width, height, &pixels)?)
+}
+
+/// The color of a cell with `fraction` of the longest time.
+fn color(fraction: f64) -> [u8; 3] {
+    let scaled = fraction.clamp(0.0, 1.0) * (COLORS.len() - 1) as f64;
+    let i = (scaled as usize).min(COLORS.len() - 2);
+    let t = scaled - i as f64;
+
+    let mut color = [0; 3];
+    for (c, (from, to)) in color.iter_mut().zip(COLORS[i].iter().zip(&COLORS[i + 1])) {
+        *c = (*from as f64 + (*to as f64 - *from as f64) * t).round() as u8;
+    }
+    color
+}
@@ -0,0 +1,48 @@
+//! A minimal PNG encoder for the heatmap, which writes a single 8-bit RGB
+//! image without any ancillary chunks.
+
+use flate2::write::ZlibEncoder;
+use flate2::{Compression, Crc};
+use std::io::{self, Write};
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n'];
+
+/// Encodes `height` rows of `width` pixels each as a PNG.
+pub fn encode_rgb(width: u32, height: u32, pixels: &[[u8; 3]]) -> io::Result<Vec<u8>> {
+    assert!(width > 0 && height > 0);
+    assert_eq!(pixels.len(), width as usize * height as usize);
+
+    let mut png = SIGNATURE.to_vec();
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&width.to_be_bytes());
+    header.extend_from_slice(&height.to_be_bytes());
+    // 8 bits per channel, RGB, the only compression and filter methods, and
+    // no interlacing.
+    header.extend_from_slice(&[8, 2, 0, 0, 0]);
+    write_chunk(&mut png, b"IHDR", &header);
+
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    for row in pixels.chunks(width as usize) {
+        // Every scanline starts with its filter type, which is none.
+        encoder.write_all(&[0])?;
+        for pixel in row {
+            encoder.write_all(pixel)?;
+        }
+    }
+    write_chunk(&mut png, b"IDAT", &encoder.finish()?);
+    write_chunk(&mut png, b"IEND", &[]);
+
+    Ok(png)
+}
+
+fn write_chunk(png: &mut Vec<u8>, chunk_type: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    png.extend_from_slice(chunk_type);
+    png.extend_from_slice(data);
+
+    let mut crc = Crc::new();
+    crc.update(chunk_type);
+    crc.update(data);
+    png.extend_from_slice(&crc.sum().to_be_bytes());
+}
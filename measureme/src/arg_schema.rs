@@ -0,0 +1,77 @@
+//! Event arguments are recorded as plain strings (see the `event_id`
+//! module). Embedders can register a schema for the arguments of an event
+//! kind via `Profiler::register_arg_schema()`, which gives each positional
+//! argument a name and a type. The schemas are stored in the profile's
+//! metadata, so that analysis tools can decode arguments into typed values
+//! and display them as, e.g., `bytes=4096`.
+//!
+//! An argument of type `ArgType::Location` is the place in the source code
+//! that recorded the event, so that tools can link a hot event to the code
+//! that created it. `EventIdBuilder::from_label_and_location()` records one,
+//! and the `profile_scope!` macro records activities of the global profiler
+//! with the location of the macro invocation.
+
+use std::fmt;
+
+/// The type of an event argument.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum ArgType {
+    String,
+    /// An unsigned integer, recorded in decimal notation.
+    U64,
+    /// Recorded as `true` or `false`.
+    Bool,
+    /// A code address, e.g. of JIT-compiled code, recorded in hexadecimal
+    /// notation with a `0x` prefix (`format!("{:#x}", address)`). Analysis
+    /// tools can resolve it to a function name.
+    Address,
+    /// A `SourceLocation`, recorded as `file:line:column`.
+    Location,
+}
+
+impl ArgType {
+    /// The name of the type as used in the profile metadata.
+    pub fn name(self) -> &'static str {
+        match self {
+            ArgType::String => "string",
+            ArgType::U64 => "u64",
+            ArgType::Bool => "bool",
+            ArgType::Address => "address",
+            ArgType::Location => "location",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<ArgType> {
+        match name {
+            "string" => Some(ArgType::String),
+            "u64" => Some(ArgType::U64),
+            "bool" => Some(ArgType::Bool),
+            "address" => Some(ArgType::Address),
+            "location" => Some(ArgType::Location),
+            _ => None,
+        }
+    }
+}
+
+/// A place in the source code, usually created with `file!()`, `line!()` and
+/// `column!()` where an event is recorded.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub struct SourceLocation {
+    pub file: &'static str,
+    pub line: u32,
+    pub column: u32,
+}
+
+impl SourceLocation {
+    pub const fn new(file: &'static str, line: u32, column: u32) -> SourceLocation {
+        SourceLocation { file, line, column }
+    }
+}
+
+impl fmt::Display for SourceLocation {
+    /// Displays the location as `file:line:column`, which is how it is
+    /// recorded.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.file, self.line, self.column)
+    }
+}
@@ -0,0 +1,127 @@
+//! A hard cap on how much the `Profiler` records, set via
+//! `ProfilerConfig::budget`, which protects e.g. CI machines from the
+//! runaway profiles of hundreds of gigabytes that a pathological workload
+//! can produce. Every event the profiler records is counted, and once the
+//! number of events or the number of bytes written exceeds the budget, the
+//! profiler stops writing events. With `BudgetPolicy::AggregateOnly`, it
+//! keeps the running totals of the `.summary` file up to date, so that the
+//! totals still cover the whole run.
+//!
+//! The bytes are those the sinks have written so far (see
+//! `Profiler::sink_stats()`), which are only looked at every
+//! `BYTES_CHECK_INTERVAL` events, so a profile can exceed `max_bytes` by
+//! that many events and by what its sinks buffer.
+//!
+//! The metadata of a profile whose budget has been exhausted contains
+//!
+//! ```json
+//! "budget_exhausted": true
+//! ```
+//!
+//! and the events that occurred after that point are missing from it.
+
+use crate::config::{BudgetPolicy, RecordingBudget};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+/// Every how many events the bytes written are compared to
+/// `RecordingBudget::max_bytes`.
+pub const BYTES_CHECK_INTERVAL: u64 = 1024;
+
+pub(crate) struct BudgetGuard {
+    budget: RecordingBudget,
+    events: AtomicU64,
+    exhausted: AtomicBool,
+}
+
+impl BudgetGuard {
+    /// Returns `None` if the budget is unlimited.
+    pub fn new(budget: RecordingBudget) -> Option<BudgetGuard> {
+        if budget.max_events.is_none() && budget.max_bytes.is_none() {
+            return None;
+        }
+
+        Some(BudgetGuard {
+            budget,
+            events: AtomicU64::new(0),
+            exhausted: AtomicBool::new(false),
+        })
+    }
+
+    /// Counts an event and returns whether it is within the budget. The
+    /// first event that isn't calls `on_exhausted`.
+    #[inline]
+    pub fn admit(&self, bytes_written: impl FnOnce() -> u64, on_exhausted: impl FnOnce()) -> bool {
+        if self.exhausted.load(Ordering::Relaxed) {
+            return false;
+        }
+
+        let events = self.events.fetch_add(1, Ordering::Relaxed) + 1;
+        let over_events = self.budget.max_events.is_some_and(|max| events > max);
+        let over_bytes = || {
+            events % BYTES_CHECK_INTERVAL == 0
+                && self
+                    .budget
+                    .max_bytes
+                    .is_some_and(|max| bytes_written() > max)
+        };
+        if !over_events && !over_bytes() {
+            return true;
+        }
+
+        // Only one thread gets to report the exhaustion.
+        if !self.exhausted.swap(true, Ordering::Relaxed) {
+            on_exhausted();
+        }
+        false
+    }
+
+    pub fn when_exhausted(&self) -> BudgetPolicy {
+        self.budget.when_exhausted
+    }
+
+    pub fn is_exhausted(&self) -> bool {
+        self.exhausted.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    #[test]
+    fn max_events() {
+        let guard = BudgetGuard::new(RecordingBudget {
+            max_events: Some(2),
+            ..RecordingBudget::default()
+        })
+        .unwrap();
+
+        let exhausted = Cell::new(0);
+        let admitted: Vec<bool> = (0..4)
+            .map(|_| guard.admit(|| 0, || exhausted.set(exhausted.get() + 1)))
+            .collect();
+
+        assert_eq!(admitted, vec![true, true, false, false]);
+        assert_eq!(exhausted.get(), 1);
+        assert!(guard.is_exhausted());
+    }
+
+    #[test]
+    fn max_bytes() {
+        let guard = BudgetGuard::new(RecordingBudget {
+            max_bytes: Some(100),
+            ..RecordingBudget::default()
+        })
+        .unwrap();
+
+        // The bytes are only checked every `BYTES_CHECK_INTERVAL` events.
+        for _ in 1..BYTES_CHECK_INTERVAL {
+            assert!(guard.admit(|| unreachable!(), || unreachable!()));
+        }
+        assert!(!guard.admit(|| 101, || {}));
+        assert!(guard.is_exhausted());
+
+        assert!(BudgetGuard::new(RecordingBudget::default()).is_none());
+    }
+}
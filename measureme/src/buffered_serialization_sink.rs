@@ -0,0 +1,489 @@
+use crate::checksum::Checksum;
+use crate::config::{OverrunPolicy, ProfilerConfig};
+use crate::diagnostics::Diagnostic;
+use crate::file_header::file_footer;
+use crate::serialization::{Addr, ProfileFileKind, SerializationSink, WriteError};
+use crate::sync::{Condvar, Mutex};
+use std::error::Error;
+use std::fs;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+const DEFAULT_CAPACITY: usize = 8 * 1024 * 1024;
+
+/// How long the writer thread waits for the buffer to fill up before writing
+/// out whatever is in there.
+const FLUSH_INTERVAL: Duration = Duration::from_millis(10);
+
+/// A `SerializationSink` that collects data in a bounded in-memory buffer
+/// which a background thread writes to the file. Recording threads never
+/// wait for I/O unless the buffer is full, in which case the sink's
+/// `OverrunPolicy` determines what happens. This is meant for embedders that
+/// cannot tolerate unbounded blocking when writing the profile, e.g. because
+/// it ends up on a slow network share.
+pub struct BufferedSerializationSink {
+    shared: Arc<Shared>,
+    writer_thread: Option<thread::JoinHandle<()>>,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    // Signalled when there's data for the writer thread, or when the sink is
+    // being closed.
+    data_available: Condvar,
+    // Signalled by the writer thread when it has taken data out of the buffer.
+    space_available: Condvar,
+    capacity: usize,
+    policy: OverrunPolicy,
+    // Mirrors `State::error.is_some()` so that `has_failed()` doesn't need to
+    // take the lock.
+    failed: AtomicBool,
+    dropped_writes: AtomicU64,
+}
+
+struct State {
+    buffer: Vec<u8>,
+    addr: u64,
+    // Set while data overflows into the spill file. All data goes to the
+    // spill file in that case, so that it stays in order.
+    spilling: bool,
+    spill: Option<SpillFile>,
+    error: Option<WriteError>,
+    closed: bool,
+}
+
+struct SpillFile {
+    file: fs::File,
+    path: PathBuf,
+    len: u64,
+    read_pos: u64,
+}
+
+impl SpillFile {
+    fn create() -> io::Result<SpillFile> {
+        static NEXT_SPILL_FILE: AtomicUsize = AtomicUsize::new(0);
+
+        let path = std::env::temp_dir().join(format!(
+            "measureme-spill-{}-{}",
+            std::process::id(),
+            NEXT_SPILL_FILE.fetch_add(1, Ordering::Relaxed)
+        ));
+
+        let file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)?;
+
+        Ok(SpillFile {
+            file,
+            path,
+            len: 0,
+            read_pos: 0,
+        })
+    }
+
+    fn is_empty(&self) -> bool {
+        self.read_pos == self.len
+    }
+
+    fn append(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.file.seek(SeekFrom::Start(self.len))?;
+        self.file.write_all(bytes)?;
+        self.len += bytes.len() as u64;
+        Ok(())
+    }
+
+    /// Reads up to `max_bytes` of spilled data that hasn't been read yet into
+    /// `out`. Resets the file once everything has been read.
+    fn read_chunk(&mut self, out: &mut Vec<u8>, max_bytes: usize) -> io::Result<()> {
+        let chunk_len = (self.len - self.read_pos).min(max_bytes as u64);
+
+        self.file.seek(SeekFrom::Start(self.read_pos))?;
+        (&mut self.file).take(chunk_len).read_to_end(out)?;
+        self.read_pos += chunk_len;
+
+        if self.is_empty() {
+            self.file.set_len(0)?;
+            self.len = 0;
+            self.read_pos = 0;
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for SpillFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+impl Shared {
+    fn record_error(&self, state: &mut State, error: &io::Error) {
+        if state.error.is_none() {
+            state.error = Some(WriteError::from(error));
+            self.failed.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
+impl BufferedSerializationSink {
+    /// Creates a sink that writes to `out`, buffering up to `capacity` bytes.
+    pub fn new(
+        out: Box<dyn Write + Send>,
+        capacity: usize,
+        policy: OverrunPolicy,
+    ) -> io::Result<BufferedSerializationSink> {
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State {
+                buffer: Vec::with_capacity(capacity),
+                addr: 0,
+                spilling: false,
+                spill: None,
+                error: None,
+                closed: false,
+            }),
+            data_available: Condvar::new(),
+            space_available: Condvar::new(),
+            capacity,
+            policy,
+            failed: AtomicBool::new(false),
+            dropped_writes: AtomicU64::new(0),
+        });
+
+        let writer_thread = {
+            let shared = shared.clone();
+            thread::Builder::new()
+                .name("measureme-writer".to_string())
+                .spawn(move || run_writer(&shared, out))?
+        };
+
+        Ok(BufferedSerializationSink {
+            shared,
+            writer_thread: Some(writer_thread),
+        })
+    }
+}
+
+fn run_writer(shared: &Shared, mut out: Box<dyn Write + Send>) {
+    let mut pending = Vec::with_capacity(shared.capacity);
+    let mut checksum = Checksum::new();
+
+    loop {
+        let mut state = shared.state.lock();
+
+        let spill_pending = state.spill.as_ref().is_some_and(|spill| !spill.is_empty());
+
+        if !state.closed && !spill_pending {
+            if state.buffer.is_empty() {
+                shared.data_available.wait(&mut state);
+            } else if state.buffer.len() < shared.capacity / 2 {
+                // Give the buffer some time to fill up, so we don't do lots
+                // of tiny writes.
+                shared.data_available.wait_for(&mut state, FLUSH_INTERVAL);
+            }
+        }
+
+        if !state.buffer.is_empty() {
+            // Data in the buffer always predates data in the spill file,
+            // since nothing ends up in the buffer while spilling.
+            mem::swap(&mut state.buffer, &mut pending);
+        } else if spill_pending {
+            let spill = state.spill.as_mut().unwrap();
+            let result = spill.read_chunk(&mut pending, shared.capacity.max(1));
+            let spill_empty = spill.is_empty();
+
+            if let Err(e) = result {
+                // The rest of the spilled data is lost.
+                shared.record_error(&mut state, &e);
+                state.spill = None;
+            }
+
+            if state.spill.is_none() || spill_empty {
+                state.spilling = false;
+            }
+        } else if state.closed {
+            break;
+        } else {
+            continue;
+        }
+
+        let failed = state.error.is_some();
+        drop(state);
+        shared.space_available.notify_all();
+
+        // Once writing has failed, all further data is discarded.
+        if !failed {
+            checksum.update(&pending);
+
+            if let Err(e) = out.write_all(&pending) {
+                shared.record_error(&mut shared.state.lock(), &e);
+            }
+        }
+
+        pending.clear();
+    }
+
+    let failed = shared.state.lock().error.is_some();
+
+    if !failed {
+        let footer = file_footer(checksum.finish());
+
+        if let Err(e) = out.write_all(&footer).and_then(|()| out.flush()) {
+            shared.record_error(&mut shared.state.lock(), &e);
+        }
+    }
+}
+
+impl SerializationSink for BufferedSerializationSink {
+    fn from_path(path: &Path) -> Result<Self, Box<dyn Error>> {
+        fs::create_dir_all(path.parent().unwrap())?;
+
+        let file = fs::File::create(path)?;
+
+        Ok(BufferedSerializationSink::new(
+            Box::new(file),
+            DEFAULT_CAPACITY,
+            OverrunPolicy::Block,
+        )?)
+    }
+
+    fn from_config(
+        path: &Path,
+        file_kind: ProfileFileKind,
+        config: &ProfilerConfig,
+    ) -> Result<Self, Box<dyn Error>> {
+        fs::create_dir_all(path.parent().unwrap())?;
+
+        let file = fs::File::create(path)?;
+
+        let policy = match (config.overrun_policy, file_kind) {
+            (OverrunPolicy::DropEvents, ProfileFileKind::Events) => OverrunPolicy::DropEvents,
+            (OverrunPolicy::DropEvents, _) => OverrunPolicy::Block,
+            (policy, _) => policy,
+        };
+
+        Ok(BufferedSerializationSink::new(
+            Box::new(file),
+            config.buffer_capacity.unwrap_or(DEFAULT_CAPACITY),
+            policy,
+        )?)
+    }
+
+    fn write_atomic<W>(&self, num_bytes: usize, write: W) -> Addr
+    where
+        W: FnOnce(&mut [u8]),
+    {
+        let shared = &*self.shared;
+        let mut state = shared.state.lock();
+
+        let fits = |state: &State| {
+            !state.spilling
+                && (state.buffer.len() + num_bytes <= shared.capacity
+                // Writes larger than the whole buffer are let through once
+                // the buffer is empty.
+                || state.buffer.is_empty())
+        };
+
+        if !fits(&state) {
+            match shared.policy {
+                OverrunPolicy::Block => {
+                    while !fits(&state) {
+                        shared.data_available.notify_one();
+                        shared.space_available.wait(&mut state);
+                    }
+                }
+                OverrunPolicy::DropEvents => {
+                    shared.dropped_writes.fetch_add(1, Ordering::Relaxed);
+                    return Addr(state.addr);
+                }
+                OverrunPolicy::SpillToTempFile => {
+                    let curr_addr = state.addr;
+                    state.addr += num_bytes as u64;
+
+                    let mut bytes = vec![0; num_bytes];
+                    write(&mut bytes[..]);
+
+                    if state.spill.is_none() {
+                        match SpillFile::create() {
+                            Ok(spill) => state.spill = Some(spill),
+                            Err(e) => shared.record_error(&mut state, &e),
+                        }
+                    }
+
+                    let result = match state.spill.as_mut() {
+                        Some(spill) => spill.append(&bytes),
+                        None => Ok(()),
+                    };
+
+                    match result {
+                        Ok(()) => state.spilling = state.spill.is_some(),
+                        Err(e) => shared.record_error(&mut state, &e),
+                    }
+
+                    shared.data_available.notify_one();
+                    return Addr(curr_addr);
+                }
+            }
+        }
+
+        let curr_addr = state.addr;
+        state.addr += num_bytes as u64;
+
+        let buf_start = state.buffer.len();
+        state.buffer.resize(buf_start + num_bytes, 0);
+        write(&mut state.buffer[buf_start..]);
+
+        if buf_start == 0 || state.buffer.len() >= shared.capacity / 2 {
+            shared.data_available.notify_one();
+        }
+
+        Addr(curr_addr)
+    }
+
+    #[inline]
+    fn has_failed(&self) -> bool {
+        self.shared.failed.load(Ordering::Relaxed)
+    }
+
+    fn write_error(&self) -> Option<WriteError> {
+        self.shared.state.lock().error.clone()
+    }
+
+    fn dropped_writes(&self) -> u64 {
+        self.shared.dropped_writes.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for BufferedSerializationSink {
+    fn drop(&mut self) {
+        self.shared.state.lock().closed = true;
+        self.shared.data_available.notify_one();
+
+        if let Some(writer_thread) = self.writer_thread.take() {
+            let _ = writer_thread.join();
+        }
+
+        if let Some(ref error) = self.shared.state.lock().error {
+            Diagnostic::from_error(error).log();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file_header::verify_file_footer;
+    use std::sync::mpsc;
+
+    /// An output that only accepts writes when `release` is signalled, to
+    /// simulate slow I/O.
+    struct SlowOutput {
+        data: Arc<Mutex<Vec<u8>>>,
+        release: mpsc::Receiver<()>,
+    }
+
+    impl Write for SlowOutput {
+        fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+            let _ = self.release.recv();
+            self.data.lock().extend_from_slice(bytes);
+            Ok(bytes.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn slow_sink(
+        policy: OverrunPolicy,
+    ) -> (
+        BufferedSerializationSink,
+        Arc<Mutex<Vec<u8>>>,
+        mpsc::Sender<()>,
+    ) {
+        let data = Arc::new(Mutex::new(Vec::new()));
+        let (release, receiver) = mpsc::channel();
+        let out = SlowOutput {
+            data: data.clone(),
+            release: receiver,
+        };
+        let sink = BufferedSerializationSink::new(Box::new(out), 8, policy).unwrap();
+        (sink, data, release)
+    }
+
+    #[test]
+    fn drop_events() {
+        let (sink, data, release) = slow_sink(OverrunPolicy::DropEvents);
+
+        for i in 1..=20 {
+            sink.write_bytes_atomic(&[i]);
+        }
+
+        // The output doesn't accept anything yet, so at most the 8 bytes
+        // in the buffer and the 8 bytes the writer thread is holding on to
+        // can have been kept.
+        let dropped = sink.dropped_writes();
+        assert!(dropped >= 4);
+
+        // Dropping the sender lets all writes through.
+        drop(release);
+        drop(sink);
+
+        let data = data.lock();
+        let data = verify_file_footer(&data).unwrap();
+        assert_eq!(data.len() as u64, 20 - dropped);
+        assert!(data.windows(2).all(|w| w[0] < w[1]));
+    }
+
+    #[test]
+    fn spill_to_temp_file() {
+        let (sink, data, release) = slow_sink(OverrunPolicy::SpillToTempFile);
+
+        for i in 1..=100 {
+            sink.write_bytes_atomic(&[i]);
+        }
+
+        drop(release);
+        drop(sink);
+
+        let expected: Vec<u8> = (1..=100).collect();
+        assert_eq!(verify_file_footer(&data.lock()).unwrap(), &expected[..]);
+    }
+
+    #[test]
+    fn block() {
+        let (sink, data, release) = slow_sink(OverrunPolicy::Block);
+
+        // Let writes through from another thread, so that the blocked writer
+        // can make progress.
+        let releaser = thread::spawn(move || {
+            for _ in 0..100 {
+                if release.send(()).is_err() {
+                    break;
+                }
+                thread::sleep(Duration::from_millis(1));
+            }
+        });
+
+        for i in 1..=50 {
+            assert_eq!(sink.write_bytes_atomic(&[i]), Addr(i as u64 - 1));
+        }
+        // A write that is larger than the whole buffer.
+        sink.write_bytes_atomic(&[0xFF; 20]);
+
+        releaser.join().unwrap();
+        drop(sink);
+
+        let mut expected: Vec<u8> = (1..=50).collect();
+        expected.extend_from_slice(&[0xFF; 20]);
+        assert_eq!(verify_file_footer(&data.lock()).unwrap(), &expected[..]);
+    }
+}
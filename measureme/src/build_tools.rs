@@ -0,0 +1,224 @@
+//! Profiling for build scripts and proc macros, so that the time they take
+//! shows up next to the profiles rustc records with `-Z self-profile`.
+//!
+//! A [`BuildToolProfiler`] is only created if `MEASUREME_OUT_DIR` (see the
+//! `config` module) is set, so authors can leave the instrumentation in
+//! their code: without the variable it costs one environment lookup. The
+//! profiles are keyed by the crate the tool runs for, which is taken from the
+//! environment cargo sets up:
+//!
+//!   - Proc macros run inside of rustc, where `CARGO_CRATE_NAME` is the name
+//!     of the crate being compiled, i.e. the crate invoking the macro.
+//!   - Build scripts run before their package is compiled, with
+//!     `CARGO_PKG_NAME` and `OUT_DIR` set.
+//!
+//! The invoking crate and the kind of tool are recorded in the flags of the
+//! profile's tool info, see [`INVOKING_CRATE_FLAG`]. Profiles are named
+//! `<invoking crate>.<tool>.<n>-<pid>`, where `<n>` counts the profiles the
+//! process has created, so that a proc macro can create one profile per
+//! expansion. `analyzeme::merge_profiles()` combines them with the profile of
+//! the invoking crate.
+//!
+//! ```ignore
+//! #[proc_macro_derive(Serialize)]
+//! pub fn derive_serialize(input: TokenStream) -> TokenStream {
+//!     let profiler = BuildToolProfiler::from_env("serde_derive", env!("CARGO_PKG_VERSION"))
+//!         .ok()
+//!         .flatten();
+//!     let _timer = profiler.as_ref().map(|p| p.time("derive(Serialize)"));
+//!     expand(input)
+//! }
+//! ```
+
+#[cfg(any(not(target_arch = "wasm32"), target_os = "wasi"))]
+use crate::{
+    config::ProfilerConfig, EventIdBuilder, FileSerializationSink, Profiler, StringId, TimingGuard,
+    ToolInfo,
+};
+#[cfg(any(not(target_arch = "wasm32"), target_os = "wasi"))]
+use std::error::Error;
+#[cfg(any(not(target_arch = "wasm32"), target_os = "wasi"))]
+use std::sync::atomic::{AtomicU32, Ordering};
+
+/// The event kind of the events recorded by build scripts.
+pub const BUILD_SCRIPT_EVENT_KIND: &str = "BuildScript";
+/// The event kind of the events recorded by proc macros.
+pub const PROC_MACRO_EVENT_KIND: &str = "ProcMacro";
+
+/// The prefix of the tool info flag that names the invoking crate, like
+/// `invoking-crate=regex`.
+pub const INVOKING_CRATE_FLAG: &str = "invoking-crate=";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BuildToolKind {
+    BuildScript,
+    ProcMacro,
+}
+
+impl BuildToolKind {
+    pub fn event_kind(self) -> &'static str {
+        match self {
+            BuildToolKind::BuildScript => BUILD_SCRIPT_EVENT_KIND,
+            BuildToolKind::ProcMacro => PROC_MACRO_EVENT_KIND,
+        }
+    }
+
+    fn flag(self) -> &'static str {
+        match self {
+            BuildToolKind::BuildScript => "build-script",
+            BuildToolKind::ProcMacro => "proc-macro",
+        }
+    }
+}
+
+/// Determines the crate a build tool runs for from the environment variables
+/// cargo sets, see the module documentation. Returns `None` if the tool
+/// doesn't run under cargo.
+pub fn invoking_crate(var: impl Fn(&str) -> Option<String>) -> Option<(String, BuildToolKind)> {
+    if let Some(crate_name) = var("CARGO_CRATE_NAME").filter(|name| !name.is_empty()) {
+        return Some((crate_name, BuildToolKind::ProcMacro));
+    }
+
+    match (var("CARGO_PKG_NAME"), var("OUT_DIR")) {
+        (Some(package), Some(_)) if !package.is_empty() => {
+            Some((package.replace('-', "_"), BuildToolKind::BuildScript))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(any(not(target_arch = "wasm32"), target_os = "wasi"))]
+static NEXT_PROFILE: AtomicU32 = AtomicU32::new(0);
+
+/// A profile of one run of a build script or one expansion of a proc macro.
+/// The profile is complete once this is dropped.
+#[cfg(any(not(target_arch = "wasm32"), target_os = "wasi"))]
+pub struct BuildToolProfiler {
+    profiler: Profiler<FileSerializationSink>,
+    event_kind: StringId,
+    invoking_crate: String,
+    kind: BuildToolKind,
+}
+
+#[cfg(any(not(target_arch = "wasm32"), target_os = "wasi"))]
+impl BuildToolProfiler {
+    /// Creates a profiler for the tool with the given name and version, if
+    /// `MEASUREME_OUT_DIR` is set and the tool runs under cargo. Returns an
+    /// error if the `MEASUREME_*` variables are invalid or the profile can't
+    /// be created. The events are always written with a
+    /// `FileSerializationSink`, regardless of `MEASUREME_SINK`.
+    pub fn from_env(
+        name: &str,
+        version: &str,
+    ) -> Result<Option<BuildToolProfiler>, Box<dyn Error>> {
+        let config = ProfilerConfig::from_env()?;
+
+        let out_dir = match &config.out_dir {
+            Some(out_dir) => out_dir.clone(),
+            None => return Ok(None),
+        };
+
+        let (invoking_crate, kind) = match invoking_crate(|name| std::env::var(name).ok()) {
+            Some(invoking) => invoking,
+            None => return Ok(None),
+        };
+
+        if !config.is_event_kind_enabled(kind.event_kind()) {
+            return Ok(None);
+        }
+
+        let path_stem = out_dir.join(format!(
+            "{}.{}.{}-{}",
+            invoking_crate,
+            name,
+            NEXT_PROFILE.fetch_add(1, Ordering::Relaxed),
+            std::process::id()
+        ));
+        let profiler = Profiler::with_config(&path_stem, &config)?;
+
+        profiler.set_tool_info(ToolInfo {
+            name: name.to_string(),
+            version: version.to_string(),
+            git_sha: None,
+            flags: vec![
+                format!("{}{}", INVOKING_CRATE_FLAG, invoking_crate),
+                kind.flag().to_string(),
+            ],
+        });
+
+        Ok(Some(BuildToolProfiler {
+            event_kind: profiler.alloc_string(kind.event_kind()),
+            profiler,
+            invoking_crate,
+            kind,
+        }))
+    }
+
+    pub fn invoking_crate(&self) -> &str {
+        &self.invoking_crate
+    }
+
+    pub fn kind(&self) -> BuildToolKind {
+        self.kind
+    }
+
+    /// The underlying profiler, for recording other kinds of events.
+    pub fn profiler(&self) -> &Profiler<FileSerializationSink> {
+        &self.profiler
+    }
+
+    /// Records an interval with the given label, e.g. `derive(Serialize)`,
+    /// on the current thread until the returned guard is dropped.
+    pub fn time(&self, label: &str) -> TimingGuard<'_, FileSerializationSink> {
+        let event_id =
+            EventIdBuilder::new(&self.profiler).from_label(self.profiler.alloc_string(label));
+        let thread_id = self.profiler.register_current_thread();
+        self.profiler
+            .start_recording_interval_event(self.event_kind, event_id, thread_id)
+    }
+
+    /// Like `time()`, with an argument like the name of the item that is
+    /// being expanded.
+    pub fn time_with_arg(&self, label: &str, arg: &str) -> TimingGuard<'_, FileSerializationSink> {
+        let event_id = EventIdBuilder::new(&self.profiler).from_label_and_arg(
+            self.profiler.alloc_string(label),
+            self.profiler.alloc_string(arg),
+        );
+        let thread_id = self.profiler.register_current_thread();
+        self.profiler
+            .start_recording_interval_event(self.event_kind, event_id, thread_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars<'a>(vars: &'a [(&str, &str)]) -> impl Fn(&str) -> Option<String> + 'a {
+        move |name| {
+            vars.iter()
+                .find(|(n, _)| *n == name)
+                .map(|(_, value)| value.to_string())
+        }
+    }
+
+    #[test]
+    fn invoking_crates() {
+        assert_eq!(
+            invoking_crate(vars(&[
+                ("CARGO_CRATE_NAME", "regex"),
+                ("CARGO_PKG_NAME", "regex"),
+            ])),
+            Some(("regex".to_string(), BuildToolKind::ProcMacro))
+        );
+        assert_eq!(
+            invoking_crate(vars(&[
+                ("CARGO_PKG_NAME", "regex-syntax"),
+                ("OUT_DIR", "target/debug/build/regex-syntax-1234/out"),
+            ])),
+            Some(("regex_syntax".to_string(), BuildToolKind::BuildScript))
+        );
+        assert_eq!(invoking_crate(vars(&[("CARGO_PKG_NAME", "regex")])), None);
+        assert_eq!(invoking_crate(vars(&[])), None);
+    }
+}
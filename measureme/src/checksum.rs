@@ -0,0 +1,183 @@
+//! A streaming implementation of the 64-bit xxHash algorithm (XXH64), which
+//! is used for the checksums in the file footer (see the `file_header`
+//! module). It is fast enough to be computed while the profile is written.
+
+use byteorder::{ByteOrder, LittleEndian};
+
+const PRIME_1: u64 = 0x9E37_79B1_85EB_CA87;
+const PRIME_2: u64 = 0xC2B2_AE3D_27D4_EB4F;
+const PRIME_3: u64 = 0x1656_67B1_9E37_79F9;
+const PRIME_4: u64 = 0x85EB_CA77_C2B2_AE63;
+const PRIME_5: u64 = 0x27D4_EB2F_1656_67C5;
+
+const STRIPE_SIZE: usize = 32;
+
+#[derive(Clone, Debug)]
+pub struct Checksum {
+    accumulators: [u64; 4],
+    // Input that doesn't fill a whole stripe yet.
+    buffer: [u8; STRIPE_SIZE],
+    buffer_len: usize,
+    total_len: u64,
+}
+
+#[inline]
+fn round(acc: u64, input: u64) -> u64 {
+    acc.wrapping_add(input.wrapping_mul(PRIME_2))
+        .rotate_left(31)
+        .wrapping_mul(PRIME_1)
+}
+
+#[inline]
+fn merge_accumulator(hash: u64, acc: u64) -> u64 {
+    (hash ^ round(0, acc))
+        .wrapping_mul(PRIME_1)
+        .wrapping_add(PRIME_4)
+}
+
+impl Checksum {
+    pub fn new() -> Checksum {
+        Checksum {
+            accumulators: [
+                PRIME_1.wrapping_add(PRIME_2),
+                PRIME_2,
+                0,
+                0u64.wrapping_sub(PRIME_1),
+            ],
+            buffer: [0; STRIPE_SIZE],
+            buffer_len: 0,
+            total_len: 0,
+        }
+    }
+
+    pub fn update(&mut self, mut bytes: &[u8]) {
+        self.total_len += bytes.len() as u64;
+
+        if self.buffer_len > 0 {
+            let n = bytes.len().min(STRIPE_SIZE - self.buffer_len);
+            self.buffer[self.buffer_len..self.buffer_len + n].copy_from_slice(&bytes[..n]);
+            self.buffer_len += n;
+            bytes = &bytes[n..];
+
+            if self.buffer_len < STRIPE_SIZE {
+                return;
+            }
+
+            let stripe = self.buffer;
+            self.process_stripe(&stripe);
+            self.buffer_len = 0;
+        }
+
+        let mut stripes = bytes.chunks_exact(STRIPE_SIZE);
+        for stripe in &mut stripes {
+            self.process_stripe(stripe);
+        }
+
+        let rest = stripes.remainder();
+        self.buffer[..rest.len()].copy_from_slice(rest);
+        self.buffer_len = rest.len();
+    }
+
+    #[inline]
+    fn process_stripe(&mut self, stripe: &[u8]) {
+        for (i, acc) in self.accumulators.iter_mut().enumerate() {
+            *acc = round(*acc, LittleEndian::read_u64(&stripe[i * 8..]));
+        }
+    }
+
+    pub fn finish(&self) -> u64 {
+        let [v1, v2, v3, v4] = self.accumulators;
+
+        let mut hash = if self.total_len >= STRIPE_SIZE as u64 {
+            let hash = v1
+                .rotate_left(1)
+                .wrapping_add(v2.rotate_left(7))
+                .wrapping_add(v3.rotate_left(12))
+                .wrapping_add(v4.rotate_left(18));
+
+            self.accumulators
+                .iter()
+                .fold(hash, |hash, &acc| merge_accumulator(hash, acc))
+        } else {
+            // `v3` is the seed.
+            v3.wrapping_add(PRIME_5)
+        };
+
+        hash = hash.wrapping_add(self.total_len);
+
+        let mut rest = &self.buffer[..self.buffer_len];
+
+        while rest.len() >= 8 {
+            hash ^= round(0, LittleEndian::read_u64(rest));
+            hash = hash
+                .rotate_left(27)
+                .wrapping_mul(PRIME_1)
+                .wrapping_add(PRIME_4);
+            rest = &rest[8..];
+        }
+
+        if rest.len() >= 4 {
+            hash ^= (LittleEndian::read_u32(rest) as u64).wrapping_mul(PRIME_1);
+            hash = hash
+                .rotate_left(23)
+                .wrapping_mul(PRIME_2)
+                .wrapping_add(PRIME_3);
+            rest = &rest[4..];
+        }
+
+        for &byte in rest {
+            hash ^= (byte as u64).wrapping_mul(PRIME_5);
+            hash = hash.rotate_left(11).wrapping_mul(PRIME_1);
+        }
+
+        hash ^= hash >> 33;
+        hash = hash.wrapping_mul(PRIME_2);
+        hash ^= hash >> 29;
+        hash = hash.wrapping_mul(PRIME_3);
+        hash ^= hash >> 32;
+        hash
+    }
+}
+
+impl Default for Checksum {
+    fn default() -> Checksum {
+        Checksum::new()
+    }
+}
+
+/// Computes the checksum of `bytes` in one go.
+pub fn checksum(bytes: &[u8]) -> u64 {
+    let mut checksum = Checksum::new();
+    checksum.update(bytes);
+    checksum.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn known_values() {
+        assert_eq!(checksum(b""), 0xEF46_DB37_51D8_E999);
+        assert_eq!(checksum(b"a"), 0xD24E_C4F1_A98C_6E5B);
+        assert_eq!(checksum(b"abc"), 0x44BC_2CF5_AD77_0999);
+        assert_eq!(
+            checksum(b"Nobody inspects the spammish repetition"),
+            0xFBCE_A83C_8A37_8BF1
+        );
+    }
+
+    #[test]
+    fn streaming() {
+        let data: Vec<u8> = (0..1000u32).map(|i| (i * 7 % 251) as u8).collect();
+        let expected = checksum(&data);
+
+        for &chunk_size in &[1, 3, 8, 31, 32, 33, 100] {
+            let mut checksum = Checksum::new();
+            for chunk in data.chunks(chunk_size) {
+                checksum.update(chunk);
+            }
+            assert_eq!(checksum.finish(), expected, "chunk size {}", chunk_size);
+        }
+    }
+}
@@ -0,0 +1,432 @@
+//! A standard set of environment variables that applications embedding
+//! `measureme` can opt into, so that end users can control profiling without
+//! every application having to invent its own flags:
+//!
+//!   - `MEASUREME_OUT_DIR`: the directory the profile files are written to.
+//!     Defaults to the directory the application chose.
+//!   - `MEASUREME_EVENT_FILTER`: a comma-separated list of event kinds that
+//!     should be recorded, e.g. `Query,GenericActivity`. All event kinds are
+//!     recorded if this is not set.
+//!   - `MEASUREME_SINK`: the `SerializationSink` to use, either `file`,
+//!     `mmap` or `buffered`. Defaults to `file`.
+//!   - `MEASUREME_CLOCK`: the clock used for event timestamps, either
+//!     `monotonic`, `wall` or `logical`. Defaults to `monotonic`.
+//!   - `MEASUREME_CONTROL_FILE`: a file for switching registered event kinds
+//!     on and off while the process runs, see the `control` module.
+//!
+//! Use `ProfilerConfig::from_env()` to read the variables and
+//! `Profiler::with_config()` to create a profiler from the result. Since the
+//! sink is a type parameter of the `Profiler`, the embedder has to pick the
+//! sink type according to `ProfilerConfig::sink`. Likewise, `measureme` does
+//! not filter events by itself: the embedder is expected to check
+//! `ProfilerConfig::is_event_kind_enabled()` before recording events of a
+//! given kind, which keeps the check out of the hot path.
+
+use crate::raw_event::{TimestampFormat, TimestampResolution};
+use crate::string_cache::DedupPolicy;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+pub const OUT_DIR_VAR: &str = "MEASUREME_OUT_DIR";
+pub const EVENT_FILTER_VAR: &str = "MEASUREME_EVENT_FILTER";
+pub const SINK_VAR: &str = "MEASUREME_SINK";
+pub const CLOCK_VAR: &str = "MEASUREME_CLOCK";
+pub const CONTROL_FILE_VAR: &str = "MEASUREME_CONTROL_FILE";
+
+/// The `SerializationSink` implementation requested via `MEASUREME_SINK`.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum SinkKind {
+    /// `FileSerializationSink`
+    #[default]
+    File,
+    /// `MmapSerializationSink`
+    Mmap,
+    /// `BufferedSerializationSink`
+    Buffered,
+}
+
+/// The clock the `Profiler` takes event timestamps from.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Clock {
+    /// A monotonic clock (`std::time::Instant`). Timestamps never go
+    /// backwards, which is what analysis tools expect.
+    #[default]
+    Monotonic,
+
+    /// The system's wall clock (`std::time::SystemTime`). This makes it
+    /// easier to correlate events with timestamps from other sources, but
+    /// adjustments of the system time show up in the profile. Timestamps are
+    /// clamped so that they never lie before the start of the profile, and
+    /// while the system time is set back, they stay at the latest timestamp
+    /// taken so far.
+    Wall,
+
+    /// A counter that advances by one nanosecond whenever the profiler
+    /// takes a timestamp. The durations are meaningless, but the same
+    /// sequence of calls always produces the same timestamps, see
+    /// `ProfilerConfig::deterministic`.
+    Logical,
+}
+
+/// Determines what the `Profiler` does when one of its sinks fails to write
+/// data, e.g. because the disk is full.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum WriteFailurePolicy {
+    /// Stop recording events and mark the profile as truncated in its
+    /// metadata. The application keeps running and can find out about the
+    /// failure via `Profiler::health()`.
+    #[default]
+    StopRecording,
+
+    /// Panic as soon as the failure is noticed.
+    Panic,
+}
+
+/// A hard cap on how much the `Profiler` records, see the `budget` module.
+/// The default is unlimited.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct RecordingBudget {
+    /// The maximum number of events to record.
+    pub max_events: Option<u64>,
+    /// The maximum number of bytes to write to the files of the profile,
+    /// including its string table.
+    pub max_bytes: Option<u64>,
+    pub when_exhausted: BudgetPolicy,
+}
+
+/// Determines what the `Profiler` does once its `RecordingBudget` is
+/// exhausted. Either way, the profile is marked in its metadata.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum BudgetPolicy {
+    /// Stop recording events.
+    #[default]
+    StopRecording,
+
+    /// Stop writing events, but keep counting them in the running totals
+    /// of the `.summary` file, like `RecordingMode::AggregateOnly`. This
+    /// enables `ProfilerConfig::summary`.
+    AggregateOnly,
+}
+
+/// Determines what a `BufferedSerializationSink` does when its buffer is full
+/// because the data cannot be written out as fast as it is recorded.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum OverrunPolicy {
+    /// Wait until there is space in the buffer again.
+    #[default]
+    Block,
+
+    /// Discard events that don't fit into the buffer. The number of dropped
+    /// events is stored in the profile's metadata. String table data is
+    /// never dropped, since that would corrupt the profile, so the sinks for
+    /// the string table block instead.
+    DropEvents,
+
+    /// Write data that doesn't fit into the buffer to a temporary file
+    /// (usually on a local disk) and copy it to the actual output later.
+    SpillToTempFile,
+}
+
+/// Determines what the `Profiler` writes to disk.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum RecordingMode {
+    /// Write every event to the event stream.
+    #[default]
+    Full,
+
+    /// Don't write any events, only the string table and the running totals
+    /// per event kind and label in the `.summary` file (see the `summary`
+    /// module). This is much cheaper in I/O when nobody is going to look at
+    /// the timeline of the profile. The `.events` file only consists of its
+    /// header, so tools that read events see an empty profile.
+    AggregateOnly,
+}
+
+/// How the `Profiler` distributes the events of different threads over files.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum EventLayout {
+    /// All threads write their events to the `.events` file.
+    #[default]
+    SingleFile,
+
+    /// Every thread writes its events to a file of its own, so that threads
+    /// never wait for each other while recording events. See the
+    /// `thread_event_files` module.
+    PerThread,
+
+    /// All threads write their events to a sequence of segment files, a new
+    /// one of which is started every so often, so that long recordings can
+    /// be processed and pruned piecemeal. See the `event_segments` module.
+    Rollover(Rollover),
+}
+
+/// When `EventLayout::Rollover` starts a new segment. At least one of the
+/// limits has to be set.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Rollover {
+    /// Start a new segment once the current one is this old.
+    pub interval: Option<Duration>,
+    /// Start a new segment once this many bytes of events have been written
+    /// to the current one.
+    pub max_bytes: Option<u64>,
+}
+
+/// How a `FileSerializationSink` writes its file. The defaults suit local
+/// disks. On network filesystems, a larger buffer and preallocation avoid
+/// many small writes and frequent growing of the file; on tmpfs, a smaller
+/// buffer saves memory without costing much.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct FileSinkConfig {
+    /// The size of the write buffer in bytes.
+    pub buffer_size: usize,
+    /// Write the buffer to the file as soon as it holds at least this many
+    /// bytes instead of waiting until it is full, which spreads the writes
+    /// more evenly and loses less data if the process crashes. `None` only
+    /// writes full buffers.
+    pub write_behind_threshold: Option<usize>,
+    /// Reserve disk space for the file in steps of this many bytes ahead of
+    /// what has been written, via `fallocate()`, so the filesystem doesn't
+    /// have to grow the file with every write. Space that hasn't been used is
+    /// released when the sink is dropped. Zero disables preallocation, which
+    /// is only supported on Linux and silently turned off on filesystems
+    /// that don't support it.
+    pub preallocate: u64,
+}
+
+impl FileSinkConfig {
+    pub const DEFAULT_BUFFER_SIZE: usize = 512 * 1024;
+}
+
+impl Default for FileSinkConfig {
+    fn default() -> FileSinkConfig {
+        FileSinkConfig {
+            buffer_size: FileSinkConfig::DEFAULT_BUFFER_SIZE,
+            write_behind_threshold: None,
+            preallocate: 0,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ProfilerConfig {
+    pub out_dir: Option<PathBuf>,
+    /// The event kinds to record, or `None` for recording all event kinds.
+    pub event_filter: Option<Vec<String>>,
+    pub sink: SinkKind,
+    pub clock: Clock,
+    /// This and the buffer settings below are not configurable via
+    /// environment variables since the right choice depends on the
+    /// application, not on the user.
+    pub write_failure_policy: WriteFailurePolicy,
+    /// The buffer size in bytes of each `BufferedSerializationSink`, or `None`
+    /// for the default size.
+    pub buffer_capacity: Option<usize>,
+    /// What a `BufferedSerializationSink` does when its buffer is full.
+    pub overrun_policy: OverrunPolicy,
+    /// The buffering and preallocation of each `FileSerializationSink`.
+    pub file_sink: FileSinkConfig,
+    /// How event timestamps are stored. Like the settings above, this is up
+    /// to the application: services that run for more than a day should use
+    /// `TimestampFormat::Wide`, or `TimestampFormat::CompactWithoutIntegers`
+    /// if they run for less than three days and don't record integer events.
+    pub timestamp_format: TimestampFormat,
+    /// The unit the timestamps are stored in. Ignored with `Clock::Logical`,
+    /// whose timestamps are counters rather than times.
+    pub timestamp_resolution: TimestampResolution,
+    /// Whether to keep running totals per event kind and label and write
+    /// them to a `.summary` file next to the profile, see the `summary`
+    /// module.
+    /// Always enabled with `RecordingMode::AggregateOnly`.
+    pub summary: bool,
+    pub recording_mode: RecordingMode,
+    /// Only supported by `Profiler::with_config()`, since the profiler has
+    /// to create the sinks for the threads itself. Profilers created via
+    /// `Profiler::with_sinks()` write all events to the given events sink.
+    pub event_layout: EventLayout,
+    /// Whether to record the CPU each event started on, for analyzing
+    /// threads migrating between cores. This is only supported on Linux and
+    /// with `TimestampFormat::Wide`, whose events have room for it, and is
+    /// ignored otherwise.
+    pub record_cpu: bool,
+    /// Whether to leave everything out of the profile that differs between
+    /// runs: the start time, process id and command line in the metadata
+    /// are zero or empty, the system isn't recorded, the session id in the file headers is
+    /// `DETERMINISTIC_SESSION_ID` and CPUs aren't recorded. Together with
+    /// `Clock::Logical` and `ThreadIdScheme::Sequential`, the same sequence
+    /// of calls on a single thread produces byte-for-byte identical files,
+    /// so profiles can be compared and cached by their contents.
+    pub deterministic: bool,
+    /// The file the profiler reads to switch registered event kinds on and
+    /// off while it runs, see the `control` module.
+    pub control_file: Option<PathBuf>,
+    /// The number of strings `Profiler::intern_string()` and a
+    /// `SharedStringCache` keep in memory for deduplication, or `None` for
+    /// keeping all of them. Capping it lets processes that record
+    /// indefinitely do so in bounded memory, at the cost of writing the
+    /// strings that have been evicted again, see the `string_cache` module.
+    pub string_cache_capacity: Option<usize>,
+    /// Which strings `Profiler::intern_string()` and a `SharedStringCache`
+    /// deduplicate at all. Deduplicating short strings may cost more time
+    /// than writing them again, see `Profiler::string_cache_stats()`.
+    pub string_dedup_policy: DedupPolicy,
+    /// A cap on the number of events or bytes the profiler records, see the
+    /// `budget` module.
+    pub budget: RecordingBudget,
+}
+
+impl ProfilerConfig {
+    /// Reads the configuration from the `MEASUREME_*` environment variables.
+    /// Variables that are not set keep their default value. Returns an error
+    /// if a variable is set to a value that is not understood.
+    pub fn from_env() -> Result<ProfilerConfig, Box<dyn Error>> {
+        ProfilerConfig::from_vars(|name| {
+            std::env::var_os(name).map(|value| value.to_string_lossy().into_owned())
+        })
+    }
+
+    fn from_vars(var: impl Fn(&str) -> Option<String>) -> Result<ProfilerConfig, Box<dyn Error>> {
+        let mut config = ProfilerConfig::default();
+
+        if let Some(out_dir) = var(OUT_DIR_VAR) {
+            if !out_dir.is_empty() {
+                config.out_dir = Some(PathBuf::from(out_dir));
+            }
+        }
+
+        if let Some(event_filter) = var(EVENT_FILTER_VAR) {
+            config.event_filter = Some(
+                event_filter
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|kind| !kind.is_empty())
+                    .map(String::from)
+                    .collect(),
+            );
+        }
+
+        if let Some(control_file) = var(CONTROL_FILE_VAR) {
+            if !control_file.is_empty() {
+                config.control_file = Some(PathBuf::from(control_file));
+            }
+        }
+
+        if let Some(sink) = var(SINK_VAR) {
+            config.sink = match sink.trim() {
+                "file" => SinkKind::File,
+                "mmap" => SinkKind::Mmap,
+                "buffered" => SinkKind::Buffered,
+                other => {
+                    return Err(format!(
+                        "invalid value `{}` for {}, expected `file`, `mmap` or `buffered`",
+                        other, SINK_VAR
+                    )
+                    .into())
+                }
+            };
+        }
+
+        if let Some(clock) = var(CLOCK_VAR) {
+            config.clock = match clock.trim() {
+                "monotonic" => Clock::Monotonic,
+                "wall" => Clock::Wall,
+                "logical" => Clock::Logical,
+                other => {
+                    return Err(format!(
+                        "invalid value `{}` for {}, expected `monotonic`, `wall` or `logical`",
+                        other, CLOCK_VAR
+                    )
+                    .into())
+                }
+            };
+        }
+
+        Ok(config)
+    }
+
+    /// Returns the path stem the profile files should be written to: the
+    /// given `path_stem` if no output directory has been configured, or the
+    /// file name of `path_stem` within the output directory otherwise.
+    pub fn path_stem(&self, path_stem: &Path) -> PathBuf {
+        match (&self.out_dir, path_stem.file_name()) {
+            (Some(out_dir), Some(file_name)) => out_dir.join(file_name),
+            _ => path_stem.to_path_buf(),
+        }
+    }
+
+    /// Returns `true` if the profiler should keep running totals per label,
+    /// see `summary`.
+    pub fn is_summary_enabled(&self) -> bool {
+        self.summary
+            || self.recording_mode == RecordingMode::AggregateOnly
+            || self.budget.when_exhausted == BudgetPolicy::AggregateOnly
+                && (self.budget.max_events.is_some() || self.budget.max_bytes.is_some())
+    }
+
+    /// Returns `true` if events of the given kind should be recorded.
+    pub fn is_event_kind_enabled(&self, event_kind: &str) -> bool {
+        match self.event_filter {
+            Some(ref kinds) => kinds.iter().any(|kind| kind == event_kind),
+            None => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config_from(vars: &[(&str, &str)]) -> Result<ProfilerConfig, Box<dyn Error>> {
+        ProfilerConfig::from_vars(|name| {
+            vars.iter()
+                .find(|&&(var, _)| var == name)
+                .map(|&(_, value)| value.to_string())
+        })
+    }
+
+    #[test]
+    fn defaults() {
+        let config = config_from(&[]).unwrap();
+
+        assert_eq!(config, ProfilerConfig::default());
+        assert_eq!(config.path_stem(Path::new("a/b")), Path::new("a/b"));
+        assert!(config.is_event_kind_enabled("Query"));
+    }
+
+    #[test]
+    fn all_vars() {
+        let config = config_from(&[
+            (OUT_DIR_VAR, "/tmp/profiles"),
+            (EVENT_FILTER_VAR, "Query, GenericActivity,,"),
+            (SINK_VAR, "mmap"),
+            (CLOCK_VAR, "wall"),
+            (CONTROL_FILE_VAR, "/tmp/profiles/control"),
+        ])
+        .unwrap();
+
+        assert_eq!(
+            config.control_file.as_deref(),
+            Some(Path::new("/tmp/profiles/control"))
+        );
+
+        assert_eq!(config.sink, SinkKind::Mmap);
+        assert_eq!(config.clock, Clock::Wall);
+        assert_eq!(
+            config.path_stem(Path::new("a/b")),
+            Path::new("/tmp/profiles/b")
+        );
+        assert!(config.is_event_kind_enabled("Query"));
+        assert!(config.is_event_kind_enabled("GenericActivity"));
+        assert!(!config.is_event_kind_enabled("QueryCacheHit"));
+    }
+
+    #[test]
+    fn invalid_values() {
+        assert!(config_from(&[(SINK_VAR, "tcp")]).is_err());
+        assert!(config_from(&[(CLOCK_VAR, "tsc")]).is_err());
+        assert_eq!(
+            config_from(&[(CLOCK_VAR, "logical")]).unwrap().clock,
+            Clock::Logical
+        );
+    }
+}
@@ -0,0 +1,210 @@
+//! Switching event kinds on and off while the process runs, e.g. for only
+//! recording expensive, verbose events during the interesting part of a long
+//! build or of a server's lifetime. The profiler reads the switches from a
+//! control file, given via `ProfilerConfig::control_file` or the
+//! `MEASUREME_CONTROL_FILE` environment variable, which has one event kind
+//! per line, followed by `on` or `off`:
+//!
+//! ```text
+//! # Only needed while investigating the cache.
+//! QueryCacheHit off
+//! IncrementalResultHashing on
+//! ```
+//!
+//! Kinds that aren't listed are recorded, as are all kinds while the file
+//! doesn't exist. Lines that don't follow the format are ignored. Only kinds
+//! registered via `Profiler::register_event_kind()` can be switched: other
+//! event kinds are plain strings, which the profiler can't tell apart by
+//! name.
+//!
+//! The profiler checks whether the file has changed at most once per
+//! `POLL_INTERVAL`, on whichever thread records the next event, so changes
+//! take effect shortly after the file has been written. Profilers without a
+//! control file don't pay for any of this.
+
+use crate::stringtable::{StringId, FIRST_EVENT_KIND_ID, MAX_EVENT_KIND_ID};
+use crate::sync::Mutex;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime};
+
+/// How often the profiler checks whether the control file has changed.
+pub const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+const EVENT_KIND_COUNT: usize = (MAX_EVENT_KIND_ID - FIRST_EVENT_KIND_ID + 1) as usize;
+
+/// Parses the contents of a control file into pairs of event kind and
+/// whether it is switched on, in the order of the lines.
+pub fn parse_control_file(text: &str) -> Vec<(String, bool)> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| {
+            let mut words = line.split_whitespace();
+            let kind = words.next()?;
+            let on = match words.next()? {
+                "on" => true,
+                "off" => false,
+                _ => return None,
+            };
+
+            match words.next() {
+                Some(_) => None,
+                None => Some((kind.to_string(), on)),
+            }
+        })
+        .collect()
+}
+
+pub(crate) struct EventKindControl {
+    path: PathBuf,
+    start: Instant,
+    // The time of the next check, in nanoseconds since `start`.
+    next_poll: AtomicU64,
+    // Bit `i % 64` of word `i / 64` is set if the registered event kind with
+    // index `i` is switched off.
+    disabled: Box<[AtomicU64]>,
+    state: Mutex<ControlState>,
+}
+
+#[derive(Default)]
+struct ControlState {
+    // The modification time and size of the file when it was last read,
+    // `None` if it didn't exist.
+    version: Option<(SystemTime, u64)>,
+    switches: Vec<(String, bool)>,
+}
+
+impl EventKindControl {
+    pub(crate) fn new(path: &Path) -> EventKindControl {
+        let control = EventKindControl {
+            path: path.to_path_buf(),
+            start: Instant::now(),
+            next_poll: AtomicU64::new(0),
+            disabled: (0..EVENT_KIND_COUNT / 64)
+                .map(|_| AtomicU64::new(0))
+                .collect(),
+            state: Mutex::new(ControlState::default()),
+        };
+
+        // Read the file right away, so that kinds that are switched off from
+        // the start are never recorded.
+        control.state.lock().switches = control.read();
+        control
+    }
+
+    #[inline]
+    pub(crate) fn is_disabled(&self, event_kind: StringId) -> bool {
+        match event_kind.event_kind_index() {
+            Some(index) => {
+                let index = index as usize;
+                self.disabled[index / 64].load(Ordering::Relaxed) & (1 << (index % 64)) != 0
+            }
+            None => false,
+        }
+    }
+
+    /// Rereads the control file if `POLL_INTERVAL` has passed and the file has
+    /// changed since it was last read. `event_kinds` are the kinds registered
+    /// with the profiler, indexed by `StringId::event_kind_index()`.
+    #[inline]
+    pub(crate) fn poll(&self, event_kinds: &Mutex<Vec<(String, StringId)>>) {
+        let now = self.start.elapsed().as_nanos() as u64;
+        let next_poll = self.next_poll.load(Ordering::Relaxed);
+
+        // Only one thread gets to check.
+        if now < next_poll
+            || self
+                .next_poll
+                .compare_exchange(
+                    next_poll,
+                    now + POLL_INTERVAL.as_nanos() as u64,
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                )
+                .is_err()
+        {
+            return;
+        }
+
+        self.reread(event_kinds);
+    }
+
+    #[cold]
+    fn reread(&self, event_kinds: &Mutex<Vec<(String, StringId)>>) {
+        let mut state = self.state.lock();
+
+        let version = self.version();
+        if version == state.version {
+            return;
+        }
+
+        state.version = version;
+        state.switches = self.read();
+        self.apply(&state.switches, &event_kinds.lock());
+    }
+
+    /// Applies the switches to newly registered event kinds.
+    pub(crate) fn event_kinds_changed(&self, event_kinds: &Mutex<Vec<(String, StringId)>>) {
+        // Lock in the same order as `reread()`.
+        let state = self.state.lock();
+        self.apply(&state.switches, &event_kinds.lock());
+    }
+
+    fn version(&self) -> Option<(SystemTime, u64)> {
+        let metadata = fs::metadata(&self.path).ok()?;
+        Some((metadata.modified().ok()?, metadata.len()))
+    }
+
+    fn read(&self) -> Vec<(String, bool)> {
+        fs::read_to_string(&self.path)
+            .map(|text| parse_control_file(&text))
+            .unwrap_or_default()
+    }
+
+    fn apply(&self, switches: &[(String, bool)], event_kinds: &[(String, StringId)]) {
+        for (index, (name, _)) in event_kinds.iter().enumerate() {
+            // The last line for a kind wins.
+            let on = switches
+                .iter()
+                .rev()
+                .find(|(kind, _)| kind == name)
+                .is_none_or(|&(_, on)| on);
+
+            let word = &self.disabled[index / 64];
+            let bit = 1 << (index % 64);
+            if on {
+                word.fetch_and(!bit, Ordering::Relaxed);
+            } else {
+                word.fetch_or(bit, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let switches = parse_control_file(
+            "# comment\n\
+             \n\
+             QueryCacheHit off\n\
+             \tIncrementalResultHashing   on  \n\
+             Query maybe\n\
+             Query off please\n\
+             GenericActivity\n",
+        );
+
+        assert_eq!(
+            switches,
+            vec![
+                ("QueryCacheHit".to_string(), false),
+                ("IncrementalResultHashing".to_string(), true),
+            ]
+        );
+    }
+}
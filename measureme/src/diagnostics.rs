@@ -0,0 +1,354 @@
+//! The errors and warnings of the crates of this repository, shared by the
+//! writing side (`measureme`) and the reading side (`analyzeme` and the
+//! tools), so that both report problems with the same codes and in the same
+//! format.
+//!
+//! Errors that occur while reading a profile carry a `LoadErrorKind`, so
+//! that tools can report them in a machine-readable form: with
+//! `--message-format json`, the tools print each error and warning as a
+//! single line of JSON to stderr, e.g.
+//!
+//! ```json
+//! {"level":"error","code":"file-missing","message":"couldn't read events file `foo.events`: ..."}
+//! ```
+//!
+//! The `code` is one of the codes of `LoadErrorKind::code()`, `write-failed`
+//! for a `WriteError` of a sink, `error` for other errors, or one of the
+//! codes of the warnings the tools emit, like `profile-truncated`. The
+//! functions of the `file_header` module return `LoadError`s too, so a
+//! reader that checks headers and footers with them reports the same kinds
+//! as `analyzeme`.
+//!
+//! Besides errors and warnings, the tools and `analyzeme` report what they
+//! do as `info` messages, e.g. `profile-loaded` with the number of events of
+//! each profile that has been loaded. These are only printed with
+//! `--verbose`, while `--quiet` leaves out the warnings as well. With
+//! `--log <file>`, all messages are additionally appended to the file as
+//! JSON, whatever the verbosity, so that runs in automated pipelines can be
+//! looked into afterwards. The tools call `init_logging()` with these flags,
+//! and the sinks of an embedder that doesn't print their write errors as
+//! text to stderr.
+
+use crate::profiler::json_string;
+use crate::serialization::WriteError;
+use std::error::Error;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{Mutex, OnceLock, PoisonError};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LoadErrorKind {
+    /// One of the files of the profile does not exist.
+    FileMissing,
+    /// One of the files exists but could not be read.
+    FileUnreadable,
+    /// One of the files doesn't start with the expected file magic.
+    NotAProfile,
+    /// The profile is encrypted and no (or the wrong) cipher was given.
+    Encrypted,
+    /// The profile has been written by a newer version of `measureme`.
+    FormatTooNew,
+    /// The profile has been written by an older version of `measureme`.
+    FormatTooOld,
+    /// One of the files is incomplete, e.g. because the process recording
+    /// the profile crashed.
+    Truncated,
+    /// One of the files is complete, but its contents are damaged.
+    Corrupt,
+    /// The files have been written by different profiling sessions, e.g.
+    /// because the files of different runs have been mixed up.
+    Mismatched,
+}
+
+impl LoadErrorKind {
+    pub fn code(self) -> &'static str {
+        match self {
+            LoadErrorKind::FileMissing => "file-missing",
+            LoadErrorKind::FileUnreadable => "file-unreadable",
+            LoadErrorKind::NotAProfile => "not-a-profile",
+            LoadErrorKind::Encrypted => "encrypted",
+            LoadErrorKind::FormatTooNew => "format-too-new",
+            LoadErrorKind::FormatTooOld => "format-too-old",
+            LoadErrorKind::Truncated => "profile-truncated",
+            LoadErrorKind::Corrupt => "profile-corrupt",
+            LoadErrorKind::Mismatched => "profile-mismatched",
+        }
+    }
+}
+
+/// The error returned when a profile can't be loaded, e.g. by
+/// `analyzeme::ProfilingData::new()` and the functions of the `file_header`
+/// module.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LoadError {
+    pub kind: LoadErrorKind,
+    pub message: String,
+}
+
+impl LoadError {
+    pub fn new(kind: LoadErrorKind, message: impl Into<String>) -> LoadError {
+        LoadError {
+            kind,
+            message: message.into(),
+        }
+    }
+
+    /// Returns the `LoadErrorKind` of `error` if it is a `LoadError`.
+    pub fn kind_of(error: &(dyn Error + 'static)) -> Option<LoadErrorKind> {
+        error.downcast_ref::<LoadError>().map(|e| e.kind)
+    }
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl Error for LoadError {}
+
+/// How tools print errors and warnings, selected via `--message-format`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MessageFormat {
+    #[default]
+    Human,
+    Json,
+}
+
+impl FromStr for MessageFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<MessageFormat, String> {
+        match s {
+            "human" => Ok(MessageFormat::Human),
+            "json" => Ok(MessageFormat::Json),
+            other => Err(format!(
+                "invalid message format `{}`, expected `human` or `json`",
+                other
+            )),
+        }
+    }
+}
+
+/// Which messages tools print to stderr, selected via `--quiet` and
+/// `--verbose`. Errors are always printed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Verbosity {
+    Quiet,
+    #[default]
+    Normal,
+    Verbose,
+}
+
+impl Verbosity {
+    /// The verbosity for the `--quiet` and `--verbose` flags. `--quiet`
+    /// wins if both are given.
+    pub fn from_flags(quiet: bool, verbose: bool) -> Verbosity {
+        if quiet {
+            Verbosity::Quiet
+        } else if verbose {
+            Verbosity::Verbose
+        } else {
+            Verbosity::Normal
+        }
+    }
+
+    fn prints(self, level: Level) -> bool {
+        match level {
+            Level::Error => true,
+            Level::Warning => self >= Verbosity::Normal,
+            Level::Info => self >= Verbosity::Verbose,
+        }
+    }
+}
+
+struct Logger {
+    verbosity: Verbosity,
+    message_format: MessageFormat,
+    log_file: Option<Mutex<File>>,
+}
+
+static LOGGER: OnceLock<Logger> = OnceLock::new();
+
+/// Configures which messages `Diagnostic::emit()` and `Diagnostic::log()`
+/// print, and opens `log_file` (if any) for appending every message to it.
+/// Only the first call has an effect. Without a call, errors and warnings
+/// are printed as text and nothing is logged.
+pub fn init_logging(
+    verbosity: Verbosity,
+    message_format: MessageFormat,
+    log_file: Option<&Path>,
+) -> Result<(), Box<dyn Error>> {
+    let log_file = match log_file {
+        Some(path) => Some(Mutex::new(
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .map_err(|e| format!("couldn't open log file `{}`: {}", path.display(), e))?,
+        )),
+        None => None,
+    };
+
+    let _ = LOGGER.set(Logger {
+        verbosity,
+        message_format,
+        log_file,
+    });
+    Ok(())
+}
+
+fn logger() -> &'static Logger {
+    LOGGER.get_or_init(|| Logger {
+        verbosity: Verbosity::Normal,
+        message_format: MessageFormat::Human,
+        log_file: None,
+    })
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Level {
+    Error,
+    Warning,
+    Info,
+}
+
+impl Level {
+    /// The name of the level in the JSON format.
+    pub fn name(self) -> &'static str {
+        match self {
+            Level::Error => "error",
+            Level::Warning => "warning",
+            Level::Info => "info",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub level: Level,
+    pub code: &'static str,
+    pub message: String,
+}
+
+impl Diagnostic {
+    pub fn from_error(error: &(dyn Error + 'static)) -> Diagnostic {
+        let code = match LoadError::kind_of(error) {
+            Some(kind) => kind.code(),
+            None if error.is::<WriteError>() => "write-failed",
+            None => "error",
+        };
+
+        Diagnostic {
+            level: Level::Error,
+            code,
+            message: error.to_string(),
+        }
+    }
+
+    pub fn warning(code: &'static str, message: impl Into<String>) -> Diagnostic {
+        Diagnostic {
+            level: Level::Warning,
+            code,
+            message: message.into(),
+        }
+    }
+
+    pub fn info(code: &'static str, message: impl Into<String>) -> Diagnostic {
+        Diagnostic {
+            level: Level::Info,
+            code,
+            message: message.into(),
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        format!(
+            "{{\"level\":\"{}\",\"code\":{},\"message\":{}}}",
+            self.level.name(),
+            json_string(self.code),
+            json_string(&self.message)
+        )
+    }
+
+    /// Prints the diagnostic to stderr, unless the verbosity given to
+    /// `init_logging()` leaves it out, and appends it to the log file.
+    pub fn emit(&self, format: MessageFormat) {
+        let logger = logger();
+        if let Some(log_file) = &logger.log_file {
+            let mut log_file = log_file.lock().unwrap_or_else(PoisonError::into_inner);
+            let _ = writeln!(log_file, "{}", self.to_json());
+        }
+
+        if !logger.verbosity.prints(self.level) {
+            return;
+        }
+        match format {
+            MessageFormat::Human => {
+                let level = match self.level {
+                    Level::Error => "Error",
+                    Level::Warning => "Warning",
+                    Level::Info => "Info",
+                };
+                eprintln!("{}: {}", level, self.message);
+            }
+            MessageFormat::Json => eprintln!("{}", self.to_json()),
+        }
+    }
+
+    /// Like `emit()`, in the message format given to `init_logging()`, for
+    /// code that doesn't know the tool's flags.
+    pub fn log(&self) {
+        self.emit(logger().message_format);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+
+    #[test]
+    fn json() {
+        let error: Box<dyn Error> = Box::new(LoadError::new(
+            LoadErrorKind::FormatTooNew,
+            "version \"10\" is not supported",
+        ));
+
+        assert_eq!(
+            Diagnostic::from_error(&*error).to_json(),
+            r#"{"level":"error","code":"format-too-new","message":"version \"10\" is not supported"}"#
+        );
+
+        let error: Box<dyn Error> = From::from("something else");
+        assert_eq!(Diagnostic::from_error(&*error).code, "error");
+
+        let error = WriteError::new(io::ErrorKind::StorageFull, "no space left");
+        assert_eq!(Diagnostic::from_error(&error).code, "write-failed");
+
+        assert_eq!(
+            Diagnostic::warning("profile-truncated", "").to_json(),
+            r#"{"level":"warning","code":"profile-truncated","message":""}"#
+        );
+        assert_eq!(
+            Diagnostic::info("profile-loaded", "").to_json(),
+            r#"{"level":"info","code":"profile-loaded","message":""}"#
+        );
+    }
+
+    #[test]
+    fn verbosity() {
+        assert_eq!(Verbosity::from_flags(true, true), Verbosity::Quiet);
+        assert_eq!(Verbosity::from_flags(false, true), Verbosity::Verbose);
+        assert_eq!(Verbosity::from_flags(false, false), Verbosity::Normal);
+
+        assert!(Verbosity::Quiet.prints(Level::Error));
+        assert!(!Verbosity::Quiet.prints(Level::Warning));
+        assert!(Verbosity::Normal.prints(Level::Warning));
+        assert!(!Verbosity::Normal.prints(Level::Info));
+        assert!(Verbosity::Verbose.prints(Level::Info));
+    }
+}
@@ -0,0 +1,330 @@
+//! With the `disabled` cargo feature, the `Profiler`, `TimingGuard` and
+//! `StringTableBuilder` that the crate root exports are the zero-sized
+//! stand-ins of this module: they have the same API, but every method does
+//! nothing and returns a placeholder, like `StringId::INVALID` for allocated
+//! strings. Embedders can thus keep their instrumentation in release builds
+//! and remove it at compile time, without any code to record events or
+//! files being written.
+//!
+//! Constructors never fail and never touch the file system. Everything else
+//! in the crate, including the sinks and `SharedStringCache`, is unaffected,
+//! as is `stringtable::StringTableBuilder`, which tools like `analyzeme` use
+//! for writing string tables of their own.
+
+use crate::arg_schema::ArgType;
+use crate::config::ProfilerConfig;
+use crate::event_id::EventId;
+use crate::profiler::{ProfilerSinkStats, ToolInfo};
+use crate::serialization::{SerializationSink, WriteError};
+use crate::shared_strings::SharedStringCache;
+use crate::string_cache::StringCacheStats;
+use crate::stringtable::{
+    ReservedStringIds, SerializableString, StringId, FIRST_RESERVED_STRING_ID,
+};
+use crate::summary::LabelTotals;
+use crate::thread_id::ThreadIdScheme;
+use std::error::Error;
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+pub struct Profiler<S: SerializationSink> {
+    _sink: PhantomData<S>,
+}
+
+// Like the real types, the stand-ins implement `Drop`, so that dropping them
+// explicitly isn't flagged as pointless.
+impl<S: SerializationSink> Drop for Profiler<S> {
+    #[inline(always)]
+    fn drop(&mut self) {}
+}
+
+impl<S: SerializationSink> Profiler<S> {
+    #[inline(always)]
+    fn disabled() -> Profiler<S> {
+        Profiler { _sink: PhantomData }
+    }
+
+    #[inline(always)]
+    pub fn new(_path_stem: &Path) -> Result<Profiler<S>, Box<dyn Error>> {
+        Ok(Profiler::disabled())
+    }
+
+    #[inline(always)]
+    pub fn with_config(
+        _path_stem: &Path,
+        _config: &ProfilerConfig,
+    ) -> Result<Profiler<S>, Box<dyn Error>> {
+        Ok(Profiler::disabled())
+    }
+
+    #[inline(always)]
+    pub fn with_sinks(
+        _event_sink: S,
+        _string_data_sink: S,
+        _string_index_sink: S,
+        _config: &ProfilerConfig,
+    ) -> Profiler<S> {
+        Profiler::disabled()
+    }
+
+    #[inline(always)]
+    pub fn resume(_path_stem: &Path) -> Result<Profiler<S>, Box<dyn Error>> {
+        Ok(Profiler::disabled())
+    }
+
+    #[inline(always)]
+    pub fn register_arg_schema(&self, _event_kind: &str, _args: &[(&str, ArgType)]) {}
+
+    #[inline(always)]
+    pub fn register_event_kind(&self, _name: &str) -> StringId {
+        StringId::new_event_kind(0)
+    }
+
+    #[inline(always)]
+    pub fn reserve_string_ids(&self, _name: &str, count: u32) -> ReservedStringIds {
+        ReservedStringIds::new(FIRST_RESERVED_STRING_ID, count)
+    }
+
+    #[inline(always)]
+    pub fn set_tool_info(&self, _tool_info: ToolInfo) {}
+
+    #[inline(always)]
+    pub fn label_totals(&self) -> Vec<LabelTotals> {
+        Vec::new()
+    }
+
+    #[inline(always)]
+    pub fn health(&self) -> Result<(), WriteError> {
+        Ok(())
+    }
+
+    #[inline(always)]
+    pub fn sink_stats(&self) -> ProfilerSinkStats {
+        ProfilerSinkStats::default()
+    }
+
+    #[inline(always)]
+    pub fn flush_strings(&self) {}
+
+    #[inline(always)]
+    pub fn finished_event_segments(&self) -> Vec<PathBuf> {
+        Vec::new()
+    }
+
+    #[inline(always)]
+    pub fn set_thread_id_scheme(&mut self, _scheme: ThreadIdScheme) {}
+
+    #[inline(always)]
+    pub fn register_current_thread(&self) -> u32 {
+        0
+    }
+
+    #[inline(always)]
+    pub fn register_current_thread_as(&self, _name: &str) -> u32 {
+        0
+    }
+
+    #[inline(always)]
+    pub fn register_external_thread(&self, _external_id: u64, _name: &str) -> u32 {
+        0
+    }
+
+    #[inline(always)]
+    pub fn finish_thread(&self, _thread_id: u32) {}
+
+    #[inline(always)]
+    pub fn open_track(&self, _name: &str) -> u32 {
+        0
+    }
+
+    #[inline(always)]
+    pub fn close_track(&self, _track_id: u32) {}
+
+    #[inline(always)]
+    pub fn map_virtual_to_concrete_string(&self, _virtual_id: StringId, _concrete_id: StringId) {}
+
+    #[inline(always)]
+    pub fn bulk_map_virtual_to_single_concrete_string<I>(
+        &self,
+        _virtual_ids: I,
+        _concrete_id: StringId,
+    ) where
+        I: Iterator<Item = StringId> + ExactSizeIterator,
+    {
+    }
+
+    #[inline(always)]
+    pub fn alloc_string<STR: SerializableString + ?Sized>(&self, _s: &STR) -> StringId {
+        StringId::INVALID
+    }
+
+    #[inline(always)]
+    pub fn set_shared_string_cache(&mut self, _cache: Arc<SharedStringCache<S>>) {}
+
+    #[inline(always)]
+    pub fn intern_string(&self, _s: &str) -> StringId {
+        StringId::INVALID
+    }
+
+    #[inline(always)]
+    pub fn string_cache_stats(&self) -> StringCacheStats {
+        StringCacheStats::default()
+    }
+
+    #[inline(always)]
+    pub fn intern_shared_string(&self, _s: &str) -> StringId {
+        StringId::INVALID
+    }
+
+    #[inline(always)]
+    pub fn record_instant_event(&self, _event_kind: StringId, _event_id: EventId, _thread_id: u32) {
+    }
+
+    #[inline(always)]
+    pub fn record_integer_event(
+        &self,
+        _event_kind: StringId,
+        _event_id: EventId,
+        _thread_id: u32,
+        _value: u64,
+    ) {
+    }
+
+    #[inline(always)]
+    pub fn record_flow_start(&self, _event_id: EventId, _thread_id: u32, _flow_id: u64) {}
+
+    #[inline(always)]
+    pub fn record_flow_end(&self, _event_id: EventId, _thread_id: u32, _flow_id: u64) {}
+
+    #[inline(always)]
+    pub fn record_heartbeat(&self, _thread_id: u32) {}
+
+    #[inline(always)]
+    pub fn pause_recording(&self, _thread_id: u32) {}
+
+    #[inline(always)]
+    pub fn resume_recording(&self, _thread_id: u32) {}
+
+    #[inline(always)]
+    pub fn is_recording_paused(&self) -> bool {
+        false
+    }
+
+    #[inline(always)]
+    pub fn start_phase(&self, _name: &str) {}
+
+    #[inline(always)]
+    pub fn end_phase(&self) {}
+
+    #[inline(always)]
+    pub fn record_session_marker(&self, _name: &str) {}
+
+    #[inline(always)]
+    pub fn start_recording_interval_event<'a>(
+        &'a self,
+        _event_kind: StringId,
+        _event_id: EventId,
+        _thread_id: u32,
+    ) -> TimingGuard<'a, S> {
+        TimingGuard {
+            _profiler: PhantomData,
+        }
+    }
+
+    #[inline(always)]
+    pub fn start_recording_blocked_interval_event<'a>(
+        &'a self,
+        event_kind: StringId,
+        event_id: EventId,
+        thread_id: u32,
+    ) -> TimingGuard<'a, S> {
+        self.start_recording_interval_event(event_kind, event_id, thread_id)
+    }
+}
+
+#[must_use]
+pub struct TimingGuard<'a, S: SerializationSink> {
+    _profiler: PhantomData<&'a Profiler<S>>,
+}
+
+impl<'a, S: SerializationSink> Drop for TimingGuard<'a, S> {
+    #[inline(always)]
+    fn drop(&mut self) {}
+}
+
+impl<'a, S: SerializationSink> TimingGuard<'a, S> {
+    #[inline(always)]
+    pub fn finish_with_override_event_id(self, _event_id: EventId) {}
+}
+
+pub struct StringTableBuilder<S: SerializationSink> {
+    _sink: PhantomData<S>,
+}
+
+impl<S: SerializationSink> Drop for StringTableBuilder<S> {
+    #[inline(always)]
+    fn drop(&mut self) {}
+}
+
+impl<S: SerializationSink> StringTableBuilder<S> {
+    #[inline(always)]
+    pub fn new(_data_sink: Arc<S>, _index_sink: Arc<S>) -> StringTableBuilder<S> {
+        StringTableBuilder { _sink: PhantomData }
+    }
+
+    #[inline(always)]
+    pub fn session_id(&self) -> u128 {
+        0
+    }
+
+    #[inline(always)]
+    pub fn map_virtual_to_concrete_string(&self, _virtual_id: StringId, _concrete_id: StringId) {}
+
+    #[inline(always)]
+    pub fn bulk_map_virtual_to_single_concrete_string<I>(
+        &self,
+        _virtual_ids: I,
+        _concrete_id: StringId,
+    ) where
+        I: Iterator<Item = StringId> + ExactSizeIterator,
+    {
+    }
+
+    #[inline(always)]
+    pub fn alloc_metadata<STR: SerializableString + ?Sized>(&self, _s: &STR) {}
+
+    #[inline(always)]
+    pub fn alloc<STR: SerializableString + ?Sized>(&self, _s: &STR) -> StringId {
+        StringId::INVALID
+    }
+
+    #[inline(always)]
+    pub fn flush(&self) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{EventId, FileSerializationSink};
+
+    #[test]
+    fn no_ops() {
+        assert_eq!(std::mem::size_of::<Profiler<FileSerializationSink>>(), 0);
+        assert_eq!(
+            std::mem::size_of::<TimingGuard<'_, FileSerializationSink>>(),
+            0
+        );
+
+        let path_stem = Path::new("test-tmp").join("disabled").join("profile");
+        let profiler = Profiler::<FileSerializationSink>::new(&path_stem).unwrap();
+        let kind = profiler.alloc_string("Query");
+        let id = EventId::from_label(profiler.alloc_string("typeck"));
+        drop(profiler.start_recording_interval_event(kind, id, 0));
+        profiler.record_instant_event(kind, id, 0);
+        assert_eq!(profiler.reserve_string_ids("queries", 3).len(), 3);
+        drop(profiler);
+
+        assert!(!path_stem.with_extension("events").exists());
+    }
+}
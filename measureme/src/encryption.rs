@@ -0,0 +1,622 @@
+//! Profiles can contain sensitive information, like source paths and
+//! identifiers. `EncryptedSerializationSink` wraps another sink and encrypts
+//! everything written to it, so that profiles are protected at rest.
+//!
+//! `measureme` does not implement any cryptography itself. Instead, the
+//! embedder provides a `ProfileCipher`, typically backed by an authenticated
+//! encryption scheme like XChaCha20-Poly1305 or `age` from a vetted crate,
+//! which also takes care of managing the key.
+//!
+//! An encrypted file starts with a file header with `FILE_MAGIC_ENCRYPTED`
+//! and the session id of the original file, followed by the file magic of
+//! the original file, which tells its kind (events, string data or string
+//! index). Then come the encrypted chunks of the original file (including
+//! its own header and footer), and a file footer covering the encrypted
+//! data, so that damaged files can be detected without the key. Each chunk
+//! is stored as a 4 byte little-endian length followed by the output of
+//! `ProfileCipher::encrypt()`.
+//!
+//! The associated data of a chunk consists of the 16 byte little-endian
+//! session id, the file magic of the original file, the 8 byte
+//! little-endian index of the chunk and a byte that is `1` for the last
+//! chunk and `0` otherwise. Reordered, missing or truncated chunks thus fail
+//! to decrypt, and so do chunks that have been copied from another file,
+//! even one of the same profile encrypted with the same key.
+//!
+//! Use `decrypt_file()` (or `analyzeme::ProfilingData::new_encrypted()`) for
+//! reading encrypted files.
+//!
+//! With the `chacha20poly1305` feature, `XChaCha20Poly1305Cipher` provides a
+//! `ProfileCipher` backed by the `chacha20poly1305` crate.
+
+use crate::checksum::Checksum;
+use crate::config::ProfilerConfig;
+use crate::file_header::{
+    file_footer, read_full_file_header, verify_file_footer, write_file_header, FILE_HEADER_SIZE,
+    FILE_MAGIC_ENCRYPTED,
+};
+use crate::serialization::{Addr, ProfileFileKind, SerializationSink, SinkStats, WriteError};
+use crate::sync::Mutex;
+use byteorder::{ByteOrder, LittleEndian};
+use std::error::Error;
+use std::mem;
+use std::path::Path;
+
+/// The size of the plaintext chunks that are encrypted individually.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// The oldest format version of encrypted files `decrypt_file()` reads.
+/// The chunks of older files have less associated data.
+const MIN_ENCRYPTED_FILE_VERSION: u32 = 13;
+
+/// An authenticated encryption scheme provided by the embedder. See the
+/// module documentation.
+pub trait ProfileCipher: Send + Sync + 'static {
+    /// Creates the cipher, e.g. by loading the key from a file or an
+    /// environment variable. This is called once for each file of the
+    /// profile.
+    fn load() -> Result<Self, Box<dyn Error>>
+    where
+        Self: Sized;
+
+    /// Encrypts and authenticates `plaintext` together with
+    /// `associated_data`. Ciphers that need a nonce must generate one and
+    /// store it in the output.
+    fn encrypt(&self, associated_data: &[u8], plaintext: &[u8]) -> Vec<u8>;
+
+    /// Reverses `encrypt()`. Must return an error if `ciphertext` or
+    /// `associated_data` have been tampered with, or if the key is wrong.
+    fn decrypt(&self, associated_data: &[u8], ciphertext: &[u8])
+        -> Result<Vec<u8>, Box<dyn Error>>;
+}
+
+/// The session id and file magic of the original file, which are bound to
+/// all of its chunks.
+#[derive(Clone, Copy)]
+struct FileIdentity {
+    session_id: u128,
+    file_magic: [u8; 4],
+}
+
+impl FileIdentity {
+    /// Takes the identity from the header at the start of the plaintext.
+    fn of_plaintext(plaintext: &[u8]) -> FileIdentity {
+        let mut file_magic = [0; 4];
+        let magic_len = plaintext.len().min(4);
+        file_magic[..magic_len].copy_from_slice(&plaintext[..magic_len]);
+
+        FileIdentity {
+            session_id: plaintext
+                .get(16..FILE_HEADER_SIZE)
+                .map_or(0, LittleEndian::read_u128),
+            file_magic,
+        }
+    }
+
+    fn associated_data(&self, chunk_index: u64, is_last: bool) -> [u8; 29] {
+        let mut data = [0; 29];
+        LittleEndian::write_u128(&mut data[0..16], self.session_id);
+        data[16..20].copy_from_slice(&self.file_magic);
+        LittleEndian::write_u64(&mut data[20..28], chunk_index);
+        data[28] = is_last as u8;
+        data
+    }
+}
+
+/// A `SerializationSink` that encrypts all data with the `ProfileCipher` `C`
+/// before handing it to the sink `S`.
+pub struct EncryptedSerializationSink<S: SerializationSink, C: ProfileCipher> {
+    inner: S,
+    cipher: C,
+    state: Mutex<State>,
+}
+
+struct State {
+    // Plaintext that hasn't been encrypted yet.
+    buffer: Vec<u8>,
+    // Set when the first chunk is written, which starts with the header of
+    // the plaintext.
+    identity: Option<FileIdentity>,
+    addr: u64,
+    next_chunk_index: u64,
+    // The checksum of the plaintext, for the footer of the plaintext file.
+    checksum: Checksum,
+}
+
+impl<S: SerializationSink, C: ProfileCipher> EncryptedSerializationSink<S, C> {
+    pub fn new(inner: S, cipher: C) -> EncryptedSerializationSink<S, C> {
+        // The header is written with the first chunk, which holds the header
+        // of the plaintext with the session id.
+        EncryptedSerializationSink {
+            inner,
+            cipher,
+            state: Mutex::new(State {
+                buffer: Vec::with_capacity(CHUNK_SIZE),
+                identity: None,
+                addr: 0,
+                next_chunk_index: 0,
+                checksum: Checksum::new(),
+            }),
+        }
+    }
+
+    fn write_chunk(&self, state: &mut State, plaintext: &[u8], is_last: bool) {
+        let identity = *state.identity.get_or_insert_with(|| {
+            let identity = FileIdentity::of_plaintext(plaintext);
+            write_file_header(&self.inner, FILE_MAGIC_ENCRYPTED, identity.session_id);
+            self.inner.write_bytes_atomic(&identity.file_magic);
+            identity
+        });
+
+        let associated_data = identity.associated_data(state.next_chunk_index, is_last);
+        let ciphertext = self.cipher.encrypt(&associated_data, plaintext);
+        state.next_chunk_index += 1;
+
+        let mut frame = vec![0; 4];
+        LittleEndian::write_u32(&mut frame[0..4], ciphertext.len() as u32);
+        frame.extend_from_slice(&ciphertext);
+
+        self.inner.write_bytes_atomic(&frame);
+    }
+}
+
+impl<S: SerializationSink, C: ProfileCipher> SerializationSink
+    for EncryptedSerializationSink<S, C>
+{
+    fn from_path(path: &Path) -> Result<Self, Box<dyn Error>> {
+        Ok(EncryptedSerializationSink::new(
+            S::from_path(path)?,
+            C::load()?,
+        ))
+    }
+
+    fn from_config(
+        path: &Path,
+        file_kind: ProfileFileKind,
+        config: &ProfilerConfig,
+    ) -> Result<Self, Box<dyn Error>> {
+        Ok(EncryptedSerializationSink::new(
+            S::from_config(path, file_kind, config)?,
+            C::load()?,
+        ))
+    }
+
+    fn write_atomic<W>(&self, num_bytes: usize, write: W) -> Addr
+    where
+        W: FnOnce(&mut [u8]),
+    {
+        let mut state = self.state.lock();
+
+        let curr_addr = state.addr;
+        state.addr += num_bytes as u64;
+
+        let buf_start = state.buffer.len();
+        state.buffer.resize(buf_start + num_bytes, 0);
+        write(&mut state.buffer[buf_start..]);
+
+        if state.buffer.len() >= CHUNK_SIZE {
+            let buffer = mem::replace(&mut state.buffer, Vec::with_capacity(CHUNK_SIZE));
+
+            let mut chunks = buffer.chunks_exact(CHUNK_SIZE);
+            for chunk in &mut chunks {
+                state.checksum.update(chunk);
+                self.write_chunk(&mut state, chunk, false);
+            }
+
+            state.buffer.extend_from_slice(chunks.remainder());
+        }
+
+        Addr(curr_addr)
+    }
+
+    fn has_failed(&self) -> bool {
+        self.inner.has_failed()
+    }
+
+    fn write_error(&self) -> Option<WriteError> {
+        self.inner.write_error()
+    }
+
+    fn dropped_writes(&self) -> u64 {
+        self.inner.dropped_writes()
+    }
+
+    fn stats(&self) -> SinkStats {
+        self.inner.stats()
+    }
+}
+
+impl<S: SerializationSink, C: ProfileCipher> Drop for EncryptedSerializationSink<S, C> {
+    fn drop(&mut self) {
+        let mut state = self.state.lock();
+
+        let mut last_chunk = mem::take(&mut state.buffer);
+        state.checksum.update(&last_chunk);
+        last_chunk.extend_from_slice(&file_footer(state.checksum.finish()));
+
+        self.write_chunk(&mut state, &last_chunk, true);
+    }
+}
+
+/// Returns the decrypted contents of a file written by an
+/// `EncryptedSerializationSink`.
+pub fn decrypt_file(data: &[u8], cipher: &dyn ProfileCipher) -> Result<Vec<u8>, Box<dyn Error>> {
+    let data = verify_file_footer(data)?;
+
+    let header = read_full_file_header(data, FILE_MAGIC_ENCRYPTED)?;
+    if header.version < MIN_ENCRYPTED_FILE_VERSION {
+        return Err(From::from(format!(
+            "Encrypted file format version '{}' is not supported by this version of `measureme`.",
+            header.version
+        )));
+    }
+    header.check_readable()?;
+
+    let file_magic = data
+        .get(FILE_HEADER_SIZE..FILE_HEADER_SIZE + 4)
+        .ok_or("encrypted file is malformed")?;
+    let identity = FileIdentity {
+        session_id: header.session_id,
+        file_magic: [file_magic[0], file_magic[1], file_magic[2], file_magic[3]],
+    };
+
+    let mut plaintext = Vec::new();
+    let mut rest = &data[FILE_HEADER_SIZE + 4..];
+    let mut chunk_index = 0;
+
+    while !rest.is_empty() {
+        if rest.len() < 4 {
+            return Err(From::from("encrypted file is malformed"));
+        }
+
+        let len = LittleEndian::read_u32(&rest[0..4]) as usize;
+        let ciphertext = rest.get(4..4 + len).ok_or("encrypted file is malformed")?;
+        rest = &rest[4 + len..];
+
+        let chunk = cipher
+            .decrypt(
+                &identity.associated_data(chunk_index, rest.is_empty()),
+                ciphertext,
+            )
+            .map_err(|e| {
+                format!(
+                    "could not decrypt file, the key is wrong or the file has been tampered with: {}",
+                    e
+                )
+            })?;
+
+        plaintext.extend_from_slice(&chunk);
+        chunk_index += 1;
+    }
+
+    if FileIdentity::of_plaintext(&plaintext).session_id != identity.session_id
+        || !plaintext.starts_with(&identity.file_magic)
+    {
+        Err("the header of the encrypted file doesn't match the decrypted file")?;
+    }
+
+    Ok(plaintext)
+}
+
+/// Returns `true` if `data` looks like a file written by an
+/// `EncryptedSerializationSink`.
+pub fn is_encrypted(data: &[u8]) -> bool {
+    data.starts_with(FILE_MAGIC_ENCRYPTED)
+}
+
+/// The environment variable `XChaCha20Poly1305Cipher::load()` reads the key
+/// from, as 64 hex digits.
+#[cfg(feature = "chacha20poly1305")]
+pub const KEY_ENV_VAR: &str = "MEASUREME_PROFILE_KEY";
+
+/// A `ProfileCipher` that uses XChaCha20-Poly1305 as implemented by the
+/// `chacha20poly1305` crate. Every chunk is encrypted with a random 192 bit
+/// nonce, which is stored in front of its ciphertext and is large enough to
+/// never repeat, no matter how many chunks are encrypted with the same key.
+/// Only available with the `chacha20poly1305` feature.
+#[cfg(feature = "chacha20poly1305")]
+pub struct XChaCha20Poly1305Cipher {
+    cipher: chacha20poly1305::XChaCha20Poly1305,
+}
+
+#[cfg(feature = "chacha20poly1305")]
+impl XChaCha20Poly1305Cipher {
+    pub const KEY_LEN: usize = 32;
+
+    pub fn new(key: &[u8; XChaCha20Poly1305Cipher::KEY_LEN]) -> XChaCha20Poly1305Cipher {
+        use chacha20poly1305::KeyInit;
+
+        XChaCha20Poly1305Cipher {
+            cipher: chacha20poly1305::XChaCha20Poly1305::new(key.into()),
+        }
+    }
+
+    /// Creates the cipher from a key given as 64 hex digits, the format of
+    /// `KEY_ENV_VAR`.
+    pub fn from_hex(hex: &str) -> Result<XChaCha20Poly1305Cipher, Box<dyn Error>> {
+        let invalid = || "the key must be 64 hex digits";
+        if hex.len() != 2 * XChaCha20Poly1305Cipher::KEY_LEN
+            || !hex.bytes().all(|b| b.is_ascii_hexdigit())
+        {
+            Err(invalid())?;
+        }
+
+        let mut key = [0; XChaCha20Poly1305Cipher::KEY_LEN];
+        for (i, byte) in key.iter_mut().enumerate() {
+            *byte = hex
+                .get(2 * i..2 * i + 2)
+                .and_then(|digits| u8::from_str_radix(digits, 16).ok())
+                .ok_or_else(invalid)?;
+        }
+
+        Ok(XChaCha20Poly1305Cipher::new(&key))
+    }
+
+    /// Returns a new random key, e.g. for setting `KEY_ENV_VAR`.
+    pub fn generate_key() -> [u8; XChaCha20Poly1305Cipher::KEY_LEN] {
+        use chacha20poly1305::aead::OsRng;
+        use chacha20poly1305::KeyInit;
+
+        chacha20poly1305::XChaCha20Poly1305::generate_key(&mut OsRng).into()
+    }
+}
+
+#[cfg(feature = "chacha20poly1305")]
+impl ProfileCipher for XChaCha20Poly1305Cipher {
+    /// Reads the key from `KEY_ENV_VAR`, see `from_hex()`.
+    fn load() -> Result<Self, Box<dyn Error>> {
+        let hex = std::env::var(KEY_ENV_VAR)
+            .map_err(|_| format!("`{}` must be set to the key of the profile", KEY_ENV_VAR))?;
+
+        XChaCha20Poly1305Cipher::from_hex(&hex)
+            .map_err(|e| format!("`{}`: {}", KEY_ENV_VAR, e).into())
+    }
+
+    fn encrypt(&self, associated_data: &[u8], plaintext: &[u8]) -> Vec<u8> {
+        use chacha20poly1305::aead::{Aead, AeadCore, OsRng, Payload};
+
+        let nonce = chacha20poly1305::XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let payload = Payload {
+            msg: plaintext,
+            aad: associated_data,
+        };
+
+        let mut ciphertext = nonce.to_vec();
+        ciphertext.extend_from_slice(
+            &self
+                .cipher
+                .encrypt(&nonce, payload)
+                .expect("chunks are far below the size limit of XChaCha20-Poly1305"),
+        );
+        ciphertext
+    }
+
+    fn decrypt(
+        &self,
+        associated_data: &[u8],
+        ciphertext: &[u8],
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        use chacha20poly1305::aead::{Aead, Payload};
+        use chacha20poly1305::XNonce;
+
+        const NONCE_LEN: usize = 24;
+
+        if ciphertext.len() < NONCE_LEN {
+            Err("the ciphertext is too short")?;
+        }
+        let (nonce, sealed) = ciphertext.split_at(NONCE_LEN);
+        let payload = Payload {
+            msg: sealed,
+            aad: associated_data,
+        };
+
+        Ok(self
+            .cipher
+            .decrypt(XNonce::from_slice(nonce), payload)
+            .map_err(|_| "authentication failed")?)
+    }
+}
+
+#[cfg(all(test, feature = "chacha20poly1305"))]
+mod tests {
+    use super::*;
+    use crate::checksum::checksum;
+    use crate::file_header::{
+        FILE_FOOTER_SIZE, FILE_MAGIC_EVENT_STREAM, FILE_MAGIC_STRINGTABLE_DATA,
+    };
+    use crate::file_serialization_sink::FileSerializationSink;
+
+    // Encrypts a file with the given header and contents, which is also
+    // returned, as `EncryptedSerializationSink` does.
+    fn encrypt_file(
+        name: &str,
+        cipher: XChaCha20Poly1305Cipher,
+        file_magic: &[u8; 4],
+        session_id: u128,
+        contents: &[u8],
+    ) -> (Vec<u8>, Vec<u8>) {
+        let path = std::env::temp_dir().join(format!(
+            "measureme-encryption-{}-{}",
+            name,
+            std::process::id()
+        ));
+
+        {
+            let inner = FileSerializationSink::from_path(&path).unwrap();
+            let sink = EncryptedSerializationSink::new(inner, cipher);
+            write_file_header(&sink, file_magic, session_id);
+            sink.write_bytes_atomic(contents);
+        }
+
+        let file = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let mut plaintext = Vec::new();
+        plaintext.extend_from_slice(file_magic);
+        plaintext.extend_from_slice(&file[4..16]);
+        plaintext.extend_from_slice(&session_id.to_le_bytes());
+        plaintext.extend_from_slice(contents);
+        (file, plaintext)
+    }
+
+    // The chunks of an encrypted file, with their length prefixes.
+    fn chunks(file: &[u8]) -> Vec<&[u8]> {
+        let mut rest = &file[FILE_HEADER_SIZE + 4..file.len() - FILE_FOOTER_SIZE];
+        let mut chunks = Vec::new();
+        while !rest.is_empty() {
+            let len = 4 + LittleEndian::read_u32(rest) as usize;
+            chunks.push(&rest[..len]);
+            rest = &rest[len..];
+        }
+        chunks
+    }
+
+    // Replaces everything between the header and the footer and updates the
+    // footer.
+    fn with_chunks(file: &[u8], chunks: &[&[u8]]) -> Vec<u8> {
+        let mut tampered = file[..FILE_HEADER_SIZE + 4].to_vec();
+        for chunk in chunks {
+            tampered.extend_from_slice(chunk);
+        }
+        let footer = file_footer(checksum(&tampered));
+        tampered.extend_from_slice(&footer);
+        tampered
+    }
+
+    #[test]
+    fn xchacha20poly1305_roundtrip() {
+        let key = XChaCha20Poly1305Cipher::generate_key();
+        let cipher = XChaCha20Poly1305Cipher::new(&key);
+
+        let ciphertext = cipher.encrypt(b"chunk 0", b"/secret/path");
+        assert_eq!(
+            cipher.decrypt(b"chunk 0", &ciphertext).unwrap(),
+            b"/secret/path"
+        );
+        assert!(cipher.decrypt(b"chunk 1", &ciphertext).is_err());
+
+        // Several chunks and the plaintext footer.
+        let contents: Vec<u8> = (0..3 * CHUNK_SIZE).map(|i| i as u8).collect();
+        let (file, plaintext) = encrypt_file(
+            "roundtrip",
+            XChaCha20Poly1305Cipher::new(&key),
+            FILE_MAGIC_EVENT_STREAM,
+            42,
+            &contents,
+        );
+        assert!(!file.windows(64).any(|w| w == &contents[..64]));
+        assert_eq!(
+            read_full_file_header(&file, FILE_MAGIC_ENCRYPTED)
+                .unwrap()
+                .session_id,
+            42
+        );
+
+        let decrypted = decrypt_file(&file, &XChaCha20Poly1305Cipher::new(&key)).unwrap();
+        assert_eq!(
+            &decrypted[..decrypted.len() - FILE_FOOTER_SIZE],
+            &plaintext[..]
+        );
+    }
+
+    #[test]
+    fn xchacha20poly1305_wrong_key() {
+        let key = XChaCha20Poly1305Cipher::generate_key();
+        let mut wrong_key = key;
+        wrong_key[0] ^= 1;
+
+        let (file, _) = encrypt_file(
+            "wrong_key",
+            XChaCha20Poly1305Cipher::new(&key),
+            FILE_MAGIC_EVENT_STREAM,
+            42,
+            b"",
+        );
+        let error = decrypt_file(&file, &XChaCha20Poly1305Cipher::new(&wrong_key)).unwrap_err();
+        assert!(error.to_string().contains("could not decrypt"), "{}", error);
+    }
+
+    // Chunks can't be reordered, or copied between files of the same key,
+    // even if the files are of the same profile.
+    #[test]
+    fn chunks_are_bound_to_their_file() {
+        let key = XChaCha20Poly1305Cipher::generate_key();
+        let contents = vec![0; 2 * CHUNK_SIZE];
+        let encrypt = |name, file_magic, session_id| {
+            let cipher = XChaCha20Poly1305Cipher::new(&key);
+            encrypt_file(name, cipher, file_magic, session_id, &contents).0
+        };
+
+        let events = encrypt("events", FILE_MAGIC_EVENT_STREAM, 42);
+        let string_data = encrypt("string_data", FILE_MAGIC_STRINGTABLE_DATA, 42);
+        let other_session = encrypt("other_session", FILE_MAGIC_EVENT_STREAM, 43);
+
+        let cipher = XChaCha20Poly1305Cipher::new(&key);
+        let decrypts = |file: &[u8]| decrypt_file(file, &cipher).is_ok();
+
+        let chunks = chunks(&events);
+        assert_eq!(chunks.len(), 3);
+        assert!(decrypts(&with_chunks(&events, &chunks)));
+        assert!(!decrypts(&with_chunks(
+            &events,
+            &[chunks[1], chunks[0], chunks[2]]
+        )));
+        assert!(!decrypts(&with_chunks(&events, &chunks[..2])));
+
+        for other in &[string_data, other_session] {
+            let other_chunks = self::chunks(other);
+            assert!(!decrypts(&with_chunks(
+                &events,
+                &[other_chunks[0], chunks[1], chunks[2]]
+            )));
+        }
+    }
+
+    #[test]
+    fn format_versions() {
+        let key = XChaCha20Poly1305Cipher::generate_key();
+        let cipher = XChaCha20Poly1305Cipher::new(&key);
+        let (file, _) = encrypt_file(
+            "versions",
+            XChaCha20Poly1305Cipher::new(&key),
+            FILE_MAGIC_EVENT_STREAM,
+            42,
+            b"abc",
+        );
+
+        let with_versions = |version: u32, min_reader_version: u32| {
+            let mut file = file[..file.len() - FILE_FOOTER_SIZE].to_vec();
+            LittleEndian::write_u32(&mut file[4..8], version);
+            LittleEndian::write_u32(&mut file[8..12], min_reader_version);
+            let footer = file_footer(checksum(&file));
+            file.extend_from_slice(&footer);
+            file
+        };
+
+        // Newer files are read as long as they don't require a newer
+        // reader.
+        let current = crate::file_header::CURRENT_FILE_FORMAT_VERSION;
+        assert!(decrypt_file(&with_versions(current + 1, current), &cipher).is_ok());
+        assert!(decrypt_file(&with_versions(current + 1, current + 1), &cipher).is_err());
+        assert!(decrypt_file(&with_versions(MIN_ENCRYPTED_FILE_VERSION - 1, 0), &cipher).is_err());
+    }
+
+    #[test]
+    fn xchacha20poly1305_key_from_hex() {
+        let key = XChaCha20Poly1305Cipher::generate_key();
+        let hex: String = key.iter().map(|byte| format!("{:02x}", byte)).collect();
+
+        let ciphertext = XChaCha20Poly1305Cipher::from_hex(&hex)
+            .unwrap()
+            .encrypt(b"", b"abc");
+        assert_eq!(
+            XChaCha20Poly1305Cipher::new(&key)
+                .decrypt(b"", &ciphertext)
+                .unwrap(),
+            b"abc"
+        );
+
+        assert!(XChaCha20Poly1305Cipher::from_hex(&hex[1..]).is_err());
+        assert!(XChaCha20Poly1305Cipher::from_hex(&hex.replace(&hex[..1], "g")).is_err());
+    }
+}
@@ -1,17 +1,18 @@
-use crate::{Profiler, SerializationSink, StringComponent, StringId};
-
-/// Event IDs are strings conforming to the following grammar:
-///
-/// ```ignore
-///   <event_id> = <label> {<argument>}
-///   <label> = <text>
-///   <argument> = '\x1E' <text>
-///   <text> = regex([^[[:cntrl:]]]+) // Anything but ASCII control characters
-///  ```
-///
-/// This means there's always a "label", followed by an optional list of
-/// arguments. Future versions my support other optional suffixes (with a tag
-/// other than '\x11' after the '\x1E' separator), such as a "category".
+//! Event IDs are strings conforming to the following grammar:
+//!
+//! ```ignore
+//!   <event_id> = <label> {<argument>}
+//!   <label> = <text>
+//!   <argument> = '\x1E' <text>
+//!   <text> = regex([^[[:cntrl:]]]+) // Anything but ASCII control characters
+//!  ```
+//!
+//! This means there's always a "label", followed by an optional list of
+//! arguments. Future versions my support other optional suffixes (with a tag
+//! other than '\x11' after the '\x1E' separator), such as a "category".
+
+use crate::stringtable::MAX_STRING_ID;
+use crate::{Profiler, SerializationSink, SourceLocation, StringComponent, StringId};
 
 /// The byte used to separate arguments from the label and each other.
 pub const SEPARATOR_BYTE: &str = "\x1E";
@@ -20,7 +21,7 @@ pub const SEPARATOR_BYTE: &str = "\x1E";
 /// corresponding string conforms to the event_id grammar.
 #[derive(Clone, Copy, Eq, PartialEq, Hash, Debug)]
 #[repr(C)]
-pub struct EventId(StringId);
+pub struct EventId(pub(crate) StringId);
 
 impl EventId {
     pub const INVALID: EventId = EventId(StringId::INVALID);
@@ -51,6 +52,19 @@ impl EventId {
     pub fn from_u32(raw_id: u32) -> EventId {
         EventId(StringId::new(raw_id))
     }
+
+    /// Like `from_u32()`, but doesn't check the id, for decoding the ids of
+    /// a profile, which may be corrupt. See `is_valid()`.
+    #[inline]
+    pub fn from_raw(raw_id: u32) -> EventId {
+        EventId(StringId::from_raw(raw_id))
+    }
+
+    /// Whether `from_u32()` would accept the id.
+    #[inline]
+    pub fn is_valid(self) -> bool {
+        self.0.as_u32() <= MAX_STRING_ID
+    }
 }
 
 pub struct EventIdBuilder<'p, S: SerializationSink> {
@@ -78,4 +92,13 @@ impl<'p, S: SerializationSink> EventIdBuilder<'p, S> {
             StringComponent::Ref(arg),
         ]))
     }
+
+    /// Creates an event id with `location` as its argument. The location is
+    /// interned, so recording many events at the same place writes it once.
+    /// Analysis tools only decode it as a location if the event kind's
+    /// argument schema says so, i.e. is `[("location", ArgType::Location)]`.
+    pub fn from_label_and_location(&self, label: StringId, location: SourceLocation) -> EventId {
+        let location = self.profiler.intern_string(&location.to_string());
+        self.from_label_and_arg(label, location)
+    }
 }
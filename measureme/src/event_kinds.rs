@@ -0,0 +1,59 @@
+//! This module contains the event kinds that have a special meaning for
+//! `measureme` itself and for the analysis tools, independently of what
+//! application is being profiled.
+
+/// Instant events of this kind are recorded periodically by a thread in order
+/// to signal that it is still making progress. Analysis tools treat long
+/// stretches without any events on a thread as a possible stall. See
+/// `Profiler::record_heartbeat()`.
+pub const HEARTBEAT_EVENT_KIND: &str = "Heartbeat";
+
+/// An instant event of this kind is recorded the first time a thread is
+/// registered via `Profiler::register_current_thread()`. Its label is the name
+/// of the thread (or `<unnamed>`).
+pub const THREAD_REGISTRATION_EVENT_KIND: &str = "ThreadRegistration";
+
+/// An instant event of this kind is recorded along with the registration
+/// event of a thread registered via `Profiler::register_external_thread()`.
+/// Its label is the external id of the thread, in decimal.
+pub const EXTERNAL_THREAD_ID_EVENT_KIND: &str = "ExternalThreadId";
+
+/// An instant event of this kind is recorded by `Profiler::finish_thread()`
+/// once a thread won't record any more events, so all of its intervals have
+/// been closed. Interval events are only recorded when they end, so for a
+/// thread without such an event, intervals that were still open when the
+/// profile ended are missing from the profile.
+pub const THREAD_FINISHED_EVENT_KIND: &str = "ThreadFinished";
+
+/// Instant events of these kinds are recorded by `Profiler::open_track()` and
+/// `Profiler::close_track()` respectively, on the track itself. The label of
+/// the former is the name of the track. Analysis tools treat them like the
+/// registration and the `ThreadFinished` event of a thread.
+pub const TRACK_OPENED_EVENT_KIND: &str = "TrackOpened";
+pub const TRACK_CLOSED_EVENT_KIND: &str = "TrackClosed";
+
+/// Instant events of these kinds are recorded by `Profiler::pause_recording()`
+/// and `Profiler::resume_recording()` respectively. No events are recorded in
+/// between the two.
+pub const PROFILER_PAUSED_EVENT_KIND: &str = "ProfilerPaused";
+pub const PROFILER_RESUMED_EVENT_KIND: &str = "ProfilerResumed";
+
+/// Instant events of these kinds are recorded by `Profiler::start_phase()` and
+/// `Profiler::end_phase()` respectively. Their label is the name of the phase.
+/// Phases apply to the whole process and can be nested.
+pub const PHASE_START_EVENT_KIND: &str = "PhaseStart";
+pub const PHASE_END_EVENT_KIND: &str = "PhaseEnd";
+
+/// Instant events of this kind are recorded by
+/// `Profiler::record_session_marker()`. Their label is the name of the segment
+/// of the session that starts with the marker and lasts until the next one,
+/// e.g. `codegen`.
+pub const SESSION_MARKER_EVENT_KIND: &str = "SessionMarker";
+
+/// Integer events of these kinds are recorded by `Profiler::record_flow_start()`
+/// and `Profiler::record_flow_end()` respectively. Their value is the id of the
+/// flow, which links its start (e.g. a work item being enqueued) to its end on
+/// the same or another thread (e.g. a worker picking the item up). Their label
+/// describes the flow.
+pub const FLOW_START_EVENT_KIND: &str = "FlowStart";
+pub const FLOW_END_EVENT_KIND: &str = "FlowEnd";
@@ -0,0 +1,245 @@
+//! With `EventLayout::Rollover`, the profiler writes its events to a
+//! sequence of numbered segment files instead of to the `.events` file,
+//! starting a new segment every `Rollover::interval` or once the current
+//! segment holds `Rollover::max_bytes`, whichever comes first. Extremely long
+//! recordings can thus be archived or pruned one segment at a time
+//! while the profiler keeps running, instead of as a single file that only
+//! grows.
+//!
+//! The segments are named `<path_stem>.<n>.segment_events`, see
+//! `ProfilerFiles::segment_events_file()`, and have the same header and
+//! footer as the `.events` file, which itself only contains the header. A
+//! segment is complete, i.e. has its footer, as soon as the next one has been
+//! started; `Profiler::finished_event_segments()` lists them. All segments
+//! share the profile's string table. Whenever a new segment is started, the
+//! metadata is updated and the strings the string table is still buffering
+//! are written to its sinks, so that they don't pile up in memory over a long
+//! recording. The string table files themselves are only complete once the
+//! profiler has been dropped, so analyzing a profile, unlike archiving or
+//! deleting its finished segments, has to wait until then.
+//!
+//! The profile's metadata lists the file names, as JSON of the form
+//!
+//! ```json
+//! "event_segments": ["foo-1234.0.segment_events", "foo-1234.1.segment_events"]
+//! ```
+//!
+//! and `analyzeme` concatenates the events of the segments when loading the
+//! profile, skipping segments that have been deleted in the meantime.
+//! Profiles recorded this way cannot be resumed via `Profiler::resume()`.
+
+use crate::config::{ProfilerConfig, Rollover};
+use crate::file_header::write_file_header_with_features;
+use crate::profiler::ProfilerFiles;
+use crate::serialization::{ProfileFileKind, SerializationSink, SinkStats, WriteError};
+use crate::sync::{Mutex, RwLock};
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+
+/// The segments of a profiler.
+pub(crate) struct EventSegments<S: SerializationSink> {
+    files: ProfilerFiles,
+    config: ProfilerConfig,
+    rollover: Rollover,
+    feature_flags: u32,
+    session_id: u128,
+    start: Instant,
+    // The segment events are written to. Rolling over replaces it, which
+    // drops the sink of the previous segment and so writes its footer.
+    current: RwLock<S>,
+    // The bytes written to the current segment.
+    bytes: AtomicU64,
+    // The time of the next rollover by time, in nanoseconds since `start`.
+    next_rollover: AtomicU64,
+    // The file names of the segments, the current one last.
+    file_names: Mutex<Vec<String>>,
+    // What the sinks of the finished segments have reported.
+    finished: Mutex<FinishedSegments>,
+}
+
+#[derive(Default)]
+struct FinishedSegments {
+    stats: SinkStats,
+    dropped_writes: u64,
+    write_error: Option<WriteError>,
+}
+
+impl<S: SerializationSink> EventSegments<S> {
+    pub fn new(
+        path_stem: &Path,
+        config: &ProfilerConfig,
+        rollover: Rollover,
+        feature_flags: u32,
+        session_id: u128,
+    ) -> Result<EventSegments<S>, Box<dyn Error>> {
+        if rollover.interval.is_none() && rollover.max_bytes.is_none() {
+            Err("`Rollover` needs an interval, a maximum size or both")?;
+        }
+
+        let files = ProfilerFiles::new(path_stem);
+        let path = files.segment_events_file(0);
+        let sink = S::from_config(&path, ProfileFileKind::Events, config)?;
+        write_file_header_with_features(
+            &sink,
+            config.timestamp_format.file_magic(),
+            feature_flags,
+            session_id,
+        );
+
+        let segments = EventSegments {
+            files,
+            config: config.clone(),
+            rollover,
+            feature_flags,
+            session_id,
+            start: Instant::now(),
+            current: RwLock::new(sink),
+            bytes: AtomicU64::new(0),
+            next_rollover: AtomicU64::new(0),
+            file_names: Mutex::new(vec![file_name(&path)]),
+            finished: Mutex::new(FinishedSegments::default()),
+        };
+        segments.schedule_next_rollover();
+        Ok(segments)
+    }
+
+    /// Writes an event to the current segment and starts a new segment if
+    /// the current one is full or old enough. Returns `true` if a new
+    /// segment has been started, which the metadata has to list.
+    #[inline]
+    pub fn write_atomic<W>(&self, num_bytes: usize, write: W) -> bool
+    where
+        W: FnOnce(&mut [u8]),
+    {
+        let mut rolled_over = false;
+        loop {
+            // The bytes are reserved while holding the lock, so that events
+            // written concurrently can't overfill the segment, and
+            // `roll_over()` can't reset the count between reserving and
+            // writing.
+            let current = self.current.read();
+            let before = self.bytes.fetch_add(num_bytes as u64, Ordering::Relaxed);
+            if !self.would_overfill(before, num_bytes) {
+                current.write_atomic(num_bytes, write);
+                break;
+            }
+            drop(current);
+            rolled_over |= self.roll_over();
+        }
+
+        (self.is_rollover_due() && self.roll_over()) || rolled_over
+    }
+
+    /// Whether an event of `num_bytes` doesn't fit into the current segment
+    /// anymore. An event always fits into an empty segment.
+    #[inline]
+    fn would_overfill(&self, before: u64, num_bytes: usize) -> bool {
+        self.rollover
+            .max_bytes
+            .is_some_and(|max_bytes| before > 0 && before + num_bytes as u64 > max_bytes)
+    }
+
+    #[inline]
+    fn is_rollover_due(&self) -> bool {
+        let is_full = self
+            .rollover
+            .max_bytes
+            .is_some_and(|max_bytes| self.bytes.load(Ordering::Relaxed) >= max_bytes);
+
+        is_full
+            || (self.rollover.interval.is_some()
+                && self.start.elapsed().as_nanos() as u64
+                    >= self.next_rollover.load(Ordering::Relaxed))
+    }
+
+    fn schedule_next_rollover(&self) {
+        let next_rollover = match self.rollover.interval {
+            Some(interval) => (self.start.elapsed() + interval).as_nanos() as u64,
+            None => u64::MAX,
+        };
+        self.next_rollover.store(next_rollover, Ordering::Relaxed);
+    }
+
+    #[cold]
+    fn roll_over(&self) -> bool {
+        let mut current = self.current.write();
+
+        // Another thread may have rolled over while this one waited for the
+        // lock.
+        if !self.is_rollover_due() {
+            return false;
+        }
+
+        // Whether or not the new segment can be created, the current one
+        // counts as new, so that a failure isn't retried with every event.
+        self.bytes.store(0, Ordering::Relaxed);
+        self.schedule_next_rollover();
+
+        let mut file_names = self.file_names.lock();
+        let path = self.files.segment_events_file(file_names.len());
+        let sink = match S::from_config(&path, ProfileFileKind::Events, &self.config) {
+            Ok(sink) => sink,
+            Err(_) => return false,
+        };
+        write_file_header_with_features(
+            &sink,
+            self.config.timestamp_format.file_magic(),
+            self.feature_flags,
+            self.session_id,
+        );
+
+        let previous = std::mem::replace(&mut *current, sink);
+        file_names.push(file_name(&path));
+
+        let mut finished = self.finished.lock();
+        finished.stats = finished.stats + previous.stats();
+        finished.dropped_writes += previous.dropped_writes();
+        finished.write_error = finished
+            .write_error
+            .take()
+            .or_else(|| previous.write_error());
+        true
+    }
+
+    /// The names of the segments created so far, for the metadata.
+    pub fn file_names(&self) -> Vec<String> {
+        self.file_names.lock().clone()
+    }
+
+    /// The paths of all segments but the current one.
+    pub fn finished_paths(&self) -> Vec<PathBuf> {
+        let file_names = self.file_names.lock();
+        (0..file_names.len() - 1)
+            .map(|index| self.files.segment_events_file(index))
+            .collect()
+    }
+
+    #[inline]
+    pub fn has_failed(&self) -> bool {
+        self.current.read().has_failed()
+    }
+
+    // These don't hold `finished` while locking `current`, which
+    // `roll_over()` locks the other way around.
+
+    pub fn write_error(&self) -> Option<WriteError> {
+        let finished = self.finished.lock().write_error.clone();
+        finished.or_else(|| self.current.read().write_error())
+    }
+
+    pub fn dropped_writes(&self) -> u64 {
+        let finished = self.finished.lock().dropped_writes;
+        finished + self.current.read().dropped_writes()
+    }
+
+    pub fn stats(&self) -> SinkStats {
+        let finished = self.finished.lock().stats;
+        finished + self.current.read().stats()
+    }
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name().unwrap().to_string_lossy().into_owned()
+}
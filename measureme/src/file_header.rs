@@ -1,35 +1,265 @@
 //! All binary files generated by measureme have a simple file header that
-//! consists of a 4 byte file magic string and a 4 byte little-endian version
-//! number.
+//! consists of
+//!
+//! - a 4 byte file magic string,
+//! - the 4 byte little-endian version of the file format the file has been
+//!   written with,
+//! - the 4 byte little-endian minimum file format version a reader has to
+//!   support in order to read the file, and
+//! - a 4 byte little-endian word of feature flags, which tell what optional
+//!   capabilities the file makes use of, and
+//! - the 16 byte little-endian id of the profiling session that wrote the
+//!   file.
+//!
+//! Readers accept files with a newer `version` as long as they support the
+//! `min_reader_version` and all *required* features (the lower 16 bits of the
+//! feature flags). That way, newer writers can add things that older readers
+//! can safely ignore (like an optional section, signalled by one of the upper
+//! 16 bits) without a hard break, and older readers that can't read a file
+//! can tell which capability they lack. Files written before format version
+//! 10 only have the magic and the version, files of version 10 don't have a
+//! session id.
+//!
+//! All files of a profile (and of a `SharedStringCache`) share a randomly
+//! generated session id, see `new_session_id()`. Readers compare the ids, so
+//! that e.g. a `.string_data` file that is combined with the `.string_index`
+//! file of a different run is rejected instead of producing garbled labels.
+//!
+//! They also end with a file footer that consists of another 4 byte file
+//! magic string and the 8 byte little-endian checksum (see the `checksum`
+//! module) of everything before the footer, including the header. The
+//! footer is written by the `SerializationSink` when it is dropped, and lets
+//! readers detect files that have been damaged after they were written, e.g.
+//! while being copied around.
+//!
+//! The functions that check headers and footers return a
+//! `diagnostics::LoadError`, whose kind tells why a file can't be read, e.g.
+//! `LoadErrorKind::Truncated` for a file without footer.
 
+use crate::checksum::checksum;
+use crate::diagnostics::{LoadError, LoadErrorKind};
 use crate::serialization::SerializationSink;
 use byteorder::{ByteOrder, LittleEndian};
+use std::collections::hash_map::RandomState;
 use std::error::Error;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::{SystemTime, UNIX_EPOCH};
 
-pub const CURRENT_FILE_FORMAT_VERSION: u32 = 5;
+pub const CURRENT_FILE_FORMAT_VERSION: u32 = 13;
+/// The `min_reader_version` of the files written by this version of
+/// measureme. Only increment it for changes that older readers of the
+/// current version can't cope with.
+pub const MIN_READER_FORMAT_VERSION: u32 = 13;
 pub const FILE_MAGIC_EVENT_STREAM: &[u8; 4] = b"MMES";
+/// The events file of a profile with `TimestampFormat::Wide` events.
+pub const FILE_MAGIC_EVENT_STREAM_WIDE: &[u8; 4] = b"MMEW";
 pub const FILE_MAGIC_STRINGTABLE_DATA: &[u8; 4] = b"MMSD";
 pub const FILE_MAGIC_STRINGTABLE_INDEX: &[u8; 4] = b"MMSI";
+pub const FILE_MAGIC_FOOTER: &[u8; 4] = b"MMCS";
+/// See the `encryption` module.
+pub const FILE_MAGIC_ENCRYPTED: &[u8; 4] = b"MMEN";
 
 /// The size of the file header in bytes. Note that functions in this module
-/// rely on this size to be `8`.
-pub const FILE_HEADER_SIZE: usize = 8;
+/// rely on this size to be `32`.
+pub const FILE_HEADER_SIZE: usize = 32;
 
-pub fn write_file_header<S: SerializationSink>(s: &S, file_magic: &[u8; 4]) {
-    // The implementation here relies on FILE_HEADER_SIZE to have the value 8.
+/// The size of the header of files written with format version 10.
+const FILE_HEADER_SIZE_V10: usize = 16;
+
+pub const FILE_FOOTER_SIZE: usize = 12;
+
+/// The events of the file have 64 bit timestamps (`TimestampFormat::Wide`).
+pub const FEATURE_WIDE_TIMESTAMPS: u32 = 1 << 0;
+
+/// The events of the file may refer to event kinds registered in the
+/// profile's metadata (see `Profiler::register_event_kind()`) instead of to
+/// the string table.
+pub const FEATURE_REGISTERED_EVENT_KINDS: u32 = 1 << 1;
+
+/// The events of the file may be blocked intervals, see
+/// `RawEvent::as_blocked()`.
+pub const FEATURE_BLOCKED_INTERVALS: u32 = 1 << 2;
+
+/// The timestamps of the events of the file are in microseconds instead of
+/// nanoseconds (`TimestampResolution::Microseconds`). Readers that don't
+/// know about it would show all times a thousand times too short, so this
+/// is a required feature.
+pub const FEATURE_MICROSECOND_TIMESTAMPS: u32 = 1 << 3;
+
+/// The compact events of the file may be integer events, whose payloads
+/// take the upper half of the range of end timestamps
+/// (`TimestampFormat::Compact`). Without it, that half belongs to intervals
+/// (`TimestampFormat::CompactWithoutIntegers`). Wide events can be integer
+/// events either way.
+pub const FEATURE_INTEGER_EVENTS: u32 = 1 << 4;
+
+/// The wide events of the file carry the CPU they started on, see
+/// `RawEvent::cpu()`. This is an optional feature: readers that don't know
+/// about it just don't see the CPUs.
+pub const FEATURE_CPU_IDS: u32 = 1 << 16;
+
+/// Features in these bits must be supported by the reader.
+pub const REQUIRED_FEATURES_MASK: u32 = 0x0000_ffff;
+
+/// The features this version of measureme knows about.
+pub const KNOWN_FEATURES: u32 = FEATURE_WIDE_TIMESTAMPS
+    | FEATURE_REGISTERED_EVENT_KINDS
+    | FEATURE_BLOCKED_INTERVALS
+    | FEATURE_MICROSECOND_TIMESTAMPS
+    | FEATURE_INTEGER_EVENTS
+    | FEATURE_CPU_IDS;
+
+/// Returns a description of the feature with the given bit, for error
+/// messages.
+pub fn feature_name(feature: u32) -> String {
+    match feature {
+        FEATURE_WIDE_TIMESTAMPS => "wide timestamps".to_string(),
+        FEATURE_REGISTERED_EVENT_KINDS => "registered event kinds".to_string(),
+        FEATURE_BLOCKED_INTERVALS => "blocked intervals".to_string(),
+        FEATURE_MICROSECOND_TIMESTAMPS => "microsecond timestamps".to_string(),
+        FEATURE_INTEGER_EVENTS => "integer events".to_string(),
+        FEATURE_CPU_IDS => "CPU ids".to_string(),
+        _ => format!("unknown feature (bit {})", feature.trailing_zeros()),
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FileHeader {
+    pub version: u32,
+    pub min_reader_version: u32,
+    pub feature_flags: u32,
+    /// `0` for files written before format version 11.
+    pub session_id: u128,
+}
+
+impl FileHeader {
+    /// Returns the required features of the file that this version of
+    /// measureme doesn't support.
+    pub fn unsupported_features(&self) -> u32 {
+        self.feature_flags & REQUIRED_FEATURES_MASK & !KNOWN_FEATURES
+    }
+
+    /// Checks that a reader for `CURRENT_FILE_FORMAT_VERSION` can read the
+    /// file, and otherwise tells which capability it lacks. This doesn't
+    /// check for files that are too *old*.
+    pub fn check_readable(&self) -> Result<(), Box<dyn Error>> {
+        let unsupported = self.unsupported_features();
+
+        if self.min_reader_version <= CURRENT_FILE_FORMAT_VERSION && unsupported == 0 {
+            return Ok(());
+        }
+
+        let mut msg = format!("file format version '{}'", self.version);
+
+        if self.min_reader_version > CURRENT_FILE_FORMAT_VERSION {
+            msg += &format!(
+                " requires a reader for version '{}' or later, but this version of \
+                 `measureme` reads version '{}'",
+                self.min_reader_version, CURRENT_FILE_FORMAT_VERSION
+            );
+        } else {
+            msg += " is readable by this version of `measureme`";
+        }
+
+        if unsupported != 0 {
+            let features: Vec<String> = (0..32)
+                .map(|bit| 1 << bit)
+                .filter(|feature| unsupported & feature != 0)
+                .map(feature_name)
+                .collect();
+
+            msg += &format!(
+                ", but the file uses features it doesn't support: {}",
+                features.join(", ")
+            );
+        }
+
+        Err(From::from(LoadError::new(LoadErrorKind::FormatTooNew, msg)))
+    }
+}
+
+/// The session id of the files of profiles recorded with
+/// `ProfilerConfig::deterministic`, which can't be random. Readers can't tell
+/// such files of different runs apart.
+pub const DETERMINISTIC_SESSION_ID: u128 = 1;
+
+/// Returns a new random session id, which is never `0`.
+///
+/// This doesn't need to be cryptographically secure, only different for
+/// every profiler, so it is derived from the randomly seeded keys of the
+/// standard library's `HashMap`, the time, the process id and a counter.
+pub fn new_session_id() -> u128 {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    let count = COUNTER.fetch_add(1, Ordering::Relaxed);
+    let hash = |half: u64| {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u64(half);
+        hasher.write_u64(count);
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            hasher.write_u32(std::process::id());
+            if let Ok(time) = SystemTime::now().duration_since(UNIX_EPOCH) {
+                hasher.write_u128(time.as_nanos());
+            }
+        }
+        hasher.finish()
+    };
+
+    let id = (hash(0) as u128) << 64 | hash(1) as u128;
+    id.max(1)
+}
+
+pub fn write_file_header<S: SerializationSink>(s: &S, file_magic: &[u8; 4], session_id: u128) {
+    write_file_header_with_features(s, file_magic, 0, session_id);
+}
+
+pub fn write_file_header_with_features<S: SerializationSink>(
+    s: &S,
+    file_magic: &[u8; 4],
+    feature_flags: u32,
+    session_id: u128,
+) {
+    // The implementation here relies on FILE_HEADER_SIZE to have the value 32.
     // Let's make sure this assumption cannot be violated without being noticed.
-    assert_eq!(FILE_HEADER_SIZE, 8);
+    assert_eq!(FILE_HEADER_SIZE, 32);
 
     s.write_atomic(FILE_HEADER_SIZE, |bytes| {
         bytes[0..4].copy_from_slice(file_magic);
         LittleEndian::write_u32(&mut bytes[4..8], CURRENT_FILE_FORMAT_VERSION);
+        LittleEndian::write_u32(&mut bytes[8..12], MIN_READER_FORMAT_VERSION);
+        LittleEndian::write_u32(&mut bytes[12..16], feature_flags);
+        LittleEndian::write_u128(&mut bytes[16..32], session_id);
     });
 }
 
+/// Checks the file magic and returns the file format version.
 pub fn read_file_header(bytes: &[u8], expected_magic: &[u8; 4]) -> Result<u32, Box<dyn Error>> {
-    // The implementation here relies on FILE_HEADER_SIZE to have the value 8.
+    Ok(read_full_file_header(bytes, expected_magic)?.version)
+}
+
+/// Checks the file magic and returns the whole header. For files written
+/// before format version 10, the `min_reader_version` is their version and
+/// the feature flags are empty, and files written before version 11 have
+/// the session id `0`.
+pub fn read_full_file_header(
+    bytes: &[u8],
+    expected_magic: &[u8; 4],
+) -> Result<FileHeader, Box<dyn Error>> {
+    // The implementation here relies on FILE_HEADER_SIZE to have the value 32.
     // Let's make sure this assumption cannot be violated without being noticed.
-    assert_eq!(FILE_HEADER_SIZE, 8);
+    assert_eq!(FILE_HEADER_SIZE, 32);
+
+    // The magic and the version, the rest of the header depends on the
+    // version.
+    if bytes.len() < 8 {
+        return Err(From::from(LoadError::new(
+            LoadErrorKind::Truncated,
+            "the file is too short to be a measureme file",
+        )));
+    }
 
     let actual_magic = &bytes[0..4];
 
@@ -41,16 +271,84 @@ pub fn read_file_header(bytes: &[u8], expected_magic: &[u8; 4]) -> Result<u32, B
             actual_magic, expected_magic,
         );
 
-        return Err(From::from(msg));
+        return Err(From::from(LoadError::new(LoadErrorKind::NotAProfile, msg)));
     }
 
-    Ok(LittleEndian::read_u32(&bytes[4..8]))
+    let version = LittleEndian::read_u32(&bytes[4..8]);
+
+    if version < 10 {
+        return Ok(FileHeader {
+            version,
+            min_reader_version: version,
+            feature_flags: 0,
+            session_id: 0,
+        });
+    }
+
+    let header_size = if version < 11 {
+        FILE_HEADER_SIZE_V10
+    } else {
+        FILE_HEADER_SIZE
+    };
+
+    if bytes.len() < header_size {
+        // With the right magic, only the rest of the header can be missing.
+        return Err(From::from(LoadError::new(
+            LoadErrorKind::Truncated,
+            "the file header is incomplete",
+        )));
+    }
+
+    Ok(FileHeader {
+        version,
+        min_reader_version: LittleEndian::read_u32(&bytes[8..12]),
+        feature_flags: LittleEndian::read_u32(&bytes[12..16]),
+        session_id: if version < 11 {
+            0
+        } else {
+            LittleEndian::read_u128(&bytes[16..32])
+        },
+    })
 }
 
 pub fn strip_file_header(data: &[u8]) -> &[u8] {
     &data[FILE_HEADER_SIZE..]
 }
 
+/// Returns the footer for a file whose contents have the given checksum.
+pub fn file_footer(checksum: u64) -> [u8; FILE_FOOTER_SIZE] {
+    let mut footer = [0; FILE_FOOTER_SIZE];
+    footer[0..4].copy_from_slice(FILE_MAGIC_FOOTER);
+    LittleEndian::write_u64(&mut footer[4..12], checksum);
+    footer
+}
+
+/// Checks that `data` ends with a file footer holding the checksum of the
+/// rest of the data, and returns the data without the footer.
+pub fn verify_file_footer(data: &[u8]) -> Result<&[u8], Box<dyn Error>> {
+    if data.len() < FILE_FOOTER_SIZE
+        || &data[data.len() - FILE_FOOTER_SIZE..][..4] != FILE_MAGIC_FOOTER
+    {
+        return Err(From::from(LoadError::new(
+            LoadErrorKind::Truncated,
+            "file corrupted in transit: the file footer is missing, \
+             the file is probably incomplete",
+        )));
+    }
+
+    let (contents, footer) = data.split_at(data.len() - FILE_FOOTER_SIZE);
+    let expected_checksum = LittleEndian::read_u64(&footer[4..12]);
+
+    if checksum(contents) != expected_checksum {
+        return Err(From::from(LoadError::new(
+            LoadErrorKind::Corrupt,
+            "file corrupted in transit: the checksum does not match the file contents",
+        )));
+    }
+
+    Ok(contents)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -60,7 +358,7 @@ mod tests {
     fn roundtrip() {
         let data_sink = ByteVecSink::new();
 
-        write_file_header(&data_sink, FILE_MAGIC_EVENT_STREAM);
+        write_file_header(&data_sink, FILE_MAGIC_EVENT_STREAM, 1);
 
         let data = data_sink.into_bytes();
 
@@ -73,7 +371,7 @@ mod tests {
     #[test]
     fn invalid_magic() {
         let data_sink = ByteVecSink::new();
-        write_file_header(&data_sink, FILE_MAGIC_STRINGTABLE_DATA);
+        write_file_header(&data_sink, FILE_MAGIC_STRINGTABLE_DATA, 1);
         let mut data = data_sink.into_bytes();
 
         // Invalidate the filemagic
@@ -85,7 +383,7 @@ mod tests {
     fn other_version() {
         let data_sink = ByteVecSink::new();
 
-        write_file_header(&data_sink, FILE_MAGIC_STRINGTABLE_INDEX);
+        write_file_header(&data_sink, FILE_MAGIC_STRINGTABLE_INDEX, 1);
 
         let mut data = data_sink.into_bytes();
 
@@ -99,4 +397,103 @@ mod tests {
             0xFFFF_FFFF
         );
     }
+
+    #[test]
+    fn feature_flags() {
+        let data_sink = ByteVecSink::new();
+        write_file_header_with_features(
+            &data_sink,
+            FILE_MAGIC_EVENT_STREAM_WIDE,
+            FEATURE_WIDE_TIMESTAMPS,
+            0x1234_5678_9abc_def0_1234_5678_9abc_def0,
+        );
+        let mut data = data_sink.into_bytes();
+
+        let header = read_full_file_header(&data, FILE_MAGIC_EVENT_STREAM_WIDE).unwrap();
+        assert_eq!(
+            header,
+            FileHeader {
+                version: CURRENT_FILE_FORMAT_VERSION,
+                min_reader_version: MIN_READER_FORMAT_VERSION,
+                feature_flags: FEATURE_WIDE_TIMESTAMPS,
+                session_id: 0x1234_5678_9abc_def0_1234_5678_9abc_def0,
+            }
+        );
+        assert!(header.check_readable().is_ok());
+
+        // A newer version with an unknown optional feature is still readable.
+        LittleEndian::write_u32(&mut data[4..8], CURRENT_FILE_FORMAT_VERSION + 1);
+        LittleEndian::write_u32(&mut data[12..16], FEATURE_WIDE_TIMESTAMPS | 1 << 20);
+        let header = read_full_file_header(&data, FILE_MAGIC_EVENT_STREAM_WIDE).unwrap();
+        assert!(header.check_readable().is_ok());
+
+        // An unknown required feature is not.
+        LittleEndian::write_u32(&mut data[12..16], 1 << 5);
+        let header = read_full_file_header(&data, FILE_MAGIC_EVENT_STREAM_WIDE).unwrap();
+        let error = header.check_readable().unwrap_err().to_string();
+        assert!(error.ends_with("unknown feature (bit 5)"), "{}", error);
+
+        // Neither is a file that needs a newer reader.
+        LittleEndian::write_u32(&mut data[8..12], CURRENT_FILE_FORMAT_VERSION + 1);
+        LittleEndian::write_u32(&mut data[12..16], 0);
+        let header = read_full_file_header(&data, FILE_MAGIC_EVENT_STREAM_WIDE).unwrap();
+        assert!(header.check_readable().is_err());
+    }
+
+    #[test]
+    fn old_header() {
+        let data = b"MMSD\x09\x00\x00\x00";
+        assert_eq!(
+            read_full_file_header(data, FILE_MAGIC_STRINGTABLE_DATA).unwrap(),
+            FileHeader {
+                version: 9,
+                min_reader_version: 9,
+                feature_flags: 0,
+                session_id: 0,
+            }
+        );
+
+        // Version 10 headers don't have a session id.
+        let data = b"MMSD\x0a\x00\x00\x00\x0a\x00\x00\x00\x01\x00\x00\x00";
+        assert_eq!(
+            read_full_file_header(data, FILE_MAGIC_STRINGTABLE_DATA).unwrap(),
+            FileHeader {
+                version: 10,
+                min_reader_version: 10,
+                feature_flags: FEATURE_WIDE_TIMESTAMPS,
+                session_id: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn session_ids() {
+        let ids: Vec<u128> = (0..100).map(|_| new_session_id()).collect();
+        let mut unique = ids.clone();
+        unique.sort_unstable();
+        unique.dedup();
+        assert_eq!(unique.len(), ids.len());
+        assert!(!ids.contains(&0));
+    }
+
+    #[test]
+    fn footer() {
+        let mut data = b"some file contents".to_vec();
+        data.extend_from_slice(&file_footer(checksum(&data)));
+
+        assert_eq!(verify_file_footer(&data).unwrap(), b"some file contents");
+
+        // Flip a bit in the contents.
+        let mut damaged = data.clone();
+        damaged[3] ^= 0x10;
+        let kind = |data: &[u8]| LoadError::kind_of(&*verify_file_footer(data).unwrap_err());
+        assert_eq!(kind(&damaged), Some(LoadErrorKind::Corrupt));
+
+        // Cut off the end.
+        assert_eq!(
+            kind(&data[..data.len() - 1]),
+            Some(LoadErrorKind::Truncated)
+        );
+        assert_eq!(kind(&[]), Some(LoadErrorKind::Truncated));
+    }
 }
@@ -1,35 +1,179 @@
-use crate::serialization::{Addr, SerializationSink};
-use parking_lot::Mutex;
+use crate::checksum::Checksum;
+use crate::config::{FileSinkConfig, ProfilerConfig};
+use crate::diagnostics::Diagnostic;
+use crate::file_header::file_footer;
+use crate::serialization::{Addr, ProfileFileKind, SerializationSink, SinkStats, WriteError};
+use crate::sync::Mutex;
 use std::error::Error;
 use std::fs;
-use std::io::Write;
+use std::io::{Seek, SeekFrom, Write};
 use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
 
 pub struct FileSerializationSink {
     data: Mutex<Inner>,
+    // Mirrors `Output::error.is_some()` so that `has_failed()` doesn't need
+    // to take the lock.
+    failed: AtomicBool,
 }
 
 struct Inner {
-    file: fs::File,
+    output: Output,
     buffer: Vec<u8>,
     buf_pos: usize,
-    addr: u32,
+    addr: u64,
+    write_behind_threshold: Option<usize>,
 }
 
-impl SerializationSink for FileSerializationSink {
-    fn from_path(path: &Path) -> Result<Self, Box<dyn Error>> {
-        fs::create_dir_all(path.parent().unwrap())?;
+struct Output {
+    file: fs::File,
+    error: Option<WriteError>,
+    // The checksum of everything written to the file so far.
+    checksum: Checksum,
+    stats: SinkStats,
+    // The size of the file once everything has been written to it.
+    len: u64,
+    // See `FileSinkConfig::preallocate`. Set to zero if the filesystem
+    // doesn't support preallocation.
+    preallocate: u64,
+    // The end of the space reserved for the file so far.
+    allocated: u64,
+}
 
-        let file = fs::File::create(path)?;
+impl Output {
+    fn new(file: fs::File, checksum: Checksum, len: u64, config: &FileSinkConfig) -> Output {
+        Output {
+            file,
+            error: None,
+            checksum,
+            stats: SinkStats::default(),
+            len,
+            preallocate: config.preallocate,
+            allocated: len,
+        }
+    }
+
+    /// Writes `bytes` to the file unless a previous write has failed
+    /// already, in which case the data is discarded. The first error is
+    /// stored in `error`.
+    fn write(&mut self, bytes: &[u8]) {
+        if self.error.is_some() || bytes.is_empty() {
+            return;
+        }
+
+        self.checksum.update(bytes);
+        self.reserve(bytes.len() as u64);
+
+        match self.file.write_all(bytes) {
+            Ok(()) => {
+                self.len += bytes.len() as u64;
+                self.stats.bytes_written += bytes.len() as u64;
+                self.stats.flushes += 1;
+            }
+            Err(e) => self.error = Some(WriteError::from(&e)),
+        }
+    }
+
+    /// Makes sure that the next `num_bytes` are covered by the reserved
+    /// space if preallocation is enabled.
+    fn reserve(&mut self, num_bytes: u64) {
+        let end = self.len + num_bytes;
+        if self.preallocate == 0 || end <= self.allocated {
+            return;
+        }
+
+        let new_allocated = end + self.preallocate;
+        if preallocate(&self.file, self.allocated, new_allocated - self.allocated) {
+            self.allocated = new_allocated;
+        } else {
+            self.preallocate = 0;
+        }
+    }
 
-        Ok(FileSerializationSink {
+    /// Releases the space that has been reserved but not used.
+    fn release_reserved(&mut self) {
+        if self.allocated > self.len {
+            let _ = self.file.set_len(self.len);
+            self.allocated = self.len;
+        }
+    }
+}
+
+/// Reserves `len` bytes of disk space for `file` starting at `offset`,
+/// without changing its size. Returns `false` if that's not supported.
+#[cfg(all(feature = "libc", target_os = "linux"))]
+fn preallocate(file: &fs::File, offset: u64, len: u64) -> bool {
+    use std::os::unix::io::AsRawFd;
+
+    let result = unsafe {
+        libc::fallocate(
+            file.as_raw_fd(),
+            libc::FALLOC_FL_KEEP_SIZE,
+            offset as libc::off_t,
+            len as libc::off_t,
+        )
+    };
+
+    result == 0
+}
+
+#[cfg(not(all(feature = "libc", target_os = "linux")))]
+fn preallocate(_file: &fs::File, _offset: u64, _len: u64) -> bool {
+    false
+}
+
+impl FileSerializationSink {
+    fn new(file: fs::File, checksum: Checksum, len: u64, config: &FileSinkConfig) -> Self {
+        FileSerializationSink {
             data: Mutex::new(Inner {
-                file,
-                buffer: vec![0; 1024 * 512],
+                output: Output::new(file, checksum, len, config),
+                buffer: vec![0; config.buffer_size.max(1)],
                 buf_pos: 0,
-                addr: 0,
+                addr: len,
+                write_behind_threshold: config.write_behind_threshold,
             }),
-        })
+            failed: AtomicBool::new(false),
+        }
+    }
+
+    /// Creates the sink for the file at `path` with the given configuration,
+    /// overwriting the file if it exists.
+    pub fn with_config(path: &Path, config: &FileSinkConfig) -> Result<Self, Box<dyn Error>> {
+        fs::create_dir_all(path.parent().unwrap())?;
+
+        let file = fs::File::create(path)?;
+
+        Ok(FileSerializationSink::new(file, Checksum::new(), 0, config))
+    }
+}
+
+impl SerializationSink for FileSerializationSink {
+    fn from_path(path: &Path) -> Result<Self, Box<dyn Error>> {
+        FileSerializationSink::with_config(path, &FileSinkConfig::default())
+    }
+
+    fn from_config(
+        path: &Path,
+        _file_kind: ProfileFileKind,
+        config: &ProfilerConfig,
+    ) -> Result<Self, Box<dyn Error>> {
+        FileSerializationSink::with_config(path, &config.file_sink)
+    }
+
+    fn from_existing(path: &Path, contents: &[u8]) -> Result<Self, Box<dyn Error>> {
+        let mut file = fs::OpenOptions::new().write(true).open(path)?;
+        file.set_len(contents.len() as u64)?;
+        file.seek(SeekFrom::End(0))?;
+
+        let mut checksum = Checksum::new();
+        checksum.update(contents);
+
+        Ok(FileSerializationSink::new(
+            file,
+            checksum,
+            contents.len() as u64,
+            &FileSinkConfig::default(),
+        ))
     }
 
     #[inline]
@@ -39,14 +183,15 @@ impl SerializationSink for FileSerializationSink {
     {
         let mut data = self.data.lock();
         let Inner {
-            ref mut file,
+            ref mut output,
             ref mut buffer,
             ref mut buf_pos,
             ref mut addr,
+            write_behind_threshold,
         } = *data;
 
         let curr_addr = *addr;
-        *addr += num_bytes as u32;
+        *addr += num_bytes as u64;
 
         let buf_start = *buf_pos;
         let buf_end = buf_start + num_bytes;
@@ -55,9 +200,14 @@ impl SerializationSink for FileSerializationSink {
             // We have enough space in the buffer, just write the data to it.
             write(&mut buffer[buf_start..buf_end]);
             *buf_pos = buf_end;
+
+            if write_behind_threshold.is_some_and(|threshold| buf_end >= threshold) {
+                output.write(&buffer[..buf_end]);
+                *buf_pos = 0;
+            }
         } else {
             // We don't have enough space in the buffer, so flush to disk
-            file.write_all(&buffer[..buf_start]).unwrap();
+            output.write(&buffer[..buf_start]);
 
             if num_bytes <= buffer.len() {
                 // There's enough space in the buffer, after flushing
@@ -68,11 +218,15 @@ impl SerializationSink for FileSerializationSink {
                 // fall back to dynamic allocation
                 let mut temp_buffer = vec![0; num_bytes];
                 write(&mut temp_buffer[..]);
-                file.write_all(&temp_buffer[..]).unwrap();
+                output.write(&temp_buffer[..]);
                 *buf_pos = 0;
             }
         }
 
+        if output.error.is_some() {
+            self.failed.store(true, Ordering::Relaxed);
+        }
+
         Addr(curr_addr)
     }
 
@@ -85,40 +239,144 @@ impl SerializationSink for FileSerializationSink {
 
         let mut data = self.data.lock();
         let Inner {
-            ref mut file,
+            ref mut output,
             ref mut buffer,
             ref mut buf_pos,
             ref mut addr,
+            write_behind_threshold: _,
         } = *data;
 
         let curr_addr = *addr;
-        *addr += bytes.len() as u32;
+        *addr += bytes.len() as u64;
 
         if *buf_pos > 0 {
             // There's something in the buffer, flush it to disk
-            file.write_all(&buffer[..*buf_pos]).unwrap();
+            output.write(&buffer[..*buf_pos]);
             *buf_pos = 0;
         }
 
         // Now write the whole input to disk, skipping the write buffer
-        file.write_all(bytes).unwrap();
+        output.write(bytes);
+
+        if output.error.is_some() {
+            self.failed.store(true, Ordering::Relaxed);
+        }
 
         Addr(curr_addr)
     }
+
+    #[inline]
+    fn has_failed(&self) -> bool {
+        self.failed.load(Ordering::Relaxed)
+    }
+
+    fn write_error(&self) -> Option<WriteError> {
+        self.data.lock().output.error.clone()
+    }
+
+    fn stats(&self) -> SinkStats {
+        self.data.lock().output.stats
+    }
 }
 
 impl Drop for FileSerializationSink {
     fn drop(&mut self) {
         let mut data = self.data.lock();
         let Inner {
-            ref mut file,
+            ref mut output,
             ref mut buffer,
             ref mut buf_pos,
-            addr: _,
+            ..
         } = *data;
 
         if *buf_pos > 0 {
-            file.write_all(&buffer[..*buf_pos]).unwrap();
+            output.write(&buffer[..*buf_pos]);
         }
+
+        let footer = file_footer(output.checksum.finish());
+        output.write(&footer);
+        output.release_reserved();
+
+        if let Some(error) = &output.error {
+            Diagnostic::from_error(error).log();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Writing to `/dev/full` always fails with `ENOSPC`.
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn disk_full() {
+        let sink = FileSerializationSink::from_path(Path::new("/dev/full")).unwrap();
+
+        // Small writes just end up in the buffer.
+        sink.write_bytes_atomic(&[1, 2, 3]);
+        assert!(!sink.has_failed());
+
+        // This one doesn't fit into the buffer anymore and forces a flush.
+        let addr = sink.write_bytes_atomic(&vec![0; 1024 * 1024]);
+        assert_eq!(addr, Addr(3));
+        assert!(sink.has_failed());
+        assert_eq!(
+            sink.write_error().map(|e| e.kind),
+            Some(std::io::ErrorKind::StorageFull)
+        );
+
+        // Further writes are discarded but still get proper addresses.
+        assert_eq!(sink.write_bytes_atomic(&[4, 5]), Addr(3 + 1024 * 1024));
+    }
+
+    #[test]
+    fn stats_and_preallocation() {
+        let dir = std::env::temp_dir().join(format!("measureme-file-sink-{}", std::process::id()));
+        let path = dir.join("stats.events");
+        let config = FileSinkConfig {
+            buffer_size: 16,
+            write_behind_threshold: Some(8),
+            preallocate: 1024 * 1024,
+        };
+
+        let sink = FileSerializationSink::with_config(&path, &config).unwrap();
+
+        // Stays in the buffer.
+        sink.write_bytes_atomic(&[1; 4]);
+        assert_eq!(sink.stats(), SinkStats::default());
+
+        // Reaches the write-behind threshold.
+        sink.write_bytes_atomic(&[2; 4]);
+        assert_eq!(
+            sink.stats(),
+            SinkStats {
+                bytes_written: 8,
+                flushes: 1
+            }
+        );
+
+        // Too large for the buffer.
+        sink.write_bytes_atomic(&[3; 200]);
+        assert_eq!(
+            sink.stats(),
+            SinkStats {
+                bytes_written: 208,
+                flushes: 2
+            }
+        );
+
+        drop(sink);
+
+        // The reserved space beyond the data has been released.
+        let contents = fs::read(&path).unwrap();
+        assert_eq!(
+            contents.len() as u64,
+            208 + crate::file_header::FILE_FOOTER_SIZE as u64
+        );
+        assert_eq!(&contents[..8], &[1, 1, 1, 1, 2, 2, 2, 2]);
+        assert_eq!(fs::metadata(&path).unwrap().len(), contents.len() as u64);
+
+        fs::remove_dir_all(&dir).unwrap();
     }
 }
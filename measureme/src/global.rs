@@ -0,0 +1,218 @@
+//! A process-wide profiler for applications that want to record events from
+//! anywhere in their code without handing a `Profiler` and a thread id to
+//! every function, like the analysis tools in this repository do with
+//! `--self-profile`.
+//!
+//! [`init()`] creates the profiler, [`activity!`] records an interval event
+//! from where it is invoked to the end of the enclosing scope, on whichever
+//! thread it runs on, and [`finish()`] removes the profiler again. Activities
+//! that are still running keep the profiler alive, so its files are complete
+//! once the last of them has ended. Without a profiler, an activity costs a
+//! read lock and nothing else, so the instrumentation can stay in the code:
+//!
+//! ```ignore
+//! fn load(path: &Path) -> Result<Data, Box<dyn Error>> {
+//!     measureme::activity!("load");
+//!     ...
+//! }
+//!
+//! global::init(Path::new("tool"), &ProfilerConfig::default())?;
+//! load(path)?;
+//! global::finish();
+//! ```
+//!
+//! [`profile_scope!`] works like `activity!`, but records the file, line and
+//! column of its invocation as the argument of the activity, so that tools
+//! can show where a hot activity comes from, e.g. as the `location` argument
+//! of the events `crox` exports.
+//!
+//! With the `disabled` cargo feature, `init()` doesn't create a profiler, so
+//! nothing is recorded at all.
+//!
+//! [`activity!`]: ../macro.activity.html
+//! [`finish()`]: fn.finish.html
+//! [`init()`]: fn.init.html
+//! [`profile_scope!`]: ../macro.profile_scope.html
+
+use crate::arg_schema::{ArgType, SourceLocation};
+use crate::config::ProfilerConfig;
+use crate::event_id::{EventId, SEPARATOR_BYTE};
+use crate::profiler::{Profiler, ToolInfo};
+use crate::raw_event::RawEvent;
+use crate::rustc::GENERIC_ACTIVITY_EVENT_KIND;
+use crate::stringtable::{StringComponent, StringId};
+use crate::FileSerializationSink;
+use std::error::Error;
+use std::path::Path;
+use std::sync::{Arc, PoisonError, RwLock};
+
+/// The event kind of activities recorded without one, which is rustc's
+/// `GenericActivity`, so that the analysis tools summarize them like the
+/// activities of rustc.
+pub const ACTIVITY_EVENT_KIND: &str = GENERIC_ACTIVITY_EVENT_KIND;
+
+static GLOBAL: RwLock<Option<Arc<Profiler<FileSerializationSink>>>> = RwLock::new(None);
+
+/// Creates the global profiler, writing to the profile at
+/// `config.path_stem(path_stem)`. A profiler that has been created before is
+/// finished like by `finish()`.
+pub fn init(path_stem: &Path, config: &ProfilerConfig) -> Result<(), Box<dyn Error>> {
+    if cfg!(feature = "disabled") {
+        return Ok(());
+    }
+
+    let profiler = Arc::new(Profiler::with_config(path_stem, config)?);
+    *GLOBAL.write().unwrap_or_else(PoisonError::into_inner) = Some(profiler);
+    Ok(())
+}
+
+/// Removes the global profiler, which writes the rest of its profile once
+/// the activities that are still running have ended.
+pub fn finish() {
+    let profiler = GLOBAL
+        .write()
+        .unwrap_or_else(PoisonError::into_inner)
+        .take();
+    drop(profiler);
+}
+
+/// Returns `true` if the global profiler has been created and not finished.
+pub fn is_enabled() -> bool {
+    GLOBAL
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .is_some()
+}
+
+/// Records the tool that created the global profiler, see
+/// `Profiler::set_tool_info()`.
+pub fn set_tool_info(tool_info: ToolInfo) {
+    if let Some(profiler) = current() {
+        profiler.set_tool_info(tool_info);
+    }
+}
+
+/// Starts an activity of the given kind, which ends when the returned
+/// `Activity` is dropped. Mostly used via the `activity!` macro.
+pub fn activity(event_kind: &str, label: &str) -> Activity {
+    start_activity(event_kind, label, None)
+}
+
+/// Like `activity()`, but records `location` as the argument of the
+/// activity. This registers the argument schema `[("location",
+/// ArgType::Location)]` for the event kind, so activities of that kind
+/// shouldn't have other arguments. Mostly used via the `profile_scope!`
+/// macro.
+pub fn activity_at(event_kind: &str, label: &str, location: SourceLocation) -> Activity {
+    start_activity(event_kind, label, Some(location))
+}
+
+fn start_activity(event_kind: &str, label: &str, location: Option<SourceLocation>) -> Activity {
+    let profiler = match current() {
+        Some(profiler) => profiler,
+        None => return Activity { running: None },
+    };
+
+    let label = profiler.intern_string(label);
+    let event_id = match location {
+        Some(location) => {
+            profiler.register_arg_schema(event_kind, &[("location", ArgType::Location)]);
+            // As `EventIdBuilder::from_label_and_location()` does, whose
+            // profiler is the no-op one with the `disabled` feature.
+            let location = profiler.intern_string(&location.to_string());
+            EventId(profiler.alloc_string(&[
+                StringComponent::Ref(label),
+                StringComponent::Value(SEPARATOR_BYTE),
+                StringComponent::Ref(location),
+            ]))
+        }
+        None => EventId::from_label(label),
+    };
+    let event_kind = profiler.intern_string(event_kind);
+    let thread_id = profiler.register_current_thread();
+    let cpu = profiler.current_cpu();
+    let start_ns = profiler.nanos_since_start();
+
+    Activity {
+        running: Some(RunningActivity {
+            profiler,
+            event_kind,
+            event_id,
+            thread_id,
+            cpu,
+            start_ns,
+        }),
+    }
+}
+
+fn current() -> Option<Arc<Profiler<FileSerializationSink>>> {
+    GLOBAL
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .clone()
+}
+
+/// Records an activity of the global profiler when dropped, see
+/// `activity()`.
+#[must_use]
+pub struct Activity {
+    running: Option<RunningActivity>,
+}
+
+struct RunningActivity {
+    profiler: Arc<Profiler<FileSerializationSink>>,
+    event_kind: StringId,
+    event_id: EventId,
+    thread_id: u32,
+    cpu: Option<u32>,
+    start_ns: u64,
+}
+
+impl Drop for Activity {
+    fn drop(&mut self) {
+        if let Some(activity) = self.running.take() {
+            let raw_event = RawEvent::new_interval_wide(
+                activity.event_kind,
+                activity.event_id,
+                activity.thread_id,
+                activity.start_ns,
+                activity.profiler.nanos_since_start(),
+            )
+            .with_cpu(activity.cpu);
+
+            activity.profiler.record_raw_event(&raw_event);
+        }
+    }
+}
+
+/// Records an activity of the global profiler from here to the end of the
+/// enclosing scope, see the `global` module. `activity!(label)` records it
+/// with the event kind `GenericActivity`, `activity!(event_kind, label)`
+/// with the given one.
+#[macro_export]
+macro_rules! activity {
+    ($label:expr) => {
+        $crate::activity!($crate::global::ACTIVITY_EVENT_KIND, $label)
+    };
+    ($event_kind:expr, $label:expr) => {
+        let _activity = $crate::global::activity($event_kind, $label);
+    };
+}
+
+/// Like `activity!`, but records the file, line and column of the invocation
+/// as the argument of the activity, see `activity_at()`.
+/// `profile_scope!(label)` records it with the event kind `GenericActivity`,
+/// `profile_scope!(event_kind, label)` with the given one.
+#[macro_export]
+macro_rules! profile_scope {
+    ($label:expr) => {
+        $crate::profile_scope!($crate::global::ACTIVITY_EVENT_KIND, $label)
+    };
+    ($event_kind:expr, $label:expr) => {
+        let _activity = $crate::global::activity_at(
+            $event_kind,
+            $label,
+            $crate::SourceLocation::new(file!(), line!(), column!()),
+        );
+    };
+}
@@ -0,0 +1,9 @@
+//! The hash map the rest of the crate uses: `rustc-hash`'s `FxHashMap`,
+//! which hashes the short keys of the profiler faster, with the `rustc-hash`
+//! feature, which is enabled by default, and `std`'s `HashMap` without it.
+
+#[cfg(feature = "rustc-hash")]
+pub(crate) use rustc_hash::FxHashMap;
+
+#[cfg(not(feature = "rustc-hash"))]
+pub(crate) type FxHashMap<K, V> = std::collections::HashMap<K, V>;
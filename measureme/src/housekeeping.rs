@@ -0,0 +1,304 @@
+//! Applications that record profiles routinely, like IDEs or build servers,
+//! accumulate profiles in their output directory over time. This module
+//! finds and deletes stale profiles: profiles that are older than a given
+//! age, and incomplete profiles (where some of the three files are missing)
+//! whose process isn't running anymore, e.g. because it crashed while
+//! creating the profiler.
+//!
+//! Files are grouped into profiles by their path stem (see `ProfilerFiles`),
+//! including the optional `.summary` file, the `.thread_events` files of
+//! the `thread_event_files` module, the `.segment_events` files of the
+//! `event_segments` module, the `.search_index` files of
+//! `analyzeme::SearchIndex` and the `.analysis` files of
+//! `analyzeme::AnalysisResults`, which don't count towards the three files
+//! of a complete profile.
+//! Since other applications might use the same extensions, a file only
+//! belongs to a profile if it starts with the file magic of its kind (see
+//! the `file_header` module; encrypted files have `FILE_MAGIC_ENCRYPTED`),
+//! and files without a magic, like `.summary` files, only if their path stem
+//! has at least one file that does. Files that fail the check are never
+//! deleted.
+//! The process id is taken from the end of the file name, following rustc's
+//! `<crate name>-<pid>` naming scheme. Profiles of the current process are
+//! never considered stale.
+
+use crate::file_header::{
+    FILE_MAGIC_ENCRYPTED, FILE_MAGIC_EVENT_STREAM, FILE_MAGIC_EVENT_STREAM_WIDE,
+    FILE_MAGIC_STRINGTABLE_DATA, FILE_MAGIC_STRINGTABLE_INDEX,
+};
+use crate::hash_map::FxHashMap;
+use crate::profiler::ProfilerFiles;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// Incomplete profiles are only considered stale after this long without
+/// modification if it can't be determined whether their process is still
+/// running.
+const INCOMPLETE_GRACE_PERIOD: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Clone, Debug, Default)]
+pub struct CleanupOptions {
+    /// Profiles whose files haven't been modified for longer than this are
+    /// stale. `None` keeps complete profiles regardless of their age.
+    pub max_age: Option<Duration>,
+    /// Whether incomplete profiles of processes that aren't running anymore
+    /// are stale.
+    pub remove_incomplete: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StaleReason {
+    TooOld,
+    Incomplete,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StaleProfile {
+    pub path_stem: PathBuf,
+    pub reason: StaleReason,
+    /// The files of the profile that exist.
+    pub files: Vec<PathBuf>,
+}
+
+/// Returns the stale profiles in `dir`, ordered by path stem.
+pub fn find_stale_profiles(dir: &Path, options: &CleanupOptions) -> io::Result<Vec<StaleProfile>> {
+    // The existing files, the most recent modification time and whether
+    // one of the files has a file magic, per path stem.
+    let mut profiles = FxHashMap::<PathBuf, (Vec<PathBuf>, SystemTime, bool)>::default();
+
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+
+        let path_stem = match path.extension().and_then(|e| e.to_str()) {
+            Some("events") | Some("string_data") | Some("string_index") | Some("summary")
+            | Some("search_index") | Some("analysis") => path.with_extension(""),
+            // `<path_stem>.<n>.thread_events` and `<path_stem>.<n>.segment_events`
+            Some("thread_events") | Some("segment_events") => {
+                path.with_extension("").with_extension("")
+            }
+            _ => continue,
+        };
+
+        if !path.is_file() {
+            continue;
+        }
+
+        let magics = file_magics(&path);
+        if !magics.is_empty() && !starts_with_magic(&path, magics)? {
+            continue;
+        }
+
+        let modified = fs::metadata(&path)?.modified()?;
+        let (files, last_modified, has_magic) = profiles
+            .entry(path_stem)
+            .or_insert_with(|| (Vec::new(), modified, false));
+
+        files.push(path);
+        *last_modified = (*last_modified).max(modified);
+        *has_magic |= !magics.is_empty();
+    }
+
+    let now = SystemTime::now();
+    let mut stale = Vec::new();
+
+    for (path_stem, (mut files, last_modified, has_magic)) in profiles {
+        if !has_magic {
+            continue;
+        }
+
+        let pid = process_id(&path_stem);
+        if pid == Some(std::process::id()) {
+            continue;
+        }
+
+        let age = now.duration_since(last_modified).unwrap_or_default();
+        let expected = ProfilerFiles::new(&path_stem);
+        let is_complete = [
+            &expected.events_file,
+            &expected.string_data_file,
+            &expected.string_index_file,
+        ]
+        .iter()
+        .all(|file| files.contains(file));
+
+        let reason = if options.max_age.is_some_and(|max_age| age > max_age) {
+            StaleReason::TooOld
+        } else if !is_complete && options.remove_incomplete {
+            let abandoned = match pid.and_then(is_process_running) {
+                Some(running) => !running,
+                None => age > INCOMPLETE_GRACE_PERIOD,
+            };
+
+            if !abandoned {
+                continue;
+            }
+
+            StaleReason::Incomplete
+        } else {
+            continue;
+        };
+
+        files.sort();
+        stale.push(StaleProfile {
+            path_stem,
+            reason,
+            files,
+        });
+    }
+
+    stale.sort_by(|a, b| a.path_stem.cmp(&b.path_stem));
+
+    Ok(stale)
+}
+
+/// Deletes the stale profiles in `dir` and returns them.
+pub fn remove_stale_profiles(
+    dir: &Path,
+    options: &CleanupOptions,
+) -> io::Result<Vec<StaleProfile>> {
+    let stale = find_stale_profiles(dir, options)?;
+
+    for profile in &stale {
+        for file in &profile.files {
+            match fs::remove_file(file) {
+                // Another process might be cleaning up at the same time.
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => {}
+                result => result?,
+            }
+        }
+    }
+
+    Ok(stale)
+}
+
+/// The file magics a file of a profile with the extension of `path` can
+/// start with. Empty for the files that don't have one.
+fn file_magics(path: &Path) -> &'static [&'static [u8; 4]] {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("events") | Some("thread_events") | Some("segment_events") => &[
+            FILE_MAGIC_EVENT_STREAM,
+            FILE_MAGIC_EVENT_STREAM_WIDE,
+            FILE_MAGIC_ENCRYPTED,
+        ],
+        Some("string_data") => &[FILE_MAGIC_STRINGTABLE_DATA, FILE_MAGIC_ENCRYPTED],
+        // `analyzeme::SearchIndex` shares the magic of the string index.
+        Some("string_index") | Some("search_index") => {
+            &[FILE_MAGIC_STRINGTABLE_INDEX, FILE_MAGIC_ENCRYPTED]
+        }
+        _ => &[],
+    }
+}
+
+fn starts_with_magic(path: &Path, magics: &[&[u8; 4]]) -> io::Result<bool> {
+    let mut magic = [0; 4];
+    match fs::File::open(path)?.read_exact(&mut magic) {
+        Ok(()) => Ok(magics.contains(&&magic)),
+        Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+fn process_id(path_stem: &Path) -> Option<u32> {
+    let file_name = path_stem.file_name()?.to_str()?;
+    let (_, pid) = file_name.split_at(file_name.rfind('-')? + 1);
+    pid.parse().ok()
+}
+
+/// Returns whether the process with the given id is running, or `None` if
+/// that can't be determined on this platform.
+fn is_process_running(pid: u32) -> Option<bool> {
+    if cfg!(target_os = "linux") {
+        Some(Path::new("/proc").join(pid.to_string()).exists())
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn process_ids() {
+        assert_eq!(process_id(Path::new("out/regex-4711")), Some(4711));
+        assert_eq!(process_id(Path::new("out/my-crate-12")), Some(12));
+        assert_eq!(process_id(Path::new("out/profile")), None);
+        assert_eq!(process_id(Path::new("out/profile-x")), None);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn stale_profiles() {
+        let dir =
+            std::env::temp_dir().join(format!("measureme-housekeeping-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let create = |stem: &str, extensions: &[&str]| {
+            for extension in extensions {
+                let path = dir.join(stem).with_extension(extension);
+                let magic = file_magics(&path)
+                    .first()
+                    .map_or(&b""[..], |magic| &magic[..]);
+                fs::write(path, magic).unwrap();
+            }
+        };
+
+        let all = &["events", "string_data", "string_index"];
+        // A process id larger than Linux's maximum, so it can't be running.
+        let dead = 4_194_305u32;
+
+        create("complete-1", all);
+        create(&format!("current-{}", std::process::id()), &["events"]);
+        create(
+            &format!("crashed-{}", dead),
+            &["events", "string_data", "0.thread_events"],
+        );
+        create("unrelated", &["txt"]);
+        // Files of other applications that happen to use the extensions.
+        create(&format!("notes-{}", dead), &["summary"]);
+        fs::write(dir.join(format!("other-{}.events", dead)), b"not a profile").unwrap();
+        fs::write(dir.join(format!("empty-{}.string_data", dead)), b"").unwrap();
+
+        let options = CleanupOptions {
+            max_age: None,
+            remove_incomplete: true,
+        };
+        let stale = remove_stale_profiles(&dir, &options).unwrap();
+
+        assert_eq!(
+            stale,
+            vec![StaleProfile {
+                path_stem: dir.join(format!("crashed-{}", dead)),
+                reason: StaleReason::Incomplete,
+                files: vec![
+                    dir.join(format!("crashed-{}.0.thread_events", dead)),
+                    dir.join(format!("crashed-{}.events", dead)),
+                    dir.join(format!("crashed-{}.string_data", dead)),
+                ],
+            }]
+        );
+        assert!(!dir.join(format!("crashed-{}.events", dead)).exists());
+
+        let options = CleanupOptions {
+            max_age: Some(Duration::from_secs(0)),
+            remove_incomplete: false,
+        };
+        std::thread::sleep(Duration::from_millis(10));
+        let stale: Vec<_> = remove_stale_profiles(&dir, &options)
+            .unwrap()
+            .into_iter()
+            .map(|profile| (profile.path_stem, profile.reason))
+            .collect();
+
+        assert_eq!(stale, vec![(dir.join("complete-1"), StaleReason::TooOld)]);
+        assert!(dir.join("unrelated.txt").exists());
+        assert!(dir.join(format!("notes-{}.summary", dead)).exists());
+        assert!(dir.join(format!("other-{}.events", dead)).exists());
+        assert!(dir.join(format!("empty-{}.string_data", dead)).exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -16,46 +16,291 @@
 //! To record an event, call the [`Profiler::record_instant_event()`] method, passing a few arguments:
 //!   - `event_kind`: a [`StringId`] which assigns an arbitrary category to the event
 //!   - `event_id`: a [`StringId`] which specifies the name of the event
-//!   - `thread_id`: a `u32` id of the thread which is recording this event
+//!   - `thread_id`: a `u32` id of the thread which is recording this event. Embedders can
+//!     choose these ids themselves or obtain them via [`Profiler::register_current_thread()`],
+//!     or map wider ids like task ids via [`Profiler::register_external_thread()`]. It can also
+//!     denote a logical task instead of a thread, see [`Profiler::open_track()`] and the
+//!     [`thread_id`] module. Code running on a thread pool can get both the
+//!     profiler and the thread id from hooks installed in the pool, see the
+//!     [`thread_pool`] module.
 //!
 //! Alternatively, events can also be recorded via the [`Profiler::start_recording_interval_event()`] method. This
 //! method records a "start" event and returns a `TimingGuard` object that will automatically record
 //! the corresponding "end" event when it is dropped.
 //!
+//! High-frequency counters, like cache sizes or byte counts, can be recorded via
+//! [`Profiler::record_integer_event()`], which stores the value in the event itself
+//! instead of in the string table.
+//!
+//! Threads that want to signal that they are still making progress can call
+//! [`Profiler::record_heartbeat()`] periodically. Analysis tools flag long
+//! stretches without any events on a thread as possible stalls. Calling
+//! [`Profiler::finish_thread()`] when a thread is done lets analysis tools tell
+//! threads that finished cleanly apart from threads that were still running, with
+//! intervals left open, when the profile ended.
+//!
+//! Recording can be suspended for the whole profiler via [`Profiler::pause_recording()`]
+//! and picked up again via [`Profiler::resume_recording()`]. Both record a marker event.
+//! Registered event kinds can also be switched on and off by the user while the process
+//! runs, through a control file, see the [`control`] module.
+//!
+//! The stages of a process, like parsing or code generation in a compiler, can be
+//! delimited via [`Profiler::start_phase()`] and [`Profiler::end_phase()`]. Analysis
+//! tools break down their statistics per phase. Stages that simply follow each other, like
+//! the start of expansion, codegen and linking in rustc, can instead be marked via
+//! [`Profiler::record_session_marker()`], which needs no matching end call; tools also
+//! report the share of the wall time each of these segments took.
+//!
+//! Handoffs between threads, like a coordinator enqueuing work that a worker later picks
+//! up, can be linked via [`Profiler::record_flow_start()`] and [`Profiler::record_flow_end()`]
+//! with the same flow id, so that analysis tools can draw an arrow from one to the other and
+//! measure the latency in between.
+//!
+//! Time in which a thread waits instead of running, e.g. for a lock, can be recorded via
+//! [`Profiler::start_recording_blocked_interval_event()`], so that analysis tools can
+//! tell it apart from the time the thread spent doing actual work.
+//!
+//! Event arguments are recorded as strings. Names and types for the arguments of an
+//! event kind can be registered via [`Profiler::register_arg_schema()`], so that
+//! analysis tools can display them as `name=value`, see the [`arg_schema`] module.
+//! An argument can also be a [`SourceLocation`], which the [`profile_scope!`] macro
+//! records for activities of the global profiler.
+//!
+//! Event kinds can also be registered via [`Profiler::register_event_kind()`], which
+//! returns an id whose name is stored in the profile's metadata rather than in the string
+//! table, so analysis tools can filter by kind with an integer comparison.
+//!
+//! Embedders that create many short-lived profilers can store the strings they all
+//! have in common once, in a [`SharedStringCache`], see the [`shared_strings`] module.
+//!
+//! Profiles can be encrypted at rest by wrapping the sink in an
+//! [`EncryptedSerializationSink`] with a cipher provided by the application, or with
+//! `XChaCha20Poly1305Cipher` (behind the `chacha20poly1305` feature), see the
+//! [`encryption`] module.
+//!
+//! Profiles can be written somewhere else than to plain files by implementing the
+//! [`SerializationSink`] trait, whose documentation lists the invariants an
+//! implementation must uphold. [`TeeSerializationSink`], which writes everything to two
+//! other sinks, shows how, see the [`tee_serialization_sink`] module. Combined with
+//! [`Profiler::with_sinks()`] and a [`RingBufferSink`], it lets a process write the
+//! profile to disk while a live dashboard looks at the most recent events, see the
+//! [`ring_buffer_sink`] module.
+//!
+//! Applications can let their users configure profiling through a standard set of
+//! `MEASUREME_*` environment variables by creating the [`Profiler`] via
+//! [`Profiler::with_config()`] and [`ProfilerConfig::from_env()`], see the [`config`] module.
+//!
+//! The buffering and preallocation of the files can be tuned for network filesystems or
+//! tmpfs via `ProfilerConfig::file_sink`, guided by the number of bytes written and
+//! flushes that [`Profiler::sink_stats()`] reports. Heavily parallel applications can
+//! set `ProfilerConfig::event_layout` to `EventLayout::PerThread`, so that every thread
+//! writes its events to a file of its own, see the [`thread_event_files`] module.
+//! Extremely long recordings can use `EventLayout::Rollover` instead, which starts a
+//! new events file every so often, so that the finished ones can be archived and
+//! pruned while the profiler keeps running, see the [`event_segments`] module.
+//!
+//! Timestamps are stored with 48 bits by default, which limits intervals to the first 39
+//! hours of a profile: since format version 7, the upper half of the 48 bit range is
+//! reserved for the payloads of integer events, which halved the previous limit of 78
+//! hours. Intervals ending later are dropped, with a warning, and counted in the
+//! profile's `dropped_events`. Long-running services can set `ProfilerConfig::timestamp_format` to
+//! [`TimestampFormat::Wide`] for 64 bit timestamps, at the cost of 8 more bytes per event.
+//! Wide events also have room for the CPU each event started on, which the profiler
+//! records on Linux if `ProfilerConfig::record_cpu` is set. Profiles that don't need
+//! nanosecond precision can set `ProfilerConfig::timestamp_resolution` to
+//! [`TimestampResolution::Microseconds`], which makes the events file compress better.
+//!
+//! Embedders that only need top-level numbers can set `ProfilerConfig::summary` to have
+//! the [`Profiler`] keep running totals per label and write them to a small sidecar
+//! file, see the [`summary`] module. With `ProfilerConfig::recording_mode` set to
+//! `RecordingMode::AggregateOnly`, the profiler writes nothing but these totals and the
+//! string table. `ProfilerConfig::budget` caps the number of events or bytes a profile
+//! may take, after which the profiler stops writing events, see the [`budget`] module.
+//!
+//! For caching layers and reproducibility checks keyed on the contents of profiles,
+//! `ProfilerConfig::deterministic` and `Clock::Logical` make the profiler write
+//! byte-for-byte identical files for identical sequences of calls.
+//!
+//! The profiler estimates the time it spends recording and stores it in the profile's
+//! metadata, so that analysis tools can show how much profiling has slowed the
+//! application down, see the [`overhead`] module. It also records the CPU model, core
+//! count, memory and OS of the machine, see the [`system_info`] module.
+//!
+//! A process that restarts can keep recording into the same profile by reopening it via
+//! [`Profiler::resume()`] instead of creating a new one.
+//!
+//! Build scripts and proc macros can record how long they take via a
+//! [`BuildToolProfiler`], which writes a profile keyed by the crate they run for if
+//! `MEASUREME_OUT_DIR` is set, see the [`build_tools`] module.
+//!
+//! Applications that want to record events from anywhere in their code, without passing
+//! a [`Profiler`] around, can create a process-wide one via `global::init()` and record
+//! activities with the [`activity!`] macro, see the [`global`] module.
+//!
+//! Stale profiles in an output directory, i.e. old ones and incomplete ones left
+//! behind by crashed processes, can be deleted via the functions in the [`housekeeping`]
+//! module.
+//!
+//! With the `disabled` cargo feature, [`Profiler`], `TimingGuard` and `StringTableBuilder`
+//! are zero-sized no-ops with the same API, so that instrumentation can stay in the code
+//! and still be compiled out entirely, see the [`disabled`] module.
+//!
+//! The only dependency the crate needs is `byteorder`. The others are behind cargo
+//! features that are enabled by default, so that embedders can leave them out with
+//! `default-features = false`: `parking_lot` and `rustc-hash` make locking and string
+//! interning a bit faster than with `std`, `mmap` provides `MmapSerializationSink`, and
+//! `libc` provides OS thread ids, CPU numbers and the preallocation of files on Linux.
+//!
 //! To create a [`StringId`], call one of the string allocation methods:
 //!   - [`Profiler::alloc_string()`]: allocates a string and returns the [`StringId`] that refers to it
 //!   - [`Profiler::alloc_string_with_reserved_id()`]: allocates a string using the specified [`StringId`].
 //!     It is up to the caller to make sure the specified [`StringId`] hasn't already been used.
+//!   - [`Profiler::reserve_string_ids()`]: reserves a range of [`StringId`]s for strings that
+//!     aren't stored in the profile, because analysis tools know them anyway.
 //!
 //! [`Profiler`]: struct.Profiler.html
 //! [`Profiler::alloc_string()`]: struct.Profiler.html#method.alloc_string
 //! [`Profiler::alloc_string_with_reserved_id()`]: struct.Profiler.html#method.alloc_string_with_reserved_id
 //! [`Profiler::new()`]: struct.Profiler.html#method.new
+//! [`Profiler::end_phase()`]: struct.Profiler.html#method.end_phase
+//! [`Profiler::record_flow_end()`]: struct.Profiler.html#method.record_flow_end
+//! [`Profiler::record_flow_start()`]: struct.Profiler.html#method.record_flow_start
+//! [`Profiler::pause_recording()`]: struct.Profiler.html#method.pause_recording
+//! [`Profiler::with_config()`]: struct.Profiler.html#method.with_config
+//! [`Profiler::with_sinks()`]: struct.Profiler.html#method.with_sinks
+//! [`ProfilerConfig::from_env()`]: config/struct.ProfilerConfig.html#method.from_env
+//! [`activity!`]: macro.activity.html
+//! [`arg_schema`]: arg_schema/index.html
+//! [`profile_scope!`]: macro.profile_scope.html
+//! [`SourceLocation`]: struct.SourceLocation.html
+//! [`BuildToolProfiler`]: build_tools/struct.BuildToolProfiler.html
+//! [`build_tools`]: build_tools/index.html
+//! [`config`]: config/index.html
+//! [`control`]: control/index.html
+//! [`disabled`]: disabled/index.html
+//! [`EncryptedSerializationSink`]: encryption/struct.EncryptedSerializationSink.html
+//! [`encryption`]: encryption/index.html
+//! [`global`]: global/index.html
+//! [`housekeeping`]: housekeeping/index.html
+//! [`overhead`]: overhead/index.html
+//! [`system_info`]: system_info/index.html
 //! [`Profiler::record_event()`]: struct.Profiler.html#method.record_event
+//! [`Profiler::record_integer_event()`]: struct.Profiler.html#method.record_integer_event
+//! [`Profiler::record_heartbeat()`]: struct.Profiler.html#method.record_heartbeat
+//! [`Profiler::finish_thread()`]: struct.Profiler.html#method.finish_thread
+//! [`Profiler::register_arg_schema()`]: struct.Profiler.html#method.register_arg_schema
+//! [`Profiler::register_event_kind()`]: struct.Profiler.html#method.register_event_kind
+//! [`Profiler::start_recording_blocked_interval_event()`]: struct.Profiler.html#method.start_recording_blocked_interval_event
+//! [`Profiler::register_current_thread()`]: struct.Profiler.html#method.register_current_thread
+//! [`Profiler::reserve_string_ids()`]: struct.Profiler.html#method.reserve_string_ids
+//! [`Profiler::resume()`]: struct.Profiler.html#method.resume
+//! [`Profiler::resume_recording()`]: struct.Profiler.html#method.resume_recording
+//! [`Profiler::start_phase()`]: struct.Profiler.html#method.start_phase
+//! [`Profiler::record_session_marker()`]: struct.Profiler.html#method.record_session_marker
+//! [`Profiler::sink_stats()`]: struct.Profiler.html#method.sink_stats
 //! [`Profiler::start_recording_interval_event()`]: struct.Profiler.html#method.start_recording_interval_event
+//! [`RingBufferSink`]: ring_buffer_sink/struct.RingBufferSink.html
+//! [`ring_buffer_sink`]: ring_buffer_sink/index.html
+//! [`SerializationSink`]: trait.SerializationSink.html
+//! [`SharedStringCache`]: shared_strings/struct.SharedStringCache.html
+//! [`shared_strings`]: shared_strings/index.html
 //! [`StringId`]: struct.StringId.html
+//! [`summary`]: summary/index.html
+//! [`budget`]: budget/index.html
+//! [`TeeSerializationSink`]: tee_serialization_sink/struct.TeeSerializationSink.html
+//! [`tee_serialization_sink`]: tee_serialization_sink/index.html
+//! [`thread_event_files`]: thread_event_files/index.html
+//! [`event_segments`]: event_segments/index.html
+//! [`thread_id`]: thread_id/index.html
+//! [`thread_pool`]: thread_pool/index.html
+//! [`TimestampFormat::Wide`]: enum.TimestampFormat.html#variant.Wide
+//! [`TimestampResolution::Microseconds`]: enum.TimestampResolution.html#variant.Microseconds
 
 #![deny(warnings)]
 
+pub mod arg_schema;
+pub mod budget;
+#[cfg(not(target_arch = "wasm32"))]
+mod buffered_serialization_sink;
+pub mod build_tools;
+pub mod checksum;
+pub mod config;
+pub mod control;
+pub mod diagnostics;
+#[cfg(feature = "disabled")]
+pub mod disabled;
+pub mod encryption;
 pub mod event_id;
+pub mod event_kinds;
+pub mod event_segments;
 pub mod file_header;
 #[cfg(any(not(target_arch = "wasm32"), target_os = "wasi"))]
 mod file_serialization_sink;
-#[cfg(not(target_arch = "wasm32"))]
+#[cfg(any(not(target_arch = "wasm32"), target_os = "wasi"))]
+pub mod global;
+mod hash_map;
+#[cfg(any(not(target_arch = "wasm32"), target_os = "wasi"))]
+pub mod housekeeping;
+#[cfg(all(feature = "mmap", not(target_arch = "wasm32")))]
 mod mmap_serialization_sink;
+pub mod overhead;
+#[cfg_attr(feature = "disabled", allow(dead_code))]
 mod profiler;
 mod raw_event;
+mod resume;
+pub mod ring_buffer_sink;
 mod serialization;
+pub mod shared_strings;
+pub mod string_cache;
 pub mod stringtable;
+pub mod summary;
+mod sync;
+pub mod system_info;
+pub mod tee_serialization_sink;
+pub mod thread_event_files;
+pub mod thread_id;
+pub mod thread_pool;
 
 pub mod rustc;
 
+pub use crate::arg_schema::{ArgType, SourceLocation};
+#[cfg(not(target_arch = "wasm32"))]
+pub use crate::buffered_serialization_sink::BufferedSerializationSink;
+#[cfg(any(not(target_arch = "wasm32"), target_os = "wasi"))]
+pub use crate::build_tools::BuildToolProfiler;
+pub use crate::config::{
+    BudgetPolicy, EventLayout, FileSinkConfig, OverrunPolicy, ProfilerConfig, RecordingBudget,
+    RecordingMode, Rollover,
+};
+#[cfg(feature = "disabled")]
+pub use crate::disabled::{Profiler, StringTableBuilder, TimingGuard};
+#[cfg(feature = "chacha20poly1305")]
+pub use crate::encryption::XChaCha20Poly1305Cipher;
+pub use crate::encryption::{EncryptedSerializationSink, ProfileCipher};
 pub use crate::event_id::{EventId, EventIdBuilder};
 #[cfg(any(not(target_arch = "wasm32"), target_os = "wasi"))]
 pub use crate::file_serialization_sink::FileSerializationSink;
-#[cfg(not(target_arch = "wasm32"))]
+#[cfg(all(feature = "mmap", not(target_arch = "wasm32")))]
 pub use crate::mmap_serialization_sink::MmapSerializationSink;
-pub use crate::profiler::{Profiler, ProfilerFiles, TimingGuard};
-pub use crate::raw_event::{RawEvent, MAX_INSTANT_TIMESTAMP, MAX_INTERVAL_TIMESTAMP};
-pub use crate::serialization::{Addr, ByteVecSink, SerializationSink};
-pub use crate::stringtable::{SerializableString, StringComponent, StringId, StringTableBuilder};
+#[cfg(not(feature = "disabled"))]
+pub use crate::profiler::{Profiler, TimingGuard};
+pub use crate::profiler::{ProfilerFiles, ProfilerSinkStats, ToolInfo};
+pub use crate::raw_event::{
+    RawEvent, TimestampFormat, TimestampResolution, MAX_INSTANT_TIMESTAMP, MAX_INTEGER_VALUE,
+    MAX_INTERVAL_TIMESTAMP, MAX_INTERVAL_TIMESTAMP_WITHOUT_INTEGERS, MAX_WIDE_INTEGER_VALUE,
+    MAX_WIDE_TIMESTAMP, RAW_EVENT_SIZE, WIDE_RAW_EVENT_SIZE,
+};
+pub use crate::ring_buffer_sink::RingBufferSink;
+pub use crate::serialization::{
+    Addr, ByteVecSink, ProfileFileKind, SerializationSink, SinkStats, WriteError,
+};
+pub use crate::shared_strings::SharedStringCache;
+pub use crate::string_cache::{DedupPolicy, StringCacheStats};
+#[cfg(not(feature = "disabled"))]
+pub use crate::stringtable::StringTableBuilder;
+pub use crate::stringtable::{ReservedStringIds, SerializableString, StringComponent, StringId};
+pub use crate::summary::LabelTotals;
+pub use crate::system_info::SystemInfo;
+pub use crate::tee_serialization_sink::TeeSerializationSink;
+pub use crate::thread_id::{checked_thread_id, ThreadIdOverflow, ThreadIdScheme};
+pub use crate::thread_pool::{with_current_profiler, ThreadPoolHooks};
@@ -1,14 +1,21 @@
-use crate::serialization::{Addr, SerializationSink};
+use crate::checksum::checksum;
+use crate::diagnostics::Diagnostic;
+use crate::file_header::file_footer;
+use crate::serialization::{Addr, SerializationSink, WriteError};
 use memmap::MmapMut;
 use std::error::Error;
 use std::fs::File;
-use std::io::{BufWriter, Write};
+use std::io::{self, BufWriter, Write};
 use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicUsize, Ordering};
 
 pub struct MmapSerializationSink {
     mapped_file: MmapMut,
     current_pos: AtomicUsize,
+    // The position of the first write that did not fit into `mapped_file`, or
+    // `usize::MAX` if all writes fit so far. Everything before this position
+    // has been written completely.
+    overflow_pos: AtomicUsize,
     path: PathBuf,
 }
 
@@ -22,6 +29,7 @@ impl SerializationSink for MmapSerializationSink {
         Ok(MmapSerializationSink {
             mapped_file,
             current_pos: AtomicUsize::new(0),
+            overflow_pos: AtomicUsize::new(usize::MAX),
             path: path.to_path_buf(),
         })
     }
@@ -34,40 +42,79 @@ impl SerializationSink for MmapSerializationSink {
         // Reserve the range of bytes we'll copy to
         let pos = self.current_pos.fetch_add(num_bytes, Ordering::SeqCst);
 
-        // Bounds checks
-        assert!(pos.checked_add(num_bytes).unwrap() <= self.mapped_file.len());
+        // Bounds checks. If we have run out of space, the data is discarded
+        // and the sink is marked as failed.
+        if pos.checked_add(num_bytes).unwrap() > self.mapped_file.len() {
+            self.overflow_pos.fetch_min(pos, Ordering::SeqCst);
+            write(&mut vec![0; num_bytes]);
+            return Addr(pos as u64);
+        }
 
         // We don't have `&mut self.mapped_file` available, so we have to go
         // through raw pointers instead of `MmapMut::get_mut()`. This is OK
         // because our runtime checks guarantee that we have exclusive access
         // to the byte range in question.
         let bytes: &mut [u8] = unsafe {
-            let start: *mut u8 = self.mapped_file.as_ptr().offset(pos as isize) as *mut u8;
+            let start: *mut u8 = self.mapped_file.as_ptr().add(pos) as *mut u8;
             std::slice::from_raw_parts_mut(start, num_bytes)
         };
 
         write(bytes);
 
-        Addr(pos as u32)
+        Addr(pos as u64)
+    }
+
+    #[inline]
+    fn has_failed(&self) -> bool {
+        self.overflow_pos.load(Ordering::Relaxed) != usize::MAX
+    }
+
+    fn write_error(&self) -> Option<WriteError> {
+        if self.has_failed() {
+            Some(WriteError::new(
+                io::ErrorKind::OutOfMemory,
+                format!(
+                    "the profiling data exceeds the {} bytes reserved by MmapSerializationSink",
+                    self.mapped_file.len()
+                ),
+            ))
+        } else {
+            None
+        }
     }
 }
 
 impl Drop for MmapSerializationSink {
     fn drop(&mut self) {
-        let actual_size = *self.current_pos.get_mut();
+        let actual_size = std::cmp::min(*self.current_pos.get_mut(), *self.overflow_pos.get_mut());
 
         let file = match File::create(&self.path) {
             Ok(file) => file,
             Err(e) => {
-                eprintln!("Error opening file for writing: {:?}", e);
+                let error = WriteError::new(
+                    e.kind(),
+                    format!("couldn't create `{}`: {}", self.path.display(), e),
+                );
+                Diagnostic::from_error(&error).log();
                 return;
             }
         };
 
         let mut file = BufWriter::new(file);
 
-        if let Err(e) = file.write_all(&self.mapped_file[0..actual_size]) {
-            eprintln!("Error writing file: {:?}", e);
+        let data = &self.mapped_file[0..actual_size];
+        let footer = file_footer(checksum(data));
+
+        if let Err(e) = file
+            .write_all(data)
+            .and_then(|()| file.write_all(&footer))
+            .and_then(|()| file.flush())
+        {
+            let error = WriteError::new(
+                e.kind(),
+                format!("couldn't write `{}`: {}", self.path.display(), e),
+            );
+            Diagnostic::from_error(&error).log();
         }
     }
 }
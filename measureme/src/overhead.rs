@@ -0,0 +1,123 @@
+//! The `Profiler` estimates how much time it spends recording events and
+//! allocating strings, so that analysis tools can tell users how much
+//! profiling has slowed the application down, and thus how far to trust the
+//! measurements. Timing every event would about double its cost, so only
+//! every `SAMPLE_INTERVAL`th event and string of a thread is timed, and the
+//! totals are extrapolated from these samples, which also count the bytes
+//! the profiler has written for the events and strings.
+//!
+//! The estimate is stored in the profile's metadata when the profiler is
+//! dropped, as JSON of the form
+//!
+//! ```json
+//! "overhead": { "recording_nanos": 1500000, "elapsed_nanos": 900000000, "bytes_written": 4194304 }
+//! ```
+//!
+//! where `recording_nanos` is summed over all threads and `elapsed_nanos` is
+//! the wall time from creating the profiler to dropping it. For resumed
+//! profiles, the numbers only cover the last session. Profiles recorded with
+//! `ProfilerConfig::deterministic` don't record the overhead.
+
+use std::cell::Cell;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread::LocalKey;
+use std::time::Instant;
+
+/// Every how many events and strings of a thread are timed.
+pub const SAMPLE_INTERVAL: u32 = 64;
+
+thread_local! {
+    static EVENT_COUNTER: Cell<u32> = const { Cell::new(0) };
+    static STRING_COUNTER: Cell<u32> = const { Cell::new(0) };
+}
+
+#[derive(Default)]
+pub(crate) struct OverheadRecorder {
+    events: Samples,
+    strings: Samples,
+}
+
+impl OverheadRecorder {
+    /// Runs `f`, which records an event of `size` bytes.
+    #[inline]
+    pub fn record_event<R>(&self, size: usize, f: impl FnOnce() -> R) -> R {
+        self.events.sample(&EVENT_COUNTER, size, f)
+    }
+
+    /// Runs `f`, which allocates a string of `size` bytes.
+    #[inline]
+    pub fn alloc_string<R>(&self, size: usize, f: impl FnOnce() -> R) -> R {
+        self.strings.sample(&STRING_COUNTER, size, f)
+    }
+
+    /// The estimated time spent recording so far, in nanoseconds.
+    pub fn recording_nanos(&self) -> u64 {
+        (self.events.nanos.load(Ordering::Relaxed) + self.strings.nanos.load(Ordering::Relaxed))
+            * SAMPLE_INTERVAL as u64
+    }
+
+    /// The estimated number of bytes written so far.
+    pub fn bytes_written(&self) -> u64 {
+        (self.events.bytes.load(Ordering::Relaxed) + self.strings.bytes.load(Ordering::Relaxed))
+            * SAMPLE_INTERVAL as u64
+    }
+}
+
+#[derive(Default)]
+struct Samples {
+    // The total time and size of the samples.
+    nanos: AtomicU64,
+    bytes: AtomicU64,
+}
+
+impl Samples {
+    #[inline]
+    fn sample<R>(
+        &self,
+        counter: &'static LocalKey<Cell<u32>>,
+        size: usize,
+        f: impl FnOnce() -> R,
+    ) -> R {
+        let n = counter.with(|counter| {
+            let n = counter.get().wrapping_add(1);
+            counter.set(n);
+            n
+        });
+
+        if n % SAMPLE_INTERVAL != 0 {
+            return f();
+        }
+
+        let start = Instant::now();
+        let result = f();
+        self.nanos
+            .fetch_add(start.elapsed().as_nanos() as u64, Ordering::Relaxed);
+        self.bytes.fetch_add(size as u64, Ordering::Relaxed);
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn sampling() {
+        let recorder = OverheadRecorder::default();
+
+        // A thread of its own, so that the counters start at zero.
+        std::thread::scope(|scope| {
+            scope.spawn(|| {
+                for _ in 0..SAMPLE_INTERVAL - 1 {
+                    recorder.record_event(16, || std::thread::sleep(Duration::from_millis(1)));
+                }
+                assert_eq!(recorder.recording_nanos(), 0);
+
+                recorder.record_event(16, || std::thread::sleep(Duration::from_millis(1)));
+                assert!(recorder.recording_nanos() >= SAMPLE_INTERVAL as u64 * 1_000_000);
+                assert_eq!(recorder.bytes_written(), SAMPLE_INTERVAL as u64 * 16);
+            });
+        });
+    }
+}
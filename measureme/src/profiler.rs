@@ -1,71 +1,927 @@
+use crate::arg_schema::ArgType;
+use crate::budget::BudgetGuard;
+use crate::build_tools::INVOKING_CRATE_FLAG;
+use crate::config::{
+    BudgetPolicy, Clock, EventLayout, ProfilerConfig, RecordingMode, WriteFailurePolicy,
+};
+use crate::control::EventKindControl;
+use crate::diagnostics::Diagnostic;
 use crate::event_id::EventId;
-use crate::file_header::{write_file_header, FILE_MAGIC_EVENT_STREAM};
-use crate::raw_event::RawEvent;
-use crate::serialization::SerializationSink;
-use crate::stringtable::{SerializableString, StringId, StringTableBuilder};
+use crate::event_kinds::{
+    EXTERNAL_THREAD_ID_EVENT_KIND, FLOW_END_EVENT_KIND, FLOW_START_EVENT_KIND,
+    HEARTBEAT_EVENT_KIND, PHASE_END_EVENT_KIND, PHASE_START_EVENT_KIND, PROFILER_PAUSED_EVENT_KIND,
+    PROFILER_RESUMED_EVENT_KIND, SESSION_MARKER_EVENT_KIND, THREAD_FINISHED_EVENT_KIND,
+    THREAD_REGISTRATION_EVENT_KIND, TRACK_CLOSED_EVENT_KIND, TRACK_OPENED_EVENT_KIND,
+};
+use crate::event_segments::EventSegments;
+use crate::file_header::{
+    new_session_id, write_file_header_with_features, DETERMINISTIC_SESSION_ID,
+    FEATURE_BLOCKED_INTERVALS, FEATURE_CPU_IDS, FEATURE_REGISTERED_EVENT_KINDS,
+};
+use crate::hash_map::FxHashMap;
+use crate::overhead::OverheadRecorder;
+use crate::raw_event::{RawEvent, TimestampFormat, TimestampResolution};
+use crate::resume::ExistingProfile;
+use crate::serialization::{ProfileFileKind, SerializationSink, SinkStats, WriteError};
+use crate::shared_strings::SharedStringCache;
+use crate::string_cache::{StringCache, StringCacheStats};
+use crate::stringtable::{
+    ReservedStringIds, SerializableString, StringId, StringTableBuilder, FIRST_RESERVED_STRING_ID,
+};
+use crate::summary::{summary_json, LabelTotals, SummaryRecorder};
+use crate::sync::Mutex;
+use crate::system_info::SystemInfo;
+use crate::thread_event_files::ThreadEventSinks;
+use crate::thread_id::{current_cpu, os_thread_id, RegistryId, ThreadIdScheme};
 use std::error::Error;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
 use std::sync::Arc;
-use std::time::Instant;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 pub struct ProfilerFiles {
     pub events_file: PathBuf,
     pub string_data_file: PathBuf,
     pub string_index_file: PathBuf,
+    /// Only written if `ProfilerConfig::summary` is enabled, see the
+    /// `summary` module.
+    pub summary_file: PathBuf,
 }
 
 impl ProfilerFiles {
+    /// The extensions of the files every profile consists of, the events
+    /// file and the string table, `<path_stem>.<extension>`.
+    pub const EXTENSIONS: [&'static str; 3] = ["events", "string_data", "string_index"];
+
     pub fn new(path_stem: &Path) -> ProfilerFiles {
+        let [events, string_data, string_index] = ProfilerFiles::EXTENSIONS;
         ProfilerFiles {
-            events_file: path_stem.with_extension("events"),
-            string_data_file: path_stem.with_extension("string_data"),
-            string_index_file: path_stem.with_extension("string_index"),
+            events_file: path_stem.with_extension(events),
+            string_data_file: path_stem.with_extension(string_data),
+            string_index_file: path_stem.with_extension(string_index),
+            summary_file: path_stem.with_extension("summary"),
         }
     }
+
+    /// The events file of the `index`th thread of a profile recorded with
+    /// `EventLayout::PerThread`, see the `thread_event_files` module.
+    pub fn thread_events_file(&self, index: usize) -> PathBuf {
+        self.events_file
+            .with_extension(format!("{}.thread_events", index))
+    }
+
+    /// The `index`th segment of the events of a profile recorded with
+    /// `EventLayout::Rollover`, see the `event_segments` module.
+    pub fn segment_events_file(&self, index: usize) -> PathBuf {
+        self.events_file
+            .with_extension(format!("{}.segment_events", index))
+    }
+}
+
+/// The I/O statistics of the sinks of a profile's files, see
+/// `Profiler::sink_stats()`.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct ProfilerSinkStats {
+    pub events: SinkStats,
+    pub string_data: SinkStats,
+    pub string_index: SinkStats,
+}
+
+impl ProfilerSinkStats {
+    pub fn total(&self) -> SinkStats {
+        self.events + self.string_data + self.string_index
+    }
+}
+
+/// Describes the application that recorded a profile, so that archived
+/// profiles remain interpretable, see `Profiler::set_tool_info()`.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ToolInfo {
+    pub name: String,
+    pub version: String,
+    /// The commit the tool has been built from, if known.
+    pub git_sha: Option<String>,
+    /// The enabled features and flags that influence the recorded data, e.g.
+    /// `-Z self-profile-events=default`.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub flags: Vec<String>,
+}
+
+impl ToolInfo {
+    /// The crate a build script or proc macro profiled via
+    /// `BuildToolProfiler` ran for.
+    pub fn invoking_crate(&self) -> Option<&str> {
+        self.flags
+            .iter()
+            .find_map(|flag| flag.strip_prefix(INVOKING_CRATE_FLAG))
+    }
 }
 
 pub struct Profiler<S: SerializationSink> {
     event_sink: Arc<S>,
+    // With `EventLayout::PerThread`, events are written here instead of to
+    // `event_sink`, see the `thread_event_files` module.
+    thread_event_sinks: Option<ThreadEventSinks<S>>,
+    // With `EventLayout::Rollover`, events are written here instead, see the
+    // `event_segments` module.
+    event_segments: Option<EventSegments<S>>,
     string_table: StringTableBuilder<S>,
     start_time: Instant,
+    start_wall_time: SystemTime,
+    // The time between `start_wall_time` and `start_time`, which is only
+    // non-zero for resumed profiles.
+    resume_offset: Duration,
+    clock: Clock,
+    // The next timestamp of `Clock::Logical`.
+    logical_time: AtomicU64,
+    // The latest timestamp of `Clock::Wall`, see `nanos_since_start()`.
+    latest_wall_time: AtomicU64,
+    timestamp_format: TimestampFormat,
+    timestamp_resolution: TimestampResolution,
+    // The number of events that didn't fit into `timestamp_format`, see
+    // `write_raw_event()`.
+    out_of_range_events: AtomicU64,
+    known_strings: KnownStrings,
+    thread_id_scheme: ThreadIdScheme,
+    thread_registry: RegistryId,
+    next_thread_id: AtomicU32,
+    // The ids assigned via `register_external_thread()`, keyed by the
+    // external id.
+    external_thread_ids: Mutex<FxHashMap<u64, u32>>,
+    recording_paused: AtomicBool,
+    write_failure_policy: WriteFailurePolicy,
+    // Set once a write failure has been noticed, see `check_sinks()`.
+    recording_stopped: AtomicBool,
+    arg_schemas: Mutex<ArgSchemas>,
+    // The kinds registered via `register_event_kind()` and the ids of their
+    // names, indexed by `StringId::event_kind_index()`.
+    event_kinds: Mutex<Vec<(String, StringId)>>,
+    // The ranges handed out by `reserve_string_ids()`, in reservation order.
+    reserved_strings: Mutex<Vec<(String, ReservedStringIds)>>,
+    // The names of the phases started via `start_phase()` that haven't ended
+    // yet, innermost last.
+    phases: Mutex<Vec<StringId>>,
+    shared_strings: Option<Arc<SharedStringCache<S>>>,
+    interned_strings: Mutex<StringCache>,
+    tool_info: Mutex<Option<ToolInfo>>,
+    summary: Option<SummaryRecorder>,
+    summary_file: Option<PathBuf>,
+    // With `RecordingMode::AggregateOnly`, events only update `summary`.
+    aggregate_only: bool,
+    // See `ProfilerConfig::record_cpu`.
+    record_cpu: bool,
+    // See `ProfilerConfig::deterministic`.
+    deterministic: bool,
+    // `None` for deterministic profiles, see the `overhead` module.
+    overhead: Option<OverheadRecorder>,
+    // `None` for deterministic profiles, see the `system_info` module.
+    system_info: Option<SystemInfo>,
+    // See the `control` module.
+    control: Option<EventKindControl>,
+    // `None` for an unlimited budget, see the `budget` module.
+    budget: Option<BudgetGuard>,
+}
+
+/// The argument schemas registered via `register_arg_schema()`, as pairs of
+/// event kind and argument names and types, in registration order.
+type ArgSchemas = Vec<(String, Vec<(String, ArgType)>)>;
+
+/// The strings for the events that the profiler records on its own behalf.
+/// These are allocated once when the profiler is created.
+struct KnownStrings {
+    heartbeat: StringId,
+    thread_registration: StringId,
+    external_thread_id: StringId,
+    thread_finished: StringId,
+    track_opened: StringId,
+    track_closed: StringId,
+    profiler_paused: StringId,
+    profiler_resumed: StringId,
+    phase_start: StringId,
+    phase_end: StringId,
+    session_marker: StringId,
+    flow_start: StringId,
+    flow_end: StringId,
+}
+
+impl KnownStrings {
+    fn new<S: SerializationSink>(string_table: &StringTableBuilder<S>) -> KnownStrings {
+        KnownStrings {
+            heartbeat: string_table.alloc(HEARTBEAT_EVENT_KIND),
+            thread_registration: string_table.alloc(THREAD_REGISTRATION_EVENT_KIND),
+            external_thread_id: string_table.alloc(EXTERNAL_THREAD_ID_EVENT_KIND),
+            thread_finished: string_table.alloc(THREAD_FINISHED_EVENT_KIND),
+            track_opened: string_table.alloc(TRACK_OPENED_EVENT_KIND),
+            track_closed: string_table.alloc(TRACK_CLOSED_EVENT_KIND),
+            profiler_paused: string_table.alloc(PROFILER_PAUSED_EVENT_KIND),
+            profiler_resumed: string_table.alloc(PROFILER_RESUMED_EVENT_KIND),
+            phase_start: string_table.alloc(PHASE_START_EVENT_KIND),
+            phase_end: string_table.alloc(PHASE_END_EVENT_KIND),
+            session_marker: string_table.alloc(SESSION_MARKER_EVENT_KIND),
+            flow_start: string_table.alloc(FLOW_START_EVENT_KIND),
+            flow_end: string_table.alloc(FLOW_END_EVENT_KIND),
+        }
+    }
 }
 
 impl<S: SerializationSink> Profiler<S> {
     pub fn new(path_stem: &Path) -> Result<Profiler<S>, Box<dyn Error>> {
-        let paths = ProfilerFiles::new(path_stem);
-        let event_sink = Arc::new(S::from_path(&paths.events_file)?);
+        Profiler::with_config(path_stem, &ProfilerConfig::default())
+    }
+
+    /// Creates a profiler that writes to `config.path_stem(path_stem)` and
+    /// takes its timestamps from `config.clock`. See the `config` module.
+    pub fn with_config(
+        path_stem: &Path,
+        config: &ProfilerConfig,
+    ) -> Result<Profiler<S>, Box<dyn Error>> {
+        let path_stem = config.path_stem(path_stem);
+        let paths = ProfilerFiles::new(&path_stem);
+
+        let mut profiler = Profiler::with_sinks(
+            S::from_config(&paths.events_file, ProfileFileKind::Events, config)?,
+            S::from_config(&paths.string_data_file, ProfileFileKind::StringData, config)?,
+            S::from_config(
+                &paths.string_index_file,
+                ProfileFileKind::StringIndex,
+                config,
+            )?,
+            config,
+        );
+
+        if config.is_summary_enabled() {
+            profiler.summary_file = Some(paths.summary_file);
+        }
+
+        match config.event_layout {
+            EventLayout::SingleFile => {}
+            EventLayout::PerThread => {
+                profiler.thread_event_sinks = Some(ThreadEventSinks::new(
+                    &path_stem,
+                    config,
+                    feature_flags(config),
+                    profiler.string_table.session_id(),
+                    profiler.event_sink.clone(),
+                ));
+            }
+            EventLayout::Rollover(rollover) => {
+                profiler.event_segments = Some(EventSegments::new(
+                    &path_stem,
+                    config,
+                    rollover,
+                    feature_flags(config),
+                    profiler.string_table.session_id(),
+                )?);
+                profiler.write_metadata();
+            }
+        }
+
+        Ok(profiler)
+    }
+
+    /// Creates a profiler that writes to sinks the embedder has created
+    /// itself, e.g. a `TeeSerializationSink` that writes to a file and to a
+    /// `RingBufferSink` a live dashboard reads from. The sinks must be
+    /// empty. The output directory in `config` is ignored.
+    pub fn with_sinks(
+        event_sink: S,
+        string_data_sink: S,
+        string_index_sink: S,
+        config: &ProfilerConfig,
+    ) -> Profiler<S> {
+        let event_sink = Arc::new(event_sink);
+
+        let string_table = StringTableBuilder::with_session_id(
+            Arc::new(string_data_sink),
+            Arc::new(string_index_sink),
+            session_id(config),
+        );
 
         // The first thing in every file we generate must be the file header.
-        write_file_header(&*event_sink, FILE_MAGIC_EVENT_STREAM);
+        write_file_header_with_features(
+            &*event_sink,
+            config.timestamp_format.file_magic(),
+            feature_flags(config),
+            string_table.session_id(),
+        );
+
+        Profiler::from_parts(
+            event_sink,
+            string_table,
+            SystemTime::now(),
+            0,
+            Vec::new(),
+            config,
+        )
+    }
+
+    /// Reopens the profile at `path_stem` and appends to it, so that a
+    /// process that restarts (e.g. a long-running build server) can keep
+    /// recording into a single profile. The profile has to be complete: the
+    /// files are validated and their footers removed, and new events, strings
+    /// and the updated metadata are appended. `S` has to support this, see
+    /// `SerializationSink::from_existing()`.
+    ///
+    /// Timestamps continue from the profile's original start time and new
+    /// threads get sequential ids that haven't been used yet. The metadata is
+    /// written anew, so argument schemas, reserved string ids, the tool info
+    /// and a shared string cache have to be set up again, just like for a new profiler. The
+    /// timestamp format is taken from the existing profile and all other
+    /// settings are the defaults.
+    pub fn resume(path_stem: &Path) -> Result<Profiler<S>, Box<dyn Error>> {
+        let existing = ExistingProfile::read(path_stem)?;
+        let paths = ProfilerFiles::new(path_stem);
 
-        let string_table = StringTableBuilder::new(
-            Arc::new(S::from_path(&paths.string_data_file)?),
-            Arc::new(S::from_path(&paths.string_index_file)?),
+        let event_sink = Arc::new(S::from_existing(&paths.events_file, &existing.events)?);
+        let string_table = StringTableBuilder::resume(
+            Arc::new(S::from_existing(
+                &paths.string_data_file,
+                &existing.string_data,
+            )?),
+            Arc::new(S::from_existing(
+                &paths.string_index_file,
+                &existing.string_index,
+            )?),
+            existing.next_string_id,
+            existing.session_id,
         );
 
+        let config = ProfilerConfig {
+            timestamp_format: existing.timestamp_format,
+            timestamp_resolution: existing.timestamp_resolution,
+            ..ProfilerConfig::default()
+        };
+
+        Ok(Profiler::from_parts(
+            event_sink,
+            string_table,
+            UNIX_EPOCH + Duration::from_nanos(existing.start_time),
+            existing.next_thread_id,
+            existing.event_kinds,
+            &config,
+        ))
+    }
+
+    fn from_parts(
+        event_sink: Arc<S>,
+        string_table: StringTableBuilder<S>,
+        start_wall_time: SystemTime,
+        next_thread_id: u32,
+        event_kinds: Vec<(String, StringId)>,
+        config: &ProfilerConfig,
+    ) -> Profiler<S> {
+        let known_strings = KnownStrings::new(&string_table);
+
         let profiler = Profiler {
             event_sink,
+            thread_event_sinks: None,
+            event_segments: None,
             string_table,
             start_time: Instant::now(),
+            start_wall_time,
+            resume_offset: SystemTime::now()
+                .duration_since(start_wall_time)
+                .unwrap_or_default(),
+            clock: config.clock,
+            logical_time: AtomicU64::new(0),
+            latest_wall_time: AtomicU64::new(0),
+            timestamp_format: config.timestamp_format,
+            timestamp_resolution: timestamp_resolution(config),
+            out_of_range_events: AtomicU64::new(0),
+            known_strings,
+            thread_id_scheme: ThreadIdScheme::default(),
+            thread_registry: RegistryId::new(),
+            next_thread_id: AtomicU32::new(next_thread_id),
+            external_thread_ids: Mutex::new(FxHashMap::default()),
+            recording_paused: AtomicBool::new(false),
+            write_failure_policy: config.write_failure_policy,
+            recording_stopped: AtomicBool::new(false),
+            arg_schemas: Mutex::new(Vec::new()),
+            event_kinds: Mutex::new(event_kinds),
+            reserved_strings: Mutex::new(Vec::new()),
+            phases: Mutex::new(Vec::new()),
+            shared_strings: None,
+            interned_strings: Mutex::new(StringCache::new(
+                config.string_cache_capacity,
+                config.string_dedup_policy,
+            )),
+            tool_info: Mutex::new(None),
+            summary: if config.is_summary_enabled() {
+                Some(SummaryRecorder::default())
+            } else {
+                None
+            },
+            summary_file: None,
+            aggregate_only: config.recording_mode == RecordingMode::AggregateOnly,
+            record_cpu: records_cpu(config),
+            deterministic: config.deterministic,
+            overhead: if config.deterministic {
+                None
+            } else {
+                Some(OverheadRecorder::default())
+            },
+            system_info: if config.deterministic {
+                None
+            } else {
+                Some(SystemInfo::capture())
+            },
+            control: config.control_file.as_deref().map(EventKindControl::new),
+            budget: BudgetGuard::new(config.budget),
         };
 
+        profiler.write_metadata();
+
+        profiler
+    }
+
+    fn write_metadata(&self) {
         let mut args = String::new();
-        for arg in std::env::args() {
-            args.push_str(&arg.escape_default().to_string());
-            args.push(' ');
-        }
-
-        profiler.string_table.alloc_metadata(&*format!(
-            r#"{{ "start_time": {}, "process_id": {}, "cmd": "{}" }}"#,
-            std::time::SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH)
-                .unwrap()
-                .as_nanos(),
-            std::process::id(),
+        if !self.deterministic {
+            for arg in std::env::args() {
+                args.push_str(&arg.escape_default().to_string());
+                args.push(' ');
+            }
+        }
+
+        let (start_time, process_id) = if self.deterministic {
+            (0, 0)
+        } else {
+            (
+                self.start_wall_time
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap()
+                    .as_nanos(),
+                std::process::id(),
+            )
+        };
+
+        let mut arg_schemas = String::new();
+        for (i, (event_kind, args)) in self.arg_schemas.lock().iter().enumerate() {
+            if i > 0 {
+                arg_schemas.push_str(", ");
+            }
+
+            let args: Vec<String> = args
+                .iter()
+                .map(|(name, arg_type)| {
+                    format!(
+                        r#"{{ "name": {}, "type": "{}" }}"#,
+                        json_string(name),
+                        arg_type.name()
+                    )
+                })
+                .collect();
+
+            arg_schemas.push_str(&format!(
+                "{}: [{}]",
+                json_string(event_kind),
+                args.join(", ")
+            ));
+        }
+
+        let event_kinds: Vec<String> = self
+            .event_kinds
+            .lock()
+            .iter()
+            .map(|&(_, name)| name.as_u32().to_string())
+            .collect();
+
+        let reserved_strings: Vec<String> = self
+            .reserved_strings
+            .lock()
+            .iter()
+            .map(|(name, ids)| {
+                format!(
+                    r#"{{ "name": {}, "first": {}, "len": {} }}"#,
+                    json_string(name),
+                    ids.first().as_u32(),
+                    ids.len()
+                )
+            })
+            .collect();
+
+        let shared_strings = match &self.shared_strings {
+            Some(cache) => json_string(&cache.path_stem().file_name().unwrap().to_string_lossy()),
+            None => "null".to_string(),
+        };
+
+        let tool_info = match &*self.tool_info.lock() {
+            Some(info) => {
+                let flags: Vec<String> = info.flags.iter().map(|f| json_string(f)).collect();
+                format!(
+                    r#"{{ "name": {}, "version": {}, "git_sha": {}, "flags": [{}] }}"#,
+                    json_string(&info.name),
+                    json_string(&info.version),
+                    info.git_sha
+                        .as_deref()
+                        .map_or_else(|| "null".to_string(), json_string),
+                    flags.join(", ")
+                )
+            }
+            None => "null".to_string(),
+        };
+
+        let thread_event_files: Vec<String> = match &self.thread_event_sinks {
+            Some(sinks) => sinks.file_names().iter().map(|f| json_string(f)).collect(),
+            None => Vec::new(),
+        };
+
+        let event_segments: Vec<String> = match &self.event_segments {
+            Some(segments) => segments
+                .file_names()
+                .iter()
+                .map(|f| json_string(f))
+                .collect(),
+            None => Vec::new(),
+        };
+
+        let overhead = match &self.overhead {
+            Some(overhead) => format!(
+                r#"{{ "recording_nanos": {}, "elapsed_nanos": {}, "bytes_written": {} }}"#,
+                overhead.recording_nanos(),
+                self.start_time.elapsed().as_nanos(),
+                overhead.bytes_written()
+            ),
+            None => "null".to_string(),
+        };
+
+        let system_info = match &self.system_info {
+            Some(info) => {
+                let optional = |value: Option<String>| value.unwrap_or_else(|| "null".to_string());
+                format!(
+                    r#"{{ "cpu_model": {}, "cores": {}, "memory_bytes": {}, "os": {}, "os_version": {}, "arch": {} }}"#,
+                    optional(info.cpu_model.as_deref().map(json_string)),
+                    optional(info.cores.map(|cores| cores.to_string())),
+                    optional(info.memory_bytes.map(|bytes| bytes.to_string())),
+                    json_string(&info.os),
+                    optional(info.os_version.as_deref().map(json_string)),
+                    json_string(&info.arch)
+                )
+            }
+            None => "null".to_string(),
+        };
+
+        // If the metadata is written more than once, the last version wins.
+        self.string_table.alloc_metadata(&*format!(
+            r#"{{ "start_time": {}, "process_id": {}, "cmd": "{}", "truncated": {}, "dropped_events": {}, "arg_schemas": {{ {} }}, "shared_strings": {}, "tool": {}, "aggregate_only": {}, "budget_exhausted": {}, "event_kinds": [{}], "reserved_strings": [{}], "thread_event_files": [{}], "event_segments": [{}], "overhead": {}, "system": {} }}"#,
+            start_time,
+            process_id,
             args,
+            self.health().is_err(),
+            self.dropped_events(),
+            arg_schemas,
+            shared_strings,
+            tool_info,
+            self.aggregate_only,
+            self.budget.as_ref().is_some_and(|budget| budget.is_exhausted()),
+            event_kinds.join(", "),
+            reserved_strings.join(", "),
+            thread_event_files.join(", "),
+            event_segments.join(", "),
+            overhead,
+            system_info,
         ));
+    }
 
-        Ok(profiler)
+    /// Registers names and types for the arguments of events of the given
+    /// kind, in the order in which the arguments are recorded. The schema is
+    /// stored in the profile's metadata and allows analysis tools to display
+    /// arguments as `name=value` instead of as positional strings. Registering
+    /// a schema for the same event kind again replaces the previous one.
+    ///
+    /// A new schema rewrites the metadata, so it should be registered once
+    /// per event kind during setup rather than in the hot path. Registering
+    /// the schema an event kind already has does nothing.
+    pub fn register_arg_schema(&self, event_kind: &str, args: &[(&str, ArgType)]) {
+        {
+            let mut arg_schemas = self.arg_schemas.lock();
+            let schema = arg_schemas.iter_mut().find(|(kind, _)| kind == event_kind);
+            let unchanged = schema.as_ref().is_some_and(|(_, schema)| {
+                schema.len() == args.len()
+                    && schema
+                        .iter()
+                        .zip(args)
+                        .all(|((name, arg_type), &(new_name, new_type))| {
+                            name == new_name && *arg_type == new_type
+                        })
+            });
+            if unchanged {
+                return;
+            }
+
+            let args = args
+                .iter()
+                .map(|&(name, arg_type)| (name.to_string(), arg_type))
+                .collect();
+            match schema {
+                Some(schema) => schema.1 = args,
+                None => arg_schemas.push((event_kind.to_string(), args)),
+            }
+        }
+
+        self.write_metadata();
+    }
+
+    /// Registers an event kind and returns the id to record its events with.
+    /// Instead of referring to the string table, such ids are small numbers
+    /// whose names are stored in the profile's metadata, so analysis tools
+    /// can compare the kinds of events without resolving any strings.
+    /// Registering the same name again returns the same id. Only registered
+    /// kinds can be switched on and off via a control file, see the
+    /// `control` module.
+    ///
+    /// Like `register_arg_schema()`, this rewrites the metadata, so it should
+    /// be called once per event kind during setup.
+    pub fn register_event_kind(&self, name: &str) -> StringId {
+        let index = {
+            let mut event_kinds = self.event_kinds.lock();
+
+            if let Some(index) = event_kinds.iter().position(|(kind, _)| kind == name) {
+                return StringId::new_event_kind(index as u32);
+            }
+
+            event_kinds.push((name.to_string(), self.string_table.alloc(name)));
+            event_kinds.len() - 1
+        };
+
+        if let Some(control) = &self.control {
+            control.event_kinds_changed(&self.event_kinds);
+        }
+
+        self.write_metadata();
+        StringId::new_event_kind(index as u32)
+    }
+
+    /// Reserves `count` string ids for strings that the application resolves
+    /// on its own, like the names of rustc's queries, which every analysis
+    /// tool for rustc's profiles knows anyway. Events refer to them like to
+    /// any other string, e.g. via `EventId::from_label(ids.get(index))`, but
+    /// the strings aren't stored in the profile. The range is recorded in the
+    /// metadata under `name`, and analysis tools resolve it via
+    /// `analyzeme::ProfilingData::register_string_resolver()`.
+    ///
+    /// Reserving the same name again returns the same range, so `count` has
+    /// to be the same. Ranges are assigned in reservation order, so after
+    /// `resume()`, the same reservations yield the same ids. Like
+    /// `register_arg_schema()`, this rewrites the metadata.
+    pub fn reserve_string_ids(&self, name: &str, count: u32) -> ReservedStringIds {
+        let ids = {
+            let mut reserved_strings = self.reserved_strings.lock();
+
+            if let Some(&(_, ids)) = reserved_strings.iter().find(|(n, _)| n == name) {
+                assert_eq!(ids.len(), count, "`{}` has been reserved before", name);
+                return ids;
+            }
+
+            let first = reserved_strings
+                .last()
+                .map_or(FIRST_RESERVED_STRING_ID, |&(_, ids)| ids.end());
+            let ids = ReservedStringIds::new(first, count);
+            reserved_strings.push((name.to_string(), ids));
+            ids
+        };
+
+        self.write_metadata();
+        ids
+    }
+
+    /// Records the name, version and flags of the application in the
+    /// profile's metadata, where analysis tools can display them. Like
+    /// `register_arg_schema()`, this rewrites the metadata.
+    pub fn set_tool_info(&self, tool_info: ToolInfo) {
+        *self.tool_info.lock() = Some(tool_info);
+        self.write_metadata();
+    }
+
+    /// Returns the running totals per event kind and event id if
+    /// `ProfilerConfig::summary` is enabled, or an empty list otherwise. See
+    /// the `summary` module.
+    pub fn label_totals(&self) -> Vec<LabelTotals> {
+        match &self.summary {
+            Some(summary) => summary.totals(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Returns an error if writing the profile has failed, e.g. because the
+    /// disk is full. With `WriteFailurePolicy::StopRecording`, the profiler
+    /// does not record any further events after a failure and the profile is
+    /// marked as truncated.
+    pub fn health(&self) -> Result<(), WriteError> {
+        match self
+            .event_sink
+            .write_error()
+            .or_else(|| {
+                self.thread_event_sinks
+                    .as_ref()
+                    .and_then(|sinks| sinks.write_error())
+            })
+            .or_else(|| {
+                self.event_segments
+                    .as_ref()
+                    .and_then(|segments| segments.write_error())
+            })
+            .or_else(|| self.string_table.write_error())
+        {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns how much the sinks have written so far, e.g. for tuning
+    /// `ProfilerConfig::file_sink`. See `SerializationSink::stats()`.
+    pub fn sink_stats(&self) -> ProfilerSinkStats {
+        let (string_data, string_index) = self.string_table.sink_stats();
+
+        let mut events = self.event_sink.stats();
+        if let Some(sinks) = &self.thread_event_sinks {
+            events = events + sinks.stats();
+        }
+        if let Some(segments) = &self.event_segments {
+            events = events + segments.stats();
+        }
+
+        ProfilerSinkStats {
+            events,
+            string_data,
+            string_index,
+        }
+    }
+
+    /// Writes the strings the string table is still buffering to the string
+    /// sinks, see the `stringtable` module. Strings are written when the
+    /// profiler is dropped anyway, so this is only needed for looking at the
+    /// string sinks while the profiler is running.
+    pub fn flush_strings(&self) {
+        self.string_table.flush();
+    }
+
+    /// Returns the segments of a profile recorded with
+    /// `EventLayout::Rollover` that are complete, oldest first, which can be
+    /// archived or deleted while the profiler keeps writing to the current
+    /// segment. See the `event_segments` module.
+    pub fn finished_event_segments(&self) -> Vec<PathBuf> {
+        match &self.event_segments {
+            Some(segments) => segments.finished_paths(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Sets the scheme `register_current_thread()` uses for assigning thread
+    /// ids. This should be called before any thread is registered.
+    pub fn set_thread_id_scheme(&mut self, scheme: ThreadIdScheme) {
+        self.thread_id_scheme = scheme;
+    }
+
+    /// Returns the thread id to use for events recorded on the current
+    /// thread. The first time a thread calls this method, it is assigned an
+    /// id according to the profiler's `ThreadIdScheme` and a thread
+    /// registration event carrying the thread's name is recorded. See the
+    /// `thread_id` module for more information.
+    pub fn register_current_thread(&self) -> u32 {
+        self.register_thread(None)
+    }
+
+    /// Like `register_current_thread()`, but records `name` as the thread's
+    /// name instead of the one it was given when it was spawned. The name
+    /// is only used if the thread isn't registered yet.
+    pub fn register_current_thread_as(&self, name: &str) -> u32 {
+        self.register_thread(Some(name))
+    }
+
+    fn register_thread(&self, name: Option<&str>) -> u32 {
+        let (thread_id, newly_registered) = self.thread_registry.get_or_assign(|| {
+            let os_thread_id = match self.thread_id_scheme {
+                ThreadIdScheme::Sequential => None,
+                ThreadIdScheme::Os => os_thread_id(),
+            };
+
+            os_thread_id.unwrap_or_else(|| self.next_sequential_thread_id())
+        });
+
+        if newly_registered {
+            let thread = std::thread::current();
+            self.record_thread_registration(
+                thread_id,
+                name.or_else(|| thread.name()).unwrap_or("<unnamed>"),
+            );
+        }
+
+        thread_id
+    }
+
+    /// Returns the thread id to use for events recorded on behalf of
+    /// `external_id`, an id from outside of `measureme` that may not fit
+    /// into the `u32` events have for thread ids, like a tokio task id. The
+    /// first time an external id is seen, it is assigned the next sequential
+    /// id and a thread registration event carrying `name` is recorded, along
+    /// with an `ExternalThreadId` event carrying the external id. The ids
+    /// are kept apart from the ones `register_current_thread()` assigns with
+    /// `ThreadIdScheme::Sequential`, but not from OS thread ids.
+    ///
+    /// A profile that is continued via `Profiler::resume()` assigns new ids
+    /// to the external ids it sees.
+    pub fn register_external_thread(&self, external_id: u64, name: &str) -> u32 {
+        let mut external_thread_ids = self.external_thread_ids.lock();
+        if let Some(&thread_id) = external_thread_ids.get(&external_id) {
+            return thread_id;
+        }
+
+        let thread_id = self.next_sequential_thread_id();
+        external_thread_ids.insert(external_id, thread_id);
+        drop(external_thread_ids);
+
+        self.record_thread_registration(thread_id, name);
+        let external_id = self.alloc_string(&external_id.to_string()[..]);
+        self.write_raw_event(
+            &RawEvent::new_instant_wide(
+                self.known_strings.external_thread_id,
+                EventId::from_label(external_id),
+                thread_id,
+                self.nanos_since_start(),
+            )
+            .with_cpu(self.current_cpu()),
+        );
+
+        thread_id
+    }
+
+    fn next_sequential_thread_id(&self) -> u32 {
+        self.next_thread_id
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |id| id.checked_add(1))
+            .expect("ran out of thread ids, every `u32` has been assigned")
+    }
+
+    fn record_thread_registration(&self, thread_id: u32, name: &str) {
+        let thread_name = self.alloc_string(name);
+
+        // Thread names should be available even if the thread is
+        // registered while recording is paused.
+        self.write_raw_event(
+            &RawEvent::new_instant_wide(
+                self.known_strings.thread_registration,
+                EventId::from_label(thread_name),
+                thread_id,
+                self.nanos_since_start(),
+            )
+            .with_cpu(self.current_cpu()),
+        );
+    }
+
+    /// Records a `ThreadFinished` marker event for the given thread, telling
+    /// analysis tools that the thread is done and that none of its intervals
+    /// is still open. Threads should call this right before they exit, after
+    /// all of their `TimingGuard`s have been dropped. The marker is recorded
+    /// even while recording is paused.
+    pub fn finish_thread(&self, thread_id: u32) {
+        self.write_raw_event(
+            &RawEvent::new_instant_wide(
+                self.known_strings.thread_finished,
+                EventId::from_label(self.known_strings.thread_finished),
+                thread_id,
+                self.nanos_since_start(),
+            )
+            .with_cpu(self.current_cpu()),
+        );
+    }
+
+    /// Opens a track named `name` for a logical task, like an async task or
+    /// a codegen unit, and returns its id, which is used in place of a
+    /// thread id for the events of the task, on whatever thread they are
+    /// recorded. Track ids are never reused and kept apart from the ones
+    /// `register_current_thread()` assigns with `ThreadIdScheme::Sequential`,
+    /// but not from OS thread ids. See the `thread_id` module.
+    pub fn open_track(&self, name: &str) -> u32 {
+        let track_id = self.next_sequential_thread_id();
+        let name = self.alloc_string(name);
+
+        self.write_raw_event(
+            &RawEvent::new_instant_wide(
+                self.known_strings.track_opened,
+                EventId::from_label(name),
+                track_id,
+                self.nanos_since_start(),
+            )
+            .with_cpu(self.current_cpu()),
+        );
+
+        track_id
+    }
+
+    /// Closes a track opened via `open_track()`, telling analysis tools that
+    /// the task is done and none of its intervals is still open, like
+    /// `finish_thread()` does for a thread.
+    pub fn close_track(&self, track_id: u32) {
+        self.write_raw_event(
+            &RawEvent::new_instant_wide(
+                self.known_strings.track_closed,
+                EventId::from_label(self.known_strings.track_closed),
+                track_id,
+                self.nanos_since_start(),
+            )
+            .with_cpu(self.current_cpu()),
+        );
     }
 
     #[inline(always)]
@@ -86,20 +942,220 @@ impl<S: SerializationSink> Profiler<S> {
             .bulk_map_virtual_to_single_concrete_string(virtual_ids, concrete_id);
     }
 
+    /// Allocates a string in the profile's string table. Once the table
+    /// has run out of ids, this returns `StringId::INVALID` and the profiler
+    /// handles it like a write failure, see `health()`.
     #[inline(always)]
     pub fn alloc_string<STR: SerializableString + ?Sized>(&self, s: &STR) -> StringId {
-        self.string_table.alloc(s)
+        match &self.overhead {
+            Some(overhead) => {
+                overhead.alloc_string(s.serialized_size(), || self.string_table.alloc(s))
+            }
+            None => self.string_table.alloc(s),
+        }
+    }
+
+    /// Makes `intern_shared_string()` store strings in `cache` instead of in
+    /// this profile, see the `shared_strings` module. This rewrites the
+    /// metadata, so it should be called right after creating the profiler.
+    pub fn set_shared_string_cache(&mut self, cache: Arc<SharedStringCache<S>>) {
+        self.shared_strings = Some(cache);
+        self.write_metadata();
+    }
+
+    /// Returns the id of `s`, allocating it the first time it is interned, so
+    /// that strings that are recorded repeatedly but aren't known upfront
+    /// (e.g. the file names of a build) are written only once. With
+    /// `ProfilerConfig::string_cache_capacity`, strings that haven't been
+    /// interned for a while are forgotten and written again on their next
+    /// use, see the `string_cache` module.
+    pub fn intern_string(&self, s: &str) -> StringId {
+        self.interned_strings
+            .lock()
+            .get_or_alloc(s, || self.alloc_string(s))
+    }
+
+    /// Returns how often `intern_string()` found its strings in the cache so
+    /// far, e.g. for tuning `ProfilerConfig::string_dedup_policy`. The
+    /// statistics of a shared string cache are reported by
+    /// `SharedStringCache::stats()`.
+    pub fn string_cache_stats(&self) -> StringCacheStats {
+        self.interned_strings.lock().stats()
+    }
+
+    /// Returns the id of `s` in the shared string cache, so that it is only
+    /// written once for all profiles sharing the cache. Without a cache, this
+    /// allocates `s` in this profile like `alloc_string()`, so it should only
+    /// be called once per string either way.
+    pub fn intern_shared_string(&self, s: &str) -> StringId {
+        match &self.shared_strings {
+            Some(cache) => cache.intern(s),
+            None => self.alloc_string(s),
+        }
     }
 
     /// Records an event with the given parameters. The event time is computed
     /// automatically.
     pub fn record_instant_event(&self, event_kind: StringId, event_id: EventId, thread_id: u32) {
         let raw_event =
-            RawEvent::new_instant(event_kind, event_id, thread_id, self.nanos_since_start());
+            RawEvent::new_instant_wide(event_kind, event_id, thread_id, self.nanos_since_start())
+                .with_cpu(self.current_cpu());
 
         self.record_raw_event(&raw_event);
     }
 
+    /// Records an instant event that carries an integer `value`, e.g. a cache
+    /// size or a byte count. The value is stored in the event itself, so
+    /// unlike an event argument it does not need to be formatted and
+    /// allocated in the string table, which makes this cheap enough for hot
+    /// paths. `value` must not exceed `MAX_INTEGER_VALUE`. Profiles recorded
+    /// with `TimestampFormat::CompactWithoutIntegers` have no room for it:
+    /// their integer events are dropped and counted as dropped.
+    pub fn record_integer_event(
+        &self,
+        event_kind: StringId,
+        event_id: EventId,
+        thread_id: u32,
+        value: u64,
+    ) {
+        let raw_event = RawEvent::new_integer_wide(
+            event_kind,
+            event_id,
+            thread_id,
+            self.nanos_since_start(),
+            value,
+        )
+        .with_cpu(self.current_cpu());
+
+        self.record_raw_event(&raw_event);
+    }
+
+    /// Records the start of the flow `flow_id`, e.g. a work item being handed
+    /// to another thread, which `record_flow_end()` with the same id
+    /// continues. Analysis tools draw flows as arrows between the two events
+    /// and measure the latency in between. Flow ids are chosen by the
+    /// embedder, must be unique within the profile and must not exceed
+    /// `MAX_INTEGER_VALUE`. Flow events are integer events, see
+    /// `record_integer_event()`.
+    pub fn record_flow_start(&self, event_id: EventId, thread_id: u32, flow_id: u64) {
+        self.record_integer_event(self.known_strings.flow_start, event_id, thread_id, flow_id);
+    }
+
+    /// Records the end of the flow `flow_id`, see `record_flow_start()`.
+    pub fn record_flow_end(&self, event_id: EventId, thread_id: u32, flow_id: u64) {
+        self.record_integer_event(self.known_strings.flow_end, event_id, thread_id, flow_id);
+    }
+
+    /// Records a heartbeat event for the given thread. Long-running threads
+    /// should call this periodically (e.g. once per iteration of their main
+    /// loop) so that analysis tools can tell the difference between a thread
+    /// that is busy and a thread that is stuck: stretches without any events
+    /// are reported as possible stalls.
+    pub fn record_heartbeat(&self, thread_id: u32) {
+        let event_id = EventId::from_label(self.known_strings.heartbeat);
+        self.record_instant_event(self.known_strings.heartbeat, event_id, thread_id);
+    }
+
+    /// Stops recording events until `resume_recording()` is called. This can
+    /// be used for excluding phases like setup and teardown from the profile,
+    /// or for only profiling a specific window of a long-running process.
+    ///
+    /// Pausing records a `ProfilerPaused` marker event on the given thread
+    /// right before recording stops. Calling this method while recording is
+    /// already paused has no effect. Interval events are recorded when they
+    /// end, so intervals that end while recording is paused are discarded,
+    /// while intervals that started before the pause and end after it are
+    /// kept.
+    pub fn pause_recording(&self, thread_id: u32) {
+        // Use `swap` so that concurrent calls only emit a single marker.
+        if !self.recording_paused.swap(true, Ordering::SeqCst) {
+            self.record_marker(self.known_strings.profiler_paused, thread_id);
+        }
+    }
+
+    /// Resumes recording events after a call to `pause_recording()` and
+    /// records a `ProfilerResumed` marker event on the given thread. Calling
+    /// this method while recording is not paused has no effect.
+    pub fn resume_recording(&self, thread_id: u32) {
+        if self.recording_paused.swap(false, Ordering::SeqCst) {
+            self.record_marker(self.known_strings.profiler_resumed, thread_id);
+        }
+    }
+
+    /// Returns `true` if recording has been paused via `pause_recording()`.
+    pub fn is_recording_paused(&self) -> bool {
+        self.recording_paused.load(Ordering::Relaxed)
+    }
+
+    /// Starts a phase with the given name, e.g. "expansion" or "codegen".
+    /// Analysis tools like `summarize` break down their statistics per phase,
+    /// attributing each event to the innermost phase it started in. Phases
+    /// apply to the whole process, not just to the current thread, and can be
+    /// nested. Each phase ends with the matching call to `end_phase()`.
+    ///
+    /// This records a `PhaseStart` marker event on the current thread (see
+    /// `register_current_thread()`), even while recording is paused.
+    pub fn start_phase(&self, name: &str) {
+        let name = self.alloc_string(name);
+
+        // Hold the lock while recording so that the markers of concurrent
+        // calls are recorded in the same order as the phases are nested.
+        let mut phases = self.phases.lock();
+        phases.push(name);
+        self.record_phase_marker(self.known_strings.phase_start, name);
+    }
+
+    /// Ends the innermost phase started via `start_phase()` and records a
+    /// `PhaseEnd` marker event for it. Calling this method while no phase is
+    /// active has no effect.
+    pub fn end_phase(&self) {
+        let mut phases = self.phases.lock();
+
+        if let Some(name) = phases.pop() {
+            self.record_phase_marker(self.known_strings.phase_end, name);
+        }
+    }
+
+    /// Records a marker for the start of a segment of the session with the
+    /// given name, e.g. one of the `measureme::rustc::*_SESSION_MARKER` names
+    /// for the stages of a compilation. The segment lasts until the next
+    /// marker, or until the end of the profile, so unlike phases, segments
+    /// are never nested and don't need to be ended. Analysis tools report the
+    /// share of the wall time each segment took and break down their
+    /// statistics per segment, attributing each event to the segment it
+    /// started in.
+    ///
+    /// This records a `SessionMarker` event on the current thread (see
+    /// `register_current_thread()`), even while recording is paused.
+    pub fn record_session_marker(&self, name: &str) {
+        let name = self.alloc_string(name);
+        self.record_phase_marker(self.known_strings.session_marker, name);
+    }
+
+    fn record_phase_marker(&self, marker: StringId, name: StringId) {
+        self.write_raw_event(
+            &RawEvent::new_instant_wide(
+                marker,
+                EventId::from_label(name),
+                self.register_current_thread(),
+                self.nanos_since_start(),
+            )
+            .with_cpu(self.current_cpu()),
+        );
+    }
+
+    fn record_marker(&self, marker: StringId, thread_id: u32) {
+        self.write_raw_event(
+            &RawEvent::new_instant_wide(
+                marker,
+                EventId::from_label(marker),
+                thread_id,
+                self.nanos_since_start(),
+            )
+            .with_cpu(self.current_cpu()),
+        );
+    }
+
     /// Creates a "start" event and returns a `TimingGuard` that will create
     /// the corresponding "end" event when it is dropped.
     #[inline]
@@ -115,22 +1171,271 @@ impl<S: SerializationSink> Profiler<S> {
             event_kind,
             thread_id,
             start_ns: self.nanos_since_start(),
+            cpu: self.current_cpu(),
+            blocked: false,
         }
     }
 
-    fn record_raw_event(&self, raw_event: &RawEvent) {
-        self.event_sink
-            .write_atomic(std::mem::size_of::<RawEvent>(), |bytes| {
-                raw_event.serialize(bytes);
-            });
+    /// Like `start_recording_interval_event()`, but for an interval in which
+    /// the thread is blocked rather than running, e.g. while it waits for a
+    /// lock, see `RawEvent::as_blocked()`.
+    #[inline]
+    pub fn start_recording_blocked_interval_event<'a>(
+        &'a self,
+        event_kind: StringId,
+        event_id: EventId,
+        thread_id: u32,
+    ) -> TimingGuard<'a, S> {
+        TimingGuard {
+            blocked: true,
+            ..self.start_recording_interval_event(event_kind, event_id, thread_id)
+        }
+    }
+
+    #[inline]
+    pub(crate) fn record_raw_event(&self, raw_event: &RawEvent) {
+        if self.recording_paused.load(Ordering::Relaxed)
+            || self.recording_stopped.load(Ordering::Relaxed)
+        {
+            return;
+        }
+
+        if let Some(control) = &self.control {
+            control.poll(&self.event_kinds);
+            if control.is_disabled(raw_event.event_kind) {
+                return;
+            }
+        }
+
+        if let Some(budget) = &self.budget {
+            let admitted = budget.admit(
+                || self.sink_stats().total().bytes_written,
+                || self.write_metadata(),
+            );
+            if !admitted {
+                if budget.when_exhausted() == BudgetPolicy::AggregateOnly {
+                    if let Some(summary) = &self.summary {
+                        summary.record(raw_event);
+                    }
+                }
+                return;
+            }
+        }
+
+        match &self.overhead {
+            Some(overhead) => overhead.record_event(self.timestamp_format.event_size(), || {
+                self.write_and_summarize(raw_event)
+            }),
+            None => self.write_and_summarize(raw_event),
+        }
+    }
+
+    #[inline]
+    fn write_and_summarize(&self, raw_event: &RawEvent) {
+        self.write_raw_event(raw_event);
+        self.check_sinks();
+
+        if let Some(summary) = &self.summary {
+            summary.record(raw_event);
+        }
+    }
+
+    #[inline]
+    fn check_sinks(&self) {
+        if self.event_sink.has_failed()
+            || self.string_table.has_failed()
+            || self
+                .thread_event_sinks
+                .as_ref()
+                .is_some_and(|sinks| sinks.current_has_failed())
+            || self
+                .event_segments
+                .as_ref()
+                .is_some_and(|segments| segments.has_failed())
+        {
+            self.handle_write_failure();
+        }
+    }
+
+    #[cold]
+    fn handle_write_failure(&self) {
+        match self.write_failure_policy {
+            WriteFailurePolicy::StopRecording => {
+                self.recording_stopped.store(true, Ordering::Relaxed);
+            }
+            WriteFailurePolicy::Panic => {
+                if let Err(error) = self.health() {
+                    panic!("{}", error);
+                }
+            }
+        }
+    }
+
+    fn write_raw_event(&self, raw_event: &RawEvent) {
+        if self.aggregate_only {
+            return;
+        }
+
+        let format = self.timestamp_format;
+        let raw_event = raw_event.to_resolution(self.timestamp_resolution);
+        if !raw_event.fits(format) {
+            self.drop_out_of_range_event();
+            return;
+        }
+        let write = |bytes: &mut [u8]| raw_event.serialize_as(format, bytes);
+
+        if let Some(segments) = &self.event_segments {
+            if segments.write_atomic(format.event_size(), write) {
+                // Don't keep the strings of the finished segment buffered
+                // for the rest of a long recording.
+                self.write_metadata();
+                self.string_table.flush();
+            }
+            return;
+        }
+
+        match &self.thread_event_sinks {
+            Some(sinks) => {
+                let (sink, new_file) = sinks.current();
+                sink.write_atomic(format.event_size(), write);
+
+                if new_file {
+                    self.write_metadata();
+                }
+            }
+            None => {
+                self.event_sink.write_atomic(format.event_size(), write);
+            }
+        }
+    }
+
+    // Events that the format can't represent, like intervals that end more
+    // than `MAX_INTERVAL_TIMESTAMP` after the start of a compact profile or
+    // integer events in a `TimestampFormat::CompactWithoutIntegers` one, are
+    // dropped and counted with the events the sinks dropped, rather than
+    // failing in the middle of a `TimingGuard`'s `drop()`.
+    #[cold]
+    fn drop_out_of_range_event(&self) {
+        if self.out_of_range_events.fetch_add(1, Ordering::Relaxed) == 0 {
+            Diagnostic::warning(
+                "event-out-of-range",
+                "dropping events that don't fit into the compact timestamp format; record \
+                 long-running processes with `TimestampFormat::Wide`, and integer events \
+                 with `TimestampFormat::Compact` or `Wide`",
+            )
+            .log();
+        }
+    }
+
+    fn dropped_events(&self) -> u64 {
+        self.out_of_range_events.load(Ordering::Relaxed)
+            + self.event_sink.dropped_writes()
+            + self
+                .thread_event_sinks
+                .as_ref()
+                .map_or(0, |sinks| sinks.dropped_writes())
+            + self
+                .event_segments
+                .as_ref()
+                .map_or(0, |segments| segments.dropped_writes())
+    }
+
+    #[inline]
+    pub(crate) fn current_cpu(&self) -> Option<u32> {
+        if self.record_cpu {
+            current_cpu()
+        } else {
+            None
+        }
+    }
+
+    pub(crate) fn nanos_since_start(&self) -> u64 {
+        let duration_since_start = match self.clock {
+            Clock::Monotonic => self.start_time.elapsed() + self.resume_offset,
+            Clock::Wall => SystemTime::now()
+                .duration_since(self.start_wall_time)
+                .unwrap_or_default(),
+            Clock::Logical => return self.logical_time.fetch_add(1, Ordering::Relaxed),
+        };
+        let nanos = duration_since_start.as_secs() * 1_000_000_000
+            + duration_since_start.subsec_nanos() as u64;
+
+        match self.clock {
+            // The system time can jump backwards, e.g. when it is adjusted via
+            // NTP. Never go back behind a timestamp that has been handed out
+            // already, so that intervals don't end before they start.
+            Clock::Wall => nanos.max(self.latest_wall_time.fetch_max(nanos, Ordering::Relaxed)),
+            _ => nanos,
+        }
     }
+}
 
-    fn nanos_since_start(&self) -> u64 {
-        let duration_since_start = self.start_time.elapsed();
-        duration_since_start.as_secs() * 1_000_000_000 + duration_since_start.subsec_nanos() as u64
+impl<S: SerializationSink> Drop for Profiler<S> {
+    fn drop(&mut self) {
+        // Mark the profile as truncated if something went wrong and record
+        // the number of dropped events and the overhead, if any. This is a
+        // best effort: if the string table sinks have failed too, there's
+        // nothing we can do.
+        if self.health().is_err() || self.dropped_events() > 0 || self.overhead.is_some() {
+            self.write_metadata();
+        }
+
+        // Like the metadata above, this is a best effort.
+        if let (Some(summary), Some(path)) = (&self.summary, &self.summary_file) {
+            let _ = std::fs::write(path, summary_json(&summary.totals()));
+        }
     }
 }
 
+/// The feature flags for the headers of the events files.
+fn feature_flags(config: &ProfilerConfig) -> u32 {
+    let mut feature_flags = config.timestamp_format.feature_flags()
+        | timestamp_resolution(config).feature_flags()
+        | FEATURE_REGISTERED_EVENT_KINDS
+        | FEATURE_BLOCKED_INTERVALS;
+    if records_cpu(config) {
+        feature_flags |= FEATURE_CPU_IDS;
+    }
+    feature_flags
+}
+
+fn timestamp_resolution(config: &ProfilerConfig) -> TimestampResolution {
+    match config.clock {
+        Clock::Logical => TimestampResolution::Nanoseconds,
+        _ => config.timestamp_resolution,
+    }
+}
+
+fn records_cpu(config: &ProfilerConfig) -> bool {
+    config.record_cpu && config.timestamp_format == TimestampFormat::Wide && !config.deterministic
+}
+
+/// The session id for the file headers of a new profile or shared string
+/// cache, see the `file_header` module.
+pub(crate) fn session_id(config: &ProfilerConfig) -> u128 {
+    if config.deterministic {
+        DETERMINISTIC_SESSION_ID
+    } else {
+        new_session_id()
+    }
+}
+
+/// Quotes and escapes `s` as a JSON string.
+pub(crate) fn json_string(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
 /// When dropped, this `TimingGuard` will record an "end" event in the
 /// `Profiler` it was created by.
 #[must_use]
@@ -140,18 +1445,27 @@ pub struct TimingGuard<'a, S: SerializationSink> {
     event_kind: StringId,
     thread_id: u32,
     start_ns: u64,
+    cpu: Option<u32>,
+    blocked: bool,
 }
 
 impl<'a, S: SerializationSink> Drop for TimingGuard<'a, S> {
     #[inline]
     fn drop(&mut self) {
-        let raw_event = RawEvent::new_interval(
+        let raw_event = RawEvent::new_interval_wide(
             self.event_kind,
             self.event_id,
             self.thread_id,
             self.start_ns,
             self.profiler.nanos_since_start(),
-        );
+        )
+        .with_cpu(self.cpu);
+
+        let raw_event = if self.blocked {
+            raw_event.as_blocked()
+        } else {
+            raw_event
+        };
 
         self.profiler.record_raw_event(&raw_event);
     }
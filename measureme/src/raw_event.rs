@@ -1,31 +1,220 @@
 use crate::event_id::EventId;
+use crate::file_header::{
+    FEATURE_INTEGER_EVENTS, FEATURE_MICROSECOND_TIMESTAMPS, FEATURE_WIDE_TIMESTAMPS,
+    FILE_MAGIC_EVENT_STREAM, FILE_MAGIC_EVENT_STREAM_WIDE,
+};
 use crate::stringtable::StringId;
+use byteorder::{ByteOrder, LittleEndian};
+
+/// The size of a serialized `RawEvent` in the compact format.
+pub const RAW_EVENT_SIZE: usize = 24;
+
+/// The size of a serialized `RawEvent` in the wide format.
+pub const WIDE_RAW_EVENT_SIZE: usize = 32;
+
+/// How the timestamps of events are stored in the events file.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum TimestampFormat {
+    /// 48 bits per timestamp, for `RAW_EVENT_SIZE` bytes per event. Integer
+    /// events take the upper half of the range of end timestamps, so
+    /// intervals have to end within about 39 hours after the start of the
+    /// profile, see `MAX_INTERVAL_TIMESTAMP`; the profiler drops, and counts
+    /// as dropped, the events that don't. Signalled by
+    /// `FEATURE_INTEGER_EVENTS`.
+    #[default]
+    Compact,
+
+    /// Like `Compact`, but without integer events, so that intervals can end
+    /// within about 78 hours after the start of the profile, see
+    /// `MAX_INTERVAL_TIMESTAMP_WITHOUT_INTEGERS`. The profiler drops, and
+    /// counts as dropped, the integer events recorded into such a profile.
+    CompactWithoutIntegers,
+
+    /// 64 bits per timestamp, for `WIDE_RAW_EVENT_SIZE` bytes per event.
+    /// Timestamps go up to `MAX_WIDE_TIMESTAMP`, i.e. more than 290 years,
+    /// which is enough for long-running services.
+    Wide,
+}
+
+impl TimestampFormat {
+    pub fn event_size(self) -> usize {
+        match self {
+            TimestampFormat::Compact | TimestampFormat::CompactWithoutIntegers => RAW_EVENT_SIZE,
+            TimestampFormat::Wide => WIDE_RAW_EVENT_SIZE,
+        }
+    }
+
+    /// The magic of the events file, which tells the compact formats apart
+    /// from the wide one.
+    pub fn file_magic(self) -> &'static [u8; 4] {
+        match self {
+            TimestampFormat::Compact | TimestampFormat::CompactWithoutIntegers => {
+                FILE_MAGIC_EVENT_STREAM
+            }
+            TimestampFormat::Wide => FILE_MAGIC_EVENT_STREAM_WIDE,
+        }
+    }
+
+    /// The feature flags of the header of the events file, which tell the
+    /// compact formats apart.
+    pub fn feature_flags(self) -> u32 {
+        match self {
+            TimestampFormat::Compact => FEATURE_INTEGER_EVENTS,
+            TimestampFormat::CompactWithoutIntegers => 0,
+            TimestampFormat::Wide => FEATURE_WIDE_TIMESTAMPS,
+        }
+    }
+
+    /// The format of an events file with the given magic, where the compact
+    /// formats are both `Compact`. Readers need the feature flags of the
+    /// file to tell them apart, see `from_file_header()`.
+    pub fn from_file_magic(magic: &[u8]) -> Option<TimestampFormat> {
+        TimestampFormat::from_file_header(magic, FEATURE_INTEGER_EVENTS)
+    }
+
+    /// The format of an events file with the given magic and header feature
+    /// flags, or `None` if the magic isn't that of an events file.
+    pub fn from_file_header(magic: &[u8], feature_flags: u32) -> Option<TimestampFormat> {
+        if magic == FILE_MAGIC_EVENT_STREAM {
+            if feature_flags & FEATURE_INTEGER_EVENTS != 0 {
+                Some(TimestampFormat::Compact)
+            } else {
+                Some(TimestampFormat::CompactWithoutIntegers)
+            }
+        } else if magic == FILE_MAGIC_EVENT_STREAM_WIDE {
+            Some(TimestampFormat::Wide)
+        } else {
+            None
+        }
+    }
+}
+
+/// The unit of the timestamps stored in the events file. Events are always
+/// recorded and analyzed with nanosecond timestamps, the profiler only
+/// converts them when it writes them, and readers convert them back.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum TimestampResolution {
+    #[default]
+    Nanoseconds,
+
+    /// For profiles that don't need nanosecond precision: the timestamps
+    /// are rounded down to whole microseconds, which leaves about ten fewer
+    /// bits of noise per timestamp and makes the events file compress much
+    /// better. Signalled by `FEATURE_MICROSECOND_TIMESTAMPS`.
+    Microseconds,
+}
+
+impl TimestampResolution {
+    pub fn nanos_per_tick(self) -> u64 {
+        match self {
+            TimestampResolution::Nanoseconds => 1,
+            TimestampResolution::Microseconds => 1_000,
+        }
+    }
+
+    /// The feature flags of the header of the events file.
+    pub fn feature_flags(self) -> u32 {
+        match self {
+            TimestampResolution::Nanoseconds => 0,
+            TimestampResolution::Microseconds => FEATURE_MICROSECOND_TIMESTAMPS,
+        }
+    }
+
+    pub fn from_feature_flags(feature_flags: u32) -> TimestampResolution {
+        if feature_flags & FEATURE_MICROSECOND_TIMESTAMPS != 0 {
+            TimestampResolution::Microseconds
+        } else {
+            TimestampResolution::Nanoseconds
+        }
+    }
+}
 
 /// `RawEvent` is how events are stored on-disk. If you change this struct,
 /// make sure that you increment `file_header::CURRENT_FILE_FORMAT_VERSION`.
-#[derive(Eq, PartialEq, Debug)]
-#[repr(C)]
+///
+/// Events are stored in one of two formats, see `TimestampFormat`. In both,
+/// every field is stored in little-endian byte order, independently of the
+/// byte order of the machine that recorded the profile, so that profiles
+/// recorded on big-endian targets like s390x can be analyzed on x86 machines
+/// and vice versa.
+///
+/// In the compact format, an event takes `RAW_EVENT_SIZE` bytes: the event
+/// kind, the event id and the thread id as `u32`s, followed by the 48 bit
+/// start and end timestamps, stored as the lower 32 bits of the start, the
+/// lower 32 bits of the end, and a `u32` holding the upper 16 bits of the
+/// start in its upper half and the upper 16 bits of the end in its lower
+/// half.
+///
+/// In the wide format, an event takes `WIDE_RAW_EVENT_SIZE` bytes: the event
+/// kind, the event id and the thread id as `u32`s, a `u32` holding the CPU the
+/// event started on plus one (or zero if it is unknown), and the start and end
+/// timestamps as `u64`s. The compact format has no room for the CPU.
+///
+/// The highest bit of the event kind is set for blocked intervals, see
+/// `as_blocked()`.
+///
+/// Instant events store a marker in place of the end timestamp and integer
+/// events an offset payload, see `INSTANT_TIMESTAMP_MARKER` and
+/// `INTEGER_PAYLOAD_OFFSET` and their wide counterparts.
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
 pub struct RawEvent {
     pub event_kind: StringId,
     pub event_id: EventId,
     pub thread_id: u32,
 
-    // The following 96 bits store the start and the end timestamp, using
-    // 48 bits for each.
-    pub start_time_lower: u32,
-    pub end_time_lower: u32,
-    pub start_and_end_upper: u32,
+    // In memory, the timestamps are always kept in the wide encoding, i.e.
+    // `end` is `WIDE_INSTANT_TIMESTAMP_MARKER` for instant events and
+    // `WIDE_INTEGER_PAYLOAD_OFFSET + value` for integer events.
+    start: u64,
+    end: u64,
+    // The CPU the event started on plus one, or zero if it is unknown. See
+    // `with_cpu()`.
+    cpu: u32,
+    blocked: bool,
 }
 
-/// `RawEvents` that have an end time stamp with this value are instant events.
+/// Blocked intervals (see `RawEvent::as_blocked()`) have this bit set in the
+/// stored event kind. `StringId`s never use it.
+const BLOCKED_FLAG: u32 = 1 << 31;
+
+/// Compact `RawEvents` that have an end time stamp with this value are
+/// instant events.
 const INSTANT_TIMESTAMP_MARKER: u64 = 0xFFFF_FFFF_FFFF;
 
+/// `RawEvents` with an end time stamp in `INTEGER_PAYLOAD_OFFSET ..
+/// INSTANT_TIMESTAMP_MARKER` are integer events: instant events that carry an
+/// integer payload of `end - INTEGER_PAYLOAD_OFFSET`. This lets events carry
+/// small values, like byte counts, without going through the string table.
+const INTEGER_PAYLOAD_OFFSET: u64 = 0x8000_0000_0000;
+
 /// The max instant timestamp we can represent with the 48 bits available.
 pub const MAX_INSTANT_TIMESTAMP: u64 = 0xFFFF_FFFF_FFFF;
 
-/// The max interval timestamp we can represent with the 48 bits available.
-/// The highest value is reserved for the `INSTANT_TIMESTAMP_MARKER`.
-pub const MAX_INTERVAL_TIMESTAMP: u64 = INSTANT_TIMESTAMP_MARKER - 1;
+/// The max interval timestamp of `TimestampFormat::Compact`, whose upper
+/// half of the end time stamp range is reserved for integer payloads and
+/// the `INSTANT_TIMESTAMP_MARKER`. This still allows for intervals ending
+/// more than 39 hours after the start of the profile. Instant events can
+/// still use the whole range.
+pub const MAX_INTERVAL_TIMESTAMP: u64 = INTEGER_PAYLOAD_OFFSET - 1;
+
+/// The max interval timestamp of `TimestampFormat::CompactWithoutIntegers`,
+/// which only reserves the `INSTANT_TIMESTAMP_MARKER`. This allows for
+/// intervals ending more than 78 hours after the start of the profile.
+pub const MAX_INTERVAL_TIMESTAMP_WITHOUT_INTEGERS: u64 = INSTANT_TIMESTAMP_MARKER - 1;
+
+/// The max integer payload of an integer event.
+pub const MAX_INTEGER_VALUE: u64 = INSTANT_TIMESTAMP_MARKER - INTEGER_PAYLOAD_OFFSET - 1;
+
+/// The counterparts of the constants above for the wide format.
+const WIDE_INSTANT_TIMESTAMP_MARKER: u64 = u64::MAX;
+const WIDE_INTEGER_PAYLOAD_OFFSET: u64 = 1 << 63;
+
+/// The max timestamp of any event in the wide format.
+pub const MAX_WIDE_TIMESTAMP: u64 = WIDE_INTEGER_PAYLOAD_OFFSET - 1;
+
+/// The max integer payload of an integer event in the wide format.
+pub const MAX_WIDE_INTEGER_VALUE: u64 =
+    WIDE_INSTANT_TIMESTAMP_MARKER - WIDE_INTEGER_PAYLOAD_OFFSET - 1;
 
 impl RawEvent {
     #[inline]
@@ -36,139 +225,348 @@ impl RawEvent {
         start_nanos: u64,
         end_nanos: u64,
     ) -> RawEvent {
-        assert!(start_nanos <= end_nanos);
         assert!(end_nanos <= MAX_INTERVAL_TIMESTAMP);
+        RawEvent::new_interval_wide(event_kind, event_id, thread_id, start_nanos, end_nanos)
+    }
 
-        let start_time_lower = start_nanos as u32;
-        let end_time_lower = end_nanos as u32;
+    #[inline]
+    pub fn new_instant(
+        event_kind: StringId,
+        event_id: EventId,
+        thread_id: u32,
+        timestamp_ns: u64,
+    ) -> RawEvent {
+        assert!(timestamp_ns <= MAX_INSTANT_TIMESTAMP);
+        RawEvent::new_instant_wide(event_kind, event_id, thread_id, timestamp_ns)
+    }
 
-        let start_time_upper = (start_nanos >> 16) as u32 & 0xFFFF_0000;
-        let end_time_upper = (end_nanos >> 32) as u32;
+    #[inline]
+    pub fn new_integer(
+        event_kind: StringId,
+        event_id: EventId,
+        thread_id: u32,
+        timestamp_ns: u64,
+        value: u64,
+    ) -> RawEvent {
+        assert!(timestamp_ns <= MAX_INSTANT_TIMESTAMP);
+        assert!(value <= MAX_INTEGER_VALUE);
+        RawEvent::new_integer_wide(event_kind, event_id, thread_id, timestamp_ns, value)
+    }
 
-        let start_and_end_upper = start_time_upper | end_time_upper;
+    /// Like `new_interval()`, but with the limits of the wide format. Such
+    /// events can only be serialized in the compact format if they are
+    /// within its limits.
+    #[inline]
+    pub fn new_interval_wide(
+        event_kind: StringId,
+        event_id: EventId,
+        thread_id: u32,
+        start_nanos: u64,
+        end_nanos: u64,
+    ) -> RawEvent {
+        assert!(start_nanos <= end_nanos);
+        assert!(end_nanos <= MAX_WIDE_TIMESTAMP);
 
         RawEvent {
             event_kind,
             event_id,
             thread_id,
-            start_time_lower,
-            end_time_lower,
-            start_and_end_upper,
+            start: start_nanos,
+            end: end_nanos,
+            cpu: 0,
+            blocked: false,
         }
     }
 
+    /// Like `new_instant()`, but with the limits of the wide format.
     #[inline]
-    pub fn new_instant(
+    pub fn new_instant_wide(
         event_kind: StringId,
         event_id: EventId,
         thread_id: u32,
         timestamp_ns: u64,
     ) -> RawEvent {
-        assert!(timestamp_ns <= MAX_INSTANT_TIMESTAMP);
+        assert!(timestamp_ns <= MAX_WIDE_TIMESTAMP);
 
-        let start_time_lower = timestamp_ns as u32;
-        let end_time_lower = 0xFFFF_FFFF;
+        RawEvent {
+            event_kind,
+            event_id,
+            thread_id,
+            start: timestamp_ns,
+            end: WIDE_INSTANT_TIMESTAMP_MARKER,
+            cpu: 0,
+            blocked: false,
+        }
+    }
 
-        let start_time_upper = (timestamp_ns >> 16) as u32;
-        let start_and_end_upper = start_time_upper | 0x0000_FFFF;
+    /// Like `new_integer()`, but with the limits of the wide format.
+    #[inline]
+    pub fn new_integer_wide(
+        event_kind: StringId,
+        event_id: EventId,
+        thread_id: u32,
+        timestamp_ns: u64,
+        value: u64,
+    ) -> RawEvent {
+        assert!(timestamp_ns <= MAX_WIDE_TIMESTAMP);
+        assert!(value <= MAX_WIDE_INTEGER_VALUE);
 
         RawEvent {
             event_kind,
             event_id,
             thread_id,
-            start_time_lower,
-            end_time_lower,
-            start_and_end_upper,
+            start: timestamp_ns,
+            end: WIDE_INTEGER_PAYLOAD_OFFSET + value,
+            cpu: 0,
+            blocked: false,
         }
     }
 
     #[inline]
     pub fn start_nanos(&self) -> u64 {
-        self.start_time_lower as u64 | (((self.start_and_end_upper & 0xFFFF_0000) as u64) << 16)
+        self.start
     }
 
+    /// Returns the end timestamp of interval events. The result is
+    /// meaningless for instant events.
     #[inline]
     pub fn end_nanos(&self) -> u64 {
-        self.end_time_lower as u64 | (((self.start_and_end_upper & 0x0000_FFFF) as u64) << 32)
+        self.end
     }
 
+    /// Returns `true` for instant events, including integer events.
     #[inline]
     pub fn is_instant(&self) -> bool {
-        self.end_nanos() == INSTANT_TIMESTAMP_MARKER
+        self.end > MAX_WIDE_TIMESTAMP
     }
 
+    /// Marks an interval event as a blocked interval: time in which the
+    /// thread was waiting (e.g. for a lock or for I/O) instead of running.
+    /// Analysis tools count blocked intervals separately from the time spent
+    /// running.
     #[inline]
-    pub fn serialize(&self, bytes: &mut [u8]) {
-        assert!(bytes.len() == std::mem::size_of::<RawEvent>());
-
-        #[cfg(target_endian = "little")]
-        {
-            let raw_event_bytes: &[u8] = unsafe {
-                std::slice::from_raw_parts(
-                    self as *const _ as *const u8,
-                    std::mem::size_of::<RawEvent>(),
-                )
-            };
-
-            bytes.copy_from_slice(raw_event_bytes);
+    pub fn as_blocked(mut self) -> RawEvent {
+        assert!(!self.is_instant());
+        self.blocked = true;
+        self
+    }
+
+    #[inline]
+    pub fn is_blocked(&self) -> bool {
+        self.blocked
+    }
+
+    /// Sets the CPU the event started on, see `cpu()`.
+    #[inline]
+    pub fn with_cpu(mut self, cpu: Option<u32>) -> RawEvent {
+        self.cpu = match cpu {
+            Some(cpu) => cpu.checked_add(1).unwrap(),
+            None => 0,
+        };
+        self
+    }
+
+    /// Returns the CPU the event started on, if the profiler recorded it
+    /// (see `ProfilerConfig::record_cpu`). It is only stored in the wide
+    /// format, so this returns `None` for events in the compact format.
+    #[inline]
+    pub fn cpu(&self) -> Option<u32> {
+        self.cpu.checked_sub(1)
+    }
+
+    /// Returns the payload of an integer event, or `None` for other events.
+    #[inline]
+    pub fn integer_value(&self) -> Option<u64> {
+        if self.is_instant() && self.end != WIDE_INSTANT_TIMESTAMP_MARKER {
+            Some(self.end - WIDE_INTEGER_PAYLOAD_OFFSET)
+        } else {
+            None
+        }
+    }
+
+    /// Converts the nanosecond timestamps of the event into ticks of
+    /// `resolution`, rounding down, which is how the profiler writes the
+    /// events of a profile with that resolution. Rounding down both ends
+    /// keeps nested events nested. The payloads of integer events are left
+    /// alone.
+    #[inline]
+    pub fn to_resolution(mut self, resolution: TimestampResolution) -> RawEvent {
+        let nanos_per_tick = resolution.nanos_per_tick();
+        if nanos_per_tick != 1 {
+            self.start /= nanos_per_tick;
+            if !self.is_instant() {
+                self.end /= nanos_per_tick;
+            }
         }
+        self
+    }
 
-        #[cfg(target_endian = "big")]
-        {
-            // We always emit data as little endian, which we have to do
-            // manually on big endian targets.
-            use byteorder::{ByteOrder, LittleEndian};
-
-            LittleEndian::write_u32(&mut bytes[0..], self.event_kind.as_u32());
-            LittleEndian::write_u32(&mut bytes[4..], self.event_id.as_u32());
-            LittleEndian::write_u32(&mut bytes[8..], self.thread_id);
-            LittleEndian::write_u32(&mut bytes[12..], self.start_time_lower);
-            LittleEndian::write_u32(&mut bytes[16..], self.end_time_lower);
-            LittleEndian::write_u32(&mut bytes[20..], self.start_and_end_upper);
+    /// The inverse of `to_resolution()`, for the events of a profile with
+    /// `resolution` that have just been deserialized.
+    #[inline]
+    pub fn from_resolution(mut self, resolution: TimestampResolution) -> RawEvent {
+        let nanos_per_tick = resolution.nanos_per_tick();
+        if nanos_per_tick != 1 {
+            let to_nanos =
+                |ticks: u64| ticks.saturating_mul(nanos_per_tick).min(MAX_WIDE_TIMESTAMP);
+            self.start = to_nanos(self.start);
+            if !self.is_instant() {
+                self.end = to_nanos(self.end);
+            }
         }
+        self
+    }
+
+    /// Serializes the event in the compact format. Panics if the event
+    /// doesn't fit into it.
+    #[inline]
+    pub fn serialize(&self, bytes: &mut [u8]) {
+        self.encode::<LittleEndian>(TimestampFormat::Compact, bytes)
     }
 
     #[inline]
     pub fn deserialize(bytes: &[u8]) -> RawEvent {
-        assert!(bytes.len() == std::mem::size_of::<RawEvent>());
-
-        #[cfg(target_endian = "little")]
-        {
-            let mut raw_event = RawEvent::default();
-            unsafe {
-                let raw_event = std::slice::from_raw_parts_mut(
-                    &mut raw_event as *mut RawEvent as *mut u8,
-                    std::mem::size_of::<RawEvent>(),
-                );
-                raw_event.copy_from_slice(bytes);
-            };
-            raw_event
+        RawEvent::decode::<LittleEndian>(TimestampFormat::Compact, bytes)
+    }
+
+    /// Whether the event can be serialized in `format`, i.e. whether its
+    /// timestamps and integer payload are within the limits of the format.
+    /// Events of the wide format always fit into it, integer events never
+    /// fit into `TimestampFormat::CompactWithoutIntegers`.
+    #[inline]
+    pub fn fits(&self, format: TimestampFormat) -> bool {
+        if format == TimestampFormat::Wide {
+            return true;
+        }
+
+        let end_fits = if self.end == WIDE_INSTANT_TIMESTAMP_MARKER {
+            true
+        } else if let Some(value) = self.integer_value() {
+            format == TimestampFormat::Compact && value <= MAX_INTEGER_VALUE
+        } else {
+            self.end <= max_interval_timestamp(format)
+        };
+        end_fits && self.start <= MAX_INSTANT_TIMESTAMP
+    }
+
+    /// Serializes the event into `format.event_size()` bytes. Panics if the
+    /// event doesn't fit into `format`, see `fits()`.
+    #[inline]
+    pub fn serialize_as(&self, format: TimestampFormat, bytes: &mut [u8]) {
+        self.encode::<LittleEndian>(format, bytes)
+    }
+
+    #[inline]
+    pub fn deserialize_as(format: TimestampFormat, bytes: &[u8]) -> RawEvent {
+        RawEvent::decode::<LittleEndian>(format, bytes)
+    }
+
+    // The byte order is a parameter only so that the tests can check that
+    // the encoding doesn't depend on the byte order of the host.
+    #[inline]
+    fn encode<B: ByteOrder>(&self, format: TimestampFormat, bytes: &mut [u8]) {
+        assert!(bytes.len() == format.event_size());
+
+        let blocked = if self.blocked { BLOCKED_FLAG } else { 0 };
+        B::write_u32(&mut bytes[0..], self.event_kind.as_u32() | blocked);
+        B::write_u32(&mut bytes[4..], self.event_id.as_u32());
+        B::write_u32(&mut bytes[8..], self.thread_id);
+
+        match format {
+            TimestampFormat::Compact | TimestampFormat::CompactWithoutIntegers => {
+                let start = self.start;
+                let end = if self.end == WIDE_INSTANT_TIMESTAMP_MARKER {
+                    INSTANT_TIMESTAMP_MARKER
+                } else if let Some(value) = self.integer_value() {
+                    assert!(format == TimestampFormat::Compact);
+                    assert!(value <= MAX_INTEGER_VALUE);
+                    INTEGER_PAYLOAD_OFFSET + value
+                } else {
+                    assert!(self.end <= max_interval_timestamp(format));
+                    self.end
+                };
+                assert!(start <= MAX_INSTANT_TIMESTAMP);
+
+                let start_time_upper = (start >> 16) as u32 & 0xFFFF_0000;
+                let end_time_upper = (end >> 32) as u32;
+
+                B::write_u32(&mut bytes[12..], start as u32);
+                B::write_u32(&mut bytes[16..], end as u32);
+                B::write_u32(&mut bytes[20..], start_time_upper | end_time_upper);
+            }
+            TimestampFormat::Wide => {
+                B::write_u32(&mut bytes[12..], self.cpu);
+                B::write_u64(&mut bytes[16..], self.start);
+                B::write_u64(&mut bytes[24..], self.end);
+            }
         }
+    }
+
+    #[inline]
+    fn decode<B: ByteOrder>(format: TimestampFormat, bytes: &[u8]) -> RawEvent {
+        assert!(bytes.len() == format.event_size());
 
-        #[cfg(target_endian = "big")]
-        {
-            use byteorder::{ByteOrder, LittleEndian};
-            RawEvent {
-                event_kind: StringId::new(LittleEndian::read_u32(&bytes[0..])),
-                event_id: EventId::from_u32(LittleEndian::read_u32(&bytes[4..])),
-                thread_id: LittleEndian::read_u32(&bytes[8..]),
-                start_time_lower: LittleEndian::read_u32(&bytes[12..]),
-                end_time_lower: LittleEndian::read_u32(&bytes[16..]),
-                start_and_end_upper: LittleEndian::read_u32(&bytes[20..]),
+        let (start, end, cpu) = match format {
+            TimestampFormat::Compact | TimestampFormat::CompactWithoutIntegers => {
+                let start_time_lower = B::read_u32(&bytes[12..]);
+                let end_time_lower = B::read_u32(&bytes[16..]);
+                let start_and_end_upper = B::read_u32(&bytes[20..]);
+
+                let start =
+                    start_time_lower as u64 | (((start_and_end_upper & 0xFFFF_0000) as u64) << 16);
+                let end =
+                    end_time_lower as u64 | (((start_and_end_upper & 0x0000_FFFF) as u64) << 32);
+
+                let end = if end == INSTANT_TIMESTAMP_MARKER {
+                    WIDE_INSTANT_TIMESTAMP_MARKER
+                } else if end > max_interval_timestamp(format) {
+                    WIDE_INTEGER_PAYLOAD_OFFSET + (end - INTEGER_PAYLOAD_OFFSET)
+                } else {
+                    end
+                };
+
+                (start, end, 0)
             }
+            TimestampFormat::Wide => (
+                B::read_u64(&bytes[16..]),
+                B::read_u64(&bytes[24..]),
+                B::read_u32(&bytes[12..]),
+            ),
+        };
+
+        let event_kind = B::read_u32(&bytes[0..]);
+
+        RawEvent {
+            event_kind: StringId::from_raw(event_kind & !BLOCKED_FLAG),
+            event_id: EventId::from_raw(B::read_u32(&bytes[4..])),
+            thread_id: B::read_u32(&bytes[8..]),
+            start,
+            end,
+            cpu,
+            blocked: event_kind & BLOCKED_FLAG != 0,
         }
     }
 }
 
+/// The max interval timestamp of a compact `format`.
+#[inline]
+fn max_interval_timestamp(format: TimestampFormat) -> u64 {
+    match format {
+        TimestampFormat::CompactWithoutIntegers => MAX_INTERVAL_TIMESTAMP_WITHOUT_INTEGERS,
+        _ => MAX_INTERVAL_TIMESTAMP,
+    }
+}
+
 impl Default for RawEvent {
     fn default() -> Self {
         RawEvent {
             event_kind: StringId::INVALID,
             event_id: EventId::INVALID,
             thread_id: 0,
-            start_time_lower: 0,
-            end_time_lower: 0,
-            start_and_end_upper: 0,
+            start: 0,
+            end: 0,
+            cpu: 0,
+            blocked: false,
         }
     }
 }
@@ -179,8 +577,278 @@ mod tests {
 
     #[test]
     fn raw_event_has_expected_size() {
-        // A test case to prevent accidental regressions of RawEvent's size.
-        assert_eq!(std::mem::size_of::<RawEvent>(), 24);
+        // A test case to prevent accidental regressions of the on-disk size
+        // of events.
+        let event = RawEvent::new_instant(StringId::INVALID, EventId::INVALID, 0, 0);
+
+        let mut compact = [0; 24];
+        event.serialize(&mut compact);
+        let mut wide = [0; 32];
+        event.serialize_as(TimestampFormat::Wide, &mut wide);
+
+        assert_eq!(TimestampFormat::Compact.event_size(), 24);
+        assert_eq!(TimestampFormat::Wide.event_size(), 32);
+    }
+
+    #[test]
+    fn events_that_fit_the_format() {
+        let kind = StringId::INVALID;
+        let id = EventId::INVALID;
+        let interval = |end| RawEvent::new_interval_wide(kind, id, 0, 0, end);
+        let instant = |timestamp| RawEvent::new_instant_wide(kind, id, 0, timestamp);
+        let integer = |value| RawEvent::new_integer_wide(kind, id, 0, 0, value);
+
+        let fitting = [
+            interval(MAX_INTERVAL_TIMESTAMP),
+            instant(MAX_INSTANT_TIMESTAMP),
+            integer(MAX_INTEGER_VALUE),
+        ];
+        let too_large = [
+            interval(MAX_INTERVAL_TIMESTAMP + 1),
+            instant(MAX_INSTANT_TIMESTAMP + 1),
+            integer(MAX_INTEGER_VALUE + 1),
+            interval(MAX_WIDE_TIMESTAMP),
+            integer(MAX_WIDE_INTEGER_VALUE),
+        ];
+
+        for event in &fitting {
+            assert!(event.fits(TimestampFormat::Compact), "{:?}", event);
+            event.serialize_as(TimestampFormat::Compact, &mut [0; RAW_EVENT_SIZE]);
+        }
+        for event in &too_large {
+            assert!(!event.fits(TimestampFormat::Compact), "{:?}", event);
+        }
+        for event in fitting.iter().chain(&too_large) {
+            assert!(event.fits(TimestampFormat::Wide));
+        }
+
+        // Without integer events, intervals get the upper half of the range.
+        let without_integers = TimestampFormat::CompactWithoutIntegers;
+        let long_interval = interval(MAX_INTERVAL_TIMESTAMP_WITHOUT_INTEGERS);
+        assert!(!long_interval.fits(TimestampFormat::Compact));
+        assert!(long_interval.fits(without_integers));
+        long_interval.serialize_as(without_integers, &mut [0; RAW_EVENT_SIZE]);
+        assert!(instant(MAX_INSTANT_TIMESTAMP).fits(without_integers));
+        assert!(!interval(MAX_INTERVAL_TIMESTAMP_WITHOUT_INTEGERS + 1).fits(without_integers));
+        assert!(!integer(0).fits(without_integers));
+    }
+
+    fn sample_event() -> RawEvent {
+        RawEvent::new_interval(
+            StringId::new(0x0102_0304),
+            EventId::from_u32(0x0506_0708),
+            0x090a_0b0c,
+            0x1122_3344_5566,
+            0x1122_3344_5577,
+        )
+    }
+
+    #[test]
+    fn serialized_layout_is_little_endian() {
+        let mut bytes = [0; RAW_EVENT_SIZE];
+        sample_event().serialize(&mut bytes);
+
+        assert_eq!(&bytes[0..4], &[0x04, 0x03, 0x02, 0x01]);
+        assert_eq!(&bytes[4..8], &[0x08, 0x07, 0x06, 0x05]);
+        assert_eq!(&bytes[8..12], &[0x0c, 0x0b, 0x0a, 0x09]);
+        assert_eq!(&bytes[12..16], &[0x66, 0x55, 0x44, 0x33]);
+        assert_eq!(&bytes[16..20], &[0x77, 0x55, 0x44, 0x33]);
+        assert_eq!(&bytes[20..24], &[0x22, 0x11, 0x22, 0x11]);
+    }
+
+    #[test]
+    fn round_trip_in_both_byte_orders() {
+        use byteorder::BigEndian;
+
+        let event = sample_event();
+
+        let mut little = [0; RAW_EVENT_SIZE];
+        event.encode::<LittleEndian>(TimestampFormat::Compact, &mut little);
+        assert_eq!(
+            RawEvent::decode::<LittleEndian>(TimestampFormat::Compact, &little),
+            event
+        );
+        assert_eq!(RawEvent::deserialize(&little), event);
+
+        let mut big = [0; RAW_EVENT_SIZE];
+        event.encode::<BigEndian>(TimestampFormat::Compact, &mut big);
+        assert_eq!(
+            RawEvent::decode::<BigEndian>(TimestampFormat::Compact, &big),
+            event
+        );
+
+        // Each field is byte-swapped, the field order stays the same.
+        for (l, b) in little.chunks(4).zip(big.chunks(4)) {
+            assert_eq!(
+                l.iter().rev().collect::<Vec<_>>(),
+                b.iter().collect::<Vec<_>>()
+            );
+        }
+    }
+
+    #[test]
+    fn round_trip_in_both_formats() {
+        let events = [
+            sample_event(),
+            RawEvent::new_instant(StringId::new(1), EventId::from_u32(2), 3, 4),
+            RawEvent::new_integer(StringId::new(1), EventId::from_u32(2), 3, 4, 5),
+        ];
+
+        let formats = [
+            TimestampFormat::Compact,
+            TimestampFormat::CompactWithoutIntegers,
+            TimestampFormat::Wide,
+        ];
+        for event in &events {
+            for &format in &formats {
+                if !event.fits(format) {
+                    continue;
+                }
+                let mut bytes = vec![0; format.event_size()];
+                event.serialize_as(format, &mut bytes);
+                assert_eq!(RawEvent::deserialize_as(format, &bytes), *event);
+            }
+        }
+
+        let long_interval = RawEvent::new_interval_wide(
+            StringId::new(1),
+            EventId::from_u32(2),
+            3,
+            4,
+            MAX_INTERVAL_TIMESTAMP_WITHOUT_INTEGERS,
+        );
+        let format = TimestampFormat::CompactWithoutIntegers;
+        let mut bytes = [0; RAW_EVENT_SIZE];
+        long_interval.serialize_as(format, &mut bytes);
+        assert_eq!(RawEvent::deserialize_as(format, &bytes), long_interval);
+    }
+
+    #[test]
+    fn formats_from_file_headers() {
+        let compact = TimestampFormat::from_file_header(FILE_MAGIC_EVENT_STREAM, 0);
+        assert_eq!(compact, Some(TimestampFormat::CompactWithoutIntegers));
+
+        for &format in &[
+            TimestampFormat::Compact,
+            TimestampFormat::CompactWithoutIntegers,
+            TimestampFormat::Wide,
+        ] {
+            assert_eq!(
+                TimestampFormat::from_file_header(format.file_magic(), format.feature_flags()),
+                Some(format)
+            );
+        }
+
+        assert_eq!(TimestampFormat::from_file_header(b"MMSD", 0), None);
+    }
+
+    #[test]
+    fn wide_timestamps() {
+        // About three years in nanoseconds.
+        let three_years = 3 * 365 * 24 * 3600 * 1_000_000_000u64;
+
+        let events = [
+            RawEvent::new_interval_wide(
+                StringId::INVALID,
+                EventId::INVALID,
+                1,
+                three_years,
+                MAX_WIDE_TIMESTAMP,
+            ),
+            RawEvent::new_instant_wide(StringId::INVALID, EventId::INVALID, 1, three_years),
+            RawEvent::new_integer_wide(
+                StringId::INVALID,
+                EventId::INVALID,
+                1,
+                MAX_WIDE_TIMESTAMP,
+                MAX_WIDE_INTEGER_VALUE,
+            ),
+        ];
+
+        let mut bytes = [0; WIDE_RAW_EVENT_SIZE];
+        for event in &events {
+            event.serialize_as(TimestampFormat::Wide, &mut bytes);
+            assert_eq!(
+                RawEvent::deserialize_as(TimestampFormat::Wide, &bytes),
+                *event
+            );
+        }
+
+        assert!(!events[0].is_instant());
+        assert_eq!(events[0].end_nanos(), MAX_WIDE_TIMESTAMP);
+        assert!(events[1].is_instant());
+        assert_eq!(events[1].integer_value(), None);
+        assert_eq!(events[2].integer_value(), Some(MAX_WIDE_INTEGER_VALUE));
+    }
+
+    #[test]
+    #[should_panic]
+    fn wide_event_does_not_fit_compact_format() {
+        let event = RawEvent::new_instant_wide(
+            StringId::INVALID,
+            EventId::INVALID,
+            1,
+            MAX_INSTANT_TIMESTAMP + 1,
+        );
+        event.serialize(&mut [0; RAW_EVENT_SIZE]);
+    }
+
+    #[test]
+    fn blocked() {
+        let event = RawEvent::new_interval(StringId::new(5), EventId::INVALID, 1, 2, 3);
+        assert!(!event.is_blocked());
+
+        let event = event.as_blocked();
+        for &format in &[TimestampFormat::Compact, TimestampFormat::Wide] {
+            let mut bytes = vec![0; format.event_size()];
+            event.serialize_as(format, &mut bytes);
+
+            let decoded = RawEvent::deserialize_as(format, &bytes);
+            assert!(decoded.is_blocked());
+            assert_eq!(decoded.event_kind, StringId::new(5));
+        }
+    }
+
+    #[test]
+    fn cpu() {
+        let event = RawEvent::new_instant(StringId::INVALID, EventId::INVALID, 1, 2);
+        assert_eq!(event.cpu(), None);
+
+        let event = event.with_cpu(Some(0));
+        assert_eq!(event.cpu(), Some(0));
+
+        let mut bytes = [0; WIDE_RAW_EVENT_SIZE];
+        event.serialize_as(TimestampFormat::Wide, &mut bytes);
+        assert_eq!(
+            RawEvent::deserialize_as(TimestampFormat::Wide, &bytes).cpu(),
+            Some(0)
+        );
+
+        // The compact format drops it.
+        let mut bytes = [0; RAW_EVENT_SIZE];
+        event.serialize(&mut bytes);
+        assert_eq!(RawEvent::deserialize(&bytes).cpu(), None);
+    }
+
+    #[test]
+    fn microsecond_resolution() {
+        let micros = TimestampResolution::Microseconds;
+
+        let event = RawEvent::new_interval(StringId::INVALID, EventId::INVALID, 1, 1_999, 5_000);
+        let stored = event.to_resolution(micros);
+        assert_eq!((stored.start_nanos(), stored.end_nanos()), (1, 5));
+        let read = stored.from_resolution(micros);
+        assert_eq!((read.start_nanos(), read.end_nanos()), (1_000, 5_000));
+
+        // Only the timestamps of instant and integer events are converted.
+        let event = RawEvent::new_integer(StringId::INVALID, EventId::INVALID, 1, 2_500, 42);
+        let read = event.to_resolution(micros).from_resolution(micros);
+        assert_eq!(read.start_nanos(), 2_000);
+        assert_eq!(read.integer_value(), Some(42));
+
+        let event = RawEvent::new_instant(StringId::INVALID, EventId::INVALID, 1, 7);
+        assert_eq!(event.to_resolution(TimestampResolution::Nanoseconds), event);
+        assert!(event.to_resolution(micros).is_instant());
     }
 
     #[test]
@@ -319,6 +987,51 @@ mod tests {
         assert_eq!(e.end_nanos(), 0x1234567890A);
     }
 
+    #[test]
+    fn integer_decoding() {
+        for &(timestamp, value) in &[
+            (0, 0),
+            (MAX_INSTANT_TIMESTAMP, MAX_INTEGER_VALUE),
+            (0x1234567890, 4096),
+        ] {
+            let e =
+                RawEvent::new_integer(StringId::INVALID, EventId::INVALID, 987, timestamp, value);
+
+            assert!(e.is_instant());
+            assert_eq!(e.start_nanos(), timestamp);
+            assert_eq!(e.integer_value(), Some(value));
+        }
+
+        assert_eq!(
+            RawEvent::new_instant(StringId::INVALID, EventId::INVALID, 987, 0).integer_value(),
+            None
+        );
+        assert_eq!(
+            RawEvent::new_interval(
+                StringId::INVALID,
+                EventId::INVALID,
+                987,
+                0,
+                MAX_INTERVAL_TIMESTAMP
+            )
+            .integer_value(),
+            None
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn invalid_integer_value() {
+        let _ = RawEvent::new_integer(
+            StringId::INVALID,
+            EventId::INVALID,
+            123,
+            0,
+            // value too large
+            MAX_INTEGER_VALUE + 1,
+        );
+    }
+
     #[test]
     fn instant_timestamp_decoding() {
         assert_eq!(
@@ -0,0 +1,255 @@
+//! Reading the state of an existing profile that `Profiler::resume()` needs
+//! for appending to it.
+
+use crate::file_header::{
+    read_full_file_header, verify_file_footer, CURRENT_FILE_FORMAT_VERSION, FILE_HEADER_SIZE,
+    FILE_MAGIC_STRINGTABLE_DATA, FILE_MAGIC_STRINGTABLE_INDEX,
+};
+use crate::profiler::ProfilerFiles;
+use crate::raw_event::{RawEvent, TimestampFormat, TimestampResolution};
+use crate::stringtable::{
+    read_index_block, read_string_addr, read_virtual_mapping, StringId, FIRST_REGULAR_STRING_ID,
+    FIRST_RESERVED_STRING_ID, INDEX_BLOCK_HEADER_SIZE, METADATA_STRING_ID, STRINGS_BLOCK,
+    STRING_ADDR_SIZE, TERMINATOR,
+};
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+pub(crate) struct ExistingProfile {
+    /// The contents of the three files, without their footers.
+    pub events: Vec<u8>,
+    pub string_data: Vec<u8>,
+    pub string_index: Vec<u8>,
+    pub timestamp_format: TimestampFormat,
+    pub timestamp_resolution: TimestampResolution,
+    pub next_string_id: u32,
+    pub next_thread_id: u32,
+    /// The event kinds registered via `Profiler::register_event_kind()`,
+    /// with the ids of their names.
+    pub event_kinds: Vec<(String, StringId)>,
+    /// The start time from the profile's metadata, in nanoseconds since the
+    /// Unix epoch.
+    pub start_time: u64,
+    /// The session id in the headers of the files.
+    pub session_id: u128,
+}
+
+impl ExistingProfile {
+    pub fn read(path_stem: &Path) -> Result<ExistingProfile, Box<dyn Error>> {
+        let paths = ProfilerFiles::new(path_stem);
+
+        let events = read_complete_file(&paths.events_file)?;
+        let file_magic = events
+            .get(..4)
+            .and_then(TimestampFormat::from_file_magic)
+            .ok_or_else(|| format!("`{}` is not an events file", paths.events_file.display()))?
+            .file_magic();
+        let session_id = check_header(&events, file_magic, &paths.events_file)?;
+        let feature_flags = read_full_file_header(&events, file_magic)?.feature_flags;
+        let timestamp_format =
+            TimestampFormat::from_file_header(file_magic, feature_flags).unwrap();
+        let timestamp_resolution = TimestampResolution::from_feature_flags(feature_flags);
+
+        let string_data = read_complete_file(&paths.string_data_file)?;
+        let string_data_session_id = check_header(
+            &string_data,
+            FILE_MAGIC_STRINGTABLE_DATA,
+            &paths.string_data_file,
+        )?;
+
+        let string_index = read_complete_file(&paths.string_index_file)?;
+        let string_index_session_id = check_header(
+            &string_index,
+            FILE_MAGIC_STRINGTABLE_INDEX,
+            &paths.string_index_file,
+        )?;
+
+        if string_data_session_id != session_id || string_index_session_id != session_id {
+            Err(format!(
+                "the files of `{}` have been written by different profiling sessions",
+                path_stem.display()
+            ))?;
+        }
+
+        // Find the next free string id and the (last) metadata string.
+        let mut next_string_id = FIRST_REGULAR_STRING_ID;
+        let mut metadata_id = None;
+        // The first id and the entries of every strings block.
+        let mut strings_blocks = Vec::new();
+
+        let corrupt = || format!("`{}` is corrupt", paths.string_index_file.display());
+        let mut index = &string_index[FILE_HEADER_SIZE..];
+        while !index.is_empty() {
+            let (header, entries) = read_index_block(index).ok_or_else(corrupt)?;
+            index = &index[INDEX_BLOCK_HEADER_SIZE + entries.len()..];
+
+            if header.kind == STRINGS_BLOCK {
+                let end = header.first_id as u64 + header.len as u64;
+                if header.first_id < next_string_id || end > FIRST_RESERVED_STRING_ID as u64 {
+                    Err(corrupt())?;
+                }
+                next_string_id = end as u32;
+                strings_blocks.push((header.first_id, entries));
+            } else {
+                for i in 0..header.len as usize {
+                    let (virtual_id, concrete_id) = read_virtual_mapping(entries, i);
+                    if virtual_id == METADATA_STRING_ID {
+                        metadata_id = Some(concrete_id as u64);
+                    }
+                }
+            }
+        }
+
+        // Only finds strings that consist of a single value component, like
+        // the metadata and the names of registered event kinds.
+        let get_string = |string_id: u64| {
+            strings_blocks
+                .iter()
+                .find_map(|&(first_id, entries)| {
+                    let index = usize::try_from(string_id.checked_sub(first_id as u64)?).ok()?;
+                    (index < entries.len() / STRING_ADDR_SIZE)
+                        .then(|| read_string_addr(entries, index))
+                })
+                .and_then(|addr| string_data.get(usize::try_from(addr).ok()?..))
+                .and_then(|bytes| bytes.split(|&b| b == TERMINATOR).next())
+                .and_then(|bytes| std::str::from_utf8(bytes).ok())
+        };
+
+        let metadata = metadata_id
+            .and_then(get_string)
+            .ok_or("the profile doesn't contain any metadata")?;
+
+        if metadata.contains(r#""truncated": true"#) {
+            Err("the profile is truncated, so it cannot be resumed")?;
+        }
+
+        if metadata.contains(r#""thread_event_files": ["#)
+            && !metadata.contains(r#""thread_event_files": []"#)
+        {
+            Err("the profile has one events file per thread, so it cannot be resumed")?;
+        }
+
+        if metadata.contains(r#""event_segments": ["#)
+            && !metadata.contains(r#""event_segments": []"#)
+        {
+            Err("the profile's events are split into segments, so it cannot be resumed")?;
+        }
+
+        let start_time = json_number(metadata, "start_time")
+            .ok_or("the profile's metadata doesn't contain a start time")?;
+
+        let event_kinds = json_numbers(metadata, "event_kinds")
+            .unwrap_or_default()
+            .into_iter()
+            .map(|id| {
+                let name = get_string(id).ok_or_else(|| {
+                    format!("the name of the registered event kind {} is missing", id)
+                })?;
+                Ok((name.to_string(), StringId::new(id as u32)))
+            })
+            .collect::<Result<Vec<_>, Box<dyn Error>>>()?;
+
+        let event_size = timestamp_format.event_size();
+        let next_thread_id = events[FILE_HEADER_SIZE..]
+            .chunks_exact(event_size)
+            .map(|bytes| RawEvent::deserialize_as(timestamp_format, bytes).thread_id as u64 + 1)
+            .max()
+            .unwrap_or(0);
+        let next_thread_id = u32::try_from(next_thread_id)
+            .map_err(|_| "the profile uses the largest thread id, so there are no ids left")?;
+
+        Ok(ExistingProfile {
+            events,
+            string_data,
+            string_index,
+            timestamp_format,
+            timestamp_resolution,
+            next_string_id,
+            next_thread_id,
+            event_kinds,
+            start_time,
+            session_id,
+        })
+    }
+}
+
+/// Reads the file at `path` and removes its footer, which must be intact.
+fn read_complete_file(path: &Path) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut data =
+        fs::read(path).map_err(|e| format!("couldn't read `{}`: {}", path.display(), e))?;
+
+    let len = verify_file_footer(&data)
+        .map_err(|e| format!("`{}`: {}", path.display(), e))?
+        .len();
+    data.truncate(len);
+
+    Ok(data)
+}
+
+/// Checks that the file can be resumed and returns its session id.
+fn check_header(data: &[u8], file_magic: &[u8; 4], path: &Path) -> Result<u128, Box<dyn Error>> {
+    if data.len() < FILE_HEADER_SIZE {
+        Err(format!("`{}` is too short", path.display()))?;
+    }
+
+    let header = read_full_file_header(data, file_magic)
+        .map_err(|e| format!("`{}`: {}", path.display(), e))?;
+    let version = header.version;
+
+    if version != CURRENT_FILE_FORMAT_VERSION {
+        Err(format!(
+            "`{}` has file format version {}, but only version {} can be resumed",
+            path.display(),
+            version,
+            CURRENT_FILE_FORMAT_VERSION
+        ))?;
+    }
+
+    Ok(header.session_id)
+}
+
+/// Returns the value of the numeric field `name` in the metadata, which the
+/// profiler writes itself, so this doesn't need a full JSON parser.
+fn json_number(json: &str, name: &str) -> Option<u64> {
+    let key = format!("\"{}\":", name);
+    let value = json[json.find(&key)? + key.len()..].trim_start();
+    let end = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    value[..end].parse().ok()
+}
+
+/// Returns the values of the field `name` in the metadata, which must be an
+/// array of numbers.
+fn json_numbers(json: &str, name: &str) -> Option<Vec<u64>> {
+    let key = format!("\"{}\":", name);
+    let value = json[json.find(&key)? + key.len()..].trim_start();
+    let value = value.strip_prefix('[')?;
+    let value = &value[..value.find(']')?];
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|n| !n.is_empty())
+        .map(|n| n.parse().ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn numbers_from_metadata() {
+        let metadata = r#"{ "start_time": 1234, "process_id": 42, "cmd": "a \"start_time\": 5" }"#;
+        assert_eq!(json_number(metadata, "start_time"), Some(1234));
+        assert_eq!(json_number(metadata, "process_id"), Some(42));
+        assert_eq!(json_number(metadata, "dropped_events"), None);
+
+        let metadata = r#"{ "event_kinds": [12, 345], "other": [] }"#;
+        assert_eq!(json_numbers(metadata, "event_kinds"), Some(vec![12, 345]));
+        assert_eq!(json_numbers(metadata, "other"), Some(vec![]));
+        assert_eq!(json_numbers(metadata, "arg_schemas"), None);
+    }
+}
@@ -0,0 +1,209 @@
+//! `RingBufferSink` keeps the most recent data written to it in memory, so
+//! that a live dashboard running in the same process can look at what the
+//! profiler has been recording lately. It is meant to be combined with a sink
+//! that writes the complete profile, via `TeeSerializationSink` and
+//! `Profiler::with_sinks()`:
+//!
+//! ```ignore
+//! let paths = ProfilerFiles::new(path_stem);
+//! let sink = |path: &Path, ring: RingBufferSink| -> Result<_, Box<dyn Error>> {
+//!     Ok(TeeSerializationSink::new(FileSerializationSink::from_path(path)?, ring))
+//! };
+//!
+//! let events = RingBufferSink::new(Some(1024 * 1024));
+//! let live_events = events.reader();
+//!
+//! let profiler = Profiler::with_sinks(
+//!     sink(&paths.events_file, events)?,
+//!     sink(&paths.string_data_file, RingBufferSink::new(None))?,
+//!     sink(&paths.string_index_file, RingBufferSink::new(None))?,
+//!     &ProfilerConfig::default(),
+//! );
+//!
+//! // On the dashboard thread:
+//! let snapshot = live_events.snapshot();
+//! ```
+//!
+//! The buffer only ever evicts whole writes. Since the profiler writes each
+//! event with a single write, a snapshot of the events file is a sequence of
+//! complete `RawEvent`s (preceded by the file header, as long as it hasn't
+//! been evicted). Strings are only written once, so the string sinks should
+//! usually be unbounded. The string table buffers strings before writing them, so
+//! call `Profiler::flush_strings()` before taking snapshots of the string
+//! sinks. A `RingBufferSink` does not write a file footer.
+//!
+//! To stream a profile over a socket instead, wrap the socket in a
+//! `BufferedSerializationSink`.
+
+use crate::config::ProfilerConfig;
+use crate::serialization::{Addr, ProfileFileKind, SerializationSink};
+use crate::sync::Mutex;
+use std::collections::VecDeque;
+use std::error::Error;
+use std::path::Path;
+use std::sync::Arc;
+
+/// The number of bytes of event data kept by a `RingBufferSink` created via
+/// `SerializationSink::from_config()`.
+pub const DEFAULT_EVENTS_CAPACITY: usize = 1024 * 1024;
+
+pub struct RingBufferSink {
+    ring: Arc<Mutex<Ring>>,
+}
+
+struct Ring {
+    capacity: Option<usize>,
+    data: VecDeque<u8>,
+    // The sizes of the writes in `data`, oldest first.
+    writes: VecDeque<usize>,
+    // The address of the first byte in `data`.
+    start_addr: u64,
+}
+
+/// A handle for reading the contents of a `RingBufferSink` while it is
+/// written to, see `RingBufferSink::reader()`.
+#[derive(Clone)]
+pub struct RingBufferReader {
+    ring: Arc<Mutex<Ring>>,
+}
+
+/// The contents of a `RingBufferSink` at some point in time.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RingBufferSnapshot {
+    /// The address of the first byte of `data` in the (complete) output.
+    pub start_addr: Addr,
+    pub data: Vec<u8>,
+}
+
+impl RingBufferSink {
+    /// Creates a sink that keeps the most recent writes that fit into
+    /// `capacity` bytes, or everything if `capacity` is `None`. A single
+    /// write larger than `capacity` is kept until the next write.
+    pub fn new(capacity: Option<usize>) -> RingBufferSink {
+        RingBufferSink {
+            ring: Arc::new(Mutex::new(Ring {
+                capacity,
+                data: VecDeque::new(),
+                writes: VecDeque::new(),
+                start_addr: 0,
+            })),
+        }
+    }
+
+    pub fn reader(&self) -> RingBufferReader {
+        RingBufferReader {
+            ring: self.ring.clone(),
+        }
+    }
+}
+
+impl RingBufferReader {
+    pub fn snapshot(&self) -> RingBufferSnapshot {
+        let ring = self.ring.lock();
+
+        RingBufferSnapshot {
+            start_addr: Addr(ring.start_addr),
+            data: ring.data.iter().copied().collect(),
+        }
+    }
+
+    /// The total number of bytes written to the sink, including evicted
+    /// ones.
+    pub fn bytes_written(&self) -> u64 {
+        let ring = self.ring.lock();
+        ring.start_addr + ring.data.len() as u64
+    }
+}
+
+impl SerializationSink for RingBufferSink {
+    fn from_path(_path: &Path) -> Result<Self, Box<dyn Error>> {
+        Ok(RingBufferSink::new(None))
+    }
+
+    fn from_config(
+        _path: &Path,
+        file_kind: ProfileFileKind,
+        _config: &ProfilerConfig,
+    ) -> Result<Self, Box<dyn Error>> {
+        Ok(match file_kind {
+            ProfileFileKind::Events => RingBufferSink::new(Some(DEFAULT_EVENTS_CAPACITY)),
+            ProfileFileKind::StringData | ProfileFileKind::StringIndex => RingBufferSink::new(None),
+        })
+    }
+
+    fn write_atomic<W>(&self, num_bytes: usize, write: W) -> Addr
+    where
+        W: FnOnce(&mut [u8]),
+    {
+        let mut ring = self.ring.lock();
+        let Ring {
+            capacity,
+            ref mut data,
+            ref mut writes,
+            ref mut start_addr,
+        } = *ring;
+
+        if let Some(capacity) = capacity {
+            while !writes.is_empty() && data.len() + num_bytes > capacity {
+                let evicted = writes.pop_front().unwrap();
+                data.drain(..evicted);
+                *start_addr += evicted as u64;
+            }
+        }
+
+        let addr = Addr(*start_addr + data.len() as u64);
+
+        let start = data.len();
+        data.resize(start + num_bytes, 0);
+        // This only moves data if the deque wraps around, i.e. at most once
+        // per `capacity` bytes written.
+        write(&mut data.make_contiguous()[start..]);
+        writes.push_back(num_bytes);
+
+        addr
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_the_most_recent_writes() {
+        let sink = RingBufferSink::new(Some(8));
+        let reader = sink.reader();
+
+        assert_eq!(sink.write_bytes_atomic(b"abc"), Addr(0));
+        assert_eq!(sink.write_bytes_atomic(b"def"), Addr(3));
+        assert_eq!(sink.write_bytes_atomic(b"ghi"), Addr(6));
+
+        assert_eq!(
+            reader.snapshot(),
+            RingBufferSnapshot {
+                start_addr: Addr(3),
+                data: b"defghi".to_vec(),
+            }
+        );
+
+        // Writes larger than the capacity replace everything else.
+        assert_eq!(sink.write_bytes_atomic(b"0123456789"), Addr(9));
+        assert_eq!(reader.snapshot().start_addr, Addr(9));
+        assert_eq!(sink.write_bytes_atomic(b"x"), Addr(19));
+        assert_eq!(reader.snapshot().data, b"x".to_vec());
+        assert_eq!(reader.bytes_written(), 20);
+    }
+
+    #[test]
+    fn unbounded() {
+        let sink = RingBufferSink::new(None);
+
+        for i in 0..100u8 {
+            sink.write_atomic(2, |bytes| bytes.copy_from_slice(&[i, i]));
+        }
+
+        let snapshot = sink.reader().snapshot();
+        assert_eq!(snapshot.start_addr, Addr(0));
+        assert_eq!(snapshot.data.len(), 200);
+        assert_eq!(&snapshot.data[198..], &[99, 99]);
+    }
+}
@@ -9,3 +9,17 @@ pub const INCREMENTAL_LOAD_RESULT_EVENT_KIND: &str = "IncrementalLoadResult";
 pub const QUERY_BLOCKED_EVENT_KIND: &str = "QueryBlocked";
 
 pub const QUERY_CACHE_HIT_EVENT_KIND: &str = "QueryCacheHit";
+
+pub const INCREMENTAL_RESULT_HASHING_EVENT_KIND: &str = "IncrementalResultHashing";
+
+pub const LLVM_PASS_EVENT_KIND: &str = "LLVM Pass";
+
+pub const ARTIFACT_SIZE_EVENT_KIND: &str = "ArtifactSize";
+
+// The names of the segments of a compilation session, for
+// `Profiler::record_session_marker()`, in the order in which they start.
+pub const PARSING_SESSION_MARKER: &str = "parsing";
+pub const EXPANSION_SESSION_MARKER: &str = "expansion";
+pub const ANALYSIS_SESSION_MARKER: &str = "analysis";
+pub const CODEGEN_SESSION_MARKER: &str = "codegen";
+pub const LINKING_SESSION_MARKER: &str = "linking";
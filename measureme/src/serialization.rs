@@ -1,9 +1,12 @@
-use parking_lot::Mutex;
+use crate::config::ProfilerConfig;
+use crate::sync::Mutex;
 use std::error::Error;
+use std::fmt;
+use std::io;
 use std::path::Path;
 
 #[derive(Clone, Copy, Eq, PartialEq, Debug)]
-pub struct Addr(pub u32);
+pub struct Addr(pub u64);
 
 impl Addr {
     pub fn as_usize(self) -> usize {
@@ -11,9 +14,128 @@ impl Addr {
     }
 }
 
+/// Describes why a `SerializationSink` could not write its data to the
+/// underlying storage, e.g. because the disk is full.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WriteError {
+    pub kind: io::ErrorKind,
+    pub message: String,
+}
+
+impl WriteError {
+    pub fn new(kind: io::ErrorKind, message: impl Into<String>) -> WriteError {
+        WriteError {
+            kind,
+            message: message.into(),
+        }
+    }
+}
+
+impl From<&io::Error> for WriteError {
+    fn from(error: &io::Error) -> WriteError {
+        WriteError::new(error.kind(), error.to_string())
+    }
+}
+
+impl fmt::Display for WriteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "failed to write profiling data: {}", self.message)
+    }
+}
+
+impl Error for WriteError {}
+
+/// I/O statistics of a sink, see `SerializationSink::stats()`.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct SinkStats {
+    /// The number of bytes written to the underlying storage, not counting
+    /// data that is still buffered.
+    pub bytes_written: u64,
+    /// The number of writes to the underlying storage, e.g. of a full
+    /// buffer.
+    pub flushes: u64,
+}
+
+impl std::ops::Add for SinkStats {
+    type Output = SinkStats;
+
+    fn add(self, other: SinkStats) -> SinkStats {
+        SinkStats {
+            bytes_written: self.bytes_written + other.bytes_written,
+            flushes: self.flushes + other.flushes,
+        }
+    }
+}
+
+/// The files a profile consists of, see `ProfilerFiles`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ProfileFileKind {
+    Events,
+    StringData,
+    StringIndex,
+}
+
+/// Where a `Profiler` writes the bytes of one of its files. Embedders can
+/// implement this trait to store profiles somewhere else than in plain files,
+/// e.g. in a database or behind a network connection. `TeeSerializationSink`
+/// is a small reference implementation that wraps two other sinks.
+///
+/// Implementations must uphold the following invariants, which the
+/// `Profiler` and the string table rely on:
+///
+///   - **Atomicity:** the bytes of a single `write_atomic()` call end up
+///     contiguously in the output. They must never be interleaved with the
+///     bytes of another call.
+///   - **Ordering:** writes are appended in the order in which they acquire
+///     the sink, and `write_atomic()` returns the offset of the first byte
+///     of the write from the start of the output. Offsets start at 0 (the
+///     file header is the first write), so the offset of a write is the sum
+///     of the sizes of all writes before it. The string index stores these
+///     offsets for the string data file, so a sink must never reorder or
+///     discard string data.
+///   - **Concurrent access:** a sink is shared between all threads that
+///     record events, so `write_atomic()` is called concurrently and must
+///     synchronize internally, typically with a mutex or an atomic cursor.
+///     It is called for every recorded event and should not block longer
+///     than necessary.
+///   - **Completion:** everything written must have reached the underlying
+///     storage once the sink is dropped. Sinks that write profile files are
+///     expected to append a file footer with the checksum of the data when
+///     they are dropped, see the `file_header` module.
+///
+/// Sinks may discard writes to the events file (see `dropped_writes()`),
+/// since nothing refers to the offsets of events.
 pub trait SerializationSink: Sized + Send + Sync + 'static {
+    /// Creates the sink for the file at `path`, overwriting it if it exists.
     fn from_path(path: &Path) -> Result<Self, Box<dyn Error>>;
 
+    /// Creates the sink for the given profile file, taking the profiler
+    /// configuration into account. This is what `Profiler::with_config()`
+    /// uses. Sinks without configuration options don't need to override this.
+    fn from_config(
+        path: &Path,
+        _file_kind: ProfileFileKind,
+        _config: &ProfilerConfig,
+    ) -> Result<Self, Box<dyn Error>> {
+        Self::from_path(path)
+    }
+
+    /// Opens the existing file at `path` for appending to it, which is what
+    /// `Profiler::resume()` uses. `contents` are the contents of the file
+    /// without its footer, which the profiler has already verified. The sink
+    /// must remove the footer and append new writes right after `contents`,
+    /// so the offsets it returns start at `contents.len()`. The footer it
+    /// writes when it is dropped has to cover `contents` too.
+    ///
+    /// Sinks that cannot append to existing data don't need to override
+    /// this, it returns an error by default.
+    fn from_existing(_path: &Path, _contents: &[u8]) -> Result<Self, Box<dyn Error>> {
+        Err(From::from(format!(
+            "`{}` cannot resume existing profiles",
+            std::any::type_name::<Self>()
+        )))
+    }
+
     /// Atomically write `num_bytes` to the sink. The implementation must ensure
     /// that concurrent invocations of `write_atomic` do not conflict with each
     /// other.
@@ -31,6 +153,39 @@ pub trait SerializationSink: Sized + Send + Sync + 'static {
     fn write_bytes_atomic(&self, bytes: &[u8]) -> Addr {
         self.write_atomic(bytes.len(), |sink| sink.copy_from_slice(bytes))
     }
+
+    /// Returns `true` if writing to the underlying storage has failed. Once a
+    /// sink has failed, it silently discards all further data, so the data it
+    /// has written so far is likely incomplete. This method is called for
+    /// every recorded event and must be cheap.
+    ///
+    /// Sinks that cannot fail don't need to override this.
+    fn has_failed(&self) -> bool {
+        false
+    }
+
+    /// Returns the error that made the sink fail, if `has_failed()` is true.
+    fn write_error(&self) -> Option<WriteError> {
+        None
+    }
+
+    /// Returns the number of `write_atomic()` calls whose data has been
+    /// discarded on purpose, e.g. because a buffer was full. For the events
+    /// file, this is the number of dropped events.
+    ///
+    /// Sinks that never discard data don't need to override this.
+    fn dropped_writes(&self) -> u64 {
+        0
+    }
+
+    /// Returns how much the sink has written to its storage so far, so that
+    /// embedders can tune the sink's configuration.
+    ///
+    /// Sinks that don't keep track of this don't need to override this, it
+    /// returns zeros by default.
+    fn stats(&self) -> SinkStats {
+        SinkStats::default()
+    }
 }
 
 /// A `SerializationSink` that writes to an internal `Vec<u8>` and can be
@@ -52,6 +207,12 @@ impl ByteVecSink {
     }
 }
 
+impl Default for ByteVecSink {
+    fn default() -> ByteVecSink {
+        ByteVecSink::new()
+    }
+}
+
 impl SerializationSink for ByteVecSink {
     fn from_path(_path: &Path) -> Result<Self, Box<dyn Error>> {
         unimplemented!()
@@ -69,7 +230,7 @@ impl SerializationSink for ByteVecSink {
 
         write(&mut data[start..]);
 
-        Addr(start as u32)
+        Addr(start as u64)
     }
 }
 
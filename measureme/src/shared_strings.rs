@@ -0,0 +1,96 @@
+//! Embedders that create many short-lived profilers, e.g. one per compilation
+//! unit, record the same labels (query names, event kinds, ...) over and over
+//! again. A `SharedStringCache` can be shared between these profilers so that
+//! such strings are written only once, into the cache's own string table
+//! files `<path_stem>.string_data` and `<path_stem>.string_index`, instead of
+//! into every profile.
+//!
+//! A string interned via `Profiler::intern_shared_string()` gets the same
+//! `StringId` in every profile that uses the cache, so tools that combine
+//! profiles can compare these ids directly instead of comparing strings.
+//!
+//! Each profile records the file name of the cache in its metadata and
+//! `analyzeme` looks for the cache's files next to the profile, so the cache
+//! has to be created in the same directory as the profiles using it. Since
+//! the cache has no `.events` file, the `housekeeping` module considers it an
+//! incomplete profile: cleaning up with `remove_incomplete` deletes it once
+//! its process has exited, which leaves the profiles using it unreadable.
+//!
+//! With `ProfilerConfig::string_cache_capacity`, strings that haven't been
+//! interned for a while are evicted from the cache. Interning them again
+//! writes them again, under a new id, so only strings that are used
+//! frequently are guaranteed to keep their id. Likewise, strings that
+//! `ProfilerConfig::string_dedup_policy` excludes are written every time.
+
+use crate::config::ProfilerConfig;
+use crate::profiler::{session_id, ProfilerFiles};
+use crate::serialization::{ProfileFileKind, SerializationSink};
+use crate::string_cache::{StringCache, StringCacheStats};
+use crate::stringtable::{StringId, StringTableBuilder};
+use crate::sync::Mutex;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+pub struct SharedStringCache<S: SerializationSink> {
+    path_stem: PathBuf,
+    string_table: StringTableBuilder<S>,
+    strings: Mutex<StringCache>,
+}
+
+impl<S: SerializationSink> SharedStringCache<S> {
+    pub fn new(path_stem: &Path) -> Result<SharedStringCache<S>, Box<dyn Error>> {
+        SharedStringCache::with_config(path_stem, &ProfilerConfig::default())
+    }
+
+    /// Creates a cache at `config.path_stem(path_stem)`, next to the profiles
+    /// of profilers created with the same `config`. The cache's files are
+    /// deterministic with `ProfilerConfig::deterministic`.
+    pub fn with_config(
+        path_stem: &Path,
+        config: &ProfilerConfig,
+    ) -> Result<SharedStringCache<S>, Box<dyn Error>> {
+        let path_stem = config.path_stem(path_stem);
+        let paths = ProfilerFiles::new(&path_stem);
+
+        let string_table = StringTableBuilder::new_shared(
+            Arc::new(S::from_config(
+                &paths.string_data_file,
+                ProfileFileKind::StringData,
+                config,
+            )?),
+            Arc::new(S::from_config(
+                &paths.string_index_file,
+                ProfileFileKind::StringIndex,
+                config,
+            )?),
+            session_id(config),
+        );
+
+        Ok(SharedStringCache {
+            path_stem,
+            string_table,
+            strings: Mutex::new(StringCache::new(
+                config.string_cache_capacity,
+                config.string_dedup_policy,
+            )),
+        })
+    }
+
+    pub fn path_stem(&self) -> &Path {
+        &self.path_stem
+    }
+
+    /// Returns the id of `s`, writing it to the cache's string table the
+    /// first time it is interned (or the first time after it was evicted).
+    pub fn intern(&self, s: &str) -> StringId {
+        self.strings
+            .lock()
+            .get_or_alloc(s, || self.string_table.alloc(s))
+    }
+
+    /// Returns how often `intern()` found its strings in the cache so far.
+    pub fn stats(&self) -> StringCacheStats {
+        self.strings.lock().stats()
+    }
+}
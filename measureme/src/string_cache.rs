@@ -0,0 +1,214 @@
+//! The cache behind `Profiler::intern_string()` and `SharedStringCache`,
+//! which makes sure a string is only written to a string table once. For
+//! processes that record indefinitely, the number of distinct strings may
+//! have no bound, so the cache can be capped via
+//! `ProfilerConfig::string_cache_capacity`. Once it is full, the strings that
+//! have been used least recently are evicted, and written again if they are
+//! interned again, which costs some space on disk but none in memory.
+//!
+//! Looking a string up isn't free either: for short strings, hashing and
+//! comparing them can cost more than writing them again would. The
+//! `DedupPolicy` in `ProfilerConfig::string_dedup_policy` decides which
+//! strings are looked up at all, and `StringCacheStats` (see
+//! `Profiler::string_cache_stats()`) tell how well the cache works, so that
+//! embedders can measure which policy suits them.
+
+use crate::hash_map::FxHashMap;
+use crate::stringtable::StringId;
+
+/// Decides which strings the string cache deduplicates. Strings that aren't
+/// deduplicated are written every time they are interned.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum DedupPolicy {
+    /// Every string is deduplicated.
+    #[default]
+    Always,
+    /// No string is deduplicated, so interning is as cheap as allocating.
+    Never,
+    /// Only strings of at least this many bytes are deduplicated.
+    MinLength(usize),
+}
+
+impl DedupPolicy {
+    pub fn dedups(self, s: &str) -> bool {
+        match self {
+            DedupPolicy::Always => true,
+            DedupPolicy::Never => false,
+            DedupPolicy::MinLength(min_length) => s.len() >= min_length,
+        }
+    }
+}
+
+/// How often the string cache found the strings it was asked for.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct StringCacheStats {
+    /// The number of strings found in the cache.
+    pub hits: u64,
+    /// The number of strings that weren't in the cache and were written.
+    pub misses: u64,
+    /// The number of strings written without looking them up, because the
+    /// `DedupPolicy` excludes them.
+    pub bypassed: u64,
+    /// The number of strings evicted because the cache was full.
+    pub evictions: u64,
+    /// The bytes that the hits saved from being written again.
+    pub bytes_saved: u64,
+    /// The bytes the misses and bypassed strings were written with.
+    pub bytes_written: u64,
+}
+
+pub(crate) struct StringCache {
+    capacity: Option<usize>,
+    policy: DedupPolicy,
+    // The id of every cached string and when it was used last.
+    strings: FxHashMap<String, (StringId, u64)>,
+    clock: u64,
+    stats: StringCacheStats,
+}
+
+impl StringCache {
+    /// A cache for at most `capacity` strings, or for all of them if it is
+    /// `None`, of those that `policy` deduplicates.
+    pub(crate) fn new(capacity: Option<usize>, policy: DedupPolicy) -> StringCache {
+        StringCache {
+            capacity,
+            policy,
+            strings: FxHashMap::default(),
+            clock: 0,
+            stats: StringCacheStats::default(),
+        }
+    }
+
+    pub(crate) fn stats(&self) -> StringCacheStats {
+        self.stats
+    }
+
+    /// Returns the cached id of `s`, or allocates one via `alloc` and
+    /// caches it.
+    pub(crate) fn get_or_alloc(&mut self, s: &str, alloc: impl FnOnce() -> StringId) -> StringId {
+        if !self.policy.dedups(s) {
+            self.stats.bypassed += 1;
+            self.stats.bytes_written += s.len() as u64;
+            return alloc();
+        }
+
+        self.clock += 1;
+
+        if let Some((id, last_used)) = self.strings.get_mut(s) {
+            *last_used = self.clock;
+            self.stats.hits += 1;
+            self.stats.bytes_saved += s.len() as u64;
+            return *id;
+        }
+
+        let id = alloc();
+        self.stats.misses += 1;
+        self.stats.bytes_written += s.len() as u64;
+        match self.capacity {
+            Some(0) => return id,
+            Some(capacity) if self.strings.len() >= capacity => self.evict(capacity),
+            _ => {}
+        }
+        self.strings.insert(s.to_string(), (id, self.clock));
+        id
+    }
+
+    /// Evicts the least recently used eighth of the strings, which makes
+    /// the cost of finding them negligible compared to evicting them one at
+    /// a time.
+    fn evict(&mut self, capacity: usize) {
+        let evicted = (capacity / 8).max(1).min(self.strings.len());
+        let mut last_uses: Vec<u64> = self.strings.values().map(|&(_, t)| t).collect();
+        let (_, &mut threshold, _) = last_uses.select_nth_unstable(evicted - 1);
+
+        let before = self.strings.len();
+        self.strings
+            .retain(|_, &mut (_, last_used)| last_used > threshold);
+        self.stats.evictions += (before - self.strings.len()) as u64;
+    }
+
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.strings.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let mut next_id = 0;
+        let mut alloc = || {
+            next_id += 1;
+            StringId::new(next_id)
+        };
+
+        let mut cache = StringCache::new(Some(8), DedupPolicy::Always);
+        let ids: Vec<_> = (0..8)
+            .map(|i| cache.get_or_alloc(&format!("s{}", i), &mut alloc))
+            .collect();
+
+        // Using `s0` again makes `s1` the least recently used string.
+        assert_eq!(cache.get_or_alloc("s0", &mut alloc), ids[0]);
+        let s8 = cache.get_or_alloc("s8", &mut alloc);
+        assert_eq!(cache.len(), 8);
+        assert_eq!(cache.get_or_alloc("s0", &mut alloc), ids[0]);
+        assert_eq!(cache.get_or_alloc("s8", &mut alloc), s8);
+
+        // `s1` was evicted, so it gets a new id.
+        assert_ne!(cache.get_or_alloc("s1", &mut alloc), ids[1]);
+        assert_eq!(cache.len(), 8);
+
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses, stats.evictions), (3, 10, 2));
+        assert_eq!(stats.bytes_saved, 6);
+        assert_eq!(stats.bytes_written, 20);
+    }
+
+    #[test]
+    fn capacities() {
+        let mut next_id = 0;
+        let mut alloc = || {
+            next_id += 1;
+            StringId::new(next_id)
+        };
+
+        let mut unbounded = StringCache::new(None, DedupPolicy::Always);
+        for i in 0..1000 {
+            unbounded.get_or_alloc(&i.to_string(), &mut alloc);
+        }
+        assert_eq!(unbounded.len(), 1000);
+
+        let mut disabled = StringCache::new(Some(0), DedupPolicy::Always);
+        let id = disabled.get_or_alloc("s", &mut alloc);
+        assert_ne!(disabled.get_or_alloc("s", &mut alloc), id);
+        assert_eq!(disabled.len(), 0);
+    }
+
+    #[test]
+    fn policies() {
+        let mut next_id = 0;
+        let mut alloc = || {
+            next_id += 1;
+            StringId::new(next_id)
+        };
+
+        let mut never = StringCache::new(None, DedupPolicy::Never);
+        let id = never.get_or_alloc("typeck", &mut alloc);
+        assert_ne!(never.get_or_alloc("typeck", &mut alloc), id);
+        assert_eq!(never.stats().bypassed, 2);
+        assert_eq!(never.len(), 0);
+
+        let mut long_only = StringCache::new(None, DedupPolicy::MinLength(4));
+        let short = long_only.get_or_alloc("a", &mut alloc);
+        let long = long_only.get_or_alloc("typeck", &mut alloc);
+        assert_ne!(long_only.get_or_alloc("a", &mut alloc), short);
+        assert_eq!(long_only.get_or_alloc("typeck", &mut alloc), long);
+
+        let stats = long_only.stats();
+        assert_eq!((stats.hits, stats.misses, stats.bypassed), (1, 1, 2));
+        assert_eq!((stats.bytes_saved, stats.bytes_written), (6, 8));
+    }
+}
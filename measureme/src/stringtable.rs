@@ -60,21 +60,79 @@
 //! From `0` to `MAX_VIRTUAL_STRING_ID` are the allowed values for virtual strings.
 //! After `MAX_VIRTUAL_STRING_ID`, there is one string id (`METADATA_STRING_ID`) which is used
 //! internally by `measureme` to record additional metadata about the profiling session.
-//! After `METADATA_STRING_ID` are all other `StringId` values.
+//! After `METADATA_STRING_ID` are all other `StringId` values. The ids from
+//! `FIRST_RESERVED_STRING_ID` to `FIRST_SHARED_STRING_ID` can be reserved for
+//! strings that aren't stored in the profile at all, but resolved by analysis
+//! tools from a table of their own, see `Profiler::reserve_string_ids()`. The
+//! ids from `FIRST_SHARED_STRING_ID` on belong to strings that many profiles have in
+//! common and that are stored once in the string table of a
+//! `SharedStringCache`, see the `shared_strings` module.
+//!
+//! ----------------------------------------------------------------------------
+//!
+//! Regular `StringId`s are handed out sequentially, so they count strings
+//! rather than bytes and the string table data can grow beyond 4 GiB. Since
+//! `StringId`s stay 32 bits wide and the regular ids end at
+//! `FIRST_RESERVED_STRING_ID`, a profile holds at most about 168 million
+//! regular strings. Once they are used up, `alloc()` returns
+//! `StringId::INVALID` and the string table reports a write error, so that
+//! the profiler stops recording (or panics) according to its
+//! `WriteFailurePolicy`, like when the disk is full. A few ids are kept back
+//! for rewriting the metadata, which thus still tells that the profile is
+//! truncated.
+//!
+//! The index maps every `StringId` to its string data. It is a sequence of
+//! blocks, each starting with an `IndexBlockHeader` of
+//! `INDEX_BLOCK_HEADER_SIZE` bytes: the kind of the block, its first
+//! `StringId` and its number of entries, as little endian `u32`s.
+//!
+//! - A `STRINGS_BLOCK` holds the (64-bit) offsets of the data of consecutive
+//!   regular `StringId`s in the `.string_data` file, `STRING_ADDR_SIZE`
+//!   bytes each, starting with the block's first id. The blocks follow each
+//!   other in the order of their ids, so readers find the offset of an id
+//!   via the block headers, without decoding the offsets.
+//! - A `VIRTUAL_MAPPINGS_BLOCK` maps virtual `StringId`s to the regular
+//!   `StringId`s they have been mapped to, `VIRTUAL_MAPPING_SIZE` bytes
+//!   each, sorted by virtual id and without duplicates. Its first id is
+//!   unused. Since virtual mappings can be (re-)defined at any point while
+//!   recording, the builder collects them and writes a block whenever it has
+//!   `VIRTUAL_BLOCK_LEN` of them, and when it is flushed. If a virtual id
+//!   occurs in more than one block, the last block wins.
+//!
+//! ----------------------------------------------------------------------------
+//!
+//! Since many threads intern strings concurrently (e.g. in the parallel
+//! compiler), the builder doesn't write every string to the sinks right away,
+//! which would make all threads contend for the locks of the two sinks.
+//! Instead, each thread is assigned one of `SHARD_COUNT` shards, which buffer
+//! the string data and index entries of their threads and write them to the
+//! sinks in batches. Ids are still handed out sequentially via an atomic
+//! counter, so the ids don't depend on the sharding, only the order of the
+//! strings in the `.string_data` file does. Strings are written when the
+//! builder is dropped at the latest, or via `flush()`. Since the strings
+//! blocks of the index are in the order of the ids, the offsets of strings
+//! that have been written before the strings with the preceding ids wait
+//! until those have been written too. If too many are waiting, e.g. because
+//! a thread that rarely interns strings holds on to an early id in its
+//! shard, the builder flushes all shards.
 //!
 
 use crate::file_header::{
-    write_file_header, FILE_MAGIC_STRINGTABLE_DATA, FILE_MAGIC_STRINGTABLE_INDEX,
+    new_session_id, write_file_header, FILE_MAGIC_STRINGTABLE_DATA, FILE_MAGIC_STRINGTABLE_INDEX,
 };
-use crate::serialization::{Addr, SerializationSink};
+use crate::serialization::{SerializationSink, SinkStats, WriteError};
+use crate::sync::Mutex;
 use byteorder::{BigEndian, ByteOrder, LittleEndian};
+use std::collections::VecDeque;
+use std::io;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
 use std::sync::Arc;
 
 /// A `StringId` is used to identify a string in the `StringTable`. It is
-/// either a regular `StringId`, meaning that it contains the absolute address
-/// of a string within the string table data. Or it is "virtual", which means
-/// that the address it points to is resolved via the string table index data,
-/// that maps virtual `StringId`s to addresses.
+/// either a regular `StringId`, meaning that it has been returned by
+/// `StringTableBuilder::alloc()` for a string in the string table data. Or it
+/// is "virtual", which means that it is mapped to a regular `StringId` via the
+/// string table index.
 #[derive(Clone, Copy, Eq, PartialEq, Debug, Hash)]
 #[repr(C)]
 pub struct StringId(u32);
@@ -88,32 +146,63 @@ impl StringId {
         StringId(id)
     }
 
+    /// Like `new()`, but also accepts the ids of registered event kinds, see
+    /// `new_event_kind()`.
     #[inline]
-    pub fn new_virtual(id: u32) -> StringId {
-        assert!(id <= MAX_USER_VIRTUAL_STRING_ID);
+    pub fn from_u32(id: u32) -> StringId {
+        assert!(id <= MAX_STRING_ID || (FIRST_EVENT_KIND_ID..=MAX_EVENT_KIND_ID).contains(&id));
         StringId(id)
     }
 
+    /// Like `from_u32()`, but doesn't check the id, for decoding the ids of
+    /// a profile, which may be corrupt. See `is_valid()`.
     #[inline]
-    pub fn is_virtual(self) -> bool {
-        self.0 <= METADATA_STRING_ID
+    pub fn from_raw(id: u32) -> StringId {
+        StringId(id)
     }
 
+    /// Whether `from_u32()` would accept the id, i.e. whether it is a string
+    /// id or the id of a registered event kind.
     #[inline]
-    pub fn as_u32(self) -> u32 {
-        self.0
+    pub fn is_valid(self) -> bool {
+        self.0 <= MAX_STRING_ID || (FIRST_EVENT_KIND_ID..=MAX_EVENT_KIND_ID).contains(&self.0)
     }
 
+    /// The id that events of the event kind with the given index in the
+    /// profile's registry of event kinds are recorded with, see
+    /// `Profiler::register_event_kind()`. It doesn't refer to an entry of
+    /// the string table.
     #[inline]
-    pub fn from_addr(addr: Addr) -> StringId {
-        let id = addr.0 + FIRST_REGULAR_STRING_ID;
-        StringId::new(id)
+    pub fn new_event_kind(index: u32) -> StringId {
+        assert!(index <= MAX_EVENT_KIND_ID - FIRST_EVENT_KIND_ID);
+        StringId(FIRST_EVENT_KIND_ID + index)
     }
 
+    /// Returns the index of the registered event kind `self` stands for, if
+    /// any. See `new_event_kind()`.
     #[inline]
-    pub fn to_addr(self) -> Addr {
-        assert!(self.0 >= FIRST_REGULAR_STRING_ID);
-        Addr(self.0 - FIRST_REGULAR_STRING_ID)
+    pub fn event_kind_index(self) -> Option<u32> {
+        if self.0 >= FIRST_EVENT_KIND_ID {
+            Some(self.0 - FIRST_EVENT_KIND_ID)
+        } else {
+            None
+        }
+    }
+
+    #[inline]
+    pub fn new_virtual(id: u32) -> StringId {
+        assert!(id <= MAX_USER_VIRTUAL_STRING_ID);
+        StringId(id)
+    }
+
+    #[inline]
+    pub fn is_virtual(self) -> bool {
+        self.0 <= METADATA_STRING_ID
+    }
+
+    #[inline]
+    pub fn as_u32(self) -> u32 {
+        self.0
     }
 }
 
@@ -135,10 +224,270 @@ const INVALID_STRING_ID: u32 = METADATA_STRING_ID + 1;
 
 pub const FIRST_REGULAR_STRING_ID: u32 = INVALID_STRING_ID + 1;
 
+/// The size of the header of a block of the index, see
+/// `IndexBlockHeader`.
+pub const INDEX_BLOCK_HEADER_SIZE: usize = 12;
+
+/// The kind of index block that holds the offsets of consecutive regular
+/// `StringId`s.
+pub const STRINGS_BLOCK: u32 = 1;
+
+/// The kind of index block that holds virtual mappings.
+pub const VIRTUAL_MAPPINGS_BLOCK: u32 = 2;
+
+/// The size of the offset of a string in a `STRINGS_BLOCK`, a little endian
+/// `u64`.
+pub const STRING_ADDR_SIZE: usize = 8;
+
+/// The size of a virtual mapping in a `VIRTUAL_MAPPINGS_BLOCK`: the virtual
+/// and the regular `StringId`, as little endian `u32`s.
+pub const VIRTUAL_MAPPING_SIZE: usize = 8;
+
+/// The builder writes a strings block of the index once it has the offsets
+/// of this many strings.
+const STRINGS_BLOCK_LEN: usize = 4096;
+
+/// The builder writes a block of virtual mappings once it has this many.
+pub const VIRTUAL_BLOCK_LEN: usize = 64 * 1024;
+
+/// The builder flushes all shards once this many offsets wait for the
+/// strings with the preceding ids, see the module documentation.
+const MAX_WAITING_ADDRS: usize = 16 * STRINGS_BLOCK_LEN;
+
+/// The header of a block of the string table index, see the module
+/// documentation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IndexBlockHeader {
+    /// `STRINGS_BLOCK` or `VIRTUAL_MAPPINGS_BLOCK`.
+    pub kind: u32,
+    pub first_id: u32,
+    pub len: u32,
+}
+
+impl IndexBlockHeader {
+    /// Decodes the header at the start of `bytes`, if there is one.
+    pub fn read(bytes: &[u8]) -> Option<IndexBlockHeader> {
+        if bytes.len() < INDEX_BLOCK_HEADER_SIZE {
+            return None;
+        }
+
+        Some(IndexBlockHeader {
+            kind: LittleEndian::read_u32(&bytes[0..4]),
+            first_id: LittleEndian::read_u32(&bytes[4..8]),
+            len: LittleEndian::read_u32(&bytes[8..12]),
+        })
+    }
+
+    /// Appends the encoded header to `bytes`.
+    pub fn write(&self, bytes: &mut Vec<u8>) {
+        bytes.extend_from_slice(&self.kind.to_le_bytes());
+        bytes.extend_from_slice(&self.first_id.to_le_bytes());
+        bytes.extend_from_slice(&self.len.to_le_bytes());
+    }
+
+    /// The size of the block's entries, not counting the header, or `None`
+    /// for an unknown kind of block.
+    pub fn data_len(&self) -> Option<usize> {
+        let entry_size = match self.kind {
+            STRINGS_BLOCK => STRING_ADDR_SIZE,
+            VIRTUAL_MAPPINGS_BLOCK => VIRTUAL_MAPPING_SIZE,
+            _ => return None,
+        };
+        (self.len as usize).checked_mul(entry_size)
+    }
+}
+
+/// Decodes the block at the start of `bytes`, returning its header and its
+/// entries, or `None` if `bytes` doesn't contain all of it or it is of an
+/// unknown kind.
+pub fn read_index_block(bytes: &[u8]) -> Option<(IndexBlockHeader, &[u8])> {
+    let header = IndexBlockHeader::read(bytes)?;
+    let end = header.data_len()?.checked_add(INDEX_BLOCK_HEADER_SIZE)?;
+    Some((header, bytes.get(INDEX_BLOCK_HEADER_SIZE..end)?))
+}
+
+/// Decodes the offset with the given index in the entries of a strings
+/// block.
+pub fn read_string_addr(entries: &[u8], index: usize) -> u64 {
+    LittleEndian::read_u64(&entries[index * STRING_ADDR_SIZE..])
+}
+
+/// Decodes the virtual mapping with the given index in the entries of a
+/// block of virtual mappings.
+pub fn read_virtual_mapping(entries: &[u8], index: usize) -> (u32, u32) {
+    let bytes = &entries[index * VIRTUAL_MAPPING_SIZE..];
+    (
+        LittleEndian::read_u32(&bytes[0..4]),
+        LittleEndian::read_u32(&bytes[4..8]),
+    )
+}
+
+/// The ids from here to `FIRST_SHARED_STRING_ID` are handed out in ranges by
+/// `Profiler::reserve_string_ids()`, see `ReservedStringIds`.
+pub const FIRST_RESERVED_STRING_ID: u32 = 0x1000_0000;
+
+/// The ids from here to `MAX_STRING_ID` belong to the strings of a
+/// `SharedStringCache`, which have the same id in every profile.
+pub const FIRST_SHARED_STRING_ID: u32 = 0x2000_0000;
+
+/// The ids from here to `MAX_EVENT_KIND_ID` stand for registered event kinds
+/// and are only used as the `event_kind` of raw events, see
+/// `StringId::new_event_kind()`.
+pub const FIRST_EVENT_KIND_ID: u32 = 0x4000_0000;
+pub const MAX_EVENT_KIND_ID: u32 = FIRST_EVENT_KIND_ID + 0xFFFF;
+
+/// A range of `StringId`s that don't refer to the string table, but to a table
+/// of strings that the application and the analysis tools both know, like
+/// the names of rustc's queries. See `Profiler::reserve_string_ids()`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ReservedStringIds {
+    first: u32,
+    len: u32,
+}
+
+impl ReservedStringIds {
+    pub(crate) fn new(first: u32, len: u32) -> ReservedStringIds {
+        assert!(
+            first >= FIRST_RESERVED_STRING_ID
+                && first as u64 + len as u64 <= FIRST_SHARED_STRING_ID as u64,
+            "StringTable: out of reserved string ids"
+        );
+        ReservedStringIds { first, len }
+    }
+
+    /// The id of the string with the given index in the application's table.
+    #[inline]
+    pub fn get(self, index: u32) -> StringId {
+        assert!(index < self.len);
+        StringId(self.first + index)
+    }
+
+    /// The index of `id` in the application's table, if it belongs to this
+    /// range.
+    #[inline]
+    pub fn index_of(self, id: StringId) -> Option<u32> {
+        id.0.checked_sub(self.first)
+            .filter(|&index| index < self.len)
+    }
+
+    /// The first id of the range.
+    pub fn first(self) -> StringId {
+        StringId(self.first)
+    }
+
+    pub fn len(self) -> u32 {
+        self.len
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.len == 0
+    }
+
+    /// The first id after the range.
+    pub(crate) fn end(self) -> u32 {
+        self.first + self.len
+    }
+}
+
+/// The number of ids at the end of a string table that only
+/// `alloc_metadata()` hands out, see the module documentation.
+const METADATA_RESERVE: u32 = 16;
+
+/// The number of shards strings are buffered in, see the module
+/// documentation.
+const SHARD_COUNT: usize = 16;
+
+/// A shard writes its buffer to the sinks once it holds this many bytes of
+/// string data. Larger strings are written right away.
+const SHARD_CAPACITY: usize = 16 * 1024;
+
+static NEXT_SHARD: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    // Threads are assigned shards round-robin, so that the threads of a
+    // thread pool end up in different shards.
+    static SHARD_INDEX: usize = NEXT_SHARD.fetch_add(1, Ordering::Relaxed) % SHARD_COUNT;
+}
+
+/// Strings that have been allocated but not written to the sinks yet.
+#[derive(Default)]
+struct Shard {
+    data: Vec<u8>,
+    // The ids of the strings in `data` and their offsets within it.
+    entries: Vec<(StringId, usize)>,
+}
+
+/// The offsets of the strings that have been written to the data sink, but
+/// not to the index yet.
+struct PendingAddrs {
+    // The first id whose string hasn't been written yet.
+    next_id: u32,
+    // The offsets of the ids from `next_id` on, with `u64::MAX` for the
+    // strings that haven't been written yet.
+    waiting: VecDeque<u64>,
+    // The offsets of the ids before `next_id`, for the next strings block.
+    ready: Vec<u64>,
+}
+
+impl PendingAddrs {
+    fn new(next_id: u32) -> PendingAddrs {
+        PendingAddrs {
+            next_id,
+            waiting: VecDeque::new(),
+            ready: Vec::new(),
+        }
+    }
+
+    fn insert(&mut self, id: StringId, addr: u64) {
+        let index = (id.0 - self.next_id) as usize;
+        if index >= self.waiting.len() {
+            self.waiting.resize(index + 1, u64::MAX);
+        }
+        self.waiting[index] = addr;
+
+        while self.waiting.front().is_some_and(|&addr| addr != u64::MAX) {
+            self.ready.extend(self.waiting.pop_front());
+            self.next_id += 1;
+        }
+    }
+
+    // Appends the strings block of the ready offsets to `bytes`.
+    fn write_block(&mut self, bytes: &mut Vec<u8>) {
+        if self.ready.is_empty() {
+            return;
+        }
+
+        let header = IndexBlockHeader {
+            kind: STRINGS_BLOCK,
+            first_id: self.next_id - self.ready.len() as u32,
+            len: self.ready.len() as u32,
+        };
+        bytes.reserve(INDEX_BLOCK_HEADER_SIZE + self.ready.len() * STRING_ADDR_SIZE);
+        header.write(bytes);
+        for addr in self.ready.drain(..) {
+            bytes.extend_from_slice(&addr.to_le_bytes());
+        }
+    }
+}
+
 /// Write-only version of the string table
 pub struct StringTableBuilder<S: SerializationSink> {
     data_sink: Arc<S>,
     index_sink: Arc<S>,
+    shards: [Mutex<Shard>; SHARD_COUNT],
+    next_string_id: AtomicU32,
+    // The first id this builder must not hand out anymore.
+    end_string_id: u32,
+    // Whether `alloc()` has run out of ids.
+    out_of_ids: AtomicBool,
+    // Blocks are written to `index_sink` while this is locked, so that the
+    // strings blocks are in the order of their ids.
+    pending_addrs: Mutex<PendingAddrs>,
+    // The virtual mappings that haven't been written yet, in the order in
+    // which they have been added.
+    virtual_mappings: Mutex<Vec<(StringId, StringId)>>,
+    // The session id in the headers of both files.
+    session_id: u128,
 }
 
 /// Anything that implements `SerializableString` can be written to a
@@ -251,22 +600,92 @@ impl_serializable_string_for_fixed_size!(14);
 impl_serializable_string_for_fixed_size!(15);
 impl_serializable_string_for_fixed_size!(16);
 
-fn serialize_index_entry<S: SerializationSink>(sink: &S, id: StringId, addr: Addr) {
-    sink.write_atomic(8, |bytes| {
-        LittleEndian::write_u32(&mut bytes[0..4], id.0);
-        LittleEndian::write_u32(&mut bytes[4..8], addr.0);
-    });
-}
-
 impl<S: SerializationSink> StringTableBuilder<S> {
     pub fn new(data_sink: Arc<S>, index_sink: Arc<S>) -> StringTableBuilder<S> {
+        StringTableBuilder::with_session_id(data_sink, index_sink, new_session_id())
+    }
+
+    /// Like `new()`, but writes the given session id into the file headers
+    /// instead of a random one.
+    pub(crate) fn with_session_id(
+        data_sink: Arc<S>,
+        index_sink: Arc<S>,
+        session_id: u128,
+    ) -> StringTableBuilder<S> {
+        StringTableBuilder::with_ids(
+            data_sink,
+            index_sink,
+            FIRST_REGULAR_STRING_ID,
+            FIRST_RESERVED_STRING_ID,
+            session_id,
+        )
+    }
+
+    /// Creates the string table of a `SharedStringCache`.
+    pub(crate) fn new_shared(
+        data_sink: Arc<S>,
+        index_sink: Arc<S>,
+        session_id: u128,
+    ) -> StringTableBuilder<S> {
+        StringTableBuilder::with_ids(
+            data_sink,
+            index_sink,
+            FIRST_SHARED_STRING_ID,
+            MAX_STRING_ID + 1,
+            session_id,
+        )
+    }
+
+    fn with_ids(
+        data_sink: Arc<S>,
+        index_sink: Arc<S>,
+        first_string_id: u32,
+        end_string_id: u32,
+        session_id: u128,
+    ) -> StringTableBuilder<S> {
         // The first thing in every file we generate must be the file header.
-        write_file_header(&*data_sink, FILE_MAGIC_STRINGTABLE_DATA);
-        write_file_header(&*index_sink, FILE_MAGIC_STRINGTABLE_INDEX);
+        write_file_header(&*data_sink, FILE_MAGIC_STRINGTABLE_DATA, session_id);
+        write_file_header(&*index_sink, FILE_MAGIC_STRINGTABLE_INDEX, session_id);
 
         StringTableBuilder {
             data_sink,
             index_sink,
+            shards: Default::default(),
+            next_string_id: AtomicU32::new(first_string_id),
+            end_string_id,
+            out_of_ids: AtomicBool::new(false),
+            pending_addrs: Mutex::new(PendingAddrs::new(first_string_id)),
+            virtual_mappings: Mutex::new(Vec::new()),
+            session_id,
+        }
+    }
+
+    /// The session id of the string table's files. The events file of a
+    /// profile has to be written with the same id, see the `file_header`
+    /// module.
+    pub fn session_id(&self) -> u128 {
+        self.session_id
+    }
+
+    /// Continues the string table of an existing profile, whose files the
+    /// sinks append to, see `Profiler::resume()`. `next_string_id` is the
+    /// first id that the existing string table doesn't use yet.
+    pub(crate) fn resume(
+        data_sink: Arc<S>,
+        index_sink: Arc<S>,
+        next_string_id: u32,
+        session_id: u128,
+    ) -> StringTableBuilder<S> {
+        StringTableBuilder {
+            data_sink,
+            index_sink,
+            shards: Default::default(),
+            next_string_id: AtomicU32::new(next_string_id),
+            end_string_id: FIRST_RESERVED_STRING_ID,
+            out_of_ids: AtomicBool::new(false),
+            pending_addrs: Mutex::new(PendingAddrs::new(next_string_id)),
+            virtual_mappings: Mutex::new(Vec::new()),
+            session_id,
         }
     }
 
@@ -276,7 +695,7 @@ impl<S: SerializationSink> StringTableBuilder<S> {
         // This assertion does not use `is_virtual` on purpose because that
         // would also allow to overwrite `METADATA_STRING_ID`.
         assert!(virtual_id.0 <= MAX_USER_VIRTUAL_STRING_ID);
-        serialize_index_entry(&*self.index_sink, virtual_id, concrete_id.to_addr());
+        self.add_virtual_mappings(std::iter::once((virtual_id, concrete_id)));
     }
 
     pub fn bulk_map_virtual_to_single_concrete_string<I>(
@@ -286,44 +705,244 @@ impl<S: SerializationSink> StringTableBuilder<S> {
     ) where
         I: Iterator<Item = StringId> + ExactSizeIterator,
     {
-        // TODO: Index data encoding could have special bulk mode that assigns
-        //       multiple StringIds to the same addr, so we don't have to repeat
-        //       the `concrete_id` over and over.
+        self.add_virtual_mappings(virtual_ids.map(|virtual_id| {
+            assert!(virtual_id.0 <= MAX_USER_VIRTUAL_STRING_ID);
+            (virtual_id, concrete_id)
+        }));
+    }
 
-        type MappingEntry = [u32; 2];
-        assert!(std::mem::size_of::<MappingEntry>() == 8);
+    fn add_virtual_mappings(&self, mappings: impl Iterator<Item = (StringId, StringId)>) {
+        let mut virtual_mappings = self.virtual_mappings.lock();
+        for mapping in mappings {
+            virtual_mappings.push(mapping);
+            if virtual_mappings.len() >= VIRTUAL_BLOCK_LEN {
+                self.write_virtual_mappings(&mut virtual_mappings);
+            }
+        }
+    }
 
-        let to_addr_le = concrete_id.to_addr().0.to_le();
+    // Writes `virtual_mappings` as a block of the index and clears it.
+    fn write_virtual_mappings(&self, virtual_mappings: &mut Vec<(StringId, StringId)>) {
+        if virtual_mappings.is_empty() {
+            return;
+        }
 
-        let serialized: Vec<MappingEntry> = virtual_ids
-            .map(|from| {
-                let id = from.0;
-                assert!(id <= MAX_USER_VIRTUAL_STRING_ID);
-                [id.to_le(), to_addr_le]
-            })
-            .collect();
+        // Sort by id, keeping mappings of the same id in the order in which
+        // they have been added, and then only keep the last of these.
+        virtual_mappings.sort_by_key(|&(id, _)| id.0);
+        virtual_mappings.reverse();
+        virtual_mappings.dedup_by_key(|&mut (id, _)| id.0);
+        virtual_mappings.reverse();
+
+        let header = IndexBlockHeader {
+            kind: VIRTUAL_MAPPINGS_BLOCK,
+            first_id: 0,
+            len: virtual_mappings.len() as u32,
+        };
+        let mut bytes = Vec::with_capacity(
+            INDEX_BLOCK_HEADER_SIZE + virtual_mappings.len() * VIRTUAL_MAPPING_SIZE,
+        );
+        header.write(&mut bytes);
+        for &(virtual_id, concrete_id) in virtual_mappings.iter() {
+            bytes.extend_from_slice(&virtual_id.0.to_le_bytes());
+            bytes.extend_from_slice(&concrete_id.0.to_le_bytes());
+        }
 
-        let num_bytes = serialized.len() * std::mem::size_of::<MappingEntry>();
-        let byte_ptr = serialized.as_ptr() as *const u8;
+        self.index_sink.write_bytes_atomic(&bytes);
+        virtual_mappings.clear();
+    }
 
-        let bytes = unsafe { std::slice::from_raw_parts(byte_ptr, num_bytes) };
+    /// Returns the I/O statistics of the data and index sink.
+    pub(crate) fn sink_stats(&self) -> (SinkStats, SinkStats) {
+        (self.data_sink.stats(), self.index_sink.stats())
+    }
 
-        self.index_sink.write_bytes_atomic(bytes);
+    /// Returns the first write error of the data or index sink, if any, or
+    /// an error if the string table has run out of ids.
+    pub(crate) fn write_error(&self) -> Option<WriteError> {
+        self.data_sink
+            .write_error()
+            .or_else(|| self.index_sink.write_error())
+            .or_else(|| {
+                self.out_of_ids.load(Ordering::Relaxed).then(|| {
+                    WriteError::new(
+                        io::ErrorKind::Other,
+                        "the string table is out of string ids",
+                    )
+                })
+            })
+    }
+
+    #[inline]
+    pub(crate) fn has_failed(&self) -> bool {
+        self.data_sink.has_failed()
+            || self.index_sink.has_failed()
+            || self.out_of_ids.load(Ordering::Relaxed)
     }
 
-    pub(crate) fn alloc_metadata<STR: SerializableString + ?Sized>(&self, s: &STR) {
-        let concrete_id = self.alloc(s);
+    /// Allocates the profile's metadata. `Profiler` does this itself, this is
+    /// for tools that write profiles without one, e.g. to annotate a copy of
+    /// an existing profile.
+    pub fn alloc_metadata<STR: SerializableString + ?Sized>(&self, s: &STR) {
+        let concrete_id = match self.next_id(0) {
+            Some(id) => self.alloc_with_id(id, s),
+            None => return,
+        };
         let virtual_id = StringId(METADATA_STRING_ID);
         assert!(virtual_id.is_virtual());
-        serialize_index_entry(&*self.index_sink, virtual_id, concrete_id.to_addr());
+        self.add_virtual_mappings(std::iter::once((virtual_id, concrete_id)));
     }
 
+    /// Allocates a string in the table. Returns `StringId::INVALID` if the
+    /// table has run out of ids, see the module documentation.
     pub fn alloc<STR: SerializableString + ?Sized>(&self, s: &STR) -> StringId {
+        match self.next_id(METADATA_RESERVE) {
+            Some(id) => self.alloc_with_id(id, s),
+            None => {
+                self.out_of_ids.store(true, Ordering::Relaxed);
+                StringId::INVALID
+            }
+        }
+    }
+
+    // Hands out the next id, unless that would leave fewer than `reserve`
+    // ids.
+    fn next_id(&self, reserve: u32) -> Option<StringId> {
+        let end = self.end_string_id - reserve;
+        self.next_string_id
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |id| {
+                (id < end).then(|| id + 1)
+            })
+            .ok()
+            .map(StringId)
+    }
+
+    fn alloc_with_id<STR: SerializableString + ?Sized>(&self, id: StringId, s: &STR) -> StringId {
         let size_in_bytes = s.serialized_size();
-        let addr = self.data_sink.write_atomic(size_in_bytes, |mem| {
-            s.serialize(mem);
-        });
 
-        StringId::from_addr(addr)
+        if size_in_bytes >= SHARD_CAPACITY {
+            let addr = self.data_sink.write_atomic(size_in_bytes, |mem| {
+                s.serialize(mem);
+            });
+
+            if self.add_addrs(std::iter::once((id, addr.0))) {
+                self.flush();
+            }
+
+            return id;
+        }
+
+        let shard_index = SHARD_INDEX.with(|index| *index);
+        let mut shard = self.shards[shard_index].lock();
+
+        let start = shard.data.len();
+        shard.data.resize(start + size_in_bytes, 0);
+        s.serialize(&mut shard.data[start..]);
+        shard.entries.push((id, start));
+
+        if shard.data.len() >= SHARD_CAPACITY && self.write_shard(&mut shard) {
+            drop(shard);
+            self.flush();
+        }
+
+        id
+    }
+
+    /// Writes the strings and virtual mappings that have been allocated so
+    /// far to the sinks, e.g. so that a live view of the string table (see
+    /// the `ring_buffer_sink` module) can resolve all of them.
+    pub fn flush(&self) {
+        for shard in &self.shards {
+            self.write_shard(&mut shard.lock());
+        }
+
+        let mut bytes = Vec::new();
+        let mut pending_addrs = self.pending_addrs.lock();
+        pending_addrs.write_block(&mut bytes);
+        if !bytes.is_empty() {
+            self.index_sink.write_bytes_atomic(&bytes);
+        }
+        drop(pending_addrs);
+
+        self.write_virtual_mappings(&mut self.virtual_mappings.lock());
+    }
+
+    // Writes the strings of `shard` to the data sink. Returns whether too
+    // many offsets are waiting for the strings of other shards, in which
+    // case the caller has to `flush()` once it has released `shard`.
+    fn write_shard(&self, shard: &mut Shard) -> bool {
+        if shard.entries.is_empty() {
+            return false;
+        }
+
+        let addr = self.data_sink.write_bytes_atomic(&shard.data);
+        let waiting = self.add_addrs(
+            shard
+                .entries
+                .iter()
+                .map(|&(id, offset)| (id, addr.0 + offset as u64)),
+        );
+
+        shard.data.clear();
+        shard.entries.clear();
+        waiting
+    }
+
+    // Adds the offsets of strings that have been written to the data sink
+    // and writes the strings blocks that are complete. Returns whether too
+    // many offsets are waiting, see `write_shard()`.
+    fn add_addrs(&self, addrs: impl Iterator<Item = (StringId, u64)>) -> bool {
+        let mut pending_addrs = self.pending_addrs.lock();
+        for (id, addr) in addrs {
+            pending_addrs.insert(id, addr);
+        }
+
+        if pending_addrs.ready.len() >= STRINGS_BLOCK_LEN {
+            let mut bytes = Vec::new();
+            pending_addrs.write_block(&mut bytes);
+            self.index_sink.write_bytes_atomic(&bytes);
+        }
+
+        pending_addrs.waiting.len() > MAX_WAITING_ADDRS
+    }
+}
+
+impl<S: SerializationSink> Drop for StringTableBuilder<S> {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn out_of_ids() {
+        use crate::ByteVecSink;
+
+        let first = FIRST_REGULAR_STRING_ID;
+        let builder = StringTableBuilder::with_ids(
+            Arc::new(ByteVecSink::new()),
+            Arc::new(ByteVecSink::new()),
+            first,
+            first + METADATA_RESERVE + 2,
+            new_session_id(),
+        );
+
+        assert_eq!(builder.alloc("a"), StringId(first));
+        assert_eq!(builder.alloc("b"), StringId(first + 1));
+        assert!(!builder.has_failed());
+
+        assert_eq!(builder.alloc("c"), StringId::INVALID);
+        assert!(builder.has_failed());
+        assert_eq!(
+            builder.write_error().unwrap().message,
+            "the string table is out of string ids"
+        );
+
+        // The metadata can still be rewritten.
+        builder.alloc_metadata("{}");
+        assert_eq!(builder.next_string_id.load(Ordering::Relaxed), first + 3);
     }
 }
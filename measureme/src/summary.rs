@@ -0,0 +1,149 @@
+//! With `ProfilerConfig::summary` enabled, the `Profiler` keeps running
+//! totals per event kind and event id in memory and writes them to a small
+//! `<path_stem>.summary` file when it is dropped. Embedders that only need
+//! top-level numbers, like the total time per event kind, can get them from
+//! this file (via `analyzeme::ProfileSummary`) or from
+//! `Profiler::label_totals()` instead of processing the whole event stream.
+//!
+//! The file refers to event kinds and labels by their `StringId`, so reading
+//! it needs the profile's string table, but not its `.events` file. It is
+//! JSON of the form
+//!
+//! ```json
+//! { "version": 1, "labels": [
+//!     { "event_kind": 3, "event_id": 12, "count": 2, "total_nanos": 1500, "max_nanos": 1000 }
+//! ] }
+//! ```
+//!
+//! Totals are kept per distinct `EventId`, so events whose ids include
+//! arguments are counted separately per argument list. The profiler's own
+//! marker events aren't counted, and neither are events recorded while
+//! recording is paused. The file is written with `std::fs` even if the
+//! profile is written through a different `SerializationSink`, and it is not
+//! written at all for profilers created via `Profiler::with_sinks()`.
+//!
+//! With `RecordingMode::AggregateOnly`, the summary is all there is: the
+//! profiler doesn't write any events, only the totals and the string table.
+
+use crate::event_id::EventId;
+use crate::hash_map::FxHashMap;
+use crate::raw_event::RawEvent;
+use crate::stringtable::StringId;
+use crate::sync::Mutex;
+
+pub const SUMMARY_FORMAT_VERSION: u32 = 1;
+
+/// The running totals for the events with a given kind and id.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct LabelTotals {
+    pub event_kind: StringId,
+    pub event_id: EventId,
+    /// The number of events, including instant and integer events.
+    pub count: u64,
+    /// The total duration of the interval events, in nanoseconds.
+    pub total_nanos: u64,
+    /// The duration of the longest interval event, in nanoseconds.
+    pub max_nanos: u64,
+}
+
+#[derive(Default)]
+pub(crate) struct SummaryRecorder {
+    totals: Mutex<FxHashMap<(StringId, EventId), LabelTotals>>,
+}
+
+impl SummaryRecorder {
+    pub(crate) fn record(&self, raw_event: &RawEvent) {
+        let duration = if raw_event.is_instant() {
+            0
+        } else {
+            raw_event
+                .end_nanos()
+                .saturating_sub(raw_event.start_nanos())
+        };
+
+        let mut totals = self.totals.lock();
+        let totals = totals
+            .entry((raw_event.event_kind, raw_event.event_id))
+            .or_insert(LabelTotals {
+                event_kind: raw_event.event_kind,
+                event_id: raw_event.event_id,
+                count: 0,
+                total_nanos: 0,
+                max_nanos: 0,
+            });
+
+        totals.count += 1;
+        totals.total_nanos = totals.total_nanos.saturating_add(duration);
+        totals.max_nanos = totals.max_nanos.max(duration);
+    }
+
+    /// Returns the totals ordered by event kind and event id.
+    pub(crate) fn totals(&self) -> Vec<LabelTotals> {
+        let mut totals: Vec<_> = self.totals.lock().values().copied().collect();
+        totals.sort_by_key(|t| (t.event_kind.as_u32(), t.event_id.as_u32()));
+        totals
+    }
+}
+
+/// Formats `totals` as the contents of a `.summary` file.
+pub fn summary_json(totals: &[LabelTotals]) -> String {
+    let labels: Vec<String> = totals
+        .iter()
+        .map(|t| {
+            format!(
+                r#"{{ "event_kind": {}, "event_id": {}, "count": {}, "total_nanos": {}, "max_nanos": {} }}"#,
+                t.event_kind.as_u32(),
+                t.event_id.as_u32(),
+                t.count,
+                t.total_nanos,
+                t.max_nanos
+            )
+        })
+        .collect();
+
+    format!(
+        "{{ \"version\": {}, \"labels\": [\n{}\n] }}\n",
+        SUMMARY_FORMAT_VERSION,
+        labels.join(",\n")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn totals_per_kind_and_id() {
+        let recorder = SummaryRecorder::default();
+        let kind = StringId::new(100);
+        let typeck = EventId::from_label(StringId::new(101));
+        let borrowck = EventId::from_label(StringId::new(102));
+
+        recorder.record(&RawEvent::new_interval_wide(kind, typeck, 0, 10, 20));
+        recorder.record(&RawEvent::new_interval_wide(kind, typeck, 1, 0, 100));
+        recorder.record(&RawEvent::new_instant_wide(kind, borrowck, 0, 5));
+
+        let totals = recorder.totals();
+        assert_eq!(
+            totals,
+            vec![
+                LabelTotals {
+                    event_kind: kind,
+                    event_id: typeck,
+                    count: 2,
+                    total_nanos: 110,
+                    max_nanos: 100,
+                },
+                LabelTotals {
+                    event_kind: kind,
+                    event_id: borrowck,
+                    count: 1,
+                    total_nanos: 0,
+                    max_nanos: 0,
+                },
+            ]
+        );
+
+        assert!(summary_json(&totals).starts_with("{ \"version\": 1, \"labels\": [\n{ \"event_kind\": 100, \"event_id\": 101, \"count\": 2,"));
+    }
+}
@@ -0,0 +1,108 @@
+//! The `Profiler` records what kind of machine a profile has been recorded
+//! on, so that timings from different machines, e.g. in a shared benchmark
+//! discussion, aren't compared as if they came from the same one. The
+//! information is captured once, when the profiler is created, and stored
+//! in the profile's metadata as JSON of the form
+//!
+//! ```json
+//! "system": { "cpu_model": "AMD Ryzen 9 5950X 16-Core Processor", "cores": 32, "memory_bytes": 67358433280, "os": "linux", "os_version": "Ubuntu 22.04.4 LTS", "arch": "x86_64" }
+//! ```
+//!
+//! `cores` is the number of threads the process may run in parallel, see
+//! `std::thread::available_parallelism()`. The CPU model, the memory and the
+//! OS version are read from `/proc` and `/etc/os-release` and are only known
+//! on Linux; elsewhere they are `null`. Profiles recorded with
+//! `ProfilerConfig::deterministic` don't record the system.
+
+use std::fs;
+
+/// The machine a profile has been recorded on, see the module
+/// documentation.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SystemInfo {
+    pub cpu_model: Option<String>,
+    pub cores: Option<u32>,
+    pub memory_bytes: Option<u64>,
+    /// The OS, as in `std::env::consts::OS`.
+    pub os: String,
+    /// The name and version of the OS distribution, if known.
+    pub os_version: Option<String>,
+    /// The CPU architecture, as in `std::env::consts::ARCH`.
+    pub arch: String,
+}
+
+impl SystemInfo {
+    /// A one-line description for report headers, like `AMD Ryzen 9 5950X,
+    /// 32 cores, 62.7 GiB, Ubuntu 22.04.4 LTS (linux, x86_64)`. Unknown
+    /// parts are left out.
+    pub fn describe(&self) -> String {
+        let mut parts = Vec::new();
+        parts.extend(self.cpu_model.clone());
+        parts.extend(self.cores.map(|cores| format!("{} cores", cores)));
+        parts.extend(
+            self.memory_bytes
+                .map(|bytes| format!("{:.1} GiB", bytes as f64 / (1u64 << 30) as f64)),
+        );
+        parts.extend(self.os_version.clone());
+        parts.push(format!("({}, {})", self.os, self.arch));
+        parts.join(", ").replace(", (", " (")
+    }
+
+    /// Captures the information about the current machine.
+    pub fn capture() -> SystemInfo {
+        let read = |path: &str| fs::read_to_string(path).unwrap_or_default();
+
+        SystemInfo {
+            cpu_model: field(&read("/proc/cpuinfo"), "model name", ':').map(str::to_string),
+            cores: std::thread::available_parallelism()
+                .ok()
+                .map(|cores| cores.get() as u32),
+            memory_bytes: field(&read("/proc/meminfo"), "MemTotal", ':').and_then(parse_kb),
+            os: std::env::consts::OS.to_string(),
+            os_version: field(&read("/etc/os-release"), "PRETTY_NAME", '=')
+                .map(|name| name.trim_matches('"').to_string()),
+            arch: std::env::consts::ARCH.to_string(),
+        }
+    }
+}
+
+/// The value of the first `<name><separator><value>` line of `text`.
+fn field<'a>(text: &'a str, name: &str, separator: char) -> Option<&'a str> {
+    text.lines().find_map(|line| {
+        let (key, value) = line.split_once(separator)?;
+        if key.trim() == name {
+            Some(value.trim()).filter(|value| !value.is_empty())
+        } else {
+            None
+        }
+    })
+}
+
+/// Parses a size like `65781672 kB` of `/proc/meminfo`.
+fn parse_kb(value: &str) -> Option<u64> {
+    let kb = value.strip_suffix("kB")?.trim().parse::<u64>().ok()?;
+    Some(kb * 1024)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fields() {
+        let cpuinfo = "processor\t: 0\nvendor_id\t: AuthenticAMD\nmodel name\t: AMD Ryzen 9\n";
+        assert_eq!(field(cpuinfo, "model name", ':'), Some("AMD Ryzen 9"));
+        assert_eq!(field(cpuinfo, "model", ':'), None);
+
+        let meminfo = "MemTotal:       65781672 kB\nMemFree:         1000 kB\n";
+        let memory = field(meminfo, "MemTotal", ':').and_then(parse_kb);
+        assert_eq!(memory, Some(65781672 * 1024));
+
+        let os_release = "NAME=\"Ubuntu\"\nPRETTY_NAME=\"Ubuntu 22.04.4 LTS\"\n";
+        assert_eq!(
+            field(os_release, "PRETTY_NAME", '='),
+            Some("\"Ubuntu 22.04.4 LTS\"")
+        );
+    }
+}
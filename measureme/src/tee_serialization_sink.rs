@@ -0,0 +1,173 @@
+//! `TeeSerializationSink` writes everything to two sinks, e.g. to the local
+//! disk and to a network share, or to a plain and an encrypted copy of the
+//! profile. It is also meant as an example of how to implement
+//! `SerializationSink` on top of other sinks.
+//!
+//! Both sinks receive exactly the same writes in exactly the same order, so
+//! both end up with a complete profile as long as neither of them discards
+//! data. Each sink appends its own file footer when it is dropped.
+
+use crate::config::ProfilerConfig;
+use crate::serialization::{Addr, ProfileFileKind, SerializationSink, SinkStats, WriteError};
+use crate::sync::Mutex;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+/// A `SerializationSink` that duplicates all writes to the sinks `A` and
+/// `B`. The returned addresses are those of `A`.
+pub struct TeeSerializationSink<A: SerializationSink, B: SerializationSink> {
+    primary: A,
+    secondary: B,
+    // Serializes writes, so that they reach both sinks in the same order.
+    // Holds the bytes of the current write.
+    buffer: Mutex<Vec<u8>>,
+}
+
+impl<A: SerializationSink, B: SerializationSink> TeeSerializationSink<A, B> {
+    pub fn new(primary: A, secondary: B) -> TeeSerializationSink<A, B> {
+        TeeSerializationSink {
+            primary,
+            secondary,
+            buffer: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn primary(&self) -> &A {
+        &self.primary
+    }
+
+    pub fn secondary(&self) -> &B {
+        &self.secondary
+    }
+
+    /// Returns the sinks, e.g. to get at the data of `ByteVecSink`s.
+    pub fn into_inner(self) -> (A, B) {
+        (self.primary, self.secondary)
+    }
+}
+
+/// The path of the copy written by the secondary sink when a
+/// `TeeSerializationSink` is created from a path: the file with the same
+/// name in the `tee` subdirectory, so that the copy is a complete profile
+/// under `<dir>/tee/<file name>`.
+pub fn secondary_path(path: &Path) -> PathBuf {
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    dir.join("tee").join(path.file_name().unwrap_or_default())
+}
+
+impl<A: SerializationSink, B: SerializationSink> SerializationSink for TeeSerializationSink<A, B> {
+    fn from_path(path: &Path) -> Result<Self, Box<dyn Error>> {
+        Ok(TeeSerializationSink::new(
+            A::from_path(path)?,
+            B::from_path(&secondary_path(path))?,
+        ))
+    }
+
+    fn from_config(
+        path: &Path,
+        file_kind: ProfileFileKind,
+        config: &ProfilerConfig,
+    ) -> Result<Self, Box<dyn Error>> {
+        Ok(TeeSerializationSink::new(
+            A::from_config(path, file_kind, config)?,
+            B::from_config(&secondary_path(path), file_kind, config)?,
+        ))
+    }
+
+    fn write_atomic<W>(&self, num_bytes: usize, write: W) -> Addr
+    where
+        W: FnOnce(&mut [u8]),
+    {
+        let mut buffer = self.buffer.lock();
+
+        buffer.clear();
+        buffer.resize(num_bytes, 0);
+        write(&mut buffer[..]);
+
+        let addr = self.primary.write_bytes_atomic(&buffer);
+        self.secondary.write_bytes_atomic(&buffer);
+        addr
+    }
+
+    fn write_bytes_atomic(&self, bytes: &[u8]) -> Addr {
+        // Both sinks have to see the writes in the same order, so this needs
+        // the lock even though the bytes don't have to be copied.
+        let _guard = self.buffer.lock();
+
+        let addr = self.primary.write_bytes_atomic(bytes);
+        self.secondary.write_bytes_atomic(bytes);
+        addr
+    }
+
+    fn has_failed(&self) -> bool {
+        self.primary.has_failed() || self.secondary.has_failed()
+    }
+
+    fn write_error(&self) -> Option<WriteError> {
+        self.primary
+            .write_error()
+            .or_else(|| self.secondary.write_error())
+    }
+
+    fn dropped_writes(&self) -> u64 {
+        self.primary
+            .dropped_writes()
+            .max(self.secondary.dropped_writes())
+    }
+
+    fn stats(&self) -> SinkStats {
+        self.primary.stats() + self.secondary.stats()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::serialization::ByteVecSink;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn both_sinks_get_the_same_data() {
+        let sink = Arc::new(TeeSerializationSink::new(
+            ByteVecSink::new(),
+            ByteVecSink::new(),
+        ));
+
+        assert_eq!(sink.write_bytes_atomic(b"header"), Addr(0));
+        assert_eq!(
+            sink.write_atomic(3, |bytes| bytes.copy_from_slice(b"abc")),
+            Addr(6)
+        );
+
+        let threads: Vec<_> = (0..4u8)
+            .map(|i| {
+                let sink = sink.clone();
+                thread::spawn(move || {
+                    for _ in 0..100 {
+                        sink.write_atomic(2, |bytes| bytes.copy_from_slice(&[i, i]));
+                    }
+                })
+            })
+            .collect();
+
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let (primary, secondary) = Arc::try_unwrap(sink).ok().unwrap().into_inner();
+        let (primary, secondary) = (primary.into_bytes(), secondary.into_bytes());
+
+        assert_eq!(primary.len(), 9 + 4 * 100 * 2);
+        assert_eq!(primary, secondary);
+        assert!(primary[9..].chunks(2).all(|pair| pair[0] == pair[1]));
+    }
+
+    #[test]
+    fn secondary_path_is_in_tee_directory() {
+        assert_eq!(
+            secondary_path(Path::new("out/foo-1234.events")),
+            Path::new("out/tee/foo-1234.events")
+        );
+    }
+}
@@ -0,0 +1,172 @@
+//! With `EventLayout::PerThread`, every thread writes its events to a file of
+//! its own instead of sharing the `.events` file with all other threads, so
+//! recording an event never waits for another thread. This pays off for
+//! heavily parallel applications, whose threads otherwise contend for the
+//! lock of the events sink.
+//!
+//! The file of a thread is created the first time the thread records an
+//! event and is named `<path_stem>.<n>.thread_events`, see
+//! `ProfilerFiles::thread_events_file()`, where `<n>` counts the files in
+//! the order in which they have been created. The files have the same header
+//! and footer as the `.events` file. The `.events` file itself only contains
+//! the header, unless the file of a thread could not be created, in which
+//! case the thread falls back to writing its events there.
+//!
+//! The profile's metadata lists the file names, as JSON of the form
+//!
+//! ```json
+//! "thread_event_files": ["foo-1234.0.thread_events", "foo-1234.1.thread_events"]
+//! ```
+//!
+//! and `analyzeme` merges the events of all files into a single stream
+//! ordered by the time the events end, just like the profiler would have
+//! written them to a single file. Profiles recorded this way cannot be
+//! resumed via `Profiler::resume()`.
+
+use crate::config::ProfilerConfig;
+use crate::file_header::write_file_header_with_features;
+use crate::profiler::ProfilerFiles;
+use crate::serialization::{ProfileFileKind, SerializationSink, SinkStats, WriteError};
+use crate::sync::Mutex;
+use std::any::Any;
+use std::cell::RefCell;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Weak};
+
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    // The sink of this thread per `ThreadEventSinks`, keyed by its id. The
+    // sinks are owned by the profiler, so that they are finished when it is
+    // dropped, which leaves behind dead entries here.
+    static THREAD_SINKS: RefCell<Vec<(usize, Weak<dyn Any + Send + Sync>)>> =
+        const { RefCell::new(Vec::new()) };
+}
+
+/// The events sinks of the threads of a profiler.
+pub(crate) struct ThreadEventSinks<S: SerializationSink> {
+    id: usize,
+    files: ProfilerFiles,
+    config: ProfilerConfig,
+    feature_flags: u32,
+    session_id: u128,
+    // The file names and sinks in the order in which they have been created.
+    sinks: Mutex<Vec<(String, Arc<S>)>>,
+    // The `.events` sink, for threads whose file couldn't be created.
+    fallback: Arc<S>,
+}
+
+impl<S: SerializationSink> ThreadEventSinks<S> {
+    pub fn new(
+        path_stem: &Path,
+        config: &ProfilerConfig,
+        feature_flags: u32,
+        session_id: u128,
+        fallback: Arc<S>,
+    ) -> ThreadEventSinks<S> {
+        ThreadEventSinks {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            files: ProfilerFiles::new(path_stem),
+            config: config.clone(),
+            feature_flags,
+            session_id,
+            sinks: Mutex::new(Vec::new()),
+            fallback,
+        }
+    }
+
+    /// Returns the sink of the current thread, creating it if the thread
+    /// hasn't recorded any events yet. The second return value is `true` if
+    /// a new file has been created, which the metadata has to list.
+    #[inline]
+    pub fn current(&self) -> (Arc<S>, bool) {
+        if let Some(sink) = self.existing() {
+            return (sink, false);
+        }
+
+        let (sink, created) = self.create();
+
+        THREAD_SINKS.with(|sinks| {
+            let mut sinks = sinks.borrow_mut();
+            sinks.retain(|(_, sink)| sink.strong_count() > 0);
+            let any_sink: Arc<dyn Any + Send + Sync> = sink.clone();
+            sinks.push((self.id, Arc::downgrade(&any_sink)));
+        });
+
+        (sink, created)
+    }
+
+    /// Returns `true` if the sink of the current thread has failed. Threads
+    /// without a sink haven't written anything, so they can't have failed.
+    #[inline]
+    pub fn current_has_failed(&self) -> bool {
+        self.existing().is_some_and(|sink| sink.has_failed())
+    }
+
+    fn existing(&self) -> Option<Arc<S>> {
+        THREAD_SINKS.with(|sinks| {
+            sinks
+                .borrow()
+                .iter()
+                .find(|&&(id, _)| id == self.id)
+                .and_then(|(_, sink)| sink.upgrade())
+                .map(|sink| sink.downcast::<S>().unwrap())
+        })
+    }
+
+    #[cold]
+    fn create(&self) -> (Arc<S>, bool) {
+        let mut sinks = self.sinks.lock();
+        let path = self.files.thread_events_file(sinks.len());
+
+        let sink = match S::from_config(&path, ProfileFileKind::Events, &self.config) {
+            Ok(sink) => sink,
+            Err(_) => return (self.fallback.clone(), false),
+        };
+
+        write_file_header_with_features(
+            &sink,
+            self.config.timestamp_format.file_magic(),
+            self.feature_flags,
+            self.session_id,
+        );
+
+        let sink = Arc::new(sink);
+        let file_name = path.file_name().unwrap().to_string_lossy().into_owned();
+        sinks.push((file_name, sink.clone()));
+        (sink, true)
+    }
+
+    /// The names of the files created so far, for the metadata.
+    pub fn file_names(&self) -> Vec<String> {
+        self.sinks
+            .lock()
+            .iter()
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+
+    pub fn write_error(&self) -> Option<WriteError> {
+        self.sinks
+            .lock()
+            .iter()
+            .find_map(|(_, sink)| sink.write_error())
+    }
+
+    pub fn dropped_writes(&self) -> u64 {
+        self.sinks
+            .lock()
+            .iter()
+            .map(|(_, sink)| sink.dropped_writes())
+            .sum()
+    }
+
+    pub fn stats(&self) -> SinkStats {
+        self.sinks
+            .lock()
+            .iter()
+            .map(|(_, sink)| sink.stats())
+            .fold(SinkStats::default(), |total, stats| total + stats)
+    }
+}
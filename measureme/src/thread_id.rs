@@ -0,0 +1,187 @@
+//! Every event carries a `u32` thread id, in both `TimestampFormat`s, so a
+//! profile can tell about four billion threads apart. Embedders can either
+//! pass their own ids to the event recording methods, or let the `Profiler`
+//! assign them via `Profiler::register_current_thread()`. The latter also works for threads
+//! that have been spawned before the `Profiler` was created (e.g. the threads
+//! of a pool that is set up at startup), since a thread is only registered
+//! the first time it asks for its id.
+//!
+//! Registered threads get their id according to the profiler's
+//! `ThreadIdScheme`. Within one profile, a thread always gets the same id, no
+//! matter how often it calls `register_current_thread()`. Mixing registered
+//! ids with ids chosen by the embedder in the same profile is not supported,
+//! as the two are likely to collide.
+//!
+//! For every registered thread, the profiler records an instant event of
+//! kind `THREAD_REGISTRATION_EVENT_KIND` whose label is the name of the
+//! thread. Analysis tools use these events to display thread names and to
+//! remap sparse ids (like OS thread ids) to small numbers.
+//!
+//! Some ids don't fit into 32 bits, e.g. tokio task ids or the thread ids of
+//! macOS, and truncating them would make different threads collide. Such ids
+//! can be mapped to a `u32` via `Profiler::register_external_thread()`, which
+//! records the original id for the analysis tools, or converted via
+//! `checked_thread_id()`, which fails for ids that don't fit. Ids assigned by
+//! the profiler itself never wrap around either: running out of them is an
+//! error as well.
+//!
+//! The thread id of an event doesn't have to denote an OS thread, it can just
+//! as well denote a logical task, like an async task, a codegen unit or a
+//! query job, whose events would be scattered across the timelines of
+//! whatever threads happened to run it otherwise. `Profiler::open_track()`
+//! assigns such a *track* an id, which its events are recorded with, no
+//! matter which thread records them, and `Profiler::close_track()` tells the
+//! analysis tools that it is done. Like those of a thread, the intervals of a
+//! track have to nest properly, so a track shouldn't be used by two threads
+//! at the same time.
+
+use std::cell::RefCell;
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt;
+use std::sync::{Arc, Weak};
+
+/// Determines how `Profiler::register_current_thread()` assigns ids to
+/// threads.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum ThreadIdScheme {
+    /// Threads are numbered in the order in which they are registered,
+    /// starting at zero. The ids are small and dense, but the same OS thread
+    /// will get different ids in different processes.
+    #[default]
+    Sequential,
+
+    /// Threads use the id that the operating system assigned to them, which
+    /// makes it possible to correlate events with other tools (like `perf`)
+    /// but produces large and sparse ids. This is currently only supported on
+    /// Linux. On other platforms it behaves like `Sequential`.
+    Os,
+}
+
+/// The error for thread ids that don't fit into the `u32` events have for
+/// them.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ThreadIdOverflow {
+    pub id: u64,
+}
+
+impl fmt::Display for ThreadIdOverflow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "thread id {} doesn't fit into the 32 bits events have for it, register it via \
+             `Profiler::register_external_thread()` instead",
+            self.id
+        )
+    }
+}
+
+impl Error for ThreadIdOverflow {}
+
+/// Converts a thread id chosen by the embedder to the `u32` that events
+/// store, failing for ids that don't fit instead of truncating them.
+pub fn checked_thread_id(id: u64) -> Result<u32, ThreadIdOverflow> {
+    u32::try_from(id).map_err(|_| ThreadIdOverflow { id })
+}
+
+/// Returns the id the operating system uses for the current thread, if that
+/// is supported on the current platform.
+#[cfg(all(feature = "libc", target_os = "linux"))]
+pub(crate) fn os_thread_id() -> Option<u32> {
+    // `gettid` cannot fail and thread ids are bounded by `pid_max`, which is
+    // at most 2^22.
+    Some(unsafe { libc::syscall(libc::SYS_gettid) } as u32)
+}
+
+#[cfg(not(all(feature = "libc", target_os = "linux")))]
+pub(crate) fn os_thread_id() -> Option<u32> {
+    None
+}
+
+/// Returns the CPU the current thread is running on, if that is supported on
+/// the current platform.
+#[cfg(all(feature = "libc", target_os = "linux"))]
+pub(crate) fn current_cpu() -> Option<u32> {
+    let cpu = unsafe { libc::sched_getcpu() };
+    if cpu >= 0 {
+        Some(cpu as u32)
+    } else {
+        None
+    }
+}
+
+#[cfg(not(all(feature = "libc", target_os = "linux")))]
+pub(crate) fn current_cpu() -> Option<u32> {
+    None
+}
+
+thread_local! {
+    // The ids this thread has been assigned so far, keyed by the marker of
+    // their `RegistryId`. The entries of registries that have been dropped
+    // are pruned whenever the thread is assigned a new id, so the list only
+    // holds an entry per live profiler, of which there's usually only one.
+    static REGISTERED_THREAD_IDS: RefCell<Vec<(Weak<()>, u32)>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Identifies the set of thread ids that belong to a single profiler. The
+/// threads only hold weak references to its marker, which tell them once
+/// the profiler is gone.
+#[derive(Debug)]
+pub(crate) struct RegistryId(Arc<()>);
+
+impl RegistryId {
+    pub(crate) fn new() -> RegistryId {
+        RegistryId(Arc::new(()))
+    }
+
+    /// Returns the thread id the current thread has been assigned within
+    /// this registry, or assigns a new one via `new_id`. The second return
+    /// value is `true` if the id has just been assigned.
+    pub(crate) fn get_or_assign(&self, new_id: impl FnOnce() -> u32) -> (u32, bool) {
+        REGISTERED_THREAD_IDS.with(|registered| {
+            let mut registered = registered.borrow_mut();
+
+            let marker = Arc::as_ptr(&self.0);
+            if let Some((_, thread_id)) = registered.iter().find(|(m, _)| m.as_ptr() == marker) {
+                return (*thread_id, false);
+            }
+
+            let thread_id = new_id();
+            registered.retain(|(marker, _)| marker.strong_count() > 0);
+            registered.push((Arc::downgrade(&self.0), thread_id));
+            (thread_id, true)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn registered_thread_ids() -> usize {
+        REGISTERED_THREAD_IDS.with(|registered| registered.borrow().len())
+    }
+
+    #[test]
+    fn dropped_registries_are_pruned() {
+        std::thread::spawn(|| {
+            let registry = RegistryId::new();
+            assert_eq!(registry.get_or_assign(|| 7), (7, true));
+            assert_eq!(registry.get_or_assign(|| 8), (7, false));
+
+            for id in 0..10 {
+                let other = RegistryId::new();
+                assert_eq!(other.get_or_assign(|| id), (id, true));
+            }
+            // Each registry of the loop has been pruned when the next one
+            // assigned an id, only the last one is left.
+            assert_eq!(registered_thread_ids(), 2);
+
+            drop(registry);
+            assert_eq!(RegistryId::new().get_or_assign(|| 9), (9, true));
+            assert_eq!(registered_thread_ids(), 1);
+        })
+        .join()
+        .unwrap();
+    }
+}
@@ -0,0 +1,169 @@
+//! Hooks for registering the threads of a thread pool with a profiler, so
+//! that code running on the pool can record events without being handed a
+//! `Profiler` and a thread id by every closure it is called from.
+//!
+//! [`ThreadPoolHooks::start_handler()`] registers the thread it runs on via
+//! `Profiler::register_current_thread_as()` and installs the profiler as the
+//! thread's current one, which [`with_current_profiler()`] then passes to
+//! the code running on the thread, together with the thread's id.
+//! [`ThreadPoolHooks::exit_handler()`] records the thread's `ThreadFinished`
+//! marker and removes the profiler again. The handlers take the index of the
+//! thread in the pool, like the ones `rayon::ThreadPoolBuilder` accepts:
+//!
+//! ```ignore
+//! let hooks = ThreadPoolHooks::new(profiler.clone()).with_name("rayon");
+//! let pool = rayon::ThreadPoolBuilder::new()
+//!     .start_handler(hooks.start_handler())
+//!     .exit_handler(hooks.exit_handler())
+//!     .build()?;
+//!
+//! pool.install(|| {
+//!     with_current_profiler(|profiler: &Profiler<FileSerializationSink>, thread_id| {
+//!         // record events
+//!     })
+//! });
+//! ```
+//!
+//! Pools without such hooks, like scoped threads of `crossbeam`, can run the
+//! body of each thread via [`ThreadPoolHooks::run()`] instead:
+//!
+//! ```ignore
+//! crossbeam::scope(|scope| {
+//!     for _ in 0..4 {
+//!         let hooks = hooks.clone();
+//!         scope.spawn(move |_| hooks.run(|| work()));
+//!     }
+//! })
+//! ```
+//!
+//! Threads are named after the name they have been spawned with. Unnamed
+//! threads get the name of the pool, followed by their index if known, like
+//! `rayon-3`.
+//!
+//! [`ThreadPoolHooks::exit_handler()`]: struct.ThreadPoolHooks.html#method.exit_handler
+//! [`ThreadPoolHooks::run()`]: struct.ThreadPoolHooks.html#method.run
+//! [`ThreadPoolHooks::start_handler()`]: struct.ThreadPoolHooks.html#method.start_handler
+//! [`with_current_profiler()`]: fn.with_current_profiler.html
+
+use crate::{Profiler, SerializationSink};
+use std::any::Any;
+use std::cell::RefCell;
+use std::sync::Arc;
+
+thread_local! {
+    // The `ThreadContext<S>` of the current thread, if it has been installed.
+    static CURRENT: RefCell<Option<Box<dyn Any>>> = RefCell::new(None);
+}
+
+struct ThreadContext<S: SerializationSink> {
+    profiler: Arc<Profiler<S>>,
+    thread_id: u32,
+}
+
+/// Calls `f` with the profiler installed on the current thread by
+/// `ThreadPoolHooks` and the thread's id. Returns `None` without calling `f`
+/// if no profiler with the sink type `S` is installed, e.g. because the code
+/// doesn't run on a pool.
+pub fn with_current_profiler<S, R, F>(f: F) -> Option<R>
+where
+    S: SerializationSink,
+    F: FnOnce(&Profiler<S>, u32) -> R,
+{
+    // The context is cloned so that `f` can itself install a profiler, e.g.
+    // by running a nested pool on the same thread.
+    let (profiler, thread_id) = CURRENT.with(|current| {
+        let current = current.borrow();
+        let context = current.as_ref()?.downcast_ref::<ThreadContext<S>>()?;
+        Some((context.profiler.clone(), context.thread_id))
+    })?;
+
+    Some(f(&profiler, thread_id))
+}
+
+/// Start and exit hooks for the threads of a pool, see the module
+/// documentation.
+pub struct ThreadPoolHooks<S: SerializationSink> {
+    profiler: Arc<Profiler<S>>,
+    name: Arc<str>,
+}
+
+impl<S: SerializationSink> Clone for ThreadPoolHooks<S> {
+    fn clone(&self) -> Self {
+        ThreadPoolHooks {
+            profiler: self.profiler.clone(),
+            name: self.name.clone(),
+        }
+    }
+}
+
+impl<S: SerializationSink> ThreadPoolHooks<S> {
+    pub fn new(profiler: Arc<Profiler<S>>) -> ThreadPoolHooks<S> {
+        ThreadPoolHooks {
+            profiler,
+            name: Arc::from("pool"),
+        }
+    }
+
+    /// Sets the name unnamed threads of the pool are named after. Defaults
+    /// to `pool`.
+    pub fn with_name(mut self, name: &str) -> ThreadPoolHooks<S> {
+        self.name = Arc::from(name);
+        self
+    }
+
+    /// Returns the hook to run when a thread of the pool starts, with the
+    /// index of the thread in the pool.
+    pub fn start_handler(&self) -> impl Fn(usize) + Send + Sync + 'static {
+        let hooks = self.clone();
+        move |index| hooks.enter(Some(index))
+    }
+
+    /// Returns the hook to run when a thread of the pool exits, with the
+    /// index of the thread in the pool.
+    pub fn exit_handler(&self) -> impl Fn(usize) + Send + Sync + 'static {
+        let hooks = self.clone();
+        move |_| hooks.exit()
+    }
+
+    /// Runs `f` as the body of a thread of the pool, i.e. between the start
+    /// and exit hooks. The exit hook also runs if `f` panics.
+    pub fn run<R>(&self, f: impl FnOnce() -> R) -> R {
+        struct Exit<'a, S: SerializationSink>(&'a ThreadPoolHooks<S>);
+
+        impl<S: SerializationSink> Drop for Exit<'_, S> {
+            fn drop(&mut self) {
+                self.0.exit();
+            }
+        }
+
+        self.enter(None);
+        let _exit = Exit(self);
+        f()
+    }
+
+    fn enter(&self, index: Option<usize>) {
+        let thread_id = match (std::thread::current().name(), index) {
+            (Some(_), _) => self.profiler.register_current_thread(),
+            (None, Some(index)) => self
+                .profiler
+                .register_current_thread_as(&format!("{}-{}", self.name, index)),
+            (None, None) => self.profiler.register_current_thread_as(&self.name),
+        };
+
+        let context = ThreadContext {
+            profiler: self.profiler.clone(),
+            thread_id,
+        };
+        CURRENT.with(|current| *current.borrow_mut() = Some(Box::new(context)));
+    }
+
+    fn exit(&self) {
+        let context = CURRENT.with(|current| current.borrow_mut().take());
+
+        if let Some(context) = context {
+            if let Some(context) = context.downcast_ref::<ThreadContext<S>>() {
+                self.profiler.finish_thread(context.thread_id);
+            }
+        }
+    }
+}
@@ -0,0 +1,180 @@
+//! `mm` bundles all tools of this repository into a single binary, so that
+//! installing it is enough to use any of them: `mm crox <file_prefix>` does
+//! the same as `crox <file_prefix>`. The sub commands of `summarize` and the
+//! commands of `cargo mm` that don't clash with them are commands of their
+//! own, e.g. `mm diff` and `mm stats`. All commands take the profile as a
+//! positional argument and share the `--message-format`, `--quiet`,
+//! `--verbose` and `--log` flags of `analyzeme::cli::OutputOpts`.
+
+use analyzeme::cli::exit_on_error;
+use std::env;
+use std::error::Error;
+use std::ffi::OsString;
+use std::process;
+
+/// The `main_with_args()` of one of the tools' libraries.
+type ToolMain = fn(Vec<OsString>) -> Result<(), Box<dyn Error>>;
+
+#[derive(Clone, Copy)]
+enum Runner {
+    /// One of the tool binaries, which doesn't have sub commands.
+    Tool(ToolMain),
+    /// One of the sub commands of `summarize`.
+    Summarize,
+    /// One of the commands of `cargo mm`, see `cargo_mm::command_names()`.
+    CargoMm,
+}
+
+struct MmCommand {
+    name: &'static str,
+    about: &'static str,
+    runner: Runner,
+}
+
+const COMMANDS: &[MmCommand] = &[
+    MmCommand {
+        name: "summarize",
+        about: "Prints a summary of a profile",
+        runner: Runner::Summarize,
+    },
+    MmCommand {
+        name: "diff",
+        about: "Compares two profiles",
+        runner: Runner::Summarize,
+    },
+    MmCommand {
+        name: "incremental",
+        about: "Reports how effective incremental compilation was",
+        runner: Runner::Summarize,
+    },
+    MmCommand {
+        name: "histogram",
+        about: "Writes histograms of the durations of each label",
+        runner: Runner::Summarize,
+    },
+    MmCommand {
+        name: "crates",
+        about: "Reports the time spent on each crate",
+        runner: Runner::Summarize,
+    },
+    MmCommand {
+        name: "percentiles",
+        about: "Reports percentiles of label durations across profiles",
+        runner: Runner::Summarize,
+    },
+    MmCommand {
+        name: "metrics",
+        about: "Exports the time of each label as Prometheus metrics",
+        runner: Runner::Summarize,
+    },
+    MmCommand {
+        name: "crox",
+        about: "Converts a profile into a Chrome or Firefox trace",
+        runner: Runner::Tool(crox::main_with_args),
+    },
+    MmCommand {
+        name: "flamegraph",
+        about: "Renders a profile as a flame graph",
+        runner: Runner::Tool(flamegraph::main_with_args),
+    },
+    MmCommand {
+        name: "stack_collapse",
+        about: "Writes the folded stacks of a profile",
+        runner: Runner::Tool(stack_collapse::main_with_args),
+    },
+    MmCommand {
+        name: "mmdot",
+        about: "Renders the call graph of a profile for Graphviz",
+        runner: Runner::Tool(mmdot::main_with_args),
+    },
+    MmCommand {
+        name: "heatmap",
+        about: "Writes the activity per time bucket as CSV or PNG",
+        runner: Runner::Tool(heatmap::main_with_args),
+    },
+    MmCommand {
+        name: "mmview",
+        about: "Prints the events of a profile",
+        runner: Runner::Tool(mmview::main_with_args),
+    },
+];
+
+fn find_command(name: &str) -> Option<Runner> {
+    if let Some(command) = COMMANDS.iter().find(|command| command.name == name) {
+        return Some(command.runner);
+    }
+
+    if cargo_mm::command_names()
+        .iter()
+        .any(|command| command == name)
+    {
+        Some(Runner::CargoMm)
+    } else {
+        None
+    }
+}
+
+fn print_help() {
+    println!("mm {}", env!("CARGO_PKG_VERSION"));
+    println!("All measureme tools in one binary\n");
+    println!("USAGE:\n    mm <COMMAND> [ARGS]...\n");
+    println!("COMMANDS:");
+    for command in COMMANDS {
+        println!("    {:<16}{}", command.name, command.about);
+    }
+    println!("\nThe commands of `cargo mm`, see `mm <COMMAND> --help`:");
+    let names: Vec<_> = cargo_mm::command_names()
+        .into_iter()
+        .filter(|name| !COMMANDS.iter().any(|command| command.name == name))
+        .collect();
+    for names in names.chunks(6) {
+        println!("    {}", names.join(", "));
+    }
+}
+
+fn main() {
+    let mut args = env::args_os().skip(1);
+    let name = match args.next() {
+        Some(name) => name,
+        None => {
+            print_help();
+            process::exit(1);
+        }
+    };
+    let mut rest: Vec<OsString> = args.collect();
+
+    let name = match name.to_str() {
+        Some("-h") | Some("--help") => return print_help(),
+        Some("-V") | Some("--version") => return println!("mm {}", env!("CARGO_PKG_VERSION")),
+        // `mm help <COMMAND>` is `mm <COMMAND> --help`.
+        Some("help") if !rest.is_empty() => {
+            let name = rest.remove(0);
+            rest = vec!["--help".into()];
+            name
+        }
+        Some("help") => return print_help(),
+        _ => name,
+    };
+
+    let runner = name.to_str().and_then(find_command);
+    exit_on_error(run(&name, runner, rest));
+}
+
+fn run(name: &OsString, runner: Option<Runner>, rest: Vec<OsString>) -> Result<(), Box<dyn Error>> {
+    let command_args = || std::iter::once("mm".into()).chain(std::iter::once(name.clone()));
+
+    match runner {
+        // The name of the binary shows up in the usage the tools print, so
+        // that it reads `mm crox [FLAGS] <file_prefix>`.
+        Some(Runner::Tool(main)) => {
+            let bin_name = format!("mm {}", name.to_string_lossy());
+            main(std::iter::once(bin_name.into()).chain(rest).collect())
+        }
+        Some(Runner::Summarize) => summarize::main_with_args(command_args().chain(rest)),
+        Some(Runner::CargoMm) => cargo_mm::command_with_args(command_args().chain(rest)),
+        None => Err(format!(
+            "unknown command `{}`, see `mm --help` for the available commands",
+            name.to_string_lossy()
+        ))?,
+    }
+}
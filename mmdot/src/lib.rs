@@ -0,0 +1,48 @@
+use std::error::Error;
+use std::ffi::OsString;
+use std::fs;
+use std::path::PathBuf;
+
+use analyzeme::cli::OutputOpts;
+use analyzeme::{call_graph, ProfilingData};
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+struct Opt {
+    file_prefix: PathBuf,
+
+    /// Leave out the labels and edges that account for less than this
+    /// percentage of the total time
+    #[structopt(long = "min-percent", default_value = "1")]
+    min_percent: f64,
+
+    #[structopt(flatten)]
+    output: OutputOpts,
+}
+
+/// Runs `mmdot` with the given command line arguments, the first of which is
+/// the name of the binary. Exits the process if the arguments are invalid,
+/// like the `mmdot` binary does, and returns the error if `mmdot` fails, for
+/// `analyzeme::cli::exit_on_error()`.
+pub fn main_with_args(args: impl IntoIterator<Item = OsString>) -> Result<(), Box<dyn Error>> {
+    run(Opt::from_iter(args))
+}
+
+fn run(opt: Opt) -> Result<(), Box<dyn Error>> {
+    opt.output.init()?;
+
+    if !(0.0..=100.0).contains(&opt.min_percent) {
+        Err(format!(
+            "`--min-percent` must be between 0 and 100, not {}",
+            opt.min_percent
+        ))?;
+    }
+
+    let profiling_data = ProfilingData::new(&opt.file_prefix)?;
+
+    let graph = call_graph(&profiling_data);
+
+    fs::write("call_graph.dot", graph.to_dot(opt.min_percent / 100.0))?;
+
+    Ok(())
+}
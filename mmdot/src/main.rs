@@ -0,0 +1,3 @@
+fn main() {
+    analyzeme::cli::exit_on_error(mmdot::main_with_args(std::env::args_os()));
+}
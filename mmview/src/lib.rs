@@ -0,0 +1,99 @@
+use analyzeme::cli::OutputOpts;
+use analyzeme::{Event, ProfilingData, SymbolMap, Timestamp};
+use std::error::Error;
+use std::ffi::OsString;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+struct Opt {
+    file_prefix: PathBuf,
+
+    /// Filter to events which occured on the specified thread id
+    #[structopt(short = "t", long = "thread-id")]
+    thread_id: Option<u32>,
+
+    /// Resolve address arguments via this symbol map in the format JITs
+    /// write for `perf` (`<start> <size> <name>` per line, in hexadecimal)
+    #[structopt(long = "symbol-map")]
+    symbol_map: Option<PathBuf>,
+
+    #[structopt(flatten)]
+    output: OutputOpts,
+}
+
+/// Runs `mmview` with the given command line arguments, the first of which is
+/// the name of the binary. Exits the process if the arguments are invalid,
+/// like the `mmview` binary does, and returns the error if `mmview` fails, for
+/// `analyzeme::cli::exit_on_error()`.
+pub fn main_with_args(args: impl IntoIterator<Item = OsString>) -> Result<(), Box<dyn Error>> {
+    run(Opt::from_iter(args))
+}
+
+fn run(opt: Opt) -> Result<(), Box<dyn Error>> {
+    opt.output.init()?;
+
+    let mut data = ProfilingData::new(&opt.file_prefix)?;
+    if let Some(path) = &opt.symbol_map {
+        data.set_symbolizer(SymbolMap::load(path)?);
+    }
+
+    let global_start_time = data.iter().map(|e| e.timestamp.start()).min().unwrap();
+
+    for event in data.iter() {
+        if let Some(thread_id) = opt.thread_id {
+            if event.thread_id != thread_id {
+                continue;
+            }
+        }
+
+        print_event(&data, &event.to_event(), global_start_time);
+    }
+
+    Ok(())
+}
+
+fn system_time_to_micros_since(t: SystemTime, since: SystemTime) -> u128 {
+    t.duration_since(since)
+        .unwrap_or(Duration::from_nanos(0))
+        .as_micros()
+}
+
+fn print_event(data: &ProfilingData, event: &Event<'_>, global_start_time: SystemTime) {
+    let additional_data: Vec<String> = data
+        .decode_args(event)
+        .iter()
+        .map(|arg| arg.to_string())
+        .collect();
+    let mut additional_data = additional_data.join(",");
+
+    if let Some(value) = event.integer_value {
+        if !additional_data.is_empty() {
+            additional_data.push(',');
+        }
+        additional_data.push_str(&format!("value={}", value));
+    }
+
+    let timestamp = match event.timestamp {
+        Timestamp::Instant(t) => {
+            format!("{} μs", system_time_to_micros_since(t, global_start_time))
+        }
+        Timestamp::Interval { start, end } => format!(
+            "{} μs - {} μs",
+            system_time_to_micros_since(start, global_start_time),
+            system_time_to_micros_since(end, global_start_time)
+        ),
+    };
+
+    println!(
+        r#"{{
+    kind: {},
+    label: {},
+    additional_data: [{}],
+    timestamp: {},
+    thread_id: {},
+}}"#,
+        event.event_kind, event.label, additional_data, timestamp, event.thread_id
+    );
+}
@@ -0,0 +1,50 @@
+use std::error::Error;
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+use analyzeme::cli::OutputOpts;
+use analyzeme::{collapse_stacks, Diagnostic, ProfilingData};
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+struct Opt {
+    file_prefix: PathBuf,
+
+    #[structopt(flatten)]
+    output: OutputOpts,
+}
+
+/// Runs `stack_collapse` with the given command line arguments, the first of which is
+/// the name of the binary. Exits the process if the arguments are invalid,
+/// like the `stack_collapse` binary does, and returns the error if `stack_collapse` fails, for
+/// `analyzeme::cli::exit_on_error()`.
+pub fn main_with_args(args: impl IntoIterator<Item = OsString>) -> Result<(), Box<dyn Error>> {
+    run(Opt::from_iter(args))
+}
+
+fn run(opt: Opt) -> Result<(), Box<dyn Error>> {
+    opt.output.init()?;
+
+    let profiling_data = ProfilingData::new(&opt.file_prefix)?;
+
+    let recorded_stacks = collapse_stacks(&profiling_data);
+
+    let mut file = BufWriter::new(File::create("out.stacks_folded")?);
+
+    //now that we've got all of the recorded data, print the results to the output file
+    let stacks = recorded_stacks.len();
+    for (unique_stack, count) in recorded_stacks {
+        writeln!(file, "{} {}", unique_stack, count)?;
+    }
+    file.flush()?;
+
+    Diagnostic::info(
+        "file-written",
+        format!("wrote {} stacks to `out.stacks_folded`", stacks),
+    )
+    .log();
+
+    Ok(())
+}
@@ -1,9 +1,14 @@
-use crate::query_data::{QueryData, Results};
-use analyzeme::{Event, ProfilingData, Timestamp};
+use crate::event_filter::{glob_matches, EventFilter};
+use crate::query_data::{
+    KindData, PhaseResults, QueryData, Results, SegmentResults, UnclosedThread,
+};
+use analyzeme::{
+    find_phases, find_session_segments, innermost_phase, session_segment, Event, ProfilingData,
+    Timestamp,
+};
 use measureme::rustc::*;
 use rustc_hash::FxHashMap;
-use std::borrow::Cow;
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime};
 
 /// Collects accumulated summary data for the given ProfilingData.
 ///
@@ -20,10 +25,12 @@ use std::time::SystemTime;
 ///
 /// For example, take the following query invocation trace:
 ///
-///                                      <== q4 ==>
-///           <== q2 ==>           <====== q3 ======>
-///     <===================== q1 =====================>
-/// -------------------------------------------------------> time
+/// ```text
+///                                  <== q4 ==>
+///       <== q2 ==>           <====== q3 ======>
+/// <===================== q1 =====================>
+/// ---------------------------------------------------> time
+/// ```
 ///
 /// Query `q1` calls `q2` and later `q3`, which in turn calls `q4`. In order
 /// to get the self-time of `q1`, we take it's entire duration and subtract the
@@ -40,7 +47,7 @@ use std::time::SystemTime;
 ///
 /// The algorithm goes as follows:
 ///
-/// ```
+/// ```text
 /// for event in profiling_data.reversed()
 ///    // Keep the stack up-to-date by popping all events that
 ///    // don't contain the current event. After this loop, the
@@ -62,14 +69,14 @@ use std::time::SystemTime;
 ///
 /// Here is an example of what updating the stack looks like:
 ///
-/// ```
+/// ```text
 ///      <--e2-->   <--e3-->
 ///  <-----------e1----------->
 /// ```
 ///
 /// In the event stream this shows up as something like:
 ///
-/// ```
+/// ```text
 /// [
 ///     { label=e2, start= 5, end=10 },
 ///     { label=e3, start=15, end=20 },
@@ -89,7 +96,7 @@ use std::time::SystemTime;
 ///
 /// Why is popping done in a `while` loop? consider the following
 ///
-/// ```
+/// ```text
 ///                  <-e4->
 ///      <--e2-->   <--e3-->
 ///  <-----------e1----------->
@@ -97,7 +104,7 @@ use std::time::SystemTime;
 ///
 /// This looks as follows in the stream:
 ///
-/// ```
+/// ```text
 /// [
 ///     { label=e2, start= 5, end=10 },
 ///     { label=e4, start=17, end=19 },
@@ -109,34 +116,227 @@ use std::time::SystemTime;
 /// In this case when we encounter `e2`, the stack is `[e1, e3, e4]`, and both
 /// `e4` and `e3` need to be popped in the same step.
 pub fn perform_analysis(data: ProfilingData) -> Results {
-    struct PerThreadState<'a> {
-        stack: Vec<Event<'a>>,
-        start: SystemTime,
-        end: SystemTime,
+    perform_filtered_analysis(data, &EventFilter::default(), &SelfTimePolicy::default())
+}
+
+/// Decides which nested events count as children of the event they are
+/// nested in, i.e. whose time is subtracted from the self time of their
+/// parent. By default, that's all of them. Events of the kinds that are
+/// attributed to their parent instead (e.g. `IncrementalResultHashing`, to
+/// count hashing as part of the query it hashes the result of) are folded
+/// into their parent: their time stays in the parent's self time, they
+/// aren't reported on their own, and their own children count as children
+/// of the parent. Top-level events of these kinds are reported as usual.
+#[derive(Clone, Debug, Default)]
+pub struct SelfTimePolicy {
+    attributed_to_parent: Vec<String>,
+}
+
+impl SelfTimePolicy {
+    /// `attributed_to_parent` are glob patterns for event kinds, like the
+    /// patterns of an `EventFilter`.
+    pub fn new(attributed_to_parent: Vec<String>) -> SelfTimePolicy {
+        SelfTimePolicy {
+            attributed_to_parent,
+        }
+    }
+
+    pub fn is_attributed_to_parent(&self, event_kind: &str) -> bool {
+        self.attributed_to_parent
+            .iter()
+            .any(|pattern| glob_matches(pattern, event_kind))
+    }
+}
+
+/// Same as `perform_analysis()` but only reports the events that pass the
+/// given filter. Events that don't pass the filter are still taken into
+/// account for computing the self-time of their parents and the total time.
+/// `policy` decides which events count as children of their parents.
+///
+/// If the profile contains phases, the results are additionally broken down
+/// per phase. Each event is attributed to the innermost phase it started in.
+/// The same goes for the segments of the session.
+///
+/// If any thread marked itself as finished, the threads that didn't are
+/// reported along with the time they spent in intervals that were never
+/// closed. Profiles without such markers don't tell which threads finished,
+/// so nothing is reported for them.
+pub fn perform_filtered_analysis(
+    data: ProfilingData,
+    filter: &EventFilter,
+    policy: &SelfTimePolicy,
+) -> Results {
+    perform_analysis_with_progress(data, filter, policy, &mut |_| {})
+}
+
+/// How many events `perform_analysis_with_progress()` processes between two
+/// calls of its callback.
+pub const PROGRESS_INTERVAL: usize = 1 << 16;
+
+/// How far `perform_analysis_with_progress()` got.
+pub struct Progress<'p, 'a> {
+    pub events_processed: usize,
+    pub total_events: usize,
+    analyzer: &'p Analyzer<'a>,
+}
+
+impl Progress<'_, '_> {
+    /// The results for the events processed so far. As the events are
+    /// processed from last to first, parents are seen before their
+    /// children, so the self time of items whose children haven't been
+    /// processed yet is too high.
+    pub fn provisional_results(&self) -> Results {
+        self.analyzer.results()
     }
+}
+
+/// Same as `perform_filtered_analysis()`, but calls `on_progress` every
+/// `PROGRESS_INTERVAL` events. Apart from looking up the phase and session
+/// markers, this takes a single pass over the events, which aggregates the
+/// results for the whole profile, its phases and its segments at once.
+pub fn perform_analysis_with_progress(
+    data: ProfilingData,
+    filter: &EventFilter,
+    policy: &SelfTimePolicy,
+    on_progress: &mut dyn FnMut(&Progress<'_, '_>),
+) -> Results {
+    measureme::activity!("perform_analysis");
+    let phases = find_phases(&data);
+    let segments = find_session_segments(&data);
+
+    let mut all = Analyzer::new(filter, policy);
+    let mut per_phase: Vec<_> = phases
+        .iter()
+        .map(|_| Analyzer::new(filter, policy))
+        .collect();
+    let mut per_segment: Vec<_> = segments
+        .iter()
+        .map(|_| Analyzer::new(filter, policy))
+        .collect();
+
+    let total_events = data.num_events();
+    for (index, lightweight_event) in data.iter().rev().enumerate() {
+        if index > 0 && index % PROGRESS_INTERVAL == 0 {
+            on_progress(&Progress {
+                events_processed: index,
+                total_events,
+                analyzer: &all,
+            });
+        }
 
-    let mut query_data = FxHashMap::<String, QueryData>::default();
-    let mut threads = FxHashMap::<_, PerThreadState>::default();
+        let start = lightweight_event.timestamp.start();
+        let phase = innermost_phase(&phases, start);
+        let segment = session_segment(&segments, start);
 
-    let mut record_event_data = |label: &Cow<'_, str>, f: &dyn Fn(&mut QueryData)| {
-        if let Some(data) = query_data.get_mut(&label[..]) {
-            f(data);
-        } else {
-            let mut data = QueryData::new(label.clone().into_owned());
-            f(&mut data);
-            query_data.insert(label.clone().into_owned(), data);
+        let event = lightweight_event.to_event();
+        if let Some(phase) = phase {
+            per_phase[phase].process(event.clone());
+        }
+        if let Some(segment) = segment {
+            per_segment[segment].process(event.clone());
         }
-    };
+        all.process(event);
+    }
+
+    let mut results = all.into_results();
 
-    for current_event in data
+    results.phases = phases
         .iter()
-        .rev()
-        .map(|lightweight_event| lightweight_event.to_event())
+        .zip(per_phase)
+        .map(|(phase, analyzer)| PhaseResults {
+            name: phase.name.clone(),
+            duration: phase.duration(),
+            results: analyzer.into_results(),
+        })
+        .collect();
+
+    let wall_time = segments.last().map_or(Duration::ZERO, |last| {
+        last.end
+            .duration_since(data.metadata.start_time)
+            .unwrap_or_default()
+    });
+    results.session_segments = segments
+        .iter()
+        .zip(per_segment)
+        .map(|(segment, analyzer)| SegmentResults {
+            name: segment.name.clone(),
+            duration: segment.duration(),
+            percent_of_wall_time: if wall_time == Duration::ZERO {
+                0.0
+            } else {
+                segment.duration().as_secs_f64() / wall_time.as_secs_f64() * 100.0
+            },
+            results: analyzer.into_results(),
+        })
+        .collect();
+
+    let thread_ends = data.thread_ends();
+    if thread_ends
+        .iter()
+        .any(|thread_end| thread_end.finished.is_some())
     {
+        results.unclosed_threads = thread_ends
+            .iter()
+            .filter(|thread_end| thread_end.finished.is_none())
+            .map(|thread_end| UnclosedThread {
+                thread_id: thread_end.thread_id,
+                unclosed_time: thread_end.unclosed_time,
+            })
+            .collect();
+    }
+
+    results
+}
+
+struct PerThreadState<'a> {
+    stack: Vec<Event<'a>>,
+    start: SystemTime,
+    end: SystemTime,
+}
+
+/// Accumulates the results for the events it is given, in reverse order.
+struct Analyzer<'a> {
+    filter: &'a EventFilter,
+    policy: &'a SelfTimePolicy,
+    query_data: FxHashMap<String, QueryData>,
+    // The self time and number of events of each event kind.
+    kinds: FxHashMap<String, (Duration, usize)>,
+    threads: FxHashMap<u32, PerThreadState<'a>>,
+}
+
+impl<'a> Analyzer<'a> {
+    fn new(filter: &'a EventFilter, policy: &'a SelfTimePolicy) -> Analyzer<'a> {
+        Analyzer {
+            filter,
+            policy,
+            query_data: FxHashMap::default(),
+            kinds: FxHashMap::default(),
+            threads: FxHashMap::default(),
+        }
+    }
+
+    fn process(&mut self, current_event: Event<'a>) {
+        let filter = self.filter;
+        let query_data = &mut self.query_data;
+        let mut record_event_data = |event: &Event<'_>, f: &dyn Fn(&mut QueryData)| {
+            if !filter.matches(&event.label, &event.event_kind) {
+                return;
+            }
+
+            let label = &event.label;
+            if let Some(data) = query_data.get_mut(&label[..]) {
+                f(data);
+            } else {
+                let mut data = QueryData::new(label.clone().into_owned());
+                f(&mut data);
+                query_data.insert(label.clone().into_owned(), data);
+            }
+        };
+
         match current_event.timestamp {
             Timestamp::Instant(_) => {
                 if &current_event.event_kind[..] == QUERY_CACHE_HIT_EVENT_KIND {
-                    record_event_data(&current_event.label, &|data| {
+                    record_event_data(&current_event, &|data| {
                         data.number_of_cache_hits += 1;
                         data.invocation_count += 1;
                     });
@@ -144,18 +344,18 @@ pub fn perform_analysis(data: ProfilingData) -> Results {
             }
             Timestamp::Interval { start, end } => {
                 // This is an interval event
-                let thread =
-                    threads
-                        .entry(current_event.thread_id)
-                        .or_insert_with(|| PerThreadState {
-                            stack: Vec::new(),
-                            start,
-                            end,
-                        });
+                let thread = self
+                    .threads
+                    .entry(current_event.thread_id)
+                    .or_insert_with(|| PerThreadState {
+                        stack: Vec::new(),
+                        start,
+                        end,
+                    });
 
                 // Pop all events from the stack that are not parents of the
                 // current event.
-                while let Some(current_top) = thread.stack.last().cloned() {
+                while let Some(current_top) = thread.stack.last() {
                     if current_top.contains(&current_event) {
                         break;
                     }
@@ -163,36 +363,70 @@ pub fn perform_analysis(data: ProfilingData) -> Results {
                     thread.stack.pop();
                 }
 
+                // Leaving the event off the stack makes its children count
+                // as children of its parent.
+                if !thread.stack.is_empty()
+                    && self
+                        .policy
+                        .is_attributed_to_parent(&current_event.event_kind)
+                {
+                    return;
+                }
                 let current_event_duration = current_event.duration().unwrap();
 
+                // The rollup by kind ignores the filter, so that it covers
+                // all of the time.
+                if let Some(current_top) = thread.stack.last() {
+                    if let Some((self_time, _)) = self.kinds.get_mut(&current_top.event_kind[..]) {
+                        *self_time -= current_event_duration;
+                    }
+                }
+                let (self_time, count) = self
+                    .kinds
+                    .entry(current_event.event_kind.clone().into_owned())
+                    .or_default();
+                *self_time += current_event_duration;
+                *count += 1;
+
                 // If there is something on the stack, subtract the current
                 // interval from it.
                 if let Some(current_top) = thread.stack.last() {
-                    record_event_data(
-                        &current_top.label,
-                        &|data| match &current_top.event_kind[..] {
-                            QUERY_EVENT_KIND | GENERIC_ACTIVITY_EVENT_KIND => {
-                                data.self_time -= current_event_duration;
-                            }
-                            INCREMENTAL_LOAD_RESULT_EVENT_KIND => {
-                                data.self_time -= current_event_duration;
-                                data.incremental_load_time -= current_event_duration;
-                            }
-                            _ => {
-                                eprintln!(
-                                    "Unexpectedly enountered event `{:?}`, \
+                    record_event_data(current_top, &|data| match &current_top.event_kind[..] {
+                        // Blocked intervals don't have any self time, the
+                        // time of their children counts as blocked time.
+                        _ if current_top.blocked => {
+                            data.blocked_time -= current_event_duration;
+                        }
+                        QUERY_EVENT_KIND | GENERIC_ACTIVITY_EVENT_KIND => {
+                            data.self_time -= current_event_duration;
+                        }
+                        INCREMENTAL_LOAD_RESULT_EVENT_KIND => {
+                            data.self_time -= current_event_duration;
+                            data.incremental_load_time -= current_event_duration;
+                        }
+                        _ => {
+                            eprintln!(
+                                "Unexpectedly enountered event `{:?}`, \
                                      while top of stack was `{:?}`. Ignoring.",
-                                    current_event, current_top
-                                );
-                            }
-                        },
-                    );
+                                current_event, current_top
+                            );
+                        }
+                    });
                 }
 
                 // Update counters for the current event
                 match &current_event.event_kind[..] {
+                    // The thread was waiting instead of running, e.g. for a
+                    // lock, so this doesn't count towards the self time.
+                    _ if current_event.blocked => {
+                        record_event_data(&current_event, &|data| {
+                            data.time += current_event_duration;
+                            data.blocked_time += current_event_duration;
+                        });
+                    }
+
                     QUERY_EVENT_KIND | GENERIC_ACTIVITY_EVENT_KIND => {
-                        record_event_data(&current_event.label, &|data| {
+                        record_event_data(&current_event, &|data| {
                             data.self_time += current_event_duration;
                             data.time += current_event_duration;
                             data.number_of_cache_misses += 1;
@@ -201,7 +435,7 @@ pub fn perform_analysis(data: ProfilingData) -> Results {
                     }
 
                     QUERY_BLOCKED_EVENT_KIND => {
-                        record_event_data(&current_event.label, &|data| {
+                        record_event_data(&current_event, &|data| {
                             data.self_time += current_event_duration;
                             data.time += current_event_duration;
                             data.blocked_time += current_event_duration;
@@ -209,7 +443,7 @@ pub fn perform_analysis(data: ProfilingData) -> Results {
                     }
 
                     INCREMENTAL_LOAD_RESULT_EVENT_KIND => {
-                        record_event_data(&current_event.label, &|data| {
+                        record_event_data(&current_event, &|data| {
                             data.self_time += current_event_duration;
                             data.time += current_event_duration;
                             data.incremental_load_time += current_event_duration;
@@ -234,23 +468,83 @@ pub fn perform_analysis(data: ProfilingData) -> Results {
         }
     }
 
-    let total_time = threads
-        .values()
-        .map(|t| t.end.duration_since(t.start).unwrap())
-        .sum();
+    /// The results for the events processed so far.
+    fn results(&self) -> Results {
+        Results {
+            query_data: self.query_data.values().cloned().collect(),
+            total_time: self.total_time(),
+            phases: Vec::new(),
+            session_segments: Vec::new(),
+            unclosed_threads: Vec::new(),
+            kinds: self.kinds(),
+        }
+    }
+
+    fn into_results(self) -> Results {
+        Results {
+            total_time: self.total_time(),
+            kinds: self.kinds(),
+            query_data: self.query_data.into_values().collect(),
+            phases: Vec::new(),
+            session_segments: Vec::new(),
+            unclosed_threads: Vec::new(),
+        }
+    }
 
-    Results {
-        query_data: query_data.drain().map(|(_, value)| value).collect(),
-        total_time,
+    fn kinds(&self) -> Vec<KindData> {
+        let total_time = self.total_time().as_secs_f64();
+        let wall_time = self.wall_time().as_secs_f64();
+        let percent = |time: Duration, of: f64| {
+            if of == 0.0 {
+                0.0
+            } else {
+                time.as_secs_f64() / of * 100.0
+            }
+        };
+
+        let mut kinds: Vec<_> = self
+            .kinds
+            .iter()
+            .map(|(event_kind, &(self_time, event_count))| KindData {
+                event_kind: event_kind.clone(),
+                self_time,
+                event_count,
+                percent_of_total_time: percent(self_time, total_time),
+                percent_of_wall_time: percent(self_time, wall_time),
+            })
+            .collect();
+        kinds.sort_by(|a, b| {
+            b.self_time
+                .cmp(&a.self_time)
+                .then_with(|| a.event_kind.cmp(&b.event_kind))
+        });
+        kinds
+    }
+
+    /// The time from the start of the first event to the end of the last,
+    /// across all threads.
+    fn wall_time(&self) -> Duration {
+        let start = self.threads.values().map(|t| t.start).min();
+        let end = self.threads.values().map(|t| t.end).max();
+        match (start, end) {
+            (Some(start), Some(end)) => end.duration_since(start).unwrap_or_default(),
+            _ => Duration::ZERO,
+        }
+    }
+
+    fn total_time(&self) -> Duration {
+        self.threads
+            .values()
+            .map(|t| t.end.duration_since(t.start).unwrap())
+            .sum()
     }
 }
 
-#[rustfmt::skip]
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::time::Duration;
     use analyzeme::ProfilingDataBuilder;
+    use std::time::Duration;
 
     #[test]
     fn total_time_and_nesting() {
@@ -267,17 +561,72 @@ mod tests {
         assert_eq!(results.total_time, Duration::from_nanos(100));
 
         // 10ns in the beginning and 10ns in the end
-        assert_eq!(results.query_data_by_label("q1").self_time, Duration::from_nanos(20));
+        assert_eq!(
+            results.query_data_by_label("q1").self_time,
+            Duration::from_nanos(20)
+        );
         // 10ns in the beginning and 10ns in the end, again
-        assert_eq!(results.query_data_by_label("q2").self_time, Duration::from_nanos(20));
+        assert_eq!(
+            results.query_data_by_label("q2").self_time,
+            Duration::from_nanos(20)
+        );
         // 60ns of uninterupted self-time
-        assert_eq!(results.query_data_by_label("q3").self_time, Duration::from_nanos(60));
+        assert_eq!(
+            results.query_data_by_label("q3").self_time,
+            Duration::from_nanos(60)
+        );
 
         assert_eq!(results.query_data_by_label("q1").invocation_count, 1);
         assert_eq!(results.query_data_by_label("q2").invocation_count, 1);
         assert_eq!(results.query_data_by_label("q3").invocation_count, 1);
     }
 
+    #[test]
+    fn kinds() {
+        let mut b = ProfilingDataBuilder::new();
+
+        b.interval(GENERIC_ACTIVITY_EVENT_KIND, "a", 0, 0, 100, |b| {
+            b.interval(QUERY_EVENT_KIND, "q", 0, 10, 60, |b| {
+                b.interval(
+                    INCREMENTAL_RESULT_HASHING_EVENT_KIND,
+                    "q",
+                    0,
+                    40,
+                    60,
+                    |_| {},
+                );
+            });
+        });
+        b.interval(LLVM_PASS_EVENT_KIND, "pass", 1, 100, 200, |_| {});
+
+        let filter = EventFilter::new(vec!["q".to_string()], Vec::new());
+        let results =
+            perform_filtered_analysis(b.into_profiling_data(), &filter, &Default::default());
+
+        let kinds: Vec<_> = results
+            .kinds
+            .iter()
+            .map(|kind| {
+                (
+                    &kind.event_kind[..],
+                    kind.self_time.as_nanos(),
+                    kind.event_count,
+                    kind.percent_of_total_time,
+                    kind.percent_of_wall_time,
+                )
+            })
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                (LLVM_PASS_EVENT_KIND, 100, 1, 50.0, 50.0),
+                (GENERIC_ACTIVITY_EVENT_KIND, 50, 1, 25.0, 25.0),
+                (QUERY_EVENT_KIND, 30, 1, 15.0, 15.0),
+                (INCREMENTAL_RESULT_HASHING_EVENT_KIND, 20, 1, 10.0, 10.0),
+            ]
+        );
+    }
+
     #[test]
     fn events_with_same_starting_time() {
         //                      <--e4-->
@@ -298,10 +647,22 @@ mod tests {
 
         assert_eq!(results.total_time, Duration::from_nanos(200));
 
-        assert_eq!(results.query_data_by_label("e1").self_time, Duration::from_nanos(100));
-        assert_eq!(results.query_data_by_label("e2").self_time, Duration::from_nanos(50));
-        assert_eq!(results.query_data_by_label("e3").self_time, Duration::from_nanos(30));
-        assert_eq!(results.query_data_by_label("e4").self_time, Duration::from_nanos(20));
+        assert_eq!(
+            results.query_data_by_label("e1").self_time,
+            Duration::from_nanos(100)
+        );
+        assert_eq!(
+            results.query_data_by_label("e2").self_time,
+            Duration::from_nanos(50)
+        );
+        assert_eq!(
+            results.query_data_by_label("e3").self_time,
+            Duration::from_nanos(30)
+        );
+        assert_eq!(
+            results.query_data_by_label("e4").self_time,
+            Duration::from_nanos(20)
+        );
 
         assert_eq!(results.query_data_by_label("e1").invocation_count, 1);
         assert_eq!(results.query_data_by_label("e2").invocation_count, 1);
@@ -329,10 +690,22 @@ mod tests {
 
         assert_eq!(results.total_time, Duration::from_nanos(200));
 
-        assert_eq!(results.query_data_by_label("e1").self_time, Duration::from_nanos(100));
-        assert_eq!(results.query_data_by_label("e2").self_time, Duration::from_nanos(50));
-        assert_eq!(results.query_data_by_label("e3").self_time, Duration::from_nanos(30));
-        assert_eq!(results.query_data_by_label("e4").self_time, Duration::from_nanos(20));
+        assert_eq!(
+            results.query_data_by_label("e1").self_time,
+            Duration::from_nanos(100)
+        );
+        assert_eq!(
+            results.query_data_by_label("e2").self_time,
+            Duration::from_nanos(50)
+        );
+        assert_eq!(
+            results.query_data_by_label("e3").self_time,
+            Duration::from_nanos(30)
+        );
+        assert_eq!(
+            results.query_data_by_label("e4").self_time,
+            Duration::from_nanos(20)
+        );
 
         assert_eq!(results.query_data_by_label("e1").invocation_count, 1);
         assert_eq!(results.query_data_by_label("e2").invocation_count, 1);
@@ -365,9 +738,18 @@ mod tests {
 
         assert_eq!(results.total_time, Duration::from_nanos(200));
 
-        assert_eq!(results.query_data_by_label("e1").self_time, Duration::from_nanos(80));
-        assert_eq!(results.query_data_by_label("e2").self_time, Duration::from_nanos(80));
-        assert_eq!(results.query_data_by_label("e3").self_time, Duration::from_nanos(40));
+        assert_eq!(
+            results.query_data_by_label("e1").self_time,
+            Duration::from_nanos(80)
+        );
+        assert_eq!(
+            results.query_data_by_label("e2").self_time,
+            Duration::from_nanos(80)
+        );
+        assert_eq!(
+            results.query_data_by_label("e3").self_time,
+            Duration::from_nanos(40)
+        );
 
         assert_eq!(results.query_data_by_label("e1").invocation_count, 2);
         assert_eq!(results.query_data_by_label("e2").invocation_count, 2);
@@ -420,9 +802,18 @@ mod tests {
 
         assert_eq!(results.total_time, Duration::from_nanos(400));
 
-        assert_eq!(results.query_data_by_label("e1").self_time, Duration::from_nanos(160));
-        assert_eq!(results.query_data_by_label("e2").self_time, Duration::from_nanos(160));
-        assert_eq!(results.query_data_by_label("e3").self_time, Duration::from_nanos(80));
+        assert_eq!(
+            results.query_data_by_label("e1").self_time,
+            Duration::from_nanos(160)
+        );
+        assert_eq!(
+            results.query_data_by_label("e2").self_time,
+            Duration::from_nanos(160)
+        );
+        assert_eq!(
+            results.query_data_by_label("e3").self_time,
+            Duration::from_nanos(80)
+        );
 
         assert_eq!(results.query_data_by_label("e1").invocation_count, 4);
         assert_eq!(results.query_data_by_label("e2").invocation_count, 4);
@@ -443,7 +834,6 @@ mod tests {
             b.instant(QUERY_CACHE_HIT_EVENT_KIND, "x", 0, 210);
 
             b.interval(QUERY_EVENT_KIND, "e2", 0, 220, 280, |b| {
-
                 b.instant(QUERY_CACHE_HIT_EVENT_KIND, "y", 0, 230);
 
                 b.interval(QUERY_EVENT_KIND, "e3", 0, 240, 260, |b| {
@@ -463,9 +853,18 @@ mod tests {
 
         assert_eq!(results.total_time, Duration::from_nanos(100));
 
-        assert_eq!(results.query_data_by_label("e1").self_time, Duration::from_nanos(40));
-        assert_eq!(results.query_data_by_label("e2").self_time, Duration::from_nanos(40));
-        assert_eq!(results.query_data_by_label("e3").self_time, Duration::from_nanos(20));
+        assert_eq!(
+            results.query_data_by_label("e1").self_time,
+            Duration::from_nanos(40)
+        );
+        assert_eq!(
+            results.query_data_by_label("e2").self_time,
+            Duration::from_nanos(40)
+        );
+        assert_eq!(
+            results.query_data_by_label("e3").self_time,
+            Duration::from_nanos(20)
+        );
 
         assert_eq!(results.query_data_by_label("e1").invocation_count, 1);
         assert_eq!(results.query_data_by_label("e2").invocation_count, 1);
@@ -494,9 +893,15 @@ mod tests {
 
         assert_eq!(results.total_time, Duration::from_nanos(100));
 
-        assert_eq!(results.query_data_by_label("e1").self_time, Duration::from_nanos(100));
+        assert_eq!(
+            results.query_data_by_label("e1").self_time,
+            Duration::from_nanos(100)
+        );
         assert_eq!(results.query_data_by_label("e1").invocation_count, 3);
-        assert_eq!(results.query_data_by_label("e1").time, Duration::from_nanos(180));
+        assert_eq!(
+            results.query_data_by_label("e1").time,
+            Duration::from_nanos(180)
+        );
     }
 
     #[test]
@@ -516,9 +921,62 @@ mod tests {
 
         assert_eq!(results.total_time, Duration::from_nanos(230));
 
-        assert_eq!(results.query_data_by_label("q1").self_time, Duration::from_nanos(230));
-        assert_eq!(results.query_data_by_label("q1").blocked_time, Duration::from_nanos(130));
-        assert_eq!(results.query_data_by_label("q1").time, Duration::from_nanos(230));
+        assert_eq!(
+            results.query_data_by_label("q1").self_time,
+            Duration::from_nanos(230)
+        );
+        assert_eq!(
+            results.query_data_by_label("q1").blocked_time,
+            Duration::from_nanos(130)
+        );
+        assert_eq!(
+            results.query_data_by_label("q1").time,
+            Duration::from_nanos(230)
+        );
+    }
+
+    #[test]
+    fn blocked_intervals() {
+        // T0: <------------------q1------------------>
+        //         <--lock (blocked)-->  <--q2-->
+        //                                 <--lock (blocked)-->
+        //     0   10             40     50 55  70    80      100
+
+        let mut b = ProfilingDataBuilder::new();
+
+        b.interval(QUERY_EVENT_KIND, "q1", 0, 0, 100, |b| {
+            b.blocked_interval(GENERIC_ACTIVITY_EVENT_KIND, "lock", 0, 10, 40);
+            b.interval(QUERY_EVENT_KIND, "q2", 0, 50, 80, |b| {
+                b.blocked_interval(GENERIC_ACTIVITY_EVENT_KIND, "lock", 0, 55, 70);
+            });
+        });
+
+        let results = perform_analysis(b.into_profiling_data());
+
+        assert_eq!(results.total_time, Duration::from_nanos(100));
+
+        assert_eq!(
+            results.query_data_by_label("q1").self_time,
+            Duration::from_nanos(40)
+        );
+        assert_eq!(
+            results.query_data_by_label("q1").blocked_time,
+            Duration::from_nanos(0)
+        );
+        assert_eq!(
+            results.query_data_by_label("q2").self_time,
+            Duration::from_nanos(15)
+        );
+        assert_eq!(
+            results.query_data_by_label("q2").time,
+            Duration::from_nanos(30)
+        );
+
+        let lock = results.query_data_by_label("lock");
+        assert_eq!(lock.self_time, Duration::from_nanos(0));
+        assert_eq!(lock.blocked_time, Duration::from_nanos(45));
+        assert_eq!(lock.time, Duration::from_nanos(45));
+        assert_eq!(lock.invocation_count, 0);
     }
 
     #[test]
@@ -538,8 +996,238 @@ mod tests {
 
         assert_eq!(results.total_time, Duration::from_nanos(230));
 
-        assert_eq!(results.query_data_by_label("q1").self_time, Duration::from_nanos(230));
-        assert_eq!(results.query_data_by_label("q1").incremental_load_time, Duration::from_nanos(230));
-        assert_eq!(results.query_data_by_label("q1").time, Duration::from_nanos(230));
+        assert_eq!(
+            results.query_data_by_label("q1").self_time,
+            Duration::from_nanos(230)
+        );
+        assert_eq!(
+            results.query_data_by_label("q1").incremental_load_time,
+            Duration::from_nanos(230)
+        );
+        assert_eq!(
+            results.query_data_by_label("q1").time,
+            Duration::from_nanos(230)
+        );
+    }
+
+    #[test]
+    fn filtered_events_still_count_towards_parent_self_time() {
+        let mut b = ProfilingDataBuilder::new();
+
+        b.interval(QUERY_EVENT_KIND, "q1", 0, 100, 200, |b| {
+            b.interval(
+                GENERIC_ACTIVITY_EVENT_KIND,
+                "LLVM_passes",
+                0,
+                110,
+                150,
+                |_| {},
+            );
+            b.instant(QUERY_CACHE_HIT_EVENT_KIND, "q2", 0, 160);
+        });
+
+        let filter = EventFilter::new(
+            vec![],
+            vec!["LLVM_*".to_string(), QUERY_CACHE_HIT_EVENT_KIND.to_string()],
+        );
+        let results =
+            perform_filtered_analysis(b.into_profiling_data(), &filter, &SelfTimePolicy::default());
+
+        assert_eq!(results.total_time, Duration::from_nanos(100));
+        assert_eq!(results.query_data.len(), 1);
+        assert_eq!(
+            results.query_data_by_label("q1").self_time,
+            Duration::from_nanos(60)
+        );
+    }
+
+    #[test]
+    fn events_attributed_to_parent() {
+        let build = || {
+            let mut b = ProfilingDataBuilder::new();
+
+            b.interval(QUERY_EVENT_KIND, "q1", 0, 100, 200, |b| {
+                b.interval(
+                    INCREMENTAL_RESULT_HASHING_EVENT_KIND,
+                    "hash_result",
+                    0,
+                    120,
+                    180,
+                    |b| {
+                        b.interval(QUERY_EVENT_KIND, "q2", 0, 130, 140, |_| {});
+                    },
+                );
+            });
+            b.interval(
+                INCREMENTAL_RESULT_HASHING_EVENT_KIND,
+                "hash_result",
+                0,
+                200,
+                210,
+                |_| {},
+            );
+
+            b.into_profiling_data()
+        };
+
+        let separate = perform_analysis(build());
+        assert_eq!(
+            separate.query_data_by_label("q1").self_time,
+            Duration::from_nanos(40)
+        );
+
+        let policy = SelfTimePolicy::new(vec!["IncrementalResult*".to_string()]);
+        let attributed = perform_filtered_analysis(build(), &EventFilter::default(), &policy);
+
+        assert_eq!(attributed.total_time, Duration::from_nanos(110));
+        assert_eq!(
+            attributed.query_data_by_label("q1").self_time,
+            Duration::from_nanos(90)
+        );
+        assert_eq!(
+            attributed.query_data_by_label("q2").self_time,
+            Duration::from_nanos(10)
+        );
+        assert!(!attributed
+            .query_data
+            .iter()
+            .any(|q| q.label == "hash_result"));
+    }
+
+    #[test]
+    fn phases() {
+        use measureme::event_kinds::{PHASE_END_EVENT_KIND, PHASE_START_EVENT_KIND};
+
+        let mut b = ProfilingDataBuilder::new();
+
+        b.instant(PHASE_START_EVENT_KIND, "expansion", 0, 0);
+        b.interval(QUERY_EVENT_KIND, "q1", 0, 10, 20, |_| {});
+        b.instant(PHASE_END_EVENT_KIND, "expansion", 0, 30);
+        b.instant(PHASE_START_EVENT_KIND, "codegen", 0, 30);
+        b.interval(QUERY_EVENT_KIND, "q1", 0, 40, 60, |b| {
+            b.interval(QUERY_EVENT_KIND, "q2", 0, 45, 50, |_| {});
+        });
+        b.instant(PHASE_END_EVENT_KIND, "codegen", 0, 70);
+
+        let results = perform_analysis(b.into_profiling_data());
+
+        assert_eq!(results.query_data_by_label("q1").invocation_count, 2);
+        assert_eq!(results.phases.len(), 2);
+
+        let expansion = &results.phases[0];
+        assert_eq!(expansion.name, "expansion");
+        assert_eq!(expansion.duration, Duration::from_nanos(30));
+        assert_eq!(expansion.results.query_data.len(), 1);
+        assert_eq!(
+            expansion.results.query_data_by_label("q1").self_time,
+            Duration::from_nanos(10)
+        );
+
+        let codegen = &results.phases[1];
+        assert_eq!(codegen.name, "codegen");
+        assert_eq!(codegen.results.total_time, Duration::from_nanos(20));
+        assert_eq!(
+            codegen.results.query_data_by_label("q1").self_time,
+            Duration::from_nanos(15)
+        );
+        assert_eq!(
+            codegen.results.query_data_by_label("q2").invocation_count,
+            1
+        );
+    }
+
+    #[test]
+    fn session_segments() {
+        use measureme::event_kinds::SESSION_MARKER_EVENT_KIND;
+
+        // Builder profiles start at the epoch, so the wall time is 100ns.
+        let mut b = ProfilingDataBuilder::new();
+
+        b.interval(QUERY_EVENT_KIND, "setup", 0, 0, 20, |_| {});
+        b.instant(SESSION_MARKER_EVENT_KIND, CODEGEN_SESSION_MARKER, 0, 20);
+        b.interval(QUERY_EVENT_KIND, "q1", 1, 30, 60, |_| {});
+        b.instant(SESSION_MARKER_EVENT_KIND, LINKING_SESSION_MARKER, 0, 80);
+        b.interval(QUERY_EVENT_KIND, "link", 0, 80, 100, |_| {});
+
+        let results = perform_analysis(b.into_profiling_data());
+        let segments: Vec<_> = results
+            .session_segments
+            .iter()
+            .map(|s| {
+                (
+                    &s.name[..],
+                    s.duration.as_nanos(),
+                    s.percent_of_wall_time,
+                    s.results.query_data.len(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            segments,
+            vec![("codegen", 60, 60.0, 1), ("linking", 20, 20.0, 1)]
+        );
+        assert_eq!(
+            results.session_segments[0]
+                .results
+                .query_data_by_label("q1")
+                .invocation_count,
+            1
+        );
+    }
+
+    #[test]
+    fn progress() {
+        let mut b = ProfilingDataBuilder::new();
+        for i in 0..PROGRESS_INTERVAL as u64 * 2 + 1 {
+            b.interval(QUERY_EVENT_KIND, "q", 0, i * 10, i * 10 + 5, |_| {});
+        }
+        let data = b.into_profiling_data();
+
+        let mut reports = Vec::new();
+        let results = perform_analysis_with_progress(
+            data,
+            &EventFilter::default(),
+            &SelfTimePolicy::default(),
+            &mut |progress| {
+                let provisional = progress.provisional_results();
+                reports.push((
+                    progress.events_processed,
+                    progress.total_events,
+                    provisional.query_data_by_label("q").invocation_count,
+                ));
+            },
+        );
+
+        let interval = PROGRESS_INTERVAL;
+        let total = interval * 2 + 1;
+        assert_eq!(
+            reports,
+            vec![
+                (interval, total, interval),
+                (interval * 2, total, interval * 2)
+            ]
+        );
+        assert_eq!(results.query_data_by_label("q").invocation_count, total);
+    }
+
+    #[test]
+    fn unclosed_threads() {
+        use measureme::event_kinds::THREAD_FINISHED_EVENT_KIND;
+
+        let mut b = ProfilingDataBuilder::new();
+
+        b.interval(QUERY_EVENT_KIND, "q1", 0, 10, 20, |_| {});
+        b.instant(THREAD_FINISHED_EVENT_KIND, "", 0, 100);
+        b.interval(QUERY_EVENT_KIND, "q1", 1, 10, 60, |_| {});
+
+        let results = perform_analysis(b.into_profiling_data());
+
+        assert_eq!(results.unclosed_threads.len(), 1);
+        assert_eq!(results.unclosed_threads[0].thread_id, 1);
+        assert_eq!(
+            results.unclosed_threads[0].unclosed_time,
+            Duration::from_nanos(40)
+        );
+        assert_eq!(results.query_data_by_label("q1").invocation_count, 2);
     }
 }
@@ -0,0 +1,260 @@
+//! Attributes the time of a profile to crates, answering "which dependency
+//! costs me the most build time". The crate of an event is taken from its
+//! arguments or its label, following rustc's conventions:
+//!
+//! - paths like `core[a1b2]::mem::replace` or `<regex::Regex as Clone>`
+//!   (query keys, recorded with `-Z self-profile-events=query-keys`),
+//! - def ids like `DefId(0:12 ~ regex[a1b2]::compile)`, and
+//! - codegen unit names like `regex.5f3a9c1e-cgu.0` (LLVM events, recorded
+//!   with `-Z self-profile-events=args`).
+//!
+//! Events without a crate belong to the crate of the event they are nested
+//! in, and top-level events to the crate being compiled. Every event adds its
+//! self time to its crate, so nested events aren't counted twice.
+
+use analyzeme::{LightweightEvent, ProfilingData};
+use rustc_hash::FxHashMap;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct CrateTime {
+    pub crate_name: String,
+    pub self_time: Duration,
+    /// The number of events whose own arguments or label named the crate.
+    pub item_count: usize,
+}
+
+/// Sums up the time per crate over the given profiles, each together with
+/// the name of the crate it compiled. The result is ordered by time,
+/// descending.
+pub fn crate_times(profiles: &[(String, ProfilingData)]) -> Vec<CrateTime> {
+    let mut totals = FxHashMap::<String, CrateTime>::default();
+
+    for (own_crate, data) in profiles {
+        add_crate_times(data, own_crate, &mut totals);
+    }
+
+    let mut crate_times: Vec<_> = totals.into_values().collect();
+    crate_times.sort_by(|a, b| {
+        b.self_time
+            .cmp(&a.self_time)
+            .then_with(|| a.crate_name.cmp(&b.crate_name))
+    });
+    crate_times
+}
+
+fn add_crate_times(
+    data: &ProfilingData,
+    own_crate: &str,
+    totals: &mut FxHashMap<String, CrateTime>,
+) {
+    let events: Vec<LightweightEvent<'_>> = data
+        .iter()
+        .filter(|event| !event.timestamp.is_instant() && !event.is_blocked())
+        .collect();
+
+    // Walking the stream backwards encounters parents before their
+    // children, see `analyzeme::EventIteratorExt::with_nesting()`.
+    let mut stacks = FxHashMap::<u32, Vec<(usize, String)>>::default();
+    let mut self_times: Vec<Duration> = events.iter().map(|e| e.duration().unwrap()).collect();
+    let mut crates = vec![String::new(); events.len()];
+
+    for (index, event) in events.iter().enumerate().rev() {
+        let stack = stacks.entry(event.thread_id).or_default();
+
+        while let Some(&(top, _)) = stack.last() {
+            if events[top].contains(event) {
+                break;
+            }
+            stack.pop();
+        }
+
+        let full_event = event.to_event();
+        let named = full_event
+            .additional_data
+            .iter()
+            .chain(std::iter::once(&full_event.label))
+            .find_map(|text| crate_in(text));
+
+        let crate_name = match (named, stack.last()) {
+            (Some(name), _) => {
+                totals_entry(totals, name).item_count += 1;
+                name.to_string()
+            }
+            (None, Some((_, parent_crate))) => parent_crate.clone(),
+            (None, None) => own_crate.to_string(),
+        };
+
+        if let Some(&(parent, _)) = stack.last() {
+            self_times[parent] = self_times[parent].saturating_sub(self_times[index]);
+        }
+
+        crates[index] = crate_name.clone();
+        stack.push((index, crate_name));
+    }
+
+    for (crate_name, self_time) in crates.iter().zip(self_times) {
+        totals_entry(totals, crate_name).self_time += self_time;
+    }
+}
+
+fn totals_entry<'t>(totals: &'t mut FxHashMap<String, CrateTime>, name: &str) -> &'t mut CrateTime {
+    totals.entry(name.to_string()).or_insert_with(|| CrateTime {
+        crate_name: name.to_string(),
+        self_time: Duration::from_nanos(0),
+        item_count: 0,
+    })
+}
+
+/// Names that can start a path without being a crate.
+const NOT_CRATES: [&str; 21] = [
+    "crate", "self", "super", "Self", "bool", "char", "str", "u8", "u16", "u32", "u64", "u128",
+    "usize", "i8", "i16", "i32", "i64", "i128", "isize", "f32", "f64",
+];
+
+/// Returns the crate named by `text` according to the conventions in the
+/// module documentation, if any.
+pub fn crate_in(text: &str) -> Option<&str> {
+    if let Some(name) = codegen_unit_crate(text) {
+        return Some(name);
+    }
+
+    let text = match text.find(" ~ ") {
+        Some(index) if text.starts_with("DefId(") => &text[index + 3..],
+        _ => text,
+    };
+
+    let mut rest = text;
+    while !rest.is_empty() {
+        let start = rest.find(is_ident_start)?;
+        let ident_len = rest[start..]
+            .find(|c: char| !is_ident_char(c))
+            .unwrap_or(rest.len() - start);
+        let ident = &rest[start..start + ident_len];
+        let after = &rest[start + ident_len..];
+
+        // As part of a longer identifier, `rest` doesn't start a path.
+        let preceded_by_ident = rest[..start].chars().last().is_some_and(is_ident_char);
+
+        if !preceded_by_ident && starts_path(after) && !NOT_CRATES.contains(&ident) {
+            return Some(ident);
+        }
+
+        rest = after;
+    }
+
+    None
+}
+
+/// `regex.5f3a9c1e-cgu.0` names a codegen unit of `regex`.
+fn codegen_unit_crate(text: &str) -> Option<&str> {
+    let (crate_name, rest) = text.split_at(text.find('.')?);
+    let (hash, cgu) = rest[1..].split_at(rest[1..].find("-cgu.")?);
+
+    let valid = !crate_name.is_empty()
+        && crate_name.starts_with(is_ident_start)
+        && crate_name.chars().all(is_ident_char)
+        && !hash.is_empty()
+        && hash.chars().all(|c| c.is_ascii_alphanumeric())
+        && cgu.len() > 5
+        && cgu[5..].chars().all(|c| c.is_ascii_digit());
+
+    if valid {
+        Some(crate_name)
+    } else {
+        None
+    }
+}
+
+/// Whether an identifier followed by `after` is the first segment of a path,
+/// i.e. `after` is `::` or a crate disambiguator like `[a1b2]::`.
+fn starts_path(after: &str) -> bool {
+    if after.starts_with("::") {
+        return true;
+    }
+
+    match after.strip_prefix('[') {
+        Some(rest) => match rest.find(']') {
+            Some(end) => {
+                rest[..end].chars().all(|c| c.is_ascii_alphanumeric())
+                    && rest[end + 1..].starts_with("::")
+            }
+            None => false,
+        },
+        None => false,
+    }
+}
+
+fn is_ident_start(c: char) -> bool {
+    c.is_alphabetic() || c == '_'
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use analyzeme::ProfilingDataBuilder;
+
+    #[test]
+    fn crate_names() {
+        assert_eq!(crate_in("core[a1b2]::mem::replace"), Some("core"));
+        assert_eq!(crate_in("std::collections::HashMap"), Some("std"));
+        assert_eq!(
+            crate_in("<regex::Regex as core::clone::Clone>"),
+            Some("regex")
+        );
+        assert_eq!(
+            crate_in("DefId(0:12 ~ regex[a1b2]::compile)"),
+            Some("regex")
+        );
+        assert_eq!(crate_in("regex.5f3a9c1e-cgu.0"), Some("regex"));
+        assert_eq!(crate_in("regex_syntax.a1-cgu.12"), Some("regex_syntax"));
+        assert_eq!(crate_in("<u8 as serde::Serialize>"), Some("serde"));
+        assert_eq!(crate_in("crate::foo"), None);
+        assert_eq!(crate_in("typeck"), None);
+        assert_eq!(crate_in("LLVM_module_codegen_emit_obj"), None);
+        assert_eq!(crate_in("foo.rs"), None);
+    }
+
+    #[test]
+    fn attribution() {
+        let mut b = ProfilingDataBuilder::new();
+        // The query key names `regex`, the nested provider doesn't name a
+        // crate, so it belongs to `regex` too.
+        b.interval("Query", "type_of\x1eregex::Regex", 0, 0, 100, |b| {
+            b.interval("QueryProvider", "type_of", 0, 10, 50, |b| {
+                b.interval("Query", "type_of\x1ecore::mem::replace", 0, 20, 30, |_| {});
+            });
+        });
+        b.interval(
+            "GenericActivity",
+            "codegen_module\x1eserde.a1b2-cgu.0",
+            0,
+            100,
+            300,
+            |_| {},
+        );
+        b.interval("Query", "typeck", 0, 300, 310, |_| {});
+
+        let data = b.into_profiling_data();
+        let times = crate_times(&[("my_crate".to_string(), data)]);
+
+        let times: Vec<_> = times
+            .iter()
+            .map(|t| (&t.crate_name[..], t.self_time.as_nanos(), t.item_count))
+            .collect();
+        assert_eq!(
+            times,
+            vec![
+                ("serde", 200, 1),
+                ("regex", 90, 1),
+                ("core", 10, 1),
+                ("my_crate", 10, 0)
+            ]
+        );
+    }
+}
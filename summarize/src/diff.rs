@@ -1,9 +1,37 @@
+use crate::output::OutputFormat;
 use crate::query_data::{QueryData, QueryDataDiff, Results};
 use crate::signed_duration::SignedDuration;
 use rustc_hash::{FxHashMap, FxHashSet};
 use serde::{Deserialize, Serialize};
+use std::fmt::Write;
+use std::str::FromStr;
 use std::time::Duration;
 
+/// How the `diff` sub command prints its results, see `--format`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DiffFormat {
+    Table,
+    GithubMarkdown,
+}
+
+impl FromStr for DiffFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<DiffFormat, String> {
+        match s {
+            "table" => Ok(DiffFormat::Table),
+            "github-markdown" => Ok(DiffFormat::GithubMarkdown),
+            other => Err(format!(
+                "invalid diff format `{}`, expected `table` or `github-markdown`",
+                other
+            )),
+        }
+    }
+}
+
+/// The number of items the Markdown table lists at most.
+const MARKDOWN_ROWS: usize = 20;
+
 #[derive(Serialize, Deserialize)]
 pub struct DiffResults {
     pub query_data: Vec<QueryDataDiff>,
@@ -12,8 +40,8 @@ pub struct DiffResults {
 
 fn build_query_lookup(query_data: &[QueryData]) -> FxHashMap<&str, usize> {
     let mut lookup = FxHashMap::with_capacity_and_hasher(query_data.len(), Default::default());
-    for i in 0..query_data.len() {
-        lookup.insert(&query_data[i].label[..], i);
+    for (i, data) in query_data.iter().enumerate() {
+        lookup.insert(&data.label[..], i);
     }
 
     lookup
@@ -51,10 +79,205 @@ pub fn calculate_diff(base: Results, change: Results) -> DiffResults {
         })
         .collect();
 
-    query_data.sort_by(|l, r| r.self_time.duration.cmp(&l.self_time.duration));
+    query_data.sort_by(|a, b| {
+        b.self_time
+            .duration
+            .cmp(&a.self_time.duration)
+            .then_with(|| a.label.cmp(&b.label))
+    });
 
     DiffResults {
         query_data,
         total_time: sd(change.total_time) - sd(base.total_time),
     }
 }
+
+/// How much an item changed, by the relative change of its self time.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Severity {
+    /// At least 10% slower, or new.
+    Regression,
+    /// At least 2% slower.
+    MinorRegression,
+    /// At least 2% faster, or gone.
+    Improvement,
+    Neutral,
+}
+
+impl Severity {
+    fn of(query_data: &QueryDataDiff) -> Severity {
+        let change = query_data.self_time_change;
+        if change >= 10.0 {
+            Severity::Regression
+        } else if change >= 2.0 {
+            Severity::MinorRegression
+        } else if change <= -2.0 {
+            Severity::Improvement
+        } else {
+            Severity::Neutral
+        }
+    }
+
+    fn marker(self) -> &'static str {
+        match self {
+            Severity::Regression => "🔴",
+            Severity::MinorRegression => "🟠",
+            Severity::Improvement => "🟢",
+            Severity::Neutral => "⚪",
+        }
+    }
+}
+
+/// Renders the diff as Markdown for a pull request comment on GitHub: a
+/// one-line summary that expands into a table of the items whose self time
+/// changed the most, each with a marker for how much it changed.
+pub fn github_markdown(results: &DiffResults, exclude: &[String], format: &OutputFormat) -> String {
+    let items: Vec<&QueryDataDiff> = results
+        .query_data
+        .iter()
+        .filter(|q| !exclude.iter().any(|e| q.label.contains(e)))
+        .filter(|q| q.self_time.as_nanos() != 0)
+        .collect();
+
+    let count = |severities: &[Severity]| {
+        items
+            .iter()
+            .filter(|q| severities.contains(&Severity::of(q)))
+            .count()
+    };
+    let regressions = count(&[Severity::Regression, Severity::MinorRegression]);
+    let improvements = count(&[Severity::Improvement]);
+
+    let total_marker = if regressions > 0 {
+        Severity::Regression.marker()
+    } else if improvements > 0 {
+        Severity::Improvement.marker()
+    } else {
+        Severity::Neutral.marker()
+    };
+
+    let mut markdown = String::new();
+    markdown.push_str("<details>\n");
+    writeln!(
+        markdown,
+        "<summary>{} Total cpu time: {} ({} regressed, {} improved)</summary>\n",
+        total_marker,
+        format.signed_duration(results.total_time),
+        regressions,
+        improvements,
+    )
+    .unwrap();
+
+    if items.is_empty() {
+        markdown.push_str("No item changed.\n");
+    } else {
+        markdown.push_str("| | Item | Self time | Change | Time | Change | Item count |\n");
+        markdown.push_str("|---|---|---:|---:|---:|---:|---:|\n");
+
+        for q in items.iter().take(MARKDOWN_ROWS) {
+            writeln!(
+                markdown,
+                "| {} | {} | {} | {} | {} | {} | {} |",
+                Severity::of(q).marker(),
+                markdown_code(&q.label),
+                format.signed_duration(q.self_time),
+                percent_change(q.self_time_change),
+                format.signed_duration(q.time),
+                percent_change(q.time_change),
+                format.count(format!("{:+}", q.invocation_count)),
+            )
+            .unwrap();
+        }
+
+        if items.len() > MARKDOWN_ROWS {
+            writeln!(
+                markdown,
+                "\n{} more items changed.",
+                items.len() - MARKDOWN_ROWS
+            )
+            .unwrap();
+        }
+    }
+
+    markdown.push_str("\n</details>\n");
+    markdown
+}
+
+fn percent_change(change: f64) -> String {
+    if change.is_infinite() {
+        "new".to_string()
+    } else if change.is_nan() {
+        "+0.00%".to_string()
+    } else {
+        format!("{:+.2}%", change)
+    }
+}
+
+/// Formats `label` as inline code that is safe to put into a table cell.
+fn markdown_code(label: &str) -> String {
+    format!("`{}`", label.replace('`', "'").replace('|', "\\|"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::{ColorChoice, TimeUnit};
+
+    fn results(items: &[(&str, u64)]) -> Results {
+        let query_data: Vec<_> = items
+            .iter()
+            .map(|&(label, nanos)| QueryData {
+                self_time: Duration::from_nanos(nanos),
+                time: Duration::from_nanos(nanos),
+                invocation_count: 1,
+                ..QueryData::new(label.to_string())
+            })
+            .collect();
+
+        Results {
+            total_time: query_data.iter().map(|q| q.self_time).sum(),
+            query_data,
+            phases: Vec::new(),
+            session_segments: Vec::new(),
+            unclosed_threads: Vec::new(),
+            kinds: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn github_markdown_table() {
+        let base = results(&[("typeck", 1000), ("mir|borrowck", 1000), ("old", 100)]);
+        let change = results(&[
+            ("typeck", 1500),
+            ("mir|borrowck", 1010),
+            ("new", 200),
+            ("excluded", 5000),
+        ]);
+
+        let format = OutputFormat {
+            time_unit: TimeUnit::Nanos,
+            thousands_separator: None,
+            color: ColorChoice::Never,
+            width: None,
+            max_label_len: None,
+        };
+        let diff = calculate_diff(base, change);
+
+        assert_eq!(
+            github_markdown(&diff, &["excluded".to_string()], &format),
+            "\
+<details>
+<summary>🔴 Total cpu time: +5610ns (2 regressed, 1 improved)</summary>
+
+| | Item | Self time | Change | Time | Change | Item count |
+|---|---|---:|---:|---:|---:|---:|
+| 🔴 | `typeck` | +500ns | +50.00% | +500ns | +50.00% | +0 |
+| 🔴 | `new` | +200ns | new | +200ns | new | +1 |
+| 🟢 | `old` | -100ns | -100.00% | -100ns | -100.00% | -1 |
+| ⚪ | `mir\\|borrowck` | +10ns | +1.00% | +10ns | +1.00% | +0 |
+
+</details>
+"
+        );
+    }
+}
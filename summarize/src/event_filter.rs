@@ -0,0 +1,98 @@
+/// Decides which events make it into the summary, based on glob patterns
+/// that are matched against both the label and the event kind of an event.
+/// Patterns support `*` (any sequence of characters) and `?` (any single
+/// character).
+#[derive(Clone, Debug, Default)]
+pub struct EventFilter {
+    include: Vec<String>,
+    exclude: Vec<String>,
+}
+
+impl EventFilter {
+    /// An event passes the filter if it matches any of the `include`
+    /// patterns (or `include` is empty) and none of the `exclude` patterns.
+    pub fn new(include: Vec<String>, exclude: Vec<String>) -> EventFilter {
+        EventFilter { include, exclude }
+    }
+
+    pub fn matches(&self, label: &str, event_kind: &str) -> bool {
+        let matches_any = |patterns: &[String]| {
+            patterns
+                .iter()
+                .any(|pattern| glob_matches(pattern, label) || glob_matches(pattern, event_kind))
+        };
+
+        (self.include.is_empty() || matches_any(&self.include)) && !matches_any(&self.exclude)
+    }
+}
+
+pub(crate) fn glob_matches(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+
+    // The position after the last `*` seen in the pattern and the position in
+    // the text it was matched up to, so we can backtrack and let the `*`
+    // consume one more character.
+    let mut backtrack: Option<(usize, usize)> = None;
+    let (mut p, mut t) = (0, 0);
+
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                p += 1;
+                backtrack = Some((p, t));
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((star_p, star_t)) => {
+                    p = star_p;
+                    t = star_t + 1;
+                    backtrack = Some((star_p, star_t + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn globs() {
+        assert!(glob_matches("LLVM_*", "LLVM_emit_obj"));
+        assert!(glob_matches("LLVM_*", "LLVM_"));
+        assert!(!glob_matches("LLVM_*", "codegen LLVM_"));
+        assert!(glob_matches("*_of", "typeck_tables_of"));
+        assert!(glob_matches("*tables*", "typeck_tables_of"));
+        assert!(glob_matches("mir_?uilt", "mir_built"));
+        assert!(!glob_matches("mir_?uilt", "mir_uilt"));
+        assert!(glob_matches("a*b*c", "aXbYbZc"));
+        assert!(!glob_matches("a*b*c", "aXbYbZ"));
+        assert!(glob_matches("exact", "exact"));
+        assert!(!glob_matches("exact", "exactly"));
+    }
+
+    #[test]
+    fn include_and_exclude() {
+        let all = EventFilter::default();
+        assert!(all.matches("anything", "Query"));
+
+        let filter = EventFilter::new(
+            vec!["LLVM_*".to_string(), "Query".to_string()],
+            vec!["*_passes".to_string(), "QueryCacheHit".to_string()],
+        );
+
+        assert!(filter.matches("LLVM_emit_obj", "GenericActivity"));
+        assert!(!filter.matches("LLVM_module_passes", "GenericActivity"));
+        assert!(filter.matches("typeck_tables_of", "Query"));
+        assert!(!filter.matches("typeck_tables_of", "QueryCacheHit"));
+        assert!(!filter.matches("codegen", "GenericActivity"));
+    }
+}
@@ -0,0 +1,109 @@
+use crate::event_filter::EventFilter;
+use analyzeme::{find_flows, ProfilingData};
+use measureme::event_kinds::FLOW_START_EVENT_KIND;
+use rustc_hash::FxHashMap;
+use std::time::Duration;
+
+/// The distribution of the latencies of all flows with the same label, i.e.
+/// of the time between the start of a flow (e.g. a work item being enqueued)
+/// and its end (e.g. a worker picking the item up).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FlowLatencies {
+    pub label: String,
+    pub count: usize,
+    pub total: Duration,
+    pub min: Duration,
+    pub median: Duration,
+    pub p90: Duration,
+    pub max: Duration,
+}
+
+impl FlowLatencies {
+    fn new(label: String, mut latencies: Vec<Duration>) -> FlowLatencies {
+        latencies.sort();
+
+        // The nearest-rank percentile.
+        let percentile = |p: usize| latencies[(latencies.len() * p).div_ceil(100).max(1) - 1];
+
+        FlowLatencies {
+            label,
+            count: latencies.len(),
+            total: latencies.iter().sum(),
+            min: latencies[0],
+            median: percentile(50),
+            p90: percentile(90),
+            max: latencies[latencies.len() - 1],
+        }
+    }
+}
+
+/// Computes the latency distribution of the flows of every label that
+/// passes `filter`, ordered by total latency. Flows only count if both their
+/// start and their end are in the profile.
+pub fn flow_latencies(data: &ProfilingData, filter: &EventFilter) -> Vec<FlowLatencies> {
+    let mut latencies = FxHashMap::<String, Vec<Duration>>::default();
+
+    for flow in find_flows(data) {
+        if filter.matches(&flow.label, FLOW_START_EVENT_KIND) {
+            let latency = flow.latency();
+            latencies.entry(flow.label).or_default().push(latency);
+        }
+    }
+
+    let mut latencies: Vec<_> = latencies
+        .into_iter()
+        .map(|(label, latencies)| FlowLatencies::new(label, latencies))
+        .collect();
+    latencies.sort_by(|a, b| b.total.cmp(&a.total).then_with(|| a.label.cmp(&b.label)));
+    latencies
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use analyzeme::ProfilingDataBuilder;
+    use measureme::event_kinds::FLOW_END_EVENT_KIND;
+
+    #[test]
+    fn latencies_per_label() {
+        let mut b = ProfilingDataBuilder::new();
+
+        for i in 0..10 {
+            b.integer(FLOW_START_EVENT_KIND, "enqueue cgu", 0, i * 100, i);
+            b.integer(FLOW_END_EVENT_KIND, "codegen", 1, i * 100 + (i + 1) * 10, i);
+        }
+        b.integer(FLOW_START_EVENT_KIND, "spawn job", 0, 2000, 10);
+        b.integer(FLOW_END_EVENT_KIND, "job", 2, 2005, 10);
+
+        let data = b.into_profiling_data();
+        let latencies = flow_latencies(&data, &EventFilter::default());
+
+        let nanos = Duration::from_nanos;
+        assert_eq!(
+            latencies,
+            vec![
+                FlowLatencies {
+                    label: "enqueue cgu".to_string(),
+                    count: 10,
+                    total: nanos(550),
+                    min: nanos(10),
+                    median: nanos(50),
+                    p90: nanos(90),
+                    max: nanos(100),
+                },
+                FlowLatencies {
+                    label: "spawn job".to_string(),
+                    count: 1,
+                    total: nanos(5),
+                    min: nanos(5),
+                    median: nanos(5),
+                    p90: nanos(5),
+                    max: nanos(5),
+                },
+            ]
+        );
+
+        let filter = EventFilter::new(vec![], vec!["spawn*".to_string()]);
+        assert_eq!(flow_latencies(&data, &filter).len(), 1);
+    }
+}
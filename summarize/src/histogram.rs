@@ -0,0 +1,211 @@
+use crate::event_filter::EventFilter;
+use analyzeme::{ProfilingData, Timestamp};
+use rustc_hash::FxHashMap;
+use serde::{Deserialize, Serialize};
+use std::fmt::Write;
+use std::time::Duration;
+
+/// The durations of all intervals with the same label, counted in
+/// power-of-two buckets: bucket `i` holds the intervals that took at least
+/// `2^i` and less than `2^(i + 1)` nanoseconds (bucket 0 also holds those
+/// that took no time at all).
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct DurationHistogram {
+    pub label: String,
+    pub count: u64,
+    pub total_time: Duration,
+    pub min: Duration,
+    pub max: Duration,
+    /// The index of the first bucket in `buckets`. The buckets below it and
+    /// above the last one are empty.
+    pub first_bucket: u32,
+    pub buckets: Vec<u64>,
+}
+
+impl DurationHistogram {
+    fn new(label: String) -> DurationHistogram {
+        DurationHistogram {
+            label,
+            count: 0,
+            total_time: Duration::from_nanos(0),
+            min: Duration::from_nanos(u64::MAX),
+            max: Duration::from_nanos(0),
+            first_bucket: 0,
+            buckets: Vec::new(),
+        }
+    }
+
+    fn add(&mut self, duration: Duration) {
+        let bucket = bucket_index(duration);
+
+        if self.buckets.is_empty() {
+            self.first_bucket = bucket;
+        } else if bucket < self.first_bucket {
+            let missing = (self.first_bucket - bucket) as usize;
+            self.buckets.splice(0..0, std::iter::repeat_n(0, missing));
+            self.first_bucket = bucket;
+        }
+
+        let index = (bucket - self.first_bucket) as usize;
+        if index >= self.buckets.len() {
+            self.buckets.resize(index + 1, 0);
+        }
+        self.buckets[index] += 1;
+
+        self.count += 1;
+        self.total_time += duration;
+        self.min = self.min.min(duration);
+        self.max = self.max.max(duration);
+    }
+
+    /// The range of durations counted in the bucket at `index` in `buckets`.
+    pub fn bucket_range(&self, index: usize) -> (Duration, Duration) {
+        let bucket = self.first_bucket + index as u32;
+        let lower = if bucket == 0 { 0 } else { 1u64 << bucket };
+        let upper = 1u64.checked_shl(bucket + 1).unwrap_or(u64::MAX);
+        (Duration::from_nanos(lower), Duration::from_nanos(upper))
+    }
+}
+
+fn bucket_index(duration: Duration) -> u32 {
+    let nanos = duration.as_nanos().clamp(1, u64::MAX as u128) as u64;
+    63 - nanos.leading_zeros()
+}
+
+/// Computes a histogram of interval durations for every label that passes
+/// `filter`, ordered by total time. Instant events have no duration and
+/// aren't counted.
+pub fn duration_histograms(data: &ProfilingData, filter: &EventFilter) -> Vec<DurationHistogram> {
+    let mut histograms = FxHashMap::<String, DurationHistogram>::default();
+
+    for event in data.iter().map(|event| event.to_event()) {
+        let duration = match event.timestamp {
+            Timestamp::Interval { start, end } => end
+                .duration_since(start)
+                .unwrap_or_else(|_| Duration::from_nanos(0)),
+            Timestamp::Instant(_) => continue,
+        };
+
+        if !filter.matches(&event.label, &event.event_kind) {
+            continue;
+        }
+
+        histograms
+            .entry(event.label.to_string())
+            .or_insert_with(|| DurationHistogram::new(event.label.into_owned()))
+            .add(duration);
+    }
+
+    let mut histograms: Vec<_> = histograms.into_values().collect();
+    histograms.sort_by(|a, b| {
+        b.total_time
+            .cmp(&a.total_time)
+            .then_with(|| a.label.cmp(&b.label))
+    });
+    histograms
+}
+
+/// Renders the histograms as a self-contained HTML page with one bar chart
+/// per label.
+pub fn histograms_html(title: &str, histograms: &[DurationHistogram]) -> String {
+    let mut html = String::new();
+
+    html.push_str("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n");
+    writeln!(html, "<title>{}</title>", escape(title)).unwrap();
+    html.push_str(
+        "<style>\n\
+         body { font-family: sans-serif; margin: 2em; }\n\
+         table { border-collapse: collapse; margin-bottom: 2em; }\n\
+         td { padding: 0.1em 0.5em; font-family: monospace; white-space: nowrap; }\n\
+         td.range { text-align: right; }\n\
+         div.bar { background: #4a7ebb; height: 0.9em; }\n\
+         </style>\n</head>\n<body>\n",
+    );
+    writeln!(html, "<h1>{}</h1>", escape(title)).unwrap();
+
+    for histogram in histograms {
+        writeln!(
+            html,
+            "<h2>{}</h2>\n<p>{} intervals, total {:.2?}, min {:.2?}, max {:.2?}</p>\n<table>",
+            escape(&histogram.label),
+            histogram.count,
+            histogram.total_time,
+            histogram.min,
+            histogram.max,
+        )
+        .unwrap();
+
+        let largest = histogram.buckets.iter().copied().max().unwrap_or(0).max(1);
+
+        for (index, &count) in histogram.buckets.iter().enumerate() {
+            let (lower, upper) = histogram.bucket_range(index);
+            writeln!(
+                html,
+                "<tr><td class=\"range\">{:.2?} &ndash; {:.2?}</td><td class=\"range\">{}</td>\
+                 <td><div class=\"bar\" style=\"width: {}px\"></div></td></tr>",
+                lower,
+                upper,
+                count,
+                count * 400 / largest,
+            )
+            .unwrap();
+        }
+
+        html.push_str("</table>\n");
+    }
+
+    html.push_str("</body>\n</html>\n");
+    html
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use analyzeme::ProfilingDataBuilder;
+
+    #[test]
+    fn buckets() {
+        let mut b = ProfilingDataBuilder::new();
+
+        b.interval("Query", "typeck", 0, 0, 3, |_| {});
+        b.interval("Query", "typeck", 0, 10, 13, |_| {});
+        b.interval("Query", "typeck", 0, 20, 1020, |_| {});
+        b.interval("Query", "mir_borrowck", 0, 2000, 2001, |_| {});
+        b.instant("QueryCacheHit", "typeck", 0, 3000);
+
+        let data = b.into_profiling_data();
+        let histograms = duration_histograms(&data, &EventFilter::default());
+
+        assert_eq!(histograms.len(), 2);
+
+        let typeck = &histograms[0];
+        assert_eq!(typeck.label, "typeck");
+        assert_eq!(typeck.count, 3);
+        assert_eq!(typeck.total_time, Duration::from_nanos(1006));
+        assert_eq!(typeck.min, Duration::from_nanos(3));
+        assert_eq!(typeck.max, Duration::from_nanos(1000));
+        // 3ns is in [2, 4), 1000ns in [512, 1024).
+        assert_eq!(typeck.first_bucket, 1);
+        assert_eq!(typeck.buckets, vec![2, 0, 0, 0, 0, 0, 0, 0, 1]);
+        assert_eq!(
+            typeck.bucket_range(8),
+            (Duration::from_nanos(512), Duration::from_nanos(1024))
+        );
+
+        let mir_borrowck = &histograms[1];
+        assert_eq!(mir_borrowck.first_bucket, 0);
+        assert_eq!(mir_borrowck.buckets, vec![1]);
+        assert_eq!(mir_borrowck.bucket_range(0).0, Duration::from_nanos(0));
+
+        let html = histograms_html("<test>", &histograms);
+        assert!(html.contains("<h1>&lt;test&gt;</h1>"));
+        assert!(html.contains("<h2>mir_borrowck</h2>"));
+    }
+}
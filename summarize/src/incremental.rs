@@ -0,0 +1,205 @@
+use analyzeme::{ProfilingData, Timestamp};
+use measureme::rustc::*;
+use rustc_hash::FxHashMap;
+use serde::{Deserialize, Serialize};
+use std::time::SystemTime;
+
+/// How much work a single build did for one query.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct QueryCounts {
+    /// The number of times the query provider had to run.
+    pub executed: u64,
+    /// The number of times the result was loaded from the incremental cache.
+    pub loaded: u64,
+}
+
+impl QueryCounts {
+    /// The fraction of results that did not have to be recomputed, or `None`
+    /// if the query wasn't needed at all.
+    pub fn efficiency(&self) -> Option<f64> {
+        let total = self.executed + self.loaded;
+
+        if total == 0 {
+            None
+        } else {
+            Some(self.loaded as f64 / total as f64)
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct BuildSummary {
+    pub profile: String,
+    pub start_time: SystemTime,
+    pub totals: QueryCounts,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct QueryHistory {
+    pub label: String,
+    /// One entry per build, in the same order as `IncrementalReport::builds`.
+    pub builds: Vec<QueryCounts>,
+}
+
+impl QueryHistory {
+    /// The number of executions in all but the first build, which usually
+    /// starts from an empty cache.
+    pub fn re_executions(&self) -> u64 {
+        self.builds
+            .iter()
+            .skip(1)
+            .map(|counts| counts.executed)
+            .sum()
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct IncrementalReport {
+    pub builds: Vec<BuildSummary>,
+    pub queries: Vec<QueryHistory>,
+}
+
+/// Counts the query executions and incremental cache loads of a single build.
+pub fn count_queries(data: &ProfilingData) -> FxHashMap<String, QueryCounts> {
+    let mut counts = FxHashMap::<String, QueryCounts>::default();
+
+    for event in data.iter().map(|event| event.to_event()) {
+        if let Timestamp::Instant(_) = event.timestamp {
+            continue;
+        }
+
+        match &event.event_kind[..] {
+            QUERY_EVENT_KIND => {
+                counts.entry(event.label.into_owned()).or_default().executed += 1;
+            }
+            INCREMENTAL_LOAD_RESULT_EVENT_KIND => {
+                counts.entry(event.label.into_owned()).or_default().loaded += 1;
+            }
+            _ => {}
+        }
+    }
+
+    counts
+}
+
+/// Builds the report for the given builds of a crate, which are expected to
+/// be ordered from oldest to newest. Queries are sorted by the number of
+/// re-executions, descending.
+pub fn incremental_report(builds: &[(String, ProfilingData)]) -> IncrementalReport {
+    let mut summaries = Vec::with_capacity(builds.len());
+    let mut histories = FxHashMap::<String, Vec<QueryCounts>>::default();
+
+    for (index, (profile, data)) in builds.iter().enumerate() {
+        let mut totals = QueryCounts::default();
+
+        for (label, counts) in count_queries(data) {
+            totals.executed += counts.executed;
+            totals.loaded += counts.loaded;
+
+            let history = histories
+                .entry(label)
+                .or_insert_with(|| vec![QueryCounts::default(); builds.len()]);
+            history[index] = counts;
+        }
+
+        summaries.push(BuildSummary {
+            profile: profile.clone(),
+            start_time: data.metadata.start_time,
+            totals,
+        });
+    }
+
+    let mut queries: Vec<_> = histories
+        .into_iter()
+        .map(|(label, builds)| QueryHistory { label, builds })
+        .collect();
+
+    queries.sort_by(|a, b| {
+        b.re_executions()
+            .cmp(&a.re_executions())
+            .then_with(|| a.label.cmp(&b.label))
+    });
+
+    IncrementalReport {
+        builds: summaries,
+        queries,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use analyzeme::ProfilingDataBuilder;
+
+    fn build(executed: &[&str], loaded: &[&str]) -> ProfilingData {
+        let mut b = ProfilingDataBuilder::new();
+        let mut time = 0;
+
+        for label in executed {
+            b.interval(QUERY_EVENT_KIND, label, 0, time, time + 10, |_| {});
+            time += 10;
+        }
+
+        for label in loaded {
+            b.interval(
+                INCREMENTAL_LOAD_RESULT_EVENT_KIND,
+                label,
+                0,
+                time,
+                time + 10,
+                |_| {},
+            );
+            time += 10;
+        }
+
+        b.instant(QUERY_CACHE_HIT_EVENT_KIND, "typeck", 0, time);
+
+        b.into_profiling_data()
+    }
+
+    #[test]
+    fn counts_executions_and_loads_across_builds() {
+        let builds = vec![
+            (
+                "first".to_string(),
+                build(&["typeck", "typeck", "mir_borrowck"], &[]),
+            ),
+            ("second".to_string(), build(&["typeck"], &["mir_borrowck"])),
+            ("third".to_string(), build(&[], &["typeck", "mir_borrowck"])),
+        ];
+
+        let report = incremental_report(&builds);
+
+        let totals: Vec<_> = report.builds.iter().map(|b| b.totals).collect();
+        assert_eq!(
+            totals,
+            vec![
+                QueryCounts {
+                    executed: 3,
+                    loaded: 0
+                },
+                QueryCounts {
+                    executed: 1,
+                    loaded: 1
+                },
+                QueryCounts {
+                    executed: 0,
+                    loaded: 2
+                },
+            ]
+        );
+        assert_eq!(report.builds[2].totals.efficiency(), Some(1.0));
+
+        let labels: Vec<_> = report.queries.iter().map(|q| &q.label[..]).collect();
+        assert_eq!(labels, vec!["typeck", "mir_borrowck"]);
+        assert_eq!(
+            report.queries[0].builds[0],
+            QueryCounts {
+                executed: 2,
+                loaded: 0
+            }
+        );
+        assert_eq!(report.queries[0].re_executions(), 1);
+        assert_eq!(report.queries[1].re_executions(), 0);
+    }
+}
@@ -0,0 +1,1295 @@
+use analysis::SelfTimePolicy;
+use analyzeme::cli::OutputOpts;
+use analyzeme::{
+    filter_self_profile_events, find_gaps, find_stalls, Diagnostic, Gap, LabelFormatter,
+    LabelRules, LightweightEvent, MessageFormat, Overhead, ProfileSummary, ProfilingData,
+    RustcLabelFormatter, SelfProfileEvents, Stall, SystemInfo, ToolInfo, Validation,
+};
+use diff::DiffFormat;
+use event_filter::EventFilter;
+use flows::FlowLatencies;
+use std::error::Error;
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use serde::Serialize;
+use structopt::StructOpt;
+
+mod analysis;
+mod crates;
+mod diff;
+mod event_filter;
+mod flows;
+mod histogram;
+mod incremental;
+mod metrics;
+mod output;
+mod percentiles;
+mod progress;
+mod query_data;
+mod signed_duration;
+mod template;
+
+use output::{ColorChoice, OutputFormat, TimeUnit};
+use progress::ProgressReporter;
+use query_data::{KindData, Results, SegmentResults, SortBy, SortKey, UnclosedThread};
+
+#[derive(StructOpt, Debug)]
+struct DiffOpt {
+    base: PathBuf,
+    change: PathBuf,
+
+    #[structopt(short = "e", long = "exclude")]
+    exclude: Vec<String>,
+
+    #[structopt(long = "json")]
+    json: bool,
+
+    /// `table`, or `github-markdown` for a collapsed table to post as a
+    /// comment on a pull request
+    #[structopt(long = "format", default_value = "table")]
+    format: DiffFormat,
+}
+
+#[derive(StructOpt, Debug)]
+struct SummarizeOpt {
+    file_prefix: PathBuf,
+
+    /// Writes the analysis to a json file next to <file_prefix> instead of stdout
+    #[structopt(long = "json")]
+    json: bool,
+
+    /// Filter the output to items whose self-time is greater than this value
+    #[structopt(short = "pa", long = "percent-above", default_value = "0.0")]
+    percent_above: f64,
+
+    /// Report stretches longer than this (in microseconds) during which a
+    /// thread did not record any events
+    #[structopt(long = "stall-threshold")]
+    stall_threshold: Option<u64>,
+
+    /// Report the <n> longest gaps during which no thread recorded any events
+    #[structopt(long = "gaps")]
+    gaps: Option<usize>,
+
+    /// Only include events whose label or event kind matches this glob
+    /// pattern (e.g. `LLVM_*`). Can be given multiple times.
+    #[structopt(long = "filter", number_of_values = 1)]
+    filter: Vec<String>,
+
+    /// Exclude events whose label or event kind matches this glob pattern
+    /// (e.g. `QueryCacheHit`). Can be given multiple times.
+    #[structopt(long = "exclude", number_of_values = 1)]
+    exclude: Vec<String>,
+
+    /// Count the time of nested events of the kinds matching this glob
+    /// pattern (e.g. `IncrementalResultHashing`) towards the self time of
+    /// the event they are nested in instead of reporting them separately.
+    /// Can be given multiple times.
+    #[structopt(long = "attribute-to-parent", number_of_values = 1)]
+    attribute_to_parent: Vec<String>,
+
+    /// Demangle rustc symbols, strip crate hashes and shorten paths in
+    /// labels. Filters still apply to the original labels.
+    #[structopt(long = "pretty-labels")]
+    pretty_labels: bool,
+
+    /// Rewrite labels with the rules in this file, one `<regex> =>
+    /// <replacement>` per line, e.g. to group all `codegen_module(...)`
+    /// events under one label. Filters apply to the rewritten labels.
+    #[structopt(long = "label-rules", parse(from_os_str))]
+    label_rules: Option<PathBuf>,
+
+    /// Only keep the events rustc would have recorded with this value for
+    /// `-Z self-profile-events` (e.g. `default` or `query-provider,args`)
+    #[structopt(long = "self-profile-events")]
+    self_profile_events: Option<String>,
+
+    /// Order the items by `self-time` (the default), `total-time`, `count`
+    /// or `label`. Items with the same value are ordered by label.
+    #[structopt(long = "sort-by", default_value = "self-time")]
+    sort_by: SortBy,
+
+    /// Render the summary with this template, e.g. for a Markdown report,
+    /// instead of printing tables. See "Report templates" in the Readme.
+    #[structopt(long = "template")]
+    template: Option<PathBuf>,
+
+    /// Check the events of the profile before summarizing it: `off`,
+    /// `lenient` to skip invalid events with a warning, or `strict` to fail
+    /// on the first one
+    #[structopt(long = "validate", default_value = "off")]
+    validate: Validation,
+
+    /// Don't show the progress on stderr, which is shown by default if
+    /// stderr is a terminal
+    #[structopt(long = "no-progress")]
+    no_progress: bool,
+
+    /// Print the items with the largest self time so far to stderr every
+    /// <seconds> while summarizing. These are provisional: items whose
+    /// nested events haven't been processed yet show too much self time.
+    #[structopt(long = "provisional-results")]
+    provisional_results: Option<u64>,
+}
+
+#[derive(StructOpt, Debug)]
+struct IncrementalOpt {
+    /// A directory containing the profiles of successive incremental builds
+    dir: PathBuf,
+
+    /// The crate to report on, if the directory contains profiles of more
+    /// than one crate
+    #[structopt(long = "crate")]
+    crate_name: Option<String>,
+
+    /// The number of queries to list, ordered by how often they were
+    /// re-executed
+    #[structopt(long = "top", default_value = "20")]
+    top: usize,
+
+    /// Writes the report to `incremental.json` in <dir> instead of stdout
+    #[structopt(long = "json")]
+    json: bool,
+}
+
+#[derive(StructOpt, Debug)]
+struct HistogramOpt {
+    file_prefix: PathBuf,
+
+    /// Writes an HTML page with a bar chart per label instead of JSON
+    #[structopt(long = "html")]
+    html: bool,
+
+    /// Only include events whose label or event kind matches this glob
+    /// pattern. Can be given multiple times.
+    #[structopt(long = "filter", number_of_values = 1)]
+    filter: Vec<String>,
+
+    /// Exclude events whose label or event kind matches this glob pattern.
+    /// Can be given multiple times.
+    #[structopt(long = "exclude", number_of_values = 1)]
+    exclude: Vec<String>,
+
+    /// The number of labels to include, ordered by total time
+    #[structopt(long = "top")]
+    top: Option<usize>,
+}
+
+#[derive(StructOpt, Debug)]
+struct CratesOpt {
+    /// The profiles of a build, e.g. of every crate in it
+    #[structopt(raw(required = "true", min_values = "1"))]
+    file_prefixes: Vec<PathBuf>,
+
+    /// The number of crates to list, ordered by time
+    #[structopt(long = "top")]
+    top: Option<usize>,
+
+    /// Writes the report to `<first file_prefix>.crates.json` instead of
+    /// stdout
+    #[structopt(long = "json")]
+    json: bool,
+}
+
+#[derive(StructOpt, Debug)]
+struct PercentilesOpt {
+    /// The profiles to pool the durations of, e.g. of every crate in a
+    /// workspace
+    #[structopt(raw(required = "true", min_values = "1"))]
+    file_prefixes: Vec<PathBuf>,
+
+    /// Only include events whose label or event kind matches this glob
+    /// pattern (e.g. `metadata_decode_entry*`). Can be given multiple times.
+    #[structopt(long = "filter", number_of_values = 1)]
+    filter: Vec<String>,
+
+    /// Exclude events whose label or event kind matches this glob pattern.
+    /// Can be given multiple times.
+    #[structopt(long = "exclude", number_of_values = 1)]
+    exclude: Vec<String>,
+
+    /// Compute the percentiles of the total time of each label per profile
+    /// instead of those of the durations of its intervals
+    #[structopt(long = "per-profile")]
+    per_profile: bool,
+
+    /// The number of labels to list, ordered by total time
+    #[structopt(long = "top")]
+    top: Option<usize>,
+
+    /// Writes the report to `<first file_prefix>.percentiles.json` instead
+    /// of stdout
+    #[structopt(long = "json")]
+    json: bool,
+}
+
+#[derive(StructOpt, Debug)]
+struct MetricsOpt {
+    /// A profile, or the results of `summarize --json`
+    file_prefix: PathBuf,
+
+    /// Tag the metrics with this crate. Defaults to the crate the profile
+    /// was recorded for.
+    #[structopt(long = "crate")]
+    crate_name: Option<String>,
+
+    /// Tag the metrics with this commit
+    #[structopt(long = "commit")]
+    commit: Option<String>,
+
+    /// Tag the metrics with this label, given as `key=value`. Can be given
+    /// multiple times.
+    #[structopt(
+        long = "tag",
+        number_of_values = 1,
+        parse(try_from_str = "metrics::parse_tag")
+    )]
+    tags: Vec<(String, String)>,
+
+    /// Only export the <n> labels with the most self time on their own and
+    /// add up the rest as `label="(other)"`
+    #[structopt(long = "top")]
+    top: Option<usize>,
+
+    /// Writes the metrics to this file instead of stdout, e.g. a `.prom`
+    /// file in the directory of the node exporter's textfile collector. The
+    /// file is replaced atomically.
+    #[structopt(long = "output", parse(from_os_str))]
+    output: Option<PathBuf>,
+
+    /// Pushes the metrics to the Prometheus Pushgateway at this URL, grouped
+    /// by the job `measureme` and the crate. Needs the `http` feature.
+    #[structopt(long = "push")]
+    push: Option<String>,
+}
+
+#[derive(StructOpt, Debug)]
+enum Opt {
+    #[structopt(name = "diff")]
+    Diff(DiffOpt),
+
+    /// Processes trace files and produces a summary
+    #[structopt(name = "summarize")]
+    Summarize(SummarizeOpt),
+
+    /// Reports how effective incremental compilation was across the profiles
+    /// of successive builds of a crate
+    #[structopt(name = "incremental")]
+    Incremental(IncrementalOpt),
+
+    /// Writes log-scaled histograms of the interval durations of each label
+    /// to `<file_prefix>.histograms.json` (or `.html`)
+    #[structopt(name = "histogram")]
+    Histogram(HistogramOpt),
+
+    /// Reports the time spent on each crate, e.g. on the dependencies of the
+    /// crates that were compiled
+    #[structopt(name = "crates")]
+    Crates(CratesOpt),
+
+    /// Reports the percentiles of the durations of each label across many
+    /// profiles, e.g. of all crates of a workspace
+    #[structopt(name = "percentiles")]
+    Percentiles(PercentilesOpt),
+
+    /// Exports the self time and count of each label as Prometheus metrics,
+    /// tagged with the crate and commit
+    #[structopt(name = "metrics")]
+    Metrics(MetricsOpt),
+}
+
+#[derive(StructOpt, Debug)]
+struct Cli {
+    #[structopt(flatten)]
+    output: OutputOpts,
+
+    /// The unit for times in tables: `auto` (picked per value), `ns`, `us`,
+    /// `ms` or `s`
+    #[structopt(long = "time-unit", default_value = "auto", raw(global = "true"))]
+    time_unit: TimeUnit,
+
+    /// Group the digits of numbers in tables with this separator (e.g. `,`)
+    #[structopt(long = "thousands-separator", raw(global = "true"))]
+    thousands_separator: Option<String>,
+
+    /// When to use colors in tables: `auto` (if stdout is a terminal),
+    /// `always` or `never`
+    #[structopt(long = "color", default_value = "auto", raw(global = "true"))]
+    color: ColorChoice,
+
+    /// Same as `--color never`
+    #[structopt(long = "no-color", raw(global = "true"))]
+    no_color: bool,
+
+    /// Fit tables into this many columns by shortening labels. Defaults to
+    /// the width of the terminal if stdout is one.
+    #[structopt(long = "width", raw(global = "true"))]
+    width: Option<usize>,
+
+    /// Shorten labels longer than this many characters, leaving an id at
+    /// their end that `cargo mm label <id>` expands. `0` keeps labels whole.
+    #[structopt(long = "max-label-len", default_value = "120", raw(global = "true"))]
+    max_label_len: usize,
+
+    /// Profile summarize itself, writing the profile to <path_stem>
+    #[structopt(long = "self-profile", parse(from_os_str), raw(global = "true"))]
+    self_profile: Option<PathBuf>,
+
+    #[structopt(subcommand)]
+    command: Opt,
+}
+
+fn process_results(file: &Path) -> Result<Results, Box<dyn Error>> {
+    if file.ends_with("json") {
+        let reader = BufReader::new(File::open(file)?);
+
+        let results: Results = serde_json::from_reader(reader)?;
+        Ok(results)
+    } else {
+        let data = ProfilingData::new(file)?;
+
+        Ok(analysis::perform_analysis(data))
+    }
+}
+
+fn render_template(
+    template: &Path,
+    file_prefix: &Path,
+    results: &Results,
+    format: &OutputFormat,
+) -> Result<(), Box<dyn Error>> {
+    let source = std::fs::read_to_string(template)
+        .map_err(|e| format!("couldn't read template `{}`: {}", template.display(), e))?;
+    let template = template::Template::parse(&source)?;
+
+    // The same data as `--json`, plus the profile the report is about.
+    let mut data = serde_json::to_value(results)?;
+    data["profile"] = file_prefix.display().to_string().into();
+
+    print!("{}", template.render(&data, format)?);
+    Ok(())
+}
+
+fn write_results_json(file: &Path, results: impl Serialize) -> Result<(), Box<dyn Error>> {
+    let file = BufWriter::new(File::create(file.with_extension("json"))?);
+    serde_json::to_writer(file, &results)?;
+    Ok(())
+}
+
+fn diff(opt: DiffOpt, format: &OutputFormat) -> Result<(), Box<dyn Error>> {
+    let base = process_results(&opt.base)?;
+    let change = process_results(&opt.change)?;
+
+    let results = diff::calculate_diff(base, change);
+
+    if opt.json {
+        write_results_json(&opt.change, results)?;
+        return Ok(());
+    }
+
+    if opt.format == DiffFormat::GithubMarkdown {
+        print!("{}", diff::github_markdown(&results, &opt.exclude, format));
+        return Ok(());
+    }
+
+    let mut rows = Vec::new();
+
+    for query_data in results.query_data {
+        let exclude = opt.exclude.iter().any(|e| query_data.label.contains(e));
+        if exclude {
+            continue;
+        }
+
+        rows.push(vec![
+            query_data.label,
+            format.signed_duration(query_data.self_time),
+            format!("{:+.2}%", query_data.self_time_change),
+            format.signed_duration(query_data.time),
+            format!("{:+.2}%", query_data.time_change),
+            format.count(format!("{:+}", query_data.invocation_count)),
+            format.count(format!("{:+}", query_data.number_of_cache_hits)),
+            format.signed_duration(query_data.blocked_time),
+            format.signed_duration(query_data.incremental_load_time),
+        ]);
+    }
+
+    format.print_table(
+        &[
+            "Item",
+            "Self Time",
+            "Self Time Change",
+            "Time",
+            "Time Change",
+            "Item count",
+            "Cache hits",
+            "Blocked time",
+            "Incremental load time",
+        ],
+        rows,
+        &[1, 2, 3, 4],
+    );
+
+    println!(
+        "Total cpu time: {}",
+        format.signed_duration(results.total_time)
+    );
+
+    Ok(())
+}
+
+fn summarize(
+    opt: SummarizeOpt,
+    message_format: MessageFormat,
+    format: &OutputFormat,
+) -> Result<(), Box<dyn Error>> {
+    let mut progress = ProgressReporter::new(
+        !opt.no_progress,
+        opt.provisional_results.map(Duration::from_secs),
+        format,
+    );
+    progress.status(&format!("Loading {}...", opt.file_prefix.display()));
+    let loaded = ProfilingData::new_validated(&opt.file_prefix, opt.validate);
+    progress.finish();
+    let mut data = loaded?;
+
+    for warning in data.warnings() {
+        warning.emit(message_format);
+    }
+
+    if let Some(spec) = &opt.self_profile_events {
+        data = filter_self_profile_events(&data, SelfProfileEvents::parse(spec)?);
+    }
+
+    if let Some(path) = &opt.label_rules {
+        data.set_label_rules(LabelRules::load(path)?);
+    }
+
+    if !opt.json {
+        print_header(data.metadata.tool.as_ref(), data.metadata.system.as_ref());
+    }
+
+    if data.metadata.truncated {
+        Diagnostic::warning(
+            "profile-truncated",
+            "the profile is truncated because the profiler failed to write it.",
+        )
+        .emit(message_format);
+    }
+
+    if data.metadata.dropped_events > 0 {
+        Diagnostic::warning(
+            "events-dropped",
+            format!(
+                "the profiler dropped {} events because its buffer was full or their timestamps were out of range.",
+                data.metadata.dropped_events
+            ),
+        )
+        .emit(message_format);
+    }
+
+    if data.metadata.budget_exhausted {
+        Diagnostic::warning(
+            "budget-exhausted",
+            "the profile is incomplete because it exceeded the profiler's recording budget.",
+        )
+        .emit(message_format);
+    }
+
+    if data.metadata.aggregate_only {
+        let filter = EventFilter::new(opt.filter.clone(), opt.exclude.clone());
+        return summarize_aggregate_only(&opt, &filter, format);
+    }
+
+    let stalls = opt
+        .stall_threshold
+        .map(|threshold| find_stalls(&data, Duration::from_micros(threshold)));
+    let start_time = data.metadata.start_time;
+    // The gaps borrow the profile, which the analysis consumes, so they are
+    // formatted right away.
+    let gaps = opt
+        .gaps
+        .map(|count| gap_rows(&find_gaps(&data, count), start_time, format));
+    let overhead = data.metadata.overhead.clone();
+
+    let filter = EventFilter::new(opt.filter, opt.exclude);
+    let flow_latencies = flows::flow_latencies(&data, &filter);
+    let policy = SelfTimePolicy::new(opt.attribute_to_parent);
+    let mut results =
+        analysis::perform_analysis_with_progress(data, &filter, &policy, &mut |current| {
+            progress.report(current)
+        });
+    progress.finish();
+    results.sort(opt.sort_by);
+
+    if let Some(template) = &opt.template {
+        return render_template(template, &opt.file_prefix, &results, format);
+    }
+
+    //just output the results into a json file
+    if opt.json {
+        write_results_json(&opt.file_prefix, &results)?;
+        return Ok(());
+    }
+
+    let percent_above = opt.percent_above;
+    //cannot be greater than 100% or less than 0%
+    if percent_above > 100.0 {
+        eprintln!("Percentage of total time cannot be more than 100.0");
+        std::process::exit(1);
+    } else if percent_above < 0.0 {
+        eprintln!("Percentage of total time cannot be less than 0.0");
+        std::process::exit(1);
+    }
+
+    let phases = std::mem::take(&mut results.phases);
+    let session_segments = std::mem::take(&mut results.session_segments);
+    let unclosed_threads = std::mem::take(&mut results.unclosed_threads);
+    let kinds = std::mem::take(&mut results.kinds);
+
+    let pretty_labels = opt.pretty_labels;
+
+    if !kinds.is_empty() {
+        print_kinds(&kinds, format);
+        println!();
+    }
+    print_results(results, percent_above, pretty_labels, format);
+
+    if let Some(overhead) = overhead {
+        print_overhead(&overhead, format);
+    }
+
+    for phase in phases {
+        println!();
+        println!(
+            "Phase `{}` ({}):",
+            phase.name,
+            format.duration(phase.duration)
+        );
+        print_results(phase.results, percent_above, pretty_labels, format);
+    }
+
+    if !session_segments.is_empty() {
+        println!();
+        print_session_segments(&session_segments, format);
+    }
+
+    for segment in session_segments {
+        println!();
+        println!(
+            "Segment `{}` ({}, {:.1}% of wall time):",
+            segment.name,
+            format.duration(segment.duration),
+            segment.percent_of_wall_time
+        );
+        print_results(segment.results, percent_above, pretty_labels, format);
+    }
+
+    if !unclosed_threads.is_empty() {
+        println!();
+        print_unclosed_threads(&unclosed_threads, format);
+    }
+
+    if !flow_latencies.is_empty() {
+        println!();
+        print_flow_latencies(&flow_latencies, format);
+    }
+
+    if let Some(stalls) = stalls {
+        print_stalls(&stalls, start_time, format);
+    }
+
+    if let Some(gaps) = gaps {
+        print_gaps(gaps, format);
+    }
+
+    Ok(())
+}
+
+/// A row of the summary of a profile recorded with
+/// `measureme::RecordingMode::AggregateOnly`.
+#[derive(Serialize)]
+struct AggregatedLabel {
+    label: String,
+    event_kind: String,
+    count: u64,
+    total_time: Duration,
+    max_time: Duration,
+}
+
+impl AggregatedLabel {
+    // There is no self time, so the total time takes its place.
+    fn sort_key(&self) -> SortKey<'_> {
+        SortKey {
+            label: &self.label,
+            self_time: self.total_time,
+            time: self.total_time,
+            count: self.count,
+        }
+    }
+}
+
+// Aggregate-only profiles don't contain any events, so the only thing to
+// show are the totals the profiler has kept while recording.
+fn summarize_aggregate_only(
+    opt: &SummarizeOpt,
+    filter: &EventFilter,
+    format: &OutputFormat,
+) -> Result<(), Box<dyn Error>> {
+    let summary = ProfileSummary::new(&opt.file_prefix)?;
+
+    let mut labels: Vec<AggregatedLabel> = summary
+        .labels
+        .into_iter()
+        .filter(|l| filter.matches(&l.label, &l.event_kind))
+        .map(|l| AggregatedLabel {
+            label: l.label,
+            event_kind: l.event_kind,
+            count: l.count,
+            total_time: l.total_time,
+            max_time: l.max_time,
+        })
+        .collect();
+
+    labels.sort_by(|a, b| {
+        opt.sort_by
+            .compare(&a.sort_key(), &b.sort_key())
+            .then_with(|| a.event_kind.cmp(&b.event_kind))
+    });
+
+    if opt.json {
+        return write_results_json(&opt.file_prefix, &labels);
+    }
+
+    println!("The profile has been recorded in aggregate-only mode, so it only contains totals per label.");
+    println!();
+
+    let rows = labels
+        .into_iter()
+        .map(|label| {
+            vec![
+                if opt.pretty_labels {
+                    RustcLabelFormatter.format(&label.label).into_owned()
+                } else {
+                    label.label
+                },
+                label.event_kind,
+                format.duration(label.total_time),
+                format.count(label.count),
+                format.duration(label.max_time),
+            ]
+        })
+        .collect();
+
+    format.print_table(
+        &["Item", "Event kind", "Total time", "Item count", "Max time"],
+        rows,
+        &[],
+    );
+
+    Ok(())
+}
+
+// Expects the results to be sorted already, see `Results::sort()`.
+fn print_results(results: Results, percent_above: f64, pretty_labels: bool, format: &OutputFormat) {
+    measureme::activity!("print_results");
+    let mut rows = Vec::new();
+
+    let total_time = results.total_time.as_nanos() as f64;
+    let mut percent_total_time: f64 = 0.0;
+
+    for query_data in results.query_data {
+        let curr_percent = (query_data.self_time.as_nanos() as f64) / total_time * 100.0;
+        if curr_percent < percent_above {
+            continue;
+        }
+
+        percent_total_time += curr_percent;
+
+        rows.push(vec![
+            if pretty_labels {
+                RustcLabelFormatter.format(&query_data.label).into_owned()
+            } else {
+                query_data.label
+            },
+            format.duration(query_data.self_time),
+            format!("{:.3}", curr_percent),
+            format.duration(query_data.time),
+            format.count(query_data.invocation_count),
+            format.count(query_data.number_of_cache_hits),
+            format.duration(query_data.blocked_time),
+            format.duration(query_data.incremental_load_time),
+        ]);
+    }
+
+    format.print_table(
+        &[
+            "Item",
+            "Self time",
+            "% of total time",
+            "Time",
+            "Item count",
+            "Cache hits",
+            "Blocked time",
+            "Incremental load time",
+        ],
+        rows,
+        &[],
+    );
+
+    println!("Total cpu time: {}", format.duration(results.total_time));
+
+    if percent_above != 0.0 {
+        println!(
+            "Filtered results account for {:.3}% of total time.",
+            percent_total_time
+        );
+    }
+}
+
+fn print_kinds(kinds: &[KindData], format: &OutputFormat) {
+    let rows = kinds
+        .iter()
+        .map(|kind| {
+            vec![
+                kind.event_kind.clone(),
+                format.duration(kind.self_time),
+                format!("{:.3}", kind.percent_of_total_time),
+                format!("{:.3}", kind.percent_of_wall_time),
+                format.count(kind.event_count),
+            ]
+        })
+        .collect();
+
+    format.print_table(
+        &[
+            "Event kind",
+            "Self time",
+            "% of total time",
+            "% of wall time",
+            "Event count",
+        ],
+        rows,
+        &[],
+    );
+}
+
+fn print_overhead(overhead: &Overhead, format: &OutputFormat) {
+    println!(
+        "Estimated profiler overhead: {} ({:.3}% of wall time), {} bytes written",
+        format.duration(overhead.recording_time()),
+        overhead.percent_of_wall_time(),
+        format.count(overhead.bytes_written)
+    );
+}
+
+fn print_header(tool: Option<&ToolInfo>, system: Option<&SystemInfo>) {
+    if tool.is_none() && system.is_none() {
+        return;
+    }
+
+    if let Some(tool) = tool {
+        let mut header = format!("Recorded by {} {}", tool.name, tool.version);
+        if let Some(git_sha) = &tool.git_sha {
+            header.push_str(&format!(" ({})", git_sha));
+        }
+        println!("{}", header);
+
+        if !tool.flags.is_empty() {
+            println!("Flags: {}", tool.flags.join(" "));
+        }
+    }
+
+    if let Some(system) = system {
+        println!("Recorded on {}", system.describe());
+    }
+    println!();
+}
+
+fn print_unclosed_threads(unclosed_threads: &[UnclosedThread], format: &OutputFormat) {
+    let rows = unclosed_threads
+        .iter()
+        .map(|thread| {
+            vec![
+                thread.thread_id.to_string(),
+                format.duration(thread.unclosed_time),
+            ]
+        })
+        .collect();
+
+    println!(
+        "Threads still running at the end of the profile (open intervals are not included above):"
+    );
+    format.print_table(&["Thread", "Unclosed time"], rows, &[]);
+}
+
+fn print_session_segments(segments: &[SegmentResults], format: &OutputFormat) {
+    let rows = segments
+        .iter()
+        .map(|segment| {
+            vec![
+                segment.name.clone(),
+                format.duration(segment.duration),
+                format!("{:.1}%", segment.percent_of_wall_time),
+            ]
+        })
+        .collect();
+
+    println!("Session segments:");
+    format.print_table(&["Segment", "Duration", "% of wall time"], rows, &[]);
+}
+
+fn print_flow_latencies(latencies: &[FlowLatencies], format: &OutputFormat) {
+    let rows = latencies
+        .iter()
+        .map(|l| {
+            vec![
+                l.label.clone(),
+                l.count.to_string(),
+                format.duration(l.total),
+                format.duration(l.min),
+                format.duration(l.median),
+                format.duration(l.p90),
+                format.duration(l.max),
+            ]
+        })
+        .collect();
+
+    println!("Flow latencies (from the start of a flow to its end, e.g. queueing delays):");
+    format.print_table(
+        &["Flow", "Count", "Total", "Min", "Median", "p90", "Max"],
+        rows,
+        &[],
+    );
+}
+
+fn print_stalls(stalls: &[Stall], start_time: SystemTime, format: &OutputFormat) {
+    if stalls.is_empty() {
+        println!("No stalls found.");
+        return;
+    }
+
+    let rows = stalls
+        .iter()
+        .map(|stall| {
+            let since_start = stall
+                .start
+                .duration_since(start_time)
+                .unwrap_or_else(|_| Duration::from_nanos(0));
+
+            vec![
+                stall.thread_id.to_string(),
+                format.duration(since_start),
+                format.duration(stall.duration()),
+            ]
+        })
+        .collect();
+
+    println!("Possible stalls (threads without any events):");
+    format.print_table(&["Thread", "Start", "Duration"], rows, &[]);
+}
+
+fn gap_rows(gaps: &[Gap<'_>], start_time: SystemTime, format: &OutputFormat) -> Vec<Vec<String>> {
+    let label = |event: &LightweightEvent<'_>| event.to_event().label.into_owned();
+
+    gaps.iter()
+        .map(|gap| {
+            let since_start = gap
+                .start
+                .duration_since(start_time)
+                .unwrap_or_else(|_| Duration::from_nanos(0));
+            let enclosing: Vec<_> = gap.enclosing.iter().map(label).collect();
+
+            vec![
+                format.duration(since_start),
+                format.duration(gap.duration()),
+                label(&gap.before),
+                label(&gap.after),
+                enclosing.join(", "),
+            ]
+        })
+        .collect()
+}
+
+fn print_gaps(rows: Vec<Vec<String>>, format: &OutputFormat) {
+    if rows.is_empty() {
+        println!("No gaps found.");
+        return;
+    }
+
+    println!("Longest gaps (no thread recorded any events):");
+    format.print_table(
+        &["Start", "Duration", "Before", "After", "Running"],
+        rows,
+        &[],
+    );
+}
+
+fn percentiles(opt: PercentilesOpt, format: &OutputFormat) -> Result<(), Box<dyn Error>> {
+    let filter = EventFilter::new(opt.filter, opt.exclude);
+    let mut corpus = percentiles::CorpusPercentiles::new(opt.per_profile);
+
+    // The profiles are loaded one after the other, so that large corpora
+    // don't have to fit into memory at once.
+    for path_stem in &opt.file_prefixes {
+        let data = ProfilingData::new(path_stem)?;
+        corpus.add_profile(crate_name(&data, path_stem), &data, &filter);
+    }
+
+    let mut percentiles = corpus.finish();
+    if let Some(top) = opt.top {
+        percentiles.truncate(top);
+    }
+
+    if opt.json {
+        let path = opt.file_prefixes[0].with_extension("percentiles.json");
+        let file = BufWriter::new(File::create(path)?);
+        serde_json::to_writer(file, &percentiles)?;
+        return Ok(());
+    }
+
+    let rows = percentiles
+        .iter()
+        .map(|p| {
+            vec![
+                p.label.clone(),
+                format.count(p.profiles),
+                format.count(p.count),
+                format.duration(p.total),
+                format.duration(p.min),
+                format.duration(p.p50),
+                format.duration(p.p90),
+                format.duration(p.p99),
+                format.duration(p.max),
+                p.max_profile.clone(),
+            ]
+        })
+        .collect();
+
+    // With `--per-profile`, the count is that of the profiles too.
+    format.print_table(
+        &[
+            "Item", "Profiles", "Count", "Total", "Min", "p50", "p90", "p99", "Max", "Max in",
+        ],
+        rows,
+        &[],
+    );
+
+    Ok(())
+}
+
+fn crate_name(data: &ProfilingData, path_stem: &Path) -> String {
+    let invoking_crate = data.metadata.tool.as_ref().and_then(|t| t.invoking_crate());
+    if let Some(crate_name) = invoking_crate {
+        return crate_name.to_string();
+    }
+
+    let cmd = &data.metadata.cmd;
+
+    if let Some(index) = cmd.find(" --crate-name ") {
+        let rest = &cmd[index + 14..];
+        return rest[..rest.find(' ').unwrap_or(rest.len())].to_string();
+    }
+
+    // rustc names profiles `<crate name>-<pid>`.
+    let stem = path_stem.file_name().unwrap().to_string_lossy();
+    match stem.rfind('-') {
+        Some(index) => stem[..index].to_string(),
+        None => stem.into_owned(),
+    }
+}
+
+fn format_efficiency(efficiency: Option<f64>) -> String {
+    efficiency
+        .map(|e| format!("{:.1}%", e * 100.0))
+        .unwrap_or_else(|| "-".to_string())
+}
+
+fn incremental(opt: IncrementalOpt, format: &OutputFormat) -> Result<(), Box<dyn Error>> {
+    let mut path_stems = Vec::new();
+
+    for entry in std::fs::read_dir(&opt.dir)? {
+        let path = entry?.path();
+        if path.extension().filter(|e| *e == "events").is_none() {
+            continue;
+        }
+
+        path_stems.push(path.with_extension(""));
+    }
+
+    let builds: Vec<_> = ProfilingData::load_all(&path_stems)?
+        .into_iter()
+        .zip(path_stems)
+        .map(|(data, path_stem)| (crate_name(&data, &path_stem), path_stem, data))
+        .collect();
+
+    let mut crate_names: Vec<_> = builds.iter().map(|(name, _, _)| name.clone()).collect();
+    crate_names.sort();
+    crate_names.dedup();
+
+    let selected = match (&opt.crate_name, &crate_names[..]) {
+        (Some(name), _) => name.clone(),
+        (None, [name]) => name.clone(),
+        (None, []) => Err(format!("no profiles found in `{}`", opt.dir.display()))?,
+        (None, _) => Err(format!(
+            "`{}` contains profiles of several crates, select one with `--crate`: {}",
+            opt.dir.display(),
+            crate_names.join(", ")
+        ))?,
+    };
+
+    let mut builds: Vec<_> = builds
+        .into_iter()
+        .filter(|(name, _, _)| *name == selected)
+        .map(|(_, path_stem, data)| (path_stem.display().to_string(), data))
+        .collect();
+
+    if builds.is_empty() {
+        Err(format!(
+            "no profiles of crate `{}` found in `{}`",
+            selected,
+            opt.dir.display()
+        ))?;
+    }
+
+    builds.sort_by_key(|(_, data)| data.metadata.start_time);
+
+    let report = incremental::incremental_report(&builds);
+
+    if opt.json {
+        let file = BufWriter::new(File::create(opt.dir.join("incremental.json"))?);
+        serde_json::to_writer(file, &report)?;
+        return Ok(());
+    }
+
+    let rows = report
+        .builds
+        .iter()
+        .enumerate()
+        .map(|(index, build)| {
+            vec![
+                (index + 1).to_string(),
+                build.profile.clone(),
+                format.count(build.totals.executed),
+                format.count(build.totals.loaded),
+                format_efficiency(build.totals.efficiency()),
+            ]
+        })
+        .collect();
+
+    println!("Incremental builds of `{}`:", selected);
+    format.print_table(
+        &["Build", "Profile", "Executed", "Loaded", "Efficiency"],
+        rows,
+        &[],
+    );
+
+    if report.builds.len() < 2 {
+        return Ok(());
+    }
+
+    let build_titles: Vec<String> = (0..report.builds.len())
+        .map(|index| format!("Build {}", index + 1))
+        .collect();
+    let mut header = vec!["Item", "Re-executions"];
+    header.extend(build_titles.iter().map(|title| &title[..]));
+
+    let rows = report
+        .queries
+        .iter()
+        .filter(|q| q.re_executions() > 0)
+        .take(opt.top)
+        .map(|query| {
+            let mut row = vec![query.label.clone(), format.count(query.re_executions())];
+            for counts in &query.builds {
+                row.push(format!(
+                    "{}/{}",
+                    format.count(counts.executed),
+                    format.count(counts.loaded)
+                ));
+            }
+            row
+        })
+        .collect();
+
+    println!("Most re-executed queries (executed/loaded per build):");
+    format.print_table(&header, rows, &[]);
+
+    Ok(())
+}
+
+fn histogram(opt: HistogramOpt) -> Result<(), Box<dyn Error>> {
+    let data = ProfilingData::new(&opt.file_prefix)?;
+    let filter = EventFilter::new(opt.filter, opt.exclude);
+
+    let mut histograms = histogram::duration_histograms(&data, &filter);
+    if let Some(top) = opt.top {
+        histograms.truncate(top);
+    }
+
+    let extension = if opt.html {
+        "histograms.html"
+    } else {
+        "histograms.json"
+    };
+    let path = opt.file_prefix.with_extension(extension);
+    let mut file = BufWriter::new(File::create(&path)?);
+
+    if opt.html {
+        let title = format!("Event durations of {}", opt.file_prefix.display());
+        file.write_all(histogram::histograms_html(&title, &histograms).as_bytes())?;
+    } else {
+        serde_json::to_writer(&mut file, &histograms)?;
+    }
+    file.flush()?;
+
+    println!(
+        "Wrote histograms of {} labels to `{}`",
+        histograms.len(),
+        path.display()
+    );
+
+    Ok(())
+}
+
+fn crates(opt: CratesOpt, format: &OutputFormat) -> Result<(), Box<dyn Error>> {
+    let profiles: Vec<_> = ProfilingData::load_all(&opt.file_prefixes)?
+        .into_iter()
+        .zip(&opt.file_prefixes)
+        .map(|(data, path_stem)| (crate_name(&data, path_stem), data))
+        .collect();
+
+    let mut crate_times = crates::crate_times(&profiles);
+    let total_time: Duration = crate_times.iter().map(|c| c.self_time).sum();
+
+    if let Some(top) = opt.top {
+        crate_times.truncate(top);
+    }
+
+    if opt.json {
+        let path = opt.file_prefixes[0].with_extension("crates.json");
+        let file = BufWriter::new(File::create(path)?);
+        serde_json::to_writer(file, &crate_times)?;
+        return Ok(());
+    }
+
+    let rows = crate_times
+        .iter()
+        .map(|c| {
+            let percent = if total_time.as_nanos() == 0 {
+                0.0
+            } else {
+                c.self_time.as_nanos() as f64 / total_time.as_nanos() as f64 * 100.0
+            };
+            vec![
+                c.crate_name.clone(),
+                format.duration(c.self_time),
+                format!("{:.2}", percent),
+                format.count(c.item_count),
+            ]
+        })
+        .collect();
+
+    format.print_table(&["Crate", "Time", "% of total time", "Items"], rows, &[]);
+
+    println!("Total cpu time: {}", format.duration(total_time));
+
+    Ok(())
+}
+
+fn metrics(opt: MetricsOpt) -> Result<(), Box<dyn Error>> {
+    let (results, profile_crate) = if opt.file_prefix.extension().is_some_and(|e| e == "json") {
+        let reader = BufReader::new(File::open(&opt.file_prefix)?);
+        let results: Results = serde_json::from_reader(reader)?;
+        let stem = opt.file_prefix.file_stem().unwrap_or_default();
+        (results, stem.to_string_lossy().into_owned())
+    } else {
+        let data = ProfilingData::new(&opt.file_prefix)?;
+        let crate_name = crate_name(&data, &opt.file_prefix);
+        (analysis::perform_analysis(data), crate_name)
+    };
+
+    let crate_name = opt.crate_name.unwrap_or(profile_crate);
+    let mut tags = vec![("crate".to_string(), crate_name.clone())];
+    if let Some(commit) = opt.commit {
+        tags.push(("commit".to_string(), commit));
+    }
+    tags.extend(opt.tags);
+
+    let text = metrics::prometheus_text(results, &tags, opt.top);
+
+    if let Some(gateway) = &opt.push {
+        metrics::push(gateway, &crate_name, &text)?;
+    }
+    match &opt.output {
+        Some(path) => {
+            // The textfile collector may read the file at any time, so it
+            // must never see it half-written.
+            let mut temp = path.as_os_str().to_os_string();
+            temp.push(".tmp");
+            std::fs::write(&temp, &text)?;
+            std::fs::rename(&temp, path)?;
+        }
+        None if opt.push.is_none() => print!("{}", text),
+        None => {}
+    }
+
+    Ok(())
+}
+
+fn init_self_profiling(path_stem: Option<&Path>) -> Result<(), Box<dyn Error>> {
+    if let Some(path_stem) = path_stem {
+        measureme::global::init(path_stem, &measureme::ProfilerConfig::default())?;
+        measureme::global::set_tool_info(ToolInfo {
+            name: "summarize".to_string(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            git_sha: None,
+            flags: Vec::new(),
+        });
+    }
+    Ok(())
+}
+
+/// Runs `summarize` with the given command line arguments, the first of which is
+/// the name of the binary. Exits the process if the arguments are invalid,
+/// like the `summarize` binary does, and returns the error if `summarize` fails, for
+/// `analyzeme::cli::exit_on_error()`.
+pub fn main_with_args(args: impl IntoIterator<Item = OsString>) -> Result<(), Box<dyn Error>> {
+    let cli = Cli::from_iter(args);
+
+    let format = OutputFormat {
+        time_unit: cli.time_unit,
+        thousands_separator: cli.thousands_separator,
+        color: if cli.no_color {
+            ColorChoice::Never
+        } else {
+            cli.color
+        },
+        width: cli.width,
+        max_label_len: Some(cli.max_label_len).filter(|&len| len > 0),
+    };
+
+    let message_format = cli.output.message_format;
+    let self_profile = cli.self_profile;
+    let result = match cli
+        .output
+        .init()
+        .and_then(|()| init_self_profiling(self_profile.as_deref()))
+    {
+        Err(error) => Err(error),
+        Ok(()) => match cli.command {
+            Opt::Summarize(opt) => summarize(opt, message_format, &format),
+            Opt::Diff(opt) => diff(opt, &format),
+            Opt::Incremental(opt) => incremental(opt, &format),
+            Opt::Histogram(opt) => histogram(opt),
+            Opt::Crates(opt) => crates(opt, &format),
+            Opt::Percentiles(opt) => percentiles(opt, &format),
+            Opt::Metrics(opt) => metrics(opt),
+        },
+    };
+    measureme::global::finish();
+    result
+}
@@ -0,0 +1,202 @@
+//! Exports the summary of a profile as Prometheus metrics, so that the build
+//! times of every commit can be graphed in existing dashboards. The metrics
+//! are written in Prometheus' text format, e.g. for the textfile collector of
+//! the node exporter, or pushed to a Pushgateway.
+//!
+//! Every sample is tagged with the given labels (e.g. `crate` and `commit`),
+//! and the per-label metrics also with the `label` of the events. Profiles
+//! can have thousands of labels, so `top` limits the labels exported on
+//! their own and adds up the rest as `label="(other)"`.
+
+use crate::query_data::{QueryData, Results, SortBy};
+use std::error::Error;
+use std::fmt::Write;
+use std::time::Duration;
+
+/// The `label` of the labels beyond the top ones.
+const OTHER_LABEL: &str = "(other)";
+
+/// Parses a `--tag` value, `key=value`, checking that the key is a valid
+/// Prometheus label name.
+pub fn parse_tag(tag: &str) -> Result<(String, String), Box<dyn Error>> {
+    let (key, value) = tag
+        .split_once('=')
+        .ok_or_else(|| format!("expected `key=value`, found `{}`", tag))?;
+
+    let valid = key.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && !key.starts_with("__")
+        && key != "label"
+        && key != "kind";
+    if !valid {
+        Err(format!("`{}` can't be used as the name of a label", key))?;
+    }
+    Ok((key.to_string(), value.to_string()))
+}
+
+/// Renders the metrics of `results` in Prometheus' text format.
+pub fn prometheus_text(
+    mut results: Results,
+    tags: &[(String, String)],
+    top: Option<usize>,
+) -> String {
+    results.sort(SortBy::SelfTime);
+
+    let mut query_data = results.query_data;
+    if let Some(top) = top {
+        if query_data.len() > top {
+            let mut other = QueryData::new(OTHER_LABEL.to_string());
+            for rest in query_data.drain(top..) {
+                other.time += rest.time;
+                other.self_time += rest.self_time;
+                other.invocation_count += rest.invocation_count;
+            }
+            query_data.push(other);
+        }
+    }
+
+    let mut out = String::new();
+    let mut family =
+        |name: &str, help: &str, samples: &mut dyn Iterator<Item = (Option<(&str, &str)>, f64)>| {
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} gauge", name);
+            for (extra, value) in samples {
+                let labels: Vec<String> = tags
+                    .iter()
+                    .map(|(k, v)| (&k[..], &v[..]))
+                    .chain(extra)
+                    .map(|(k, v)| format!("{}=\"{}\"", k, escape(v)))
+                    .collect();
+                let _ = writeln!(out, "{}{{{}}} {}", name, labels.join(","), value);
+            }
+        };
+
+    family(
+        "measureme_total_time_seconds",
+        "The summed self time of all events.",
+        &mut std::iter::once((None, seconds(results.total_time))),
+    );
+    family(
+        "measureme_self_time_seconds",
+        "The self time of the events of a label.",
+        &mut query_data
+            .iter()
+            .map(|q| (Some(("label", &q.label[..])), seconds(q.self_time))),
+    );
+    family(
+        "measureme_time_seconds",
+        "The time of the events of a label, including nested events.",
+        &mut query_data
+            .iter()
+            .map(|q| (Some(("label", &q.label[..])), seconds(q.time))),
+    );
+    family(
+        "measureme_invocations",
+        "The number of events of a label.",
+        &mut query_data
+            .iter()
+            .map(|q| (Some(("label", &q.label[..])), q.invocation_count as f64)),
+    );
+    if !results.kinds.is_empty() {
+        family(
+            "measureme_kind_self_time_seconds",
+            "The self time of the events of an event kind.",
+            &mut results
+                .kinds
+                .iter()
+                .map(|k| (Some(("kind", &k.event_kind[..])), seconds(k.self_time))),
+        );
+    }
+
+    out
+}
+
+/// Replaces the metrics of `crate_name` on the Pushgateway at `gateway`
+/// with `text`, grouped by the job `measureme` and the crate.
+#[cfg(feature = "http")]
+pub fn push(gateway: &str, crate_name: &str, text: &str) -> Result<(), Box<dyn Error>> {
+    if crate_name.is_empty() || crate_name.contains('/') {
+        Err(format!("`{}` can't be pushed as a crate name", crate_name))?;
+    }
+
+    let url = format!(
+        "{}/metrics/job/measureme/crate/{}",
+        gateway.trim_end_matches('/'),
+        crate_name
+    );
+    ureq::put(&url)
+        .set("Content-Type", "text/plain; version=0.0.4")
+        .send_string(text)
+        .map_err(|e| format!("couldn't push the metrics to `{}`: {}", url, e))?;
+    Ok(())
+}
+
+#[cfg(not(feature = "http"))]
+pub fn push(_gateway: &str, _crate_name: &str, _text: &str) -> Result<(), Box<dyn Error>> {
+    Err("pushing metrics needs summarize to be built with the `http` feature")?
+}
+
+fn seconds(duration: Duration) -> f64 {
+    duration.as_secs_f64()
+}
+
+/// Escapes a label value as the text format requires.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query(label: &str, self_millis: u64) -> QueryData {
+        QueryData {
+            self_time: Duration::from_millis(self_millis),
+            time: Duration::from_millis(self_millis * 2),
+            invocation_count: 1,
+            ..QueryData::new(label.to_string())
+        }
+    }
+
+    fn results() -> Results {
+        Results {
+            query_data: vec![query("typeck", 500), query("a \"b\"", 250), query("c", 250)],
+            total_time: Duration::from_secs(1),
+            phases: Vec::new(),
+            session_segments: Vec::new(),
+            unclosed_threads: Vec::new(),
+            kinds: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn text_format() {
+        let tags = vec![parse_tag("crate=serde").unwrap()];
+
+        let text = prometheus_text(results(), &tags, Some(1));
+        assert!(text.contains("# TYPE measureme_self_time_seconds gauge\n"));
+        assert!(text.contains("measureme_total_time_seconds{crate=\"serde\"} 1\n"));
+        assert!(
+            text.contains("measureme_self_time_seconds{crate=\"serde\",label=\"typeck\"} 0.5\n")
+        );
+        assert!(text.contains("measureme_invocations{crate=\"serde\",label=\"(other)\"} 2\n"));
+        assert!(!text.contains("measureme_kind_self_time_seconds"));
+
+        let text = prometheus_text(results(), &tags, None);
+        assert!(text.contains("label=\"a \\\"b\\\"\""), "{}", text);
+    }
+
+    #[test]
+    fn tags() {
+        assert_eq!(
+            parse_tag("commit=abc=1").unwrap(),
+            ("commit".to_string(), "abc=1".to_string())
+        );
+        assert!(parse_tag("commit").is_err());
+        assert!(parse_tag("1st=x").is_err());
+        assert!(parse_tag("label=x").is_err());
+    }
+}
@@ -0,0 +1,378 @@
+//! How the tables that `summarize` prints to stdout are formatted, see the
+//! `--time-unit`, `--thousands-separator`, `--color`, `--width` and
+//! `--max-label-len` options.
+
+use crate::signed_duration::SignedDuration;
+use analyzeme::truncate_label;
+use prettytable::{Cell, Row, Table};
+use std::borrow::Cow;
+use std::io::IsTerminal;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Labels aren't shortened below this many characters to make a table fit
+/// into the terminal.
+const MIN_LABEL_WIDTH: usize = 16;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TimeUnit {
+    /// Picks a unit per value, like the `Debug` output of `Duration`.
+    #[default]
+    Auto,
+    Nanos,
+    Micros,
+    Millis,
+    Secs,
+}
+
+impl FromStr for TimeUnit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<TimeUnit, String> {
+        match s {
+            "auto" => Ok(TimeUnit::Auto),
+            "ns" => Ok(TimeUnit::Nanos),
+            "us" | "µs" => Ok(TimeUnit::Micros),
+            "ms" => Ok(TimeUnit::Millis),
+            "s" => Ok(TimeUnit::Secs),
+            other => Err(format!(
+                "invalid time unit `{}`, expected `auto`, `ns`, `us`, `ms` or `s`",
+                other
+            )),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ColorChoice {
+    /// Colors are used if stdout is a terminal and `NO_COLOR` isn't set.
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl FromStr for ColorChoice {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<ColorChoice, String> {
+        match s {
+            "auto" => Ok(ColorChoice::Auto),
+            "always" => Ok(ColorChoice::Always),
+            "never" => Ok(ColorChoice::Never),
+            other => Err(format!(
+                "invalid color choice `{}`, expected `auto`, `always` or `never`",
+                other
+            )),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct OutputFormat {
+    pub time_unit: TimeUnit,
+    /// Inserted between groups of three digits of the integer part of
+    /// numbers, e.g. `,` or `.` depending on the locale.
+    pub thousands_separator: Option<String>,
+    pub color: ColorChoice,
+    /// The width tables are fitted into. By default, this is the width of
+    /// the terminal, and tables aren't fitted if stdout isn't a terminal.
+    pub width: Option<usize>,
+    /// Labels longer than this are shortened regardless of the width, if
+    /// set.
+    pub max_label_len: Option<usize>,
+}
+
+impl OutputFormat {
+    pub fn duration(&self, d: Duration) -> String {
+        let nanos = d.as_nanos() as f64;
+
+        let formatted = match self.time_unit {
+            TimeUnit::Auto => format!("{:.2?}", d),
+            TimeUnit::Nanos => format!("{}ns", d.as_nanos()),
+            TimeUnit::Micros => format!("{:.2}µs", nanos / 1e3),
+            TimeUnit::Millis => format!("{:.2}ms", nanos / 1e6),
+            TimeUnit::Secs => format!("{:.2}s", nanos / 1e9),
+        };
+
+        self.group_digits(&formatted)
+    }
+
+    pub fn signed_duration(&self, d: SignedDuration) -> String {
+        let sign = if d.is_positive { "+" } else { "-" };
+        format!("{}{}", sign, self.duration(d.duration))
+    }
+
+    pub fn count(&self, n: impl ToString) -> String {
+        self.group_digits(&n.to_string())
+    }
+
+    /// Inserts the thousands separator into the first run of digits of `s`.
+    fn group_digits(&self, s: &str) -> String {
+        let separator = match &self.thousands_separator {
+            Some(separator) => separator,
+            None => return s.to_string(),
+        };
+
+        let start = s.find(|c: char| c.is_ascii_digit()).unwrap_or(s.len());
+        let end = s[start..]
+            .find(|c: char| !c.is_ascii_digit())
+            .map_or(s.len(), |len| start + len);
+        let digits = &s[start..end];
+
+        let mut result = s[..start].to_string();
+        for (i, digit) in digits.chars().enumerate() {
+            if i > 0 && (digits.len() - i) % 3 == 0 {
+                result.push_str(separator);
+            }
+            result.push(digit);
+        }
+        result.push_str(&s[end..]);
+
+        result
+    }
+
+    fn use_color(&self) -> bool {
+        match self.color {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => {
+                std::env::var_os("NO_COLOR").is_none() && std::io::stdout().is_terminal()
+            }
+        }
+    }
+
+    fn table_width(&self) -> Option<usize> {
+        if self.width.is_some() {
+            return self.width;
+        }
+
+        if !std::io::stdout().is_terminal() {
+            return None;
+        }
+
+        std::env::var("COLUMNS")
+            .ok()
+            .and_then(|columns| columns.parse().ok())
+            .or_else(terminal_width)
+    }
+
+    /// Prints a table with the given header. The first column is shortened
+    /// if it is longer than `max_label_len` or the table doesn't fit into
+    /// the width of the terminal, see `truncate_label()`. With colors, the
+    /// header is bold and values of the `change_columns` are red if they are
+    /// positive (i.e. got slower) and green otherwise.
+    pub fn print_table(
+        &self,
+        header: &[&str],
+        mut rows: Vec<Vec<String>>,
+        change_columns: &[usize],
+    ) {
+        if let Some(max_len) = self.max_label_len {
+            shorten_first_column(&mut rows, max_len);
+        }
+
+        if let Some(width) = self.table_width() {
+            fit_first_column(header, &mut rows, width);
+        }
+
+        let color = self.use_color();
+        let style = |spec: &str| {
+            if color {
+                spec.to_string()
+            } else {
+                String::new()
+            }
+        };
+
+        let mut table = Table::new();
+        table.add_row(Row::new(
+            header
+                .iter()
+                .map(|title| Cell::new(title).style_spec(&style("b")))
+                .collect(),
+        ));
+
+        for row in rows {
+            table.add_row(Row::new(
+                row.iter()
+                    .enumerate()
+                    .map(|(column, value)| {
+                        let spec = if change_columns.contains(&column) {
+                            change_style(value)
+                        } else {
+                            ""
+                        };
+                        Cell::new(value).style_spec(&style(spec))
+                    })
+                    .collect(),
+            ));
+        }
+
+        if color {
+            let _ = table.print_tty(true);
+        } else {
+            let _ = table.print(&mut std::io::stdout());
+        }
+    }
+}
+
+/// Red for values that start with `+`, green for those that start with `-`,
+/// unless they are zero.
+fn change_style(value: &str) -> &'static str {
+    if !value.chars().any(|c| c.is_ascii_digit() && c != '0') {
+        ""
+    } else if value.starts_with('+') {
+        "Fr"
+    } else if value.starts_with('-') {
+        "Fg"
+    } else {
+        ""
+    }
+}
+
+/// Shortens the values of the first column (with a trailing `…` and label
+/// id) so that the table is at most `width` characters wide, if possible.
+fn fit_first_column(header: &[&str], rows: &mut [Vec<String>], width: usize) {
+    let column_count = header.len();
+    let mut column_widths: Vec<usize> = header.iter().map(|title| title.chars().count()).collect();
+
+    for row in rows.iter() {
+        for (column, value) in row.iter().enumerate().take(column_count) {
+            column_widths[column] = column_widths[column].max(value.chars().count());
+        }
+    }
+
+    // Every column has a space of padding on both sides and is followed by
+    // a border, and there is a border at the very left.
+    let table_width: usize = column_widths.iter().map(|w| w + 3).sum::<usize>() + 1;
+    if table_width <= width {
+        return;
+    }
+
+    let excess = table_width - width;
+    let label_width = column_widths[0]
+        .saturating_sub(excess)
+        .max(MIN_LABEL_WIDTH.min(column_widths[0]));
+
+    shorten_first_column(rows, label_width);
+}
+
+fn shorten_first_column(rows: &mut [Vec<String>], max_len: usize) {
+    for row in rows.iter_mut() {
+        if let Some(label) = row.first_mut() {
+            if let Cow::Owned(shortened) = truncate_label(label, max_len) {
+                *label = shortened;
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+fn terminal_width() -> Option<usize> {
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    let result = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) };
+
+    if result == 0 && size.ws_col > 0 {
+        Some(size.ws_col as usize)
+    } else {
+        None
+    }
+}
+
+#[cfg(not(unix))]
+fn terminal_width() -> Option<usize> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use analyzeme::label_id;
+
+    fn format(time_unit: TimeUnit, thousands_separator: Option<&str>) -> OutputFormat {
+        OutputFormat {
+            time_unit,
+            thousands_separator: thousands_separator.map(String::from),
+            ..OutputFormat::default()
+        }
+    }
+
+    #[test]
+    fn durations() {
+        let d = Duration::from_nanos(1_234_567_890);
+
+        assert_eq!(format(TimeUnit::Auto, None).duration(d), "1.23s");
+        assert_eq!(format(TimeUnit::Nanos, None).duration(d), "1234567890ns");
+        assert_eq!(
+            format(TimeUnit::Nanos, Some(",")).duration(d),
+            "1,234,567,890ns"
+        );
+        assert_eq!(
+            format(TimeUnit::Micros, Some(",")).duration(d),
+            "1,234,567.89µs"
+        );
+        assert_eq!(
+            format(TimeUnit::Millis, Some(".")).duration(d),
+            "1.234.57ms"
+        );
+        assert_eq!(format(TimeUnit::Secs, Some(",")).duration(d), "1.23s");
+
+        let d = SignedDuration::from_nanos(-12_345_000);
+        assert_eq!(
+            format(TimeUnit::Micros, Some(",")).signed_duration(d),
+            "-12,345.00µs"
+        );
+    }
+
+    #[test]
+    fn counts() {
+        assert_eq!(format(TimeUnit::Auto, None).count(1234567), "1234567");
+        assert_eq!(
+            format(TimeUnit::Auto, Some(",")).count(1234567),
+            "1,234,567"
+        );
+        assert_eq!(format(TimeUnit::Auto, Some("'")).count(123), "123");
+        assert_eq!(format(TimeUnit::Auto, Some(",")).count("+1234"), "+1,234");
+        assert_eq!(format(TimeUnit::Auto, Some(",")).count(0), "0");
+    }
+
+    #[test]
+    fn change_styles() {
+        assert_eq!(change_style("+1.50%"), "Fr");
+        assert_eq!(change_style("-20.00ms"), "Fg");
+        assert_eq!(change_style("+0.00%"), "");
+        assert_eq!(change_style("12"), "");
+    }
+
+    #[test]
+    fn fitting_into_width() {
+        let header = ["Item", "Time"];
+        let mut rows = vec![
+            vec![
+                "a_very_long_label_that_does_not_fit".to_string(),
+                "1.00ms".to_string(),
+            ],
+            vec!["short".to_string(), "2.00ms".to_string()],
+        ];
+
+        // | a_very_long_label_that_does_not_fit | 1.00ms |
+        fit_first_column(&header, &mut rows, 48);
+        assert_eq!(rows[0][0], "a_very_long_label_that_does_not_fit");
+
+        fit_first_column(&header, &mut rows, 30);
+        assert_eq!(
+            rows[0][0],
+            format!(
+                "a_very_…#{}",
+                label_id("a_very_long_label_that_does_not_fit")
+            )
+        );
+        assert_eq!(rows[0][0].chars().count(), 17);
+        assert_eq!(rows[1][0], "short");
+
+        // Labels aren't shortened below `MIN_LABEL_WIDTH`.
+        fit_first_column(&header, &mut rows, 10);
+        assert_eq!(rows[0][0].chars().count(), MIN_LABEL_WIDTH);
+    }
+}
@@ -0,0 +1,204 @@
+//! Percentiles of the durations of each label across many profiles, e.g. of
+//! all crates of a workspace, answering "how does `metadata_decode_entry`
+//! behave across all of our crates", which the summary of a single profile
+//! can't show. The durations are those of the intervals themselves, i.e.
+//! including the time of the events nested in them, pooled over all
+//! profiles. Alternatively, every profile contributes the total time of each
+//! of its labels, so that the percentiles describe how much a label costs
+//! per profile.
+
+use crate::event_filter::EventFilter;
+use analyzeme::{ProfilingData, Timestamp};
+use rustc_hash::FxHashMap;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct LabelPercentiles {
+    pub label: String,
+    /// The number of profiles the label occurs in.
+    pub profiles: usize,
+    /// The number of durations, i.e. of intervals, or of profiles with
+    /// `per_profile`.
+    pub count: usize,
+    pub total: Duration,
+    pub min: Duration,
+    pub p50: Duration,
+    pub p90: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+    /// The name of the profile the longest duration is from.
+    pub max_profile: String,
+}
+
+#[derive(Default)]
+struct LabelDurations {
+    profiles: usize,
+    last_profile: Option<usize>,
+    durations: Vec<Duration>,
+    max: Duration,
+    max_profile: usize,
+}
+
+impl LabelDurations {
+    fn add(&mut self, profile: usize, duration: Duration) {
+        if self.last_profile != Some(profile) {
+            self.profiles += 1;
+            self.last_profile = Some(profile);
+        }
+        if self.durations.is_empty() || duration > self.max {
+            self.max = duration;
+            self.max_profile = profile;
+        }
+        self.durations.push(duration);
+    }
+}
+
+/// Collects the durations of the labels of one profile after the other, so
+/// that only one profile has to be in memory at a time.
+pub struct CorpusPercentiles {
+    per_profile: bool,
+    profile_names: Vec<String>,
+    labels: FxHashMap<String, LabelDurations>,
+}
+
+impl CorpusPercentiles {
+    pub fn new(per_profile: bool) -> CorpusPercentiles {
+        CorpusPercentiles {
+            per_profile,
+            profile_names: Vec::new(),
+            labels: FxHashMap::default(),
+        }
+    }
+
+    /// Adds the intervals of `data` whose label or event kind pass `filter`.
+    pub fn add_profile(&mut self, name: String, data: &ProfilingData, filter: &EventFilter) {
+        let profile = self.profile_names.len();
+        self.profile_names.push(name);
+
+        let mut profile_totals = FxHashMap::<String, Duration>::default();
+        for event in data.iter().map(|event| event.to_event()) {
+            let duration = match event.timestamp {
+                Timestamp::Interval { start, end } => end
+                    .duration_since(start)
+                    .unwrap_or_else(|_| Duration::from_nanos(0)),
+                Timestamp::Instant(_) => continue,
+            };
+
+            if !filter.matches(&event.label, &event.event_kind) {
+                continue;
+            }
+
+            if self.per_profile {
+                *profile_totals.entry(event.label.into_owned()).or_default() += duration;
+                continue;
+            }
+
+            match self.labels.get_mut(&event.label[..]) {
+                Some(label) => label.add(profile, duration),
+                None => self
+                    .labels
+                    .entry(event.label.into_owned())
+                    .or_default()
+                    .add(profile, duration),
+            }
+        }
+
+        for (label, total) in profile_totals {
+            self.labels.entry(label).or_default().add(profile, total);
+        }
+    }
+
+    /// The percentiles of every label, ordered by total time.
+    pub fn finish(self) -> Vec<LabelPercentiles> {
+        let profile_names = self.profile_names;
+
+        let mut percentiles: Vec<_> = self
+            .labels
+            .into_iter()
+            .map(|(label, mut durations)| {
+                durations.durations.sort();
+                let sorted = &durations.durations;
+
+                // The nearest-rank percentile.
+                let percentile = |p: usize| sorted[(sorted.len() * p).div_ceil(100).max(1) - 1];
+
+                LabelPercentiles {
+                    label,
+                    profiles: durations.profiles,
+                    count: sorted.len(),
+                    total: sorted.iter().sum(),
+                    min: sorted[0],
+                    p50: percentile(50),
+                    p90: percentile(90),
+                    p99: percentile(99),
+                    max: sorted[sorted.len() - 1],
+                    max_profile: profile_names[durations.max_profile].clone(),
+                }
+            })
+            .collect();
+
+        percentiles.sort_by(|a, b| b.total.cmp(&a.total).then_with(|| a.label.cmp(&b.label)));
+        percentiles
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use analyzeme::ProfilingDataBuilder;
+
+    fn profile(durations: &[u64]) -> ProfilingData {
+        let mut b = ProfilingDataBuilder::new();
+        let mut start = 0;
+        for &duration in durations {
+            b.interval(
+                "Query",
+                "metadata_decode_entry",
+                0,
+                start,
+                start + duration,
+                |_| {},
+            );
+            start += duration;
+        }
+        b.instant("QueryCacheHit", "metadata_decode_entry", 0, start);
+        b.into_profiling_data()
+    }
+
+    fn nanos(d: Duration) -> u64 {
+        d.as_nanos() as u64
+    }
+
+    #[test]
+    fn pooled_durations() {
+        let filter = EventFilter::new(Vec::new(), Vec::new());
+        let mut corpus = CorpusPercentiles::new(false);
+        corpus.add_profile("a".to_string(), &profile(&[10, 30, 20]), &filter);
+        corpus.add_profile("b".to_string(), &profile(&[50, 40]), &filter);
+
+        let percentiles = corpus.finish();
+        assert_eq!(percentiles.len(), 1);
+        let p = &percentiles[0];
+        assert_eq!((p.profiles, p.count, nanos(p.total)), (2, 5, 150));
+        assert_eq!(
+            [p.min, p.p50, p.p90, p.p99, p.max].map(nanos),
+            [10, 30, 50, 50, 50]
+        );
+        assert_eq!(p.max_profile, "b");
+    }
+
+    #[test]
+    fn per_profile_totals() {
+        let filter = EventFilter::new(Vec::new(), Vec::new());
+        let mut corpus = CorpusPercentiles::new(true);
+        corpus.add_profile("a".to_string(), &profile(&[10, 30, 20]), &filter);
+        corpus.add_profile("b".to_string(), &profile(&[50, 40]), &filter);
+        corpus.add_profile("c".to_string(), &profile(&[5]), &filter);
+
+        let p = &corpus.finish()[0];
+        assert_eq!((p.profiles, p.count, nanos(p.total)), (3, 3, 155));
+        assert_eq!([p.min, p.p50, p.max].map(nanos), [5, 60, 90]);
+        assert_eq!(p.max_profile, "b");
+    }
+}
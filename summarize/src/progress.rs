@@ -0,0 +1,129 @@
+use crate::analysis::Progress;
+use crate::output::OutputFormat;
+use crate::query_data::SortBy;
+use std::io::{IsTerminal, Write};
+use std::time::{Duration, Instant};
+
+/// How often the progress indicator is redrawn at most.
+const REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How many items provisional results show.
+const PROVISIONAL_ITEMS: usize = 10;
+
+/// Shows how far summarizing got on stderr, which leaves stdout to the
+/// actual results, and optionally the provisional results every so often.
+pub struct ProgressReporter<'f> {
+    show_progress: bool,
+    provisional_interval: Option<Duration>,
+    format: &'f OutputFormat,
+    started: Instant,
+    last_redraw: Option<Instant>,
+    last_provisional: Instant,
+    line_len: usize,
+}
+
+impl<'f> ProgressReporter<'f> {
+    /// The progress indicator is only shown if `show_progress` is set and
+    /// stderr is a terminal.
+    pub fn new(
+        show_progress: bool,
+        provisional_interval: Option<Duration>,
+        format: &'f OutputFormat,
+    ) -> ProgressReporter<'f> {
+        let now = Instant::now();
+        ProgressReporter {
+            show_progress: show_progress && std::io::stderr().is_terminal(),
+            provisional_interval,
+            format,
+            started: now,
+            last_redraw: None,
+            last_provisional: now,
+            line_len: 0,
+        }
+    }
+
+    /// Shows a message in place of the progress indicator, e.g. while the
+    /// profile is loaded.
+    pub fn status(&mut self, message: &str) {
+        if self.show_progress {
+            self.draw(message.to_string());
+        }
+    }
+
+    pub fn report(&mut self, progress: &Progress<'_, '_>) {
+        let now = Instant::now();
+
+        if let Some(interval) = self.provisional_interval {
+            if now.duration_since(self.last_provisional) >= interval {
+                self.last_provisional = now;
+                self.print_provisional_results(progress);
+            }
+        }
+
+        if !self.show_progress
+            || self
+                .last_redraw
+                .is_some_and(|last_redraw| now.duration_since(last_redraw) < REDRAW_INTERVAL)
+        {
+            return;
+        }
+        self.last_redraw = Some(now);
+
+        let line = format!(
+            "Summarizing: {:.0}% ({} of {} events, {})",
+            percentage(progress),
+            self.format.count(progress.events_processed),
+            self.format.count(progress.total_events),
+            self.format.duration(now.duration_since(self.started)),
+        );
+        self.draw(line);
+    }
+
+    /// Removes the progress indicator.
+    pub fn finish(&mut self) {
+        self.clear();
+    }
+
+    fn print_provisional_results(&mut self, progress: &Progress<'_, '_>) {
+        self.clear();
+
+        let mut results = progress.provisional_results();
+        results.sort(SortBy::SelfTime);
+
+        let mut stderr = std::io::stderr().lock();
+        let _ = writeln!(
+            stderr,
+            "Provisional results after {:.0}% of the events:",
+            percentage(progress)
+        );
+        for query_data in results.query_data.iter().take(PROVISIONAL_ITEMS) {
+            let _ = writeln!(
+                stderr,
+                "  {:>12}  {}",
+                self.format.duration(query_data.self_time),
+                query_data.label
+            );
+        }
+        // The progress indicator is redrawn below the results right away.
+        self.last_redraw = None;
+    }
+
+    fn draw(&mut self, line: String) {
+        let padding = self.line_len.saturating_sub(line.len());
+        eprint!("\r{}{:padding$}", line, "", padding = padding);
+        let _ = std::io::stderr().flush();
+        self.line_len = line.len();
+    }
+
+    fn clear(&mut self) {
+        if self.line_len > 0 {
+            eprint!("\r{:len$}\r", "", len = self.line_len);
+            let _ = std::io::stderr().flush();
+            self.line_len = 0;
+        }
+    }
+}
+
+fn percentage(progress: &Progress<'_, '_>) -> f64 {
+    progress.events_processed as f64 / progress.total_events.max(1) as f64 * 100.0
+}
@@ -1,6 +1,8 @@
 use crate::signed_duration::SignedDuration;
 use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
 use std::ops::Sub;
+use std::str::FromStr;
 use std::time::Duration;
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -16,6 +18,15 @@ pub struct QueryData {
 }
 
 impl QueryData {
+    pub fn sort_key(&self) -> SortKey<'_> {
+        SortKey {
+            label: &self.label,
+            self_time: self.self_time,
+            time: self.time,
+            count: self.invocation_count as u64,
+        }
+    }
+
     pub fn new(label: String) -> QueryData {
         QueryData {
             label,
@@ -55,9 +66,9 @@ impl QueryData {
         QueryDataDiff {
             label: self.label.clone(),
             time: self.time.into(),
-            time_change: std::f64::INFINITY,
+            time_change: f64::INFINITY,
             self_time: self.self_time.into(),
-            self_time_change: std::f64::INFINITY,
+            self_time_change: f64::INFINITY,
             number_of_cache_misses: self.number_of_cache_misses as i64,
             number_of_cache_hits: self.number_of_cache_hits as i64,
             invocation_count: self.invocation_count as i64,
@@ -119,6 +130,117 @@ fn percentage_change(base: Duration, change: Duration) -> f64 {
 pub struct Results {
     pub query_data: Vec<QueryData>,
     pub total_time: Duration,
+    /// The same statistics for each phase recorded in the profile, see
+    /// `measureme::Profiler::start_phase()`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub phases: Vec<PhaseResults>,
+    /// The same statistics for each segment of the session, see
+    /// `measureme::Profiler::record_session_marker()`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub session_segments: Vec<SegmentResults>,
+    /// The threads that were still running when the profile ended, see
+    /// `analyzeme::ThreadEnd`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub unclosed_threads: Vec<UnclosedThread>,
+    /// The self time of all events, filtered or not, rolled up by their
+    /// event kind, longest first.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub kinds: Vec<KindData>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct KindData {
+    pub event_kind: String,
+    pub self_time: Duration,
+    pub event_count: usize,
+    pub percent_of_total_time: f64,
+    /// The share of the time from the first event to the last. Kinds whose
+    /// events run on several threads at once can exceed 100%.
+    pub percent_of_wall_time: f64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct UnclosedThread {
+    pub thread_id: u32,
+    pub unclosed_time: Duration,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PhaseResults {
+    pub name: String,
+    pub duration: Duration,
+    pub results: Results,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SegmentResults {
+    pub name: String,
+    pub duration: Duration,
+    /// The share of the time from the start of the profile to its last
+    /// event.
+    pub percent_of_wall_time: f64,
+    pub results: Results,
+}
+
+/// The order of the rows in the output of `summarize summarize`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SortBy {
+    #[default]
+    SelfTime,
+    TotalTime,
+    Count,
+    Label,
+}
+
+impl FromStr for SortBy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<SortBy, String> {
+        match s {
+            "self-time" => Ok(SortBy::SelfTime),
+            "total-time" => Ok(SortBy::TotalTime),
+            "count" => Ok(SortBy::Count),
+            "label" => Ok(SortBy::Label),
+            other => Err(format!(
+                "invalid sort order `{}`, expected `self-time`, `total-time`, `count` or `label`",
+                other
+            )),
+        }
+    }
+}
+
+/// The values of a row that `SortBy` can order by.
+pub struct SortKey<'a> {
+    pub label: &'a str,
+    pub self_time: Duration,
+    pub time: Duration,
+    pub count: u64,
+}
+
+impl SortBy {
+    /// Numbers sort in descending order, and rows with equal numbers by
+    /// label, so that the order doesn't change from run to run.
+    pub fn compare(self, a: &SortKey<'_>, b: &SortKey<'_>) -> Ordering {
+        match self {
+            SortBy::SelfTime => b.self_time.cmp(&a.self_time),
+            SortBy::TotalTime => b.time.cmp(&a.time),
+            SortBy::Count => b.count.cmp(&a.count),
+            SortBy::Label => Ordering::Equal,
+        }
+        .then_with(|| a.label.cmp(b.label))
+    }
+}
+
+impl Results {
+    /// Sorts the rows, including those of each phase.
+    pub fn sort(&mut self, sort_by: SortBy) {
+        self.query_data
+            .sort_by(|a, b| sort_by.compare(&a.sort_key(), &b.sort_key()));
+
+        for phase in &mut self.phases {
+            phase.results.sort(sort_by);
+        }
+    }
 }
 
 // For now this is only needed for tests it seems
@@ -128,3 +250,47 @@ impl Results {
         self.query_data.iter().find(|qd| qd.label == label).unwrap()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sort_orders() {
+        let row = |label: &str, self_time: u64, time: u64, count: usize| QueryData {
+            self_time: Duration::from_nanos(self_time),
+            time: Duration::from_nanos(time),
+            invocation_count: count,
+            ..QueryData::new(label.to_string())
+        };
+        let mut results = Results {
+            query_data: vec![
+                row("b", 10, 50, 1),
+                row("d", 20, 20, 3),
+                row("a", 10, 50, 1),
+                row("c", 10, 60, 3),
+            ],
+            total_time: Duration::from_nanos(100),
+            phases: Vec::new(),
+            session_segments: Vec::new(),
+            unclosed_threads: Vec::new(),
+            kinds: Vec::new(),
+        };
+        let mut labels = |sort_by| {
+            results.sort(sort_by);
+            results
+                .query_data
+                .iter()
+                .map(|qd| qd.label.clone())
+                .collect::<Vec<_>>()
+                .join("")
+        };
+
+        assert_eq!(labels(SortBy::SelfTime), "dabc");
+        assert_eq!(labels(SortBy::TotalTime), "cabd");
+        assert_eq!(labels(SortBy::Count), "cdab");
+        assert_eq!(labels(SortBy::Label), "abcd");
+
+        assert!("size".parse::<SortBy>().is_err());
+    }
+}
@@ -21,7 +21,7 @@ impl SignedDuration {
         let is_positive = nanos >= 0;
 
         SignedDuration {
-            duration: Duration::from_nanos(nanos.abs() as u64),
+            duration: Duration::from_nanos(nanos.unsigned_abs() as u64),
             is_positive,
         }
     }
@@ -69,6 +69,7 @@ impl fmt::Debug for SignedDuration {
 }
 
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
 mod test {
     use super::SignedDuration;
     use std::time::Duration;
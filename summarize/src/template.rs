@@ -0,0 +1,452 @@
+//! A small template language for custom reports, e.g. Markdown for pull
+//! request comments or HTML for dashboards, rendered over the same data that
+//! `--json` writes:
+//!
+//!   - `{{ total_time | duration }}` inserts a value. A path like
+//!     `query_data.0.label` walks into objects and arrays. Filters format the
+//!     value: `duration` for durations (in the unit given by `--time-unit`),
+//!     `escape` for HTML and `length` for the length of an array or string.
+//!   - `{% for query in query_data %} .. {% endfor %}` repeats its body for
+//!     every element of an array, optionally only the first few via
+//!     `{% for query in query_data limit 10 %}`. Within the body, `loop.index`
+//!     counts from 1 and `loop.first` and `loop.last` are booleans.
+//!   - `{% if phases %} .. {% else %} .. {% endif %}` renders its body if the
+//!     value is truthy, i.e. not `null`, `false`, zero or empty. `if not`
+//!     inverts the test.
+//!
+//! Tags on a line of their own don't leave an empty line behind.
+
+use crate::output::OutputFormat;
+use serde_json::{Map, Value};
+use std::error::Error;
+use std::time::Duration;
+
+#[derive(Debug)]
+pub struct Template {
+    nodes: Vec<Node>,
+}
+
+#[derive(Debug)]
+enum Node {
+    Text(String),
+    Value(Expr),
+    For {
+        var: String,
+        path: Path,
+        limit: Option<usize>,
+        body: Vec<Node>,
+    },
+    If {
+        negated: bool,
+        path: Path,
+        then: Vec<Node>,
+        otherwise: Vec<Node>,
+    },
+}
+
+type Path = Vec<String>;
+
+/// Parsed nodes and the tag that ended them, if any.
+type Block<'a> = (Vec<Node>, Option<&'a str>);
+
+#[derive(Debug)]
+struct Expr {
+    path: Path,
+    filters: Vec<Filter>,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Filter {
+    Duration,
+    Escape,
+    Length,
+}
+
+enum Token<'a> {
+    Text(&'a str),
+    Value(&'a str),
+    Tag(&'a str),
+}
+
+impl Template {
+    pub fn parse(source: &str) -> Result<Template, Box<dyn Error>> {
+        let tokens = tokenize(source)?;
+        let mut tokens = tokens.into_iter();
+        let (nodes, end) = parse_nodes(&mut tokens)?;
+
+        if let Some(end) = end {
+            Err(format!("unexpected `{{% {} %}}` in template", end))?;
+        }
+        Ok(Template { nodes })
+    }
+
+    pub fn render(&self, data: &Value, format: &OutputFormat) -> Result<String, Box<dyn Error>> {
+        let mut output = String::new();
+        let mut scopes = vec![];
+        render_nodes(&self.nodes, data, &mut scopes, format, &mut output)?;
+        Ok(output)
+    }
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token<'_>>, Box<dyn Error>> {
+    let mut tokens = Vec::new();
+    let mut rest = source;
+    // Whether `rest` starts at the beginning of a line.
+    let mut at_line_start = true;
+
+    while let Some(start) = find_open(rest) {
+        let (text, tag) = rest.split_at(start);
+        let is_block = tag.starts_with("{%");
+        let close = if is_block { "%}" } else { "}}" };
+        let end = tag
+            .find(close)
+            .ok_or_else(|| format!("unclosed `{}` in template", &tag[..2]))?;
+        let inner = tag[2..end].trim();
+        let mut after = &tag[end + 2..];
+
+        let mut text = text;
+        let mut ends_line = false;
+        if is_block {
+            // Drop the line of a tag that stands on its own.
+            let line_start = text.rfind('\n').map_or(0, |i| i + 1);
+            let line_end = after.find('\n');
+            let before_is_blank = text[line_start..].trim().is_empty();
+            let after_is_blank = after[..line_end.unwrap_or(after.len())].trim().is_empty();
+            if before_is_blank && after_is_blank && (line_start > 0 || at_line_start) {
+                text = &text[..line_start];
+                after = &after[line_end.map_or(after.len(), |i| i + 1)..];
+                ends_line = true;
+            }
+        }
+
+        if !text.is_empty() {
+            tokens.push(Token::Text(text));
+        }
+        tokens.push(if is_block {
+            Token::Tag(inner)
+        } else {
+            Token::Value(inner)
+        });
+        rest = after;
+        at_line_start = ends_line;
+    }
+
+    if !rest.is_empty() {
+        tokens.push(Token::Text(rest));
+    }
+    Ok(tokens)
+}
+
+fn find_open(s: &str) -> Option<usize> {
+    match (s.find("{{"), s.find("{%")) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+/// Parses nodes up to the end of the input or the next `else`, `endfor` or
+/// `endif` tag, which is returned.
+fn parse_nodes<'a>(
+    tokens: &mut impl Iterator<Item = Token<'a>>,
+) -> Result<Block<'a>, Box<dyn Error>> {
+    let mut nodes = Vec::new();
+
+    while let Some(token) = tokens.next() {
+        match token {
+            Token::Text(text) => nodes.push(Node::Text(text.to_string())),
+            Token::Value(expr) => nodes.push(Node::Value(parse_expr(expr)?)),
+            Token::Tag(tag) => {
+                let words: Vec<&str> = tag.split_whitespace().collect();
+
+                match words[..] {
+                    ["for", var, "in", path] | ["for", var, "in", path, "limit", _] => {
+                        let limit = match words.get(5) {
+                            Some(limit) => Some(
+                                limit
+                                    .parse()
+                                    .map_err(|_| format!("invalid limit in `{{% {} %}}`", tag))?,
+                            ),
+                            None => None,
+                        };
+                        let (body, end) = parse_nodes(tokens)?;
+                        expect_end(tag, end, "endfor")?;
+                        nodes.push(Node::For {
+                            var: var.to_string(),
+                            path: parse_path(path),
+                            limit,
+                            body,
+                        });
+                    }
+                    ["if", path] | ["if", "not", path] => {
+                        let (then, mut end) = parse_nodes(tokens)?;
+                        let otherwise = if end == Some("else") {
+                            let (otherwise, else_end) = parse_nodes(tokens)?;
+                            end = else_end;
+                            otherwise
+                        } else {
+                            Vec::new()
+                        };
+                        expect_end(tag, end, "endif")?;
+                        nodes.push(Node::If {
+                            negated: words.len() == 3,
+                            path: parse_path(path),
+                            then,
+                            otherwise,
+                        });
+                    }
+                    ["else"] | ["endfor"] | ["endif"] => return Ok((nodes, Some(words[0]))),
+                    _ => Err(format!("unknown tag `{{% {} %}}` in template", tag))?,
+                }
+            }
+        }
+    }
+
+    Ok((nodes, None))
+}
+
+fn expect_end(tag: &str, end: Option<&str>, expected: &str) -> Result<(), Box<dyn Error>> {
+    if end != Some(expected) {
+        Err(format!(
+            "`{{% {} %}}` in template is missing its `{{% {} %}}`",
+            tag, expected
+        ))?;
+    }
+    Ok(())
+}
+
+fn parse_expr(expr: &str) -> Result<Expr, Box<dyn Error>> {
+    let mut parts = expr.split('|').map(str::trim);
+    let path = parse_path(parts.next().unwrap_or(""));
+
+    let filters = parts
+        .map(|filter| match filter {
+            "duration" => Ok(Filter::Duration),
+            "escape" => Ok(Filter::Escape),
+            "length" => Ok(Filter::Length),
+            _ => Err(format!("unknown filter `{}` in template", filter)),
+        })
+        .collect::<Result<_, _>>()?;
+
+    Ok(Expr { path, filters })
+}
+
+fn parse_path(path: &str) -> Path {
+    path.split('.').map(str::to_string).collect()
+}
+
+/// Looks `path` up in the loop variables, innermost first, and then in
+/// `data`.
+fn lookup<'a>(
+    path: &[String],
+    data: &'a Value,
+    scopes: &'a [(String, Value)],
+) -> Option<&'a Value> {
+    let (first, rest) = path.split_first()?;
+    let mut value = scopes
+        .iter()
+        .rev()
+        .find(|(name, _)| name == first)
+        .map(|(_, value)| value)
+        .or_else(|| data.get(first))?;
+
+    for segment in rest {
+        value = match value {
+            Value::Array(array) => array.get(segment.parse::<usize>().ok()?)?,
+            _ => value.get(segment)?,
+        };
+    }
+    Some(value)
+}
+
+fn lookup_or_err<'a>(
+    path: &[String],
+    data: &'a Value,
+    scopes: &'a [(String, Value)],
+) -> Result<&'a Value, Box<dyn Error>> {
+    lookup(path, data, scopes).ok_or_else(|| {
+        format!(
+            "template refers to `{}`, which doesn't exist",
+            path.join(".")
+        )
+        .into()
+    })
+}
+
+fn is_truthy(value: Option<&Value>) -> bool {
+    match value {
+        None | Some(Value::Null) | Some(Value::Bool(false)) => false,
+        Some(Value::Number(n)) => n.as_f64() != Some(0.0),
+        Some(Value::String(s)) => !s.is_empty(),
+        Some(Value::Array(a)) => !a.is_empty(),
+        Some(Value::Object(o)) => !o.is_empty(),
+        Some(Value::Bool(true)) => true,
+    }
+}
+
+fn render_nodes(
+    nodes: &[Node],
+    data: &Value,
+    scopes: &mut Vec<(String, Value)>,
+    format: &OutputFormat,
+    output: &mut String,
+) -> Result<(), Box<dyn Error>> {
+    for node in nodes {
+        match node {
+            Node::Text(text) => output.push_str(text),
+            Node::Value(expr) => {
+                let value = lookup_or_err(&expr.path, data, scopes)?.clone();
+                output.push_str(&apply_filters(value, &expr.filters, format)?);
+            }
+            Node::For {
+                var,
+                path,
+                limit,
+                body,
+            } => {
+                let items = match lookup_or_err(path, data, scopes)? {
+                    Value::Array(items) => items.clone(),
+                    _ => Err(format!("`{}` in template is not a list", path.join(".")))?,
+                };
+                let count = limit.map_or(items.len(), |limit| limit.min(items.len()));
+
+                for (index, item) in items.into_iter().take(count).enumerate() {
+                    let mut info = Map::new();
+                    info.insert("index".to_string(), Value::from(index + 1));
+                    info.insert("first".to_string(), Value::from(index == 0));
+                    info.insert("last".to_string(), Value::from(index + 1 == count));
+
+                    scopes.push(("loop".to_string(), Value::Object(info)));
+                    scopes.push((var.clone(), item));
+                    let result = render_nodes(body, data, scopes, format, output);
+                    scopes.truncate(scopes.len() - 2);
+                    result?;
+                }
+            }
+            Node::If {
+                negated,
+                path,
+                then,
+                otherwise,
+            } => {
+                let nodes = if is_truthy(lookup(path, data, scopes)) != *negated {
+                    then
+                } else {
+                    otherwise
+                };
+                render_nodes(nodes, data, scopes, format, output)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn apply_filters(
+    mut value: Value,
+    filters: &[Filter],
+    format: &OutputFormat,
+) -> Result<String, Box<dyn Error>> {
+    for &filter in filters {
+        value = match filter {
+            Filter::Duration => {
+                let duration: Duration = serde_json::from_value(value)
+                    .map_err(|_| "the `duration` filter needs a duration")?;
+                Value::String(format.duration(duration))
+            }
+            Filter::Escape => Value::String(
+                to_text(&value)
+                    .replace('&', "&amp;")
+                    .replace('<', "&lt;")
+                    .replace('>', "&gt;")
+                    .replace('"', "&quot;"),
+            ),
+            Filter::Length => match &value {
+                Value::Array(array) => Value::from(array.len()),
+                Value::String(s) => Value::from(s.chars().count()),
+                _ => Err("the `length` filter needs a list or a string")?,
+            },
+        };
+    }
+    Ok(to_text(&value))
+}
+
+fn to_text(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::{ColorChoice, TimeUnit};
+    use serde_json::json;
+
+    fn render(source: &str, data: &Value) -> Result<String, Box<dyn Error>> {
+        let format = OutputFormat {
+            time_unit: TimeUnit::Micros,
+            thousands_separator: None,
+            color: ColorChoice::Never,
+            width: None,
+            max_label_len: None,
+        };
+        Template::parse(source)?.render(data, &format)
+    }
+
+    #[test]
+    fn values_loops_and_conditions() {
+        let data = json!({
+            "total_time": { "secs": 0, "nanos": 1500 },
+            "query_data": [
+                { "label": "typeck", "self_time": { "secs": 0, "nanos": 1000 } },
+                { "label": "<mir>", "self_time": { "secs": 0, "nanos": 500 } },
+                { "label": "unused", "self_time": { "secs": 0, "nanos": 0 } },
+            ],
+            "phases": [],
+        });
+
+        let template = "\
+# Total: {{ total_time | duration }}
+
+| # | Query | Self time |
+|---|-------|-----------|
+{% for q in query_data limit 2 %}
+| {{ loop.index }} | {{ q.label | escape }} | {{ q.self_time | duration }} |
+{% endfor %}
+{% if not phases %}
+No phases{% if query_data %}, {{ query_data | length }} queries{% endif %}.
+{% else %}
+Phases
+{% endif %}
+First: {{ query_data.0.label }}
+";
+
+        assert_eq!(
+            render(template, &data).unwrap(),
+            "\
+# Total: 1.50µs
+
+| # | Query | Self time |
+|---|-------|-----------|
+| 1 | typeck | 1.00µs |
+| 2 | &lt;mir&gt; | 0.50µs |
+No phases, 3 queries.
+First: typeck
+"
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let data = json!({ "total_time": 1 });
+
+        assert!(render("{{ missing }}", &data).is_err());
+        assert!(render("{{ total_time | upper }}", &data).is_err());
+        assert!(render("{{ total_time | duration }}", &data).is_err());
+        assert!(render("{% for x in total_time %}{% endfor %}", &data).is_err());
+        assert!(render("{% if total_time %}", &data).is_err());
+        assert!(render("{% endif %}", &data).is_err());
+        assert!(render("{{ total_time ", &data).is_err());
+    }
+}
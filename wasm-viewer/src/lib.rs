@@ -0,0 +1,176 @@
+//! A minimal profile viewer that runs entirely in the browser. This crate is
+//! compiled to WebAssembly and loaded by `index.html`, which hands it the
+//! contents of the three files of a profile and renders the summary it
+//! returns. See the Readme for how to build it.
+//!
+//! The interface to JavaScript is deliberately low-level, so that it doesn't
+//! need any bindings generator:
+//!
+//! 1. JavaScript allocates a buffer for each file via `alloc_buffer()` and
+//!    copies the file contents into the module's memory.
+//! 2. `summarize()` takes ownership of the buffers, analyzes the profile and
+//!    returns the length of the resulting JSON document.
+//! 3. JavaScript reads the document from `result_ptr()`.
+
+use analyzeme::{ProfilingData, Timestamp};
+use serde_json::{json, Value};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+/// The number of labels included in the summary.
+const TOP_LABELS: usize = 100;
+
+thread_local! {
+    static RESULT: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Allocates a zeroed buffer of `len` bytes that can be passed to
+/// `summarize()`.
+#[no_mangle]
+pub extern "C" fn alloc_buffer(len: usize) -> *mut u8 {
+    Box::into_raw(vec![0u8; len].into_boxed_slice()) as *mut u8
+}
+
+/// Summarizes the profile made up of the given files and returns the length
+/// of the summary, a JSON document that can be read from `result_ptr()`. If
+/// the profile can't be read, the document is an object with a single
+/// `error` field.
+///
+/// # Safety
+///
+/// Each pair of pointer and length must have been returned by and passed to
+/// `alloc_buffer()`, respectively. The buffers are freed by this function.
+#[no_mangle]
+pub unsafe extern "C" fn summarize(
+    events: *mut u8,
+    events_len: usize,
+    string_data: *mut u8,
+    string_data_len: usize,
+    string_index: *mut u8,
+    string_index_len: usize,
+) -> usize {
+    let profile = ProfilingData::from_bytes(
+        take_buffer(events, events_len),
+        take_buffer(string_data, string_data_len),
+        take_buffer(string_index, string_index_len),
+    );
+
+    let summary = match profile {
+        Ok(profile) => summarize_profile(&profile),
+        Err(e) => json!({ "error": e.to_string() }),
+    };
+
+    RESULT.with(|result| {
+        let mut result = result.borrow_mut();
+        *result = summary.to_string().into_bytes();
+        result.len()
+    })
+}
+
+/// Returns a pointer to the summary computed by the last call to
+/// `summarize()`. It stays valid until the next call.
+#[no_mangle]
+pub extern "C" fn result_ptr() -> *const u8 {
+    RESULT.with(|result| result.borrow().as_ptr())
+}
+
+unsafe fn take_buffer(ptr: *mut u8, len: usize) -> Vec<u8> {
+    Box::from_raw(std::ptr::slice_from_raw_parts_mut(ptr, len)).into_vec()
+}
+
+#[derive(Default)]
+struct LabelStats {
+    count: u64,
+    total_time: Duration,
+}
+
+/// Computes the summary that `index.html` renders: general information about
+/// the profile and the labels that account for the most time.
+pub fn summarize_profile(profile: &ProfilingData) -> Value {
+    let mut labels = HashMap::<String, LabelStats>::new();
+    let mut threads = HashSet::new();
+    let mut end = profile.metadata.start_time;
+
+    for event in profile.iter() {
+        threads.insert(event.thread_id);
+        end = end.max(event.timestamp.end());
+
+        let event = event.to_event();
+        let stats = labels.entry(event.label.into_owned()).or_default();
+        stats.count += 1;
+
+        if let Timestamp::Interval { start, end } = event.timestamp {
+            stats.total_time += end.duration_since(start).unwrap_or_default();
+        }
+    }
+
+    let mut labels: Vec<_> = labels.into_iter().collect();
+    labels.sort_by(|(a_label, a), (b_label, b)| {
+        b.total_time
+            .cmp(&a.total_time)
+            .then(b.count.cmp(&a.count))
+            .then(a_label.cmp(b_label))
+    });
+
+    let duration = end
+        .duration_since(profile.metadata.start_time)
+        .unwrap_or_default();
+
+    json!({
+        "cmd": profile.metadata.cmd,
+        "process_id": profile.metadata.process_id,
+        "num_events": profile.num_events(),
+        "num_threads": threads.len(),
+        "duration_nanos": duration.as_nanos() as u64,
+        "labels": labels
+            .iter()
+            .take(TOP_LABELS)
+            .map(|(label, stats)| json!({
+                "label": label,
+                "count": stats.count,
+                "total_time_nanos": stats.total_time.as_nanos() as u64,
+            }))
+            .collect::<Vec<_>>(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use analyzeme::ProfilingDataBuilder;
+
+    #[test]
+    fn summary() {
+        let mut b = ProfilingDataBuilder::new();
+
+        b.interval("Query", "typeck", 0, 10, 100, |b| {
+            b.interval("Query", "mir_borrowck", 0, 20, 30, |_| {});
+        });
+        b.interval("Query", "mir_borrowck", 1, 40, 60, |_| {});
+        b.instant("QueryCacheHit", "typeck", 1, 70);
+
+        let summary = summarize_profile(&b.into_profiling_data());
+
+        assert_eq!(summary["num_events"], 4);
+        assert_eq!(summary["num_threads"], 2);
+        assert_eq!(summary["duration_nanos"], 100);
+        assert_eq!(
+            summary["labels"],
+            json!([
+                { "label": "typeck", "count": 2, "total_time_nanos": 90 },
+                { "label": "mir_borrowck", "count": 2, "total_time_nanos": 30 },
+            ])
+        );
+    }
+
+    #[test]
+    fn invalid_profile() {
+        let buffer = || alloc_buffer(4);
+        let len = unsafe { summarize(buffer(), 4, buffer(), 4, buffer(), 4) };
+        let result = unsafe { std::slice::from_raw_parts(result_ptr(), len) };
+        let result: Value = serde_json::from_slice(result).unwrap();
+
+        assert!(result["error"].is_string());
+    }
+}